@@ -14,6 +14,7 @@ use std::fmt;
 use std::num::NonZeroU64;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 
 use chrono::DateTime;
 use chrono::Utc;
@@ -26,6 +27,8 @@ use serde::Serialize;
 use serde::Serializer;
 
 use crate::pid::DetTid;
+use crate::schedule::RecordMetadata;
+use crate::time::LogicalTime;
 
 /// Configuration options for detcore.
 #[derive(Debug, Serialize, Deserialize, Clone, Parser)]
@@ -38,12 +41,22 @@ pub struct Config {
     #[clap(long = "no-virtualize-cpuid", parse(from_flag = std::ops::Not::not))]
     pub virtualize_cpuid: bool,
 
+    /// The CPU model, feature flags, and cache topology reported by virtual cpuid.  Either one of
+    /// the built-in profiles ("default", "baseline" without AVX, "avx2", or "avx512"), or the path
+    /// to a JSON file giving the `[eax, ebx, ecx, edx]` results as `{"basic": [...],
+    /// "extended": [...], "subleaves": {"leaf": [...]}}`.
+    #[clap(long, value_name = "name|path")]
+    pub cpu_profile: Option<String>,
+
     /// Epoch of the logical time.
     ///
     /// This is the datetime from which all time and date modtimes begin and
     /// monotonically increase. It is in RFC3339 format such as: 1999-12-31T23:59:59Z"
+    /// Setting it lets programs that branch on the date (e.g. certificate expiry, leap years)
+    /// be tested deterministically at any point in time.
     #[clap(
         long,
+        alias = "virtual-epoch",
         env = "HERMIT_EPOCH",
         value_name = "YYYY-MM-DDThh:mm:ssZ",
         default_value = DEFAULT_EPOCH_STR
@@ -57,15 +70,49 @@ pub struct Config {
     #[clap(long, env = "HERMIT_PRNG", default_value = "0", value_name = "uint64")]
     pub seed: u64,
 
-    /// Logical clock multiplier. Values above one make time appear to go faster within the sandbox.
-    #[clap(long, value_name = "float")]
+    /// Logical clock multiplier. Values above one make time appear to go faster within the sandbox,
+    /// which also fast-forwards sleeps and timeouts by the same factor.
+    #[clap(long, alias = "time-dilation", value_name = "float")]
     pub clock_multiplier: Option<f64>,
 
+    /// How the virtual time stamp counter read by `rdtsc` behaves, when time is virtualized.
+    /// "Fixed" runs the counter at `--tsc-mhz` against the thread's logical time, counting each
+    /// `rdtsc` as progress.  "Instruction" advances the counter by exactly `--tsc-increment`
+    /// cycles per `rdtsc`.  "Syscall" ties the counter to the thread's logical time from its
+    /// other progress (syscalls, and branches if counted), without `rdtsc` itself ticking the
+    /// clock; repeated reads in between only advance by a single cycle.
+    #[clap(long, default_value = "fixed", value_name = "fixed|instruction|syscall")]
+    pub rdtsc_model: RdtscModel,
+
+    /// The frequency of the virtual time stamp counter, in MHz, for the "fixed" and "syscall"
+    /// rdtsc models.
+    #[clap(long, default_value = "1000", value_name = "MHz")]
+    pub tsc_mhz: u64,
+
+    /// The number of cycles each `rdtsc` advances the virtual time stamp counter by, under
+    /// `--rdtsc-model=instruction`.
+    #[clap(long, default_value = "1000", value_name = "cycles")]
+    pub tsc_increment: u64,
+
     /// Disable substitution of virtual (deterministic) file metadata, in lieu
     /// of the real metadata returned by `fstat`, implies `virtualize_time`.
     #[clap(long = "no-virtualize-metadata", parse(from_flag = std::ops::Not::not))]
     pub virtualize_metadata: bool,
 
+    /// Disable the deterministic ordering of directory entries.  By default, when metadata is
+    /// virtualized, the first `getdents64` on a directory reads the whole listing, which is
+    /// then returned sorted by name (or, under `--chaos`, permuted by the seed) instead of in
+    /// the order the host filesystem happens to store it.
+    #[clap(long = "no-sort-dirents", parse(from_flag = std::ops::Not::not))]
+    pub sort_dirents: bool,
+
+    /// Normalize the rest of the `stat` results which vary from host to host, beyond the
+    /// inode numbers and modification times covered by virtual metadata: all timestamps follow
+    /// the logical mtime, device ids are renumbered in order of discovery, and the block size
+    /// and block count are derived from the file size.
+    #[clap(long)]
+    pub normalize_stat: bool,
+
     /// Sequentialize thread execution deterministically.
     #[clap(long)]
     pub sequentialize_threads: bool,
@@ -90,12 +137,15 @@ pub struct Config {
     #[clap(long)]
     pub record_preemptions: bool,
 
-    /// File to write the record of preemptions (in JSON).  Implies `--record-preemptions`.
+    /// File to write the record of preemptions (in JSON, or in the compact binary format if the
+    /// file name ends in `.bin`).  A file name ending in `.jsonl` is instead appended to as the
+    /// run goes, so that long runs are recorded in bounded memory and a crashed run leaves a
+    /// usable prefix.  Implies `--record-preemptions`.
     #[clap(long, value_name = "filepath")]
     pub record_preemptions_to: Option<PathBuf>,
 
-    /// JSON file to read recorded preemptions from.  When `--chaos` mode is activated, these
-    /// recorded preemption points take the place of randomized scheduling decisions.
+    /// File (JSON or binary) to read recorded preemptions from.  When `--chaos` mode is activated,
+    /// these recorded preemption points take the place of randomized scheduling decisions.
     #[clap(long, value_name = "filepath", conflicts_with = "replay-schedule-from")]
     pub replay_preemptions_from: Option<PathBuf>,
 
@@ -113,11 +163,42 @@ pub struct Config {
     #[clap(long)]
     pub replay_exhausted_panic: bool,
 
+    /// Replay the schedule in a file up to the event with the given index, then hand the run over
+    /// to the chaos scheduler (seeded as usual by `--sched-seed`), as `FILE:INDEX`.  This explores
+    /// the schedules "near" a known interesting point, such as races late in a long run.
+    #[clap(
+        long,
+        value_name = "FILE:INDEX",
+        conflicts_with_all = &["replay-schedule-from", "replay-preemptions-from"]
+    )]
+    pub replay_prefix_then_chaos: Option<SchedulePrefix>,
+
     /// When playing a schedule trace from disk, bail out on the first time we desynchronize from
-    /// the event sequence specified in the trace.
+    /// the event sequence specified in the trace.  The same as `--on-divergence=abort`.
     #[clap(long)]
     pub die_on_desync: bool,
 
+    /// What to do when the guest diverges from a schedule being replayed, by running a different
+    /// operation (such as another syscall) than the schedule says comes next.  With "abort", the
+    /// run stops with a report of where it diverged.  With "resync", the recorded events up to the
+    /// next one matching what the guest did are skipped, if there is one close by.  With "record",
+    /// the schedule is dropped and the run carries on under the ordinary scheduler, still
+    /// recording if asked to.  If unset, the run keeps following the schedule as best it can.
+    #[clap(
+        long,
+        value_name = "abort|resync|record",
+        requires = "replay-schedule-from"
+    )]
+    pub on_divergence: Option<DivergencePolicy>,
+
+    /// When playing a schedule trace from disk, match events loosely: by the identity of their
+    /// thread, their kind, and roughly their count of branches, skipping over recorded events and
+    /// running unrecorded ones where needed.  This lets a schedule recorded against one build of
+    /// the guest guide a run of a slightly changed one.  How far the run strayed from the
+    /// schedule is reported at the end.  Never bails out, whatever `--die-on-desync` says.
+    #[clap(long, requires = "replay-schedule-from")]
+    pub fuzzy_replay: bool,
+
     /// Given schedule events traced on recording or replaying, print the stack trace at the moment
     /// after the Nth event in the trace. Optionally, provide an output file into which the stack
     /// trace will be printed, otherwise it goes to stderr.
@@ -143,6 +224,25 @@ pub struct Config {
     #[clap(long, value_name = "filepath")]
     pub preemption_stacktrace_log_file: Option<PathBuf>,
 
+    /// When a guest thread receives a crashing signal (SIGSEGV, SIGBUS, SIGILL, SIGFPE, SIGABRT
+    /// or SIGSYS), write a crash report with its registers and backtrace into this directory.
+    /// The report is named after the thread and the logical time of the crash, and records the
+    /// scheduler turn and schedule event at which it happened.  Only supported on x86_64.
+    #[clap(long, value_name = "DIR")]
+    pub crash_report_dir: Option<PathBuf>,
+
+    /// Write a timeline of the run to this file, which ui.perfetto.dev and chrome://tracing open:
+    /// a track for each guest thread in virtual time, with its scheduled events and syscalls as
+    /// slices, and its preemptions and signals as markers.  Implies `--record-preemptions`.
+    #[clap(long, value_name = "PATH")]
+    pub emit_trace: Option<PathBuf>,
+
+    /// Audit the syscalls which are not made deterministic: those passed through to the host,
+    /// and those answered with a stand-in, like `ENOSYS`.  At the end of the run, each kind is
+    /// listed, with how many times it was made, and the stack of its first call.
+    #[clap(long)]
+    pub audit_syscalls: bool,
+
     /// Enable deterministic IO by reassuring we always read/write the maximum possible bytes
     /// from IO syscalls. There might be cases that read/write syscalls return less bytes than
     /// requests. Detcore, makes an effort to request additional bytes until we reach the ones
@@ -150,6 +250,59 @@ pub struct Config {
     #[clap(long)]
     pub deterministic_io: bool,
 
+    /// Assign the addresses of `mmap`, `mremap`, and `brk` allocations from a deterministic
+    /// per-process allocator, instead of letting the kernel place them.  Pointer values then
+    /// no longer depend on ASLR or on the memory layout of the host machine.
+    #[clap(long)]
+    pub deterministic_mmap: bool,
+
+    /// Limit the memory each guest process can map, counting its `mmap`, `mremap`, `brk`,
+    /// and `shmat` allocations.  Takes a number of bytes, or shorthand (e.g. "512MB").  An
+    /// allocation which would go over the limit fails with ENOMEM, at the same point in every
+    /// run, so that out-of-memory handling can be tested.  Implies `--deterministic-mmap`.
+    #[clap(long, parse(try_from_str = try_parse_memory), value_name = "bytesize")]
+    pub memory_limit: Option<u64>,
+
+    /// Stop the guest once this much virtual time has passed since it started, by sending
+    /// `--timeout-signal` to the root process.  Takes a number with a unit, e.g. "30s" or
+    /// "1500ms".  As the time is virtual, a run that hangs is stopped at exactly the same
+    /// point every time.
+    #[clap(long, parse(try_from_str = try_parse_duration), value_name = "duration")]
+    pub timeout: Option<Duration>,
+
+    /// The signal sent to the guest on `--timeout`.  Accepts either signal names or numbers.
+    #[clap(long, value_name = "signame", default_value = "SIGTERM", parse(try_from_str))]
+    pub timeout_signal: SigWrapper,
+
+    /// How to handle io_uring, whose operations complete asynchronously inside the kernel, out
+    /// of sight of the scheduler.  "Disable" fails `io_uring_setup` with ENOSYS, as on a kernel
+    /// without io_uring, so that async runtimes fall back to epoll and the ordinary syscalls,
+    /// which are determinized.  "Passthrough" lets the guest use io_uring as-is: its
+    /// `io_uring_enter` and `io_uring_register` calls are still scheduler points, but the order in
+    /// which the kernel completes the submitted operations is not determinized, so runs may
+    /// differ.
+    #[clap(long, default_value = "disable", value_name = "disable|passthrough")]
+    pub io_uring: IoUringMode,
+
+    /// Emulate the seccomp filters (and strict mode) the guest installs, rather than handing
+    /// them to the kernel, where they would also apply to the syscalls hermit injects and
+    /// interfere with its own interception.  Every syscall is then intercepted, to be checked
+    /// against the guest's filters before it is handled.
+    #[clap(long)]
+    pub emulate_seccomp: bool,
+
+    /// How the guest's syscalls are intercepted.  "ptrace" stops the guest on every syscall.
+    /// "seccomp-fast" installs a seccomp-bpf filter so that only the syscalls detcore handles
+    /// stop the guest, and the rest run natively (debug builds still stop on every syscall).
+    /// Either way, each syscall that is intercepted costs a ptrace stop: there is no backend
+    /// which instruments the guest to handle syscalls in its own address space.
+    #[clap(
+        long,
+        default_value = "seccomp-fast",
+        value_name = "ptrace|seccomp-fast"
+    )]
+    pub backend: Backend,
+
     /// DANGEROUS: Panic on unsupported syscalls, this is useful for
     /// debugging detcore itself, not recommended otherwise.
     #[clap(long)]
@@ -164,15 +317,28 @@ pub struct Config {
     #[clap(skip)]
     pub replay_data: Option<PathBuf>,
 
+    /// [Internal] Written into the header of the record of preemptions, if one is made.
+    #[clap(skip)]
+    pub record_metadata: Option<RecordMetadata>,
+
     /// Kill all remaining tasks iff daemons are the only ones left.
     /// Disabled by default.
     #[clap(long)]
     pub kill_daemons: bool,
 
-    /// Start gdbserver on `gdbserver_port` for remote debugging
-    /// Disabled by default.
-    #[clap(long)]
-    pub gdbserver: bool,
+    /// Start gdbserver for remote debugging, listening on `:<port>`, or on `gdbserver_port` if no
+    /// port is given. The guest is paused at its start until gdb attaches, and its threads are
+    /// still run one at a time by the scheduler, so that a failing schedule found by `hermit
+    /// analyze` can be stepped through exactly. There is no reverse execution
+    /// (`reverse-continue`, `reverse-step`): to go back, run again, which replays the same
+    /// schedule. Disabled by default.
+    #[clap(
+        long,
+        value_name = ":port",
+        min_values = 0,
+        parse(try_from_str = try_parse_gdbserver_port)
+    )]
+    pub gdbserver: Option<Option<u16>>,
     /// port gdbserver listening on
     #[clap(
         long,
@@ -194,6 +360,22 @@ pub struct Config {
                 parse(try_from_str = parse_preemption_timeout))]
     pub preemption_timeout: MaybePreemptionTimeout,
 
+    /// The quantum of virtual time, in nanoseconds, that elapses on every step of the scheduler.
+    /// This is the slice by which global time advances independently of guest threads' own
+    /// progress.  Finer quanta interleave timed events more precisely, whereas coarser quanta let
+    /// sleeping workloads reach their deadlines in fewer scheduler turns.
+    #[clap(long,
+                value_name = "nanos",
+                default_value = "500000",
+                parse(try_from_str = parse_virtual_quantum))]
+    pub virtual_quantum: u64,
+
+    /// The granularity, in virtual nanoseconds, of timer expirations.  Sleeps, timeouts, and
+    /// alarms are rounded up to the next multiple of this value, which is also reported to the
+    /// guest by `clock_getres`.  If unset, timers expire at exactly the requested nanosecond.
+    #[clap(long, value_name = "nanos")]
+    pub timer_resolution: Option<NonZeroU64>,
+
     /// Shut down immediately upon SIGINT, rather than letting the guest handle it.
     #[clap(long)]
     pub sigint_instakill: bool,
@@ -202,6 +384,32 @@ pub struct Config {
     #[clap(long)]
     pub warn_non_zero_binds: bool,
 
+    /// Support guests whose TCP and UDP traffic stays on the loopback interface, between
+    /// processes and threads in the same container.  Sockets which connect or send without
+    /// being bound first get a port from the deterministic pool, instead of a kernel-chosen
+    /// ephemeral one, and socket operations are traced as `Socket` schedule events, so that the
+    /// interleaving of messages between clients and servers can be recorded and analyzed.
+    /// Requires `--sequentialize-threads`.  Under `hermit run`, this implies `--no-networking`.
+    #[clap(long)]
+    pub loopback_networking: bool,
+
+    /// Record the bytes exchanged with external (non-loopback) peers over TCP, and when the
+    /// responses arrived, to this JSON file, for use with `--replay-network-from`.
+    #[clap(long, value_name = "filepath", conflicts_with = "replay-network-from")]
+    pub record_network_to: Option<PathBuf>,
+
+    /// Serve connections to external peers from a recording made with `--record-network-to`,
+    /// rather than the network.  The n-th connection to an address gets the responses
+    /// recorded for the n-th connection to it, which arrive at the recorded (virtual) times
+    /// under `--sequentialize-threads`.  Connections which were not recorded are refused.
+    /// Replayed sockets cannot be waited on with poll, select, or epoll.
+    #[clap(
+        long,
+        value_name = "filepath",
+        conflicts_with = "record-network-to"
+    )]
+    pub replay_network_from: Option<PathBuf>,
+
     /// Apply a specialized scheduling heuristic which may help exercise certain bugs.
     #[clap(long, default_value = "none", value_name = "str")]
     // TODO: Rename this to scheduler_strategy?
@@ -220,6 +428,28 @@ pub struct Config {
     #[clap(long, default_value = "0.0", value_name = "double")]
     pub sched_sticky_random_param: f64,
 
+    /// Add extra scheduling points at condition variable operations (wait, signal, and
+    /// broadcast), as recognized from the futex calls made by pthreads.  In `--chaos` mode,
+    /// threads are also randomly reprioritized at these points.  These operations are then
+    /// recorded as condvar events in the schedule, rather than as futex syscalls.
+    #[clap(long)]
+    pub condvar_sched_points: bool,
+
+    /// Make fewer round trips to the scheduler on the hot paths of heavily threaded guests.
+    /// Futex waits and wakes do not check in again once they are done, a zero-length
+    /// `nanosleep` yields without reading the time, and timed sleeps and futex waits read the
+    /// global time in the same request that waits on it.  This changes the schedules of runs, so
+    /// a schedule recorded with this setting only replays with it, and vice versa.
+    #[clap(long)]
+    pub lean_sched_points: bool,
+
+    /// Control the order in which a newly cloned thread begins running relative to its parent.
+    /// With "random", the order is chosen anew for every clone, determined by the scheduler seed.
+    /// If unset, the order simply follows from thread priorities.  The choice made for each clone
+    /// is saved by `--record-preemptions`, and followed by `--replay-preemptions-from`.
+    #[clap(long, value_name = "childfirst|parentfirst|random")]
+    pub spawn_order: Option<SpawnOrder>,
+
     /// [Internal] An internal flag for indicating to Detcore whether we are in `hermit record` or
     /// `hermit replay` mode.  This is necessary because there are DIFFERENT global
     /// invariants in record mode (e.g. files dont exist).  If we move to a chroot model
@@ -273,16 +503,41 @@ pub struct Config {
     pub sysinfo_uptime_offset: u64,
 
     /// Configure memory available for the container.  Takes a number of bytes, or shorthand (e.g.
-    /// "1GB"). Right now this doesn't enforce an upper bound, but does affect the amount of memory
-    /// reported to the guest.
+    /// "1GB"). This doesn't enforce an upper bound (see `--memory-limit`), but does affect the
+    /// amount of memory reported to the guest.
     #[clap(long, default_value = "1GB", parse(try_from_str = try_parse_memory), value_name = "bytesize")]
     pub memory: u64,
 
+    /// The hostname reported to the guest by `uname` (and therefore `gethostname`), in place of
+    /// the host's.  The part after the last dot is reported as the domain name.
+    #[clap(long, default_value = DEFAULT_HOSTNAME, value_name = "str")]
+    pub hostname: String,
+
+    /// The kernel release reported to the guest by `uname`.
+    #[clap(long, default_value = "5.2.0", value_name = "str")]
+    pub uname_release: String,
+
+    /// Report this user id from `getuid` and `geteuid`, instead of the guest's actual one.
+    #[clap(long, value_name = "uint32")]
+    pub uid: Option<u32>,
+
+    /// Report this group id from `getgid` and `getegid`, instead of the guest's actual one.
+    #[clap(long, value_name = "uint32")]
+    pub gid: Option<u32>,
+
     /// Configure extra interrupt points based on thread id and rcb counter. Detcore will raise a precise
     /// timer for this RCB whenever it detects that current current thread timeslice intercects any of the
     /// interrupt points specified
     #[clap(long, value_name = "tid:rcbs", parse(try_from_str = try_parse_numbers_with_colon))]
     pub interrupt_at: Vec<(DetTid, u64)>,
+
+    /// Serve the guest's randomness (`getrandom`, `/dev/urandom`, `/dev/random`) from the bytes of
+    /// a file, rather than deriving it from `--seed`.  May be given more than once.  A path
+    /// prefixed with a thread id (e.g. "3:/tmp/bytes") is consumed only by that thread, while an
+    /// unprefixed path is consumed, in order, by all the threads without a file of their own.
+    /// Once a file is exhausted, randomness is again derived from the seed.
+    #[clap(long, value_name = "[tid:]path", parse(try_from_str = parse_entropy_file))]
+    pub entropy_file: Vec<(Option<DetTid>, PathBuf)>,
 }
 
 fn try_parse_numbers_with_colon(from_str: &str) -> anyhow::Result<(DetTid, u64)> {
@@ -307,13 +562,59 @@ fn try_parse_memory(from_str: &str) -> anyhow::Result<u64> {
         .map_err(anyhow::Error::msg)
 }
 
-impl Config {
-    /// Sanity check the flags, and update any wherever flag B is implied by A.
-    pub fn validate(&mut self) {
-        assert!(self.sched_sticky_random_param >= 0.0);
-        assert!(self.sched_sticky_random_param <= 1.0);
+fn try_parse_gdbserver_port(from_str: &str) -> anyhow::Result<u16> {
+    let port = from_str.strip_prefix(':').unwrap_or(from_str);
+    port.parse()
+        .map_err(|_| anyhow::anyhow!("expected :<port>, got '{}'", from_str))
+}
+
+fn try_parse_duration(from_str: &str) -> anyhow::Result<Duration> {
+    let split = from_str
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(from_str.len());
+    let (number, unit) = from_str.split_at(split);
+    if !number.contains(|c: char| c.is_ascii_digit()) {
+        anyhow::bail!("expected a number in '{}'", from_str);
+    }
+    let unit_nanos: u128 = match unit {
+        "ns" => 1,
+        "us" | "µs" => 1_000,
+        "ms" => 1_000_000,
+        "s" => 1_000_000_000,
+        "m" => 60_000_000_000,
+        "h" => 3_600_000_000_000,
+        _ => anyhow::bail!(
+            "expected a unit of ns, us, ms, s, m, or h in '{}'",
+            from_str
+        ),
+    };
+    // Scale the whole and fractional parts separately, so that e.g. "0.1s" is exact.
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    let fraction = &fraction[..fraction.len().min(18)];
+    let whole: u128 = if whole.is_empty() { 0 } else { whole.parse()? };
+    let mut nanos = whole * unit_nanos;
+    if !fraction.is_empty() {
+        nanos += fraction.parse::<u128>()? * unit_nanos / 10u128.pow(fraction.len() as u32);
+    }
+    let nanos =
+        u64::try_from(nanos).map_err(|_| anyhow::anyhow!("duration '{}' is too long", from_str))?;
+    Ok(Duration::from_nanos(nanos))
+}
 
-        if self.record_preemptions_to.is_some() {
+impl Config {
+    /// Sanity check the flags, and update any wherever flag B is implied by A.  Fails on flags
+    /// which cannot be used together, or values out of range.
+    pub fn validate(&mut self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            (0.0..=1.0).contains(&self.sched_sticky_random_param),
+            "--sched-sticky-random-param must be between 0 and 1"
+        );
+        anyhow::ensure!(
+            self.virtual_quantum > 0,
+            "--virtual-quantum must be non-zero"
+        );
+
+        if self.record_preemptions_to.is_some() || self.emit_trace.is_some() {
             self.record_preemptions = true;
         }
         // TODO: separate out recording flags: --record-preemptions vs --record-schedule-trace
@@ -323,8 +624,14 @@ impl Config {
         //     );
         // }
 
+        if let Some(prefix) = &self.replay_prefix_then_chaos {
+            // The prefix is replayed like any schedule, and chaos mode takes over after it.
+            self.replay_schedule_from = Some(prefix.path.clone());
+            self.chaos = true;
+        }
+
         if self.replay_schedule_from.is_some() && self.replay_preemptions_from.is_some() {
-            panic!("Cannot set both --replay-preemptions-from and --replay-schedule-from!!");
+            anyhow::bail!("Cannot set both --replay-preemptions-from and --replay-schedule-from");
         }
 
         if self.chaos {
@@ -343,12 +650,121 @@ impl Config {
             );
             self.stop_after_turn = None;
         }
+        if self.timeout.is_some() && !self.sequentialize_threads {
+            tracing::warn!(
+                "--timeout will have no effect unless --sequentialize-threads is enabled (e.g. via --strict)"
+            );
+            self.timeout = None;
+        }
         if self.stop_after_iter.is_some() && !self.sequentialize_threads {
             tracing::warn!(
                 "--stop-after--iter will have no effect if --sequentialize-threads is enabled (e.g. via --strict)"
             );
             self.stop_after_iter = None;
         }
+        if let Some(Some(port)) = self.gdbserver {
+            self.gdbserver_port = port;
+            self.gdbserver = Some(None);
+        }
+
+        if self.condvar_sched_points && !self.sequentialize_threads {
+            tracing::warn!(
+                "--condvar-sched-points will have no effect unless --sequentialize-threads is enabled (e.g. via --strict)"
+            );
+            self.condvar_sched_points = false;
+        }
+
+        if self.lean_sched_points && !self.sequentialize_threads {
+            tracing::warn!(
+                "--lean-sched-points will have no effect unless --sequentialize-threads is enabled (e.g. via --strict)"
+            );
+            self.lean_sched_points = false;
+        }
+
+        if let Some(m) = self.clock_multiplier {
+            anyhow::ensure!(
+                m.is_finite() && m > 0.0,
+                "--clock-multiplier (--time-dilation) must be a positive number, got {}",
+                m
+            );
+        }
+
+        if self.cpu_profile.is_some() && !self.virtualize_cpuid {
+            tracing::warn!("--cpu-profile will have no effect with --no-virtualize-cpuid");
+        }
+
+        anyhow::ensure!(self.tsc_mhz > 0, "--tsc-mhz must be nonzero");
+        if !self.virtualize_time && (self.rdtsc_model != RdtscModel::Fixed || self.tsc_mhz != 1000)
+        {
+            tracing::warn!(
+                "--rdtsc-model and --tsc-mhz will have no effect unless time is virtualized"
+            );
+        }
+
+        if self.fuzzy_replay && self.on_divergence.is_some() {
+            tracing::warn!(
+                "--on-divergence will have no effect with --fuzzy-replay, which always resynchronizes"
+            );
+            self.on_divergence = None;
+        }
+
+        if self.spawn_order.is_some()
+            && (!self.sequentialize_threads || self.replay_schedule_from.is_some())
+        {
+            tracing::warn!(
+                "--spawn-order will have no effect unless --sequentialize-threads is enabled (e.g. via --strict), and a schedule is not being replayed"
+            );
+            self.spawn_order = None;
+        }
+
+        // The utsname fields are 65 bytes, including the terminator.
+        anyhow::ensure!(
+            self.hostname.len() < 65 && self.uname_release.len() < 65,
+            "--hostname and --uname-release must be at most 64 bytes"
+        );
+
+        if self.io_uring == IoUringMode::Passthrough && self.sequentialize_threads {
+            tracing::warn!(
+                "--io-uring=passthrough breaks determinism: io_uring completions are not determinized, and may arrive in any order"
+            );
+        }
+
+        if self.normalize_stat && !self.virtualize_metadata {
+            tracing::warn!("--normalize-stat will have no effect with --no-virtualize-metadata");
+            self.normalize_stat = false;
+        }
+
+        if self.loopback_networking && (!self.sequentialize_threads || self.recordreplay_modes) {
+            tracing::warn!(
+                "--loopback-networking will have no effect unless --sequentialize-threads is enabled (e.g. via --strict), and not when recording or replaying"
+            );
+            self.loopback_networking = false;
+        }
+
+        if (self.record_network_to.is_some() || self.replay_network_from.is_some())
+            && self.recordreplay_modes
+        {
+            tracing::warn!(
+                "--record-network-to and --replay-network-from have no effect when recording or replaying"
+            );
+            self.record_network_to = None;
+            self.replay_network_from = None;
+        }
+
+        if self.memory_limit.is_some() {
+            self.deterministic_mmap = true;
+        }
+
+        if self.deterministic_mmap && self.recordreplay_modes {
+            tracing::warn!("--deterministic-mmap is not supported when recording or replaying");
+            self.deterministic_mmap = false;
+            self.memory_limit = None;
+        }
+
+        if self.emulate_seccomp && self.recordreplay_modes {
+            tracing::warn!("--emulate-seccomp is not supported when recording or replaying");
+            self.emulate_seccomp = false;
+        }
 
         if self.debug_externalize_sockets && !self.sequentialize_threads {
             tracing::warn!(
@@ -360,6 +776,7 @@ impl Config {
         if self.preemption_stacktrace_log_file.is_some() {
             self.preemption_stacktrace = true;
         }
+        Ok(())
     }
 
     /// Should we use RCB in computing logical time?
@@ -381,6 +798,52 @@ impl Config {
         self.record_preemptions || self.replay_schedule_from.is_some()
     }
 
+    /// A hash of the settings which decide how threads are scheduled, other than `--seed`.  A
+    /// schedule recorded with one hash is not expected to replay faithfully with another.  The
+    /// hash is the FNV-1a of the settings serialized as JSON, which stays the same from one
+    /// build of hermit to the next.
+    pub fn schedule_hash(&self) -> String {
+        let settings = (
+            (
+                self.sequentialize_threads,
+                self.chaos,
+                self.sched_seed,
+                self.sched_heuristic,
+                self.sched_sticky_random_param,
+                self.condvar_sched_points,
+                self.spawn_order,
+            ),
+            (
+                self.preemption_timeout,
+                self.virtual_quantum,
+                self.timer_resolution,
+                self.no_rcb_time,
+            ),
+            (
+                self.virtualize_time,
+                self.rdtsc_model,
+                self.tsc_mhz,
+                self.tsc_increment,
+                self.imprecise_timers,
+            ),
+        );
+        let mut json = serde_json::to_vec(&settings).expect("config to serialize");
+        // Settings added since are only hashed when they are set, so that the hashes in records
+        // made before them still match.
+        if self.lean_sched_points {
+            json.extend_from_slice(b"lean_sched_points");
+        }
+        format!("{:016x}", fnv1a(&json))
+    }
+
+    /// Round the expiration time of a guest timer up to the configured `--timer-resolution`.
+    pub fn round_timer_expiration(&self, target: LogicalTime) -> LogicalTime {
+        match self.timer_resolution {
+            Some(res) => target.round_up_to(res.get()),
+            None => target,
+        }
+    }
+
     /// Returns manual interuption points for a given thread
     pub fn interrupts_for_thread(&self, thread_id: DetTid) -> BTreeSet<u64> {
         self.interrupt_at
@@ -479,6 +942,182 @@ impl FromStr for SchedHeuristic {
     }
 }
 
+/// The policy for virtualizing the time stamp counter, see `--rdtsc-model`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum RdtscModel {
+    /// Constant frequency relative to thread logical time.
+    Fixed,
+    /// Constant increment per `rdtsc` instruction.
+    Instruction,
+    /// Follows logical time, which `rdtsc` does not advance.
+    Syscall,
+}
+
+impl Default for RdtscModel {
+    fn default() -> Self {
+        RdtscModel::Fixed
+    }
+}
+
+impl FromStr for RdtscModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fixed" => Ok(RdtscModel::Fixed),
+            "instruction" => Ok(RdtscModel::Instruction),
+            "syscall" => Ok(RdtscModel::Syscall),
+            _ => Err(format!(
+                "Expected Fixed|Instruction|Syscall, could not parse: {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// How the guest's use of io_uring is handled, see `--io-uring`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum IoUringMode {
+    /// io_uring is reported as unavailable.
+    Disable,
+    /// io_uring is available, and not determinized.
+    Passthrough,
+}
+
+impl Default for IoUringMode {
+    fn default() -> Self {
+        IoUringMode::Disable
+    }
+}
+
+impl FromStr for IoUringMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "disable" => Ok(IoUringMode::Disable),
+            "passthrough" => Ok(IoUringMode::Passthrough),
+            _ => Err(format!("Expected Disable|Passthrough, could not parse: {:?}", s)),
+        }
+    }
+}
+
+/// How the guest's syscalls are intercepted, see `--backend`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Backend {
+    /// Every syscall stops the guest.
+    Ptrace,
+    /// Only the syscalls detcore handles stop the guest.
+    SeccompFast,
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::SeccompFast
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ptrace" => Ok(Backend::Ptrace),
+            "seccomp-fast" => Ok(Backend::SeccompFast),
+            _ => Err(format!(
+                "Expected ptrace|seccomp-fast, could not parse: {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// The order in which a newly cloned thread begins running relative to its parent.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum SpawnOrder {
+    /// The child runs before the parent continues past the clone.
+    ChildFirst,
+    /// The parent keeps running after the clone, and the child runs later.
+    ParentFirst,
+    /// Randomly choose between child-first and parent-first on every clone.
+    Random,
+}
+
+impl FromStr for SpawnOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "childfirst" => Ok(SpawnOrder::ChildFirst),
+            "parentfirst" => Ok(SpawnOrder::ParentFirst),
+            "random" => Ok(SpawnOrder::Random),
+            _ => Err(format!(
+                "Expected ChildFirst|ParentFirst|Random, could not parse: {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// What to do when a replayed schedule diverges, see `--on-divergence`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum DivergencePolicy {
+    /// Stop the run, reporting where it diverged.
+    Abort,
+    /// Skip ahead to the next recorded event matching what the guest did.
+    Resync,
+    /// Stop replaying, and carry on as an ordinary run.
+    Record,
+}
+
+impl FromStr for DivergencePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "abort" => Ok(DivergencePolicy::Abort),
+            "resync" => Ok(DivergencePolicy::Resync),
+            "record" => Ok(DivergencePolicy::Record),
+            _ => Err(format!(
+                "Expected abort|resync|record, could not parse: {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// The first events of a schedule, see `--replay-prefix-then-chaos`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct SchedulePrefix {
+    /// The file the schedule is in.
+    pub path: PathBuf,
+    /// How many of its events to replay.
+    pub events: u64,
+}
+
+impl FromStr for SchedulePrefix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, events) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("Expected FILE:INDEX, could not parse: {:?}", s))?;
+        let events = events
+            .parse()
+            .map_err(|e| format!("Invalid event index {:?}: {}", events, e))?;
+        Ok(SchedulePrefix {
+            path: PathBuf::from(path),
+            events,
+        })
+    }
+}
+
+impl fmt::Display for SchedulePrefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.path.display(), self.events)
+    }
+}
+
 /// If this is set to None, the RCB (retired conditional branch) hardware counter feature is disabled.
 ///
 /// Limitations with clap require a type alias here.
@@ -499,6 +1138,23 @@ fn parse_preemption_timeout(
     }
 }
 
+fn parse_virtual_quantum(src: &str) -> Result<u64, String> {
+    match src.parse::<u64>() {
+        Ok(0) => Err("the virtual quantum must be at least one nanosecond".to_owned()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(format!("Failed to parse virtual quantum: {e}")),
+    }
+}
+
+fn parse_entropy_file(src: &str) -> Result<(Option<DetTid>, PathBuf), String> {
+    if let Some((tid_str, path)) = src.split_once(':') {
+        if let Ok(tid) = tid_str.parse::<DetTid>() {
+            return Ok((Some(tid), PathBuf::from(path)));
+        }
+    }
+    Ok((None, PathBuf::from(src)))
+}
+
 fn parse_index_with_path(src: &str) -> Result<(u64, Option<PathBuf>), String> {
     let convert = |e| format!("Failed to parse int index before comma: {e}");
     if let Some((index_str, path)) = src.split_once(',') {
@@ -542,6 +1198,9 @@ impl std::error::Error for ParsePreemptionTimeoutError {
 /// original unix epoch (time zero).
 pub static DEFAULT_EPOCH_STR: &str = "1999-12-31T23:59:59Z";
 
+/// The default hostname seen by the guest.
+pub static DEFAULT_HOSTNAME: &str = "hermetic-container.local";
+
 impl Config {
     /// Construct the config using environment variables only, not CLI args.
     pub fn from_env() -> Self {
@@ -558,6 +1217,13 @@ impl Config {
     }
 }
 
+/// The 64-bit FNV-1a hash of some bytes.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
 /// N.B. we don't want to specify two different notions of "default", so we use the
 /// `Clap` instance above.
 impl Default for Config {
@@ -615,3 +1281,28 @@ impl FromStr for SigWrapper {
         Ok(SigWrapper(Signal::from_str(s)?))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_known_values() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn schedule_hash_ignores_backend() {
+        let mut config = Config::default();
+        let hash = config.schedule_hash();
+        config.backend = Backend::Ptrace;
+        assert_eq!(config.schedule_hash(), hash);
+        config.chaos = !config.chaos;
+        assert_ne!(config.schedule_hash(), hash);
+        let hash = config.schedule_hash();
+        config.lean_sched_points = true;
+        assert_ne!(config.schedule_hash(), hash);
+    }
+}
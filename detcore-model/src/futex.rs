@@ -6,9 +6,24 @@
  * LICENSE file in the root directory of this source tree.
  */
 
+use serde::Deserialize;
+use serde::Serialize;
+
 use crate::pid::DetPid;
 
-// TODO: use newtype pattern here.
-/// Because we don't yet support inter-process sharing of futexes, a futex is uniquely
-/// identified by Pid and virtual address (within that Pid's address space).
-pub type FutexID = (DetPid, usize);
+/// Identifies a futex word, as the kernel does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FutexID {
+    /// A process-private futex, by Pid and virtual address (within that Pid's address space).
+    Private(DetPid, usize),
+    /// A futex in memory shared between processes, each of which may map it at a different
+    /// address: by the device and inode of the shared object, and the word's offset in it.
+    Shared {
+        /// Major and minor number of the device holding the shared object.
+        dev: (u64, u64),
+        /// Inode of the shared object.
+        inode: u64,
+        /// Offset of the futex word within the shared object.
+        offset: u64,
+    },
+}
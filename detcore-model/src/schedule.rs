@@ -34,6 +34,10 @@ pub struct SchedEvent {
     /// An optional snapshot of the thread logical time at this point.
     /// This includes time waiting on the global scheduler.
     pub end_time: Option<LogicalTime>,
+    /// The identity of the thread, which still holds if a change to the guest shifts its
+    /// `dettid`.  Absent in schedules from before this was tracked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<ThreadIdentity>,
 }
 
 /// A smaller version of `SchedEvent` that we can use to do comparisons on the
@@ -67,6 +71,7 @@ impl From<&MiniSchedEvent> for SchedEvent {
             start_rip: None,
             end_rip: None,
             end_time: None,
+            identity: None,
         }
     }
 }
@@ -90,6 +95,7 @@ impl From<MiniSchedEvent> for SchedEvent {
             start_rip: None,
             end_rip: None,
             end_time: None,
+            identity: None,
         }
     }
 }
@@ -105,6 +111,34 @@ impl SchedEvent {
             start_rip: None,
             end_rip: None,
             end_time: None,
+            identity: None,
+        }
+    }
+
+    /// Add a futex syscall that was recognized as a condition variable operation to the global
+    /// scheduling history.
+    pub fn condvar(dettid: DetTid, op: CondvarOp, phase: SyscallPhase) -> SchedEvent {
+        SchedEvent {
+            dettid,
+            op: Op::Condvar(op, phase),
+            count: 1,
+            start_rip: None,
+            end_rip: None,
+            end_time: None,
+            identity: None,
+        }
+    }
+
+    /// Add a socket operation to the global scheduling history.
+    pub fn socket(dettid: DetTid, op: SocketOp, phase: SyscallPhase) -> SchedEvent {
+        SchedEvent {
+            dettid,
+            op: Op::Socket(op, phase),
+            count: 1,
+            start_rip: None,
+            end_rip: None,
+            end_time: None,
+            identity: None,
         }
     }
 
@@ -117,6 +151,7 @@ impl SchedEvent {
             start_rip: None, // TODO: track the start of the interval as well.
             end_rip: None,
             end_time: None,
+            identity: None,
         }
     }
 
@@ -137,6 +172,59 @@ impl SchedEvent {
         self.end_rip = Some(end_rip);
         self
     }
+
+    /// Set the identity of the thread that originated the event.
+    pub fn with_identity(mut self, identity: ThreadIdentity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+}
+
+/// A way to name a thread that, unlike its `DetTid`, does not change when an unrelated thread is
+/// spawned earlier or later: where it is in the tree of spawned threads, and what it is called.
+#[derive(PartialEq, Debug, Eq, Clone, Hash, Serialize, Deserialize)]
+pub struct ThreadIdentity {
+    /// Which child of its parent each thread is, in the order they were spawned, on the way down
+    /// from the root thread.  Empty for the root thread itself.
+    pub path: Vec<u32>,
+    /// The name of the thread (its `comm`), if it has been seen.
+    pub name: Option<String>,
+}
+
+impl ThreadIdentity {
+    /// The identity of the first thread of the guest.
+    pub fn root() -> Self {
+        ThreadIdentity {
+            path: Vec::new(),
+            name: None,
+        }
+    }
+
+    /// The identity of the `index`th child spawned by this thread, which starts out with the
+    /// same name, just as Linux copies `comm` to a new thread.
+    pub fn child(&self, index: u32) -> Self {
+        let mut path = self.path.clone();
+        path.push(index);
+        ThreadIdentity {
+            path,
+            name: self.name.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for ThreadIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "/")?;
+        }
+        for index in &self.path {
+            write!(f, "/{}", index)?;
+        }
+        if let Some(name) = &self.name {
+            write!(f, " ({})", name)?;
+        }
+        Ok(())
+    }
 }
 
 /// The type of the RIP value.
@@ -151,6 +239,9 @@ pub enum SyscallPhase {
     /// An internal (nonblocking) retry of the syscall to check if its done yet (but it wasn't).
     Polling,
 
+    /// The syscall's logical timeout expired before it was ready, so it returns as timed out.
+    TimedOut,
+
     /// The event was recorded after the syscall logically completed.
     Posthook,
 }
@@ -180,7 +271,95 @@ pub enum Op {
     /// after.
     Syscall(Sysno, SyscallPhase),
 
+    /// A futex system call which was recognized as a condition variable operation.  This takes
+    /// the place of the `Syscall` event that would otherwise be recorded for the futex.
+    Condvar(CondvarOp, SyscallPhase),
+
+    /// A send, receive, or connection on a socket, under `--loopback-networking`.  This takes the
+    /// place of the `Syscall` event, so that the interleaving of messages between the endpoints
+    /// stands out in the schedule.
+    Socket(SocketOp, SyscallPhase),
+
     /// An unknown number of other instructions that occured BETWEEN hermit-interceptable events.
     /// The only way to preempt inbewteen these is expensive single-stepping.
     OtherInstructions,
 }
+
+/// A condition variable operation, as recognized from the futex calls that pthreads uses to
+/// implement `pthread_cond_*`.
+#[derive(PartialEq, Debug, Eq, Copy, Clone, Hash, Serialize, Deserialize)]
+pub enum CondvarOp {
+    /// Waiting to be signaled (`pthread_cond_wait`, `pthread_cond_timedwait`).
+    Wait,
+
+    /// Waking a single waiter (`pthread_cond_signal`).
+    Signal,
+
+    /// Waking all waiters (`pthread_cond_broadcast`).
+    Broadcast,
+}
+
+/// A socket operation, see `Op::Socket`.
+#[derive(PartialEq, Debug, Eq, Copy, Clone, Hash, Serialize, Deserialize)]
+pub enum SocketOp {
+    /// Connecting to a listening socket (`connect`).
+    Connect,
+
+    /// Accepting a connection (`accept`, `accept4`).
+    Accept,
+
+    /// Sending data (`sendto`, `sendmsg`, `sendmmsg`, or `write` on a socket).
+    Send,
+
+    /// Receiving data (`recvfrom`, `recvmsg`, `recvmmsg`, or `read` on a socket).
+    Recv,
+}
+
+/// Where a schedule or preemption record came from, so that replaying it under a different
+/// configuration can be caught rather than silently diverging.
+#[derive(PartialEq, Debug, Eq, Clone, Hash, Serialize, Deserialize)]
+pub struct RecordMetadata {
+    /// The version of hermit that made the record.
+    pub hermit_version: String,
+    /// The `Config::schedule_hash` of the run.
+    pub config_hash: String,
+    /// The guest's command line.
+    pub command: Vec<String>,
+    /// The `--seed` of the run.
+    pub seed: u64,
+    /// When the record was made, in RFC 3339 format.
+    pub recorded_at: String,
+}
+
+impl RecordMetadata {
+    /// Describes each way that a run described by `current` differs from the one that made this
+    /// record, other than when it happened.
+    pub fn differences(&self, current: &RecordMetadata) -> Vec<String> {
+        let mut diffs = Vec::new();
+        if self.hermit_version != current.hermit_version {
+            diffs.push(format!(
+                "recorded by hermit {}, but this is hermit {}",
+                self.hermit_version, current.hermit_version
+            ));
+        }
+        if self.config_hash != current.config_hash {
+            diffs.push(format!(
+                "recorded with scheduling settings {}, but these are {}",
+                self.config_hash, current.config_hash
+            ));
+        }
+        if self.command != current.command {
+            diffs.push(format!(
+                "recorded running {:?}, but this runs {:?}",
+                self.command, current.command
+            ));
+        }
+        if self.seed != current.seed {
+            diffs.push(format!(
+                "recorded with --seed={}, but this has --seed={}",
+                self.seed, current.seed
+            ));
+        }
+        diffs
+    }
+}
@@ -41,7 +41,8 @@ pub const NANOS_PER_RCB: f64 = 10.0;
 /// Virtual nanoseconds elapsed per nondeterministic instruction other than system calls.
 pub const NANOS_PER_NONDET_INSTR: f64 = 25.0;
 
-/// Virtual nanoseconds elapsed per step of the scheduler.
+/// Default virtual nanoseconds elapsed per step of the scheduler.  This can be overridden
+/// with `--virtual-quantum`.
 pub const NANOS_PER_SCHED: f64 = 500_000.0;
 
 // TODO: should map addresses to physical addresses.
@@ -153,6 +154,16 @@ impl LogicalTime {
         (self.0 as f64 / NANOS_PER_RCB) as u64
     }
 
+    /// Round up to the next multiple of `granularity` nanoseconds, saturating at `MAX`.
+    pub fn round_up_to(self, granularity: u64) -> Self {
+        let rem = self.0 % granularity;
+        if rem == 0 {
+            self
+        } else {
+            LogicalTime(self.0.saturating_add(granularity - rem))
+        }
+    }
+
     /// Test if the quantity is zero nanoseconds.
     pub fn is_zero(&self) -> bool {
         self.0 == 0
@@ -246,6 +257,15 @@ impl From<LogicalTime> for Timeval {
     }
 }
 
+#[test]
+fn round_up_nanoseconds() {
+    assert_eq!(LogicalTime(0).round_up_to(1000), LogicalTime(0));
+    assert_eq!(LogicalTime(1).round_up_to(1000), LogicalTime(1000));
+    assert_eq!(LogicalTime(2000).round_up_to(1000), LogicalTime(2000));
+    assert_eq!(LogicalTime(2001).round_up_to(1000), LogicalTime(3000));
+    assert_eq!(LogicalTime::MAX.round_up_to(1000), LogicalTime::MAX);
+}
+
 #[test]
 fn print_nanoseconds() {
     let ns1 = LogicalTime(946_684_799_000_000_000);
@@ -255,6 +275,21 @@ fn print_nanoseconds() {
     assert_eq!(format!("{}", ns2), "729_860_000ns");
 }
 
+#[test]
+fn cpu_time_excludes_inherited_time() {
+    let mut time = GlobalTime::default();
+    let parent = DetTid::from_raw(1);
+    let child = DetTid::from_raw(2);
+    time.update_global_time(parent, LogicalTime(100));
+    time.update_global_time(parent, LogicalTime(250));
+    // The child's clock starts as a copy of its parent's.
+    time.update_global_time(child, LogicalTime(250));
+    time.update_global_time(child, LogicalTime(300));
+    assert_eq!(time.cpu_time(parent), Duration::from_nanos(150));
+    assert_eq!(time.cpu_time(child), Duration::from_nanos(50));
+    assert_eq!(time.cpu_time(DetTid::from_raw(3)), Duration::ZERO);
+}
+
 /// The same basic type alias as nanoseconds. Just for clarity/readability.
 pub type Microseconds = u64;
 
@@ -463,6 +498,10 @@ pub struct GlobalTime {
     /// waiting.
     time_vector: HashMap<DetTid, LogicalTime>,
 
+    /// The first time reported by each thread.  A new thread starts from a copy of its
+    /// parent's clock, so this is where its own work (i.e. its CPU time) begins.
+    births: HashMap<DetTid, LogicalTime>,
+
     /// A source of central, logically external, time passage generated by the scheduler.
     extra_time: LogicalTime,
 
@@ -472,6 +511,9 @@ pub struct GlobalTime {
 
     /// Immutable. Simply copied from the Config.
     multiplier: f64,
+
+    /// Immutable. Virtual nanoseconds per scheduler step, copied from the Config.
+    quantum: u64,
 }
 
 impl GlobalTime {
@@ -481,9 +523,11 @@ impl GlobalTime {
         GlobalTime {
             starting_nanos: LogicalTime::from_micros(micros_from_utc(&cfg.epoch)),
             time_vector: HashMap::new(),
+            births: HashMap::new(),
             extra_time: LogicalTime::from_nanos(0),
             total: base.as_nanos(),
             multiplier: cfg.clock_multiplier.unwrap_or(1.0),
+            quantum: cfg.virtual_quantum,
         }
     }
 
@@ -515,6 +559,7 @@ impl GlobalTime {
             let LogicalTime(diff) = newtime - old;
             self.bump_total(Duration::from_nanos(diff));
         } else {
+            self.births.insert(tid, newtime);
             // Don't add starting_nanos in because it's already accounted for
             // and we don't want to count it multiple times anyway:
             self.bump_total(Duration::from_nanos(newtime.0));
@@ -543,7 +588,7 @@ impl GlobalTime {
     /// This is effectively used to account for "time" consumed by the scheduler, and to
     /// ensure monotonic increase of global time while scheduling.
     pub fn add_scheduler_time(&mut self) -> LogicalTime {
-        let delta = Duration::from_nanos((NANOS_PER_SCHED * self.multiplier) as u64);
+        let delta = Duration::from_nanos((self.quantum as f64 * self.multiplier) as u64);
         self.add_extra_time(delta)
     }
 
@@ -571,6 +616,15 @@ impl GlobalTime {
             })
     }
 
+    /// The virtual CPU time of a particular thread: the work it has done since it was created,
+    /// not counting what it inherited from its parent.  Zero for unknown threads.
+    pub fn cpu_time(&self, dtid: DetTid) -> Duration {
+        match (self.time_vector.get(&dtid), self.births.get(&dtid)) {
+            (Some(now), Some(birth)) => now.duration_since(*birth),
+            _ => Duration::ZERO,
+        }
+    }
+
     #[allow(unused)]
     /// Deterministic lower bound on the amount of work that has happened across all
     /// threads, starting with the same epoch time as individual thread clocks.
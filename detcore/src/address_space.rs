@@ -0,0 +1,233 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Deterministic placement of guest memory mappings (`--deterministic-mmap`).
+//!
+//! Rather than letting the kernel pick addresses (which depend on ASLR and on whatever is
+//! already mapped), each address space carves anonymous and file mappings out of a fixed
+//! window, lowest address first, and emulates the program break in a second window.
+//!
+//! Each address space also caches its shared mappings, by which futexes shared between
+//! processes are identified.
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Page size assumed for rounding lengths and addresses.
+pub const PAGE_SIZE: u64 = 4096;
+
+/// Start of the window in which `mmap` placements are chosen.
+pub const MMAP_BASE: u64 = 0x2000_0000_0000;
+
+/// End (exclusive) of the window in which `mmap` placements are chosen.
+pub const MMAP_END: u64 = 0x5000_0000_0000;
+
+/// Address of the initial program break.
+pub const BRK_BASE: u64 = 0x1000_0000_0000;
+
+/// The program break cannot grow past this point.
+pub const BRK_END: u64 = MMAP_BASE;
+
+/// Round up to a multiple of the page size.
+pub fn page_align(n: u64) -> u64 {
+    n.saturating_add(PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+/// The mappings detcore knows about in one guest address space.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddressSpace {
+    /// Occupied ranges within the mmap window, as start => end (exclusive).  Adjacent
+    /// ranges are not merged.
+    regions: BTreeMap<u64, u64>,
+    /// The current (emulated) program break.
+    brk: u64,
+    /// The shared mappings, as last read from the guest's `/proc/pid/maps`, or `None` if a
+    /// mapping has been added, moved or removed since.
+    #[serde(skip)]
+    shared_mappings: Option<Vec<SharedMapping>>,
+}
+
+/// A mapping of an object (a file, or shared memory) which is shared with other processes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedMapping {
+    /// Start address of the mapping.
+    pub start: u64,
+    /// End address (exclusive) of the mapping.
+    pub end: u64,
+    /// Major and minor number of the device holding the object.
+    pub dev: (u64, u64),
+    /// Inode of the object.
+    pub inode: u64,
+    /// Offset within the object at which the mapping starts.
+    pub offset: u64,
+}
+
+impl Default for AddressSpace {
+    fn default() -> Self {
+        AddressSpace {
+            regions: BTreeMap::new(),
+            brk: BRK_BASE,
+            shared_mappings: None,
+        }
+    }
+}
+
+impl AddressSpace {
+    /// Pick the lowest free, page-aligned range of at least `len` bytes in the mmap window
+    /// and mark it as occupied.  Returns `None` if the window is full.
+    pub fn allocate(&mut self, len: u64) -> Option<u64> {
+        let len = page_align(len);
+        let mut candidate = MMAP_BASE;
+        for (&start, &end) in self.regions.range(MMAP_BASE..) {
+            if start >= candidate.checked_add(len)? {
+                break;
+            }
+            candidate = candidate.max(end);
+        }
+        if candidate.checked_add(len)? > MMAP_END {
+            return None;
+        }
+        self.reserve(candidate, len);
+        Some(candidate)
+    }
+
+    /// Mark a range as occupied, e.g. because a mapping was placed there with `MAP_FIXED`
+    /// or by the kernel.  Ranges outside the mmap window are not tracked.
+    pub fn reserve(&mut self, addr: u64, len: u64) {
+        let start = addr.max(MMAP_BASE);
+        let end = addr.saturating_add(page_align(len)).min(MMAP_END);
+        if start < end {
+            // Drop any overlap first, so that the regions stay disjoint.
+            self.release(start, end - start);
+            self.regions.insert(start, end);
+        }
+    }
+
+    /// Mark a range as free again (`munmap`), splitting any regions which straddle it.
+    pub fn release(&mut self, addr: u64, len: u64) {
+        let start = addr;
+        let end = addr.saturating_add(page_align(len));
+        let overlapping: Vec<(u64, u64)> = self
+            .regions
+            .range(..end)
+            .filter(|(_, &e)| e > start)
+            .map(|(&s, &e)| (s, e))
+            .collect();
+        for (s, e) in overlapping {
+            self.regions.remove(&s);
+            if s < start {
+                self.regions.insert(s, start);
+            }
+            if e > end {
+                self.regions.insert(end, e);
+            }
+        }
+    }
+
+    /// Mark the region starting at `addr` as free again, e.g. a detached shared memory
+    /// segment, whose length is not given.
+    pub fn release_region(&mut self, addr: u64) {
+        self.regions.remove(&addr);
+    }
+
+    /// How many bytes are occupied in the mmap window, or by the program break.
+    pub fn mapped(&self) -> u64 {
+        let regions: u64 = self.regions.iter().map(|(start, end)| end - start).sum();
+        regions + (page_align(self.brk) - BRK_BASE)
+    }
+
+    /// How many bytes of a range in the mmap window are not occupied yet.
+    pub fn unoccupied(&self, addr: u64, len: u64) -> u64 {
+        let start = addr.max(MMAP_BASE);
+        let end = addr.saturating_add(page_align(len)).min(MMAP_END);
+        if start >= end {
+            return 0;
+        }
+        let occupied: u64 = self
+            .regions
+            .range(..end)
+            .filter(|(_, &e)| e > start)
+            .map(|(&s, &e)| e.min(end) - s.max(start))
+            .sum();
+        end - start - occupied
+    }
+
+    /// The current program break.
+    pub fn brk(&self) -> u64 {
+        self.brk
+    }
+
+    /// Record a new program break.
+    pub fn set_brk(&mut self, brk: u64) {
+        self.brk = brk;
+    }
+
+    /// The cached shared mappings, unless they need to be read again.
+    pub fn shared_mappings(&self) -> Option<&[SharedMapping]> {
+        self.shared_mappings.as_deref()
+    }
+
+    /// Cache the shared mappings, as just read from the guest.
+    pub fn set_shared_mappings(&mut self, mappings: Vec<SharedMapping>) {
+        self.shared_mappings = Some(mappings);
+    }
+
+    /// Forget the cached shared mappings, because a mapping was added, moved or removed.
+    pub fn mappings_changed(&mut self) {
+        self.shared_mappings = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_fit_allocation() {
+        let mut space = AddressSpace::default();
+        let a = space.allocate(1).unwrap();
+        let b = space.allocate(3 * PAGE_SIZE).unwrap();
+        let c = space.allocate(PAGE_SIZE).unwrap();
+        assert_eq!(a, MMAP_BASE);
+        assert_eq!(b, MMAP_BASE + PAGE_SIZE);
+        assert_eq!(c, MMAP_BASE + 4 * PAGE_SIZE);
+
+        // Punch a hole in the middle of `b`, which is then reused.
+        space.release(b + PAGE_SIZE, PAGE_SIZE);
+        assert_eq!(space.allocate(PAGE_SIZE), Some(b + PAGE_SIZE));
+        // Too big for any hole:
+        assert_eq!(space.allocate(2 * PAGE_SIZE), Some(c + PAGE_SIZE));
+
+        // Something else was mapped right after `c`'s neighbor:
+        space.reserve(c + 3 * PAGE_SIZE, PAGE_SIZE);
+        assert_eq!(space.allocate(PAGE_SIZE), Some(c + 4 * PAGE_SIZE));
+    }
+
+    #[test]
+    fn mapped_bytes() {
+        let mut space = AddressSpace::default();
+        assert_eq!(space.mapped(), 0);
+        let a = space.allocate(3 * PAGE_SIZE).unwrap();
+        space.set_brk(BRK_BASE + 1);
+        assert_eq!(space.mapped(), 4 * PAGE_SIZE);
+
+        assert_eq!(space.unoccupied(a, 3 * PAGE_SIZE), 0);
+        assert_eq!(
+            space.unoccupied(a + PAGE_SIZE, 4 * PAGE_SIZE),
+            2 * PAGE_SIZE
+        );
+        // Outside of the window, nothing is tracked.
+        assert_eq!(space.unoccupied(BRK_BASE, PAGE_SIZE), 0);
+
+        space.release(a + PAGE_SIZE, PAGE_SIZE);
+        assert_eq!(space.unoccupied(a, 3 * PAGE_SIZE), PAGE_SIZE);
+        assert_eq!(space.mapped(), 3 * PAGE_SIZE);
+    }
+}
@@ -0,0 +1,152 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! The audit of the syscalls hermit does not make deterministic, for `--audit-syscalls`.
+//!
+//! Most syscalls are emulated, or have their results made deterministic. The rest are either
+//! passed through to the host, whose answers may differ from one run to the next, or stubbed
+//! out, e.g. failed with `ENOSYS`, which the guest may not cope with. Each kind of such call is
+//! counted, and the stack of its first occurrence kept, so that the summary at the end of the run
+//! says where the program still depends on the host.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::types::DetTid;
+
+/// How a syscall falls short of being deterministic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Serialize, Deserialize)]
+pub enum Imperfection {
+    /// Hermit does not handle the syscall, and it was made on the host.
+    PassedThrough,
+    /// The syscall was answered with a stand-in, rather than as the kernel would answer it.
+    Stubbed,
+}
+
+impl fmt::Display for Imperfection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Imperfection::PassedThrough => write!(f, "passed through to the host"),
+            Imperfection::Stubbed => write!(f, "stubbed"),
+        }
+    }
+}
+
+/// One kind of audited syscall.
+#[derive(Debug)]
+struct AuditedCall {
+    count: u64,
+    /// The thread which made the first call.
+    first_dettid: DetTid,
+    /// The stack of the first call, if it could be unwound.
+    first_stack: Option<String>,
+}
+
+/// The syscalls of a run which were not made deterministic, by name and imperfection.
+#[derive(Debug, Default)]
+pub struct SyscallAudit {
+    calls: BTreeMap<(String, Imperfection), AuditedCall>,
+}
+
+impl SyscallAudit {
+    /// Count a call. Returns true if it is the first of its kind, whose stack should then be
+    /// added with `set_stack`.
+    pub fn record(&mut self, name: String, how: Imperfection, dettid: DetTid) -> bool {
+        let mut first = false;
+        let call = self.calls.entry((name, how)).or_insert_with(|| {
+            first = true;
+            AuditedCall {
+                count: 0,
+                first_dettid: dettid,
+                first_stack: None,
+            }
+        });
+        call.count += 1;
+        first
+    }
+
+    /// Keep the stack of the first call of a kind.
+    pub fn set_stack(&mut self, name: String, how: Imperfection, stack: String) {
+        if let Some(call) = self.calls.get_mut(&(name, how)) {
+            call.first_stack.get_or_insert(stack);
+        }
+    }
+}
+
+impl fmt::Display for SyscallAudit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.calls.is_empty() {
+            return writeln!(f, "Syscall audit: every syscall was made deterministic.");
+        }
+        let total: u64 = self.calls.values().map(|call| call.count).sum();
+        writeln!(
+            f,
+            "Syscall audit: {} call(s) to {} syscall(s) were not made deterministic:",
+            total,
+            self.calls.len()
+        )?;
+        for ((name, how), call) in &self.calls {
+            writeln!(
+                f,
+                "  {} ({}): {} call(s), first by thread {}",
+                name, how, call.count, call.first_dettid
+            )?;
+            match &call.first_stack {
+                Some(stack) => {
+                    for line in stack.lines() {
+                        writeln!(f, "      {}", line)?;
+                    }
+                }
+                None => writeln!(f, "      (no stack)")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_calls_and_keeps_the_first_stack() {
+        let (t3, t4) = (DetTid::from_raw(3), DetTid::from_raw(4));
+        let mut audit = SyscallAudit::default();
+        assert!(audit.record("io_uring_enter".to_owned(), Imperfection::PassedThrough, t4));
+        audit.set_stack(
+            "io_uring_enter".to_owned(),
+            Imperfection::PassedThrough,
+            "main\nstart".to_owned(),
+        );
+        assert!(!audit.record("io_uring_enter".to_owned(), Imperfection::PassedThrough, t3));
+        audit.set_stack(
+            "io_uring_enter".to_owned(),
+            Imperfection::PassedThrough,
+            "later".to_owned(),
+        );
+        assert!(audit.record("rseq".to_owned(), Imperfection::Stubbed, t3));
+
+        let report = audit.to_string();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "Syscall audit: 3 call(s) to 2 syscall(s) were not made deterministic:",
+                "  io_uring_enter (passed through to the host): 2 call(s), first by thread 4",
+                "      main",
+                "      start",
+                "  rseq (stubbed): 1 call(s), first by thread 3",
+                "      (no stack)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,303 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! 32-bit x86 guests: i386 binaries, and x32 ones.
+//!
+//! Their syscalls enter the kernel through its compat paths, with numbers of their own.  An
+//! i386 syscall also takes its arguments in other registers, and lays out its structures with
+//! 32-bit words.  Reverie decodes every syscall as a native one, so those of a 32-bit process
+//! are decoded again here, from its registers, before they are handled.
+
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// The x32 syscall numbers are those of x86_64 (or x32-specific ones, from 512) with this bit
+/// set.
+pub const X32_SYSCALL_BIT: u64 = 0x4000_0000;
+
+/// A 32-bit x86 ABI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompatAbi {
+    /// 32-bit x86, entering the kernel with `int 0x80` or `sysenter`.
+    I386,
+    /// x86_64 with 32-bit pointers, entering the kernel with `syscall`.
+    X32,
+}
+
+impl fmt::Display for CompatAbi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompatAbi::I386 => write!(f, "i386"),
+            CompatAbi::X32 => write!(f, "x32"),
+        }
+    }
+}
+
+impl CompatAbi {
+    /// The 32-bit ABI an ELF binary uses, given the start of its header, if it uses one.
+    pub fn of_elf(header: &[u8]) -> Option<Self> {
+        const ELFCLASS32: u8 = 1;
+        const EM_386: u16 = 3;
+        const EM_X86_64: u16 = 62;
+        if header.len() < 20 || header[..4] != *b"\x7fELF" || header[4] != ELFCLASS32 {
+            return None;
+        }
+        match u16::from_le_bytes([header[18], header[19]]) {
+            EM_386 => Some(CompatAbi::I386),
+            EM_X86_64 => Some(CompatAbi::X32),
+            _ => None,
+        }
+    }
+
+    /// The 32-bit ABI of the binary at `path` (e.g. `/proc/<pid>/exe`), if it uses one and can
+    /// be read.
+    pub fn of_exe(path: &Path) -> Option<Self> {
+        let mut header = [0; 20];
+        File::open(path).ok()?.read_exact(&mut header).ok()?;
+        Self::of_elf(&header)
+    }
+
+    /// The size of a `time_t`, and of the other fields of `struct timeval` and `struct
+    /// timespec`.  x32 has a 64-bit `time_t`, i386 a 32-bit one.
+    pub fn time_size(self) -> usize {
+        match self {
+            CompatAbi::I386 => 4,
+            CompatAbi::X32 => 8,
+        }
+    }
+}
+
+/// The compat syscalls which are not just passed through to the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompatOp {
+    /// `exit`
+    Exit,
+    /// `exit_group`
+    ExitGroup,
+    /// `fork`
+    Fork,
+    /// `vfork`
+    Vfork,
+    /// `clone`
+    Clone,
+    /// `clone3`
+    Clone3,
+    /// `execve`
+    Execve,
+    /// `execveat`
+    Execveat,
+    /// `time`
+    Time,
+    /// `gettimeofday`
+    Gettimeofday,
+    /// `clock_gettime`, with the ABI's `struct timespec`.
+    ClockGettime,
+    /// `clock_gettime64`, with a 64-bit `struct timespec` (i386 only).
+    ClockGettime64,
+    /// `getrandom`
+    Getrandom,
+}
+
+impl CompatOp {
+    /// The syscall's name.
+    pub fn name(self) -> &'static str {
+        match self {
+            CompatOp::Exit => "exit",
+            CompatOp::ExitGroup => "exit_group",
+            CompatOp::Fork => "fork",
+            CompatOp::Vfork => "vfork",
+            CompatOp::Clone => "clone",
+            CompatOp::Clone3 => "clone3",
+            CompatOp::Execve => "execve",
+            CompatOp::Execveat => "execveat",
+            CompatOp::Time => "time",
+            CompatOp::Gettimeofday => "gettimeofday",
+            CompatOp::ClockGettime => "clock_gettime",
+            CompatOp::ClockGettime64 => "clock_gettime64",
+            CompatOp::Getrandom => "getrandom",
+        }
+    }
+
+    /// Whether the syscall reads the time.
+    pub fn reads_time(self) -> bool {
+        matches!(
+            self,
+            CompatOp::Time
+                | CompatOp::Gettimeofday
+                | CompatOp::ClockGettime
+                | CompatOp::ClockGettime64
+        )
+    }
+}
+
+/// A syscall made by a 32-bit process, decoded from its registers at the syscall stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompatCall {
+    /// The ABI the syscall was made with.
+    pub abi: CompatAbi,
+    /// The syscall number, without the x32 bit.
+    pub nr: u64,
+    /// The arguments, in order.  Only the low 32 bits of an i386 argument count.
+    pub args: [u64; 6],
+}
+
+impl CompatCall {
+    /// Decode the syscall a thread of a process using `abi` is stopped at.
+    pub fn decode(abi: CompatAbi, regs: &libc::user_regs_struct) -> Self {
+        match abi {
+            CompatAbi::I386 => CompatCall {
+                abi,
+                nr: regs.orig_rax & 0xffff_ffff,
+                args: [regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi, regs.rbp]
+                    .map(|arg| arg & 0xffff_ffff),
+            },
+            CompatAbi::X32 => CompatCall {
+                abi,
+                nr: regs.orig_rax & !X32_SYSCALL_BIT,
+                args: [regs.rdi, regs.rsi, regs.rdx, regs.r10, regs.r8, regs.r9],
+            },
+        }
+    }
+
+    /// What the syscall is, if it is one that is not just passed through.
+    pub fn op(&self) -> Option<CompatOp> {
+        let op = match (self.abi, self.nr) {
+            (CompatAbi::I386, 1) | (CompatAbi::X32, 60) => CompatOp::Exit,
+            (CompatAbi::I386, 252) | (CompatAbi::X32, 231) => CompatOp::ExitGroup,
+            (CompatAbi::I386, 2) | (CompatAbi::X32, 57) => CompatOp::Fork,
+            (CompatAbi::I386, 190) | (CompatAbi::X32, 58) => CompatOp::Vfork,
+            (CompatAbi::I386, 120) | (CompatAbi::X32, 56) => CompatOp::Clone,
+            (_, 435) => CompatOp::Clone3,
+            (CompatAbi::I386, 11) | (CompatAbi::X32, 520) => CompatOp::Execve,
+            (CompatAbi::I386, 358) | (CompatAbi::X32, 545) => CompatOp::Execveat,
+            (CompatAbi::I386, 13) | (CompatAbi::X32, 201) => CompatOp::Time,
+            (CompatAbi::I386, 78) | (CompatAbi::X32, 96) => CompatOp::Gettimeofday,
+            (CompatAbi::I386, 265) | (CompatAbi::X32, 228) => CompatOp::ClockGettime,
+            (CompatAbi::I386, 403) => CompatOp::ClockGettime64,
+            (CompatAbi::I386, 355) | (CompatAbi::X32, 318) => CompatOp::Getrandom,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// The syscall's name, or its ABI and number if it is not one we know by name.
+    pub fn name(&self) -> String {
+        match self.op() {
+            Some(op) => format!("{} {}", self.abi, op.name()),
+            None => format!("{} syscall {}", self.abi, self.nr),
+        }
+    }
+
+    /// Whether the syscall reads one of the CPU-time clocks, which are named by negative clock
+    /// IDs, encoding the process or thread whose CPU time they count.
+    pub fn is_cpu_clock(&self) -> bool {
+        let clock_gettime = matches!(
+            self.op(),
+            Some(CompatOp::ClockGettime | CompatOp::ClockGettime64)
+        );
+        clock_gettime && (self.args[0] as i32) < 0
+    }
+
+    /// The child's thread ID address of a `clone`, which the kernel clears when the child exits
+    /// (with `CLONE_CHILD_CLEARTID`).  The arguments of i386's `clone` come in another order.
+    pub fn clone_child_tid(&self) -> u64 {
+        match self.abi {
+            CompatAbi::I386 => self.args[4],
+            CompatAbi::X32 => self.args[3],
+        }
+    }
+}
+
+/// Lay out the fields of a `struct timeval` or `struct timespec` (or a lone `time_t`), each
+/// `size` bytes wide.
+pub fn encode_time_fields(size: usize, fields: &[i64]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(size * fields.len());
+    for field in fields {
+        match size {
+            4 => bytes.extend((*field as i32).to_le_bytes()),
+            _ => bytes.extend(field.to_le_bytes()),
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn elf_header(class: u8, machine: u16) -> Vec<u8> {
+        let mut header = b"\x7fELF".to_vec();
+        header.extend([class, 1, 1, 0]);
+        header.extend([0; 10]);
+        header.extend(machine.to_le_bytes());
+        header
+    }
+
+    #[test]
+    fn compat_elf_abis() {
+        assert_eq!(CompatAbi::of_elf(&elf_header(1, 3)), Some(CompatAbi::I386));
+        assert_eq!(CompatAbi::of_elf(&elf_header(1, 62)), Some(CompatAbi::X32));
+        assert_eq!(CompatAbi::of_elf(&elf_header(2, 62)), None);
+        assert_eq!(CompatAbi::of_elf(b"#!/bin/sh\necho hello\n"), None);
+        assert_eq!(CompatAbi::of_elf(b"\x7fELF"), None);
+    }
+
+    fn regs() -> libc::user_regs_struct {
+        // Safe: the registers are plain integers.
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.rbx = 1;
+        regs.rcx = 2;
+        regs.rdx = 3;
+        regs.rsi = 4;
+        regs.rdi = 5;
+        regs.rbp = 6;
+        regs.r10 = 7;
+        regs.r8 = 8;
+        regs.r9 = 9;
+        regs
+    }
+
+    #[test]
+    fn decode_i386() {
+        let mut regs = regs();
+        regs.orig_rax = 265;
+        regs.rcx = 0xffff_ffff_0000_1000;
+        let call = CompatCall::decode(CompatAbi::I386, &regs);
+        assert_eq!(call.args, [1, 0x1000, 3, 4, 5, 6]);
+        assert_eq!(call.op(), Some(CompatOp::ClockGettime));
+        assert_eq!(call.name(), "i386 clock_gettime");
+        assert_eq!(call.clone_child_tid(), 5);
+    }
+
+    #[test]
+    fn decode_x32() {
+        let mut regs = regs();
+        regs.orig_rax = X32_SYSCALL_BIT | 228;
+        let call = CompatCall::decode(CompatAbi::X32, &regs);
+        assert_eq!(call.nr, 228);
+        assert_eq!(call.args, [5, 4, 3, 7, 8, 9]);
+        assert_eq!(call.op(), Some(CompatOp::ClockGettime));
+        assert_eq!(call.clone_child_tid(), 7);
+
+        regs.orig_rax = X32_SYSCALL_BIT | 1;
+        let call = CompatCall::decode(CompatAbi::X32, &regs);
+        assert_eq!(call.op(), None);
+        assert_eq!(call.name(), "x32 syscall 1");
+    }
+
+    #[test]
+    fn time_fields() {
+        assert_eq!(
+            encode_time_fields(4, &[1, -1]),
+            [1, 0, 0, 0, 255, 255, 255, 255]
+        );
+        assert_eq!(encode_time_fields(8, &[2]), [2, 0, 0, 0, 0, 0, 0, 0]);
+    }
+}
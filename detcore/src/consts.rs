@@ -24,7 +24,9 @@ pub static DET_SPECIAL_INODE_OFFSET: DetInode = 1000;
 /// `DET_SPECIAL_INODE_OFFSET`.
 pub static DET_INODE_OFFSET: DetInode = 9000;
 
-pub const DEFAULT_HOSTNAME: &str = "hermetic-container.local";
-
 /// A convention of how we set up our PID namespace leaves us with a starting pid of 3.
 pub const ROOT_DETPID: DetPid = DetPid::from_raw(3);
+
+/// The target of the spans which hermit exports to an OpenTelemetry collector. These are left
+/// out of the logs, whose lines would otherwise be prefixed with them.
+pub const SPAN_TARGET: &str = "hermit::spans";
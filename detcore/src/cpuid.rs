@@ -8,27 +8,187 @@
 
 //! cpuid interception
 
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
 use raw_cpuid::CpuIdResult;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Names of the CPU profiles built into detcore, see `--cpu-profile`.
+pub const BUILTIN_CPU_PROFILES: &[&str] = &["default", "baseline", "avx2", "avx512"];
+
+lazy_static! {
+    /// The profiles loaded so far, by the name or path they were given as.  Every guest process
+    /// reports the same profile, which is only read from its file once.
+    static ref LOADED_PROFILES: Mutex<HashMap<String, CpuProfile>> = Mutex::new(HashMap::new());
+}
+
+// Feature bits in leaf 1, ecx.
+const LEAF1_ECX_SSSE3: u32 = 1 << 9;
+const LEAF1_ECX_FMA: u32 = 1 << 12;
+const LEAF1_ECX_SSE4_1: u32 = 1 << 19;
+const LEAF1_ECX_SSE4_2: u32 = 1 << 20;
+const LEAF1_ECX_POPCNT: u32 = 1 << 23;
+const LEAF1_ECX_XSAVE: u32 = 1 << 26;
+const LEAF1_ECX_OSXSAVE: u32 = 1 << 27;
+const LEAF1_ECX_AVX: u32 = 1 << 28;
+
+// Feature bits in leaf 7 (subleaf 0), ebx.
+const LEAF7_EBX_BMI1: u32 = 1 << 3;
+const LEAF7_EBX_AVX2: u32 = 1 << 5;
+const LEAF7_EBX_BMI2: u32 = 1 << 8;
+const LEAF7_EBX_AVX512F: u32 = 1 << 16;
+const LEAF7_EBX_AVX512DQ: u32 = 1 << 17;
+const LEAF7_EBX_AVX512CD: u32 = 1 << 28;
+const LEAF7_EBX_AVX512BW: u32 = 1 << 30;
+const LEAF7_EBX_AVX512VL: u32 = 1 << 31;
+
+const LEAF1_ECX_AVX_ALL: u32 = LEAF1_ECX_FMA | LEAF1_ECX_XSAVE | LEAF1_ECX_OSXSAVE | LEAF1_ECX_AVX;
+const LEAF7_EBX_AVX2_ALL: u32 = LEAF7_EBX_BMI1 | LEAF7_EBX_AVX2 | LEAF7_EBX_BMI2;
+const LEAF7_EBX_AVX512_ALL: u32 = LEAF7_EBX_AVX512F
+    | LEAF7_EBX_AVX512DQ
+    | LEAF7_EBX_AVX512CD
+    | LEAF7_EBX_AVX512BW
+    | LEAF7_EBX_AVX512VL;
+
+/// A fixed CPU model to report to the guest: the `[eax, ebx, ecx, edx]` result of every
+/// cpuid leaf.  This is also the format of a `--cpu-profile` file, as JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CpuProfile {
+    /// Leaves `0..`, where leaf 0 reports the highest basic leaf in eax.
+    basic: Vec<[u32; 4]>,
+    /// Leaves `0x80000000..`, where the first reports the highest extended leaf in eax.
+    extended: Vec<[u32; 4]>,
+    /// Leaves whose results depend on the subleaf in ecx (e.g. the cache topology in leaf 4),
+    /// indexed by subleaf.  These take precedence over `basic` and `extended`.
+    #[serde(default)]
+    subleaves: BTreeMap<u32, Vec<[u32; 4]>>,
+}
+
+impl CpuProfile {
+    /// Look up one of the profiles built into detcore by name.
+    pub fn builtin(name: &str) -> Option<Self> {
+        let mut profile = CpuProfile {
+            basic: CPUIDS.iter().map(to_array).collect(),
+            extended: EXTENDED_CPUIDS.iter().map(to_array).collect(),
+            subleaves: BTreeMap::new(),
+        };
+        match name {
+            "default" => {}
+            "baseline" => {
+                // Plain x86-64: no AVX or newer vector extensions.
+                profile.basic[1][2] &= !LEAF1_ECX_AVX_ALL;
+                profile.basic[7][1] &= !(LEAF7_EBX_AVX2_ALL | LEAF7_EBX_AVX512_ALL);
+            }
+            "avx2" | "avx512" => {
+                profile.basic[1][2] |= LEAF1_ECX_SSSE3
+                    | LEAF1_ECX_SSE4_1
+                    | LEAF1_ECX_SSE4_2
+                    | LEAF1_ECX_POPCNT
+                    | LEAF1_ECX_AVX_ALL;
+                profile.basic[7][1] |= LEAF7_EBX_AVX2_ALL;
+                if name == "avx512" {
+                    profile.basic[7][1] |= LEAF7_EBX_AVX512_ALL;
+                }
+            }
+            _ => return None,
+        }
+        Some(profile)
+    }
+
+    /// Read a profile from a JSON file.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+        let profile: CpuProfile = serde_json::from_str(&text)
+            .map_err(|e| format!("unable to parse {}: {}", path.display(), e))?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    /// Look up a built-in profile by name, or otherwise read it from a file.
+    pub fn from_name_or_path(name_or_path: &str) -> Result<Self, String> {
+        match Self::builtin(name_or_path) {
+            Some(profile) => Ok(profile),
+            None => Self::from_file(Path::new(name_or_path)),
+        }
+    }
+
+    /// Load a `--cpu-profile`, by name or from a file, reading the file only the first time.
+    /// Callers can use this to report a bad profile before the guest starts.
+    pub fn load(name_or_path: &str) -> Result<Self, String> {
+        let mut loaded = LOADED_PROFILES.lock().unwrap();
+        if let Some(profile) = loaded.get(name_or_path) {
+            return Ok(profile.clone());
+        }
+        let profile = Self::from_name_or_path(name_or_path).map_err(|e| {
+            format!(
+                "Invalid --cpu-profile, expected one of {:?} or a file: {}",
+                BUILTIN_CPU_PROFILES, e
+            )
+        })?;
+        loaded.insert(name_or_path.to_owned(), profile.clone());
+        Ok(profile)
+    }
+
+    /// Check that the highest leaves reported by the profile are actually present.
+    fn validate(&self) -> Result<(), String> {
+        match self.basic.first() {
+            Some([max, ..]) if (*max as usize) < self.basic.len() => {}
+            _ => return Err("basic leaf 0 must report the number of basic leaves".to_string()),
+        }
+        match self.extended.first() {
+            Some([max, ..]) if (*max & !0x80000000) as usize + 1 == self.extended.len() => {}
+            _ => {
+                return Err(
+                    "extended leaf 0x80000000 must report the number of extended leaves"
+                        .to_string(),
+                );
+            }
+        }
+        Ok(())
+    }
+}
 
-#[derive(Debug, Clone, Copy)]
-pub struct InterceptedCpuid();
+fn to_array(r: &CpuIdResult) -> [u32; 4] {
+    [r.eax, r.ebx, r.ecx, r.edx]
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InterceptedCpuid {
+    profile: CpuProfile,
+}
 
 impl InterceptedCpuid {
-    pub fn new() -> Self {
-        InterceptedCpuid()
+    /// Report the given CPU profile, or the default one if none is given.  Panics if the
+    /// profile cannot be loaded, which the CLI checks with `CpuProfile::load` beforehand.
+    pub fn new(cpu_profile: Option<&str>) -> Self {
+        let profile =
+            CpuProfile::load(cpu_profile.unwrap_or("default")).unwrap_or_else(|e| panic!("{}", e));
+        InterceptedCpuid { profile }
     }
 }
 
 impl InterceptedCpuid {
-    pub fn cpuid(&self, index: u32) -> Option<CpuIdResult> {
+    /// The result of cpuid for the given leaf (eax) and subleaf (ecx).  Leaves outside of the
+    /// profile report all zeroes.
+    pub fn cpuid(&self, index: u32, subindex: u32) -> CpuIdResult {
         let request = index as usize;
-        if request >= 0x80000000 && request < 0x80000000 + EXTENDED_CPUIDS.len() {
-            Some(EXTENDED_CPUIDS[request - 0x80000000])
-        } else if request < CPUIDS.len() {
-            Some(CPUIDS[request])
+        let profile = &self.profile;
+        let result = if let Some(subleaves) = profile.subleaves.get(&index) {
+            subleaves.get(subindex as usize)
+        } else if request >= 0x80000000 {
+            profile.extended.get(request - 0x80000000)
         } else {
-            None
-        }
+            profile.basic.get(request)
+        };
+        let [eax, ebx, ecx, edx] = result.copied().unwrap_or_default();
+        cpuid_result(eax, ebx, ecx, edx)
     }
 }
 
@@ -88,4 +248,30 @@ mod tests {
             EXTENDED_CPUIDS.len()
         );
     }
+
+    #[test]
+    fn builtin_profiles() {
+        for name in BUILTIN_CPU_PROFILES {
+            let profile = CpuProfile::builtin(name).unwrap();
+            assert_eq!(profile.validate(), Ok(()));
+        }
+        let avx2 = InterceptedCpuid::new(Some("avx2"));
+        assert_ne!(avx2.cpuid(7, 0).ebx & LEAF7_EBX_AVX2, 0);
+        assert_eq!(avx2.cpuid(7, 0).ebx & LEAF7_EBX_AVX512F, 0);
+        let avx512 = InterceptedCpuid::new(Some("avx512"));
+        assert_ne!(avx512.cpuid(7, 0).ebx & LEAF7_EBX_AVX512F, 0);
+        let baseline = InterceptedCpuid::new(Some("baseline"));
+        assert_eq!(baseline.cpuid(1, 0).ecx & LEAF1_ECX_AVX, 0);
+        assert_eq!(baseline.cpuid(0x40000000, 0), cpuid_result(0, 0, 0, 0));
+    }
+
+    #[test]
+    fn load_profiles() {
+        assert_eq!(
+            CpuProfile::load("avx2"),
+            Ok(CpuProfile::builtin("avx2").unwrap())
+        );
+        let err = CpuProfile::load("/nonexistent/cpu.json").unwrap_err();
+        assert!(err.starts_with("Invalid --cpu-profile"), "{}", err);
+    }
 }
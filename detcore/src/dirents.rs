@@ -12,6 +12,9 @@ use std::cmp::Ordering;
 use std::ffi::CString;
 use std::ptr;
 
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_pcg::Pcg64Mcg;
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -172,6 +175,75 @@ pub unsafe fn serialize_dirents(dents: &[Dirent64], bytes: &mut [u8]) -> usize {
     dents.len()
 }
 
+/// The complete listing of a directory being read with `getdents64`, in deterministic order.
+/// Shared (via `Arc`) by every dup of the fd.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DirStream {
+    /// All entries, with their offsets renumbered.
+    entries: Vec<Dirent64>,
+    /// Index of the next entry to return.
+    next: usize,
+}
+
+impl Dirent64 {
+    /// The entry's name, without the terminator and padding.
+    fn file_name(&self) -> &[u8] {
+        let bytes = self.name.as_bytes();
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        &bytes[..len]
+    }
+}
+
+impl DirStream {
+    /// Order the full listing of a directory: sorted by name, or (given a seed) shuffled,
+    /// always keeping "." and ".." in front.
+    pub fn new(mut entries: Vec<Dirent64>, shuffle_seed: Option<u64>) -> Self {
+        entries.sort();
+        if let Some(seed) = shuffle_seed {
+            let dots = entries
+                .iter()
+                .take_while(|ent| matches!(ent.file_name(), b"." | b".."))
+                .count();
+            entries[dots..].shuffle(&mut Pcg64Mcg::seed_from_u64(seed));
+        }
+        // The offset of an entry is where reading resumes after it, as with the kernel.  The
+        // host's offsets are filesystem-specific hashes.
+        for (i, ent) in entries.iter_mut().enumerate() {
+            ent.off = i as i64 + 1;
+        }
+        DirStream { entries, next: 0 }
+    }
+
+    /// Take as many of the remaining entries as fit in `len` bytes.
+    pub fn take(&mut self, len: usize) -> &[Dirent64] {
+        let start = self.next;
+        let mut used = 0;
+        while let Some(ent) = self.entries.get(self.next) {
+            used += ent.reclen as usize;
+            if used > len {
+                break;
+            }
+            self.next += 1;
+        }
+        &self.entries[start..self.next]
+    }
+
+    /// The offset of the next entry to be read.
+    pub fn tell(&self) -> u64 {
+        self.next as u64
+    }
+
+    /// Whether every entry has been read.
+    pub fn at_end(&self) -> bool {
+        self.next == self.entries.len()
+    }
+
+    /// Resume reading at the given offset (`seekdir`).
+    pub fn seek(&mut self, off: u64) {
+        self.next = (off as usize).min(self.entries.len());
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -657,4 +729,37 @@ mod test {
         let res2 = unsafe { deserialize_dirents64(vv.as_slice()) };
         assert_eq!(res.len(), res2.len());
     }
+
+    #[test]
+    fn dir_stream_sorts_and_pages() {
+        let dents = unsafe { deserialize_dirents64(HOME_DIRENTS64) };
+        let total = dents.len();
+        let mut stream = DirStream::new(dents.clone(), None);
+
+        // The first page holds exactly "." and ".." (24 bytes each).
+        let page = stream.take(50).to_vec();
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0].file_name(), b".");
+        assert_eq!(page[1].file_name(), b"..");
+        assert_eq!(page[1].off, 2);
+
+        let rest = stream.take(usize::MAX).to_vec();
+        assert_eq!(rest.len(), total - 2);
+        assert!(rest.windows(2).all(|w| w[0].file_name() < w[1].file_name()));
+        assert!(stream.take(usize::MAX).is_empty());
+
+        stream.seek(1);
+        assert_eq!(stream.take(24)[0].file_name(), b"..");
+
+        // A shuffle is a permutation of the same entries, and depends only on the seed.
+        let mut a = DirStream::new(dents.clone(), Some(42));
+        let mut b = DirStream::new(dents, Some(42));
+        let a = a.take(usize::MAX).to_vec();
+        assert_eq!(a, b.take(usize::MAX));
+        assert_eq!(a[0].file_name(), b".");
+        let mut names: Vec<_> = a.iter().map(|ent| ent.file_name().to_vec()).collect();
+        names[2..].sort();
+        let sorted: Vec<_> = rest.iter().map(|ent| ent.file_name().to_vec()).collect();
+        assert_eq!(names[2..], sorted[..]);
+    }
 }
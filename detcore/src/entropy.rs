@@ -0,0 +1,111 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! User-provided entropy (`--entropy-file`), replayed to the guest in place of seeded
+//! randomness.
+
+use std::collections::HashMap;
+use std::fs;
+
+use tracing::info;
+
+use crate::config::Config;
+use crate::types::DetTid;
+
+/// A byte stream read from one entropy file, consumed front to back.
+#[derive(Debug)]
+struct EntropyStream {
+    bytes: Vec<u8>,
+    pos: usize,
+}
+
+impl EntropyStream {
+    fn new(bytes: Vec<u8>) -> Self {
+        EntropyStream { bytes, pos: 0 }
+    }
+
+    /// Take up to `len` bytes, fewer if the stream runs dry.
+    fn take(&mut self, len: usize) -> &[u8] {
+        let start = self.pos;
+        self.pos = self.bytes.len().min(start.saturating_add(len));
+        &self.bytes[start..self.pos]
+    }
+}
+
+/// All the entropy files for a container, demultiplexed by thread.
+#[derive(Debug, Default)]
+pub struct EntropyPool {
+    /// Bytes reserved for one particular thread.
+    per_thread: HashMap<DetTid, EntropyStream>,
+    /// Bytes consumed by every thread which doesn't have its own stream.
+    shared: Option<EntropyStream>,
+}
+
+impl EntropyPool {
+    /// Load the entropy files named in the config.  Panics if a file cannot be read, which the
+    /// CLI checks before the run.
+    pub fn new(cfg: &Config) -> Self {
+        let mut pool = EntropyPool::default();
+        for (tid, path) in &cfg.entropy_file {
+            let bytes = fs::read(path).unwrap_or_else(|e| {
+                panic!("Unable to read --entropy-file {}: {}", path.display(), e)
+            });
+            info!(
+                "Loaded {} bytes of entropy from {} (thread: {:?})",
+                bytes.len(),
+                path.display(),
+                tid
+            );
+            // Multiple files for the same stream are concatenated.
+            let stream = match tid {
+                Some(tid) => pool
+                    .per_thread
+                    .entry(*tid)
+                    .or_insert_with(|| EntropyStream::new(Vec::new())),
+                None => pool
+                    .shared
+                    .get_or_insert_with(|| EntropyStream::new(Vec::new())),
+            };
+            stream.bytes.extend(bytes);
+        }
+        pool
+    }
+
+    /// Take up to `len` bytes on behalf of the given thread.  An empty result means the
+    /// thread's stream is exhausted (or it never had one).
+    pub fn take(&mut self, dettid: DetTid, len: usize) -> Vec<u8> {
+        let stream = match self.per_thread.get_mut(&dettid) {
+            Some(stream) => stream,
+            None => match &mut self.shared {
+                Some(stream) => stream,
+                None => return Vec::new(),
+            },
+        };
+        stream.take(len).to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demultiplex_entropy() {
+        let mut pool = EntropyPool::default();
+        pool.shared = Some(EntropyStream::new(vec![1, 2, 3, 4, 5]));
+        pool.per_thread
+            .insert(DetTid::from_raw(7), EntropyStream::new(vec![70, 71]));
+
+        assert_eq!(pool.take(DetTid::from_raw(3), 2), vec![1, 2]);
+        assert_eq!(pool.take(DetTid::from_raw(7), 3), vec![70, 71]);
+        assert_eq!(pool.take(DetTid::from_raw(5), 2), vec![3, 4]);
+        assert_eq!(pool.take(DetTid::from_raw(7), 1), Vec::<u8>::new());
+        assert_eq!(pool.take(DetTid::from_raw(3), 4), vec![5]);
+        assert_eq!(pool.take(DetTid::from_raw(3), 4), Vec::<u8>::new());
+    }
+}
@@ -13,13 +13,19 @@ use std::hash::Hash;
 use std::hash::Hasher;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
 
 use nix::fcntl::OFlag;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::dirents::DirStream;
+use crate::inotify::InotifyCookies;
+use crate::netrecord::NetStream;
 use crate::resources::ResourceID;
 use crate::stat::*;
+use crate::timers::TimerfdState;
 use crate::types::RawFd;
 use crate::types::*;
 
@@ -40,10 +46,14 @@ pub enum FdType {
     Pipe,
     /// memfd
     Memfd,
+    /// memfd standing in for a `memfd_secret` fd, which may only be mapped
+    Secretmem,
     /// pidfd
     Pidfd,
     /// userfaultfd
     Userfaultfd,
+    /// inotify instance
+    Inotify,
     ///
     Rng,
 }
@@ -58,7 +68,7 @@ impl Default for FdType {
 ///
 /// Notice `statbuf` can be cached here, this is because
 /// `stat` is valid as long as fd stays open.
-#[derive(Default, Debug, Eq, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct DetFd {
     /// underlying file descriptor
     pub(crate) fd: RawFd,
@@ -93,6 +103,23 @@ pub struct DetFd {
     pub(crate) stat: Option<DetStat>,
     /// resource
     pub(crate) resource: Option<ResourceID>,
+    /// Virtual timer state, for a timerfd which detcore virtualizes.  Shared with any
+    /// dups of this fd.
+    pub(crate) timer: Option<Arc<Mutex<TimerfdState>>>,
+    /// The listing of a directory, once it has been read with `getdents64` under
+    /// `--sort-dirents`.
+    pub(crate) dir: Option<Arc<Mutex<DirStream>>>,
+    /// For a socket connected to an external peer, its recording or replay under
+    /// `--record-network-to` or `--replay-network-from`.
+    pub(crate) net: Option<NetStream>,
+    /// For an inotify instance, the rename cookies handed out so far.  Shared with any dups
+    /// of this fd.
+    pub(crate) inotify: Option<Arc<Mutex<InotifyCookies>>>,
+    /// For a pidfd, the process it refers to (when we know it).
+    pub(crate) pidfd: Option<DetPid>,
+    /// For a pidfd, whether the kernel has been seen to report it readable, once its process
+    /// exited in the schedule.
+    pub(crate) pidfd_settled: bool,
 }
 
 impl PartialEq for DetFd {
@@ -101,6 +128,8 @@ impl PartialEq for DetFd {
     }
 }
 
+impl Eq for DetFd {}
+
 impl Hash for DetFd {
     // fd is owned by process and is unique per process/thread
     fn hash<H: Hasher>(&self, state: &mut H) {
@@ -127,6 +156,12 @@ impl DetFd {
             dirty: false,
             stat: None,
             resource: None,
+            timer: None,
+            dir: None,
+            net: None,
+            inotify: None,
+            pidfd: None,
+            pidfd_settled: false,
             // By default, we assume it matches the flags we were given:
             physically_nonblocking: oflags_nonblocking(bits),
         }
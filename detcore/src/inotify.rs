@@ -0,0 +1,106 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Determinizing the events read from an inotify instance.
+//!
+//! Under sequentialization, the guest's own filesystem operations (and hence the events
+//! they queue) happen in a deterministic order, and reads of the queue happen at points
+//! chosen by the scheduler.  What remains is the rename cookie, which the kernel draws from
+//! a global counter, and which we replace with a sequence number per inotify instance.
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// The fixed-size part of a `struct inotify_event`: wd, mask, cookie, len.
+const EVENT_HEADER_SIZE: usize = 16;
+
+/// Offset of the cookie within an event.
+const COOKIE_OFFSET: usize = 8;
+
+/// Offset of the name length within an event.
+const LEN_OFFSET: usize = 12;
+
+/// The deterministic cookies handed out by one inotify instance.  Shared with any dups of
+/// its fd.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct InotifyCookies {
+    /// Cookies of renames whose `IN_MOVED_TO` half has not been read yet.
+    pending: BTreeMap<u32, u32>,
+    /// The last cookie handed out.
+    last: u32,
+}
+
+impl InotifyCookies {
+    /// Replace the kernel's cookies in a buffer of events, as returned by a read of the
+    /// inotify fd.  Both halves of a rename get the same cookie, even when they are read
+    /// separately.
+    pub fn renumber(&mut self, buf: &mut [u8]) {
+        let mut pos = 0;
+        while pos + EVENT_HEADER_SIZE <= buf.len() {
+            let mask = read_u32(buf, pos + 4);
+            let cookie = read_u32(buf, pos + COOKIE_OFFSET);
+            if cookie != 0 {
+                let det_cookie = if mask & libc::IN_MOVED_TO != 0 {
+                    self.pending.remove(&cookie)
+                } else {
+                    None
+                };
+                let det_cookie = det_cookie.unwrap_or_else(|| {
+                    self.last += 1;
+                    if mask & libc::IN_MOVED_FROM != 0 {
+                        self.pending.insert(cookie, self.last);
+                    }
+                    self.last
+                });
+                buf[pos + COOKIE_OFFSET..pos + COOKIE_OFFSET + 4]
+                    .copy_from_slice(&det_cookie.to_ne_bytes());
+            }
+            pos += EVENT_HEADER_SIZE + read_u32(buf, pos + LEN_OFFSET) as usize;
+        }
+    }
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> u32 {
+    u32::from_ne_bytes(buf[pos..pos + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(mask: u32, cookie: u32, name: &[u8]) -> Vec<u8> {
+        let mut ev = Vec::new();
+        for field in [1, mask, cookie, name.len() as u32] {
+            ev.extend(field.to_ne_bytes());
+        }
+        ev.extend(name);
+        ev
+    }
+
+    #[test]
+    fn renames_get_sequential_cookies() {
+        let mut cookies = InotifyCookies::default();
+        let mut buf = event(libc::IN_MODIFY, 0, b"");
+        buf.extend(event(libc::IN_MOVED_FROM, 7001, b"a\0\0\0"));
+        buf.extend(event(libc::IN_MOVED_FROM, 6000, b"b\0\0\0"));
+        cookies.renumber(&mut buf);
+        assert_eq!(read_u32(&buf, COOKIE_OFFSET), 0);
+        assert_eq!(read_u32(&buf, 16 + COOKIE_OFFSET), 1);
+        assert_eq!(read_u32(&buf, 36 + COOKIE_OFFSET), 2);
+
+        // The other halves, in a later read:
+        let mut buf = event(libc::IN_MOVED_TO, 6000, b"");
+        buf.extend(event(libc::IN_MOVED_TO, 7001, b""));
+        cookies.renumber(&mut buf);
+        assert_eq!(read_u32(&buf, COOKIE_OFFSET), 2);
+        assert_eq!(read_u32(&buf, 16 + COOKIE_OFFSET), 1);
+        assert!(cookies.pending.is_empty());
+    }
+}
@@ -0,0 +1,307 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Instrumentation of the guest layered on top of detcore, such as a custom syscall logger, which
+//! sees the guest exactly as detcore determinizes it.
+//!
+//! An instrument is either registered from code with [`register_instrument`], or built into a
+//! `cdylib` which exports it with [`export_instrument!`](crate::export_instrument) and is loaded
+//! with `hermit run --tool`.  Like a [`SchedulerHook`](crate::SchedulerHook), it is taken by the
+//! next run, and is dropped when the run after that starts.
+
+use std::ffi::c_void;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+use reverie::syscalls::Errno;
+use reverie::syscalls::Syscall;
+use reverie::syscalls::SyscallArgs;
+use reverie::syscalls::Sysno;
+use reverie::Error;
+
+use crate::types::DetTid;
+
+/// Observes the guest alongside detcore.  Every method has a default which does nothing.
+///
+/// The methods are called from every guest thread, in the order of detcore's schedule when threads
+/// are sequentialized, so an instrument which only records what it sees is deterministic too.
+pub trait Instrument: Send + Sync {
+    /// Thread `dettid` made the syscall `call`, which detcore is about to handle.
+    fn syscall_entry(&self, _dettid: DetTid, _call: &Syscall) {}
+
+    /// The syscall `call` of thread `dettid` is returning `result` to the guest.
+    fn syscall_exit(&self, _dettid: DetTid, _call: &Syscall, _result: &Result<i64, Error>) {}
+
+    /// Thread `dettid` exited.
+    fn thread_exit(&self, _dettid: DetTid) {}
+}
+
+lazy_static! {
+    /// Instruments registered, but not yet taken by a run.
+    static ref REGISTERED: Mutex<Vec<Arc<dyn Instrument>>> = Mutex::new(Vec::new());
+
+    /// The instruments of the current run.
+    static ref INSTRUMENTS: RwLock<Vec<Arc<dyn Instrument>>> = RwLock::new(Vec::new());
+}
+
+/// Whether the current run has any instrument, so that syscalls skip the lock when it has none.
+static ANY_INSTRUMENTS: AtomicBool = AtomicBool::new(false);
+
+/// Register an instrument for the next guest run in this process, which is the one of the next
+/// container run.  Instruments are called in the order they were registered.
+pub fn register_instrument(instrument: Arc<dyn Instrument>) {
+    REGISTERED.lock().unwrap().push(instrument);
+}
+
+/// Start a run: the instruments registered so far replace those of the last run.
+pub(crate) fn take_registered_instruments() {
+    let instruments = std::mem::take(&mut *REGISTERED.lock().unwrap());
+    ANY_INSTRUMENTS.store(!instruments.is_empty(), Ordering::Release);
+    *INSTRUMENTS.write().unwrap() = instruments;
+}
+
+/// Call `f` on every instrument of the current run, in the order they were registered.
+pub(crate) fn for_each_instrument(f: impl Fn(&dyn Instrument)) {
+    if ANY_INSTRUMENTS.load(Ordering::Acquire) {
+        for instrument in INSTRUMENTS.read().unwrap().iter() {
+            f(instrument.as_ref());
+        }
+    }
+}
+
+/// The version of [`InstrumentVtable`].  A tool library exports the version it was built with,
+/// and hermit refuses to load one built with another.
+pub const INSTRUMENT_ABI_VERSION: u32 = 1;
+
+/// An instrument as a tool library hands it to hermit: the instrument behind an opaque pointer,
+/// with plain C functions to call it, so that the library need not share hermit's Rust layouts.
+///
+/// A syscall is passed as its number and arguments, and its result as the kernel would return
+/// it: the value, or the negated errno.
+#[repr(C)]
+pub struct InstrumentVtable {
+    instrument: *mut c_void,
+    syscall_entry: unsafe extern "C" fn(*mut c_void, i32, i32, *const usize),
+    syscall_exit: unsafe extern "C" fn(*mut c_void, i32, i32, *const usize, i64),
+    thread_exit: unsafe extern "C" fn(*mut c_void, i32),
+    drop: unsafe extern "C" fn(*mut c_void),
+}
+
+// The instrument behind the pointer is itself `Send` and `Sync`, as `InstrumentVtable::new`
+// requires.
+unsafe impl Send for InstrumentVtable {}
+unsafe impl Sync for InstrumentVtable {}
+
+impl InstrumentVtable {
+    /// Wrap an instrument, for [`export_instrument!`](crate::export_instrument).
+    pub fn new<I: Instrument + 'static>(instrument: I) -> Self {
+        InstrumentVtable {
+            instrument: Box::into_raw(Box::new(instrument)).cast(),
+            syscall_entry: vtable_syscall_entry::<I>,
+            syscall_exit: vtable_syscall_exit::<I>,
+            thread_exit: vtable_thread_exit::<I>,
+            drop: vtable_drop::<I>,
+        }
+    }
+}
+
+impl Instrument for InstrumentVtable {
+    fn syscall_entry(&self, dettid: DetTid, call: &Syscall) {
+        let (sysno, args) = call.into_parts();
+        let args = raw_args(&args);
+        let (f, instrument) = (self.syscall_entry, self.instrument);
+        // SAFETY: The functions were made by `InstrumentVtable::new` for this instrument.
+        unsafe { f(instrument, dettid.as_raw(), sysno as i32, args.as_ptr()) }
+    }
+
+    fn syscall_exit(&self, dettid: DetTid, call: &Syscall, result: &Result<i64, Error>) {
+        let (sysno, args) = call.into_parts();
+        let args = raw_args(&args);
+        let ret = match result {
+            Ok(ret) => *ret,
+            Err(Error::Errno(errno)) => -errno.into_raw() as i64,
+            Err(_) => -libc::ENOSYS as i64,
+        };
+        let (f, instrument) = (self.syscall_exit, self.instrument);
+        let (dettid, sysno) = (dettid.as_raw(), sysno as i32);
+        // SAFETY: As above.
+        unsafe { f(instrument, dettid, sysno, args.as_ptr(), ret) }
+    }
+
+    fn thread_exit(&self, dettid: DetTid) {
+        // SAFETY: As above.
+        unsafe { (self.thread_exit)(self.instrument, dettid.as_raw()) }
+    }
+}
+
+impl Drop for InstrumentVtable {
+    fn drop(&mut self) {
+        // SAFETY: As above, and the instrument is not used again.
+        unsafe { (self.drop)(self.instrument) }
+    }
+}
+
+fn raw_args(args: &SyscallArgs) -> [usize; 6] {
+    [
+        args.arg0, args.arg1, args.arg2, args.arg3, args.arg4, args.arg5,
+    ]
+}
+
+/// The syscall passed to a vtable function, or None if this side does not know its number.
+unsafe fn vtable_syscall(sysno: i32, args: *const usize) -> Option<Syscall> {
+    let sysno = Sysno::new(sysno as usize)?;
+    let a = std::slice::from_raw_parts(args, 6);
+    let args = SyscallArgs::new(a[0], a[1], a[2], a[3], a[4], a[5]);
+    Some(Syscall::from_raw(sysno, args))
+}
+
+unsafe extern "C" fn vtable_syscall_entry<I: Instrument>(
+    instrument: *mut c_void,
+    dettid: i32,
+    sysno: i32,
+    args: *const usize,
+) {
+    if let Some(call) = vtable_syscall(sysno, args) {
+        let instrument = &*instrument.cast::<I>();
+        instrument.syscall_entry(DetTid::from_raw(dettid), &call);
+    }
+}
+
+unsafe extern "C" fn vtable_syscall_exit<I: Instrument>(
+    instrument: *mut c_void,
+    dettid: i32,
+    sysno: i32,
+    args: *const usize,
+    ret: i64,
+) {
+    if let Some(call) = vtable_syscall(sysno, args) {
+        let result = if (-4095..0).contains(&ret) {
+            Err(Errno::new(-ret as i32).into())
+        } else {
+            Ok(ret)
+        };
+        let instrument = &*instrument.cast::<I>();
+        instrument.syscall_exit(DetTid::from_raw(dettid), &call, &result);
+    }
+}
+
+unsafe extern "C" fn vtable_thread_exit<I: Instrument>(instrument: *mut c_void, dettid: i32) {
+    let instrument = &*instrument.cast::<I>();
+    instrument.thread_exit(DetTid::from_raw(dettid));
+}
+
+unsafe extern "C" fn vtable_drop<I: Instrument>(instrument: *mut c_void) {
+    drop(Box::from_raw(instrument.cast::<I>()));
+}
+
+/// Exports an instrument from a `cdylib`, for `hermit run --tool`.  The library must be built
+/// against a version of hermit with the same [`INSTRUMENT_ABI_VERSION`] as the `hermit` which
+/// loads it, which checks that before calling anything else.
+///
+/// ```ignore
+/// struct Logger;
+///
+/// impl detcore::Instrument for Logger {
+///     fn syscall_entry(&self, dettid: detcore::DetTid, call: &Syscall) {
+///         eprintln!("[{}] {}", dettid, call.name());
+///     }
+/// }
+///
+/// detcore::export_instrument!(Logger);
+/// ```
+#[macro_export]
+macro_rules! export_instrument {
+    ($instrument:expr) => {
+        #[no_mangle]
+        pub extern "C" fn hermit_instrument_abi_version() -> u32 {
+            $crate::INSTRUMENT_ABI_VERSION
+        }
+
+        #[no_mangle]
+        pub extern "C" fn hermit_instrument() -> $crate::InstrumentVtable {
+            $crate::InstrumentVtable::new($instrument)
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicU64;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct CountExits(AtomicU64);
+
+    impl Instrument for CountExits {
+        fn thread_exit(&self, _dettid: DetTid) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Shares what it sees, so that it can be checked once the instrument is behind a vtable.
+    #[derive(Default)]
+    struct Shared {
+        exits: Arc<AtomicU64>,
+        syscalls: Arc<Mutex<Vec<(Sysno, bool)>>>,
+    }
+
+    impl Instrument for Shared {
+        fn syscall_exit(&self, _dettid: DetTid, call: &Syscall, result: &Result<i64, Error>) {
+            let failed = matches!(result, Err(Error::Errno(Errno::EBADF)));
+            let sysno = call.into_parts().0;
+            self.syscalls.lock().unwrap().push((sysno, failed));
+        }
+
+        fn thread_exit(&self, _dettid: DetTid) {
+            self.exits.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn vtable_calls_through() {
+        let shared = Shared::default();
+        let (exits, syscalls) = (shared.exits.clone(), shared.syscalls.clone());
+        let vtable = InstrumentVtable::new(shared);
+
+        let close = Syscall::from_raw(Sysno::close, SyscallArgs::new(3, 0, 0, 0, 0, 0));
+        let dettid = DetTid::from_raw(3);
+        vtable.syscall_exit(dettid, &close, &Ok(0));
+        vtable.syscall_exit(dettid, &close, &Err(Errno::EBADF.into()));
+        vtable.thread_exit(dettid);
+        assert_eq!(
+            *syscalls.lock().unwrap(),
+            vec![(Sysno::close, false), (Sysno::close, true)]
+        );
+        assert_eq!(exits.load(Ordering::Relaxed), 1);
+
+        drop(vtable);
+        assert_eq!(Arc::strong_count(&exits), 1);
+    }
+
+    #[test]
+    fn registered_instruments_last_one_run() {
+        let counter = Arc::new(CountExits::default());
+        register_instrument(counter.clone());
+        for_each_instrument(|i| i.thread_exit(DetTid::from_raw(2)));
+        take_registered_instruments();
+        for_each_instrument(|i| i.thread_exit(DetTid::from_raw(3)));
+        for_each_instrument(|i| i.thread_exit(DetTid::from_raw(4)));
+        assert_eq!(counter.0.load(Ordering::Relaxed), 2);
+
+        // The next run drops it, so nothing is left behind.
+        take_registered_instruments();
+        for_each_instrument(|i| i.thread_exit(DetTid::from_raw(5)));
+        assert_eq!(counter.0.load(Ordering::Relaxed), 2);
+        assert_eq!(Arc::strong_count(&counter), 1);
+    }
+}
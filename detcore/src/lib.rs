@@ -13,48 +13,78 @@
 #![feature(nonzero_ops)]
 #![deny(clippy::all)]
 #![deny(missing_docs)]
+mod address_space;
+mod audit;
+mod compat;
 mod config;
 mod consts;
 mod cpuid;
 mod dirents;
+mod entropy;
 mod fd;
+mod inotify;
+mod instrument;
 #[allow(unused)]
 mod ivar;
 pub mod logdiff;
 #[allow(unused)]
 mod mvar;
+mod netrecord;
 mod procmaps;
+mod ptrace;
 mod record_or_replay;
 mod resources;
 mod scheduler;
+mod seccomp;
+mod stacktrace;
 mod stat;
+mod summary;
 mod syscalls;
+mod timers;
 mod tool_global;
 mod tool_local;
 pub mod util;
 
 pub mod detlog;
+pub mod perfetto;
 pub mod preemptions;
 pub mod types;
 use std::fs::File;
 use std::io::Write;
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::Duration;
 
+pub use config::Backend;
 pub use config::BlockingMode;
 pub use config::Config;
+pub use config::DivergencePolicy;
+pub use config::IoUringMode;
+pub use config::RdtscModel;
 pub use config::SchedHeuristic;
+pub use config::SpawnOrder;
+pub use consts::SPAN_TARGET;
+pub use cpuid::CpuProfile;
+use instrument::for_each_instrument;
+pub use instrument::register_instrument;
+pub use instrument::Instrument;
+pub use instrument::InstrumentVtable;
+pub use instrument::INSTRUMENT_ABI_VERSION;
+pub use netrecord::NetRecording;
 use rand::Rng;
 use raw_cpuid::cpuid;
 use raw_cpuid::CpuIdResult;
 pub use record_or_replay::RecordOrReplay;
+pub use resources::Permission;
+pub use resources::ResourceID;
+pub use resources::Resources;
 pub use reverie::process::Namespace;
 use reverie::syscalls::CloneFlags;
 use reverie::syscalls::Displayable;
 use reverie::syscalls::EpollCreate1;
 use reverie::syscalls::Errno;
-use reverie::syscalls::Fork;
+use reverie::syscalls::InotifyInit1;
 use reverie::syscalls::MemoryAccess;
 use reverie::syscalls::Syscall;
 use reverie::syscalls::SyscallInfo;
@@ -72,12 +102,22 @@ use reverie::Subscription;
 use reverie::Tid;
 use reverie::TimerSchedule;
 use reverie::Tool;
+pub use scheduler::fuzzy_replay::ReplayDivergence;
+pub use scheduler::hooks::register_hook;
+pub use scheduler::hooks::SchedulerHook;
+pub use scheduler::hooks::Verdict;
 pub use scheduler::runqueue::DEFAULT_PRIORITY;
 pub use scheduler::runqueue::FIRST_PRIORITY;
 pub use scheduler::runqueue::LAST_PRIORITY;
 pub use scheduler::Priority;
+pub use summary::RunSummary;
+pub use summary::SchedOverhead;
+pub use summary::Scorecard;
+pub use summary::ThreadSummary;
+pub use summary::Virtualization;
 use tool_global::create_child_thread;
 use tool_global::deregister_thread;
+use tool_global::report_thread_stats;
 pub use tool_global::GlobalState;
 pub use tool_local::thread_rng_from_parent;
 pub use tool_local::Detcore;
@@ -93,9 +133,19 @@ pub use types::DetTid;
 use types::*;
 pub use util::punch_out_print;
 
+use crate::audit::Imperfection;
+use crate::compat::CompatAbi;
+use crate::seccomp::SeccompAction;
+use crate::tool_global::audit_syscall;
+use crate::tool_global::claim_sigchld;
+use crate::tool_global::process_execed;
 use crate::tool_global::resource_request;
 use crate::tool_global::trace_schedevent;
+use crate::tool_global::trace_signal;
 use crate::tool_global::unrecoverable_shutdown;
+use crate::tool_global::vfork_done;
+#[cfg(target_arch = "x86_64")]
+use crate::tool_global::write_crash_report;
 
 #[macro_use]
 extern crate bitflags;
@@ -190,6 +240,7 @@ impl<T: RecordOrReplay> Detcore<T> {
                         start_rip: None,
                         end_rip: None,
                         end_time: Some(nanos),
+                        identity: None,
                     },
                     true, // Fill in end_rip because current rip represents the end of this event.
                 )
@@ -411,6 +462,11 @@ impl<T: RecordOrReplay> Detcore<T> {
             _ => syscall.display_with_outputs(memory),
         }
     }
+
+    /// Whether every syscall is intercepted, rather than only those with handlers of their own.
+    fn subscribes_all(config: &Config) -> bool {
+        cfg!(debug_assertions) || config.emulate_seccomp || config.backend == Backend::Ptrace
+    }
 }
 
 #[reverie::tool]
@@ -425,6 +481,7 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
             detpid,
             cfg: cfg.clone(),
             record_or_replay: T::new(pid, cfg),
+            cpuid: cpuid::InterceptedCpuid::new(cfg.cpu_profile.as_deref()),
         }
     }
 
@@ -434,7 +491,8 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
         let do_sched =
             config.sched_heuristic != SchedHeuristic::None || config.sequentialize_threads;
 
-        if cfg!(debug_assertions) {
+        if Self::subscribes_all(config) {
+            // Under --emulate-seccomp, the guest's filters get to judge every syscall.
             Subscription::all()
         } else {
             let mut subscription = Subscription::none();
@@ -448,11 +506,13 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
                 Sysno::mmap,
                 Sysno::fcntl,
                 Sysno::futex,
+                Sysno::set_robust_list,
                 Sysno::clone,
                 Sysno::clone3,
                 Sysno::fork,
                 Sysno::vfork,
                 Sysno::wait4,
+                Sysno::waitid,
                 Sysno::setsid,
                 Sysno::uname,
                 Sysno::exit_group,
@@ -475,8 +535,19 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
                 Sysno::sched_setaffinity,
                 Sysno::signalfd,
                 Sysno::signalfd4,
+                Sysno::inotify_init,
+                Sysno::inotify_init1,
+                Sysno::semop,
+                Sysno::semtimedop,
+                Sysno::msgsnd,
+                Sysno::msgrcv,
+                Sysno::mq_timedsend,
+                Sysno::mq_timedreceive,
+                Sysno::shmat,
+                Sysno::shmdt,
                 Sysno::timerfd_create,
                 Sysno::memfd_create,
+                Sysno::memfd_secret,
                 Sysno::userfaultfd,
                 Sysno::accept,
                 Sysno::accept4,
@@ -484,6 +555,9 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
                 Sysno::clock_nanosleep,
                 Sysno::sched_yield,
                 Sysno::poll,
+                Sysno::ppoll,
+                Sysno::select,
+                Sysno::pselect6,
                 Sysno::epoll_create,
                 Sysno::epoll_create1,
                 Sysno::epoll_ctl,
@@ -496,12 +570,21 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
                 Sysno::execve,
                 Sysno::execveat,
                 Sysno::getcpu,
+                Sysno::rseq,
+                Sysno::membarrier,
+                Sysno::perf_event_open,
+                Sysno::pidfd_open,
+                Sysno::pidfd_send_signal,
+                Sysno::pidfd_getfd,
+                Sysno::process_vm_readv,
+                Sysno::process_vm_writev,
+                Sysno::ptrace,
                 Sysno::rt_sigprocmask,
                 Sysno::rt_sigaction,
                 Sysno::sysinfo,
-                // TODO(T137258824): add proper Select / PSelect6
-                // Sysno::pselect6,
-                // Sysno::select,
+                Sysno::io_uring_setup,
+                Sysno::io_uring_enter,
+                Sysno::io_uring_register,
             ]);
 
             if do_sched {
@@ -509,13 +592,56 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
                     // TODO: some of the above could probably move to this bucket.
                     Sysno::alarm,
                     Sysno::pause,
+                    Sysno::setitimer,
+                    Sysno::getitimer,
+                    Sysno::timer_create,
+                    Sysno::timer_settime,
+                    Sysno::timer_gettime,
+                    Sysno::timer_getoverrun,
+                    Sysno::timer_delete,
+                    Sysno::timerfd_settime,
+                    Sysno::timerfd_gettime,
+                ]);
+            }
+
+            if config.deterministic_mmap {
+                subscription.syscalls([Sysno::brk, Sysno::munmap, Sysno::mremap]);
+            } else if config.sequentialize_threads {
+                // Only to notice shared mappings going away or moving, for shared futexes.
+                subscription.syscalls([Sysno::munmap, Sysno::mremap]);
+            }
+
+            if config.loopback_networking {
+                subscription.syscalls([
+                    Sysno::sendto,
+                    Sysno::sendmsg,
+                    Sysno::sendmmsg,
+                    Sysno::recvmsg,
                 ]);
             }
 
+            if config.record_network_to.is_some() || config.replay_network_from.is_some() {
+                subscription.syscalls([
+                    Sysno::connect,
+                    Sysno::sendto,
+                    Sysno::sendmsg,
+                    Sysno::recvmsg,
+                    Sysno::getpeername,
+                ]);
+            }
+
+            if config.uid.is_some() {
+                subscription.syscalls([Sysno::getuid, Sysno::geteuid]);
+            }
+            if config.gid.is_some() {
+                subscription.syscalls([Sysno::getgid, Sysno::getegid]);
+            }
+
             if config.virtualize_metadata {
                 subscription.syscalls([
                     Sysno::getdents,
                     Sysno::getdents64,
+                    Sysno::lseek,
                     Sysno::stat,
                     Sysno::lstat,
                     Sysno::fstat,
@@ -542,6 +668,8 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
                     Sysno::time,
                     Sysno::clock_gettime,
                     Sysno::clock_getres,
+                    Sysno::getrusage,
+                    Sysno::times,
                 ]);
             }
 
@@ -566,7 +694,6 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
         let res = if self.cfg.virtualize_cpuid {
             let dettid = guest.thread_state().dettid;
             let time = &mut guest.thread_state_mut().thread_logical_time;
-            let intercepted = cpuid::InterceptedCpuid::new();
             time.add_cpuid();
             let nanos = time.as_nanos();
             trace!(
@@ -584,12 +711,13 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
                         start_rip: None,
                         end_rip: None,
                         end_time: Some(nanos),
+                        identity: None,
                     },
                     true,
                 )
                 .await;
             }
-            intercepted.cpuid(eax).unwrap()
+            self.cpuid.cpuid(eax, ecx)
         } else {
             cpuid!(eax, ecx)
         };
@@ -606,12 +734,12 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
         self.pre_handler_hook(guest).await;
         let result = if guest.config().virtualize_time {
             let dettid = guest.thread_state().dettid;
-            let time = &mut guest.thread_state_mut().thread_logical_time;
-            time.add_rdtsc();
+            let tsc = guest.thread_state_mut().next_virtual_tsc(&self.cfg);
+            let time = &guest.thread_state().thread_logical_time;
             let nanos = time.as_nanos();
             info!(
-                "[dtid {}] inbound rdtsc, new logical time: {:?}",
-                dettid, time
+                "[dtid {}] inbound rdtsc ({}), new logical time: {:?}",
+                dettid, tsc, time
             );
             if self.cfg.should_trace_schedevent() {
                 trace_schedevent(
@@ -623,15 +751,13 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
                         start_rip: None,
                         end_rip: None,
                         end_time: Some(nanos),
+                        identity: None,
                     },
                     true,
                 )
                 .await;
             }
-            Ok(RdtscResult {
-                tsc: nanos.as_nanos(), // We treat virtual cycles as equivalent to virtual nanoseconds.
-                aux: None,
-            })
+            Ok(RdtscResult { tsc, aux: None })
         } else {
             self.record_or_replay
                 .handle_rdtsc_event(&mut guest.into_guest(), request)
@@ -659,6 +785,48 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
             );
             thread_state.stats.count_signal();
 
+            if self.cfg.emit_trace.is_some() {
+                trace_signal(guest, signal).await;
+            }
+            // The report's registers are x86_64's.
+            #[cfg(target_arch = "x86_64")]
+            if let Some(dir) = &self.cfg.crash_report_dir {
+                if matches!(
+                    signal,
+                    Signal::SIGSEGV
+                        | Signal::SIGBUS
+                        | Signal::SIGILL
+                        | Signal::SIGFPE
+                        | Signal::SIGABRT
+                        | Signal::SIGSYS
+                ) {
+                    write_crash_report(guest, dir, signal).await;
+                }
+            }
+
+            if signal == Signal::SIGCHLD
+                && self.cfg.sequentialize_threads
+                && !self.cfg.recordreplay_modes
+            {
+                // The scheduler sends SIGCHLD itself when a child exits, at a deterministic
+                // point. The kernel's own notification is suppressed.
+                let detpid = guest.thread_state().detpid.expect("detpid unset");
+                if !claim_sigchld(guest, detpid).await {
+                    info!("suppressing SIGCHLD not sent by the scheduler");
+                    self.post_handler_hook(guest).await;
+                    return Ok(None);
+                }
+            }
+
+            // A ptraced thread stops first, and its tracer decides what is delivered.
+            let signal = match self.ptrace_signal_stop(guest, signal).await {
+                Some(signal) => signal,
+                None => {
+                    self.post_handler_hook(guest).await;
+                    return Ok(None);
+                }
+            };
+
             // TODO(T98118634): suppress every signal and delay it until the scheduler is
             // ready to deliver.
             self.post_handler_hook(guest).await;
@@ -701,6 +869,36 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
                             Arc::new(Mutex::new(pts.1.file_metadata.lock().unwrap().clone()))
                         }
                     },
+                    condvar_futexes: if clone_flags.contains(CloneFlags::CLONE_VM) {
+                        pts.1.condvar_futexes.clone()
+                    } else {
+                        Arc::new(Mutex::new(pts.1.condvar_futexes.lock().unwrap().clone()))
+                    },
+                    address_space: if clone_flags.contains(CloneFlags::CLONE_VM) {
+                        pts.1.address_space.clone()
+                    } else {
+                        Arc::new(Mutex::new(pts.1.address_space.lock().unwrap().clone()))
+                    },
+                    membarrier_registrations: if clone_flags.contains(CloneFlags::CLONE_VM) {
+                        pts.1.membarrier_registrations.clone()
+                    } else {
+                        Arc::new(Mutex::new(*pts.1.membarrier_registrations.lock().unwrap()))
+                    },
+                    // A new process starts without robust lists, as its one thread has yet to
+                    // register one.  (Even if it shares our memory, like a vfork child.)
+                    robust_lists: if clone_flags.contains(CloneFlags::CLONE_THREAD) {
+                        pts.1.robust_lists.clone()
+                    } else {
+                        Default::default()
+                    },
+                    vfork_child: clone_flags.contains(CloneFlags::CLONE_VFORK),
+                    ptrace_syscall_stops: false,
+                    seccomp: if clone_flags.contains(CloneFlags::CLONE_THREAD) {
+                        pts.1.seccomp.clone()
+                    } else {
+                        Arc::new(Mutex::new(pts.1.seccomp.lock().unwrap().clone()))
+                    },
+                    compat_abi: pts.1.compat_abi,
                     clone_flags: None,
 
                     // For a child thread, we use the parent to initialize our rng state:
@@ -713,6 +911,7 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
                     thread_logical_time: pts.1.thread_logical_time.clone(),
                     // A new thread gets a new clock, so we've committed 0 ticks
                     committed_clock_value: 0,
+                    last_tsc: pts.1.last_tsc,
 
                     end_of_timeslice: None,
                     last_rcb_timer: None,
@@ -778,6 +977,25 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
         guest.thread_state_mut().past_global_first_execve = true;
         self.pre_handler_hook(guest).await;
 
+        if std::mem::take(&mut guest.thread_state_mut().vfork_child) {
+            // Having exec'd, we no longer share the parent's memory, and it may resume.
+            vfork_done(guest).await;
+        }
+        process_execed(guest).await;
+
+        let exe = format!("/proc/{}/exe", guest.tid());
+        let compat_abi = CompatAbi::of_exe(Path::new(&exe));
+        if let Some(abi) = compat_abi {
+            if !Self::subscribes_all(guest.config()) {
+                warn!(
+                    "[dtid {}] execed an {} binary, only some of whose syscalls are intercepted without --backend=ptrace or --emulate-seccomp",
+                    guest.thread_state().dettid,
+                    abi
+                );
+            }
+        }
+        guest.thread_state_mut().compat_abi = compat_abi;
+
         if let Some(ptr) = guest.auxv().at_random() {
             // It is safe to mutate this address since libc has not yet had a
             // chance to modify or copy the auxv table.
@@ -791,6 +1009,8 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
             guest.memory().write_value(ptr, &bytes)?;
         }
 
+        self.ptrace_exec_stop(guest).await;
+
         self.post_handler_hook(guest).await;
         Ok(())
     }
@@ -802,6 +1022,7 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
             guest.thread_state().dettid
         );
         if guest.config().preemption_stacktrace {
+            let backtrace = stacktrace::backtrace(guest).await;
             let mut file_writer: Box<dyn Write> =
                 match &guest.config().preemption_stacktrace_log_file {
                     Some(path) => Box::new(
@@ -817,12 +1038,8 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
                 ts.thread_logical_time.as_nanos(),
             )
             .unwrap();
-            if let Some(backtrace) = guest.backtrace() {
-                if let Ok(pbt) = backtrace.pretty() {
-                    writeln!(file_writer, "{}", pbt).unwrap();
-                } else {
-                    writeln!(file_writer, "{}", backtrace).unwrap();
-                }
+            if let Some(backtrace) = backtrace {
+                writeln!(file_writer, "{}", backtrace).unwrap();
             } else {
                 warn!("Could not read backtrace!");
             }
@@ -850,14 +1067,35 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
                 call.display(&guest.memory())
             );
         }
+        for_each_instrument(|i| i.syscall_entry(dettid, &call));
 
         let config = guest.config().clone(); // TODO/FIXME: this is an inefficient and unnecessary copy
 
+        // With `--condvar-sched-points`, futexes recognized as condvar operations are traced as
+        // such, rather than as syscalls.
+        let condvar_op = match call {
+            Syscall::Futex(s) if config.sequentialize_threads && config.condvar_sched_points => {
+                Self::classify_condvar_futex(guest, &s)
+            }
+            _ => None,
+        };
+        // As are socket operations, when studying the messages between them.
+        let socket_op = if config.loopback_networking {
+            Self::classify_socket_op(guest, &call)
+        } else {
+            None
+        };
+        let syscall_schedevent = |phase| match (condvar_op, socket_op) {
+            (Some(op), _) => SchedEvent::condvar(dettid, op, phase),
+            (None, Some(op)) => SchedEvent::socket(dettid, op, phase),
+            (None, None) => SchedEvent::syscall(dettid, call.number(), phase),
+        };
+
         if config.sequentialize_threads && self.cfg.should_trace_schedevent() {
             let nanos = guest.thread_state_mut().thread_logical_time.as_nanos();
             trace_schedevent(
                 guest,
-                SchedEvent::syscall(dettid, call.number(), SyscallPhase::Prehook).with_time(nanos),
+                syscall_schedevent(SyscallPhase::Prehook).with_time(nanos),
                 true,
             )
             .await;
@@ -867,7 +1105,7 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
         let new_count = {
             // which results from not being able to borrow guest twice.
             let thread_state = guest.thread_state_mut();
-            thread_state.stats.count_syscall();
+            thread_state.stats.count_syscall(call.name());
 
             // Add the syscall to our thread's logical progress, advancing logical time.
             if config.sequentialize_threads {
@@ -888,7 +1126,26 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
             thread_state.stats.syscall_count
         };
 
+        if guest.thread_state().ptrace_syscall_stops {
+            self.ptrace_syscall_stop(guest, None).await;
+        }
+
+        // The filters a 32-bit process installs are left to the kernel (see `syscalls::compat`).
+        let compat_abi = guest.thread_state().compat_abi;
+        let seccomp_action = if compat_abi.is_some() {
+            SeccompAction::Allow
+        } else {
+            self.seccomp_action(guest, &call).await
+        };
+
         let res = match call {
+            _ if compat_abi.is_some() => {
+                self.handle_compat_syscall(guest, compat_abi.unwrap(), call)
+                    .await
+            }
+            _ if seccomp_action != SeccompAction::Allow => {
+                self.handle_seccomp_action(guest, call, seccomp_action).await
+            }
             Syscall::Write(w) => self.handle_write(guest, w).await,
             Syscall::Openat(o) => self.handle_openat(guest, o).await,
             Syscall::Open(o) => self.handle_openat(guest, o.into()).await,
@@ -903,15 +1160,14 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
             Syscall::Statx(s) => self.handle_statx(guest, s).await,
             Syscall::Fcntl(s) => self.handle_fcntl(guest, s).await,
             Syscall::Futex(s) => self.handle_futex(guest, s).await,
+            Syscall::SetRobustList(s) => self.handle_set_robust_list(guest, s).await,
 
-            // TODO(): fix vfork and handle CLONE_VFORK cases here:
             Syscall::Clone(s) => self.handle_clone_family(guest, s.into()).await,
             Syscall::Clone3(s) => self.handle_clone_family(guest, s.into()).await,
             Syscall::Fork(s) => self.handle_clone_family(guest, s.into()).await,
-
-            // Our child-thread-creation protocol doesn't support vfork blocking the parent thread yet:
-            Syscall::Vfork(_s) => self.handle_clone_family(guest, Fork::new().into()).await,
+            Syscall::Vfork(s) => self.handle_vfork(guest, s).await,
             Syscall::Wait4(s) => self.handle_wait4(guest, s).await,
+            Syscall::Waitid(s) => self.handle_waitid(guest, s).await,
 
             Syscall::Setsid(s) => self.handle_setsid(guest, s).await,
             Syscall::Gettimeofday(s) if virtualize_time => self.handle_gettimeofday(guest, s).await,
@@ -920,7 +1176,18 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
                 self.handle_clock_gettime(guest, s).await
             }
             Syscall::ClockGetres(s) if virtualize_time => self.handle_clock_getres(guest, s).await,
+            _ if virtualize_time && call.number() == Sysno::getrusage => {
+                self.handle_getrusage(guest, call).await
+            }
+            _ if virtualize_time && call.number() == Sysno::times => {
+                self.handle_times(guest, call).await
+            }
             Syscall::Uname(s) => self.handle_uname(guest, s).await,
+            Syscall::Getuid(s) => self.handle_getid(guest, s, config.uid).await,
+            Syscall::Geteuid(s) => self.handle_getid(guest, s, config.uid).await,
+            Syscall::Getgid(s) => self.handle_getid(guest, s, config.gid).await,
+            Syscall::Getegid(s) => self.handle_getid(guest, s, config.gid).await,
+            Syscall::IoUringSetup(s) => self.handle_io_uring_setup(guest, s).await,
             Syscall::ExitGroup(s) => self.handle_exit_group(guest, s).await,
             Syscall::Exit(s) => self.handle_exit(guest, s).await,
 
@@ -951,6 +1218,22 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
                 .await
                 .map_err(Into::into),
             Syscall::Signalfd4(s) => self.handle_signalfd4(guest, s).await.map_err(Into::into),
+            Syscall::InotifyInit(_) => self
+                .handle_inotify_init1(guest, InotifyInit1::new())
+                .await
+                .map_err(Into::into),
+            Syscall::InotifyInit1(s) => self
+                .handle_inotify_init1(guest, s)
+                .await
+                .map_err(Into::into),
+            Syscall::Semop(s) => self.handle_semop(guest, s).await,
+            Syscall::Semtimedop(s) => self.handle_semtimedop(guest, s).await,
+            Syscall::Msgsnd(s) => self.handle_msgsnd(guest, s).await,
+            Syscall::Msgrcv(s) => self.handle_msgrcv(guest, s).await,
+            Syscall::MqTimedsend(s) => self.handle_mq_timedsend(guest, s).await,
+            Syscall::MqTimedreceive(s) => self.handle_mq_timedreceive(guest, s).await,
+            Syscall::Shmat(s) => self.handle_shmat(guest, s).await,
+            Syscall::Shmdt(s) => self.handle_shmdt(guest, s).await,
             Syscall::TimerfdCreate(s) => self
                 .handle_timerfd_create(guest, s)
                 .await
@@ -974,8 +1257,12 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
             // see: sysdeps/unix/sysv/linux/getdents.c.
             Syscall::Getdents(s) => self.handle_getdents(guest, s).await,
             Syscall::Getdents64(s) => self.handle_getdents64(guest, s).await,
+            Syscall::Lseek(s) => self.handle_lseek(guest, s).await,
 
             Syscall::Poll(s) => self.handle_poll(guest, s).await,
+            Syscall::Ppoll(s) => self.handle_ppoll(guest, s).await,
+            Syscall::Select(s) => self.handle_select(guest, s).await,
+            Syscall::Pselect6(s) => self.handle_pselect6(guest, s).await,
             Syscall::EpollCreate(s) => {
                 self.handle_epoll_create1(guest, EpollCreate1::from(s))
                     .await
@@ -1001,6 +1288,7 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
             Syscall::Sendto(s) => self.handle_sendrecv(guest, s).await,
             Syscall::Sendmsg(s) => self.handle_sendrecv(guest, s).await,
             Syscall::Sendmmsg(s) => self.handle_sendrecv(guest, s).await,
+            Syscall::Getpeername(s) => self.handle_getpeername(guest, s).await,
 
             // TODO: handle timeout behavior:
             // Syscall::Recvmmsg(_) => self.handle_recvmmsg(guest, call).await,
@@ -1010,14 +1298,33 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
             Syscall::Execveat(s) => self.handle_execveat(guest, s).await,
 
             Syscall::Getcpu(s) => self.handle_getcpu(guest, s).await,
+            Syscall::Rseq(s) => self.handle_rseq(guest, s).await,
+            Syscall::Membarrier(s) => self.handle_membarrier(guest, s).await,
+            Syscall::PerfEventOpen(s) => self.handle_perf_event_open(guest, s).await,
+            Syscall::PidfdOpen(s) => self.handle_pidfd_open(guest, s).await,
+            Syscall::PidfdSendSignal(s) => self.handle_pidfd_send_signal(guest, s).await,
+            Syscall::PidfdGetfd(s) => self.handle_pidfd_getfd(guest, s).await,
+            Syscall::Seccomp(s) => self.handle_seccomp(guest, s).await,
+            Syscall::ProcessVmReadv(s) => self.handle_process_vm_readv(guest, s).await,
+            Syscall::ProcessVmWritev(s) => self.handle_process_vm_writev(guest, s).await,
+            Syscall::Ptrace(s) => self.handle_ptrace(guest, s).await,
             Syscall::RtSigprocmask(s) => self.handle_rt_sigprocmask(guest, s).await,
             Syscall::RtSigaction(s) => self.handle_rt_sigaction(guest, s).await,
             Syscall::Alarm(s) => self.handle_alarm(guest, s).await,
             Syscall::Pause(s) => self.handle_pause(guest, s).await,
+            Syscall::Setitimer(s) => self.handle_setitimer(guest, s).await,
+            Syscall::Getitimer(s) => self.handle_getitimer(guest, s).await,
+            Syscall::TimerCreate(s) => self.handle_timer_create(guest, s).await,
+            Syscall::TimerSettime(s) => self.handle_timer_settime(guest, s).await,
+            Syscall::TimerGettime(s) => self.handle_timer_gettime(guest, s).await,
+            Syscall::TimerGetoverrun(s) => self.handle_timer_getoverrun(guest, s).await,
+            Syscall::TimerDelete(s) => self.handle_timer_delete(guest, s).await,
+            Syscall::TimerfdSettime(s) => self.handle_timerfd_settime(guest, s).await,
+            Syscall::TimerfdGettime(s) => self.handle_timerfd_gettime(guest, s).await,
 
             // These are to allow execution of a minimal rust executable
             // (namely //hermetic_infra/detcore:get-syscall-support)
-            Syscall::Brk(_) => self.passthrough(guest, call).await,
+            Syscall::Brk(s) => self.handle_brk(guest, s).await,
             Syscall::Readlink(_) => self.passthrough(guest, call).await,
             Syscall::Access(_) => self.passthrough(guest, call).await,
             Syscall::Mprotect(_) => self.passthrough(guest, call).await,
@@ -1027,11 +1334,21 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
             Syscall::Prlimit64(_) => self.passthrough(guest, call).await,
             Syscall::Readlinkat(_) => self.passthrough(guest, call).await,
             Syscall::Madvise(_) => self.passthrough(guest, call).await,
-            Syscall::Munmap(_) => self.passthrough(guest, call).await,
-            Syscall::Prctl(_) => self.passthrough(guest, call).await,
+            Syscall::Munmap(s) => self.handle_munmap(guest, s).await,
+            Syscall::Mremap(s) => self.handle_mremap(guest, s).await,
+            Syscall::Prctl(s) => self.handle_prctl(guest, s).await,
             Syscall::Sigaltstack(_) => self.passthrough(guest, call).await,
             Syscall::Sysinfo(s) => self.handle_sysinfo(guest, s).await,
 
+            _ if call.number() == Sysno::memfd_secret => {
+                self.handle_memfd_secret(guest, call).await
+            }
+            _ if call.number() == Sysno::io_uring_enter
+                || call.number() == Sysno::io_uring_register =>
+            {
+                self.handle_io_uring_call(guest, call).await
+            }
+
             _ => {
                 if config.panic_on_unsupported_syscalls {
                     error!(
@@ -1041,6 +1358,14 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
                     );
                     panic!("unsupported syscall: {:?}", call);
                 }
+                detlog!(
+                    "[detcore, dtid {}] passing unsupported syscall {} through to the host",
+                    dettid,
+                    call.name()
+                );
+                if config.audit_syscalls {
+                    audit_syscall(guest, call.number(), Imperfection::PassedThrough).await;
+                }
                 self.passthrough(guest, call).await
             }
         };
@@ -1055,16 +1380,21 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
 
         self.detlog_memory_maps(guest)?;
 
+        if guest.thread_state().ptrace_syscall_stops {
+            self.ptrace_syscall_stop(guest, Some(&res)).await;
+        }
+
         if config.sequentialize_threads && self.cfg.should_trace_schedevent() {
             let nanos = guest.thread_state_mut().thread_logical_time.as_nanos();
             trace_schedevent(
                 guest,
-                SchedEvent::syscall(dettid, call.number(), SyscallPhase::Posthook).with_time(nanos),
+                syscall_schedevent(SyscallPhase::Posthook).with_time(nanos),
                 true,
             )
             .await;
         }
 
+        for_each_instrument(|i| i.syscall_exit(dettid, &call, &res));
         self.post_handler_hook(guest).await;
         res
     }
@@ -1081,7 +1411,16 @@ impl<T: RecordOrReplay> Tool for Detcore<T> {
             "[detcore, dtid {}] thread exit hook, deregistering from scheduler.",
             dettid
         );
+        for_each_instrument(|i| i.thread_exit(dettid));
         let detpid = thread_state.detpid.expect("Missing DetPid");
+        report_thread_stats(
+            dettid,
+            detpid,
+            thread_state.thread_logical_time.clone(),
+            thread_state.stats.clone(),
+            global_state,
+        )
+        .await;
         deregister_thread(
             dettid,
             thread_state.thread_logical_time.clone(),
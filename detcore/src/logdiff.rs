@@ -15,12 +15,24 @@ use std::cmp::Ordering;
 use std::cmp::Reverse;
 use std::collections::BTreeMap;
 use std::ops::Bound;
+use std::ops::Range;
 use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
 
 use clap;
 use clap::Parser;
 use lazy_static::lazy_static;
 use regex::Regex;
+use reverie::syscalls::Sysno;
+use serde::Serialize;
+
+use crate::preemptions::read_trace;
+use crate::types::DetTid;
+use crate::types::Op;
+use crate::types::SchedEvent;
+use crate::types::SyscallPhase;
+use crate::util::ZSTD_MAGIC;
 
 /// Options for calling `log_diff`.
 #[derive(Debug, Parser)]
@@ -37,6 +49,19 @@ pub struct LogDiffOpts {
     #[clap(long)]
     pub ignore_lines: Vec<String>,
 
+    /// A file of rules describing the known-benign differences between runs of a program, applied
+    /// to the messages of both logs before comparison.  Each line is one rule:
+    ///
+    ///   mask timestamps|pointers|pids|numbers
+    ///   replace REGEX => REPLACEMENT
+    ///   ignore REGEX
+    ///
+    /// `replace` substitutes every match of a regex, where the replacement may refer to capture
+    /// groups as `$1`, and `ignore` drops the messages a regex matches.  Blank lines and lines
+    /// starting with `#` are skipped.
+    #[clap(long, value_name = "PATH")]
+    pub rules: Option<PathBuf>,
+
     /// Show specified number of syscall prior to a divergence point in the diff view. Set to 0 for ommiting the history
     #[clap(long, default_value = "0")]
     pub syscall_history: u64,
@@ -44,6 +69,15 @@ pub struct LogDiffOpts {
     #[clap(long)]
     pub no_color: bool,
 
+    /// Show mismatching log entries side by side, with the part that differs highlighted, rather
+    /// than as a diff of the whole entries.
+    #[clap(long)]
+    pub side_by_side: bool,
+
+    /// With `--side-by-side`, how many entries to show before and after each mismatch.
+    #[clap(long, default_value = "0", value_name = "ENTRIES")]
+    pub context: usize,
+
     /// Do not consider "COMMIT" messages for deterministic check
     #[clap(long)]
     pub skip_commit: bool,
@@ -51,6 +85,120 @@ pub struct LogDiffOpts {
     /// Do not consider "DETLOG" messages for deterministic check
     #[clap(long)]
     pub skip_detlog: bool,
+
+    /// Print the result as JSON to stdout, for scripts, instead of describing it on stderr.
+    #[clap(long)]
+    pub json: bool,
+
+    /// The schedule recorded by the run which made the first log.  With it, the first divergence
+    /// is also reported as the schedule event nearest to it.
+    #[clap(long, value_name = "PATH")]
+    pub sched1: Option<PathBuf>,
+
+    /// The schedule recorded by the run which made the second log.
+    #[clap(long, value_name = "PATH")]
+    pub sched2: Option<PathBuf>,
+}
+
+/// The result of comparing two logs, as printed by `--json`.  Pairs hold the value for the first
+/// log, then the second.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct LogDiffReport {
+    /// Whether the logs differ substantively.
+    pub differs: bool,
+    /// How many messages each log contains, before any are ignored.
+    pub messages: (usize, usize),
+    /// How many messages of each log were dropped by `--ignore-lines`.
+    pub ignored: (usize, usize),
+    /// How many messages of each log were compared.
+    pub compared: (usize, usize),
+    /// How many compared messages matched their counterpart.
+    pub matched: usize,
+    /// The first place where the logs differ, if any.
+    pub first_divergence: Option<LogDivergence>,
+}
+
+/// Where two logs first differ.  When one log runs out of messages first, its side is empty.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LogDivergence {
+    /// Which kind of messages differ, e.g. "DETLOGs".
+    pub which: String,
+    /// The index of the differing message in each log.
+    pub lines: (Option<usize>, Option<usize>),
+    /// The differing messages, as compared (stripped with `--strip-lines`).
+    pub messages: (Option<String>, Option<String>),
+    /// The schedule event nearest to the differing message of each log, given `--sched1` and
+    /// `--sched2`.
+    pub schedule: (Option<ScheduleLocation>, Option<ScheduleLocation>),
+}
+
+/// A schedule event, which a log message is nearest to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ScheduleLocation {
+    /// The index of the event, as printed by `hermit sched inspect`.
+    pub event: usize,
+    /// The thread the event, and the message, are about.
+    pub thread: DetTid,
+}
+
+impl Display for ScheduleLocation {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "event #{} (thread {})", self.event, self.thread)
+    }
+}
+
+/// Find the schedule event nearest to message `line` of a log: the latest syscall, at or before
+/// the message, of the thread the message is about.  Syscalls are matched up by counting those of
+/// the thread in the log and in the schedule, skipping the ones before the guest's first execve,
+/// which are not logged.
+fn locate_in_schedule(
+    log: &[(usize, &str)],
+    line: usize,
+    schedule: &[SchedEvent],
+) -> Option<ScheduleLocation> {
+    lazy_static! {
+        static ref DTID: Regex = Regex::new(r"\bdtid (\d+)\b").unwrap();
+    }
+    let dtid = |s: &str| {
+        let caps = DTID.captures(s)?;
+        caps[1].parse().ok().map(DetTid::from_raw)
+    };
+    let upto: Vec<&str> = log
+        .iter()
+        .take_while(|(ix, _)| *ix <= line)
+        .map(|(_, s)| *s)
+        .collect();
+    let thread = upto.iter().rev().find_map(|s| dtid(s))?;
+    let logged = upto
+        .iter()
+        .filter(|s| s.contains("inbound syscall") && dtid(s) == Some(thread))
+        .count();
+
+    let start = schedule
+        .iter()
+        .position(|ev| ev.op == Op::Syscall(Sysno::execve, SyscallPhase::Prehook))
+        .map_or(0, |ix| ix + 1);
+    let mut events = schedule
+        .iter()
+        .enumerate()
+        .skip(start)
+        .filter(|(_, ev)| ev.dettid == thread);
+    let event = if logged == 0 {
+        events.next()
+    } else {
+        events
+            .filter(|(_, ev)| {
+                matches!(
+                    ev.op,
+                    Op::Syscall(_, SyscallPhase::Prehook)
+                        | Op::Condvar(_, SyscallPhase::Prehook)
+                        | Op::Socket(_, SyscallPhase::Prehook)
+                )
+            })
+            .take(logged)
+            .last()
+    };
+    event.map(|(event, _)| ScheduleLocation { event, thread })
 }
 
 /// N.B. we don't want to specify two different notions of "default", so we use the
@@ -111,6 +259,151 @@ pub fn strip_log_entry(log: &str) -> String {
     String::from(log)
 }
 
+/// A kind of nondeterministic field to mask in log messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogField {
+    /// Durations and times, like `946_684_800.709_180s`.
+    Timestamps,
+    /// Addresses, like `0x7fcfb7e7d450`.
+    Pointers,
+    /// Host process ids, like `/proc/231635/` or `pid 231635`.
+    Pids,
+    /// Every number.
+    Numbers,
+}
+
+impl FromStr for LogField {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "timestamps" => Ok(LogField::Timestamps),
+            "pointers" => Ok(LogField::Pointers),
+            "pids" => Ok(LogField::Pids),
+            "numbers" => Ok(LogField::Numbers),
+            _ => Err(format!(
+                "Expected timestamps|pointers|pids|numbers, could not parse: {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// One rule of a `--rules` file.
+#[derive(Debug, Clone)]
+enum LogRule {
+    Mask(LogField),
+    Replace(Regex, String),
+    Ignore(Regex),
+}
+
+/// The rules of a `--rules` file, applied in order to each log message.
+#[derive(Debug, Clone, Default)]
+pub struct LogRules {
+    rules: Vec<LogRule>,
+}
+
+impl FromStr for LogRules {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let regex = |re: &str| Regex::new(re.trim()).map_err(|e| e.to_string());
+        let mut rules = Vec::new();
+        for (ix, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (verb, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rule = match verb {
+                "mask" => rest.trim().parse().map(LogRule::Mask),
+                "ignore" => regex(rest).map(LogRule::Ignore),
+                "replace" => match rest.split_once(" => ") {
+                    Some((re, replacement)) => {
+                        regex(re).map(|re| LogRule::Replace(re, replacement.trim().to_string()))
+                    }
+                    None => Err("expected replace REGEX => REPLACEMENT".to_string()),
+                },
+                _ => Err(format!("unknown rule {:?}", verb)),
+            };
+            rules.push(rule.map_err(|e| format!("line {}: {}", ix + 1, e))?);
+        }
+        Ok(LogRules { rules })
+    }
+}
+
+/// Read a log, decompressing it if it was written to a `--log-file` ending in `.zst`, or
+/// compressed since.
+pub fn read_log(path: &Path) -> std::io::Result<Vec<u8>> {
+    let bytes = std::fs::read(path)?;
+    if bytes.starts_with(ZSTD_MAGIC) {
+        zstd::decode_all(bytes.as_slice())
+    } else {
+        Ok(bytes)
+    }
+}
+
+impl LogRules {
+    /// Read the rules in a file.
+    pub fn from_file(path: &Path) -> std::result::Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read rules from {}: {}", path.display(), e))?;
+        contents
+            .parse()
+            .map_err(|e| format!("Invalid rules in {}: {}", path.display(), e))
+    }
+
+    /// Rewrite a log message by the rules, or return None if it is to be ignored.
+    pub fn apply(&self, message: &str) -> Option<String> {
+        lazy_static! {
+            static ref TIMESTAMP: Regex = Regex::new(r"\b[\d][\d_.]*s\b").unwrap();
+            static ref POINTER: Regex = Regex::new(r"\b0[xX][A-Fa-f0-9]+\b").unwrap();
+            static ref PROC_PID: Regex = Regex::new(r"/proc/[\d]+/").unwrap();
+            static ref PID: Regex = Regex::new(r"\b(pid|tid)([ =:]+)[\d]+\b").unwrap();
+            static ref NUMBER: Regex = Regex::new(r"\b[\d]+\b").unwrap();
+        }
+        let mut message = message.to_string();
+        for rule in &self.rules {
+            message = match rule {
+                LogRule::Mask(LogField::Timestamps) => TIMESTAMP
+                    .replace_all(&message, "<NANOSECONDS>")
+                    .into_owned(),
+                LogRule::Mask(LogField::Pointers) => {
+                    POINTER.replace_all(&message, "<ADDR>").into_owned()
+                }
+                LogRule::Mask(LogField::Pids) => {
+                    let message = PROC_PID.replace_all(&message, "/proc/<PID>/");
+                    PID.replace_all(&message, "$1$2<PID>").into_owned()
+                }
+                LogRule::Mask(LogField::Numbers) => {
+                    NUMBER.replace_all(&message, "<NUM>").into_owned()
+                }
+                LogRule::Replace(re, replacement) => {
+                    re.replace_all(&message, replacement.as_str()).into_owned()
+                }
+                LogRule::Ignore(re) if re.is_match(&message) => return None,
+                LogRule::Ignore(_) => message,
+            };
+        }
+        Some(message)
+    }
+}
+
+/// The `--rules`, if any.
+fn load_rules(opts: &LogDiffOpts) -> LogRules {
+    match &opts.rules {
+        Some(path) => LogRules::from_file(path).unwrap_or_else(|e| panic!("{}", e)),
+        None => LogRules::default(),
+    }
+}
+
+/// Apply the rules to each message of a log, dropping those they ignore.
+fn apply_rules(rules: &LogRules, v: &[(usize, &str)]) -> Vec<(usize, String)> {
+    v.iter()
+        .filter_map(|(ix, s)| rules.apply(s).map(|s| (*ix, s)))
+        .collect()
+}
+
 /// Separate a full, continuous log into discrete (possibly-multiline) log messages,
 /// stripping off the timestamps in the process.  Return lines tagged with their
 /// index number.
@@ -253,6 +546,102 @@ impl<'a> Display for Comparison<'a> {
     }
 }
 
+/// The width of each side of a `SideBySide`, in characters.
+const SIDE_BY_SIDE_WIDTH: usize = 60;
+
+/// How much of what two entries have in common is shown before the part that differs.
+const SIDE_BY_SIDE_LEAD: usize = 10;
+
+/// Rows of log entries shown side by side, as with `diff --side-by-side`: the gutter between them
+/// is `|` where they differ, and `<` or `>` where only one side has an entry.  Entries too wide
+/// for their side are elided, keeping the part that differs in view, and that part is highlighted.
+pub struct SideBySide {
+    rows: Vec<(Option<String>, Option<String>)>,
+    no_color: bool,
+}
+
+impl SideBySide {
+    /// Store rows to be shown.
+    pub fn new(no_color: bool, rows: Vec<(Option<String>, Option<String>)>) -> Self {
+        SideBySide { rows, no_color }
+    }
+}
+
+/// One side of a row of a `SideBySide`, padded to its width.
+fn side_by_side_cell(text: &[char], differs: Range<usize>, color: Option<&str>) -> String {
+    const RESET: &str = "\u{1b}[0m";
+    let start = differs.start.saturating_sub(SIDE_BY_SIDE_LEAD);
+    let mut cell = String::new();
+    let mut width = 0;
+    if start > 0 {
+        cell.push('…');
+        width += 1;
+    }
+    let mut highlighting = false;
+    for (ix, c) in text.iter().enumerate().skip(start) {
+        if width + 1 == SIDE_BY_SIDE_WIDTH && ix + 1 < text.len() {
+            cell.push_str(if highlighting { RESET } else { "" });
+            highlighting = false;
+            cell.push('…');
+            width += 1;
+            break;
+        }
+        if let Some(color) = color {
+            if differs.contains(&ix) != highlighting {
+                cell.push_str(if highlighting { RESET } else { color });
+                highlighting = !highlighting;
+            }
+        }
+        cell.push(*c);
+        width += 1;
+    }
+    if highlighting {
+        cell.push_str(RESET);
+    }
+    cell.extend(std::iter::repeat(' ').take(SIDE_BY_SIDE_WIDTH.saturating_sub(width)));
+    cell
+}
+
+impl Display for SideBySide {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        let (red, green) = if self.no_color {
+            (None, None)
+        } else {
+            (Some("\u{1b}[1;31m"), Some("\u{1b}[1;32m"))
+        };
+        for (left, right) in &self.rows {
+            let a: Vec<char> = left.as_deref().unwrap_or("").chars().collect();
+            let b: Vec<char> = right.as_deref().unwrap_or("").chars().collect();
+            let gutter = match (left, right) {
+                (Some(_), None) => '<',
+                (None, Some(_)) => '>',
+                _ if a != b => '|',
+                _ => ' ',
+            };
+            let (differs_a, differs_b) = if gutter == '|' {
+                let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+                let suffix = a[prefix..]
+                    .iter()
+                    .rev()
+                    .zip(b[prefix..].iter().rev())
+                    .take_while(|(x, y)| x == y)
+                    .count();
+                (prefix..a.len() - suffix, prefix..b.len() - suffix)
+            } else {
+                (0..0, 0..0)
+            };
+            let row = format!(
+                "{} {} {}",
+                side_by_side_cell(&a, differs_a, red),
+                gutter,
+                side_by_side_cell(&b, differs_b, green)
+            );
+            writeln!(f, "{}", row.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
 /// Returns `true` if a difference is found.
 ///
 /// We could use an existing diff library on the entire log, but this provides us more
@@ -270,27 +659,58 @@ fn diff_vecs(
     opts: &LogDiffOpts,
     w: &mut impl std::io::Write,
     syscalls: &BTreeMap<Reverse<usize>, &str>,
+    report: &mut LogDiffReport,
 ) -> std::io::Result<bool> {
     writeln!(w, "  Comparing {} messages...\n", which)?;
+    report.compared.0 += v1.len();
+    report.compared.1 += v2.len();
     if v1.is_empty() && v2.is_empty() {
         Ok(false)
     } else {
         let mut diff_count = 0;
-        for (_ix, ((oix, ox), (_, oy))) in v1.iter().zip(v2.iter()).enumerate() {
-            let (x, y) = if opts.strip_lines {
-                (strip_log_entry(ox), strip_log_entry(oy))
+        let compared = |s: &str| {
+            if opts.strip_lines {
+                strip_log_entry(s)
             } else {
-                (ox.to_string(), oy.to_string())
-            };
-            if x != y {
-                write!(
-                    w,
-                    "({}) Mismatch in log entries, line {}: {}",
-                    which,
-                    oix,
-                    Comparison::new(opts.no_color, &x, &y)
-                )?;
-                if opts.strip_lines {
+                s.to_string()
+            }
+        };
+        for (ix, ((oix, ox), (oiy, oy))) in v1.iter().zip(v2.iter()).enumerate() {
+            let (x, y) = (compared(ox), compared(oy));
+            if x == y {
+                report.matched += 1;
+            } else {
+                if report.first_divergence.is_none() {
+                    report.first_divergence = Some(LogDivergence {
+                        which: which.to_string(),
+                        lines: (Some(*oix), Some(*oiy)),
+                        messages: (Some(x.clone()), Some(y.clone())),
+                        schedule: (None, None),
+                    });
+                }
+                if opts.side_by_side {
+                    let rows = (ix.saturating_sub(opts.context)..ix + 1 + opts.context)
+                        .map(|i| (v1.get(i), v2.get(i)))
+                        .take_while(|row| *row != (None, None))
+                        .map(|(a, b)| (a.map(|(_, s)| compared(s)), b.map(|(_, s)| compared(s))))
+                        .collect();
+                    write!(
+                        w,
+                        "({}) Mismatch in log entries, line {}:\n{}",
+                        which,
+                        oix,
+                        SideBySide::new(opts.no_color, rows)
+                    )?;
+                } else {
+                    write!(
+                        w,
+                        "({}) Mismatch in log entries, line {}: {}",
+                        which,
+                        oix,
+                        Comparison::new(opts.no_color, &x, &y)
+                    )?;
+                }
+                if opts.strip_lines && !opts.side_by_side {
                     write!(
                         w,
                         "({}) Original entries, before stripping {}: {}",
@@ -329,6 +749,17 @@ fn diff_vecs(
             }
         }
 
+        if v1.len() != v2.len() && report.first_divergence.is_none() {
+            let extra = |v: &[(usize, &str)]| v.get(v1.len().min(v2.len())).copied();
+            let (a, b) = (extra(v1), extra(v2));
+            report.first_divergence = Some(LogDivergence {
+                which: which.to_string(),
+                lines: (a.map(|(ix, _)| ix), b.map(|(ix, _)| ix)),
+                messages: (a.map(|(_, s)| s.to_string()), b.map(|(_, s)| s.to_string())),
+                schedule: (None, None),
+            });
+        }
+
         match v1.len().cmp(&v2.len()) {
             Ordering::Less => {
                 writeln!(
@@ -377,12 +808,20 @@ pub fn log_diff(file_a: &Path, file_b: &Path, opts: &LogDiffOpts) -> bool {
     // For now the log-diff mode reads both logs fully into memory. This could be
     // modified in the future for a streaming solution, at least for scrolling through
     // the identical prefixes of very large logs.
-    let vec_a = std::fs::read(file_a).expect("Could not open first input file.");
-    let vec_b = std::fs::read(file_b).expect("Could not open second input file.");
+    let vec_a = read_log(file_a).expect("Could not open first input file.");
+    let vec_b = read_log(file_b).expect("Could not open second input file.");
     let str_a = String::from_utf8_lossy(&vec_a);
     let str_b = String::from_utf8_lossy(&vec_b);
-    log_diff_from_strs(str_a, str_b, opts, &mut std::io::stderr())
-        .expect("should write succesfully")
+    if opts.json {
+        let report = log_diff_from_strs(str_a, str_b, opts, &mut std::io::sink())
+            .expect("should write succesfully");
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        report.differs
+    } else {
+        log_diff_from_strs(str_a, str_b, opts, &mut std::io::stderr())
+            .expect("should write succesfully")
+            .differs
+    }
 }
 
 fn log_diff_from_strs(
@@ -390,12 +829,22 @@ fn log_diff_from_strs(
     file_b_str: impl AsRef<str>,
     opts: &LogDiffOpts,
     w: &mut impl std::io::Write,
-) -> std::io::Result<bool> {
-    let vec_a = extract_log_messages(file_a_str.as_ref());
-    let vec_b = extract_log_messages(file_b_str.as_ref());
-
-    let vec_a = filter_ignored(vec_a, &opts.ignore_lines);
-    let vec_b = filter_ignored(vec_b, &opts.ignore_lines);
+) -> std::io::Result<LogDiffReport> {
+    let mut report = LogDiffReport::default();
+    let messages_a = extract_log_messages(file_a_str.as_ref());
+    let messages_b = extract_log_messages(file_b_str.as_ref());
+    report.messages = (messages_a.len(), messages_b.len());
+
+    let vec_a = filter_ignored(messages_a.clone(), &opts.ignore_lines);
+    let vec_b = filter_ignored(messages_b.clone(), &opts.ignore_lines);
+    let rules = load_rules(opts);
+    let (rewritten_a, rewritten_b) = (apply_rules(&rules, &vec_a), apply_rules(&rules, &vec_b));
+    let vec_a: Vec<(usize, &str)> = rewritten_a.iter().map(|(i, s)| (*i, s.as_str())).collect();
+    let vec_b: Vec<(usize, &str)> = rewritten_b.iter().map(|(i, s)| (*i, s.as_str())).collect();
+    report.ignored = (
+        report.messages.0 - vec_a.len(),
+        report.messages.1 - vec_b.len(),
+    );
 
     writeln!(
         w,
@@ -436,18 +885,475 @@ fn log_diff_from_strs(
 
     let mut diff_found = false;
 
-    diff_found |= diff_vecs("DETLOGs", &detlogs_a, &detlogs_b, opts, w, &syscalls)?;
+    diff_found |= diff_vecs(
+        "DETLOGs",
+        &detlogs_a,
+        &detlogs_b,
+        opts,
+        w,
+        &syscalls,
+        &mut report,
+    )?;
 
     // The TRACE level lines will generally include reorderings from racing threads.
     // So not doing any comparisons here for now:
     // diff_found |= diff_vecs("ALL", &vec_a, &vec_b);
 
+    if let Some(divergence) = &mut report.first_divergence {
+        let locate = |log: &[(usize, &str)], line: Option<usize>, path: &Option<PathBuf>| {
+            let schedule = read_trace(path.as_ref()?);
+            locate_in_schedule(log, line?, &schedule)
+        };
+        divergence.schedule = (
+            locate(&messages_a, divergence.lines.0, &opts.sched1),
+            locate(&messages_b, divergence.lines.1, &opts.sched2),
+        );
+        let describe = |location: Option<ScheduleLocation>| match location {
+            Some(location) => location.to_string(),
+            None => "unknown".to_string(),
+        };
+        if divergence.schedule != (None, None) {
+            writeln!(
+                w,
+                "The logs first differ nearest to schedule {} in the first run, and {} in the second.",
+                describe(divergence.schedule.0),
+                describe(divergence.schedule.1)
+            )?;
+        }
+    }
+
     if diff_found {
         writeln!(w, "Done processing logs, differences found.")?;
     } else {
         writeln!(w, "Done processing logs, no substantive differences found.")?;
     }
-    Ok(diff_found)
+    report.differs = diff_found;
+    Ok(report)
+}
+
+/// What most likely made two runs of the same guest differ, judging by the first message where
+/// their logs differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum NondeterminismSource {
+    /// A syscall returned something different.
+    SyscallResult,
+    /// A different random value was drawn, by the guest or by the scheduler.
+    RandomValue,
+    /// The scheduler committed a different thread or resources.
+    Schedule,
+    /// Something else, such as the contents of memory, or a message only one run logged.
+    Unknown,
+}
+
+impl NondeterminismSource {
+    fn of(message: &str) -> Self {
+        if is_detlog_syscall_result(message) {
+            NondeterminismSource::SyscallResult
+        } else if is_random(message) {
+            NondeterminismSource::RandomValue
+        } else if is_commit(message) {
+            NondeterminismSource::Schedule
+        } else {
+            NondeterminismSource::Unknown
+        }
+    }
+}
+
+impl Display for NondeterminismSource {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            NondeterminismSource::SyscallResult => write!(f, "a syscall result"),
+            NondeterminismSource::RandomValue => write!(f, "a random value"),
+            NondeterminismSource::Schedule => write!(f, "a scheduling decision"),
+            NondeterminismSource::Unknown => write!(f, "an unknown source"),
+        }
+    }
+}
+
+fn is_random(line: &str) -> bool {
+    is_detlog(line) && (line.contains("RAND") || line.contains(" DETLOG RNG "))
+}
+
+/// Syscalls whose results differ when a file changes between runs.
+const FILE_SYSCALLS: &[&str] = &[
+    "access",
+    "faccessat",
+    "fstat",
+    "getdents",
+    "getdents64",
+    "lseek",
+    "lstat",
+    "newfstatat",
+    "open",
+    "openat",
+    "pread64",
+    "read",
+    "readlink",
+    "readlinkat",
+    "stat",
+    "statx",
+];
+
+/// Syscalls whose results differ when peers outside the container behave differently.
+const NETWORK_SYSCALLS: &[&str] = &[
+    "accept", "accept4", "connect", "recvfrom", "recvmmsg", "recvmsg", "sendmmsg", "sendmsg",
+    "sendto",
+];
+
+/// What made two runs differ in the first place, as far as the logs tell, which says what can be
+/// done about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum RootCause {
+    /// A syscall hermit does not handle, which was passed through to the host.
+    UnsupportedSyscall,
+    /// Peers outside the container, over the real network.
+    RealNetwork,
+    /// A file which changed between the runs, from outside of them or by the first run.
+    ExternalFile,
+    /// Memory shared with a process hermit does not run.
+    SharedMemory,
+}
+
+impl RootCause {
+    /// Work out the cause from the first differing messages, and the messages before them.
+    fn classify(differing: [Option<&str>; 2], upto: [&[String]; 2]) -> Option<Self> {
+        lazy_static! {
+            static ref RESULT: Regex = Regex::new(r"finish syscall #\d+: (\w+)\(").unwrap();
+            static ref UNSUPPORTED: Regex =
+                Regex::new(r"passing unsupported syscall (\w+) through").unwrap();
+        }
+        let syscall = differing
+            .iter()
+            .flatten()
+            .find_map(|s| RESULT.captures(s).map(|caps| caps[1].to_string()));
+        let before = || upto.iter().flat_map(|log| log.iter());
+        let passed_through = before()
+            .filter_map(|s| UNSUPPORTED.captures(s))
+            .any(|caps| Some(&caps[1]) == syscall.as_deref());
+        let is = |names: &[&str]| syscall.as_deref().map_or(false, |s| names.contains(&s));
+        if passed_through {
+            Some(RootCause::UnsupportedSyscall)
+        } else if is(NETWORK_SYSCALLS)
+            || before().any(|s| s.contains("Nondeterministic external actions"))
+        {
+            Some(RootCause::RealNetwork)
+        } else if is(FILE_SYSCALLS) {
+            Some(RootCause::ExternalFile)
+        } else if before().any(|s| {
+            s.contains("mmap(") && s.contains("MAP_SHARED") && !s.contains("MAP_ANONYMOUS")
+        }) {
+            Some(RootCause::SharedMemory)
+        } else {
+            None
+        }
+    }
+
+    /// The hermit flags, or the limitation of hermit, to look into.
+    pub fn suggestion(&self) -> &'static str {
+        match self {
+            RootCause::UnsupportedSyscall => {
+                "hermit passes this syscall through to the host. Run with --audit-syscalls to \
+                 list every such call; avoiding them is the only remedy."
+            }
+            RootCause::RealNetwork => {
+                "run with --no-networking, or record the external connections with \
+                 --record-network-to and replay them with --replay-network-from."
+            }
+            RootCause::ExternalFile => {
+                "start each run from the same files, with --cow-root, or by binding the inputs \
+                 read-only with --bind src:dst:ro."
+            }
+            RootCause::SharedMemory => {
+                "hermit cannot order accesses to memory shared with processes it does not run. \
+                 Run those processes under the same hermit, or avoid the shared mapping."
+            }
+        }
+    }
+}
+
+impl Display for RootCause {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            RootCause::UnsupportedSyscall => write!(f, "an unsupported syscall"),
+            RootCause::RealNetwork => write!(f, "the real network"),
+            RootCause::ExternalFile => write!(f, "a file changed between the runs"),
+            RootCause::SharedMemory => write!(f, "memory shared with a process outside hermit"),
+        }
+    }
+}
+
+/// Where two runs of the same guest first differ, with what led up to it in each run, as
+/// reported by `hermit run --verify`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NondeterminismReport {
+    /// The first differing messages.
+    pub divergence: LogDivergence,
+    /// What the differing messages are about.
+    pub source: NondeterminismSource,
+    /// What made them differ, if it can be told.
+    pub cause: Option<RootCause>,
+    /// The virtual time of each run, as of its last scheduler commit up to the divergence.
+    pub time: (Option<String>, Option<String>),
+    /// The last syscall result each run logged, up to the divergence.
+    pub syscall: (Option<String>, Option<String>),
+    /// The last random value each run logged, up to the divergence.
+    pub random: (Option<String>, Option<String>),
+}
+
+impl NondeterminismReport {
+    /// Gather what led up to `divergence` in each of the logs, given their raw messages.
+    fn new(divergence: LogDivergence, log_a: &[(usize, &str)], log_b: &[(usize, &str)]) -> Self {
+        lazy_static! {
+            static ref COMMITTED: Regex = Regex::new(r"on previously committed (\S+)").unwrap();
+        }
+        let upto = |log: &[(usize, &str)], line: Option<usize>| -> Vec<String> {
+            log.iter()
+                .take_while(|(ix, _)| line.map_or(true, |line| *ix <= line))
+                .map(|(_, s)| s.to_string())
+                .collect()
+        };
+        let (upto_a, upto_b) = (
+            upto(log_a, divergence.lines.0),
+            upto(log_b, divergence.lines.1),
+        );
+        let last =
+            |log: &[String], pred: fn(&str) -> bool| log.iter().rev().find(|s| pred(s)).cloned();
+        let time = |log: &[String]| {
+            log.iter()
+                .rev()
+                .find_map(|s| COMMITTED.captures(s).map(|caps| caps[1].to_string()))
+        };
+        let differing = |log: &[(usize, &str)], line: Option<usize>| {
+            let line = line?;
+            log.iter().find(|(ix, _)| *ix == line).map(|(_, s)| *s)
+        };
+        let differing = (
+            differing(log_a, divergence.lines.0),
+            differing(log_b, divergence.lines.1),
+        );
+        let cause = RootCause::classify([differing.0, differing.1], [&upto_a[..], &upto_b[..]]);
+        let source = match differing {
+            (Some(a), Some(b)) => match NondeterminismSource::of(a) {
+                NondeterminismSource::Unknown => NondeterminismSource::of(b),
+                source => source,
+            },
+            (Some(s), None) | (None, Some(s)) => NondeterminismSource::of(s),
+            (None, None) => NondeterminismSource::Unknown,
+        };
+        NondeterminismReport {
+            source,
+            cause,
+            time: (time(&upto_a), time(&upto_b)),
+            syscall: (
+                last(&upto_a, is_detlog_syscall_result),
+                last(&upto_b, is_detlog_syscall_result),
+            ),
+            random: (last(&upto_a, is_random), last(&upto_b, is_random)),
+            divergence,
+        }
+    }
+}
+
+impl Display for NondeterminismReport {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        let show = |s: &Option<String>| s.clone().unwrap_or_else(|| "(none)".to_string());
+        let line = |line: Option<usize>| line.map_or("(end)".to_string(), |l| l.to_string());
+        writeln!(
+            f,
+            "Nondeterminism from {}: the runs first differ in {} at line {} | {}",
+            self.source,
+            self.divergence.which,
+            line(self.divergence.lines.0),
+            line(self.divergence.lines.1)
+        )?;
+        let pairs = [
+            ("Differing message", &self.divergence.messages),
+            ("Virtual time", &self.time),
+            ("Last syscall result", &self.syscall),
+            ("Last random value", &self.random),
+        ];
+        for (what, (a, b)) in pairs {
+            writeln!(
+                f,
+                "  {}:\n    first run:  {}\n    second run: {}",
+                what,
+                show(a),
+                show(b)
+            )?;
+        }
+        if let (Some(a), Some(b)) = &self.divergence.schedule {
+            writeln!(
+                f,
+                "  Nearest schedule event:\n    first run:  {}\n    second run: {}",
+                a, b
+            )?;
+        }
+        if let Some(cause) = &self.cause {
+            writeln!(f, "  Likely caused by {}: {}", cause, cause.suggestion())?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare the logs of two runs of the same guest like `log_diff`, and if they differ, report
+/// where, and what most likely made them differ.
+pub fn log_diff_nondeterminism(
+    file_a: &Path,
+    file_b: &Path,
+    opts: &LogDiffOpts,
+) -> Option<NondeterminismReport> {
+    let vec_a = read_log(file_a).expect("Could not open first input file.");
+    let vec_b = read_log(file_b).expect("Could not open second input file.");
+    let str_a = String::from_utf8_lossy(&vec_a);
+    let str_b = String::from_utf8_lossy(&vec_b);
+    let report = log_diff_from_strs(&str_a, &str_b, opts, &mut std::io::stderr())
+        .expect("should write succesfully");
+    if !report.differs {
+        return None;
+    }
+    let divergence = report.first_divergence?;
+    Some(NondeterminismReport::new(
+        divergence,
+        &extract_log_messages(&str_a),
+        &extract_log_messages(&str_b),
+    ))
+}
+
+/// The messages of a log which `log_diff` compares, as they are compared.
+fn compared_messages(contents: &str, rules: &LogRules, opts: &LogDiffOpts) -> Vec<(usize, String)> {
+    let messages = filter_ignored(extract_log_messages(contents), &opts.ignore_lines);
+    let rewritten = apply_rules(rules, &messages);
+    let rewritten: Vec<(usize, &str)> = rewritten.iter().map(|(i, s)| (*i, s.as_str())).collect();
+    let detcore = filter_detcore(&rewritten);
+    filter_deterministic(&detcore, opts.skip_commit, opts.skip_detlog)
+        .into_iter()
+        .map(|(i, s)| {
+            if opts.strip_lines {
+                (i, strip_log_entry(s))
+            } else {
+                (i, s.to_string())
+            }
+        })
+        .collect()
+}
+
+/// The result of comparing many logs, as printed by `--json`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct ManyLogDiffReport {
+    /// The logs which agree with the most others, in the order given.
+    pub consensus: Vec<PathBuf>,
+    /// The rest of the logs, in the order given.
+    pub outliers: Vec<LogOutlier>,
+}
+
+/// A log which differs from the consensus of many.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LogOutlier {
+    /// The log.
+    pub log: PathBuf,
+    /// How many of its messages match the consensus before it differs.
+    pub matched: usize,
+    /// The index of its first message which differs, unless it ends early instead.
+    pub line: Option<usize>,
+    /// That message, as compared.
+    pub message: Option<String>,
+}
+
+impl ManyLogDiffReport {
+    /// Group logs whose compared messages are the same, and take the largest group, or the first
+    /// of the largest, as the consensus.
+    fn new(files: &[PathBuf], logs: &[Vec<(usize, String)>]) -> Self {
+        let same = |a: &[(usize, String)], b: &[(usize, String)]| {
+            a.len() == b.len() && a.iter().zip(b).all(|((_, x), (_, y))| x == y)
+        };
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        for (ix, log) in logs.iter().enumerate() {
+            match groups.iter_mut().find(|group| same(&logs[group[0]], log)) {
+                Some(group) => group.push(ix),
+                None => groups.push(vec![ix]),
+            }
+        }
+        let consensus = match groups.iter().rev().max_by_key(|group| group.len()) {
+            Some(group) => group.clone(),
+            None => return Self::default(),
+        };
+
+        let reference = &logs[consensus[0]];
+        let outliers = (0..logs.len())
+            .filter(|ix| !consensus.contains(ix))
+            .map(|ix| {
+                let matched = logs[ix]
+                    .iter()
+                    .zip(reference)
+                    .take_while(|((_, x), (_, y))| x == y)
+                    .count();
+                let differing = logs[ix].get(matched);
+                LogOutlier {
+                    log: files[ix].clone(),
+                    matched,
+                    line: differing.map(|(line, _)| *line),
+                    message: differing.map(|(_, message)| message.clone()),
+                }
+            })
+            .collect();
+        ManyLogDiffReport {
+            consensus: consensus.iter().map(|ix| files[*ix].clone()).collect(),
+            outliers,
+        }
+    }
+}
+
+impl Display for ManyLogDiffReport {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        let total = self.consensus.len() + self.outliers.len();
+        if self.outliers.is_empty() {
+            return writeln!(f, "All {} logs agree.", total);
+        }
+        writeln!(f, "{} of {} logs agree:", self.consensus.len(), total)?;
+        for log in &self.consensus {
+            writeln!(f, "  {}", log.display())?;
+        }
+        for outlier in &self.outliers {
+            match (&outlier.line, &outlier.message) {
+                (Some(line), Some(message)) => writeln!(
+                    f,
+                    "{} differs after {} matching messages, at line {}:\n  {}",
+                    outlier.log.display(),
+                    outlier.matched,
+                    line,
+                    message
+                )?,
+                _ => writeln!(
+                    f,
+                    "{} ends after {} matching messages.",
+                    outlier.log.display(),
+                    outlier.matched
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compare many logs, reporting which of them form the consensus, and where each of the others
+/// first differs from it.  Returns `true` if any log differs.
+pub fn log_diff_many(files: &[PathBuf], opts: &LogDiffOpts) -> bool {
+    let rules = load_rules(opts);
+    let logs: Vec<_> = files
+        .iter()
+        .map(|file| {
+            let bytes = read_log(file)
+                .unwrap_or_else(|e| panic!("Could not open {}: {}", file.display(), e));
+            compared_messages(&String::from_utf8_lossy(&bytes), &rules, opts)
+        })
+        .collect();
+    let report = ManyLogDiffReport::new(files, &logs);
+    if opts.json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        eprint!("{}", report);
+    }
+    !report.outliers.is_empty()
 }
 
 #[cfg(test)]
@@ -485,6 +1391,39 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_side_by_side() {
+        let rows = vec![
+            (Some("COMMIT 1".to_string()), Some("COMMIT 1".to_string())),
+            (Some("COMMIT 2".to_string()), Some("COMMIT 3".to_string())),
+            (Some(format!("{}A", "x".repeat(100))), None),
+        ];
+        assert_eq!(
+            super::SideBySide::new(true, rows.clone()).to_string(),
+            format!(
+                "{:60}   COMMIT 1\n{:60} | COMMIT 3\n{:60} <\n",
+                "COMMIT 1",
+                "COMMIT 2",
+                format!("{}…", "x".repeat(59))
+            )
+        );
+        assert_eq!(
+            super::SideBySide::new(false, rows[1..2].to_vec()).to_string(),
+            format!(
+                "COMMIT \u{1b}[1;31m2\u{1b}[0m{:52} | COMMIT \u{1b}[1;32m3\u{1b}[0m\n",
+                ""
+            )
+        );
+
+        // The part that differs is kept in view.
+        let long = |end| Some(format!("{}{}", "x".repeat(100), end));
+        let rows = vec![(long("A"), long("B"))];
+        assert_eq!(
+            super::SideBySide::new(true, rows).to_string(),
+            format!("{:60} | …xxxxxxxxxxB\n", "…xxxxxxxxxxA")
+        );
+    }
+
     #[test]
     fn test_log_diff_with_color() -> std::io::Result<()> {
         let str1 = "INFO detcore: DETLOG [syscall][detcore, dtid 3]  finish syscall #11: mmap(NULL, 3954880, PROT_READ | PROT_EXEC, MAP_PRIVATE | MAP_DENYWRITE, 3, 0) = Ok(140737347883008)";
@@ -499,9 +1438,15 @@ mod test {
                 strip_lines: false,
                 syscall_history: 5,
                 no_color: false,
+                side_by_side: false,
+                context: 0,
                 skip_commit: false,
                 skip_detlog: false,
                 ignore_lines: Vec::new(),
+                rules: None,
+                json: false,
+                sched1: None,
+                sched2: None,
             },
             &mut result,
         )?;
@@ -557,9 +1502,15 @@ mod test {
                 strip_lines: false,
                 syscall_history: 5,
                 no_color: true,
+                side_by_side: false,
+                context: 0,
                 skip_commit: false,
                 skip_detlog: false,
                 ignore_lines: Vec::new(),
+                rules: None,
+                json: false,
+                sched1: None,
+                sched2: None,
             },
             &mut result,
         )?;
@@ -642,6 +1593,221 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_log_diff_report() -> std::io::Result<()> {
+        let log_file_a = r#"2022-09-06T14:15:47.891501Z  INFO detcore: DETLOG [syscall][detcore, dtid 3] finish syscall: write(1, 0x6022a0, 70) = 1
+2022-09-06T14:15:48.904049Z  INFO detcore: CHAOSRAND 2
+2022-09-06T14:15:48.904049Z  INFO detcore: COMMIT 2"#;
+        let log_file_b = r#"2022-09-06T14:15:47.891501Z  INFO detcore: DETLOG [syscall][detcore, dtid 3] finish syscall: write(1, 0x6022a0, 70) = 1
+2022-09-06T14:15:48.904049Z  INFO detcore: COMMIT 1
+2022-09-06T14:15:48.904049Z  INFO detcore: COMMIT 3"#;
+        let log_options = super::LogDiffOpts {
+            ignore_lines: vec!["CHAOSRAND".to_string()],
+            ..Default::default()
+        };
+        let report =
+            super::log_diff_from_strs(log_file_a, log_file_b, &log_options, &mut std::io::sink())?;
+
+        assert_eq!(
+            report,
+            super::LogDiffReport {
+                differs: true,
+                messages: (3, 3),
+                ignored: (1, 0),
+                compared: (2, 3),
+                matched: 1,
+                first_divergence: Some(super::LogDivergence {
+                    which: "DETLOGs".to_string(),
+                    lines: (Some(3), Some(2)),
+                    messages: (
+                        Some("INFO detcore: COMMIT 2".to_string()),
+                        Some("INFO detcore: COMMIT 1".to_string())
+                    ),
+                    schedule: (None, None),
+                }),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_rules() {
+        let rules: super::LogRules = "# Known-benign differences
+mask pointers
+mask pids
+
+replace worker-[a-z]+ => worker-$$
+ignore ^INFO detcore: heartbeat"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            rules.apply("INFO detcore: worker-bob read 0x7fcf from /proc/1234/maps, pid 1234"),
+            Some("INFO detcore: worker-$ read <ADDR> from /proc/<PID>/maps, pid <PID>".to_string())
+        );
+        assert_eq!(rules.apply("INFO detcore: heartbeat 5"), None);
+
+        assert!("mask colors".parse::<super::LogRules>().is_err());
+        assert!("replace ( => x".parse::<super::LogRules>().is_err());
+        assert!("replace x".parse::<super::LogRules>().is_err());
+        assert!("frobnicate".parse::<super::LogRules>().is_err());
+    }
+
+    #[test]
+    fn test_locate_in_schedule() {
+        use super::*;
+
+        let t3 = DetTid::from_raw(3);
+        let t4 = DetTid::from_raw(4);
+        let schedule = vec![
+            SchedEvent::syscall(t3, Sysno::brk, SyscallPhase::Prehook),
+            SchedEvent::syscall(t3, Sysno::execve, SyscallPhase::Prehook),
+            SchedEvent::syscall(t3, Sysno::execve, SyscallPhase::Posthook),
+            SchedEvent::syscall(t3, Sysno::write, SyscallPhase::Prehook),
+            SchedEvent::syscall(t4, Sysno::read, SyscallPhase::Prehook),
+            SchedEvent::branches(t3, 100),
+            SchedEvent::syscall(t3, Sysno::write, SyscallPhase::Prehook),
+        ];
+        let write = "INFO detcore: [detcore, dtid 3] inbound syscall: write(1) = ?";
+        let read = "INFO detcore: [detcore, dtid 4] inbound syscall: read(0) = ?";
+        let log = [
+            (1, write),
+            (2, read),
+            (3, write),
+            (4, "INFO detcore: COMMIT turn 9, dettid 3"),
+        ];
+        let at = |event, thread| Some(ScheduleLocation { event, thread });
+        assert_eq!(locate_in_schedule(&log, 1, &schedule), at(3, t3));
+        assert_eq!(locate_in_schedule(&log, 2, &schedule), at(4, t4));
+        // The last message mentions no thread, so it's about the one before it.
+        assert_eq!(locate_in_schedule(&log, 4, &schedule), at(6, t3));
+        assert_eq!(locate_in_schedule(&log, 0, &schedule), None);
+    }
+
+    #[test]
+    fn test_nondeterminism_report() {
+        use super::*;
+
+        let commit = "INFO detcore: COMMIT turn 1, dettid 3 using resources {}, on previously \
+                      committed 1_000.000_000_000s";
+        let rand = "INFO detcore: DETLOG [dtid 3] CHAOSRAND => next_rcbs = 12";
+        let getpid = "INFO detcore: DETLOG [syscall][detcore, dtid 3] finish syscall #1: \
+                      getpid() = Ok(3)";
+        let read_a = "INFO detcore: DETLOG [syscall][detcore, dtid 3] finish syscall #2: \
+                      read(3) = Ok(8)";
+        let read_b = "INFO detcore: DETLOG [syscall][detcore, dtid 3] finish syscall #2: \
+                      read(3) = Ok(5)";
+        let log_a = [(1, commit), (2, rand), (3, getpid), (4, read_a)];
+        let log_b = [(1, commit), (2, rand), (3, getpid), (4, read_b)];
+        let divergence = LogDivergence {
+            which: "DETLOGs".to_string(),
+            lines: (Some(4), Some(4)),
+            messages: (Some(read_a.to_string()), Some(read_b.to_string())),
+            schedule: (None, None),
+        };
+        let report = NondeterminismReport::new(divergence, &log_a, &log_b);
+        assert_eq!(report.source, NondeterminismSource::SyscallResult);
+        let both = |s: &str| (Some(s.to_string()), Some(s.to_string()));
+        assert_eq!(report.time, both("1_000.000_000_000s"));
+        assert_eq!(report.random, both(rand));
+        assert_eq!(report.syscall.0.as_deref(), Some(read_a));
+
+        // The second run ended early, before the random value the first drew.
+        let divergence = LogDivergence {
+            which: "DETLOGs".to_string(),
+            lines: (Some(2), None),
+            messages: (Some(rand.to_string()), None),
+            schedule: (None, None),
+        };
+        let report = NondeterminismReport::new(divergence, &log_a, &log_b[..1]);
+        assert_eq!(report.source, NondeterminismSource::RandomValue);
+        assert_eq!(report.syscall, (None, None));
+        assert_eq!(report.cause, None);
+    }
+
+    #[test]
+    fn test_root_causes() {
+        use super::*;
+
+        let result = |call: &str| {
+            format!(
+                "INFO detcore: DETLOG [syscall][detcore, dtid 3] finish syscall #2: {} = Ok(1)",
+                call
+            )
+        };
+        let classify = |call: &str, before: &[&str]| {
+            let before: Vec<String> = before.iter().map(|s| s.to_string()).collect();
+            let result = result(call);
+            RootCause::classify([Some(result.as_str()), None], [&before[..], &[]])
+        };
+        let passed = "INFO detcore: DETLOG [detcore, dtid 3] passing unsupported syscall \
+                      io_uring_enter through to the host";
+        assert_eq!(
+            classify("io_uring_enter(3, 1, 0, 0)", &[passed]),
+            Some(RootCause::UnsupportedSyscall)
+        );
+        assert_eq!(
+            classify("recvfrom(4, 0x1000, 64)", &[]),
+            Some(RootCause::RealNetwork)
+        );
+        assert_eq!(classify("read(3)", &[]), Some(RootCause::ExternalFile));
+        let mmap = "INFO detcore: DETLOG [syscall][detcore, dtid 3] finish syscall #1: \
+                    mmap(NULL, 4096, PROT_READ | PROT_WRITE, MAP_SHARED, 5, 0) = Ok(4096)";
+        assert_eq!(classify("getpid()", &[mmap]), Some(RootCause::SharedMemory));
+        assert_eq!(classify("getpid()", &[]), None);
+    }
+
+    #[test]
+    fn test_many_logs() {
+        use std::path::PathBuf;
+
+        let log = |messages: &[&str]| -> Vec<(usize, String)> {
+            messages
+                .iter()
+                .enumerate()
+                .map(|(ix, s)| (ix + 1, s.to_string()))
+                .collect()
+        };
+        let files: Vec<PathBuf> = ["a", "b", "c", "d"].iter().map(PathBuf::from).collect();
+        let logs = [
+            log(&["COMMIT 1", "COMMIT 3"]),
+            log(&["COMMIT 1", "COMMIT 2"]),
+            log(&["COMMIT 1", "COMMIT 2"]),
+            log(&["COMMIT 1"]),
+        ];
+        let report = super::ManyLogDiffReport::new(&files, &logs);
+        assert_eq!(
+            report.consensus,
+            vec![PathBuf::from("b"), PathBuf::from("c")]
+        );
+        assert_eq!(
+            report.outliers,
+            vec![
+                super::LogOutlier {
+                    log: PathBuf::from("a"),
+                    matched: 1,
+                    line: Some(2),
+                    message: Some("COMMIT 3".to_string()),
+                },
+                super::LogOutlier {
+                    log: PathBuf::from("d"),
+                    matched: 1,
+                    line: None,
+                    message: None,
+                },
+            ]
+        );
+        assert_eq!(
+            report.to_string(),
+            "2 of 4 logs agree:\n  b\n  c\n\
+             a differs after 1 matching messages, at line 2:\n  COMMIT 3\n\
+             d ends after 1 matching messages.\n"
+        );
+
+        let report = super::ManyLogDiffReport::new(&files[..2], &logs[1..3]);
+        assert!(report.outliers.is_empty());
+        assert_eq!(report.to_string(), "All 2 logs agree.\n");
+    }
+
     #[test]
     fn test_filter_deterministic() {
         let v = super::filter_deterministic(
@@ -719,6 +1885,22 @@ Jun 09 06:49:17.742 TRACE detcore::scheduler: [scheduler] Guest unblocked (<ivar
         assert_eq!(v.len(), 5);
     }
 
+    #[test]
+    fn test_read_compressed_log() -> std::io::Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let log = "INFO detcore: COMMIT turn 1\nINFO detcore: COMMIT turn 2\n";
+        let plain = dir.path().join("run.log");
+        let compressed = dir.path().join("run.log.zst");
+        std::fs::write(&plain, log)?;
+        std::fs::write(&compressed, zstd::encode_all(log.as_bytes(), 0)?)?;
+        assert_eq!(super::read_log(&plain)?, log.as_bytes());
+        assert_eq!(super::read_log(&compressed)?, log.as_bytes());
+        crate::util::compress_in_place(&plain)?;
+        assert!(crate::util::is_zstd_file(&plain));
+        assert_eq!(super::read_log(&plain)?, log.as_bytes());
+        Ok(())
+    }
+
     #[test]
     fn test_strip_log() {
         assert_eq!(super::strip_log_entry("800.709_180s"), "<NANOSECONDS>");
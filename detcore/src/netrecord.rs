@@ -0,0 +1,282 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Recordings of the guest's connections to external (non-loopback) peers, made with
+//! `--record-network-to` and served back with `--replay-network-from`, so that a program
+//! which talks to the outside world runs hermetically once its traffic has been captured.
+
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use serde::Deserialize;
+use serde::Serialize;
+use tracing::info;
+
+use crate::types::LogicalTime;
+
+/// Name lookups go to this port, on whichever resolver the host is configured with.
+pub const DNS_PORT: u16 = 53;
+
+/// Connections to any resolver are recorded (and replayed) under this name, rather than the
+/// resolver's address, so that a recording of lookups does not depend on the host.
+pub const DNS_PEER: &str = "resolver:53";
+
+/// Which way a chunk of data went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    /// Written by the guest.
+    Sent,
+    /// Read by the guest.
+    Received,
+}
+
+/// A run of bytes moving in one direction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetChunk {
+    /// Which way the bytes went.
+    pub direction: Direction,
+    /// The bytes themselves.
+    pub data: Vec<u8>,
+    /// Host time between the connection being made and the (first of these) bytes
+    /// arriving or leaving.
+    pub elapsed: Duration,
+}
+
+/// Everything exchanged over one connection, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetConnection {
+    /// The address connected to, e.g. "93.184.216.34:80".
+    pub peer: String,
+    /// The traffic.  Consecutive chunks always go in opposite directions.
+    pub chunks: Vec<NetChunk>,
+}
+
+impl NetConnection {
+    fn push(&mut self, direction: Direction, data: Vec<u8>, elapsed: Duration) {
+        match self.chunks.last_mut() {
+            Some(last) if last.direction == direction => last.data.extend(data),
+            _ => self.chunks.push(NetChunk {
+                direction,
+                data,
+                elapsed,
+            }),
+        }
+    }
+}
+
+/// All the connections of a container, in the order they were made.  This is the format
+/// of the file written by `--record-network-to`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct NetRecording {
+    connections: Vec<NetConnection>,
+}
+
+impl NetRecording {
+    /// Read a recording for replay.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = fs::read_to_string(path).map_err(|e| {
+            format!(
+                "Unable to read --replay-network-from {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        let recording: NetRecording = serde_json::from_str(&json)
+            .map_err(|e| format!("Malformed network recording {}: {}", path.display(), e))?;
+        info!(
+            "Loaded {} recorded connection(s) from {}",
+            recording.connections.len(),
+            path.display()
+        );
+        Ok(recording)
+    }
+
+    /// Write out the recording, as JSON.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, json)
+            .map_err(|e| format!("Unable to write network recording {:?}: {}", path, e))
+    }
+
+    /// The number of connections in the recording.
+    pub fn num_connections(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Start recording a new connection, returning its id.
+    pub fn open(&mut self, peer: String) -> usize {
+        self.connections.push(NetConnection {
+            peer,
+            chunks: Vec::new(),
+        });
+        self.connections.len() - 1
+    }
+
+    /// Append traffic to a connection being recorded.
+    pub fn append(&mut self, id: usize, direction: Direction, data: Vec<u8>, elapsed: Duration) {
+        self.connections[id].push(direction, data, elapsed);
+    }
+
+    /// Hand out the earliest remaining recorded connection to the peer, for replay.
+    pub fn take(&mut self, peer: &str) -> Option<NetConnection> {
+        let ix = self.connections.iter().position(|c| c.peer == peer)?;
+        Some(self.connections.remove(ix))
+    }
+}
+
+/// A recorded connection being served back to the guest.  The two directions are
+/// independent: what the guest sends is checked against the recorded bytes, and what it
+/// receives is served from the recorded responses, in the same chunks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayedStream {
+    conn: NetConnection,
+    /// The address the guest connected to, reported by `getpeername`.
+    addr: SocketAddr,
+    /// When the guest connected, against which the recorded timings are reproduced.
+    connected_at: LogicalTime,
+    /// Bytes sent by the guest so far.
+    sent: usize,
+    /// The next chunk to receive from, and the position within it.
+    recv_chunk: usize,
+    recv_offset: usize,
+}
+
+impl ReplayedStream {
+    /// Replay a connection made to the given address at the given time.
+    pub fn new(conn: NetConnection, addr: SocketAddr, connected_at: LogicalTime) -> Self {
+        ReplayedStream {
+            conn,
+            addr,
+            connected_at,
+            sent: 0,
+            recv_chunk: 0,
+            recv_offset: 0,
+        }
+    }
+
+    /// The peer this connection was made to.
+    pub fn peer(&self) -> &str {
+        &self.conn.peer
+    }
+
+    /// The address the guest connected to.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The logical time at which the next bytes to receive arrive, or `None` if the peer
+    /// has nothing more to send.
+    pub fn next_arrival(&self) -> Option<LogicalTime> {
+        let pending = self.conn.chunks[self.recv_chunk.min(self.conn.chunks.len())..]
+            .iter()
+            .enumerate()
+            .find(|(ix, chunk)| {
+                let offset = if *ix == 0 { self.recv_offset } else { 0 };
+                chunk.direction == Direction::Received && offset < chunk.data.len()
+            })?;
+        Some(self.connected_at + pending.1.elapsed.as_nanos())
+    }
+
+    /// Account for bytes sent by the guest.  Returns false if they diverge from the
+    /// recording.
+    pub fn send(&mut self, data: &[u8]) -> bool {
+        let start = self.sent;
+        self.sent += data.len();
+        let mut expected = self
+            .conn
+            .chunks
+            .iter()
+            .filter(|c| c.direction == Direction::Sent)
+            .flat_map(|c| c.data.iter())
+            .skip(start);
+        data.iter().all(|b| expected.next() == Some(b))
+    }
+
+    /// Receive up to `len` bytes, which never span chunks, along with the logical time
+    /// at which they arrive.  No bytes means the peer has closed the connection.
+    pub fn recv(&mut self, len: usize) -> (Vec<u8>, LogicalTime) {
+        while let Some(chunk) = self.conn.chunks.get(self.recv_chunk) {
+            if chunk.direction == Direction::Received && self.recv_offset < chunk.data.len() {
+                let start = self.recv_offset;
+                let end = chunk.data.len().min(start.saturating_add(len));
+                self.recv_offset = end;
+                return (
+                    chunk.data[start..end].to_vec(),
+                    self.connected_at + chunk.elapsed.as_nanos(),
+                );
+            }
+            self.recv_chunk += 1;
+            self.recv_offset = 0;
+        }
+        (Vec::new(), self.connected_at)
+    }
+}
+
+/// What detcore knows about a socket connected to an external peer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum NetStream {
+    /// Traffic is passed through and recorded, under the given connection id, from the
+    /// given (host) time.
+    Recording(usize, SystemTime),
+    /// Traffic is served from a recording.  Shared with any dups of the fd.
+    Replaying(Arc<Mutex<ReplayedStream>>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_serves_recorded_chunks() {
+        let mut recording = NetRecording::default();
+        let id = recording.open("10.0.0.1:80".to_string());
+        let ms = Duration::from_millis;
+        recording.append(id, Direction::Sent, b"GET ".to_vec(), ms(0));
+        recording.append(id, Direction::Sent, b"/".to_vec(), ms(1));
+        recording.append(id, Direction::Received, b"200 OK".to_vec(), ms(30));
+        recording.append(id, Direction::Received, b"!".to_vec(), ms(31));
+        recording.append(id, Direction::Sent, b"bye".to_vec(), ms(40));
+        recording.append(id, Direction::Received, b"ok".to_vec(), ms(50));
+        recording.open("10.0.0.2:80".to_string());
+        assert_eq!(recording.num_connections(), 2);
+
+        assert_eq!(recording.take("10.0.0.3:80"), None);
+        let conn = recording.take("10.0.0.1:80").unwrap();
+        assert_eq!(conn.chunks.len(), 4);
+        assert_eq!(recording.take("10.0.0.1:80"), None);
+
+        let start = LogicalTime::from_secs(1);
+        let addr: SocketAddr = "10.0.0.1:80".parse().unwrap();
+        let mut stream = ReplayedStream::new(conn, addr, start);
+        assert_eq!(stream.addr(), addr);
+        assert!(stream.send(b"GET /"));
+        assert_eq!(stream.next_arrival(), Some(start + ms(30).as_nanos()));
+        assert_eq!(stream.recv(4), (b"200 ".to_vec(), start + ms(30).as_nanos()));
+        assert_eq!(stream.recv(10), (b"OK!".to_vec(), start + ms(30).as_nanos()));
+        assert!(!stream.send(b"BYE"));
+        assert_eq!(stream.next_arrival(), Some(start + ms(50).as_nanos()));
+        assert_eq!(stream.recv(10), (b"ok".to_vec(), start + ms(50).as_nanos()));
+        assert_eq!(stream.next_arrival(), None);
+        assert_eq!(stream.recv(10), (Vec::new(), start));
+    }
+
+    #[test]
+    fn load_reports_errors() {
+        let err = NetRecording::load(Path::new("/nonexistent/network.json")).unwrap_err();
+        assert!(
+            err.starts_with("Unable to read --replay-network-from"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,237 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Timelines of runs in the JSON trace event format of Chrome, which can be opened in
+//! ui.perfetto.dev or chrome://tracing.
+//!
+//! Each guest thread is a track. Its events are slices laid out one after the other in virtual
+//! time, so that the thread is shown running during its slices, and waiting between them. Each
+//! syscall is a slice spanning its events, from before it starts to after it completes, and the
+//! preemptions and signals of the thread are markers.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::scheduler::Priority;
+use crate::types::DetTid;
+use crate::types::LogicalTime;
+use crate::types::Op;
+use crate::types::SchedEvent;
+use crate::types::SyscallPhase;
+
+/// How long an event without a time is shown to take, in nanoseconds.
+const UNTIMED_EVENT_NS: u64 = 1000;
+
+/// A trace in the Chrome trace event format.
+#[derive(Debug, Serialize)]
+pub struct Trace {
+    #[serde(rename = "traceEvents")]
+    trace_events: Vec<TraceEvent>,
+    #[serde(rename = "displayTimeUnit")]
+    display_time_unit: &'static str,
+}
+
+/// One entry of a [`Trace`]. Times are in microseconds.
+#[derive(Debug, PartialEq, Serialize)]
+struct TraceEvent {
+    name: String,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    cat: &'static str,
+    ph: &'static str,
+    pid: u32,
+    tid: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ts: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<f64>,
+    /// The scope of an instant event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    s: Option<&'static str>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    args: BTreeMap<&'static str, String>,
+}
+
+impl TraceEvent {
+    fn new(name: String, ph: &'static str, tid: DetTid) -> Self {
+        TraceEvent {
+            name,
+            cat: "",
+            ph,
+            pid: 1,
+            tid: tid.as_raw(),
+            ts: None,
+            dur: None,
+            s: None,
+            args: BTreeMap::new(),
+        }
+    }
+
+    /// A marker on the track of `tid`, at virtual time `time`.
+    fn marker(name: String, cat: &'static str, tid: DetTid, time: LogicalTime) -> Self {
+        let mut marker = TraceEvent::new(name, "i", tid);
+        marker.cat = cat;
+        marker.ts = Some(micros(time.as_nanos()));
+        marker.s = Some("t");
+        marker
+    }
+}
+
+fn micros(nanos: u64) -> f64 {
+    nanos as f64 / 1000.0
+}
+
+/// The name and phase of the call an event is part of, if it is part of one.
+fn call_of(op: &Op) -> Option<(String, SyscallPhase)> {
+    match op {
+        Op::Syscall(sysno, phase) => Some((sysno.to_string(), *phase)),
+        Op::Condvar(op, phase) => Some((format!("{:?}", op), *phase)),
+        Op::Socket(op, phase) => Some((format!("{:?}", op), *phase)),
+        Op::Branch | Op::Rdtsc | Op::Cpuid | Op::OtherInstructions => None,
+    }
+}
+
+impl Trace {
+    /// Lays the events out one after the other, each ending at its `end_time`, and marks each
+    /// preemption on the track of its thread.
+    pub fn new(
+        preemptions: &BTreeMap<DetTid, Vec<(LogicalTime, Priority)>>,
+        events: &[SchedEvent],
+    ) -> Self {
+        let mut trace_events = Vec::new();
+        let mut threads: Vec<DetTid> = preemptions.keys().copied().collect();
+        threads.extend(events.iter().map(|ev| ev.dettid));
+        threads.sort();
+        threads.dedup();
+        for tid in threads {
+            let mut meta = TraceEvent::new("thread_name".to_owned(), "M", tid);
+            meta.args.insert("name", format!("thread {}", tid));
+            trace_events.push(meta);
+        }
+
+        let mut now = 0;
+        // The syscall each thread is in, and when it started.
+        let mut calls: BTreeMap<DetTid, (String, u64)> = BTreeMap::new();
+        for (i, ev) in events.iter().enumerate() {
+            let end = ev
+                .end_time
+                .map_or(now + UNTIMED_EVENT_NS, |t| t.as_nanos())
+                .max(now);
+            let mut slice = TraceEvent::new(format!("{:?}", ev.op), "X", ev.dettid);
+            slice.cat = "event";
+            slice.ts = Some(micros(now));
+            slice.dur = Some(micros(end - now));
+            slice.args.insert("index", i.to_string());
+            slice.args.insert("count", ev.count.to_string());
+            trace_events.push(slice);
+
+            match call_of(&ev.op) {
+                Some((name, SyscallPhase::Prehook)) => {
+                    calls.insert(ev.dettid, (name, now));
+                }
+                Some((_, SyscallPhase::Posthook | SyscallPhase::TimedOut)) => {
+                    if let Some((name, start)) = calls.remove(&ev.dettid) {
+                        let mut call = TraceEvent::new(name, "X", ev.dettid);
+                        call.cat = "syscall";
+                        call.ts = Some(micros(start));
+                        call.dur = Some(micros(end - start));
+                        trace_events.push(call);
+                    }
+                }
+                Some((_, SyscallPhase::Polling)) | None => {}
+            }
+            now = end;
+        }
+
+        for (tid, history) in preemptions {
+            for (time, prio) in history {
+                let name = format!("priority {}", prio);
+                trace_events.push(TraceEvent::marker(name, "preemption", *tid, *time));
+            }
+        }
+
+        Trace {
+            trace_events,
+            display_time_unit: "ns",
+        }
+    }
+
+    /// Marks the delivery of a signal to a thread, at the thread's virtual time.
+    pub fn add_signal(&mut self, tid: DetTid, time: LogicalTime, signal: impl Display) {
+        let name = signal.to_string();
+        self.trace_events
+            .push(TraceEvent::marker(name, "signal", tid, time));
+    }
+
+    /// Writes the trace to `path` as JSON.
+    pub fn write_to(&self, path: &Path) -> io::Result<()> {
+        let out = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(out, self)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn perfetto_trace() {
+        let tid = DetTid::from_raw(3);
+        let mut preemptions = BTreeMap::new();
+        preemptions.insert(tid, vec![(LogicalTime::from_nanos(2000), 20)]);
+        let mut events = vec![
+            SchedEvent::branches(tid, 5),
+            SchedEvent::branches(DetTid::from_raw(4), 2),
+        ];
+        events[1].end_time = Some(LogicalTime::from_nanos(3500));
+
+        let trace = Trace::new(&preemptions, &events);
+        let kinds: Vec<&str> = trace.trace_events.iter().map(|ev| ev.ph).collect();
+        assert_eq!(kinds, vec!["M", "M", "X", "X", "i"]);
+        // The untimed event takes up a fixed slot, and the next one ends at its time.
+        assert_eq!(trace.trace_events[2].ts, Some(0.0));
+        assert_eq!(trace.trace_events[2].dur, Some(1.0));
+        assert_eq!(trace.trace_events[3].ts, Some(1.0));
+        assert_eq!(trace.trace_events[3].dur, Some(2.5));
+        assert_eq!(trace.trace_events[3].tid, 4);
+        assert_eq!(trace.trace_events[4].ts, Some(2.0));
+    }
+
+    #[test]
+    fn syscalls_and_signals() {
+        let tid = DetTid::from_raw(3);
+        let read = |phase, nanos| {
+            SchedEvent::syscall(tid, reverie::syscalls::Sysno::read, phase)
+                .with_time(LogicalTime::from_nanos(nanos))
+        };
+        let events = vec![
+            read(SyscallPhase::Prehook, 1000),
+            SchedEvent::branches(DetTid::from_raw(4), 2),
+            read(SyscallPhase::Posthook, 5000),
+        ];
+
+        let mut trace = Trace::new(&BTreeMap::new(), &events);
+        trace.add_signal(tid, LogicalTime::from_nanos(6000), "SIGUSR1");
+        let call = trace
+            .trace_events
+            .iter()
+            .find(|ev| ev.cat == "syscall")
+            .unwrap();
+        assert_eq!(call.name, "read");
+        assert_eq!((call.tid, call.ts, call.dur), (3, Some(0.0), Some(5.0)));
+        let signal = trace.trace_events.last().unwrap();
+        assert_eq!((signal.name.as_str(), signal.ph), ("SIGUSR1", "i"));
+        assert_eq!(signal.ts, Some(6.0));
+    }
+}
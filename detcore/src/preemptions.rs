@@ -9,22 +9,97 @@
 //! A datatype to abstract a record of thread preemptions, as generated during chaos mode execution.
 
 use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::ops::Range;
+use std::os::unix::io::AsRawFd;
 use std::path::Path;
 use std::path::PathBuf;
 
 use serde::Deserialize;
 use serde::Serialize;
 use tracing::trace;
+use tracing::warn;
 
 use crate::scheduler::runqueue::is_ordinary_priority;
 use crate::scheduler::runqueue::DEFAULT_PRIORITY;
 use crate::scheduler::runqueue::FIRST_PRIORITY;
 use crate::scheduler::runqueue::LAST_PRIORITY;
 use crate::scheduler::Priority;
+use crate::config::SpawnOrder;
 use crate::types::DetTid;
+use crate::types::InstructionPointer;
 use crate::types::LogicalTime;
+use crate::types::Op;
+use crate::types::RecordMetadata;
 use crate::types::SchedEvent;
+use crate::types::ThreadIdentity;
+use crate::util::is_zstd_file;
+use crate::util::ZSTD_MAGIC;
+
+/// The first bytes of a record in the binary format.
+const BINARY_MAGIC: &[u8; 8] = b"HRMTSCHD";
+
+/// The version of the binary format, which follows the magic as a little-endian `u32`.  Version
+/// 1 lacked `metadata`, and versions before 3 lacked the identities of threads on events.
+const BINARY_VERSION: u32 = 3;
+
+/// The version of the JSON schema of `PreemptionRecord` and the `SchedEvent`s in it, written into
+/// each record as `"version"`.  Records from before it was written are version 1.  Bump it, and
+/// add a step to `migrate`, whenever a change would keep older records from parsing.
+const SCHEMA_VERSION: u64 = 2;
+
+/// The version of the streaming format, given in its header.
+const STREAM_VERSION: u32 = 1;
+
+/// How the header of the streaming format starts.
+const STREAM_START: &[u8] = b"{\"Header\":";
+
+/// How a `PreemptionRecord` is stored on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    /// A single line of JSON.
+    Json,
+    /// A magic header and version, followed by the record encoded with bincode.  Much smaller
+    /// and faster to parse than JSON for long runs.
+    Binary,
+    /// JSON lines, each a change to the record, appended as they happen.  A `PreemptionWriter`
+    /// writing this format does not keep the scheduling events in memory, and a run that
+    /// crashes leaves a usable prefix of its record behind.
+    Stream,
+}
+
+impl RecordFormat {
+    /// The format to write to a path in: binary if its extension is `.bin`, streaming if it is
+    /// `.jsonl`, and JSON otherwise.
+    pub fn for_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("bin") => RecordFormat::Binary,
+            Some("jsonl") => RecordFormat::Stream,
+            _ => RecordFormat::Json,
+        }
+    }
+}
+
+impl std::str::FromStr for RecordFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(RecordFormat::Json),
+            "binary" => Ok(RecordFormat::Binary),
+            "stream" => Ok(RecordFormat::Stream),
+            _ => Err(format!(
+                "Expected json|binary|stream, could not parse: {:?}",
+                s
+            )),
+        }
+    }
+}
 
 /// A record of all the preemptions and other scheduling events that occur during execution.
 #[derive(PartialEq, Default, Debug, Eq, Clone, Hash, Serialize, Deserialize)]
@@ -33,21 +108,70 @@ pub struct PreemptionRecord {
     /// A sorted list of end-of-timeslice preemption times for each thread.
     per_thread: BTreeMap<DetTid, ThreadHistory>,
     global: Vec<SchedEvent>,
+    /// The architecture the record was made on.  Its times are counted in branches and its
+    /// events carry instruction pointers, neither of which mean anything on another.  Absent in
+    /// records from before this was tracked, which are not checked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    arch: Option<String>,
+    /// Where the record came from.  Absent in records from before this was tracked, and in
+    /// schedules made up by tools, neither of which are checked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<RecordMetadata>,
+}
+
+/// A `PreemptionRecord` as written in JSON, with the version of its schema.
+#[derive(Serialize)]
+struct Versioned<'a> {
+    version: u64,
+    #[serde(flatten)]
+    record: &'a PreemptionRecord,
 }
 
 impl std::fmt::Display for PreemptionRecord {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let str = serde_json::to_string(&self).unwrap();
+        let versioned = Versioned {
+            version: SCHEMA_VERSION,
+            record: self,
+        };
+        let str = serde_json::to_string(&versioned).unwrap();
         write!(f, "{}", str)
     }
 }
 
+/// Brings a record in JSON up to the current `SCHEMA_VERSION`.
+fn migrate(mut value: serde_json::Value) -> Result<serde_json::Value, String> {
+    let record = value
+        .as_object_mut()
+        .ok_or("PreemptionRecord in JSON is not an object")?;
+    let version = match record.remove("version") {
+        None => 1,
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| format!("invalid PreemptionRecord version {}", version))?,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "PreemptionRecord has schema version {}, but this hermit reads up to version {}",
+            version, SCHEMA_VERSION
+        ));
+    }
+    if version < 2 {
+        // Version 1 records made only to replay preemptions need not have a schedule.
+        record
+            .entry("global")
+            .or_insert_with(|| serde_json::Value::Array(Vec::new()));
+    }
+    Ok(value)
+}
+
 impl PreemptionRecord {
     /// TODO: This is not ideal because PreemptionRecord shouldn't be doing double duty as an eventlog.
     pub fn from_sched_events(events: Vec<SchedEvent>) -> Self {
         Self {
             per_thread: Default::default(),
             global: events,
+            arch: None,
+            metadata: None,
         }
     }
 
@@ -69,6 +193,7 @@ impl PreemptionRecord {
         for (_tid, history) in self.per_thread.iter_mut() {
             history.prio_changes = Vec::new();
             history.final_prio = 1000;
+            history.spawn_order = None;
         }
         self.global = Vec::new();
         self
@@ -88,6 +213,7 @@ impl PreemptionRecord {
                 ThreadHistory {
                     final_prio: DEFAULT_PRIORITY,
                     prio_changes: Vec::new(),
+                    spawn_order: None,
                 }
             } else {
                 // We could insist on this invariant, but it's a little more flexible not to:
@@ -111,6 +237,7 @@ impl PreemptionRecord {
                 ThreadHistory {
                     final_prio,
                     prio_changes,
+                    spawn_order: None,
                 }
             };
             bt2.insert(*tid, th);
@@ -118,6 +245,8 @@ impl PreemptionRecord {
         PreemptionRecord {
             per_thread: bt2,
             global: Vec::new(),
+            arch: None,
+            metadata: None,
         }
     }
 
@@ -126,12 +255,223 @@ impl PreemptionRecord {
         self.global
     }
 
-    /// Save to disk.
+    /// Where the record came from, if that is known.
+    pub fn metadata(&self) -> Option<&RecordMetadata> {
+        self.metadata.as_ref()
+    }
+
+    /// The global events, for editing in place.
+    pub fn global_mut(&mut self) -> &mut Vec<SchedEvent> {
+        &mut self.global
+    }
+
+    /// Remove the `index`th preemption of a thread, merging the timeslices on either side of it
+    /// into one with the priority of the earlier. Returns false if there is no such preemption.
+    pub fn remove_preemption(&mut self, tid: DetTid, index: usize) -> bool {
+        let history = match self.per_thread.get_mut(&tid) {
+            Some(history) if index < history.prio_changes.len() => history,
+            _ => return false,
+        };
+        let (_ns, prio) = history.prio_changes.remove(index);
+        match history.prio_changes.get_mut(index) {
+            Some((_ns, next)) => *next = prio,
+            None => history.final_prio = prio,
+        }
+        true
+    }
+
+    /// Keep only the threads, and their events, for which `keep` returns true.
+    pub fn retain_threads(&mut self, mut keep: impl FnMut(DetTid) -> bool) {
+        self.per_thread.retain(|tid, _| keep(*tid));
+        self.global.retain(|ev| keep(ev.dettid));
+    }
+
+    /// Keep only the preemptions at times in `start..end`. Each thread then starts with the
+    /// priority it had at `start`, and ends with the priority it had at `end`. The global events
+    /// are left as they are.
+    pub fn retain_preemptions_between(&mut self, start: LogicalTime, end: LogicalTime) {
+        for history in self.per_thread.values_mut() {
+            if let Some(ix) = history.prio_changes.iter().position(|(ns, _)| *ns >= end) {
+                history.final_prio = history.prio_changes[ix].1;
+                history.prio_changes.truncate(ix);
+            }
+            history.prio_changes.retain(|(ns, _)| *ns >= start);
+        }
+    }
+
+    /// Encode the record in the given format.
+    pub fn to_bytes(&self, format: RecordFormat) -> Vec<u8> {
+        match format {
+            RecordFormat::Json => {
+                let mut str: String = self.to_string();
+                str.push('\n');
+                str.into_bytes()
+            }
+            RecordFormat::Binary => {
+                let mut bytes = BINARY_MAGIC.to_vec();
+                bytes.extend_from_slice(&BINARY_VERSION.to_le_bytes());
+                bincode::serialize_into(&mut bytes, &BinaryRecord::from(self.clone())).unwrap();
+                bytes
+            }
+            RecordFormat::Stream => {
+                let mut bytes = Vec::new();
+                for entry in self.stream_entries() {
+                    entry.write_to(&mut bytes).unwrap();
+                }
+                bytes
+            }
+        }
+    }
+
+    /// The entries that a streaming `PreemptionWriter` would have written for this record.
+    fn stream_entries(&self) -> Vec<StreamEntry> {
+        let mut entries = vec![StreamEntry::Header {
+            version: STREAM_VERSION,
+            arch: self.arch.clone(),
+            metadata: self.metadata.clone(),
+        }];
+        for (tid, history) in &self.per_thread {
+            let tid = *tid;
+            entries.push(StreamEntry::Register {
+                tid,
+                prio: history.initial_priority(),
+            });
+            if let Some(order) = history.spawn_order {
+                entries.push(StreamEntry::SpawnOrder { tid, order });
+            }
+            let next_prios = history
+                .prio_changes
+                .iter()
+                .skip(1)
+                .map(|(_, prio)| *prio)
+                .chain(std::iter::once(history.final_prio));
+            for ((time, prior_prio), next_prio) in history.prio_changes.iter().zip(next_prios) {
+                entries.push(StreamEntry::Reprioritize {
+                    tid,
+                    time: *time,
+                    prior_prio: *prior_prio,
+                    next_prio,
+                });
+            }
+        }
+        entries.extend(self.global.iter().cloned().map(StreamEntry::Event));
+        entries
+    }
+
+    /// Rebuild a record from the entries of a streamed one.  A final line that was cut short,
+    /// as by a crash of the writer, is ignored.
+    fn from_stream<R: BufRead>(reader: R) -> Result<Self, String> {
+        let mut pr = PreemptionRecord::default();
+        for entry in StreamEntries::new(reader) {
+            match entry? {
+                StreamEntry::Header {
+                    version,
+                    arch,
+                    metadata,
+                } => {
+                    if version != STREAM_VERSION {
+                        return Err(format!(
+                            "unsupported streamed PreemptionRecord version {}, expected {}",
+                            version, STREAM_VERSION
+                        ));
+                    }
+                    pr.arch = arch;
+                    pr.metadata = metadata;
+                }
+                StreamEntry::Register { tid, prio } => {
+                    let history = ThreadHistory {
+                        final_prio: prio,
+                        prio_changes: Vec::new(),
+                        spawn_order: None,
+                    };
+                    if pr.per_thread.insert(tid, history).is_some() {
+                        return Err(format!("thread {} registered twice", tid));
+                    }
+                }
+                StreamEntry::SpawnOrder { tid, order } => {
+                    pr.thread_mut(tid)?.spawn_order = Some(order);
+                }
+                StreamEntry::Reprioritize {
+                    tid,
+                    time,
+                    prior_prio,
+                    next_prio,
+                } => {
+                    let history = pr.thread_mut(tid)?;
+                    history.prio_changes.push((time, prior_prio));
+                    history.final_prio = next_prio;
+                }
+                StreamEntry::SetCurrent { tid, prio } => {
+                    pr.thread_mut(tid)?.final_prio = prio;
+                }
+                StreamEntry::Event(ev) => pr.global.push(ev),
+            }
+        }
+        Ok(pr)
+    }
+
+    fn thread_mut(&mut self, tid: DetTid) -> Result<&mut ThreadHistory, String> {
+        self.per_thread
+            .get_mut(&tid)
+            .ok_or_else(|| format!("thread {} used before it was registered", tid))
+    }
+
+    /// Decode a record in any format, telling them apart by how they start.  A record may also
+    /// be compressed with zstd.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.starts_with(ZSTD_MAGIC) {
+            let bytes = zstd::decode_all(bytes)
+                .map_err(|e| format!("corrupt compressed PreemptionRecord: {}", e))?;
+            return PreemptionRecord::from_bytes(&bytes);
+        }
+        if bytes.starts_with(STREAM_START) {
+            return PreemptionRecord::from_stream(bytes);
+        }
+        match bytes.strip_prefix(BINARY_MAGIC) {
+            Some(rest) => {
+                if rest.len() < 4 {
+                    return Err("truncated header of binary PreemptionRecord".to_string());
+                }
+                let (version, rest) = rest.split_at(4);
+                let record = match u32::from_le_bytes(version.try_into().unwrap()) {
+                    1 => bincode::deserialize::<BinaryRecordV1>(rest).map(BinaryRecord::from),
+                    2 => bincode::deserialize::<BinaryRecordV2>(rest).map(BinaryRecord::from),
+                    BINARY_VERSION => bincode::deserialize::<BinaryRecord>(rest),
+                    version => {
+                        return Err(format!(
+                            "unsupported binary PreemptionRecord version {}, expected {}",
+                            version, BINARY_VERSION
+                        ));
+                    }
+                };
+                record
+                    .map(PreemptionRecord::from)
+                    .map_err(|e| format!("Error parsing binary PreemptionRecord: {}", e))
+            }
+            None => serde_json::from_slice(bytes)
+                .map_err(|e| e.to_string())
+                .and_then(migrate)
+                .and_then(|value| serde_json::from_value(value).map_err(|e| e.to_string()))
+                .map_err(|e| {
+                    format!(
+                        "Error parsing PreemptionRecord from JSON: {}\nJSON contents:\n{}",
+                        e,
+                        String::from_utf8_lossy(bytes)
+                    )
+                }),
+        }
+    }
+
+    /// Save to disk, in the format given by [`RecordFormat::for_path`].
     pub fn write_to_disk(&self, path: &Path) -> Result<(), String> {
-        let mut str: String = self.to_string();
-        str.push('\n');
+        self.write_to_disk_as(path, RecordFormat::for_path(path))
+    }
+
+    /// Save to disk in the given format.
+    pub fn write_to_disk_as(&self, path: &Path, format: RecordFormat) -> Result<(), String> {
+        let bytes = self.to_bytes(format);
         match File::create(path) {
-            Ok(mut file) => match std::io::Write::write_all(&mut file, str.as_bytes()) {
+            Ok(mut file) => match std::io::Write::write_all(&mut file, &bytes) {
                 Ok(_) => Ok(()),
                 Err(err) => Err(format!(
                     "Failed to write preemption record to file {:?}, error: {}",
@@ -145,6 +485,19 @@ impl PreemptionRecord {
         }
     }
 
+    /// Check that the record can be replayed on this architecture.  Records which do not say
+    /// where they were made are not checked.
+    pub fn check_arch(&self) -> Result<(), String> {
+        match &self.arch {
+            Some(arch) if arch != std::env::consts::ARCH => Err(format!(
+                "record was made on {}, and cannot be replayed on {}",
+                arch,
+                std::env::consts::ARCH
+            )),
+            _ => Ok(()),
+        }
+    }
+
     /// Perform internal invariant checks on the PreemptionRecord and return an error if
     /// it is not well formed.
     pub fn validate(&self) -> Result<(), String> {
@@ -207,7 +560,10 @@ impl PreemptionRecord {
         // One more pass to remove anything that just has default priority its whole lifetime.
         let mut finalmap = BTreeMap::new();
         for (tid, history) in clone.per_thread.into_iter() {
-            if history.final_prio != DEFAULT_PRIORITY || !history.prio_changes.is_empty() {
+            if history.final_prio != DEFAULT_PRIORITY
+                || !history.prio_changes.is_empty()
+                || history.spawn_order.is_some()
+            {
                 assert!(finalmap.insert(tid, history).is_none());
             }
         }
@@ -257,6 +613,134 @@ impl PreemptionRecord {
         }
         clone
     }
+
+    /// Merge schedule fragments, such as those recorded per process or per segment of a run, into
+    /// one record.  The events of the fragments are interleaved by their end times, keeping the
+    /// order within each fragment, and an event without a time stays right after the one before
+    /// it.  A thread in several fragments has its preemptions joined in the order of the
+    /// fragments.
+    ///
+    /// Fails if the fragments were made on different machines or by different runs, if the events
+    /// of a fragment go back in time, or if the fragments disagree about a thread: its priority
+    /// where one fragment leaves off and the next picks up, its spawn order, or its identity.
+    /// Fragments are numbered from zero in errors.
+    pub fn merge(fragments: Vec<PreemptionRecord>) -> Result<PreemptionRecord, String> {
+        let mut merged = PreemptionRecord::default();
+        let mut identities: HashMap<DetTid, &ThreadIdentity> = HashMap::new();
+        let mut identified: HashMap<&ThreadIdentity, DetTid> = HashMap::new();
+        for (n, fragment) in fragments.iter().enumerate() {
+            match (&merged.arch, &fragment.arch) {
+                (Some(arch), Some(other)) if arch != other => {
+                    return Err(format!(
+                        "fragment {} was made on {}, but an earlier one on {}",
+                        n, other, arch
+                    ));
+                }
+                (None, Some(other)) => merged.arch = Some(other.clone()),
+                _ => {}
+            }
+            match (&merged.metadata, &fragment.metadata) {
+                (Some(metadata), Some(other)) => {
+                    let diffs = metadata.differences(other);
+                    if !diffs.is_empty() {
+                        return Err(format!(
+                            "fragment {} is from a different run than an earlier one: {}",
+                            n,
+                            diffs.join("; ")
+                        ));
+                    }
+                }
+                (None, Some(other)) => merged.metadata = Some(other.clone()),
+                _ => {}
+            }
+            for (tid, history) in &fragment.per_thread {
+                match merged.per_thread.get_mut(tid) {
+                    Some(earlier) => earlier
+                        .join(history)
+                        .map_err(|e| format!("fragment {}, thread {}: {}", n, tid, e))?,
+                    None => {
+                        merged.per_thread.insert(*tid, history.clone());
+                    }
+                }
+            }
+
+            let mut time_last = LogicalTime::ZERO;
+            for (ix, ev) in fragment.global.iter().enumerate() {
+                if let Some(time) = ev.end_time {
+                    if time < time_last {
+                        return Err(format!(
+                            "event #{} of fragment {} ends at {}, before the event before it",
+                            ix, n, time
+                        ));
+                    }
+                    time_last = time;
+                }
+                if let Some(identity) = &ev.identity {
+                    let tid = *identified.entry(identity).or_insert(ev.dettid);
+                    if tid != ev.dettid {
+                        return Err(format!(
+                            "event #{} of fragment {} is by thread {} {}, but an earlier event \
+                             gives thread {} that identity",
+                            ix, n, ev.dettid, identity, tid
+                        ));
+                    }
+                    let other = *identities.entry(ev.dettid).or_insert(identity);
+                    if other != identity {
+                        return Err(format!(
+                            "event #{} of fragment {} is by thread {} {}, but an earlier event \
+                             gives it the identity {}",
+                            ix, n, ev.dettid, identity, other
+                        ));
+                    }
+                }
+            }
+        }
+
+        merged.global = interleave(fragments.into_iter().map(|f| f.global).collect());
+        if !merged.per_thread.is_empty() {
+            if let Some((ix, ev)) = merged
+                .global
+                .iter()
+                .enumerate()
+                .find(|(_, ev)| !merged.per_thread.contains_key(&ev.dettid))
+            {
+                return Err(format!(
+                    "event #{} of the merged schedule is by thread {}, which no fragment registers",
+                    ix, ev.dettid
+                ));
+            }
+        }
+        merged.validate()?;
+        Ok(merged)
+    }
+}
+
+/// Interleave sequences of events by their end times, taking the earliest event of the first
+/// sequence among equals.  Events without a time are taken along with the event before them.
+fn interleave(sequences: Vec<Vec<SchedEvent>>) -> Vec<SchedEvent> {
+    let mut sequences: Vec<_> = sequences
+        .into_iter()
+        .map(|events| events.into_iter().peekable())
+        .collect();
+    let mut merged = Vec::new();
+    loop {
+        let next = sequences
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(n, events)| {
+                let ev = events.peek()?;
+                Some((ev.end_time.unwrap_or(LogicalTime::ZERO), n))
+            })
+            .min();
+        let events = match next {
+            Some((_time, n)) => &mut sequences[n],
+            None => return merged,
+        };
+        merged.extend(events.next());
+        while let Some(ev) = events.next_if(|ev| ev.end_time.is_none()) {
+            merged.push(ev);
+        }
+    }
 }
 
 /// The record of priorities and preemptions for a particular thread.
@@ -278,6 +762,11 @@ pub struct ThreadHistory {
     /// private, and thus you must go through the iterator, as this will change in the
     /// future.
     prio_changes: Vec<(LogicalTime, Priority)>,
+
+    /// Whether this thread ran before or after its parent when it was cloned, if that
+    /// order was being perturbed (`--spawn-order`).  Never `Random`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spawn_order: Option<SpawnOrder>,
 }
 
 impl ThreadHistory {
@@ -286,6 +775,7 @@ impl ThreadHistory {
         ThreadHistory {
             final_prio: DEFAULT_PRIORITY,
             prio_changes: Vec::new(),
+            spawn_order: None,
         }
     }
 
@@ -323,6 +813,40 @@ impl ThreadHistory {
             self.final_prio
         }
     }
+
+    /// Continue this history with a later one, which must start where this one leaves off.
+    fn join(&mut self, later: &ThreadHistory) -> Result<(), String> {
+        if later.initial_priority() != self.final_prio {
+            return Err(format!(
+                "starts at priority {}, but an earlier fragment leaves it at {}",
+                later.initial_priority(),
+                self.final_prio
+            ));
+        }
+        if let (Some((last, _)), Some((first, _))) =
+            (self.prio_changes.last(), later.prio_changes.first())
+        {
+            if first <= last {
+                return Err(format!(
+                    "preempted at {}, before its last preemption in an earlier fragment, at {}",
+                    first, last
+                ));
+            }
+        }
+        match (self.spawn_order, later.spawn_order) {
+            (Some(order), Some(other)) if order != other => {
+                return Err(format!(
+                    "spawned {:?}, but {:?} in an earlier fragment",
+                    other, order
+                ));
+            }
+            (None, other) => self.spawn_order = other,
+            _ => {}
+        }
+        self.prio_changes.extend_from_slice(&later.prio_changes);
+        self.final_prio = later.final_prio;
+        Ok(())
+    }
 }
 
 impl Default for ThreadHistory {
@@ -369,6 +893,123 @@ impl Iterator for ThreadHistoryIterator {
     }
 }
 
+/// Builds a [`PreemptionRecord`] from code, so that tools and tests can make up schedules without
+/// writing them out in JSON.
+///
+/// Threads are registered with their initial priority, and then given priority changes in order.
+/// Mistakes, such as preempting a thread that was never registered, are reported by
+/// [`PreemptionRecordBuilder::build`], which also checks the finished record with
+/// [`PreemptionRecord::validate`].
+#[derive(Debug, Clone, Default)]
+pub struct PreemptionRecordBuilder {
+    record: PreemptionRecord,
+    /// The first mistake made while building, reported by `build`.
+    error: Option<String>,
+}
+
+impl PreemptionRecordBuilder {
+    /// An empty record, with no threads or events.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn fail(&mut self, error: String) {
+        self.error.get_or_insert(error);
+    }
+
+    fn check_priority(&mut self, tid: DetTid, prio: Priority) -> bool {
+        let ok = is_ordinary_priority(prio);
+        if !ok {
+            self.fail(format!("priority {} for thread {} is invalid", prio, tid));
+        }
+        ok
+    }
+
+    /// Register a thread, which starts running at priority `prio`.
+    pub fn thread(mut self, tid: DetTid, prio: Priority) -> Self {
+        if self.check_priority(tid, prio) {
+            let history = ThreadHistory {
+                final_prio: prio,
+                ..ThreadHistory::new()
+            };
+            if self.record.per_thread.insert(tid, history).is_some() {
+                self.fail(format!("thread {} registered twice", tid));
+            }
+        }
+        self
+    }
+
+    /// Preempt a registered thread at `time`, after which it runs at priority `prio`.  Each
+    /// thread's preemptions must be added in order of time.
+    pub fn preempt(mut self, tid: DetTid, time: LogicalTime, prio: Priority) -> Self {
+        if !self.check_priority(tid, prio) {
+            return self;
+        }
+        match self.record.per_thread.get_mut(&tid) {
+            None => self.fail(format!("thread {} preempted before it was registered", tid)),
+            Some(history) => match history.prio_changes.last() {
+                Some((last, _)) if *last >= time => {
+                    let error = format!(
+                        "thread {} preempted at {} after a preemption at {}",
+                        tid, time, last
+                    );
+                    self.fail(error);
+                }
+                _ => {
+                    history.prio_changes.push((time, history.final_prio));
+                    history.final_prio = prio;
+                }
+            },
+        }
+        self
+    }
+
+    /// Make a registered thread run before or after its parent when it is cloned.
+    pub fn spawn_order(mut self, tid: DetTid, order: SpawnOrder) -> Self {
+        match self.record.per_thread.get_mut(&tid) {
+            None => self.fail(format!("spawn order set for unregistered thread {}", tid)),
+            Some(_) if order == SpawnOrder::Random => {
+                self.fail(format!("spawn order of thread {} must be fixed", tid))
+            }
+            Some(history) => history.spawn_order = Some(order),
+        }
+        self
+    }
+
+    /// Append an event to the global schedule.
+    pub fn event(mut self, event: SchedEvent) -> Self {
+        self.record.global.push(event);
+        self
+    }
+
+    /// Append events to the global schedule, in order.
+    pub fn events(mut self, events: impl IntoIterator<Item = SchedEvent>) -> Self {
+        self.record.global.extend(events);
+        self
+    }
+
+    /// Record where the schedule came from.
+    pub fn metadata(mut self, metadata: RecordMetadata) -> Self {
+        self.record.metadata = Some(metadata);
+        self
+    }
+
+    /// Finish the record, or report the first mistake made while building it.
+    pub fn build(self) -> Result<PreemptionRecord, String> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        self.record.validate()?;
+        Ok(self.record)
+    }
+
+    /// Like [`PreemptionRecordBuilder::build`], but [normalizing](PreemptionRecord::normalize)
+    /// the priorities of the finished record.
+    pub fn build_normalized(self) -> Result<PreemptionRecord, String> {
+        self.build().map(|record| record.normalize())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use pretty_assertions::assert_eq;
@@ -437,6 +1078,133 @@ mod tests {
         std::fs::remove_file(path).unwrap();
     }
 
+    #[test]
+    fn binary_format_roundtrip() {
+        let tid = DetTid::from_raw(3);
+        let mut pw = PreemptionWriter::new(None);
+        pw.register_thread(tid, 1000);
+        pw.set_spawn_order(tid, SpawnOrder::ChildFirst);
+        pw.insert_reprioritization(tid, LogicalTime::from_nanos(7), 1000, 7);
+        pw.insert_schedevent(SchedEvent::branches(tid, 5).with_time(LogicalTime::from_nanos(9)));
+        let pr = pw.snapshot();
+
+        let binary = pr.to_bytes(RecordFormat::Binary);
+        let json = pr.to_bytes(RecordFormat::Json);
+        assert!(binary.len() < json.len());
+        assert_eq!(PreemptionRecord::from_bytes(&binary).unwrap(), pr);
+        assert_eq!(PreemptionRecord::from_bytes(&json).unwrap(), pr);
+
+        let mut future = binary.clone();
+        future[BINARY_MAGIC.len()] = 2;
+        assert!(PreemptionRecord::from_bytes(&future).is_err());
+        assert!(PreemptionRecord::from_bytes(&binary[..10]).is_err());
+
+        assert_eq!(
+            RecordFormat::for_path(Path::new("run1.preempts.bin")),
+            RecordFormat::Binary
+        );
+        assert_eq!(
+            RecordFormat::for_path(Path::new("run1.preempts")),
+            RecordFormat::Json
+        );
+    }
+
+    #[test]
+    fn stream_format_keeps_prefix() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("run1.preempts.jsonl");
+        let tid = DetTid::from_raw(3);
+        let mut pw = PreemptionWriter::new(Some(path.clone()));
+        pw.register_thread(tid, 1000);
+        pw.insert_reprioritization(tid, LogicalTime::from_nanos(7), 1000, 7);
+        pw.set_current(tid, 8);
+        for i in 1..=3 {
+            pw.insert_schedevent(SchedEvent::branches(tid, i));
+        }
+        let pr = pw.snapshot();
+        assert_eq!(pr.global.len(), 3);
+        assert!(pw.inner.global.is_empty());
+        pw.flush().unwrap();
+
+        let reader = PreemptionReader::new(&path);
+        assert_eq!(reader.num_events(), 3);
+        assert_eq!(reader.event(0), pr.global[0]);
+        assert_eq!(reader.slice(1..3), pr.global[1..3]);
+        assert_eq!(reader.iter_events().collect::<Vec<_>>(), pr.global);
+        assert_eq!(reader.into_inner(), pr);
+        assert_eq!(read_trace(&path), pr.global);
+        assert_eq!(
+            PreemptionRecord::from_bytes(&pr.to_bytes(RecordFormat::Stream)).unwrap(),
+            pr
+        );
+
+        // Compressed in place, it is loaded whole.
+        let compressed = dir.path().join("compressed.preempts.jsonl");
+        std::fs::copy(&path, &compressed).unwrap();
+        crate::util::compress_in_place(&compressed).unwrap();
+        assert_eq!(PreemptionReader::new(&compressed).into_inner(), pr);
+        assert_eq!(read_trace(&compressed), pr.global);
+
+        // Cut the last event short, as a crash would.
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 10]).unwrap();
+        let prefix = PreemptionReader::new(&path).into_inner();
+        assert_eq!(prefix.per_thread, pr.per_thread);
+        assert_eq!(prefix.global, pr.global[..2]);
+    }
+
+    #[test]
+    fn json_and_binary_formats_read_lazily() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let tid = DetTid::from_raw(3);
+        let mut pw = PreemptionWriter::new(None);
+        pw.register_thread(tid, 1000);
+        pw.insert_reprioritization(tid, LogicalTime::from_nanos(7), 1000, 7);
+        for i in 1..=3 {
+            pw.insert_schedevent(SchedEvent::branches(tid, i));
+        }
+        let pr = pw.snapshot();
+        for (name, format) in [("json", RecordFormat::Json), ("bin", RecordFormat::Binary)] {
+            // Not named after their formats, which are told apart by their contents.
+            let path = dir.path().join(name);
+            pr.write_to_disk_as(&path, format).unwrap();
+            let reader = PreemptionReader::new(&path);
+            assert!(matches!(reader.events, Events::Mapped { .. }));
+            assert_eq!(reader.num_events(), 3);
+            assert_eq!(reader.slice(1..3), pr.global[1..3]);
+            assert_eq!(reader.preemptions().per_thread, pr.per_thread);
+            assert!(reader.preemptions().global.is_empty());
+            assert_eq!(reader.into_inner(), pr);
+        }
+    }
+
+    #[test]
+    fn sched_events_written_one_at_a_time() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let tid = DetTid::from_raw(3);
+        let events: Vec<SchedEvent> = (1..=3).map(|i| SchedEvent::branches(tid, i)).collect();
+        let pr = PreemptionRecord::from_sched_events(events.clone());
+        for name in ["events", "events.jsonl", "events.bin"] {
+            let path = dir.path().join(name);
+            write_sched_events(&path, events.iter().cloned()).unwrap();
+            assert_eq!(PreemptionReader::new(&path).into_inner(), pr);
+        }
+        assert_eq!(
+            std::fs::read(dir.path().join("events")).unwrap(),
+            pr.to_bytes(RecordFormat::Json)
+        );
+    }
+
+    #[test]
+    fn json_event_spans_skip_strings() {
+        let json = br#"{"a":{"global":"]"},"global":[{"b":[1,{"c":"}\""}]}, {}],"d":[]}"#;
+        let (array, spans) = json_event_spans(json).unwrap();
+        assert_eq!(&json[array], br#"[{"b":[1,{"c":"}\""}]}, {}]"#);
+        let events: Vec<&[u8]> = spans.into_iter().map(|span| &json[span]).collect();
+        assert_eq!(events, [&br#"{"b":[1,{"c":"}\""}]}"#[..], b"{}"]);
+        assert_eq!(json_event_spans(br#"{"global_not":[]}"#), None);
+    }
+
     #[test]
     fn round_trip_vec_representations() {
         let str = r#"{"per_thread":{"2":{"final_prio":1716,"prio_changes":[[946684799000013020,7301],[946684799000034020,9081],[946684799000041600,9238],[946684799000054790,865],
@@ -461,6 +1229,286 @@ mod tests {
         assert_eq!(pr, pr2);
     }
 
+    #[test]
+    fn remove_preemption_merges_timeslices() {
+        let tid = DetTid::from_raw(3);
+        let mut vecs = BTreeMap::new();
+        vecs.insert(
+            tid,
+            vec![
+                (LogicalTime::from_nanos(0), 10),
+                (LogicalTime::from_nanos(100), 20),
+                (LogicalTime::from_nanos(200), 30),
+            ],
+        );
+        let mut pr = PreemptionRecord::from_vecs(&vecs);
+        assert!(!pr.remove_preemption(tid, 2));
+        assert!(pr.remove_preemption(tid, 0));
+        assert_eq!(
+            pr.as_vecs()[&tid],
+            vec![
+                (LogicalTime::from_nanos(0), 10),
+                (LogicalTime::from_nanos(200), 30)
+            ]
+        );
+        assert!(pr.remove_preemption(tid, 0));
+        assert_eq!(pr.as_vecs()[&tid], vec![(LogicalTime::from_nanos(0), 10)]);
+    }
+
+    #[test]
+    fn retain_preemptions_between_times() {
+        let tid = DetTid::from_raw(3);
+        let mut vecs = BTreeMap::new();
+        vecs.insert(
+            tid,
+            vec![
+                (LogicalTime::from_nanos(0), 10),
+                (LogicalTime::from_nanos(100), 20),
+                (LogicalTime::from_nanos(200), 30),
+                (LogicalTime::from_nanos(300), 40),
+            ],
+        );
+        let mut pr = PreemptionRecord::from_vecs(&vecs);
+        pr.retain_preemptions_between(LogicalTime::from_nanos(150), LogicalTime::from_nanos(300));
+        assert_eq!(
+            pr.as_vecs()[&tid],
+            vec![
+                (LogicalTime::from_nanos(0), 20),
+                (LogicalTime::from_nanos(200), 30)
+            ]
+        );
+    }
+
+    #[test]
+    fn metadata_roundtrip() {
+        let metadata = RecordMetadata {
+            hermit_version: "0.1".to_owned(),
+            config_hash: "0123456789abcdef".to_owned(),
+            command: vec!["/bin/true".to_owned()],
+            seed: 7,
+            recorded_at: "2022-11-01T00:00:00+00:00".to_owned(),
+        };
+        let mut pw = PreemptionWriter::with_metadata(None, Some(metadata.clone()));
+        pw.register_thread(DetTid::from_raw(3), 1000);
+        let pr = pw.snapshot();
+        for format in [
+            RecordFormat::Json,
+            RecordFormat::Binary,
+            RecordFormat::Stream,
+        ] {
+            let pr2 = PreemptionRecord::from_bytes(&pr.to_bytes(format)).unwrap();
+            assert_eq!(pr2.metadata(), Some(&metadata));
+        }
+
+        let mut current = metadata.clone();
+        assert!(metadata.differences(&current).is_empty());
+        current.seed = 8;
+        current.recorded_at = "2022-11-02T00:00:00+00:00".to_owned();
+        assert_eq!(metadata.differences(&current).len(), 1);
+    }
+
+    #[test]
+    fn identities_roundtrip() {
+        let mut pw = PreemptionWriter::new(None);
+        pw.register_thread(DetTid::from_raw(3), 1000);
+        let identity = ThreadIdentity::root().child(2);
+        pw.insert_schedevent(SchedEvent::branches(DetTid::from_raw(3), 5));
+        pw.insert_schedevent(
+            SchedEvent::branches(DetTid::from_raw(3), 7).with_identity(identity.clone()),
+        );
+        let pr = pw.snapshot();
+        for format in [
+            RecordFormat::Json,
+            RecordFormat::Binary,
+            RecordFormat::Stream,
+        ] {
+            let global = PreemptionRecord::from_bytes(&pr.to_bytes(format))
+                .unwrap()
+                .into_global();
+            assert_eq!(global[0].identity, None);
+            assert_eq!(global[1].identity, Some(identity.clone()));
+        }
+    }
+
+    #[test]
+    fn older_schemas_migrate() {
+        let v1 = r#"{"per_thread":{"2":{"final_prio":1000,"prio_changes":[[100,7]]}}}"#;
+        let pr = PreemptionRecord::from_bytes(v1.as_bytes()).unwrap();
+        assert_eq!(pr.as_vecs()[&DetTid::from_raw(2)].len(), 2);
+        assert!(pr.to_string().starts_with(r#"{"version":2,"#));
+        let reread = PreemptionRecord::from_bytes(pr.to_string().as_bytes());
+        assert_eq!(reread, Ok(pr));
+
+        let future = r#"{"version":3,"per_thread":{},"global":[]}"#;
+        assert!(PreemptionRecord::from_bytes(future.as_bytes()).is_err());
+
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        let ev = SchedEvent::branches(DetTid::from_raw(2), 5);
+        let v1 = BinaryRecordV1 {
+            per_thread: Vec::new(),
+            global: vec![BinaryEventV2 {
+                dettid: ev.dettid,
+                op: ev.op,
+                count: ev.count,
+                start_rip: None,
+                end_rip: None,
+                end_time: None,
+            }],
+            arch: None,
+        };
+        bincode::serialize_into(&mut bytes, &v1).unwrap();
+        let pr = PreemptionRecord::from_bytes(&bytes).unwrap();
+        assert_eq!(pr.into_global(), vec![ev]);
+    }
+
+    #[test]
+    fn old_binary_versions_decode() {
+        // One branch event of thread 2, byte for byte as binary versions 1 and 2 wrote it.
+        let mut v1 = BINARY_MAGIC.to_vec();
+        v1.extend_from_slice(&1u32.to_le_bytes());
+        v1.extend_from_slice(&0u64.to_le_bytes()); // per_thread: []
+        v1.extend_from_slice(&1u64.to_le_bytes()); // global: one event
+        v1.extend_from_slice(&2i32.to_le_bytes()); // dettid
+        v1.extend_from_slice(&0u32.to_le_bytes()); // op: Branch
+        v1.extend_from_slice(&5u32.to_le_bytes()); // count
+        v1.extend_from_slice(&[0, 0, 0]); // start_rip, end_rip, end_time: None
+        v1.push(1); // arch: Some
+        v1.extend_from_slice(&6u64.to_le_bytes());
+        v1.extend_from_slice(b"x86_64");
+        let mut v2 = v1.clone();
+        v2[BINARY_MAGIC.len()..BINARY_MAGIC.len() + 4].copy_from_slice(&2u32.to_le_bytes());
+        v2.push(0); // metadata: None
+
+        for bytes in [v1, v2] {
+            let pr = PreemptionRecord::from_bytes(&bytes).unwrap();
+            assert_eq!(pr.arch.as_deref(), Some("x86_64"));
+            assert_eq!(
+                pr.into_global(),
+                vec![SchedEvent::branches(DetTid::from_raw(2), 5)]
+            );
+        }
+    }
+
+    #[test]
+    fn foreign_arch_rejected() {
+        let str = r#"{"per_thread":{},"global":[],"arch":"sparc64"}"#;
+        let pr: PreemptionRecord = serde_json::from_str(str).unwrap();
+        // Still well formed, and may be analyzed, just not replayed.
+        pr.validate().unwrap();
+        assert!(pr.check_arch().is_err());
+    }
+
+    #[test]
+    fn build_preemption_record() {
+        let t3 = DetTid::from_raw(3);
+        let t5 = DetTid::from_raw(5);
+        let record = PreemptionRecordBuilder::new()
+            .thread(t3, 1500)
+            .thread(t5, 1200)
+            .preempt(t5, LogicalTime::from_nanos(100), 1800)
+            .preempt(t5, LogicalTime::from_nanos(200), 1100)
+            .spawn_order(t5, SpawnOrder::ChildFirst)
+            .event(SchedEvent::branches(t3, 100))
+            .build()
+            .unwrap();
+        assert_eq!(
+            record.as_vecs()[&t3],
+            vec![(LogicalTime::from_nanos(0), 1500)]
+        );
+        assert_eq!(
+            record.as_vecs()[&t5],
+            vec![
+                (LogicalTime::from_nanos(0), 1200),
+                (LogicalTime::from_nanos(100), 1800),
+                (LogicalTime::from_nanos(200), 1100),
+            ]
+        );
+        assert_eq!(record.global, vec![SchedEvent::branches(t3, 100)]);
+
+        let normalized = PreemptionRecordBuilder::new()
+            .thread(t3, 1500)
+            .thread(t5, 1200)
+            .build_normalized()
+            .unwrap();
+        assert_eq!(
+            normalized.as_vecs()[&t3],
+            vec![(LogicalTime::from_nanos(0), 1001)]
+        );
+
+        let unregistered = PreemptionRecordBuilder::new().preempt(t3, LogicalTime::ZERO, 1500);
+        assert!(unregistered.build().is_err());
+        let backwards = PreemptionRecordBuilder::new()
+            .thread(t3, 1500)
+            .preempt(t3, LogicalTime::from_nanos(200), 1200)
+            .preempt(t3, LogicalTime::from_nanos(100), 1300);
+        assert!(backwards.build().is_err());
+        let twice = PreemptionRecordBuilder::new()
+            .thread(t3, 1500)
+            .thread(t3, 1500);
+        assert!(twice.build().is_err());
+        let invalid = PreemptionRecordBuilder::new().thread(t3, 0);
+        assert!(invalid.build().is_err());
+    }
+
+    #[test]
+    fn merge_fragments() {
+        let t3 = DetTid::from_raw(3);
+        let t5 = DetTid::from_raw(5);
+        let at = |mut ev: SchedEvent, nanos| {
+            ev.end_time = Some(LogicalTime::from_nanos(nanos));
+            ev
+        };
+        let first = PreemptionRecordBuilder::new()
+            .thread(t3, 1200)
+            .preempt(t3, LogicalTime::from_nanos(100), 1300)
+            .events(vec![
+                at(SchedEvent::branches(t3, 1), 10),
+                SchedEvent::branches(t3, 2),
+                at(SchedEvent::branches(t3, 3), 30),
+            ])
+            .build()
+            .unwrap();
+        let second = PreemptionRecordBuilder::new()
+            .thread(t3, 1300)
+            .thread(t5, 1500)
+            .preempt(t3, LogicalTime::from_nanos(200), 1400)
+            .events(vec![
+                at(SchedEvent::branches(t5, 4), 5),
+                at(SchedEvent::branches(t5, 5), 30),
+            ])
+            .build()
+            .unwrap();
+
+        let merged = PreemptionRecord::merge(vec![first.clone(), second.clone()]).unwrap();
+        let counts: Vec<u32> = merged.global.iter().map(|ev| ev.count).collect();
+        assert_eq!(counts, vec![4, 1, 2, 3, 5]);
+        assert_eq!(
+            merged.as_vecs()[&t3],
+            vec![
+                (LogicalTime::from_nanos(0), 1200),
+                (LogicalTime::from_nanos(100), 1300),
+                (LogicalTime::from_nanos(200), 1400),
+            ]
+        );
+
+        // The second fragment doesn't pick up where the first leaves off.
+        assert!(PreemptionRecord::merge(vec![second, first.clone()]).is_err());
+        // An event by a thread which is not registered.
+        let t7 = DetTid::from_raw(7);
+        let stray = PreemptionRecord::from_sched_events(vec![SchedEvent::branches(t7, 1)]);
+        assert!(PreemptionRecord::merge(vec![first, stray]).is_err());
+        // Two threads with the same identity.
+        let identity = ThreadIdentity::root().child(0);
+        let named = |tid| {
+            PreemptionRecord::from_sched_events(vec![
+                SchedEvent::branches(tid, 1).with_identity(identity.clone()),
+            ])
+        };
+        assert!(PreemptionRecord::merge(vec![named(t3), named(t3)]).is_ok());
+        assert!(PreemptionRecord::merge(vec![named(t3), named(t5)]).is_err());
+    }
+
     #[test]
     fn normalize_preemption_record() {
         let str = r#"{"per_thread":{
@@ -554,16 +1602,75 @@ pub struct PreemptionWriter {
     inner: PreemptionRecord,
     dest: Option<PathBuf>,
     flushed: bool,
+    /// Where each change is appended as it is made, when `dest` is in the streaming format.
+    /// The scheduling events are then not kept in `inner`.
+    stream: Option<BufWriter<File>>,
 }
 
 impl PreemptionWriter {
     /// A new, empty record of preemptions, with an optional location on disk that it will be
-    /// written to.  If no path is supplied, the results will accumulate in memory only.
+    /// written to.  If no path is supplied, the results will accumulate in memory only.  A path
+    /// ending in `.jsonl` is written to as the record grows (see [`RecordFormat::Stream`]).
     pub fn new(path: Option<PathBuf>) -> Self {
-        PreemptionWriter {
-            inner: Default::default(),
+        Self::with_metadata(path, None)
+    }
+
+    /// Like [`PreemptionWriter::new`], but recording where the record came from.
+    pub fn with_metadata(path: Option<PathBuf>, metadata: Option<RecordMetadata>) -> Self {
+        let mut writer = PreemptionWriter {
+            inner: PreemptionRecord {
+                arch: Some(std::env::consts::ARCH.to_string()),
+                metadata,
+                ..Default::default()
+            },
             dest: path,
             flushed: false,
+            stream: None,
+        };
+        if let Some(path) = &writer.dest {
+            if RecordFormat::for_path(path) == RecordFormat::Stream {
+                let file = File::create(path).unwrap_or_else(|e| {
+                    panic!(
+                        "Failed to create file for preemption record {:?}, error: {}",
+                        path, e
+                    )
+                });
+                writer.stream = Some(BufWriter::new(file));
+                writer.append(StreamEntry::Header {
+                    version: STREAM_VERSION,
+                    arch: writer.inner.arch.clone(),
+                    metadata: writer.inner.metadata.clone(),
+                });
+            }
+        }
+        writer
+    }
+
+    fn append(&mut self, entry: StreamEntry) {
+        if let Some(stream) = &mut self.stream {
+            if let Err(e) = entry.write_to(stream) {
+                panic!(
+                    "Failed to append to preemption record {:?}, error: {}",
+                    self.dest, e
+                );
+            }
+        }
+    }
+
+    /// A copy of everything recorded so far.
+    pub fn snapshot(&mut self) -> PreemptionRecord {
+        match &mut self.stream {
+            Some(stream) => {
+                // The events are only on disk.
+                stream.flush().unwrap();
+                let path = self.dest.as_ref().unwrap();
+                let file = File::open(path).unwrap_or_else(|e| {
+                    panic!("Error reading file {:?}:\n {}", path, e);
+                });
+                PreemptionRecord::from_stream(BufReader::new(file))
+                    .unwrap_or_else(|e| panic!("{}", e))
+            }
+            None => self.inner.clone(),
         }
     }
 
@@ -591,6 +1698,7 @@ impl PreemptionWriter {
                 ThreadHistory {
                     final_prio: prio,
                     prio_changes: Vec::new(),
+                    spawn_order: None,
                 },
             )
             .is_some()
@@ -600,6 +1708,20 @@ impl PreemptionWriter {
                 tid
             )
         }
+        self.append(StreamEntry::Register { tid, prio });
+    }
+
+    /// Record whether a (registered) thread ran before or after its parent at clone time.
+    pub fn set_spawn_order(&mut self, tid: DetTid, order: SpawnOrder) {
+        assert_ne!(order, SpawnOrder::Random);
+        match self.inner.per_thread.get_mut(&tid) {
+            Some(history) => history.spawn_order = Some(order),
+            None => panic!(
+                "PreemptionRecord: error, cannot set spawn order for unregistered thread: {}",
+                tid
+            ),
+        }
+        self.append(StreamEntry::SpawnOrder { tid, order });
     }
 
     /// Insert a new preemption point for the given thread.
@@ -627,12 +1749,22 @@ impl PreemptionWriter {
         }
         history.prio_changes.push((time, prior_prio));
         history.final_prio = next_prio;
+        self.append(StreamEntry::Reprioritize {
+            tid,
+            time,
+            prior_prio,
+            next_prio,
+        });
     }
 
     /// Add a SchedEvent to the global log of thread behavior.
     pub fn insert_schedevent(&mut self, ev: SchedEvent) {
         if ev.count > 0 {
-            self.inner.global.push(ev)
+            if self.stream.is_some() {
+                self.append(StreamEntry::Event(ev));
+            } else {
+                self.inner.global.push(ev)
+            }
             // TODO, aggregation: possibly check if the last event was the SAME, and combine them,
             // increasing the count.
         } else {
@@ -649,6 +1781,10 @@ impl PreemptionWriter {
             )
         });
         history.final_prio = new_prio;
+        self.append(StreamEntry::SetCurrent {
+            tid,
+            prio: new_prio,
+        });
     }
 
     /// Abort writing to disk and instead gather the output thusfar into a string.
@@ -665,6 +1801,15 @@ impl PreemptionWriter {
     }
 
     fn write_to_disk(&mut self) -> Result<(), String> {
+        if let Some(stream) = &mut self.stream {
+            // Everything is already written, but may still be buffered.
+            return stream.flush().map_err(|e| {
+                format!(
+                    "Failed to write preemption record to file {:?}, error: {}",
+                    self.dest, e
+                )
+            });
+        }
         if let Some(path) = &self.dest {
             self.inner.write_to_disk(path)
         } else {
@@ -685,28 +1830,534 @@ impl Drop for PreemptionWriter {
     }
 }
 
+/// `PreemptionRecord` without the fields skipped when serializing, which bincode cannot decode.
+#[derive(Serialize, Deserialize)]
+struct BinaryRecord {
+    per_thread: Vec<BinaryThread>,
+    global: Vec<BinaryEvent>,
+    arch: Option<String>,
+    metadata: Option<RecordMetadata>,
+}
+
+/// A `BinaryRecord` of version 2.
+#[derive(Serialize, Deserialize)]
+struct BinaryRecordV2 {
+    per_thread: Vec<BinaryThread>,
+    global: Vec<BinaryEventV2>,
+    arch: Option<String>,
+    metadata: Option<RecordMetadata>,
+}
+
+/// A `BinaryRecord` of version 1.
+#[derive(Serialize, Deserialize)]
+struct BinaryRecordV1 {
+    per_thread: Vec<BinaryThread>,
+    global: Vec<BinaryEventV2>,
+    arch: Option<String>,
+}
+
+impl From<BinaryRecordV2> for BinaryRecord {
+    fn from(v2: BinaryRecordV2) -> Self {
+        BinaryRecord {
+            per_thread: v2.per_thread,
+            global: v2.global.into_iter().map(BinaryEvent::from).collect(),
+            arch: v2.arch,
+            metadata: v2.metadata,
+        }
+    }
+}
+
+impl From<BinaryRecordV1> for BinaryRecord {
+    fn from(v1: BinaryRecordV1) -> Self {
+        BinaryRecord {
+            per_thread: v1.per_thread,
+            global: v1.global.into_iter().map(BinaryEvent::from).collect(),
+            arch: v1.arch,
+            metadata: None,
+        }
+    }
+}
+
+/// `SchedEvent` without the fields skipped when serializing.
+#[derive(Serialize, Deserialize)]
+struct BinaryEvent {
+    dettid: DetTid,
+    op: Op,
+    count: u32,
+    start_rip: Option<InstructionPointer>,
+    end_rip: Option<InstructionPointer>,
+    end_time: Option<LogicalTime>,
+    identity: Option<ThreadIdentity>,
+}
+
+/// A `BinaryEvent` of versions 1 and 2.
+#[derive(Serialize, Deserialize)]
+struct BinaryEventV2 {
+    dettid: DetTid,
+    op: Op,
+    count: u32,
+    start_rip: Option<InstructionPointer>,
+    end_rip: Option<InstructionPointer>,
+    end_time: Option<LogicalTime>,
+}
+
+impl From<BinaryEventV2> for BinaryEvent {
+    fn from(v2: BinaryEventV2) -> Self {
+        BinaryEvent {
+            dettid: v2.dettid,
+            op: v2.op,
+            count: v2.count,
+            start_rip: v2.start_rip,
+            end_rip: v2.end_rip,
+            end_time: v2.end_time,
+            identity: None,
+        }
+    }
+}
+
+impl From<SchedEvent> for BinaryEvent {
+    fn from(ev: SchedEvent) -> Self {
+        BinaryEvent {
+            dettid: ev.dettid,
+            op: ev.op,
+            count: ev.count,
+            start_rip: ev.start_rip,
+            end_rip: ev.end_rip,
+            end_time: ev.end_time,
+            identity: ev.identity,
+        }
+    }
+}
+
+impl From<BinaryEvent> for SchedEvent {
+    fn from(be: BinaryEvent) -> Self {
+        SchedEvent {
+            dettid: be.dettid,
+            op: be.op,
+            count: be.count,
+            start_rip: be.start_rip,
+            end_rip: be.end_rip,
+            end_time: be.end_time,
+            identity: be.identity,
+        }
+    }
+}
+
+/// One thread of a `BinaryRecord`.
+#[derive(Serialize, Deserialize)]
+struct BinaryThread {
+    tid: DetTid,
+    final_prio: Priority,
+    prio_changes: Vec<(LogicalTime, Priority)>,
+    spawn_order: Option<SpawnOrder>,
+}
+
+impl From<PreemptionRecord> for BinaryRecord {
+    fn from(pr: PreemptionRecord) -> Self {
+        BinaryRecord {
+            per_thread: pr
+                .per_thread
+                .into_iter()
+                .map(|(tid, th)| BinaryThread {
+                    tid,
+                    final_prio: th.final_prio,
+                    prio_changes: th.prio_changes,
+                    spawn_order: th.spawn_order,
+                })
+                .collect(),
+            global: pr.global.into_iter().map(BinaryEvent::from).collect(),
+            arch: pr.arch,
+            metadata: pr.metadata,
+        }
+    }
+}
+
+impl From<BinaryRecord> for PreemptionRecord {
+    fn from(br: BinaryRecord) -> Self {
+        PreemptionRecord {
+            per_thread: br
+                .per_thread
+                .into_iter()
+                .map(|bt| {
+                    let th = ThreadHistory {
+                        final_prio: bt.final_prio,
+                        prio_changes: bt.prio_changes,
+                        spawn_order: bt.spawn_order,
+                    };
+                    (bt.tid, th)
+                })
+                .collect(),
+            global: br.global.into_iter().map(SchedEvent::from).collect(),
+            arch: br.arch,
+            metadata: br.metadata,
+        }
+    }
+}
+
+/// One line of a streamed `PreemptionRecord`.  Each is a call made on the `PreemptionWriter`.
+#[derive(Debug, Serialize, Deserialize)]
+enum StreamEntry {
+    /// Always the first line.
+    Header {
+        version: u32,
+        arch: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        metadata: Option<RecordMetadata>,
+    },
+    Register {
+        tid: DetTid,
+        prio: Priority,
+    },
+    SpawnOrder {
+        tid: DetTid,
+        order: SpawnOrder,
+    },
+    Reprioritize {
+        tid: DetTid,
+        time: LogicalTime,
+        prior_prio: Priority,
+        next_prio: Priority,
+    },
+    SetCurrent {
+        tid: DetTid,
+        prio: Priority,
+    },
+    Event(SchedEvent),
+}
+
+impl StreamEntry {
+    fn write_to<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+        serde_json::to_writer(&mut w, self)?;
+        w.write_all(b"\n")
+    }
+}
+
+/// Parses the lines of a streamed record one at a time.
+struct StreamEntries<R> {
+    lines: std::io::Lines<R>,
+}
+
+impl<R: BufRead> StreamEntries<R> {
+    fn new(reader: R) -> Self {
+        StreamEntries {
+            lines: reader.lines(),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for StreamEntries<R> {
+    type Item = Result<StreamEntry, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = match self.lines.next()? {
+            Ok(line) => line,
+            Err(e) => return Some(Err(format!("Error reading streamed record: {}", e))),
+        };
+        match serde_json::from_str(&line) {
+            Ok(entry) => Some(Ok(entry)),
+            Err(e) if e.is_eof() && self.lines.next().is_none() => {
+                warn!("Ignoring the truncated last line of a streamed record");
+                None
+            }
+            Err(e) => Some(Err(format!(
+                "Error parsing streamed PreemptionRecord line: {}\n{}",
+                e, line
+            ))),
+        }
+    }
+}
+
+/// Reads the scheduling events of a streamed record (`.jsonl`) one at a time, so that the whole
+/// record is never in memory.
+pub struct StreamedEvents {
+    entries: StreamEntries<BufReader<File>>,
+}
+
+impl StreamedEvents {
+    /// Open a streamed record.
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        Ok(StreamedEvents {
+            entries: StreamEntries::new(BufReader::new(File::open(path)?)),
+        })
+    }
+}
+
+impl Iterator for StreamedEvents {
+    type Item = SchedEvent;
+
+    fn next(&mut self) -> Option<SchedEvent> {
+        loop {
+            match self.entries.next()? {
+                Ok(StreamEntry::Event(ev)) => return Some(ev),
+                Ok(_) => {}
+                Err(e) => panic!("{}", e),
+            }
+        }
+    }
+}
+
+/// How a streamed event line starts.
+const STREAM_EVENT_START: &[u8] = b"{\"Event\":";
+
 /// A reader for a stream of preemption events.
 #[derive(Debug)]
 pub struct PreemptionReader {
+    /// The record, with its scheduling events only if they are `Events::Loaded`.
     inner: PreemptionRecord,
+    events: Events,
+}
+
+/// Where the scheduling events of a `PreemptionReader` are read from.
+#[derive(Debug)]
+enum Events {
+    /// The events are in the `global` of the record, as it is compressed, or in an older binary
+    /// format, and can only be read whole.
+    Loaded,
+    /// The events are parsed on demand from the record, mapped into memory.  Each span holds
+    /// one event, encoded in `format`: a line of a streamed record, an element of the `global`
+    /// array of a JSON one, or the bincode of a binary one.
+    Mapped {
+        map: Mmap,
+        format: RecordFormat,
+        spans: Vec<Range<usize>>,
+    },
+}
+
+/// Records read whole which are at least this large are warned about.
+const WHOLE_READ_WARN_BYTES: u64 = 64 << 20;
+
+/// A file mapped read-only into memory.  The file must not be truncated while it is mapped.
+struct Mmap {
+    ptr: *const u8,
+    len: usize,
+}
+
+// Safe because the mapping is read-only, and lives as long as the `Mmap`.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    fn open(path: &Path) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            // An empty mapping is an error, so there is nothing to map.
+            return Ok(Mmap {
+                ptr: std::ptr::NonNull::dangling().as_ptr(),
+                len,
+            });
+        }
+        // Safe because the file is open, and the mapping is checked before it is used.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Mmap {
+            ptr: ptr as *const u8,
+            len,
+        })
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        // Safe because the mapping is `len` bytes long, and unmapped only on drop.
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            // Safe because nothing borrows the mapping past the life of the `Mmap`.
+            unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+        }
+    }
+}
+
+impl std::fmt::Debug for Mmap {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Mmap({} bytes)", self.len)
+    }
+}
+
+/// The record of a streamed record, without its scheduling events, and the span of the line of
+/// each event.
+fn stream_event_spans(bytes: &[u8]) -> Result<(PreemptionRecord, Vec<Range<usize>>), String> {
+    let mut spans = Vec::new();
+    let mut others = Vec::new();
+    let mut start = 0;
+    for line in bytes.split_inclusive(|b| *b == b'\n') {
+        if line.starts_with(STREAM_EVENT_START) {
+            spans.push(start..start + line.len());
+        } else {
+            others.extend_from_slice(line);
+        }
+        start += line.len();
+    }
+    // A final line that was cut short, as by a crash of the writer, is ignored.
+    if let Some(last) = spans.last() {
+        let last = &bytes[last.clone()];
+        if !last.ends_with(b"\n") && serde_json::from_slice::<StreamEntry>(last).is_err() {
+            warn!("Ignoring the truncated last line of a streamed record");
+            spans.pop();
+        }
+    }
+    Ok((PreemptionRecord::from_stream(others.as_slice())?, spans))
+}
+
+/// The record of a binary record, of the current version, without its scheduling events, and
+/// the span of the bincode of each event.  The fields of a `BinaryRecord` start at `offset`.
+fn binary_event_spans(
+    bytes: &[u8],
+    offset: usize,
+) -> Result<(PreemptionRecord, Vec<Range<usize>>), String> {
+    let error = |e: bincode::Error| format!("Error parsing binary PreemptionRecord: {}", e);
+    let mut rest = &bytes[offset..];
+    let per_thread: Vec<BinaryThread> = bincode::deserialize_from(&mut rest).map_err(error)?;
+    let len: u64 = bincode::deserialize_from(&mut rest).map_err(error)?;
+    let mut spans = Vec::new();
+    for _ in 0..len {
+        // Each event is only skipped over, as its size depends on its contents.
+        let start = bytes.len() - rest.len();
+        bincode::deserialize_from::<_, BinaryEvent>(&mut rest).map_err(error)?;
+        spans.push(start..bytes.len() - rest.len());
+    }
+    let record = BinaryRecord {
+        per_thread,
+        global: Vec::new(),
+        arch: bincode::deserialize_from(&mut rest).map_err(error)?,
+        metadata: bincode::deserialize_from(&mut rest).map_err(error)?,
+    };
+    Ok((PreemptionRecord::from(record), spans))
+}
+
+/// The span of the `"global"` array of a record in JSON, and the span of each event in it,
+/// found without parsing them.  None if the record has no such array, or is not JSON.
+fn json_event_spans(bytes: &[u8]) -> Option<(Range<usize>, Vec<Range<usize>>)> {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    // The last string closed in the top-level object, and the key it is the value of.
+    let mut string_start = 0;
+    let mut last_string: &[u8] = &[];
+    let mut key: &[u8] = &[];
+    let mut array_start = None;
+    let mut event_start = 0;
+    let mut spans = Vec::new();
+    for (i, b) in bytes.iter().enumerate() {
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => {
+                    in_string = false;
+                    last_string = &bytes[string_start..i];
+                }
+                _ => {}
+            }
+            continue;
+        }
+        match b {
+            b'"' => {
+                in_string = true;
+                string_start = i + 1;
+            }
+            b':' if depth == 1 => key = last_string,
+            b'{' | b'[' => {
+                depth += 1;
+                match array_start {
+                    None if depth == 2 && *b == b'[' && key == b"global" => array_start = Some(i),
+                    Some(_) if depth == 3 => event_start = i,
+                    _ => {}
+                }
+            }
+            b'}' | b']' => {
+                depth -= 1;
+                match array_start {
+                    Some(start) if depth == 1 => return Some((start..i + 1, spans)),
+                    Some(_) if depth == 2 => spans.push(event_start..i + 1),
+                    _ if depth == 0 => return None,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    None
 }
 
 /// Read a full trace from disk.  Panic if it doesn't load.
 pub fn read_trace(path: &Path) -> Vec<SchedEvent> {
+    if RecordFormat::for_path(path) == RecordFormat::Stream && !is_zstd_file(path) {
+        return StreamedEvents::open(path)
+            .unwrap_or_else(|e| panic!("Error reading file {:?}:\n {}", &path, e))
+            .collect();
+    }
     let pr = read_preemption_record(path);
     pr.global
 }
 
+/// Write a schedule of `events` alone, as `PreemptionRecord::from_sched_events` would, but one
+/// event at a time, so that they need not all be in memory.  The format is given by
+/// [`RecordFormat::for_path`], though a binary record is only written once it is complete.
+pub fn write_sched_events(
+    path: &Path,
+    events: impl IntoIterator<Item = SchedEvent>,
+) -> Result<(), String> {
+    let format = RecordFormat::for_path(path);
+    if format == RecordFormat::Binary {
+        let pr = PreemptionRecord::from_sched_events(events.into_iter().collect());
+        return pr.write_to_disk_as(path, format);
+    }
+    let write = || -> std::io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        if format == RecordFormat::Stream {
+            let header = StreamEntry::Header {
+                version: STREAM_VERSION,
+                arch: None,
+                metadata: None,
+            };
+            header.write_to(&mut w)?;
+            for ev in events {
+                StreamEntry::Event(ev).write_to(&mut w)?;
+            }
+        } else {
+            write!(
+                w,
+                "{{\"version\":{},\"per_thread\":{{}},\"global\":[",
+                SCHEMA_VERSION
+            )?;
+            for (i, ev) in events.into_iter().enumerate() {
+                if i > 0 {
+                    w.write_all(b",")?;
+                }
+                serde_json::to_writer(&mut w, &ev)?;
+            }
+            w.write_all(b"]}\n")?;
+        }
+        w.flush()
+    };
+    write().map_err(|e| {
+        format!(
+            "Failed to write preemption record to file {:?}, error: {}",
+            path, e
+        )
+    })
+}
+
 // TODO: we should implement streaming and not read this all at once.
 fn read_preemption_record(path: &Path) -> PreemptionRecord {
-    let string = std::fs::read_to_string(path)
-        .unwrap_or_else(|e| panic!("Error reading file {:?}:\n {}", &path, e));
-    let pr: PreemptionRecord = serde_json::from_str(&string).unwrap_or_else(|e| {
-        panic!(
-            "Error parsing PreemptionRecord from JSON: {}\nJSON contents:\n{}",
-            e, string
-        )
-    });
+    let bytes =
+        std::fs::read(path).unwrap_or_else(|e| panic!("Error reading file {:?}:\n {}", &path, e));
+    let pr = PreemptionRecord::from_bytes(&bytes).unwrap_or_else(|e| panic!("{}", e));
     if let Err(e) = pr.validate() {
         panic!(
             "Invalid PreemptionRecord when loading from path {}. Error:\n {}",
@@ -717,17 +2368,138 @@ fn read_preemption_record(path: &Path) -> PreemptionRecord {
     pr
 }
 
-// TODO: eventually this should do streaming IO, and abstract it behind this interface.
 impl PreemptionReader {
     /// Access the stored `PreemptionRecord`, and eagerly or lazily load its data from disk.
+    ///
+    /// A record is mapped into memory, and only its threads are parsed up front.  Its scheduling
+    /// events are only found, and are parsed as they are asked for, so a huge trace can be
+    /// walked, or sliced, without ever holding all of it.  That holds for each format, whatever
+    /// the name of the file, but not for compressed records, nor for binary ones older than
+    /// version 3, which are loaded whole.
     pub fn new(path: &Path) -> Self {
-        let pr = read_preemption_record(path);
-        PreemptionReader { inner: pr }
+        PreemptionReader::map(path)
+            .unwrap_or_else(|e| panic!("Error reading file {:?}:\n {}", &path, e))
+            .unwrap_or_else(|| PreemptionReader::load(path))
+    }
+
+    /// Read the record whole, warning if it is large.
+    fn load(path: &Path) -> Self {
+        let len = std::fs::metadata(path).map_or(0, |m| m.len());
+        if len >= WHOLE_READ_WARN_BYTES {
+            warn!(
+                "Reading all {} bytes of {} into memory, as its events cannot be read one at a \
+                time: it is compressed, or in an older binary format",
+                len,
+                path.display()
+            );
+        }
+        PreemptionReader {
+            inner: read_preemption_record(path),
+            events: Events::Loaded,
+        }
+    }
+
+    /// Map the record into memory, if its format can be read lazily.
+    fn map(path: &Path) -> Result<Option<Self>, String> {
+        let map = Mmap::open(path).map_err(|e| e.to_string())?;
+        let bytes = map.as_bytes();
+        let (inner, format, spans) = if bytes.starts_with(ZSTD_MAGIC) {
+            return Ok(None);
+        } else if bytes.starts_with(STREAM_START) {
+            let (inner, spans) = stream_event_spans(bytes)?;
+            (inner, RecordFormat::Stream, spans)
+        } else if let Some(rest) = bytes.strip_prefix(BINARY_MAGIC) {
+            if rest.get(..4) != Some(&BINARY_VERSION.to_le_bytes()[..]) {
+                return Ok(None);
+            }
+            let (inner, spans) = binary_event_spans(bytes, BINARY_MAGIC.len() + 4)?;
+            (inner, RecordFormat::Binary, spans)
+        } else {
+            match json_event_spans(bytes) {
+                // Let the record fail to parse as a whole, to say why.
+                None => return Ok(None),
+                Some((array, spans)) => {
+                    // The rest of the record is parsed with an empty schedule in its place.
+                    let mut others = bytes[..array.start].to_vec();
+                    others.extend_from_slice(b"[]");
+                    others.extend_from_slice(&bytes[array.end..]);
+                    let inner = PreemptionRecord::from_bytes(&others)?;
+                    (inner, RecordFormat::Json, spans)
+                }
+            }
+        };
+        inner.validate().map_err(|e| {
+            format!(
+                "Invalid PreemptionRecord when loading from path {}. Error:\n {}",
+                path.display(),
+                e
+            )
+        })?;
+        Ok(Some(PreemptionReader {
+            inner,
+            events: Events::Mapped { map, format, spans },
+        }))
     }
 
     /// Gets the inner `PreemptionRecord`.
     pub fn into_inner(self) -> PreemptionRecord {
-        self.inner
+        match self.events {
+            Events::Loaded => self.inner,
+            Events::Mapped { .. } => self.load_all(),
+        }
+    }
+
+    /// The number of scheduling events in the record.
+    pub fn num_events(&self) -> usize {
+        match &self.events {
+            Events::Loaded => self.inner.global.len(),
+            Events::Mapped { spans, .. } => spans.len(),
+        }
+    }
+
+    /// The scheduling event at `index`.  Panics if it is out of bounds, or does not parse.
+    pub fn event(&self, index: usize) -> SchedEvent {
+        match &self.events {
+            Events::Loaded => self.inner.global[index].clone(),
+            Events::Mapped { map, format, spans } => {
+                let bytes = &map.as_bytes()[spans[index].clone()];
+                let parsed = match format {
+                    RecordFormat::Stream => match serde_json::from_slice(bytes) {
+                        Ok(StreamEntry::Event(ev)) => Ok(ev),
+                        Ok(entry) => panic!("Expected a streamed event, found {:?}", entry),
+                        Err(e) => Err(e.to_string()),
+                    },
+                    RecordFormat::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+                    RecordFormat::Binary => bincode::deserialize::<BinaryEvent>(bytes)
+                        .map(SchedEvent::from)
+                        .map_err(|e| e.to_string()),
+                };
+                parsed.unwrap_or_else(|e| {
+                    panic!(
+                        "Error parsing event {} of a PreemptionRecord: {}\n{}",
+                        index,
+                        e,
+                        String::from_utf8_lossy(bytes)
+                    )
+                })
+            }
+        }
+    }
+
+    /// Iterate over the scheduling events of the record, in order, parsing each one only as it
+    /// is reached.
+    pub fn iter_events(&self) -> impl Iterator<Item = SchedEvent> + '_ {
+        (0..self.num_events()).map(move |index| self.event(index))
+    }
+
+    /// Like `iter_events`, but taking the reader along, e.g. to replay the events from.
+    pub fn into_events(self) -> impl Iterator<Item = SchedEvent> + Send {
+        (0..self.num_events()).map(move |index| self.event(index))
+    }
+
+    /// The scheduling events in `range`, without parsing any of the others.
+    pub fn slice(&self, range: Range<usize>) -> Vec<SchedEvent> {
+        range.map(|index| self.event(index)).collect()
     }
 
     /// Extract a copy of all the entries for a particular thread.  Note that this returns None if
@@ -742,15 +2514,32 @@ impl PreemptionReader {
         self.inner.per_thread.get(tid).map(|x| x.initial_priority())
     }
 
+    /// Return the recorded order in which the thread started relative to its parent, if any.
+    pub fn thread_spawn_order(&self, tid: &DetTid) -> Option<SpawnOrder> {
+        self.inner.per_thread.get(tid).and_then(|x| x.spawn_order)
+    }
+
     /// Return all the threads in this record.
     pub fn all_threads(&self) -> Vec<DetTid> {
         // TODO(T110956298): have this return an iterator.
         self.inner.per_thread.keys().copied().collect()
     }
 
+    /// A copy of the record without its scheduling events, which are not read.  That is all
+    /// that replaying, or minimizing, the preemptions needs.
+    pub fn preemptions(&self) -> PreemptionRecord {
+        let mut pr = self.inner.clone();
+        pr.preemptions_only();
+        pr
+    }
+
     /// Load the full record of preemptions into memory.
     pub fn load_all(&self) -> PreemptionRecord {
-        self.inner.clone()
+        let mut pr = self.inner.clone();
+        if let Events::Mapped { .. } = self.events {
+            pr.global = self.iter_events().collect();
+        }
+        pr
     }
 
     /// Size in number of preemptions, across all threads.
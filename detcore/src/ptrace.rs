@@ -0,0 +1,339 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Emulating ptrace between guests.
+//!
+//! Every guest thread is already traced by hermit, so one guest cannot really ptrace another.
+//! Instead, under sequentialization, the scheduler keeps track of who traces whom, and of the
+//! stops of each tracee.  A tracee "stops" by parking in detcore, having published its
+//! registers, until its tracer resumes it; the tracer sees the stop through its waits, in the
+//! order the stops happened in the schedule.
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::types::DetPid;
+use crate::types::DetTid;
+
+/// The `PTRACE_O_*` options that are emulated: `TRACESYSGOOD`, `TRACEEXEC` and `EXITKILL`.
+pub const SUPPORTED_OPTIONS: u32 = 0x1 | 0x10 | 0x10_0000;
+
+/// `PTRACE_O_TRACESYSGOOD`: mark syscall stops with `SIGTRAP | 0x80`.
+pub const OPTION_TRACESYSGOOD: u32 = 0x1;
+
+/// `PTRACE_O_TRACEEXEC`: report a `PTRACE_EVENT_EXEC` stop after an exec.
+pub const OPTION_TRACEEXEC: u32 = 0x10;
+
+/// `PTRACE_O_EXITKILL`: kill the tracee when its tracer exits.
+pub const OPTION_EXITKILL: u32 = 0x10_0000;
+
+/// Why a thread could not be attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AttachError {
+    /// The thread does not exist (ESRCH).
+    NoSuchThread,
+    /// The thread is already traced, or belongs to the tracer itself (EPERM).
+    NotPermitted,
+}
+
+/// How a tracer resumed a stopped tracee.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Resume {
+    /// `PTRACE_CONT` or `PTRACE_SYSCALL`: continue, delivering the signal (if nonzero), and
+    /// stopping again at the next syscall entry or exit if asked to.
+    Continue {
+        /// The signal to deliver, or zero.
+        signal: i32,
+        /// Whether to stop at the next syscall entry or exit.
+        syscall_stops: bool,
+    },
+    /// `PTRACE_DETACH`: continue untraced, delivering the signal (if nonzero).
+    Detach {
+        /// The signal to deliver, or zero.
+        signal: i32,
+    },
+    /// `PTRACE_KILL`, or the tracer exited with `PTRACE_O_EXITKILL` set.
+    Kill,
+}
+
+/// A tracee's current stop.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stop {
+    /// The status a wait reports for the stop.
+    pub status: i32,
+    /// The signal which caused it, reported by `PTRACE_GETSIGINFO`.
+    pub signal: i32,
+    /// The tracee's registers, in the layout of `struct user_regs_struct`.
+    pub regs: Vec<u64>,
+    /// Whether a wait has reported the stop yet.
+    pub reported: bool,
+    /// The position of this stop among all stops, which orders the reports to the tracer.
+    seq: u64,
+    /// How the tracer resumed the tracee, if it has.
+    resume: Option<Resume>,
+}
+
+#[derive(Debug, Clone)]
+struct Tracee {
+    /// The tracing process.  Any of its threads may make requests, and wait.
+    tracer: DetPid,
+    /// The `PTRACE_O_*` options in force.
+    options: u32,
+    stop: Option<Stop>,
+}
+
+/// Every tracing relationship between guests.
+#[derive(Debug, Default)]
+pub struct PtraceState {
+    tracees: BTreeMap<DetTid, Tracee>,
+    next_seq: u64,
+}
+
+impl PtraceState {
+    /// Start tracing a thread.  Fails if it is already traced.
+    pub fn attach(&mut self, tracee: DetTid, tracer: DetPid, options: u32) -> bool {
+        if self.tracees.contains_key(&tracee) {
+            return false;
+        }
+        self.tracees.insert(
+            tracee,
+            Tracee {
+                tracer,
+                options,
+                stop: None,
+            },
+        );
+        true
+    }
+
+    /// The process tracing a thread, if any, and the options it set.
+    pub fn tracer_of(&self, tracee: DetTid) -> Option<(DetPid, u32)> {
+        self.tracees.get(&tracee).map(|t| (t.tracer, t.options))
+    }
+
+    /// A tracee enters a stop.  Returns its tracer, to notify, or `None` if it is not traced
+    /// (any more).
+    pub fn stop(
+        &mut self,
+        tracee: DetTid,
+        status: i32,
+        signal: i32,
+        regs: Vec<u64>,
+    ) -> Option<DetPid> {
+        let seq = self.next_seq;
+        let t = self.tracees.get_mut(&tracee)?;
+        t.stop = Some(Stop {
+            status,
+            signal,
+            regs,
+            reported: false,
+            seq,
+            resume: None,
+        });
+        self.next_seq += 1;
+        Some(t.tracer)
+    }
+
+    /// A stopped tracee checks whether it has been resumed.  A tracee whose tracer has gone
+    /// away resumes as if detached.
+    pub fn poll_resume(&mut self, tracee: DetTid) -> Option<Resume> {
+        let resume = match self.tracees.get_mut(&tracee) {
+            None => return Some(Resume::Detach { signal: 0 }),
+            Some(t) => t.stop.as_ref()?.resume?,
+        };
+        match resume {
+            Resume::Continue { .. } => {
+                self.tracees.get_mut(&tracee).unwrap().stop = None;
+            }
+            Resume::Detach { .. } | Resume::Kill => {
+                self.tracees.remove(&tracee);
+            }
+        }
+        Some(resume)
+    }
+
+    /// The current stop of a tracee, provided it is traced by `tracer` and has not been
+    /// resumed yet.  Tracer requests fail with ESRCH otherwise.
+    pub fn stopped(&self, tracee: DetTid, tracer: DetPid) -> Option<&Stop> {
+        let t = self.tracees.get(&tracee).filter(|t| t.tracer == tracer)?;
+        t.stop.as_ref().filter(|stop| stop.resume.is_none())
+    }
+
+    /// Set the options of a stopped tracee.
+    pub fn set_options(&mut self, tracee: DetTid, tracer: DetPid, options: u32) -> bool {
+        if self.stopped(tracee, tracer).is_none() {
+            return false;
+        }
+        self.tracees.get_mut(&tracee).unwrap().options = options;
+        true
+    }
+
+    /// Resume a stopped tracee.
+    pub fn resume(&mut self, tracee: DetTid, tracer: DetPid, resume: Resume) -> bool {
+        if self.stopped(tracee, tracer).is_none() {
+            return false;
+        }
+        let stop = self
+            .tracees
+            .get_mut(&tracee)
+            .unwrap()
+            .stop
+            .as_mut()
+            .unwrap();
+        stop.resume = Some(resume);
+        true
+    }
+
+    /// The unreported stops of a process's tracees, in the order they stopped, with all of its
+    /// tracees.
+    pub fn stops_for(&self, tracer: DetPid) -> (Vec<(DetTid, i32)>, Vec<DetTid>) {
+        let tracees: Vec<_> = self
+            .tracees
+            .iter()
+            .filter(|(_, t)| t.tracer == tracer)
+            .collect();
+        let mut stops: Vec<_> = tracees
+            .iter()
+            .filter_map(|(tid, t)| match &t.stop {
+                Some(stop) if !stop.reported && stop.resume.is_none() => {
+                    Some((stop.seq, **tid, stop.status))
+                }
+                _ => None,
+            })
+            .collect();
+        stops.sort_unstable();
+        (
+            stops
+                .into_iter()
+                .map(|(_, tid, status)| (tid, status))
+                .collect(),
+            tracees.into_iter().map(|(tid, _)| *tid).collect(),
+        )
+    }
+
+    /// A wait has reported a tracee's stop.
+    pub fn stop_reported(&mut self, tracee: DetTid) {
+        if let Some(stop) = self.tracees.get_mut(&tracee).and_then(|t| t.stop.as_mut()) {
+            stop.reported = true;
+        }
+    }
+
+    /// A thread has exited: it is traced no longer.
+    pub fn thread_exited(&mut self, dettid: DetTid) {
+        self.tracees.remove(&dettid);
+    }
+
+    /// A tracing process has exited, detaching its tracees.  Those with `PTRACE_O_EXITKILL`
+    /// set are killed instead: stopped ones resume only to die, and the running ones are
+    /// returned, for the caller to kill.
+    pub fn tracer_exited(&mut self, tracer: DetPid) -> Vec<DetTid> {
+        let mut to_kill = Vec::new();
+        self.tracees.retain(|tid, t| {
+            if t.tracer != tracer {
+                return true;
+            }
+            if t.options & OPTION_EXITKILL == 0 {
+                return false;
+            }
+            match &mut t.stop {
+                Some(stop) => {
+                    stop.resume = Some(Resume::Kill);
+                    true
+                }
+                None => {
+                    to_kill.push(*tid);
+                    false
+                }
+            }
+        });
+        to_kill
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tid(raw: i32) -> DetTid {
+        DetTid::from_raw(raw)
+    }
+
+    #[test]
+    fn stop_and_resume() {
+        let mut state = PtraceState::default();
+        assert!(state.attach(tid(11), tid(10), 0));
+        assert!(!state.attach(tid(11), tid(12), 0));
+        assert_eq!(state.tracer_of(tid(11)), Some((tid(10), 0)));
+        // Not stopped yet: no requests, and nothing to wait for.
+        assert!(state.stopped(tid(11), tid(10)).is_none());
+        assert_eq!(state.stops_for(tid(10)), (vec![], vec![tid(11)]));
+
+        assert_eq!(state.stop(tid(11), 0x137f, 19, vec![1, 2]), Some(tid(10)));
+        assert_eq!(state.stops_for(tid(10)).0, vec![(tid(11), 0x137f)]);
+        state.stop_reported(tid(11));
+        assert_eq!(state.stops_for(tid(10)).0, vec![]);
+        // Only the tracer can see the stop.
+        assert!(state.stopped(tid(11), tid(12)).is_none());
+        assert_eq!(state.stopped(tid(11), tid(10)).unwrap().regs, vec![1, 2]);
+
+        assert_eq!(state.poll_resume(tid(11)), None);
+        let cont = Resume::Continue {
+            signal: 0,
+            syscall_stops: true,
+        };
+        assert!(state.resume(tid(11), tid(10), cont));
+        // Resuming twice fails, as the tracee is no longer stopped.
+        assert!(!state.resume(tid(11), tid(10), cont));
+        assert_eq!(state.poll_resume(tid(11)), Some(cont));
+        assert!(state.stopped(tid(11), tid(10)).is_none());
+
+        state.stop(tid(11), 0x57f, 5, vec![]);
+        assert!(state.resume(tid(11), tid(10), Resume::Detach { signal: 0 }));
+        assert_eq!(
+            state.poll_resume(tid(11)),
+            Some(Resume::Detach { signal: 0 })
+        );
+        assert_eq!(state.tracer_of(tid(11)), None);
+        // An untraced thread has nothing to stop for.
+        assert_eq!(state.stop(tid(11), 0x57f, 5, vec![]), None);
+    }
+
+    #[test]
+    fn stops_are_reported_in_order() {
+        let mut state = PtraceState::default();
+        for tracee in [21, 22, 23] {
+            state.attach(tid(tracee), tid(20), 0);
+        }
+        state.stop(tid(23), 1, 0, vec![]);
+        state.stop(tid(21), 2, 0, vec![]);
+        state.stop(tid(22), 3, 0, vec![]);
+        assert_eq!(
+            state.stops_for(tid(20)).0,
+            vec![(tid(23), 1), (tid(21), 2), (tid(22), 3)]
+        );
+    }
+
+    #[test]
+    fn tracer_exit() {
+        let mut state = PtraceState::default();
+        state.attach(tid(31), tid(30), 0);
+        state.attach(tid(32), tid(30), OPTION_EXITKILL);
+        state.attach(tid(33), tid(30), OPTION_EXITKILL);
+        state.stop(tid(31), 0x137f, 19, vec![]);
+        state.stop(tid(32), 0x137f, 19, vec![]);
+        assert_eq!(state.tracer_exited(tid(30)), vec![tid(33)]);
+        assert_eq!(
+            state.poll_resume(tid(31)),
+            Some(Resume::Detach { signal: 0 })
+        );
+        assert_eq!(state.poll_resume(tid(32)), Some(Resume::Kill));
+        assert_eq!(state.tracer_of(tid(32)), None);
+    }
+}
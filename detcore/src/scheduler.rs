@@ -8,22 +8,32 @@
 
 //! Deterministic scheduling algorithm.
 
+pub mod fuzzy_replay;
+pub mod hooks;
 pub mod replay_cursor;
 pub mod runqueue;
 pub mod timed_waiters;
 
+use std::collections::hash_map::DefaultHasher;
 use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fmt::Write;
+use std::hash::Hash;
+use std::hash::Hasher;
 use std::iter::Peekable;
+use std::num::NonZeroU64;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::Duration;
+use std::time::Instant;
 use std::vec::IntoIter;
 
+use fuzzy_replay::ReplayDivergence;
+use hooks::Hooks;
+use hooks::Verdict;
 use nix::sys::signal;
 use nix::sys::signal::Signal;
 use nix::unistd::Pid;
@@ -45,17 +55,28 @@ use timed_waiters::TimedEvents;
 use tracing::debug;
 use tracing::enabled;
 use tracing::info;
+use tracing::info_span;
 use tracing::trace;
+use tracing::Instrument;
 use tracing::Level;
+use tracing::Span;
 
 use crate::config::Config;
+use crate::config::DivergencePolicy;
+use crate::consts::SPAN_TARGET;
 use crate::detlog_debug;
 use crate::ivar::Ivar;
-use crate::preemptions::read_trace;
+use crate::preemptions::PreemptionReader;
 use crate::preemptions::PreemptionWriter;
+use crate::ptrace::AttachError;
+use crate::ptrace::PtraceState;
 use crate::resources::Permission;
 use crate::resources::ResourceID;
 use crate::resources::Resources;
+use crate::summary::SchedOverhead;
+use crate::timers::TimerId;
+use crate::timers::VirtualTimer;
+use crate::timers::FIRST_VIRTUAL_TIMER_ID;
 use crate::types::DetPid;
 use crate::types::DetTid;
 use crate::types::FutexID;
@@ -65,6 +86,10 @@ use crate::types::Op;
 use crate::types::SchedEvent;
 use crate::types::SigWrapper;
 use crate::types::SyscallPhase;
+use crate::types::ThreadIdentity;
+
+/// How many iterations of the scheduler loop are traced as one span.
+const SPAN_ITERS: u64 = 1024;
 
 /// Unique identifier for an action.
 pub type ActionID = u64;
@@ -228,6 +253,9 @@ pub struct Scheduler {
     /// The logical, global time consumed by actions that have been committed already.
     pub committed_time: LogicalTime,
 
+    /// A running hash of the turns committed so far, and which thread each went to.
+    pub schedule_hash: u64,
+
     /// INVARIANT: Thread IDs in `blocked` are absent from `run_queue`.
     pub blocked: BlockedPool,
 
@@ -266,6 +294,20 @@ pub struct Scheduler {
     /// A cursor that holds our place in the global total order of events being replayed.
     pub replay_cursor: Option<ReplayCursor<SchedEvent>>,
 
+    /// Under `--fuzzy-replay`, how far the run has strayed from the schedule being replayed.
+    pub fuzzy_replay: Option<ReplayDivergence>,
+
+    /// Where the scheduler loop's wall-clock time went, for the run summary.
+    pub overhead: SchedOverhead,
+
+    /// Set once the schedule being replayed is dropped by `--on-divergence=record`, after which
+    /// the run carries on as if it were not replaying.
+    pub replay_abandoned: bool,
+
+    /// Under `--replay-prefix-then-chaos`, the index of the first event which is no longer
+    /// replayed.
+    replay_prefix_end: Option<u64>,
+
     /// Keep track of how many events we have replayed.  The current value is the event number of
     /// the NEXT event to replay.
     pub traced_event_count: u64,
@@ -285,10 +327,67 @@ pub struct Scheduler {
     stop_after_iter: Option<u64>,
     /// A cached copy of the same (immutable) field in Config.
     recordreplay_modes: bool,
-    /// A cached copy of the same (immutable) field in Config.
-    die_on_desync: bool,
+    /// What to do when the replayed schedule diverges: `Config::on_divergence`, or
+    /// `DivergencePolicy::Abort` if `Config::die_on_desync` is set.
+    on_divergence: Option<DivergencePolicy>,
     /// A cached copy of the same (immutable) field in Config.
     replay_exhausted_panic: bool,
+    /// A cached copy of the same (immutable) field in Config.
+    timer_resolution: Option<NonZeroU64>,
+
+    /// The signal-delivering timers of each process: its alarm/`ITIMER_REAL` timer and
+    /// any POSIX timers.  Pending expirations are mirrored in `blocked.timed_waiters`.
+    ///
+    /// NB: BTreeMap over HashMap for deterministic printing.
+    pub process_timers: BTreeMap<(DetPid, TimerId), ProcessTimer>,
+
+    /// The next POSIX timer id to hand out, per process.
+    next_timer_ids: BTreeMap<DetPid, i32>,
+
+    /// The `--timeout` and the signal to send when it expires, until it is armed.
+    timeout: Option<(Duration, Signal)>,
+
+    /// Child processes whose last thread has exited, but which have not been waited for yet,
+    /// per parent process, in the order they exited.  Waits reap children in this order,
+    /// rather than whichever the host kernel happens to have finished tearing down first.
+    ///
+    /// NB: BTreeMap over HashMap for deterministic printing.
+    pub exited_children: BTreeMap<DetPid, Vec<DetPid>>,
+
+    /// The virtual CPU time of the children each process has waited for, and of the children
+    /// they in turn waited for (i.e. `RUSAGE_CHILDREN`).
+    ///
+    /// NB: BTreeMap over HashMap for deterministic printing.
+    reaped_cpu: BTreeMap<DetPid, Duration>,
+
+    /// The processes with a SIGCHLD sent by the scheduler pending, which they have not
+    /// received yet.  Like any standard signal, a SIGCHLD sent while one is pending is merged
+    /// into it.  Any other SIGCHLD (i.e. the kernel's own, sent whenever the host gets around
+    /// to it) is suppressed.
+    sigchld_pending: BTreeSet<DetPid>,
+
+    /// Children created with `CLONE_VFORK` which have neither exec'd nor exited yet.  Their
+    /// parents stay suspended until then.
+    pub vfork_children: BTreeSet<DetTid>,
+
+    /// Which guest threads are ptraced by which guest processes, and their stops.
+    pub ptrace: PtraceState,
+
+    /// The hooks of external tools, consulted on every turn.
+    hooks: Hooks,
+}
+
+/// A per-process timer which delivers a signal on each expiration.
+#[derive(Debug, Clone)]
+pub struct ProcessTimer {
+    /// When the timer next expires, and its reload interval.
+    pub timer: VirtualTimer,
+    /// The signal to deliver, or `None` for a timer created with `SIGEV_NONE`.
+    pub signal: Option<Signal>,
+    /// The thread we would like to deliver to, if it still exists.
+    pub target: DetTid,
+    /// Expirations which occurred before the last signal was delivered (`timer_getoverrun`).
+    pub overrun: u64,
 }
 
 type StacktraceEventsIter = Peekable<IntoIter<(u64, Option<PathBuf>)>>;
@@ -311,6 +410,10 @@ pub struct ThreadTree {
     /// transitive closure of `thread_tree`).  Every thread should have an entry in
     /// here. If, however, a thread is a group leader, this will map back to itself.
     thread_to_leader: HashMap<DetTid, DetPid>,
+
+    /// The identity of every thread in the tree, which is stable across runs of slightly
+    /// different guests where its `DetTid` is not.
+    identities: HashMap<DetTid, ThreadIdentity>,
 }
 
 use pretty::Doc;
@@ -325,11 +428,20 @@ impl ThreadTree {
                 self.root = Some(child);
                 // Ensure an entry, even if the children vector is empty:
                 let _vec = self.tree.entry(child).or_default();
+                self.identities.insert(child, ThreadIdentity::root());
             }
             Some(p) => {
                 let vec = self.tree.entry(p).or_default();
                 vec.push(child);
+                let index = (vec.len() - 1) as u32;
                 let _vec = self.tree.entry(child).or_default();
+                let identity = self
+                    .identities
+                    .get(&p)
+                    .cloned()
+                    .unwrap_or_else(ThreadIdentity::root)
+                    .child(index);
+                self.identities.insert(child, identity);
             }
         }
     }
@@ -448,6 +560,55 @@ impl ThreadTree {
         }
     }
 
+    /// The identity of a thread, if the thread is known.
+    pub fn identity_of(&self, tid: &DetTid) -> Option<&ThreadIdentity> {
+        self.identities.get(tid)
+    }
+
+    /// The thread with the given identity, if there is one.
+    pub fn find_identity(&self, identity: &ThreadIdentity) -> Option<DetTid> {
+        self.identities
+            .iter()
+            .find(|(_, i)| *i == identity)
+            .map(|(tid, _)| *tid)
+    }
+
+    /// Record the new name of a thread, which threads spawned after this inherit.
+    pub fn rename(&mut self, tid: &DetTid, name: String) {
+        if let Some(identity) = self.identities.get_mut(tid) {
+            identity.name = Some(name);
+        }
+    }
+
+    /// The process containing a thread, if the thread is known.
+    pub fn leader_of(&self, tid: &DetTid) -> Option<DetPid> {
+        self.thread_to_leader.get(tid).copied()
+    }
+
+    /// The process which created a process, i.e. that of the thread which forked it.
+    pub fn parent_process(&self, leader: &DetPid) -> Option<DetPid> {
+        let (parent, _) = self
+            .tree
+            .iter()
+            .find(|(_, children)| children.contains(leader))?;
+        self.leader_of(parent)
+    }
+
+    /// The processes created by the threads of a process.
+    pub fn child_processes(&mut self, leader: &DetPid) -> Vec<DetPid> {
+        let mut acc = Vec::new();
+        for tid in self.my_thread_group(leader) {
+            if let Some(children) = self.tree.get(&tid) {
+                acc.extend(
+                    children
+                        .iter()
+                        .filter(|child| self.thread_group_leaders.contains(child)),
+                );
+            }
+        }
+        acc
+    }
+
     /// Return the set of thread IDs in the "same process" as me (same TGID), including
     /// myself.
     ///
@@ -548,13 +709,16 @@ pub(crate) async fn sched_loop(sched: Arc<Mutex<Scheduler>>, timer: Arc<Mutex<Gl
     // We keep track of whether the last turn was a SKIP:
     let mut last_res = Err(SkipTurn);
     let mut backoff = Backoff::new();
+    let mut batch = Span::none();
 
     loop {
         // TODO (T137183027, T137184765): as part of the current strategy for blocking IO ops (see
         // SPINNING below), we need to make sure that other threads can progress so we don't
         // busy-wait too tightly.
         if last_res.is_err() {
+            let start = Instant::now();
             backoff.further().await;
+            SchedOverhead::add(&mut sched.lock().unwrap().overhead.backoff_ns, start.elapsed());
         } else {
             backoff.reset();
         }
@@ -569,11 +733,15 @@ pub(crate) async fn sched_loop(sched: Arc<Mutex<Scheduler>>, timer: Arc<Mutex<Gl
             );
             immediate_fatal_exit(); // We don't want a backtrace of this thread.
         }
+        if iter % SPAN_ITERS == 0 {
+            let turn = sched.lock().unwrap().turn;
+            batch = info_span!(target: SPAN_TARGET, "sched_batch", first_iter = iter, turn);
+        }
         iter += 1;
 
         // If there are NO threads left in the system, then we're truly done:
         {
-            let sched = sched.lock().unwrap();
+            let mut sched = sched.lock().unwrap();
             if sched.run_queue.is_empty() && sched.blocked.is_empty() {
                 info!("[scheduler] run queue empty, exiting sched_loop.");
                 return;
@@ -591,7 +759,9 @@ pub(crate) async fn sched_loop(sched: Arc<Mutex<Scheduler>>, timer: Arc<Mutex<Gl
 
         // Otherwise we trust the turn function to either choose a runnable thread or wait
         // until something blocked is ready to run again.
-        last_res = do_a_turn_blocking(sched.clone(), timer.clone(), &last_res).await;
+        last_res = do_a_turn_blocking(sched.clone(), timer.clone(), &last_res)
+            .instrument(batch.clone())
+            .await;
     }
 }
 
@@ -632,15 +802,21 @@ pub async fn do_a_turn_blocking(
             }
         };
         trace!("Scheduler wait for full quiescense, on {}...", req_ivar);
+        let start = Instant::now();
         let _ = req_ivar.await;
+        SchedOverhead::add(&mut sched.lock().unwrap().overhead.quiesce_ns, start.elapsed());
     }
 
     // Here we copy some information while holding the sched lock, and then release it so
     // we can `.await` below:
     let (next_dtid, req, resp) = {
+        let start = Instant::now();
         let mut sched = sched.lock().unwrap();
-        sched.step2_process_blocked(&global_time)?;
-        sched.step3_peek().ok_or(SkipTurn)?
+        let next = sched
+            .step2_process_blocked(&global_time)
+            .and_then(|()| sched.step3_peek().ok_or(SkipTurn));
+        SchedOverhead::add(&mut sched.overhead.decide_ns, start.elapsed());
+        next?
     };
 
     // Step 1B: wait for the selected thread to make its request.
@@ -648,7 +824,10 @@ pub async fn do_a_turn_blocking(
         "[sched-daemon] waiting for next thread (dtid {}) to park...",
         next_dtid
     );
-    let rsrcs: Resources = match req.get().await {
+    let start = Instant::now();
+    let requested = req.get().await;
+    SchedOverhead::add(&mut sched.lock().unwrap().overhead.request_ns, start.elapsed());
+    let rsrcs: Resources = match requested {
         Err(ThreadExited) => {
             debug!(
                 "[sched-daemon] woke up on request {}, but fizzling because next thread, {}, exited.",
@@ -670,19 +849,34 @@ pub async fn do_a_turn_blocking(
             &next_dtid
         );
     } else {
+        let start = Instant::now();
         let mut mg = sched.lock().unwrap();
-
-        // The logical COMMIT point for the turn is during step4:
-        mg.step4_resource_block(next_dtid, &rsrcs, &resp)?;
-        mg.step5_guest_unblock(next_dtid, &rsrcs, &resp)?;
-        mg.step6_reenquue(next_dtid);
-        if let Some(call) = rsrcs.as_exit_syscall() {
-            mg.step7_simulate_exit_posthook(next_dtid, call, &global_time);
-        }
+        let committed = commit_turn(&mut mg, next_dtid, &rsrcs, &resp, &global_time);
+        SchedOverhead::add(&mut mg.overhead.decide_ns, start.elapsed());
+        committed?;
     }
     Ok(rsrcs)
 }
 
+/// Steps 3 to 7 of a turn, which go ahead with the request of the thread chosen to run.
+fn commit_turn(
+    mg: &mut Scheduler,
+    next_dtid: DetTid,
+    rsrcs: &Resources,
+    resp: &Ivar<SchedResponse>,
+    global_time: &Arc<Mutex<GlobalTime>>,
+) -> Result<(), SkipTurn> {
+    mg.step3_consult_hooks(next_dtid, rsrcs)?;
+    // The logical COMMIT point for the turn is during step4:
+    mg.step4_resource_block(next_dtid, rsrcs, resp)?;
+    mg.step5_guest_unblock(next_dtid, rsrcs, resp)?;
+    mg.step6_reenquue(next_dtid);
+    if let Some(call) = rsrcs.as_exit_syscall() {
+        mg.step7_simulate_exit_posthook(next_dtid, call, global_time);
+    }
+    Ok(())
+}
+
 // A futex request contains only one resource request, for FutexWait.
 fn assert_futex_request(nextturn: &ThreadNextTurn) {
     match nextturn.req.try_read() {
@@ -709,6 +903,9 @@ fn is_futex_request(nextturn: &ThreadNextTurn) -> bool {
     }
 }
 
+/// How many recorded events are shown when reporting a divergence from the schedule.
+const DIVERGENCE_REPORT_EVENTS: usize = 5;
+
 /// Is a desync considered fatal, or just kinda bad.
 /// RCB divergence is the later, mismatched syscalls are currently the former.
 fn is_hard_desync(observed: &SchedEvent, expected: &SchedEvent) -> bool {
@@ -721,6 +918,29 @@ fn is_hard_desync(observed: &SchedEvent, expected: &SchedEvent) -> bool {
     strip1 != strip2
 }
 
+/// Prepare an event expected while replaying for comparison with the observed one.  If both are by
+/// the same thread, either with the same `DetTid` or the same identity, the expected event takes
+/// on the observed `DetTid`, which may have shifted since the schedule was recorded.  Identities
+/// are dropped from both, having served their purpose.
+fn match_thread(observed: &SchedEvent, expected: SchedEvent) -> (SchedEvent, SchedEvent) {
+    let same_identity = observed.identity.is_some() && observed.identity == expected.identity;
+    let dettid = if same_identity {
+        observed.dettid
+    } else {
+        expected.dettid
+    };
+    let observed = SchedEvent {
+        identity: None,
+        ..observed.clone()
+    };
+    let expected = SchedEvent {
+        dettid,
+        identity: None,
+        ..expected
+    };
+    (observed, expected)
+}
+
 fn compare_desync(observed: &SchedEvent, expected: &SchedEvent) -> String {
     if *observed == *expected {
         "MATCHED".to_string()
@@ -782,19 +1002,32 @@ impl Scheduler {
         let sched_seed = cfg.sched_seed.unwrap_or(cfg.seed);
         Self {
             preemption_writer: if cfg.record_preemptions {
-                Some(PreemptionWriter::new(cfg.record_preemptions_to.clone()))
+                Some(PreemptionWriter::with_metadata(
+                    cfg.record_preemptions_to.clone(),
+                    cfg.record_metadata.clone(),
+                ))
             } else {
                 None
             },
             replay_cursor: match &cfg.replay_schedule_from {
                 Some(path) => {
                     trace!("Scheduler loading trace from path {}", path.display());
-                    let vec = read_trace(path);
-                    trace!("Trace loaded, length {}", vec.len());
-                    Some(vec.into_iter().collect())
+                    let reader = PreemptionReader::new(path);
+                    trace!("Trace opened, length {}", reader.num_events());
+                    // Only the events which may be peeked at are read ahead of the replay.
+                    let lookahead = fuzzy_replay::LOOKAHEAD.max(DIVERGENCE_REPORT_EVENTS);
+                    Some(ReplayCursor::lazy(reader.into_events(), lookahead))
                 }
                 None => None,
             },
+            fuzzy_replay: if cfg.fuzzy_replay {
+                Some(Default::default())
+            } else {
+                None
+            },
+            overhead: Default::default(),
+            replay_abandoned: false,
+            replay_prefix_end: cfg.replay_prefix_then_chaos.as_ref().map(|p| p.events),
             traced_event_count: 0,
             recorded_event_count: 0,
             stacktrace_events: if cfg.stacktrace_event.is_empty() {
@@ -810,17 +1043,31 @@ impl Scheduler {
                 sched_seed,
                 cfg.sched_sticky_random_param,
             ),
-            die_on_desync: cfg.die_on_desync,
+            on_divergence: match cfg.on_divergence {
+                None if cfg.die_on_desync => Some(DivergencePolicy::Abort),
+                policy => policy,
+            },
             replay_exhausted_panic: cfg.replay_exhausted_panic,
+            timer_resolution: cfg.timer_resolution,
             turn: 0,
             next_turns: Default::default(),
             bg_action_pool: Default::default(),
             committed_time: Default::default(),
+            schedule_hash: 0,
             blocked: Default::default(),
             resources: Default::default(),
             started_up: Default::default(),
             thread_tree: Default::default(),
             priorities: Default::default(),
+            process_timers: Default::default(),
+            next_timer_ids: Default::default(),
+            timeout: cfg.timeout.map(|timeout| (timeout, cfg.timeout_signal.0)),
+            exited_children: Default::default(),
+            reaped_cpu: Default::default(),
+            sigchld_pending: Default::default(),
+            vfork_children: Default::default(),
+            ptrace: Default::default(),
+            hooks: Hooks::take_registered(),
         }
     }
 
@@ -884,18 +1131,64 @@ impl Scheduler {
         debug_assert!(self.replay_cursor.is_some());
 
         let mytid = observed.dettid;
-        let time = observed.end_time.expect("timestamps required for now");
 
         let current_ix = self.traced_event_count;
         self.traced_event_count += 1;
         let print_stack = self.try_pop_stacktrace_event(current_ix);
 
+        if self.replay_prefix_end == Some(current_ix) {
+            info!(
+                "[scheduler] Replayed {} events of the schedule prefix, handing over to chaos mode",
+                current_ix
+            );
+            self.abandon_replay();
+        }
+
+        if self.replay_abandoned {
+            return ConsumeResult {
+                keep_running: true,
+                print_stack,
+                event_ix: current_ix,
+            };
+        }
+
+        if let Some(divergence) = &mut self.fuzzy_replay {
+            let cursor = self.replay_cursor.as_mut().unwrap();
+            match fuzzy_replay::find_match(cursor, observed) {
+                Some((skip, matched)) => {
+                    for skipped in cursor.by_ref().take(skip) {
+                        divergence.skipped(current_ix, &skipped);
+                    }
+                    divergence.matched(current_ix, observed, cursor.peek().unwrap(), matched);
+                }
+                None => {
+                    divergence.unmatched(current_ix, observed);
+                    if cursor.peek().is_some() {
+                        // Leave the recorded events for later events to match.
+                        return ConsumeResult {
+                            keep_running: self.follow_replay(observed),
+                            print_stack,
+                            event_ix: current_ix,
+                        };
+                    }
+                }
+            }
+        } else if let Some(keep_running) = self.check_divergence(observed, current_ix) {
+            return ConsumeResult {
+                keep_running,
+                print_stack,
+                event_ix: current_ix,
+            };
+        }
+
         if let Some(expected) = self
             .replay_cursor
             .as_mut()
             .expect("replay iterator set while in replay mode")
             .next()
         {
+            let (observed, expected) = match_thread(observed, expected);
+            let observed = &observed;
             debug!(
                 "[detcore, dtid {}] {}: Ran event #{} {:?}, current replay event: {:?}",
                 mytid,
@@ -910,41 +1203,7 @@ impl Scheduler {
                 self.replay_cursor.as_mut().unwrap().peek()
             );
 
-            if is_hard_desync(observed, &expected) && self.die_on_desync {
-                eprintln!("Replay mode desynchronized from trace, bailing out.");
-                immediate_fatal_exit();
-            }
-
-            let keep_running = if let Some(next_ev) = self.replay_cursor.as_mut().unwrap().peek() {
-                let next_tid = next_ev.dettid;
-                if next_tid != observed.dettid {
-                    let is_prehook = matches!(observed.op, Op::Syscall(_, SyscallPhase::Prehook));
-                    if is_prehook {
-                        info!(
-                            "[detcore, dtid {}] CONTEXT SWITCH to {} after this syscall blocks.  Reprioritizing at time {}",
-                            &mytid, next_tid, time
-                        );
-                    } else {
-                        info!(
-                            "[detcore, dtid {}] CONTEXT SWITCH to {} after the last event retired.  Reprioritizing at time {}",
-                            &mytid, next_tid, time
-                        );
-                    }
-                    self.requeue_with_new_priority(mytid, REPLAY_DEFERRED_PRIORITY);
-                    self.requeue_with_new_priority(next_tid, REPLAY_FOREGROUND_PRIORITY);
-                    // The *downgrading* of the current thread will be handled by the caller if the
-                    // context switch is *now*.  If the last traced event on this thread is instead
-                    // a prehook, well we don't deschedule the current thread quite yet.  Rather, we
-                    // let the thread plow ahead, and actually block on the syscall, which will have
-                    // the effect of descheduling the therad anyway.  After that, the priorities
-                    // be set so as to make sure the correct thread (next_tid) runs.
-                    is_prehook
-                } else {
-                    true // We're still running the next event.
-                }
-            } else {
-                true // We're the very last event.  Nothing to do.
-            };
+            let keep_running = self.follow_replay(observed);
             ConsumeResult {
                 keep_running,
                 print_stack,
@@ -972,6 +1231,128 @@ impl Scheduler {
         }
     }
 
+    /// Apply the `--on-divergence` policy if `observed`, event `index` of the run, diverges from
+    /// the next event of the schedule.  Returns whether the thread keeps running if that is the
+    /// end of it, or None if the event is still to be consumed as usual.
+    fn check_divergence(&mut self, observed: &SchedEvent, index: u64) -> Option<bool> {
+        let policy = self.on_divergence?;
+        let expected = self.replay_cursor.as_ref().unwrap().peek()?.clone();
+        let (stripped, expected) = match_thread(observed, expected);
+        if !is_hard_desync(&stripped, &expected) {
+            return None;
+        }
+        match policy {
+            DivergencePolicy::Abort => {
+                eprint!("{}", self.divergence_report(observed, index));
+                eprintln!("Replay mode desynchronized from trace, bailing out.");
+                immediate_fatal_exit();
+                None
+            }
+            DivergencePolicy::Resync => {
+                let cursor = self.replay_cursor.as_mut().unwrap();
+                match fuzzy_replay::find_match(cursor, observed) {
+                    Some((skip, _)) => {
+                        info!(
+                            "[detcore, dtid {}] Replay diverged at event #{}, skipping {} recorded events to resynchronize",
+                            observed.dettid, index, skip
+                        );
+                        cursor.by_ref().take(skip).for_each(drop);
+                        None
+                    }
+                    None => {
+                        info!(
+                            "[detcore, dtid {}] Replay diverged at event #{}, and no recorded event close by matches",
+                            observed.dettid, index
+                        );
+                        Some(self.follow_replay(observed))
+                    }
+                }
+            }
+            DivergencePolicy::Record => {
+                eprint!("{}", self.divergence_report(observed, index));
+                eprintln!("Dropping the schedule, and continuing without it.");
+                self.abandon_replay();
+                Some(true)
+            }
+        }
+    }
+
+    /// Describe where the run diverged from the schedule: the event it ran, event `index`, and the
+    /// recorded events it ran instead of.
+    fn divergence_report(&self, observed: &SchedEvent, index: u64) -> String {
+        let cursor = self.replay_cursor.as_ref().unwrap();
+        let mut report = format!("Replay diverged from the schedule at event #{}.\n", index);
+        writeln!(report, "  ran:      {:?}", observed).unwrap();
+        for n in 0..DIVERGENCE_REPORT_EVENTS {
+            if let Some(ev) = cursor.peek_nth(n) {
+                let label = if n == 0 { "expected:" } else { "then:" };
+                writeln!(report, "  {:<9} {:?}", label, ev).unwrap();
+            }
+        }
+        report
+    }
+
+    /// Stop following the schedule being replayed, and put every thread back at the default
+    /// priority, as if the run had not been replaying.
+    fn abandon_replay(&mut self) {
+        self.replay_abandoned = true;
+        let tids: Vec<DetTid> = self.priorities.keys().copied().collect();
+        for tid in tids {
+            self.requeue_with_new_priority(tid, DEFAULT_PRIORITY);
+        }
+    }
+
+    /// Set up the next replayed event to run, after `observed`.  Return true if the current thread
+    /// will keep running and false if it needs to be descheduled.
+    fn follow_replay(&mut self, observed: &SchedEvent) -> bool {
+        let mytid = observed.dettid;
+        let time = observed.end_time.expect("timestamps required for now");
+        if let Some(next_ev) = self.replay_cursor.as_ref().unwrap().peek() {
+            let next_tid = self.replay_tid(next_ev);
+            if next_tid != observed.dettid {
+                let is_prehook = matches!(
+                    observed.op,
+                    Op::Syscall(_, SyscallPhase::Prehook)
+                        | Op::Condvar(_, SyscallPhase::Prehook)
+                        | Op::Socket(_, SyscallPhase::Prehook)
+                );
+                if is_prehook {
+                    info!(
+                        "[detcore, dtid {}] CONTEXT SWITCH to {} after this syscall blocks.  Reprioritizing at time {}",
+                        &mytid, next_tid, time
+                    );
+                } else {
+                    info!(
+                        "[detcore, dtid {}] CONTEXT SWITCH to {} after the last event retired.  Reprioritizing at time {}",
+                        &mytid, next_tid, time
+                    );
+                }
+                self.requeue_with_new_priority(mytid, REPLAY_DEFERRED_PRIORITY);
+                self.requeue_with_new_priority(next_tid, REPLAY_FOREGROUND_PRIORITY);
+                // The *downgrading* of the current thread will be handled by the caller if the
+                // context switch is *now*.  If the last traced event on this thread is instead
+                // a prehook, well we don't deschedule the current thread quite yet.  Rather, we
+                // let the thread plow ahead, and actually block on the syscall, which will have
+                // the effect of descheduling the therad anyway.  After that, the priorities
+                // be set so as to make sure the correct thread (next_tid) runs.
+                is_prehook
+            } else {
+                true // We're still running the next event.
+            }
+        } else {
+            true // We're the very last event.  Nothing to do.
+        }
+    }
+
+    /// The thread that should run an event being replayed: the thread with the identity recorded
+    /// for it, if there is one yet, and otherwise the thread with the recorded `DetTid`.
+    fn replay_tid(&self, ev: &SchedEvent) -> DetTid {
+        ev.identity
+            .as_ref()
+            .and_then(|identity| self.thread_tree.find_identity(identity))
+            .unwrap_or(ev.dettid)
+    }
+
     /// Remove a thread from the deterministic scheduler.  In order to call this, the precondition
     /// is that this thread will execute no further (visible) instructions.
     ///
@@ -991,6 +1372,9 @@ impl Scheduler {
         self.remove_blocking_entries(dtid);
 
         let _ = self.priorities.remove(dtid);
+        // A vfork parent resumes when the child exits:
+        self.vfork_children.remove(dtid);
+        self.ptrace.thread_exited(*dtid);
         match self.next_turns.remove(dtid) {
             None => {
                 trace!(
@@ -1006,11 +1390,191 @@ impl Scheduler {
                 // down the exit scenarios and ensure that they happen when the guest is running and
                 // has NOT filled its request to the scheduler yet.
                 nextturn.req.try_put(Err(ThreadExited));
-                self.wake_futex_child_cleartid((*detpid, nextturn.child_tid_addr), *dtid);
+                let futid = FutexID::Private(*detpid, nextturn.child_tid_addr);
+                self.wake_futex_child_cleartid(futid, *dtid);
+                if let Some(leader) = self.thread_tree.leader_of(dtid) {
+                    if !self.process_alive(&leader) {
+                        self.notify_process_exit(leader);
+                        for tracee in self.ptrace.tracer_exited(leader) {
+                            if let ThreadStatus::Gone = self.thread_status(tracee) {
+                                continue;
+                            }
+                            info!("[dtid {}] killing tracee of exited {}", tracee, leader);
+                            self.signal_guest(tracee, Signal::SIGKILL);
+                        }
+                    }
+                }
             }
         }
     }
 
+    /// Does any thread of the process remain?
+    fn process_alive(&mut self, leader: &DetPid) -> bool {
+        self.thread_tree
+            .my_thread_group(leader)
+            .iter()
+            .any(|tid| self.next_turns.contains_key(tid))
+    }
+
+    /// The last thread of a process is gone.  Make the process available to its parent's
+    /// waits, and notify the parent with SIGCHLD, at this point in the schedule.
+    fn notify_process_exit(&mut self, detpid: DetPid) {
+        // Only the run's `--timeout` outlives the process that armed it.
+        self.clear_process_timers(detpid, |id| id != TimerId::Timeout);
+        self.next_timer_ids.remove(&detpid);
+        let parent = match self.thread_tree.parent_process(&detpid) {
+            Some(parent) => parent,
+            None => return,
+        };
+        info!("[detpid {}] process exited, parent is {}", detpid, parent);
+        self.exited_children.entry(parent).or_default().push(detpid);
+        if self.recordreplay_modes {
+            // Signals are recorded and replayed as they come from the kernel.
+            return;
+        }
+        self.send_sigchld(parent);
+    }
+
+    /// Send SIGCHLD to a process, unless it has exited.
+    fn send_sigchld(&mut self, detpid: DetPid) {
+        if let ThreadStatus::Gone = self.thread_status(detpid) {
+            return;
+        }
+        let target = self.select_signal_target(detpid, None);
+        self.sigchld_pending.insert(detpid);
+        self.signal_guest(target, Signal::SIGCHLD);
+    }
+
+    /// Start ptracing a thread on behalf of a process or, for `PTRACE_TRACEME`, of the parent
+    /// of the thread's process.  With `stop` (i.e. for `PTRACE_ATTACH`), the tracee is sent
+    /// SIGSTOP, to stop it at this point in the schedule.
+    pub fn ptrace_attach(
+        &mut self,
+        tracee: DetTid,
+        tracer: Option<DetPid>,
+        options: u32,
+        stop: bool,
+    ) -> Result<(), AttachError> {
+        let leader = match self.thread_tree.leader_of(&tracee) {
+            Some(leader) if self.next_turns.contains_key(&tracee) => leader,
+            _ => return Err(AttachError::NoSuchThread),
+        };
+        let tracer = tracer
+            .or_else(|| self.thread_tree.parent_process(&leader))
+            .ok_or(AttachError::NotPermitted)?;
+        if tracer == leader || !self.ptrace.attach(tracee, tracer, options) {
+            return Err(AttachError::NotPermitted);
+        }
+        info!("[dtid {}] ptraced by {}", tracee, tracer);
+        if stop {
+            self.signal_guest(tracee, Signal::SIGSTOP);
+        }
+        Ok(())
+    }
+
+    /// A ptraced thread enters a stop, with the given wait status, at this point in the
+    /// schedule.  Notify its tracer with SIGCHLD.  Returns false if it is not traced.
+    pub fn ptrace_stop(
+        &mut self,
+        dettid: DetTid,
+        status: i32,
+        signal: i32,
+        regs: Vec<u64>,
+    ) -> bool {
+        match self.ptrace.stop(dettid, status, signal, regs) {
+            Some(tracer) => {
+                info!("[dtid {}] ptrace stop {:#x}, tracer {}", dettid, status, tracer);
+                self.send_sigchld(tracer);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Has a (guest) process exited, in the schedule?
+    pub fn process_exited(&mut self, detpid: DetPid) -> bool {
+        self.thread_tree.leader_of(&detpid).is_some() && !self.process_alive(&detpid)
+    }
+
+    /// Which of the given processes have exited in the schedule, but have not been waited for
+    /// yet.  (Once waited for, the kernel has certainly finished with them too.)
+    pub fn exited_unreaped(&self, targets: &[DetPid]) -> Vec<DetPid> {
+        targets
+            .iter()
+            .filter(|target| {
+                self.exited_children
+                    .values()
+                    .any(|children| children.contains(target))
+            })
+            .copied()
+            .collect()
+    }
+
+    /// A process received SIGCHLD.  Returns true if it is one we sent, and false if it is one
+    /// to suppress.
+    pub fn claim_sigchld(&mut self, detpid: DetPid) -> bool {
+        self.sigchld_pending.remove(&detpid)
+    }
+
+    /// The child processes of a process which may be waited for: those which have exited (in
+    /// the order they exited), and those which are still running.
+    pub fn child_processes(&mut self, detpid: DetPid) -> (Vec<DetPid>, Vec<DetPid>) {
+        let exited = self
+            .exited_children
+            .get(&detpid)
+            .cloned()
+            .unwrap_or_default();
+        let running = self
+            .thread_tree
+            .child_processes(&detpid)
+            .into_iter()
+            .filter(|child| !exited.contains(child) && self.process_alive(child))
+            .collect();
+        (exited, running)
+    }
+
+    /// An exited child process has been waited for, and is gone for good.  Its CPU time, and
+    /// that of the children it waited for, is added to its parent's, and returned.
+    pub fn reap_child(
+        &mut self,
+        parent: DetPid,
+        child: DetPid,
+        global_time: &GlobalTime,
+    ) -> Duration {
+        if let Some(children) = self.exited_children.get_mut(&parent) {
+            children.retain(|c| *c != child);
+        }
+        let cpu = self.process_cpu_time(&child, global_time)
+            + self.reaped_cpu.remove(&child).unwrap_or_default();
+        *self.reaped_cpu.entry(parent).or_default() += cpu;
+        cpu
+    }
+
+    /// The virtual CPU time of a process: the sum of that of all of its threads, living or
+    /// dead.
+    fn process_cpu_time(&mut self, detpid: &DetPid, global_time: &GlobalTime) -> Duration {
+        self.thread_tree
+            .my_thread_group(detpid)
+            .into_iter()
+            .map(|dtid| global_time.cpu_time(dtid))
+            .sum()
+    }
+
+    /// The virtual CPU times of a thread, of its process, and of the children its process has
+    /// waited for.  `None` if the thread does not exist.
+    pub fn cpu_times(
+        &mut self,
+        dettid: DetTid,
+        global_time: &GlobalTime,
+    ) -> Option<(Duration, Duration, Duration)> {
+        let leader = self.thread_tree.leader_of(&dettid)?;
+        Some((
+            global_time.cpu_time(dettid),
+            self.process_cpu_time(&leader, global_time),
+            self.reaped_cpu.get(&leader).copied().unwrap_or_default(),
+        ))
+    }
+
     /// Remove entries from everywhere that non-runnable threads lurk.
     fn remove_blocking_entries(&mut self, dtid: &DetTid) {
         self.blocked.timed_waiters.remove(*dtid);
@@ -1160,18 +1724,49 @@ impl Scheduler {
         {
             match evt {
                 TimedEvent::ThreadEvt(dtid) => self.wake_timed_event(time_ns, dtid),
-                TimedEvent::AlarmEvt(dpid, dtid, sig) => self.fire_alarm(dpid, dtid, sig),
+                TimedEvent::AlarmEvt(dpid, id, dtid, sig) => {
+                    self.fire_alarm(dpid, id, dtid, sig, self.committed_time)
+                }
             }
         }
     }
 
-    fn fire_alarm(&mut self, dtid: DetTid, dpid: DetPid, sig: Signal) {
+    fn fire_alarm(
+        &mut self,
+        dpid: DetPid,
+        id: TimerId,
+        dtid: DetTid,
+        sig: Signal,
+        now: LogicalTime,
+    ) {
+        if let (ThreadStatus::Gone, ThreadStatus::Gone) =
+            (self.thread_status(dtid), self.thread_status(dpid))
+        {
+            info!(
+                "[detpid {}] Timer {} fired after the process exited, dropping it.",
+                dpid, id
+            );
+            self.process_timers.remove(&(dpid, id));
+            return;
+        }
         let target = self.select_signal_target(dpid, Some(dtid));
         info!(
-            "[dtid {}] Alarm fired, delivering signal {} to guest.",
-            target, sig
+            "[dtid {}] Alarm fired ({}), delivering signal {} to guest.",
+            target, id, sig
         );
         self.signal_guest(target, sig);
+
+        // Reload a periodic timer.  Any periods which elapsed in the meantime are folded into
+        // this one signal and counted as overruns.
+        if let Some(pt) = self.process_timers.get_mut(&(dpid, id)) {
+            let expirations = pt.timer.expire(now);
+            pt.overrun = expirations.saturating_sub(1);
+            if let Some(next) = pt.timer.deadline {
+                self.blocked
+                    .timed_waiters
+                    .insert_alarm(next, dpid, id, dtid, sig);
+            }
+        }
     }
 
     // Follow Linux semantics for delivering a signal to a thread within a process group.
@@ -1426,7 +2021,9 @@ impl Scheduler {
 
                 match evt {
                     TimedEvent::ThreadEvt(dtid) => self.wake_timed_event(event_ns, dtid),
-                    TimedEvent::AlarmEvt(dpid, dtid, sig) => self.fire_alarm(dpid, dtid, sig),
+                    TimedEvent::AlarmEvt(dpid, id, dtid, sig) => {
+                        self.fire_alarm(dpid, id, dtid, sig, event_ns)
+                    }
                 }
                 return Err(SkipTurn);
             }
@@ -1476,6 +2073,32 @@ impl Scheduler {
         }
     }
 
+    /// Step: Let the registered hooks veto the thread picked in step3.  A vetoed thread goes to
+    /// the back of the run queue, keeping its request, and the turn is skipped.
+    fn step3_consult_hooks(&mut self, next_dtid: DetTid, rsrcs: &Resources) -> Result<(), SkipTurn> {
+        if self.hooks.is_empty() {
+            return Ok(());
+        }
+        if self.hooks.pick(self.turn, next_dtid, rsrcs) == Verdict::Allow {
+            return Ok(());
+        }
+        if self.replay_cursor.is_some() && !self.replay_abandoned {
+            debug!(
+                "[sched-step3] ignoring hook veto of dettid {} while replaying",
+                next_dtid
+            );
+            return Ok(());
+        }
+        info!(
+            "[sched-step3] hook vetoed turn {} for dettid {}",
+            self.turn, next_dtid
+        );
+        let popped = self.run_queue.commit_tentative_pop(); // Begun in step3.
+        assert_eq!(next_dtid, popped);
+        self.runqueue_push_back(next_dtid);
+        self.skip_turn()
+    }
+
     /// Deschedule, but do not clear request/response. This should be used when
     /// the turn was skipped because the blocked-on resource is still blocking.
     fn skip_turn_blocked(&mut self, dettid: DetTid) -> Result<(), SkipTurn> {
@@ -1700,6 +2323,12 @@ impl Scheduler {
         guest_time: LogicalTime,
     ) -> Result<(), SkipTurn> {
         assert!(runqueue::is_ordinary_priority(new_priority));
+        // While a schedule is being replayed, it alone decides priorities, so a chaos changepoint
+        // only requeues the thread.
+        let new_priority = match self.priorities.get(&dettid) {
+            Some(prio) if self.replay_cursor.is_some() && !self.replay_abandoned => *prio,
+            _ => new_priority,
+        };
         // Alter the threads priority and requeue.
         let old_priority = self.priorities.insert(dettid, new_priority);
 
@@ -1866,6 +2495,12 @@ impl Scheduler {
                     "[sched-step5] >>>>>>>\n\n COMMIT turn {}, dettid {} using resources {:?}, on previously committed {}",
                     self.turn, next_dtid, rsrcs.resources, self.committed_time
                 );
+                for note in self.hooks.commit(self.turn, next_dtid, rsrcs) {
+                    info!("[sched-step5] hook note on turn {}: {}", self.turn, note);
+                }
+                let mut hasher = DefaultHasher::new();
+                (self.schedule_hash, self.turn, next_dtid).hash(&mut hasher);
+                self.schedule_hash = hasher.finish();
                 self.unblock_guest(next_dtid, resp);
                 Ok(())
             }
@@ -1996,8 +2631,8 @@ impl Scheduler {
     }
 
     /// Push_front a thread onto the runqueue, respecting its persistent priority
-    /// value. This should be the ordinary way threads are pushed onto the queue.
-    fn runqueue_push_front(&mut self, dettid: DetTid) -> PrioritizedOrder {
+    /// value. Used to let a thread jump ahead of others at the same priority.
+    pub fn runqueue_push_front(&mut self, dettid: DetTid) -> PrioritizedOrder {
         let priority = self.get_priority(dettid);
         self.run_queue.push_front(dettid, priority)
     }
@@ -2023,7 +2658,7 @@ impl Scheduler {
                             return ThreadStatus::NotRunning;
                         }
                     }
-                    TimedEvent::AlarmEvt(_, _, _) => {}
+                    TimedEvent::AlarmEvt(_, _, _, _) => {}
                 }
             }
             if self.blocked.external_io_blockers.contains(&dtid) {
@@ -2109,21 +2744,167 @@ impl Scheduler {
         seconds: Seconds,
         sig: Signal,
     ) -> Seconds {
-        let old = if seconds == 0 {
-            // Alarm of 0 cancels any pending signal.
-            self.blocked.timed_waiters.remove_alarm(detpid)
+        self.process_timers
+            .entry((detpid, TimerId::Alarm))
+            .or_insert_with(|| ProcessTimer::new(Some(sig), dettid))
+            .signal = Some(sig);
+        let (old_value, _) = self
+            .set_timer(
+                detpid,
+                dettid,
+                TimerId::Alarm,
+                Duration::from_secs(seconds as u64),
+                Duration::ZERO,
+                false,
+            )
+            .expect("alarm timer exists");
+        // Return 0 if no previous alarm, as per https://man7.org/linux/man-pages/man2/alarm.2.html
+        old_value.as_secs() as u32
+    }
+
+    /// Start counting down the `--timeout`, if there is one, on behalf of the root thread.
+    /// Only the first call does anything.
+    pub fn arm_timeout(&mut self, detpid: DetPid, dettid: DetTid) {
+        if let Some((timeout, sig)) = self.timeout.take() {
+            info!(
+                "[scheduler] Arming --timeout of {:?}, after which {} is sent to the guest.",
+                timeout, sig
+            );
+            self.process_timers.insert(
+                (detpid, TimerId::Timeout),
+                ProcessTimer::new(Some(sig), dettid),
+            );
+            self.set_timer(
+                detpid,
+                dettid,
+                TimerId::Timeout,
+                timeout,
+                Duration::ZERO,
+                false,
+            );
+        }
+    }
+
+    /// Create a new POSIX timer for the process, initially disarmed, and return its id.
+    pub fn create_timer(&mut self, detpid: DetPid, dettid: DetTid, sig: Option<Signal>) -> i32 {
+        let next_id = self
+            .next_timer_ids
+            .entry(detpid)
+            .or_insert(FIRST_VIRTUAL_TIMER_ID);
+        let id = *next_id;
+        *next_id += 1;
+        self.process_timers
+            .insert((detpid, TimerId::Posix(id)), ProcessTimer::new(sig, dettid));
+        id
+    }
+
+    /// Arm (or, with a zero `value`, disarm) a process timer.  The value is relative to the
+    /// current time unless `absolute` is set.  The alarm timer is created on demand.
+    ///
+    /// Returns the previous `(value, interval)` setting, or `None` if there is no such timer.
+    pub fn set_timer(
+        &mut self,
+        detpid: DetPid,
+        dettid: DetTid,
+        id: TimerId,
+        value: Duration,
+        interval: Duration,
+        absolute: bool,
+    ) -> Option<(Duration, Duration)> {
+        let now = self.committed_time;
+        let deadline = if value.is_zero() {
+            None
         } else {
-            let target_time = self.committed_time + Duration::from_secs(seconds as u64);
-            self.blocked
-                .timed_waiters
-                .insert_alarm(target_time, detpid, dettid, sig)
+            let mut target_time = if absolute {
+                LogicalTime::from_nanos(value.as_nanos() as u64)
+            } else {
+                now + value
+            };
+            if let Some(res) = self.timer_resolution {
+                target_time = target_time.round_up_to(res.get());
+            }
+            Some(target_time)
         };
-        if let Some(old_target_time) = old {
-            let remain_ns: u64 = old_target_time.as_nanos() - self.committed_time.as_nanos();
-            (remain_ns / 1_000_000_000) as u32
-        } else {
-            // Return 0 if no previous alarm, as per https://man7.org/linux/man-pages/man2/alarm.2.html
-            0
+
+        let pt = match id {
+            TimerId::Alarm => {
+                let pt = self
+                    .process_timers
+                    .entry((detpid, id))
+                    .or_insert_with(|| ProcessTimer::new(Some(Signal::SIGALRM), dettid));
+                // Like a process-directed signal, prefer the thread that last set the alarm.
+                pt.target = dettid;
+                pt
+            }
+            TimerId::Posix(_) | TimerId::Timeout => self.process_timers.get_mut(&(detpid, id))?,
+        };
+        let old = pt.timer.remaining(now);
+        pt.timer.set(deadline, interval);
+        pt.overrun = 0;
+        let (signal, target) = (pt.signal, pt.target);
+
+        match (deadline, signal) {
+            (Some(deadline), Some(sig)) => {
+                self.blocked
+                    .timed_waiters
+                    .insert_alarm(deadline, detpid, id, target, sig);
+            }
+            _ => {
+                self.blocked.timed_waiters.remove_alarm(detpid, id);
+            }
+        }
+        Some(old)
+    }
+
+    /// The current `(value, interval)` setting and overrun count of a process timer, or `None`
+    /// if there is no such timer.
+    pub fn get_timer(&self, detpid: DetPid, id: TimerId) -> Option<(Duration, Duration, u64)> {
+        match self.process_timers.get(&(detpid, id)) {
+            Some(pt) => {
+                let (value, interval) = pt.timer.remaining(self.committed_time);
+                Some((value, interval, pt.overrun))
+            }
+            // The alarm timer reads as disarmed before its first use.
+            None if id == TimerId::Alarm => Some((Duration::ZERO, Duration::ZERO, 0)),
+            None => None,
+        }
+    }
+
+    /// Delete a POSIX timer, cancelling any pending expiration.  Returns false if there was
+    /// no such timer.
+    pub fn delete_timer(&mut self, detpid: DetPid, id: TimerId) -> bool {
+        self.blocked.timed_waiters.remove_alarm(detpid, id);
+        self.process_timers.remove(&(detpid, id)).is_some()
+    }
+
+    /// A process has exec'd.  As under Linux, its POSIX timers are deleted, whereas its alarm
+    /// and interval timers carry over to the new program.
+    pub fn process_execed(&mut self, detpid: DetPid) {
+        self.clear_process_timers(detpid, |id| matches!(id, TimerId::Posix(_)));
+    }
+
+    /// Delete the timers of a process for which `which` holds, along with any pending
+    /// expirations.
+    fn clear_process_timers(&mut self, detpid: DetPid, which: impl Fn(TimerId) -> bool) {
+        let ids: Vec<TimerId> = self
+            .process_timers
+            .keys()
+            .filter(|(pid, id)| *pid == detpid && which(*id))
+            .map(|(_, id)| *id)
+            .collect();
+        for id in ids {
+            self.delete_timer(detpid, id);
+        }
+    }
+}
+
+impl ProcessTimer {
+    fn new(signal: Option<Signal>, target: DetTid) -> Self {
+        ProcessTimer {
+            timer: VirtualTimer::default(),
+            signal,
+            target,
+            overrun: 0,
         }
     }
 }
@@ -2171,4 +2952,37 @@ mod test {
         tracing_subscriber::fmt::init();
         tree.final_report();
     }
+
+    #[test]
+    fn thread_identities() {
+        let mut tree: ThreadTree = Default::default();
+        let p1 = DetPid::from_raw(3);
+        let p2 = DetPid::from_raw(4);
+        let p3 = DetPid::from_raw(5);
+        let p4 = DetPid::from_raw(7);
+        tree.add_child(p1, p1, true);
+        tree.rename(&p1, "server".to_owned());
+        tree.add_child(p1, p2, false);
+        tree.add_child(p1, p3, false);
+        tree.rename(&p3, "worker".to_owned());
+        tree.add_child(p3, p4, false);
+
+        assert_eq!(tree.identity_of(&p1).unwrap().to_string(), "/ (server)");
+        assert_eq!(tree.identity_of(&p2).unwrap().to_string(), "/0 (server)");
+        assert_eq!(tree.identity_of(&p4).unwrap().to_string(), "/1/0 (worker)");
+
+        // The same guest, but with another thread spawned earlier, which shifts the DetTids of
+        // the threads after it.
+        let mut shifted: ThreadTree = Default::default();
+        shifted.add_child(p1, p1, true);
+        shifted.rename(&p1, "server".to_owned());
+        shifted.add_child(p1, p2, false);
+        shifted.add_child(p2, p3, false);
+        shifted.add_child(p1, p4, false);
+        shifted.rename(&p4, "worker".to_owned());
+        assert_eq!(
+            shifted.find_identity(tree.identity_of(&p3).unwrap()),
+            Some(p4)
+        );
+    }
 }
@@ -0,0 +1,232 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Loose matching of a run against a schedule recorded from a slightly different guest, for
+//! `--fuzzy-replay`.
+
+use std::fmt;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use super::match_thread;
+use super::replay_cursor::ReplayCursor;
+use crate::types::Op;
+use crate::types::SchedEvent;
+
+/// How many recorded events, starting at the cursor, are searched for a match to an event.
+pub(crate) const LOOKAHEAD: usize = 16;
+
+/// How far off, as a percentage of the recorded count, the branches of a matched event may be.
+const BRANCH_TOLERANCE_PERCENT: u64 = 25;
+
+/// How many divergences are described in a `ReplayDivergence`.  The rest are only counted.
+const MAX_DIVERGENCES: usize = 100;
+
+/// How an event of the run matched a recorded one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Match {
+    /// The same thread ran the same operation, the same number of times.
+    Exact,
+    /// The same thread ran roughly as many branches.
+    Approximate,
+}
+
+/// How well an event of the run matches a recorded one, if at all.  Instruction pointers and
+/// times are ignored, as both move as soon as the guest is rebuilt.
+pub fn fuzzy_match(observed: &SchedEvent, expected: &SchedEvent) -> Option<Match> {
+    let (observed, expected) = match_thread(observed, expected.clone());
+    if observed.dettid != expected.dettid || observed.op != expected.op {
+        None
+    } else if observed.count == expected.count {
+        Some(Match::Exact)
+    } else if observed.op == Op::Branch && is_close(observed.count, expected.count) {
+        Some(Match::Approximate)
+    } else {
+        None
+    }
+}
+
+fn is_close(observed: u32, expected: u32) -> bool {
+    let diff = if observed > expected {
+        observed - expected
+    } else {
+        expected - observed
+    };
+    diff as u64 * 100 <= expected as u64 * BRANCH_TOLERANCE_PERCENT
+}
+
+/// Find the first of the next few recorded events which an event of the run matches, returning
+/// how many recorded events come before it.
+pub fn find_match(
+    cursor: &ReplayCursor<SchedEvent>,
+    observed: &SchedEvent,
+) -> Option<(usize, Match)> {
+    (0..LOOKAHEAD)
+        .map_while(|n| cursor.peek_nth(n))
+        .enumerate()
+        .find_map(|(n, expected)| fuzzy_match(observed, expected).map(|m| (n, m)))
+}
+
+/// How far a run strayed from the schedule it replayed under `--fuzzy-replay`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayDivergence {
+    /// Events which ran as recorded.
+    pub exact: u64,
+    /// Events which ran a different number of branches than recorded.
+    pub approximate: u64,
+    /// Recorded events which no event of the run matched.
+    pub skipped: u64,
+    /// Events of the run which matched no recorded event.
+    pub unmatched: u64,
+    /// The first places where the run strayed, in order.
+    pub divergences: Vec<String>,
+}
+
+impl ReplayDivergence {
+    /// Count event `index` of the run, which matched `expected`.
+    pub fn matched(
+        &mut self,
+        index: u64,
+        observed: &SchedEvent,
+        expected: &SchedEvent,
+        matched: Match,
+    ) {
+        match matched {
+            Match::Exact => self.exact += 1,
+            Match::Approximate => {
+                self.approximate += 1;
+                self.describe(|| {
+                    format!(
+                        "event #{}: thread {} ran {} branches, {} were recorded",
+                        index, observed.dettid, observed.count, expected.count
+                    )
+                });
+            }
+        }
+    }
+
+    /// Count a recorded event, skipped before event `index` of the run.
+    pub fn skipped(&mut self, index: u64, expected: &SchedEvent) {
+        self.skipped += 1;
+        self.describe(|| {
+            format!(
+                "event #{}: skipped recorded {:?} x{} of thread {}",
+                index, expected.op, expected.count, expected.dettid
+            )
+        });
+    }
+
+    /// Count event `index` of the run, which was not recorded.
+    pub fn unmatched(&mut self, index: u64, observed: &SchedEvent) {
+        self.unmatched += 1;
+        self.describe(|| {
+            format!(
+                "event #{}: thread {} ran unrecorded {:?} x{}",
+                index, observed.dettid, observed.op, observed.count
+            )
+        });
+    }
+
+    fn describe(&mut self, divergence: impl FnOnce() -> String) {
+        if self.divergences.len() < MAX_DIVERGENCES {
+            self.divergences.push(divergence());
+        }
+    }
+}
+
+impl fmt::Display for ReplayDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Fuzzy replay matched {} events exactly and {} approximately, skipped {} recorded \
+             events, and ran {} unrecorded events.",
+            self.exact, self.approximate, self.skipped, self.unmatched
+        )?;
+        for divergence in &self.divergences {
+            writeln!(f, "  {}", divergence)?;
+        }
+        let described = self.approximate + self.skipped + self.unmatched;
+        if described > self.divergences.len() as u64 {
+            writeln!(
+                f,
+                "  ... and {} more",
+                described - self.divergences.len() as u64
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reverie::syscalls::Sysno;
+
+    use super::*;
+    use crate::types::DetTid;
+    use crate::types::SyscallPhase;
+    use crate::types::ThreadIdentity;
+
+    #[test]
+    fn matches_loosely() {
+        let t3 = DetTid::from_raw(3);
+        let t4 = DetTid::from_raw(4);
+        let write = SchedEvent::syscall(t3, Sysno::write, SyscallPhase::Posthook);
+        assert_eq!(fuzzy_match(&write, &write), Some(Match::Exact));
+        let read = SchedEvent::syscall(t3, Sysno::read, SyscallPhase::Posthook);
+        assert_eq!(fuzzy_match(&read, &write), None);
+
+        let recorded = SchedEvent::branches(t3, 1000);
+        assert_eq!(
+            fuzzy_match(&SchedEvent::branches(t3, 1200), &recorded),
+            Some(Match::Approximate)
+        );
+        assert!(fuzzy_match(&SchedEvent::branches(t3, 1300), &recorded).is_none());
+        assert!(fuzzy_match(&SchedEvent::branches(t4, 1000), &recorded).is_none());
+
+        // The thread is now 4, but has the identity recorded for 3.
+        let identity = ThreadIdentity::root().child(0);
+        let recorded = recorded.with_identity(identity.clone());
+        let shifted = SchedEvent::branches(t4, 1000).with_identity(identity);
+        assert_eq!(fuzzy_match(&shifted, &recorded), Some(Match::Exact));
+    }
+
+    #[test]
+    fn skips_to_match() {
+        let t3 = DetTid::from_raw(3);
+        let t4 = DetTid::from_raw(4);
+        let cursor: ReplayCursor<SchedEvent> = vec![
+            SchedEvent::branches(t3, 100),
+            SchedEvent::syscall(t4, Sysno::read, SyscallPhase::Prehook),
+            SchedEvent::branches(t4, 100),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            find_match(&cursor, &SchedEvent::branches(t4, 110)),
+            Some((2, Match::Approximate))
+        );
+        assert_eq!(
+            find_match(&cursor, &SchedEvent::branches(t3, 100)),
+            Some((0, Match::Exact))
+        );
+        let write = SchedEvent::syscall(t4, Sysno::write, SyscallPhase::Prehook);
+        assert_eq!(find_match(&cursor, &write), None);
+
+        let mut divergence = ReplayDivergence::default();
+        divergence.skipped(0, cursor.peek().unwrap());
+        divergence.unmatched(1, &write);
+        assert_eq!(
+            divergence.to_string(),
+            "Fuzzy replay matched 0 events exactly and 0 approximately, skipped 1 recorded \
+             events, and ran 1 unrecorded events.\n  \
+             event #0: skipped recorded Branch x100 of thread 3\n  \
+             event #1: thread 4 ran unrecorded Syscall(write, Prehook) x1\n"
+        );
+    }
+}
@@ -0,0 +1,170 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Hooks which let tools built on detcore watch and steer the scheduler, without forking it.
+//!
+//! A hook is registered with [`register_hook`] before the container starts, and is then taken by
+//! the scheduler of the next run, which consults it on every turn:
+//!
+//! ```ignore
+//! struct NoTwoInARow(Option<DetTid>);
+//!
+//! impl SchedulerHook for NoTwoInARow {
+//!     fn pick(&mut self, _turn: u64, dettid: DetTid, _rsrcs: &Resources) -> Verdict {
+//!         if self.0 == Some(dettid) { Verdict::Veto } else { Verdict::Allow }
+//!     }
+//!     fn commit(&mut self, _turn: u64, dettid: DetTid, _rsrcs: &Resources) -> Option<String> {
+//!         self.0 = Some(dettid);
+//!         None
+//!     }
+//! }
+//!
+//! detcore::register_hook(Box::new(NoTwoInARow(None)));
+//! ```
+
+use std::fmt;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+use crate::resources::Resources;
+use crate::types::DetTid;
+
+/// What a hook thinks of the thread the scheduler picked for a turn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Let the thread take its turn.
+    Allow,
+    /// Skip the turn, and send the thread to the back of the run queue at its current priority.
+    /// It keeps its request, and is offered again when it next comes up.
+    Veto,
+}
+
+/// Callbacks into an external tool, at the scheduler's decision points.  Every method has a
+/// default which does nothing, so a hook implements only what it cares about.
+///
+/// The scheduler calls these while holding its lock, so they must be quick and must not call
+/// back into detcore.  They are called in the same order on every run, so a hook which decides
+/// deterministically keeps the run deterministic.
+pub trait SchedulerHook: Send {
+    /// The scheduler picked `dettid` to take turn `turn`, making the request `rsrcs`.
+    ///
+    /// A veto is ignored while a recorded schedule is replayed, because the recording alone decides
+    /// that schedule.  A hook which vetoes every runnable thread forever stalls the run.
+    fn pick(&mut self, _turn: u64, _dettid: DetTid, _rsrcs: &Resources) -> Verdict {
+        Verdict::Allow
+    }
+
+    /// Thread `dettid` committed turn `turn`, and is about to be granted `rsrcs`.  Any note
+    /// returned is written to the log alongside the commit.
+    fn commit(&mut self, _turn: u64, _dettid: DetTid, _rsrcs: &Resources) -> Option<String> {
+        None
+    }
+}
+
+/// The hooks registered with a scheduler.
+#[derive(Default)]
+pub struct Hooks(Vec<Box<dyn SchedulerHook>>);
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Hooks({})", self.0.len())
+    }
+}
+
+lazy_static! {
+    /// Hooks registered, but not yet taken by a scheduler.
+    static ref REGISTERED: Mutex<Vec<Box<dyn SchedulerHook>>> = Mutex::new(Vec::new());
+}
+
+/// Register a hook for the next scheduler to be created, which is the one of the next container
+/// run in this process.  Hooks are consulted in the order they were registered.
+pub fn register_hook(hook: Box<dyn SchedulerHook>) {
+    REGISTERED.lock().unwrap().push(hook);
+}
+
+impl Hooks {
+    /// Take all of the hooks registered so far.
+    pub fn take_registered() -> Self {
+        Hooks(std::mem::take(&mut *REGISTERED.lock().unwrap()))
+    }
+
+    /// Whether no hook is registered.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Ask every hook about the picked thread.  Every hook sees the pick, even after a veto.
+    pub fn pick(&mut self, turn: u64, dettid: DetTid, rsrcs: &Resources) -> Verdict {
+        let mut verdict = Verdict::Allow;
+        for hook in self.0.iter_mut() {
+            if hook.pick(turn, dettid, rsrcs) == Verdict::Veto {
+                verdict = Verdict::Veto;
+            }
+        }
+        verdict
+    }
+
+    /// Tell every hook about the commit, collecting their notes.
+    pub fn commit(&mut self, turn: u64, dettid: DetTid, rsrcs: &Resources) -> Vec<String> {
+        self.0
+            .iter_mut()
+            .filter_map(|hook| hook.commit(turn, dettid, rsrcs))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Vetoes every other pick of each thread, and notes each commit.
+    #[derive(Default)]
+    struct EveryOther {
+        picks: u64,
+    }
+
+    impl SchedulerHook for EveryOther {
+        fn pick(&mut self, _turn: u64, _dettid: DetTid, _rsrcs: &Resources) -> Verdict {
+            self.picks += 1;
+            if self.picks % 2 == 0 {
+                Verdict::Veto
+            } else {
+                Verdict::Allow
+            }
+        }
+
+        fn commit(&mut self, turn: u64, dettid: DetTid, _rsrcs: &Resources) -> Option<String> {
+            Some(format!("turn {} went to {}", turn, dettid))
+        }
+    }
+
+    /// Only watches.
+    struct Watcher;
+
+    impl SchedulerHook for Watcher {}
+
+    #[test]
+    fn hooks_veto_and_annotate() {
+        let dettid = DetTid::from_raw(3);
+        let rsrcs = Resources::new(dettid);
+
+        register_hook(Box::new(EveryOther::default()));
+        register_hook(Box::new(Watcher));
+        let mut hooks = Hooks::take_registered();
+        assert!(Hooks::take_registered().is_empty());
+
+        assert_eq!(hooks.pick(0, dettid, &rsrcs), Verdict::Allow);
+        assert_eq!(hooks.pick(1, dettid, &rsrcs), Verdict::Veto);
+        assert_eq!(hooks.pick(2, dettid, &rsrcs), Verdict::Allow);
+        assert_eq!(
+            hooks.commit(2, dettid, &rsrcs),
+            vec!["turn 2 went to 3".to_owned()]
+        );
+    }
+}
@@ -7,6 +7,7 @@
  */
 
 use std::collections::VecDeque;
+use std::fmt;
 use std::iter::FromIterator;
 
 /// This is a queue like data structure used when replaying schedule
@@ -14,12 +15,41 @@ use std::iter::FromIterator;
 /// Apart from being an [Iterator] is supports methods [peek] and [peek_nth] to be able to look ahead arbitrary number of events forward
 /// look ahead while replaying.
 /// all of supported operations have O(1) time complexity
-#[derive(Debug)]
 pub struct ReplayCursor<T> {
     inner_data: VecDeque<T>,
+    /// The items after those in `inner_data`, if they are only read as the cursor reaches them.
+    rest: Option<Box<dyn Iterator<Item = T> + Send>>,
+    /// How many items are read ahead of the cursor, when the rest are read lazily.
+    lookahead: usize,
 }
 
 impl<T> ReplayCursor<T> {
+    /// A cursor which reads the items from `iter` only as it reaches them, keeping just the next
+    /// `lookahead` in memory, so that only that many can be peeked at.
+    pub fn lazy(iter: impl Iterator<Item = T> + Send + 'static, lookahead: usize) -> Self {
+        let mut cursor = Self {
+            inner_data: VecDeque::with_capacity(lookahead),
+            rest: Some(Box::new(iter)),
+            lookahead,
+        };
+        cursor.read_ahead();
+        cursor
+    }
+
+    fn read_ahead(&mut self) {
+        if let Some(rest) = &mut self.rest {
+            while self.inner_data.len() < self.lookahead {
+                match rest.next() {
+                    Some(item) => self.inner_data.push_back(item),
+                    None => {
+                        self.rest = None;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
     /// peeks the following nth element from the top of the cursor
     pub fn peek_nth(&self, index: usize) -> Option<&T> {
         self.inner_data.get(index)
@@ -38,16 +68,29 @@ impl<T> Iterator for ReplayCursor<T> {
         if self.inner_data.is_empty() {
             None
         } else {
-            self.inner_data.pop_front()
+            let item = self.inner_data.pop_front();
+            self.read_ahead();
+            item
         }
     }
 }
 
+impl<T: fmt::Debug> fmt::Debug for ReplayCursor<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReplayCursor")
+            .field("inner_data", &self.inner_data)
+            .field("lazy", &self.rest.is_some())
+            .finish()
+    }
+}
+
 impl<T> FromIterator<T> for ReplayCursor<T> {
     fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
         let reverse_data: VecDeque<_> = iter.into_iter().collect();
         Self {
             inner_data: reverse_data,
+            rest: None,
+            lookahead: 0,
         }
     }
 }
@@ -74,6 +117,8 @@ mod tests {
     fn test_peek_empty() {
         let cursor = ReplayCursor::<usize> {
             inner_data: VecDeque::new(),
+            rest: None,
+            lookahead: 0,
         };
         assert_eq!(cursor.peek(), None);
     }
@@ -96,6 +141,8 @@ mod tests {
     fn test_peek_pair_empty() {
         let cursor: ReplayCursor<usize> = ReplayCursor {
             inner_data: VecDeque::new(),
+            rest: None,
+            lookahead: 0,
         };
         assert_eq!(peek_pair(&cursor), (None, None));
         assert_eq!(peek_pair(&cursor), (None, None));
@@ -108,6 +155,16 @@ mod tests {
         assert_eq!(result, vec![Some(1), Some(2), Some(3), None]);
     }
 
+    #[test]
+    fn test_lazy() {
+        let mut cursor = ReplayCursor::lazy(1..=5, 2);
+        assert_eq!(peek_pair(&cursor), (Some(&1), Some(&2)));
+        assert_eq!(cursor.peek_nth(2), None);
+        assert_eq!(cursor.next(), Some(1));
+        assert_eq!(cursor.peek_nth(1), Some(&3));
+        assert_eq!(cursor.collect::<Vec<_>>(), vec![2, 3, 4, 5]);
+    }
+
     #[test]
     fn test_peek_nth() {
         let cursor: ReplayCursor<usize> = vec![1, 2, 3].into_iter().collect();
@@ -12,6 +12,7 @@ use std::fmt;
 
 use nix::sys::signal::Signal;
 
+use crate::timers::TimerId;
 use crate::types::DetPid;
 use crate::types::DetTid;
 use crate::types::LogicalTime;
@@ -25,17 +26,17 @@ pub struct TimedEvents {
     // Inner btreeset is *always* non-empty:
     map: BTreeMap<LogicalTime, BTreeSet<TimedEvent>>,
 
-    // There is only one alarm allowed at a time per process, so we keep track of the current alarm
-    // for each process and replace it if any other is inserted.
-    alarm_times: BTreeMap<DetPid, LogicalTime>,
+    // There is only one pending expiration allowed at a time per process timer, so we keep track
+    // of the current one for each timer and replace it if any other is inserted.
+    alarm_times: BTreeMap<(DetPid, TimerId), LogicalTime>,
 }
 
 /// An event that occurs at a particular time in the execution, typically at an offset in the future.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum TimedEvent {
-    // An upcoming alarm, destined for particular pids, with a designated tid in that process (if it
-    // still exists).
-    AlarmEvt(DetPid, DetTid, Signal),
+    // An upcoming alarm (or other process timer expiration), destined for particular pids, with a
+    // designated tid in that process (if it still exists).
+    AlarmEvt(DetPid, TimerId, DetTid, Signal),
 
     /// A timed event on a particular thread (sleep, timeout, etc)
     ThreadEvt(DetTid),
@@ -45,7 +46,9 @@ impl fmt::Display for TimedEvent {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             TimedEvent::ThreadEvt(dt) => write!(f, "ThreadEvt({})", dt),
-            TimedEvent::AlarmEvt(dp, dt, sig) => write!(f, "AlarmEvt({},{},{})", dp, dt, sig),
+            TimedEvent::AlarmEvt(dp, id, dt, sig) => {
+                write!(f, "AlarmEvt({},{},{},{})", dp, id, dt, sig)
+            }
         }
     }
 }
@@ -61,19 +64,20 @@ impl TimedEvents {
         }
     }
 
-    // Return the last absolute alarm time for this pid, if any.
+    // Return the last absolute alarm time for this timer, if any.
     pub fn insert_alarm(
         &mut self,
         ns: LogicalTime,
         dp: DetPid,
+        id: TimerId,
         dt: DetTid,
         sig: Signal,
     ) -> Option<LogicalTime> {
-        let old = self.alarm_times.insert(dp, ns);
-        self.clear_old_alarm(old);
+        let old = self.alarm_times.insert((dp, id), ns);
+        self.clear_old_alarm(dp, id, old);
 
         let set = self.map.entry(ns).or_insert_with(BTreeSet::new);
-        let evt = TimedEvent::AlarmEvt(dp, dt, sig);
+        let evt = TimedEvent::AlarmEvt(dp, id, dt, sig);
         if !set.insert(evt) {
             panic!(
                 "TimedEvents::insert should not insert an alarm event which is *already* in the set: {}",
@@ -83,7 +87,7 @@ impl TimedEvents {
         old
     }
 
-    fn clear_old_alarm(&mut self, old: Option<LogicalTime>) {
+    fn clear_old_alarm(&mut self, dp: DetPid, id: TimerId, old: Option<LogicalTime>) {
         if let Some(time) = old {
             let set = self
                 .map
@@ -93,7 +97,7 @@ impl TimedEvents {
             // Could use a drain_filter here, but it is nightly only:
             let mut to_remove = None;
             for evt in set.iter() {
-                if matches!(evt, TimedEvent::AlarmEvt(_, _, _)) {
+                if matches!(evt, TimedEvent::AlarmEvt(dp2, id2, _, _) if *dp2 == dp && *id2 == id) {
                     assert!(to_remove.is_none());
                     to_remove = Some(*evt);
                 }
@@ -101,13 +105,17 @@ impl TimedEvents {
             if let Some(evt) = to_remove {
                 assert!(set.remove(&evt));
             }
+            // Cannot allow empty sets to remain:
+            if set.is_empty() {
+                self.map.remove(&time);
+            }
         }
     }
 
-    // Return the time of any previous alarm on this process.
-    pub fn remove_alarm(&mut self, dp: DetPid) -> Option<LogicalTime> {
-        let old = self.alarm_times.remove(&dp);
-        self.clear_old_alarm(old);
+    // Return the time of any previous alarm on this timer.
+    pub fn remove_alarm(&mut self, dp: DetPid, id: TimerId) -> Option<LogicalTime> {
+        let old = self.alarm_times.remove(&(dp, id));
+        self.clear_old_alarm(dp, id, old);
         old
     }
 
@@ -125,11 +133,15 @@ impl TimedEvents {
             let time_ns = *entry.key();
             if time_ns <= current_time {
                 let set = entry.get_mut();
-                let dettid = set.pop_first().expect("inner set cannot be empty");
+                let evt = set.pop_first().expect("inner set cannot be empty");
                 if set.is_empty() {
                     entry.remove();
                 }
-                Some((time_ns, dettid))
+                if let TimedEvent::AlarmEvt(dp, id, _, _) = evt {
+                    // The alarm is no longer pending:
+                    self.alarm_times.remove(&(dp, id));
+                }
+                Some((time_ns, evt))
             } else {
                 None
             }
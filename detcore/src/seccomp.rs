@@ -0,0 +1,545 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Emulating the seccomp filters installed by the guest (`--emulate-seccomp`).
+//!
+//! Installed in the kernel, a guest's filter would also judge the syscalls hermit injects on
+//! its behalf, and a `SECCOMP_RET_TRACE` would compete with our own interception.  Instead,
+//! we keep the filters here and run them ourselves, with a small classic-BPF interpreter,
+//! against each syscall the guest makes, before handling it.
+
+use std::sync::Arc;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// The largest filter program the kernel accepts (`BPF_MAXINSNS`).
+pub const MAX_INSNS: usize = 4096;
+
+/// The number of scratch memory words available to a program (`BPF_MEMWORDS`).
+const MEMWORDS: usize = 16;
+
+/// The size of `struct seccomp_data`, which is all a filter can load from.
+const SECCOMP_DATA_SIZE: u32 = 64;
+
+/// The `AUDIT_ARCH_*` value a filter sees for syscalls made with the native ABI.
+#[cfg(target_arch = "x86_64")]
+pub const AUDIT_ARCH: u32 = 0xc000_003e;
+/// The `AUDIT_ARCH_*` value a filter sees for syscalls made with the native ABI.
+#[cfg(target_arch = "aarch64")]
+pub const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// Filter return values (`SECCOMP_RET_*`).
+pub mod ret {
+    /// Kill the whole process.
+    pub const KILL_PROCESS: u32 = 0x8000_0000;
+    /// Kill the calling thread.
+    pub const KILL_THREAD: u32 = 0x0000_0000;
+    /// Send the caller a SIGSYS instead of running the syscall.
+    pub const TRAP: u32 = 0x0003_0000;
+    /// Fail the syscall with the errno in the data bits.
+    pub const ERRNO: u32 = 0x0005_0000;
+    /// Notify the user-space listener.
+    pub const USER_NOTIF: u32 = 0x7fc0_0000;
+    /// Notify the ptrace tracer.
+    pub const TRACE: u32 = 0x7ff0_0000;
+    /// Run the syscall, after logging it.
+    pub const LOG: u32 = 0x7ffc_0000;
+    /// Run the syscall.
+    pub const ALLOW: u32 = 0x7fff_0000;
+    /// The action bits of a return value.
+    pub const ACTION_FULL: u32 = 0xffff_0000;
+    /// The data bits of a return value.
+    pub const DATA: u32 = 0x0000_ffff;
+}
+
+// Classic BPF instruction classes and fields, from linux/filter.h.
+const BPF_LD: u16 = 0x00;
+const BPF_LDX: u16 = 0x01;
+const BPF_ST: u16 = 0x02;
+const BPF_STX: u16 = 0x03;
+const BPF_ALU: u16 = 0x04;
+const BPF_JMP: u16 = 0x05;
+const BPF_RET: u16 = 0x06;
+const BPF_MISC: u16 = 0x07;
+
+const BPF_IMM: u16 = 0x00;
+const BPF_ABS: u16 = 0x20;
+const BPF_MEM: u16 = 0x60;
+const BPF_LEN: u16 = 0x80;
+
+const BPF_ADD: u16 = 0x00;
+const BPF_SUB: u16 = 0x10;
+const BPF_MUL: u16 = 0x20;
+const BPF_DIV: u16 = 0x30;
+const BPF_OR: u16 = 0x40;
+const BPF_AND: u16 = 0x50;
+const BPF_LSH: u16 = 0x60;
+const BPF_RSH: u16 = 0x70;
+const BPF_NEG: u16 = 0x80;
+const BPF_MOD: u16 = 0x90;
+const BPF_XOR: u16 = 0xa0;
+
+const BPF_JA: u16 = 0x00;
+const BPF_JEQ: u16 = 0x10;
+const BPF_JGT: u16 = 0x20;
+const BPF_JGE: u16 = 0x30;
+const BPF_JSET: u16 = 0x40;
+
+const BPF_K: u16 = 0x00;
+const BPF_X: u16 = 0x08;
+const BPF_A: u16 = 0x10;
+
+const BPF_TAX: u16 = 0x00;
+const BPF_TXA: u16 = 0x80;
+
+/// One instruction of a classic BPF program, as in `struct sock_filter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SockFilter {
+    /// The opcode.
+    pub code: u16,
+    /// Jump offset if the condition holds.
+    pub jt: u8,
+    /// Jump offset if it does not.
+    pub jf: u8,
+    /// The immediate operand.
+    pub k: u32,
+}
+
+impl SockFilter {
+    /// Decode an instruction from the eight bytes it occupies in memory.
+    pub fn from_raw(raw: u64) -> Self {
+        let bytes = raw.to_ne_bytes();
+        SockFilter {
+            code: u16::from_ne_bytes([bytes[0], bytes[1]]),
+            jt: bytes[2],
+            jf: bytes[3],
+            k: u32::from_ne_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        }
+    }
+}
+
+/// What a filter is shown of a syscall, as in `struct seccomp_data`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeccompData {
+    /// The syscall number.
+    pub nr: i32,
+    /// The `AUDIT_ARCH_*` value of the syscall's ABI.
+    pub arch: u32,
+    /// The address just past the syscall instruction.
+    pub instruction_pointer: u64,
+    /// The syscall arguments.
+    pub args: [u64; 6],
+}
+
+impl SeccompData {
+    /// A 32-bit load at a (valid) offset into the structure, as laid out in memory.
+    fn load(&self, offset: u32) -> u32 {
+        let word = match offset {
+            0 => return self.nr as u32,
+            4 => return self.arch,
+            8 | 12 => self.instruction_pointer,
+            _ => self.args[(offset as usize - 16) / 8],
+        };
+        if offset & 4 == 0 {
+            word as u32
+        } else {
+            (word >> 32) as u32
+        }
+    }
+}
+
+/// The outcome of running a syscall past the filters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeccompAction {
+    /// Run the syscall.
+    Allow,
+    /// Run the syscall, and log it.
+    Log,
+    /// Fail the syscall with this errno.
+    Errno(i32),
+    /// Hand the syscall to a ptrace tracer, with this data for it.
+    Trace(u16),
+    /// Raise a SIGSYS, with this data as its `si_errno`.
+    Trap(u16),
+    /// Hand the syscall to the user-space listener.
+    UserNotif,
+    /// Kill the calling thread with SIGSYS.
+    KillThread,
+    /// Kill the whole process with SIGSYS.
+    KillProcess,
+    /// A syscall not permitted in strict mode, for which the kernel sends SIGKILL.
+    StrictViolation,
+}
+
+impl SeccompAction {
+    /// Interpret a filter's return value.
+    pub fn from_ret(value: u32) -> Self {
+        let data = (value & ret::DATA) as u16;
+        match value & ret::ACTION_FULL {
+            ret::ALLOW => SeccompAction::Allow,
+            ret::LOG => SeccompAction::Log,
+            // As the kernel does, clamp to MAX_ERRNO.
+            ret::ERRNO => SeccompAction::Errno(i32::from(data).min(4095)),
+            ret::TRACE => SeccompAction::Trace(data),
+            ret::TRAP => SeccompAction::Trap(data),
+            ret::USER_NOTIF => SeccompAction::UserNotif,
+            ret::KILL_THREAD => SeccompAction::KillThread,
+            // Unknown actions are treated as the most severe.
+            _ => SeccompAction::KillProcess,
+        }
+    }
+}
+
+/// The seccomp state of a process: strict mode, or the stack of filters it has installed.
+/// Inherited across fork and kept across execve.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct SeccompState {
+    /// Whether strict mode (`SECCOMP_MODE_STRICT`) is in force.
+    strict: bool,
+    /// The installed filters, oldest first.
+    filters: Vec<Arc<Vec<SockFilter>>>,
+}
+
+impl SeccompState {
+    /// The current mode, as returned by `prctl(PR_GET_SECCOMP)`.
+    pub fn mode(&self) -> i64 {
+        if self.strict {
+            libc::SECCOMP_MODE_STRICT as i64
+        } else if !self.filters.is_empty() {
+            libc::SECCOMP_MODE_FILTER as i64
+        } else {
+            libc::SECCOMP_MODE_DISABLED as i64
+        }
+    }
+
+    /// Whether any syscalls are restricted at all.
+    pub fn is_enabled(&self) -> bool {
+        self.strict || !self.filters.is_empty()
+    }
+
+    /// Enter strict mode.  Fails if filters are already installed.
+    pub fn set_strict(&mut self) -> bool {
+        if self.filters.is_empty() {
+            self.strict = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Install another (validated) filter.  Fails in strict mode.
+    pub fn add_filter(&mut self, prog: Vec<SockFilter>) -> bool {
+        if self.strict {
+            false
+        } else {
+            self.filters.push(Arc::new(prog));
+            true
+        }
+    }
+
+    /// Decide what happens to a syscall.  Every filter runs, and the most severe action wins;
+    /// of equally severe actions, that of the most recently installed filter.
+    pub fn check(&self, data: &SeccompData) -> SeccompAction {
+        if self.strict {
+            let allowed = [
+                libc::SYS_read,
+                libc::SYS_write,
+                libc::SYS_exit,
+                libc::SYS_rt_sigreturn,
+            ];
+            return if allowed.contains(&(data.nr as i64)) {
+                SeccompAction::Allow
+            } else {
+                SeccompAction::StrictViolation
+            };
+        }
+        let mut result = ret::ALLOW;
+        for filter in self.filters.iter().rev() {
+            let value = run(filter, data);
+            // The severity order is that of the action bits, taken as signed.
+            if ((value & ret::ACTION_FULL) as i32) < ((result & ret::ACTION_FULL) as i32) {
+                result = value;
+            }
+        }
+        SeccompAction::from_ret(result)
+    }
+}
+
+/// Check a program as the kernel would before accepting it as a filter: only the
+/// instructions seccomp permits, in-bounds loads and jumps, and a return at the end.
+pub fn validate(prog: &[SockFilter]) -> bool {
+    if prog.is_empty() || prog.len() > MAX_INSNS {
+        return false;
+    }
+    let in_bounds = |pc: usize, offset: u32| pc + 1 + (offset as usize) < prog.len();
+    let valid = prog.iter().enumerate().all(|(pc, insn)| {
+        let k = insn.k;
+        match insn.code {
+            c if c == BPF_LD | BPF_ABS => k < SECCOMP_DATA_SIZE && k & 3 == 0,
+            c if c == BPF_LD | BPF_LEN || c == BPF_LDX | BPF_LEN => true,
+            c if c == BPF_LD | BPF_IMM || c == BPF_LDX | BPF_IMM => true,
+            c if c == BPF_LD | BPF_MEM || c == BPF_LDX | BPF_MEM => (k as usize) < MEMWORDS,
+            BPF_ST | BPF_STX => (k as usize) < MEMWORDS,
+            c if c == BPF_RET | BPF_K || c == BPF_RET | BPF_A => true,
+            c if c == BPF_MISC | BPF_TAX || c == BPF_MISC | BPF_TXA => true,
+            c if c == BPF_ALU | BPF_NEG => true,
+            c if c & 0x07 == BPF_ALU && c & !0xff == 0 => match c & 0xf0 {
+                BPF_DIV | BPF_MOD => c & BPF_X != 0 || k != 0,
+                BPF_ADD | BPF_SUB | BPF_MUL | BPF_OR | BPF_AND | BPF_LSH | BPF_RSH | BPF_XOR => {
+                    true
+                }
+                _ => false,
+            },
+            c if c == BPF_JMP | BPF_JA => in_bounds(pc, k),
+            c if c & 0x07 == BPF_JMP && c & !0xff == 0 => match c & 0xf0 {
+                BPF_JEQ | BPF_JGT | BPF_JGE | BPF_JSET => {
+                    in_bounds(pc, insn.jt.into()) && in_bounds(pc, insn.jf.into())
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    });
+    valid && prog[prog.len() - 1].code & 0x07 == BPF_RET
+}
+
+/// Run a validated program against a syscall, returning the filter's verdict.
+fn run(prog: &[SockFilter], data: &SeccompData) -> u32 {
+    let mut a: u32 = 0;
+    let mut x: u32 = 0;
+    let mut mem = [0u32; MEMWORDS];
+    let mut pc = 0;
+    loop {
+        let insn = prog[pc];
+        let k = insn.k;
+        pc += 1;
+        match insn.code & 0x07 {
+            BPF_LD | BPF_LDX => {
+                let value = match insn.code & 0xe0 {
+                    BPF_ABS => data.load(k),
+                    BPF_LEN => SECCOMP_DATA_SIZE,
+                    BPF_MEM => mem[k as usize],
+                    _ => k,
+                };
+                if insn.code & 0x07 == BPF_LD {
+                    a = value
+                } else {
+                    x = value
+                }
+            }
+            BPF_ST => mem[k as usize] = a,
+            BPF_STX => mem[k as usize] = x,
+            BPF_ALU => {
+                let operand = if insn.code & BPF_X != 0 { x } else { k };
+                a = match insn.code & 0xf0 {
+                    BPF_ADD => a.wrapping_add(operand),
+                    BPF_SUB => a.wrapping_sub(operand),
+                    BPF_MUL => a.wrapping_mul(operand),
+                    // Dividing by a zero X register ends the program, returning 0.
+                    BPF_DIV if operand == 0 => return 0,
+                    BPF_DIV => a / operand,
+                    BPF_MOD if operand == 0 => return 0,
+                    BPF_MOD => a % operand,
+                    BPF_OR => a | operand,
+                    BPF_AND => a & operand,
+                    BPF_LSH => a.checked_shl(operand).unwrap_or(0),
+                    BPF_RSH => a.checked_shr(operand).unwrap_or(0),
+                    BPF_NEG => a.wrapping_neg(),
+                    _ => a ^ operand,
+                };
+            }
+            BPF_JMP => {
+                let operand = if insn.code & BPF_X != 0 { x } else { k };
+                let taken = match insn.code & 0xf0 {
+                    BPF_JA => {
+                        pc += k as usize;
+                        continue;
+                    }
+                    BPF_JEQ => a == operand,
+                    BPF_JGT => a > operand,
+                    BPF_JGE => a >= operand,
+                    _ => a & operand != 0,
+                };
+                pc += usize::from(if taken { insn.jt } else { insn.jf });
+            }
+            BPF_RET => return if insn.code & BPF_A != 0 { a } else { k },
+            _ => {
+                if insn.code & 0xf8 == BPF_TXA {
+                    a = x
+                } else {
+                    x = a
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stmt(code: u16, k: u32) -> SockFilter {
+        SockFilter {
+            code,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    fn jump(code: u16, k: u32, jt: u8, jf: u8) -> SockFilter {
+        SockFilter { code, jt, jf, k }
+    }
+
+    fn syscall(nr: i64, args: [u64; 6]) -> SeccompData {
+        SeccompData {
+            nr: nr as i32,
+            arch: AUDIT_ARCH,
+            instruction_pointer: 0x7fff_0000_1234,
+            args,
+        }
+    }
+
+    /// Deny `nr` with EPERM, allowing everything else.
+    fn deny(nr: i64) -> Vec<SockFilter> {
+        vec![
+            stmt(BPF_LD | BPF_ABS, 0),
+            jump(BPF_JMP | BPF_JEQ | BPF_K, nr as u32, 0, 1),
+            stmt(BPF_RET | BPF_K, ret::ERRNO | libc::EPERM as u32),
+            stmt(BPF_RET | BPF_K, ret::ALLOW),
+        ]
+    }
+
+    #[test]
+    fn validation() {
+        assert!(validate(&deny(libc::SYS_getpid)));
+        assert!(!validate(&[]));
+        // Must end in a return.
+        assert!(!validate(&[stmt(BPF_LD | BPF_ABS, 0)]));
+        // Loads beyond seccomp_data, or unaligned.
+        assert!(!validate(&[
+            stmt(BPF_LD | BPF_ABS, 64),
+            stmt(BPF_RET | BPF_A, 0)
+        ]));
+        assert!(!validate(&[
+            stmt(BPF_LD | BPF_ABS, 2),
+            stmt(BPF_RET | BPF_A, 0)
+        ]));
+        // A jump past the end.
+        assert!(!validate(&[
+            jump(BPF_JMP | BPF_JEQ | BPF_K, 0, 1, 0),
+            stmt(BPF_RET | BPF_A, 0),
+        ]));
+        // Division by a constant zero.
+        assert!(!validate(&[
+            stmt(BPF_ALU | BPF_DIV | BPF_K, 0),
+            stmt(BPF_RET | BPF_A, 0),
+        ]));
+        // Packet loads, which seccomp does not allow.
+        assert!(!validate(&[stmt(0x28, 0), stmt(BPF_RET | BPF_A, 0)]));
+    }
+
+    #[test]
+    fn single_filter() {
+        let mut state = SeccompState::default();
+        assert!(!state.is_enabled());
+        assert!(state.add_filter(deny(libc::SYS_getpid)));
+        assert_eq!(state.mode(), libc::SECCOMP_MODE_FILTER as i64);
+        assert_eq!(
+            state.check(&syscall(libc::SYS_getpid, [0; 6])),
+            SeccompAction::Errno(libc::EPERM)
+        );
+        assert_eq!(
+            state.check(&syscall(libc::SYS_getppid, [0; 6])),
+            SeccompAction::Allow
+        );
+        // No strict mode on top of filters.
+        assert!(!state.set_strict());
+    }
+
+    #[test]
+    fn most_severe_action_wins() {
+        let mut state = SeccompState::default();
+        let trap_all = vec![stmt(BPF_RET | BPF_K, ret::TRAP | 7)];
+        state.add_filter(trap_all);
+        state.add_filter(deny(libc::SYS_getpid));
+        assert_eq!(
+            state.check(&syscall(libc::SYS_getpid, [0; 6])),
+            SeccompAction::Trap(7)
+        );
+        state.add_filter(vec![stmt(BPF_RET | BPF_K, ret::KILL_PROCESS)]);
+        assert_eq!(
+            state.check(&syscall(libc::SYS_getppid, [0; 6])),
+            SeccompAction::KillProcess
+        );
+    }
+
+    #[test]
+    fn arguments_and_arithmetic() {
+        // Allow only if the high half of arg1, masked and shifted, equals 3, using scratch
+        // memory and the X register along the way.
+        let prog = vec![
+            stmt(BPF_LD | BPF_ABS, 28),
+            stmt(BPF_ALU | BPF_AND | BPF_K, 0xf0),
+            stmt(BPF_ALU | BPF_RSH | BPF_K, 4),
+            stmt(BPF_ST, 5),
+            stmt(BPF_LDX | BPF_MEM, 5),
+            stmt(BPF_LD | BPF_IMM, 3),
+            jump(BPF_JMP | BPF_JEQ | BPF_X, 0, 0, 1),
+            stmt(BPF_RET | BPF_K, ret::ALLOW),
+            stmt(BPF_RET | BPF_K, ret::KILL_THREAD),
+        ];
+        assert!(validate(&prog));
+        let mut state = SeccompState::default();
+        state.add_filter(prog);
+        assert_eq!(
+            state.check(&syscall(0, [0, 0x35_0000_0000, 0, 0, 0, 0])),
+            SeccompAction::Allow
+        );
+        assert_eq!(
+            state.check(&syscall(0, [0, 0x35, 0, 0, 0, 0])),
+            SeccompAction::KillThread
+        );
+    }
+
+    #[test]
+    fn division_by_zero_kills() {
+        let prog = vec![
+            stmt(BPF_LDX | BPF_IMM, 0),
+            stmt(BPF_ALU | BPF_DIV | BPF_X, 0),
+            stmt(BPF_RET | BPF_K, ret::ALLOW),
+        ];
+        assert!(validate(&prog));
+        assert_eq!(run(&prog, &syscall(0, [0; 6])), ret::KILL_THREAD);
+    }
+
+    #[test]
+    fn strict_mode() {
+        let mut state = SeccompState::default();
+        assert!(state.set_strict());
+        assert_eq!(
+            state.check(&syscall(libc::SYS_write, [0; 6])),
+            SeccompAction::Allow
+        );
+        assert_eq!(
+            state.check(&syscall(libc::SYS_getpid, [0; 6])),
+            SeccompAction::StrictViolation
+        );
+        assert!(!state.add_filter(deny(libc::SYS_getpid)));
+    }
+
+    #[test]
+    fn decodes_instructions() {
+        let raw = u64::from_ne_bytes([0x15, 0x00, 0x02, 0x03, 0x27, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            SockFilter::from_raw(raw),
+            jump(BPF_JMP | BPF_JEQ | BPF_K, 39, 2, 3)
+        );
+    }
+}
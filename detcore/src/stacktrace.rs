@@ -0,0 +1,258 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Stack traces of guest threads.
+//!
+//! These come from Reverie's unwinder, which reads the unwind tables of the guest's binaries.
+//! When it cannot unwind, e.g. through code built without unwind tables, we fall back to walking
+//! the chain of frame pointers, which is fast but only complete for code built with them.  Those
+//! frames are named from the symbol tables of the guest's binaries, which are parsed once per
+//! binary and kept for the rest of the run, across events and guest processes.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use goblin::elf::program_header;
+use goblin::elf::Elf;
+use lazy_static::lazy_static;
+use reverie::syscalls::Addr;
+use reverie::syscalls::MemoryAccess;
+use reverie::Guest;
+use reverie::Tool;
+use tracing::warn;
+
+use crate::procmaps;
+use crate::procmaps::MMapPath;
+use crate::procmaps::MemoryMap;
+
+/// The most frames the frame pointer chain is followed for, in case it loops.
+const MAX_FRAMES: usize = 256;
+
+lazy_static! {
+    /// The symbols of each binary seen so far, by its path and modification time, so that one
+    /// replaced during the run is parsed again.  `None` for those which could not be parsed.
+    static ref SYMBOLS: Mutex<HashMap<(PathBuf, SystemTime), Option<Arc<Symbols>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// The function symbols of a binary, and where its segments are loaded from, to name the code
+/// addresses in it.
+#[derive(Debug, Default, PartialEq)]
+struct Symbols {
+    /// The address, size and name of each function, sorted by address.
+    functions: Vec<(u64, u64, String)>,
+    /// The file offset, address and size of each loaded segment.
+    segments: Vec<(u64, u64, u64)>,
+}
+
+impl Symbols {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let elf = Elf::parse(bytes).ok()?;
+        let symtab = elf.syms.iter().map(|sym| (sym, &elf.strtab));
+        let dynsym = elf.dynsyms.iter().map(|sym| (sym, &elf.dynstrtab));
+        let mut functions: Vec<_> = symtab
+            .chain(dynsym)
+            .filter(|(sym, _)| sym.is_function() && sym.st_value != 0)
+            .filter_map(|(sym, strtab)| {
+                let name = strtab.get_at(sym.st_name)?;
+                Some((sym.st_value, sym.st_size, name.to_owned()))
+            })
+            .collect();
+        functions.sort();
+        functions.dedup_by_key(|function| function.0);
+        let segments = elf
+            .program_headers
+            .iter()
+            .filter(|ph| ph.p_type == program_header::PT_LOAD)
+            .map(|ph| (ph.p_offset, ph.p_vaddr, ph.p_filesz))
+            .collect();
+        Some(Symbols {
+            functions,
+            segments,
+        })
+    }
+
+    /// The function with the code at `offset` in the file, and how far into the function it is.
+    fn lookup(&self, offset: u64) -> Option<(&str, u64)> {
+        let (file_offset, addr, _) = self
+            .segments
+            .iter()
+            .find(|(start, _, size)| *start <= offset && offset < start + size)?;
+        let addr = offset - file_offset + addr;
+        let ix = self
+            .functions
+            .partition_point(|function| function.0 <= addr);
+        let (start, size, name) = self.functions.get(ix.checked_sub(1)?)?;
+        // A function without a size, e.g. one written in assembly, runs up to the next one.
+        if *size != 0 && addr >= start + size {
+            return None;
+        }
+        Some((name.as_str(), addr - start))
+    }
+}
+
+/// The symbols of the binary at `path`, which are only parsed the first time they are asked for.
+fn symbols(path: &Path) -> Option<Arc<Symbols>> {
+    let mtime = fs::metadata(path).and_then(|meta| meta.modified()).ok()?;
+    let key = (path.to_path_buf(), mtime);
+    if let Some(symbols) = SYMBOLS.lock().unwrap().get(&key) {
+        return symbols.clone();
+    }
+    // Parsed without holding the lock, which other threads' stack traces may need meanwhile.
+    let symbols = fs::read(path)
+        .ok()
+        .and_then(|bytes| Symbols::parse(&bytes))
+        .map(Arc::new);
+    SYMBOLS.lock().unwrap().insert(key, symbols.clone());
+    symbols
+}
+
+/// The stack trace of the guest thread, symbolized if possible, or `None` if it could not be read
+/// at all.
+pub async fn backtrace<G, T>(guest: &mut G) -> Option<String>
+where
+    G: Guest<T>,
+    T: Tool,
+{
+    if let Some(backtrace) = guest.backtrace() {
+        return Some(match backtrace.pretty() {
+            Ok(pbt) => pbt.to_string(),
+            Err(_) => backtrace.to_string(),
+        });
+    }
+    let frames = frame_pointer_chain(guest).await;
+    let maps = match procmaps::from_pid(guest.pid(), |map| map.perms.contains('x')) {
+        Ok(maps) => maps,
+        Err(err) => {
+            warn!("Could not read the memory maps of the guest: {}", err);
+            Vec::new()
+        }
+    };
+    let mut out = String::from("(unwound by frame pointers)\n");
+    for (i, ip) in frames.into_iter().enumerate() {
+        let name = describe_address(ip, &maps);
+        writeln!(out, "{:>4}: {:#018x} {}", i, ip, name).unwrap();
+    }
+    Some(out)
+}
+
+/// The instruction pointer, followed by the return address of each frame found by following the
+/// saved frame pointers up the stack.
+async fn frame_pointer_chain<G, T>(guest: &mut G) -> Vec<u64>
+where
+    G: Guest<T>,
+    T: Tool,
+{
+    let regs = guest.regs().await;
+    let memory = guest.memory();
+    let read = |addr: u64| {
+        Addr::<u64>::from_raw(addr as usize).and_then(|addr| memory.read_value(addr).ok())
+    };
+    let mut frames = vec![regs.rip];
+    let mut fp = regs.rbp;
+    while frames.len() < MAX_FRAMES && fp != 0 && fp % 8 == 0 {
+        // Each frame starts with the caller's frame pointer, followed by the return address.
+        let (next_fp, ret) = match (read(fp), read(fp + 8)) {
+            (Some(next_fp), Some(ret)) if ret != 0 => (next_fp, ret),
+            _ => break,
+        };
+        frames.push(ret);
+        // The stack grows down, so the callers' frames are above.
+        if next_fp <= fp {
+            break;
+        }
+        fp = next_fp;
+    }
+    frames
+}
+
+/// Name a code address by the binary it is in and its offset in that file, which is what
+/// `addr2line` takes, followed by the function it is in, if the binary has a symbol for it.
+fn describe_address(ip: u64, maps: &[MemoryMap]) -> String {
+    match maps
+        .iter()
+        .find(|map| map.address.0 <= ip && ip < map.address.1)
+    {
+        Some(map) => match &map.pathname {
+            MMapPath::Path(path) => {
+                let offset = ip - map.address.0 + map.offset;
+                let symbols = symbols(path);
+                let symbol = symbols
+                    .as_deref()
+                    .and_then(|symbols| symbols.lookup(offset));
+                match symbol {
+                    Some((name, delta)) => {
+                        format!("{}+{:#x} ({}+{:#x})", path.display(), offset, name, delta)
+                    }
+                    None => format!("{}+{:#x}", path.display(), offset),
+                }
+            }
+            _ => procmaps::display(map),
+        },
+        None => String::from("??"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn describes_addresses_by_file_offset() {
+        let map = MemoryMap {
+            address: (0x7f00_0000_0000, 0x7f00_0010_0000),
+            perms: String::from("r-xp"),
+            offset: 0x2000,
+            dev: (8, 1),
+            inode: 42,
+            pathname: MMapPath::Path(PathBuf::from("/usr/lib/libfoo.so")),
+        };
+        let maps = [map];
+        assert_eq!(
+            describe_address(0x7f00_0000_0123, &maps),
+            "/usr/lib/libfoo.so+0x2123"
+        );
+        assert_eq!(describe_address(0x1000, &maps), "??");
+    }
+
+    #[test]
+    fn looks_up_functions_by_file_offset() {
+        let symbols = Symbols {
+            functions: vec![
+                (0x1000, 0x20, String::from("foo")),
+                (0x1040, 0, String::from("bar")),
+            ],
+            segments: vec![(0x0, 0x0, 0x800), (0x800, 0x1000, 0x1000)],
+        };
+        assert_eq!(symbols.lookup(0x810), Some(("foo", 0x10)));
+        assert_eq!(symbols.lookup(0x830), None);
+        assert_eq!(symbols.lookup(0x900), Some(("bar", 0xc0)));
+        assert_eq!(symbols.lookup(0x100), None);
+        assert_eq!(symbols.lookup(0x2000), None);
+    }
+
+    #[test]
+    fn names_own_functions_once() {
+        let pid = reverie::Pid::from_raw(std::process::id() as i32);
+        let maps = procmaps::from_pid(pid, |map| map.perms.contains('x')).unwrap();
+        let ip = names_own_functions_once as usize as u64;
+        assert!(describe_address(ip, &maps).contains("names_own_functions_once"));
+
+        let exe = std::env::current_exe().unwrap();
+        let first = symbols(&exe).unwrap();
+        assert!(Arc::ptr_eq(&first, &symbols(&exe).unwrap()));
+    }
+}
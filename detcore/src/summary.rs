@@ -0,0 +1,419 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! A machine-readable summary of a run, for tools that collect metrics from hermit.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Debug;
+use std::time::Duration;
+
+use reverie::ExitStatus;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::config::IoUringMode;
+use crate::scheduler::fuzzy_replay::ReplayDivergence;
+use crate::tool_local::ThreadStats;
+use crate::types::DetPid;
+use crate::types::DetTid;
+
+/// What happened in one run, as reported by `--summary-json`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunSummary {
+    /// The exit code of the guest, if it exited normally.
+    pub exit_code: Option<i32>,
+
+    /// The signal that killed the guest, if it did not exit normally.
+    pub exit_signal: Option<String>,
+
+    /// The `--seed` of the run.
+    pub seed: u64,
+
+    /// The seed of the scheduler's choices, which defaults to `seed`.
+    pub sched_seed: u64,
+
+    /// How many turns the scheduler ran.
+    pub turns: u64,
+
+    /// A hash of which thread ran on each turn. Two runs with the same schedule have the
+    /// same hash.
+    pub schedule_hash: String,
+
+    /// Virtual time elapsed since the epoch, if time was virtualized.
+    pub virtual_time_ns: Option<u64>,
+
+    /// Each thread, by thread id.
+    pub threads: Vec<ThreadSummary>,
+
+    /// Syscalls made by all threads, by name.
+    pub syscall_counts: BTreeMap<String, u64>,
+
+    /// Ways in which the run was not fully deterministic.
+    pub nondeterminism_warnings: Vec<String>,
+
+    /// How well each source of nondeterminism the guest exercised was virtualized.
+    #[serde(default)]
+    pub scorecard: Scorecard,
+
+    /// How far the run strayed from the schedule it replayed, under `--fuzzy-replay`.
+    pub replay_divergence: Option<ReplayDivergence>,
+
+    /// Where the scheduler's wall-clock time went. Unlike the rest of the summary, this differs
+    /// from run to run.
+    #[serde(default)]
+    pub sched_overhead: SchedOverhead,
+}
+
+/// The wall-clock time the scheduler spent on each part of its turns, in nanoseconds. Only the
+/// scheduler's own work is overhead that hermit adds; the rest is time it waited on the guest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchedOverhead {
+    /// Waiting for every running thread to park, before choosing the next one.
+    pub quiesce_ns: u64,
+    /// Waiting for the chosen thread to make its request.
+    pub request_ns: u64,
+    /// Choosing the next thread and granting its request, holding the scheduler lock.
+    pub decide_ns: u64,
+    /// Backing off after turns that were skipped because no thread could run.
+    pub backoff_ns: u64,
+}
+
+impl SchedOverhead {
+    /// Counts `elapsed` towards one of the parts.
+    pub fn add(part: &mut u64, elapsed: Duration) {
+        *part += elapsed.as_nanos() as u64;
+    }
+}
+
+/// What one thread did during a run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThreadSummary {
+    /// The thread.
+    pub dettid: DetTid,
+    /// Its process.
+    pub detpid: DetPid,
+    /// How many syscalls it made.
+    pub syscalls: u64,
+    /// How many signals it received.
+    pub signals: u64,
+    /// How many timeslices it completed.
+    pub timeslices: u64,
+    /// Its syscalls, by name.
+    pub syscall_counts: BTreeMap<String, u64>,
+}
+
+impl ThreadSummary {
+    /// Summarizes the final stats of a thread.
+    pub fn new(dettid: DetTid, detpid: DetPid, stats: &ThreadStats) -> Self {
+        ThreadSummary {
+            dettid,
+            detpid,
+            syscalls: stats.syscall_count,
+            signals: stats.signal_count,
+            timeslices: stats.timeslice_count,
+            syscall_counts: stats.syscall_counts.clone(),
+        }
+    }
+}
+
+impl RunSummary {
+    /// Adds a thread, counting its syscalls towards the totals.
+    pub fn add_thread(&mut self, thread: ThreadSummary) {
+        for (name, count) in &thread.syscall_counts {
+            *self.syscall_counts.entry(name.clone()).or_insert(0) += count;
+        }
+        self.threads.push(thread);
+    }
+
+    /// Records how the guest exited.
+    pub fn set_exit_status(&mut self, status: ExitStatus) {
+        match status {
+            ExitStatus::Exited(code) => self.exit_code = Some(code),
+            ExitStatus::Signaled(sig, _) => self.exit_signal = Some(sig.to_string()),
+        }
+    }
+
+    /// Describes each way in which this run behaved differently from `reference`, as far as
+    /// their summaries tell. Empty if they behaved the same.
+    pub fn differences(&self, reference: &RunSummary) -> Vec<String> {
+        let mut differences = Vec::new();
+        let mut compare = |what: &str, this: &dyn Debug, that: &dyn Debug| {
+            let (this, that) = (format!("{:?}", this), format!("{:?}", that));
+            if this != that {
+                differences.push(format!("{}: {} (reference: {})", what, this, that));
+            }
+        };
+        compare("exit code", &self.exit_code, &reference.exit_code);
+        compare("exit signal", &self.exit_signal, &reference.exit_signal);
+        compare("seed", &self.seed, &reference.seed);
+        compare("sched seed", &self.sched_seed, &reference.sched_seed);
+        compare("turns", &self.turns, &reference.turns);
+        compare(
+            "schedule hash",
+            &self.schedule_hash,
+            &reference.schedule_hash,
+        );
+        compare(
+            "virtual time (ns)",
+            &self.virtual_time_ns,
+            &reference.virtual_time_ns,
+        );
+        compare("threads", &self.threads.len(), &reference.threads.len());
+        for (this, that) in self.threads.iter().zip(&reference.threads) {
+            let counts = |t: &ThreadSummary| (t.dettid, t.syscalls, t.signals, t.timeslices);
+            compare(
+                "thread (tid, syscalls, signals, timeslices)",
+                &counts(this),
+                &counts(that),
+            );
+        }
+        let names: std::collections::BTreeSet<_> = self
+            .syscall_counts
+            .keys()
+            .chain(reference.syscall_counts.keys())
+            .collect();
+        for name in names {
+            compare(
+                &format!("{} syscalls", name),
+                &self.syscall_counts.get(name).unwrap_or(&0),
+                &reference.syscall_counts.get(name).unwrap_or(&0),
+            );
+        }
+        differences
+    }
+}
+
+/// The settings which leave part of a run up to the host.
+pub fn nondeterminism_warnings(cfg: &Config) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if !cfg.sequentialize_threads {
+        warnings.push("threads are not sequentialized, so the host orders them".to_owned());
+    }
+    if !cfg.virtualize_time {
+        warnings.push("time is not virtualized".to_owned());
+    }
+    if !cfg.virtualize_metadata {
+        warnings.push("file metadata is not virtualized".to_owned());
+    }
+    if cfg.io_uring == IoUringMode::Passthrough {
+        warnings.push("io_uring operations are passed through to the host".to_owned());
+    }
+    if cfg.debug_externalize_sockets {
+        warnings.push("sockets are externalized".to_owned());
+    }
+    warnings
+}
+
+/// How much of one source of nondeterminism hermit took over from the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Virtualization {
+    /// The guest saw only what hermit decided.
+    Full,
+    /// Some of it was left up to the host.
+    Partial,
+    /// All of it was left up to the host.
+    PassedThrough,
+}
+
+impl fmt::Display for Virtualization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Virtualization::Full => write!(f, "fully virtualized"),
+            Virtualization::Partial => write!(f, "partially virtualized"),
+            Virtualization::PassedThrough => write!(f, "passed through"),
+        }
+    }
+}
+
+/// The syscalls by which the guest exercises each source of nondeterminism.
+const TIME_SYSCALLS: &[&str] = &[
+    "clock_gettime",
+    "clock_getres",
+    "clock_nanosleep",
+    "gettimeofday",
+    "nanosleep",
+    "time",
+    "times",
+];
+const RNG_SYSCALLS: &[&str] = &["getrandom"];
+const NETWORK_SYSCALLS: &[&str] = &[
+    "accept", "accept4", "bind", "connect", "listen", "recvfrom", "recvmmsg", "recvmsg",
+    "sendmmsg", "sendmsg", "sendto", "socket",
+];
+const METADATA_SYSCALLS: &[&str] = &[
+    "fstat",
+    "getdents",
+    "getdents64",
+    "lstat",
+    "newfstatat",
+    "stat",
+    "statx",
+];
+
+/// How well each source of nondeterminism was virtualized, for those the guest exercised. The
+/// others are `None`. A run which exercises nothing that is not fully virtualized should be
+/// reproducible, so this tells where to look when `hermit verify` finds that it is not.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Scorecard {
+    /// Clocks and sleeps.
+    pub time: Option<Virtualization>,
+    /// Random numbers from the kernel.
+    pub rng: Option<Virtualization>,
+    /// The interleaving of threads, if there were several.
+    pub scheduling: Option<Virtualization>,
+    /// Sockets.
+    pub network: Option<Virtualization>,
+    /// File metadata, such as inodes and modification times.
+    pub filesystem_metadata: Option<Virtualization>,
+}
+
+impl Scorecard {
+    /// Scores a run with the given settings, which made the given syscalls on this many threads.
+    pub fn new(cfg: &Config, syscall_counts: &BTreeMap<String, u64>, threads: usize) -> Self {
+        let exercised =
+            |names: &[&str]| names.iter().any(|name| syscall_counts.contains_key(*name));
+        let either = |full: bool| {
+            if full {
+                Virtualization::Full
+            } else {
+                Virtualization::PassedThrough
+            }
+        };
+        let network = if !cfg.sequentialize_threads || cfg.debug_externalize_sockets {
+            Virtualization::PassedThrough
+        } else if cfg.replay_network_from.is_some() || cfg.loopback_networking {
+            Virtualization::Full
+        } else {
+            // Local sockets are deterministic, but what external peers send, and when, is not.
+            Virtualization::Partial
+        };
+        Scorecard {
+            time: Some(either(cfg.virtualize_time)).filter(|_| exercised(TIME_SYSCALLS)),
+            rng: Some(Virtualization::Full).filter(|_| exercised(RNG_SYSCALLS)),
+            scheduling: Some(either(cfg.sequentialize_threads)).filter(|_| threads > 1),
+            network: Some(network).filter(|_| exercised(NETWORK_SYSCALLS)),
+            filesystem_metadata: Some(either(cfg.virtualize_metadata))
+                .filter(|_| exercised(METADATA_SYSCALLS)),
+        }
+    }
+}
+
+impl fmt::Display for Scorecard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Determinism scorecard:")?;
+        for (name, score) in [
+            ("time", self.time),
+            ("rng", self.rng),
+            ("scheduling", self.scheduling),
+            ("network", self.network),
+            ("filesystem metadata", self.filesystem_metadata),
+        ] {
+            match score {
+                Some(score) => writeln!(f, "  {:<20} {}", name, score)?,
+                None => writeln!(f, "  {:<20} not exercised", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn totals_syscalls_across_threads() {
+        let mut stats = ThreadStats::new();
+        stats.count_syscall("read");
+        stats.count_syscall("read");
+        stats.count_syscall("write");
+
+        let mut summary = RunSummary::default();
+        summary.add_thread(ThreadSummary::new(
+            DetTid::from_raw(3),
+            DetPid::from_raw(3),
+            &stats,
+        ));
+        summary.add_thread(ThreadSummary::new(
+            DetTid::from_raw(4),
+            DetPid::from_raw(3),
+            &stats,
+        ));
+        summary.set_exit_status(ExitStatus::Exited(2));
+
+        assert_eq!(summary.threads[1].syscalls, 3);
+        assert_eq!(summary.syscall_counts["read"], 4);
+        assert_eq!(summary.syscall_counts["write"], 2);
+        assert_eq!(summary.exit_code, Some(2));
+        assert_eq!(summary.exit_signal, None);
+    }
+
+    #[test]
+    fn describes_differences() {
+        let mut stats = ThreadStats::new();
+        stats.count_syscall("read");
+        let mut reference = RunSummary::default();
+        reference.add_thread(ThreadSummary::new(
+            DetTid::from_raw(3),
+            DetPid::from_raw(3),
+            &stats,
+        ));
+        reference.set_exit_status(ExitStatus::Exited(0));
+        assert!(reference.differences(&reference).is_empty());
+
+        let mut summary = reference.clone();
+        summary.exit_code = Some(1);
+        *summary.syscall_counts.get_mut("read").unwrap() += 1;
+        summary.syscall_counts.insert("write".to_owned(), 1);
+        assert_eq!(
+            summary.differences(&reference),
+            vec![
+                "exit code: Some(1) (reference: Some(0))",
+                "read syscalls: 2 (reference: 1)",
+                "write syscalls: 1 (reference: 0)",
+            ]
+        );
+    }
+
+    #[test]
+    fn scores_what_was_exercised() {
+        let mut cfg = Config {
+            sequentialize_threads: true,
+            virtualize_time: true,
+            virtualize_metadata: false,
+            ..Default::default()
+        };
+        let mut counts = BTreeMap::new();
+        counts.insert("clock_gettime".to_owned(), 3);
+        counts.insert("newfstatat".to_owned(), 1);
+        counts.insert("connect".to_owned(), 1);
+
+        let scorecard = Scorecard::new(&cfg, &counts, 1);
+        assert_eq!(scorecard.time, Some(Virtualization::Full));
+        assert_eq!(scorecard.rng, None);
+        assert_eq!(scorecard.scheduling, None);
+        assert_eq!(scorecard.network, Some(Virtualization::Partial));
+        assert_eq!(
+            scorecard.filesystem_metadata,
+            Some(Virtualization::PassedThrough)
+        );
+        assert!(
+            scorecard
+                .to_string()
+                .contains("  rng                  not exercised\n")
+        );
+
+        cfg.sequentialize_threads = false;
+        let scorecard = Scorecard::new(&cfg, &counts, 2);
+        assert_eq!(scorecard.scheduling, Some(Virtualization::PassedThrough));
+        assert_eq!(scorecard.network, Some(Virtualization::PassedThrough));
+    }
+}
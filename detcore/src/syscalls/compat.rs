@@ -0,0 +1,220 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! The syscalls of 32-bit guests (see `crate::compat`).
+//!
+//! Those which create and end threads and processes, or exec, keep the same bookkeeping as
+//! their native counterparts, and those which read the time or take randomness are made
+//! deterministic.  The rest are passed through to the kernel as they are, as possibly blocking
+//! external calls, each of which is a scheduler point, and are reported by `--audit-syscalls`.
+//!
+//! Passing reverie's (native) decoding of a syscall back to the kernel runs the original
+//! syscall, with the registers as the guest left them.  Nothing else can be injected into a
+//! 32-bit process, which is why:
+//!  - the seccomp filters it installs are not emulated, but installed in the kernel, and
+//!  - under sequentialization, a `vfork`, or a `clone` with `CLONE_VFORK`, fails with ENOSYS,
+//!    as the kernel would otherwise hold the parent until the child execs, while the child
+//!    waits for the parent to register it with the scheduler.  Only `clone3` keeps its flags in
+//!    memory, where `CLONE_VFORK` can be taken out of them, as for native guests.
+//!
+//! Only with every syscall intercepted (`--backend=ptrace`, `--emulate-seccomp`, or in debug
+//! builds) are all of a 32-bit guest's syscalls seen here.  Otherwise those whose compat
+//! numbers are not among the native ones subscribed to run unseen.
+
+use reverie::syscalls::Addr;
+use reverie::syscalls::AddrMut;
+use reverie::syscalls::CloneFlags;
+use reverie::syscalls::Errno;
+use reverie::syscalls::MemoryAccess;
+use reverie::syscalls::Syscall;
+use reverie::Error;
+use reverie::Guest;
+use tracing::warn;
+
+use crate::audit::Imperfection;
+use crate::compat::encode_time_fields;
+use crate::compat::CompatAbi;
+use crate::compat::CompatCall;
+use crate::compat::CompatOp;
+use crate::detlog;
+use crate::record_or_replay::RecordOrReplay;
+use crate::resources::Permission;
+use crate::resources::ResourceID;
+use crate::tool_global::audit_syscall;
+use crate::tool_global::create_child_thread;
+use crate::tool_global::fill_guest_random;
+use crate::tool_global::resource_request;
+use crate::tool_global::thread_observe_time;
+use crate::tool_local::Detcore;
+use crate::types::DetTid;
+
+impl<T: RecordOrReplay> Detcore<T> {
+    /// A syscall made by a thread of a 32-bit process, which reverie decoded as `call`, as if it
+    /// were a native one.
+    pub(crate) async fn handle_compat_syscall<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        abi: CompatAbi,
+        call: Syscall,
+    ) -> Result<i64, Error> {
+        let compat = CompatCall::decode(abi, &guest.regs().await);
+        detlog!(
+            "[detcore, dtid {}] inbound {} = ?, args {:x?}",
+            guest.thread_state().dettid,
+            compat.name(),
+            compat.args
+        );
+        match compat.op() {
+            Some(CompatOp::Exit) => self.handle_compat_exit(guest, call, false).await,
+            Some(CompatOp::ExitGroup) => self.handle_compat_exit(guest, call, true).await,
+            Some(op @ (CompatOp::Fork | CompatOp::Vfork | CompatOp::Clone | CompatOp::Clone3)) => {
+                self.handle_compat_clone(guest, op, &compat, call).await
+            }
+            Some(CompatOp::Execve | CompatOp::Execveat) => self.exec_with(guest, call).await,
+            Some(op) if op.reads_time() && self.cfg.virtualize_time && !compat.is_cpu_clock() => {
+                self.handle_compat_time(guest, op, &compat, call).await
+            }
+            Some(CompatOp::Getrandom) => {
+                let mut buf = vec![0; compat.args[1] as usize];
+                fill_guest_random(guest, &mut buf).await;
+                let addr = AddrMut::from_raw(compat.args[0] as usize).ok_or(Errno::EFAULT)?;
+                Ok(guest.memory().write(addr, &buf)? as i64)
+            }
+            _ => {
+                if self.cfg.audit_syscalls {
+                    audit_syscall(guest, compat.name(), Imperfection::PassedThrough).await;
+                }
+                if self.cfg.sequentialize_threads && !self.cfg.recordreplay_modes {
+                    self.record_or_replay_blocking(guest, call).await
+                } else {
+                    Ok(self.record_or_replay(guest, call).await?)
+                }
+            }
+        }
+    }
+
+    /// exit and exit_group.  A 32-bit process registers its robust futexes with the kernel,
+    /// which releases them.
+    async fn handle_compat_exit<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: Syscall,
+        group: bool,
+    ) -> Result<i64, Error> {
+        let request = guest
+            .thread_state()
+            .mk_request(ResourceID::Exit(group), Permission::RW);
+        resource_request(guest, request).await;
+        // It's ok here that we skip running the posthook:
+        guest.tail_inject(call).await
+    }
+
+    /// fork, vfork, clone and clone3, as `handle_clone_family` does for native guests.
+    async fn handle_compat_clone<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        op: CompatOp,
+        compat: &CompatCall,
+        call: Syscall,
+    ) -> Result<i64, Error> {
+        // A clone3's `struct clone_args` starts with its flags, followed by the pidfd and child
+        // tid addresses, each 64 bits wide in every ABI.
+        let clone_args = compat.args[0] as usize;
+        let (flags, ctid) = match op {
+            CompatOp::Fork => (CloneFlags::empty(), 0),
+            CompatOp::Vfork => (CloneFlags::CLONE_VM | CloneFlags::CLONE_VFORK, 0),
+            CompatOp::Clone => (
+                CloneFlags::from_bits_truncate(compat.args[0] as _),
+                compat.clone_child_tid() as usize,
+            ),
+            _ => {
+                let flags = Addr::<u64>::from_raw(clone_args).ok_or(Errno::EFAULT)?;
+                let ctid = Addr::<u64>::from_raw(clone_args + 16).ok_or(Errno::EFAULT)?;
+                (
+                    CloneFlags::from_bits_truncate(guest.memory().read_value(flags)? as _),
+                    guest.memory().read_value(ctid)? as usize,
+                )
+            }
+        };
+
+        let vfork = flags.contains(CloneFlags::CLONE_VFORK) && self.cfg.sequentialize_threads;
+        if vfork && op != CompatOp::Clone3 {
+            warn!(
+                "[dtid {}] {} with CLONE_VFORK, which cannot be sequentialized, failing with ENOSYS",
+                guest.thread_state().dettid,
+                compat.name()
+            );
+            return Err(Errno::ENOSYS.into());
+        }
+
+        let ts = guest.thread_state_mut();
+        assert_eq!(ts.clone_flags, None);
+        ts.clone_flags = Some(flags);
+        let maybe_res = if vfork {
+            let vfork_bit = CloneFlags::CLONE_VFORK.bits() as u64;
+            let flags_addr = AddrMut::<u64>::from_raw(clone_args).ok_or(Errno::EFAULT)?;
+            let raw_flags = guest
+                .memory()
+                .read_value(Addr::<u64>::from_raw(clone_args).unwrap())?;
+            guest
+                .memory()
+                .write_value(flags_addr, &(raw_flags & !vfork_bit))?;
+            let res = guest.inject(call).await;
+            guest.memory().write_value(flags_addr, &raw_flags)?;
+            res
+        } else {
+            guest.inject(call).await
+        };
+        guest.thread_state_mut().clone_flags = None; // Unset, now that it has been read by the child.
+
+        let child_dettid = DetTid::from_raw(maybe_res? as i32);
+        create_child_thread(guest, child_dettid, ctid, Some(flags)).await;
+        if vfork {
+            Self::wait_for_vfork_child(guest, child_dettid).await;
+        }
+        Ok(child_dettid.as_raw() as i64)
+    }
+
+    /// time, gettimeofday, clock_gettime and clock_gettime64, with the layouts of the 32-bit
+    /// ABI.  Like their native counterparts, they report global virtual time.
+    async fn handle_compat_time<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        op: CompatOp,
+        compat: &CompatCall,
+        call: Syscall,
+    ) -> Result<i64, Error> {
+        let now = thread_observe_time(guest).await;
+        let secs = now.as_secs() as i64;
+        // clock_gettime64 takes a 64-bit `struct timespec`, whatever the ABI's `time_t`.
+        let size = match op {
+            CompatOp::ClockGettime64 => 8,
+            _ => compat.abi.time_size(),
+        };
+        let (ret, addr, fields) = match op {
+            CompatOp::Time => (secs, compat.args[0], vec![secs]),
+            CompatOp::Gettimeofday => {
+                // The timezone, if asked for, comes from the kernel.
+                let ret = self.record_or_replay(guest, call).await?;
+                (ret, compat.args[0], vec![secs, now.subsec_micros() as i64])
+            }
+            _ => {
+                if compat.args[1] == 0 {
+                    return Err(Errno::EFAULT.into());
+                }
+                (0, compat.args[1], vec![secs, now.subsec_nanos() as i64])
+            }
+        };
+        if let Some(addr) = AddrMut::<u8>::from_raw(addr as usize) {
+            guest
+                .memory()
+                .write(addr, &encode_time_fields(size, &fields))?;
+        }
+        Ok(ret)
+    }
+}
@@ -14,9 +14,12 @@ use std::hash::Hasher;
 use std::net::Ipv4Addr;
 use std::net::Ipv6Addr;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
 
 use nix::fcntl::OFlag;
-use rand::Rng;
+use nix::sys::eventfd::EfdFlags;
+use nix::sys::signalfd::SfdFlags;
 use reverie::syscalls;
 use reverie::syscalls::family::StatFamily;
 use reverie::syscalls::Addr;
@@ -28,7 +31,11 @@ use reverie::syscalls::ReadAddr;
 use reverie::syscalls::SockFlag;
 use reverie::syscalls::StatPtr;
 use reverie::syscalls::Syscall;
+use reverie::syscalls::SyscallArgs;
+use reverie::syscalls::SyscallInfo;
+use reverie::syscalls::Sysno;
 use reverie::syscalls::Timespec;
+use reverie::syscalls::Whence;
 use reverie::Error;
 use reverie::Guest;
 use reverie::Stack;
@@ -45,10 +52,14 @@ use crate::resources::Permission;
 use crate::resources::ResourceID;
 use crate::scheduler::runqueue::LAST_PRIORITY;
 use crate::stat::*;
+use crate::timers::TimerfdState;
 use crate::tool_global::*;
 use crate::tool_local::Detcore;
 use crate::types::*;
 
+/// The block size reported with `--normalize-stat`.
+const NORMALIZED_BLKSIZE: i64 = 4096;
+
 /// A conversion from SOCK_* flags to O_* flags which makes unsafe (but checked during testing) assumptions.
 fn oflag_from_sock_bits(s_bits: i32) -> OFlag {
     // An otherwise unsafe "cast" which leans on the `linux_flags_assumptions` below.
@@ -90,7 +101,7 @@ impl<T: RecordOrReplay> Detcore<T> {
     }
 
     // helper function to track a new file descriptor.
-    async fn add_fd<G: Guest<Self>>(
+    pub(crate) async fn add_fd<G: Guest<Self>>(
         &self,
         guest: &mut G,
         fd: RawFd,
@@ -123,6 +134,10 @@ impl<T: RecordOrReplay> Detcore<T> {
         match res {
             Ok(fd) => {
                 let fd = fd as RawFd;
+                if guest.config().virtualize_time && !guest.config().recordreplay_modes {
+                    self.virtualize_proc_stat(guest, &path, fd, call.flags())
+                        .await?;
+                }
                 let fd_type = path.to_str().map_or(FdType::Regular, |fname| {
                     if fname == "/dev/random" || fname == "/dev/urandom" {
                         FdType::Rng
@@ -176,9 +191,29 @@ impl<T: RecordOrReplay> Detcore<T> {
             return Ok(res);
         }
 
-        let (fd_type, resource) = guest
-            .thread_state_mut()
-            .with_detfd(call.fd(), |detfd| (detfd.ty, detfd.resource.clone()))?;
+        let (fd_type, resource, timer, net, nonblocking) =
+            guest.thread_state_mut().with_detfd(call.fd(), |detfd| {
+                (
+                    detfd.ty,
+                    detfd.resource.clone(),
+                    detfd.timer.clone(),
+                    detfd.net.clone(),
+                    detfd.is_nonblocking(),
+                )
+            })?;
+
+        if let Some(timer) = timer {
+            return self
+                .read_virtual_timerfd(guest, call, timer, nonblocking)
+                .await;
+        }
+
+        if let Some(stream) = net {
+            let bufs = [(call.buf().map_or(0, |addr| addr.as_raw()), call.len())];
+            return self
+                .external_recv(guest, stream, call, &bufs, nonblocking)
+                .await;
+        }
 
         if let Some(resource) = resource {
             let request = guest.thread_state().mk_request(resource, Permission::R);
@@ -190,10 +225,7 @@ impl<T: RecordOrReplay> Detcore<T> {
                 trace!("Read call RNG fd {}, simulating...", call.fd());
                 let remote_buf = call.buf().ok_or(Errno::EFAULT)?;
                 let mut local_buf: Vec<u8> = vec![0; call.len()];
-                guest
-                    .thread_state_mut()
-                    .thread_prng()
-                    .fill(local_buf.as_mut_slice());
+                fill_guest_random(guest, local_buf.as_mut_slice()).await;
                 let n = guest.memory().write(remote_buf, &local_buf)?;
                 if cfg!(debug_assertions) {
                     let mut hasher = DefaultHasher::new();
@@ -214,9 +246,7 @@ impl<T: RecordOrReplay> Detcore<T> {
                     Ok(self.record_or_replay(guest, call).await?)
                 }
             }
-            FdType::Signalfd
-            | FdType::Eventfd
-            | FdType::Timerfd
+            FdType::Timerfd
             | FdType::Memfd
             | FdType::Pidfd
             | FdType::Userfaultfd => {
@@ -225,7 +255,9 @@ impl<T: RecordOrReplay> Detcore<T> {
                 Ok(self.record_or_replay(guest, call).await?)
             }
 
-            FdType::Socket | FdType::Pipe => {
+            FdType::Inotify => self.read_inotify(guest, call).await,
+            FdType::Secretmem => Err(Errno::EINVAL.into()),
+            FdType::Socket | FdType::Pipe | FdType::Signalfd | FdType::Eventfd => {
                 trace!(
                     "Possibly blocking read call on {:?} fd {}",
                     fd_type,
@@ -235,9 +267,33 @@ impl<T: RecordOrReplay> Detcore<T> {
             }
         };
         resource_release_all(guest).await;
+        if fd_type == FdType::Eventfd && res.is_ok() {
+            // Taking from the counter may unblock writers.
+            self.wakeup_sched_point(guest).await;
+        }
         res
     }
 
+    /// Read events from an inotify instance, at a point chosen by the scheduler, and with
+    /// deterministic rename cookies.
+    async fn read_inotify<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Read,
+    ) -> Result<i64, Error> {
+        let n = self.execute_nonblockable_fd_syscall(guest, call).await?;
+        let cookies = guest
+            .thread_state()
+            .with_detfd(call.fd(), |detfd| detfd.inotify.clone())?;
+        if let (Some(cookies), Some(buf)) = (cookies, call.buf()) {
+            let mut events = vec![0; n as usize];
+            guest.memory().read_exact(buf, &mut events)?;
+            cookies.lock().unwrap().renumber(&mut events);
+            guest.memory().write_exact(buf, &events)?;
+        }
+        Ok(n)
+    }
+
     /// Helper for performing a deterministic read that retries until it gets all its
     /// bytes.
     async fn deterministic_read<G: Guest<Self>>(
@@ -287,9 +343,22 @@ impl<T: RecordOrReplay> Detcore<T> {
         guest: &mut G,
         mut call: syscalls::Write,
     ) -> Result<i64, Error> {
-        let (resource, raw_ino) = guest.thread_state().with_detfd(call.fd(), |detfd| {
-            (detfd.resource.clone(), detfd.stat.map(|x| x.inode))
-        })?;
+        let (fd_type, resource, raw_ino, net) =
+            guest.thread_state().with_detfd(call.fd(), |detfd| {
+                (
+                    detfd.ty,
+                    detfd.resource.clone(),
+                    detfd.stat.map(|x| x.inode),
+                    detfd.net.clone(),
+                )
+            })?;
+        if let Some(stream) = net {
+            let bufs = [(call.buf().map_or(0, |addr| addr.as_raw()), call.len())];
+            return self.external_send(guest, stream, call, &bufs).await;
+        }
+        if fd_type == FdType::Secretmem {
+            return Err(Errno::EINVAL.into());
+        }
         // It doesn't matter much where the linearization point for this mtime bump falls:
         if guest.config().virtualize_metadata {
             let r =
@@ -302,7 +371,10 @@ impl<T: RecordOrReplay> Detcore<T> {
             resource_request(guest, request).await;
         }
 
-        let res = if guest.config().deterministic_io {
+        let res = if fd_type == FdType::Eventfd {
+            // Adding to the counter blocks if it would overflow, until a reader makes room.
+            self.execute_nonblockable_fd_syscall(guest, call).await
+        } else if guest.config().deterministic_io {
             let mut total_written_bytes = 0;
             let mut remaining_buf = call.len();
 
@@ -342,15 +414,44 @@ impl<T: RecordOrReplay> Detcore<T> {
         };
 
         resource_release_all(guest).await;
+        if fd_type == FdType::Eventfd && res.is_ok() {
+            // Adding to the counter may wake up readers.
+            self.wakeup_sched_point(guest).await;
+        }
         res
     }
 
+    /// After an eventfd operation which may wake up other threads: under `--chaos`, a
+    /// priority change point, so that whether the waker or the threads it woke run first
+    /// varies from one chaos seed to the next (and is reproducible for any one seed).
+    async fn wakeup_sched_point<G: Guest<Self>>(&self, guest: &mut G) {
+        let ts = guest.thread_state();
+        if self.cfg.chaos
+            && ts.preemption_points.is_none()
+            && self.cfg.replay_schedule_from.is_none()
+        {
+            let now = ts.thread_logical_time.as_nanos();
+            let req = Self::random_priority_changepoint_request(guest, now);
+            resource_request(guest, req).await;
+        }
+    }
+
     /// SYS_mmap system call.
     pub async fn handle_mmap<G: Guest<Self>>(
         &self,
         guest: &mut G,
         call: syscalls::Mmap,
     ) -> Result<i64, Error> {
+        guest
+            .thread_state()
+            .address_space
+            .lock()
+            .unwrap()
+            .mappings_changed();
+        if self.cfg.deterministic_mmap {
+            self.check_mmap_memory_limit(guest, call)?;
+            return self.handle_deterministic_mmap(guest, call).await;
+        }
         // This is a far-from-complete placeholder:
         if call.fd() == -1 {
             return Ok(self.record_or_replay(guest, call).await?);
@@ -371,6 +472,8 @@ impl<T: RecordOrReplay> Detcore<T> {
     //     increase monolitically and won't be re-used (like ext4)
     //   - use logical modtime which could be used by program like GNU make
     //     to determine file changes
+    //   - with `--normalize-stat`, also the other timestamps, device ids, and
+    //     block counts
     async fn determinize_stat<G, S>(&self, guest: &mut G, stat: S) -> Result<DetStat, Error>
     where
         G: Guest<Self>,
@@ -394,6 +497,18 @@ impl<T: RecordOrReplay> Detcore<T> {
 
         stat.mtime = mtime;
 
+        if cfg.normalize_stat {
+            // Writing the contents also changes the status, and ordering the timestamps like
+            // this keeps "newer than" comparisons between them consistent.
+            stat.atime = mtime;
+            stat.ctime = mtime;
+            stat.dev = determinize_device(guest, stat.dev).await;
+            stat.blksize = NORMALIZED_BLKSIZE;
+            // Whole blocks, counted in 512-byte units regardless of blksize.
+            let whole_blocks = (stat.size.max(0) + NORMALIZED_BLKSIZE - 1) / NORMALIZED_BLKSIZE;
+            stat.blocks = whole_blocks * (NORMALIZED_BLKSIZE / 512);
+        }
+
         Ok(stat)
     }
 
@@ -459,6 +574,15 @@ impl<T: RecordOrReplay> Detcore<T> {
                 guest.thread_state_mut().dup_fd(fd, newfd, o_cloexec)?;
                 Ok(newfd as i64)
             }
+            F_ADD_SEALS(_) | F_GET_SEALS => {
+                // Our stand-in for a memfd_secret fd is a memfd, but the real thing can't be
+                // sealed.  (Other fds are left to the kernel to refuse.)
+                let fd_type = guest.thread_state().with_detfd(fd, |detfd| detfd.ty);
+                if fd_type == Ok(FdType::Secretmem) {
+                    return Err(Errno::EINVAL.into());
+                }
+                Ok(self.record_or_replay(guest, call).await?)
+            }
             _ => {
                 trace!(
                     "[detcore-finishme]: fcntl unhandled cases: {:?}",
@@ -820,13 +944,19 @@ impl<T: RecordOrReplay> Detcore<T> {
         Ok(res)
     }
 
-    /// socket system call.
+    /// eventfd2 system call.  Under sequentialization the eventfd is made nonblocking in the
+    /// kernel, and blocking reads and writes of its counter are retried by the scheduler.
     pub async fn handle_eventfd2<G: Guest<Self>>(
         &self,
         guest: &mut G,
         call: syscalls::Eventfd2,
     ) -> Result<i64, Error> {
-        let fd = self.record_or_replay(guest, call).await? as RawFd;
+        let call2 = if self.cfg.use_nonblocking_sockets() {
+            call.with_flags(call.flags() | EfdFlags::EFD_NONBLOCK)
+        } else {
+            call
+        };
+        let fd = self.record_or_replay(guest, call2).await? as RawFd;
         self.add_fd(
             guest,
             fd,
@@ -836,26 +966,66 @@ impl<T: RecordOrReplay> Detcore<T> {
             FdType::Eventfd,
         )
         .await?;
+        self.maybe_set_nonblocking_fd(guest, fd);
         Ok(fd as i64)
     }
 
-    /// signalfd4 system call.
+    /// signalfd4 system call.  As with eventfds, a new signalfd is made nonblocking in the
+    /// kernel under sequentialization, so that signals are read from it only at the points
+    /// chosen by the scheduler.
     pub async fn handle_signalfd4<G: Guest<Self>>(
         &self,
         guest: &mut G,
         call: syscalls::Signalfd4,
     ) -> Result<i64, Error> {
-        let signalfd = self.record_or_replay(guest, call).await? as RawFd;
+        // Otherwise, this only changes the signal mask of an existing signalfd.
+        let new_fd = call.fd() == -1;
+        let call2 = if new_fd && self.cfg.use_nonblocking_sockets() {
+            call.with_flags(call.flags() | SfdFlags::SFD_NONBLOCK)
+        } else {
+            call
+        };
+        let signalfd = self.record_or_replay(guest, call2).await? as RawFd;
+        if new_fd {
+            self.add_fd(
+                guest,
+                signalfd,
+                OFlag::from_bits_truncate(
+                    call.flags().bits() & (libc::SFD_CLOEXEC | libc::SFD_NONBLOCK),
+                ),
+                FdType::Signalfd,
+            )
+            .await?;
+            self.maybe_set_nonblocking_fd(guest, signalfd);
+        }
+        Ok(signalfd as i64)
+    }
+
+    /// inotify_init1 system call.  The events of an inotify instance are read at points
+    /// chosen by the scheduler, like those of a signalfd, and see `read_inotify`.
+    pub async fn handle_inotify_init1<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::InotifyInit1,
+    ) -> Result<i64, Error> {
+        let call2 = if self.cfg.use_nonblocking_sockets() {
+            call.with_flags(call.flags() | libc::IN_NONBLOCK)
+        } else {
+            call
+        };
+        let fd = self.record_or_replay(guest, call2).await? as RawFd;
         self.add_fd(
             guest,
-            signalfd,
-            OFlag::from_bits_truncate(
-                call.flags().bits() & (libc::SFD_CLOEXEC | libc::SFD_NONBLOCK),
-            ),
-            FdType::Signalfd,
+            fd,
+            OFlag::from_bits_truncate(call.flags() & (libc::IN_CLOEXEC | libc::IN_NONBLOCK)),
+            FdType::Inotify,
         )
         .await?;
-        Ok(signalfd as i64)
+        guest.thread_state().with_detfd(fd, |detfd| {
+            detfd.inotify = Some(Default::default());
+        })?;
+        self.maybe_set_nonblocking_fd(guest, fd);
+        Ok(fd as i64)
     }
 
     /// timerfd_create system call.
@@ -874,6 +1044,16 @@ impl<T: RecordOrReplay> Detcore<T> {
             FdType::Timerfd,
         )
         .await?;
+        if self.cfg.sequentialize_threads
+            && self.cfg.virtualize_time
+            && !self.cfg.recordreplay_modes
+        {
+            // The timer itself is kept in logical time, see `handle_timerfd_settime`.
+            let timer = Arc::new(Mutex::new(TimerfdState::default()));
+            guest
+                .thread_state()
+                .with_detfd(fd, |detfd| detfd.timer = Some(timer.clone()))?;
+        }
         Ok(fd as i64)
     }
 
@@ -894,6 +1074,47 @@ impl<T: RecordOrReplay> Detcore<T> {
         Ok(fd as i64)
     }
 
+    /// memfd_secret system call.  Whether the host kernel provides this depends on how it
+    /// was booted, so under sequentialization we always stand in a memfd for it, which the
+    /// guest may likewise size with ftruncate and map, but not read or write.
+    pub async fn handle_memfd_secret<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: Syscall,
+    ) -> Result<i64, Error> {
+        if !self.cfg.sequentialize_threads || self.cfg.recordreplay_modes {
+            return Ok(self.record_or_replay(guest, call).await?);
+        }
+        let (_, args) = call.into_parts();
+        let flags = args.arg0 as i32;
+        if flags & !libc::O_CLOEXEC != 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let mfd_flags = if flags & libc::O_CLOEXEC != 0 {
+            libc::MFD_CLOEXEC
+        } else {
+            0
+        };
+        let (name, _guard) = {
+            let mut stack = guest.stack().await;
+            let name = stack.push(*b"secretmem\0");
+            let guard = stack.commit()?;
+            (name, guard)
+        };
+        let memfd_create = SyscallArgs::new(name.as_raw(), mfd_flags as usize, 0, 0, 0, 0);
+        let fd = self
+            .record_or_replay(guest, Syscall::from_raw(Sysno::memfd_create, memfd_create))
+            .await? as RawFd;
+        self.add_fd(
+            guest,
+            fd,
+            OFlag::from_bits_truncate(flags),
+            FdType::Secretmem,
+        )
+        .await?;
+        Ok(fd as i64)
+    }
+
     /// userfaultfd system call.
     pub async fn handle_userfaultfd<G: Guest<Self>>(
         &self,
@@ -987,7 +1208,8 @@ impl<T: RecordOrReplay> Detcore<T> {
         Ok(nb)
     }
 
-    /// getdents64 system call.
+    /// getdents64 system call.  Unless `--no-sort-dirents` is given, the whole directory is
+    /// listed by the first call, and then served in a deterministic order from a [`DirStream`].
     pub async fn handle_getdents64<G: Guest<Self>>(
         &self,
         guest: &mut G,
@@ -998,6 +1220,36 @@ impl<T: RecordOrReplay> Detcore<T> {
         }
 
         let dirent = call.dirent().ok_or(Errno::EFAULT)?;
+        let fd = call.fd() as RawFd;
+
+        if guest.config().sort_dirents {
+            // Untracked fds fall through to the host order.
+            if let Ok(stream) = guest.thread_state().with_detfd(fd, |detfd| detfd.dir.clone()) {
+                let stream = match stream {
+                    Some(stream) => stream,
+                    None => {
+                        let stream = Arc::new(Mutex::new(self.list_dir(guest, call).await?));
+                        guest
+                            .thread_state()
+                            .with_detfd(fd, |detfd| detfd.dir = Some(stream.clone()))?;
+                        stream
+                    }
+                };
+                let (page, at_end) = {
+                    let mut stream = stream.lock().unwrap();
+                    (stream.take(call.count() as usize).to_vec(), stream.at_end())
+                };
+                if page.is_empty() && !at_end {
+                    // The buffer cannot hold the next entry.
+                    return Err(Errno::EINVAL.into());
+                }
+                let nb: usize = page.iter().map(|dent| dent.reclen as usize).sum();
+                let mut bytes = vec![0; nb];
+                let _ = unsafe { serialize_dirents64(&page, bytes.as_mut_slice()) };
+                guest.memory().write_exact(dirent.cast(), bytes.as_slice())?;
+                return Ok(nb as i64);
+            }
+        }
 
         let nb = self.record_or_replay(guest, call).await?;
         if nb == 0 {
@@ -1012,7 +1264,6 @@ impl<T: RecordOrReplay> Detcore<T> {
             .read_exact(dirent.cast(), cached_bytes.as_mut_slice())?;
 
         let mut dents = unsafe { deserialize_dirents64(&cached_bytes) };
-        dents.sort();
         for dent in &mut dents {
             let (d_ino, _) = determinize_inode(guest, dent.ino).await;
             dent.ino = d_ino;
@@ -1024,6 +1275,76 @@ impl<T: RecordOrReplay> Detcore<T> {
             .write_exact(dirent.cast(), cached_bytes.as_slice())?;
         Ok(nb)
     }
+
+    /// Read the complete listing of a directory, through the guest's own buffer, and put it
+    /// in deterministic order: by name, or permuted by the chaos seed under `--chaos`.
+    async fn list_dir<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Getdents64,
+    ) -> Result<DirStream, Error> {
+        let dirent = call.dirent().ok_or(Errno::EFAULT)?;
+        let mut dents = Vec::new();
+        loop {
+            let nb = self.record_or_replay(guest, call).await?;
+            if nb == 0 {
+                break;
+            }
+            let mut bytes = vec![0; nb as usize];
+            guest
+                .memory()
+                .read_exact(dirent.cast(), bytes.as_mut_slice())?;
+            dents.extend(unsafe { deserialize_dirents64(&bytes) });
+        }
+
+        // Inodes are numbered in order of discovery, so this must go in a fixed order too.
+        dents.sort();
+        for dent in &mut dents {
+            let (d_ino, _) = determinize_inode(guest, dent.ino).await;
+            dent.ino = d_ino;
+        }
+        let seed = if guest.config().chaos {
+            Some(guest.thread_state_mut().chaos_prng_next_u64("getdents64"))
+        } else {
+            None
+        };
+        Ok(DirStream::new(dents, seed))
+    }
+
+    /// lseek system call.  On a directory served from a [`DirStream`], offsets are positions
+    /// in the stream, and rewinding to the start lists the directory afresh.
+    pub async fn handle_lseek<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Lseek,
+    ) -> Result<i64, Error> {
+        let fd = call.fd() as RawFd;
+        let stream = guest
+            .thread_state()
+            .with_detfd(fd, |detfd| detfd.dir.clone())
+            .ok()
+            .flatten();
+        let stream = match stream {
+            Some(stream) => stream,
+            None => return Ok(self.record_or_replay(guest, call).await?),
+        };
+
+        match call.whence() {
+            Whence::SEEK_SET if call.offset() == 0 => {
+                let ret = self.record_or_replay(guest, call).await?;
+                guest
+                    .thread_state()
+                    .with_detfd(fd, |detfd| detfd.dir = None)?;
+                Ok(ret)
+            }
+            Whence::SEEK_SET if call.offset() > 0 => {
+                stream.lock().unwrap().seek(call.offset() as u64);
+                Ok(call.offset() as i64)
+            }
+            Whence::SEEK_CUR if call.offset() == 0 => Ok(stream.lock().unwrap().tell() as i64),
+            _ => Err(Errno::EINVAL.into()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -23,6 +23,7 @@ use crate::record_or_replay::RecordOrReplay;
 use crate::resources::Permission;
 use crate::resources::ResourceID;
 use crate::resources::Resources;
+use crate::tool_global::poll_resource_request;
 use crate::tool_global::resource_request;
 use crate::tool_global::thread_observe_time;
 use crate::tool_global::trace_schedevent;
@@ -191,7 +192,7 @@ pub fn ioaction_based_on_fd_status<
 ///
 /// It does not cover system calls with multiple fd arguments, with pointers to heap
 /// structures that contain fds.
-fn get_fd(s: Syscall) -> Option<i32> {
+pub(crate) fn get_fd(s: Syscall) -> Option<i32> {
     match s {
         Syscall::Recvfrom(s) => Some(s.fd()),
         Syscall::Recvmsg(s) => Some(s.sockfd()),
@@ -360,6 +361,147 @@ impl TimeoutableSyscall for reverie::syscalls::EpollWait {
     }
 }
 
+#[async_trait]
+impl NonblockableSyscall for reverie::syscalls::EpollPwait {
+    async fn into_nonblocking<T: RecordOrReplay, G: Guest<Detcore<T>>>(
+        self,
+        _guest: &mut G,
+    ) -> (Self, Option<<G::Stack as Stack>::StackGuard>) {
+        (self.with_timeout(0), None)
+    }
+}
+
+impl TimeoutableSyscall for reverie::syscalls::EpollPwait {
+    fn timeout_return_val(&self) -> Result<i64, Errno> {
+        Ok(0)
+    }
+}
+
+#[async_trait]
+impl NonblockableSyscall for reverie::syscalls::Ppoll {
+    async fn into_nonblocking<T: RecordOrReplay, G: Guest<Detcore<T>>>(
+        self,
+        guest: &mut G,
+    ) -> (Self, Option<<G::Stack as Stack>::StackGuard>) {
+        let (tp, guard) = zero_timespec(guest).await;
+        (self.with_timeout(Some(tp)), Some(guard))
+    }
+}
+
+impl TimeoutableSyscall for reverie::syscalls::Ppoll {
+    fn timeout_return_val(&self) -> Result<i64, Errno> {
+        Ok(0)
+    }
+}
+
+#[async_trait]
+impl NonblockableSyscall for reverie::syscalls::Semtimedop {
+    async fn into_nonblocking<T: RecordOrReplay, G: Guest<Detcore<T>>>(
+        self,
+        guest: &mut G,
+    ) -> (Self, Option<<G::Stack as Stack>::StackGuard>) {
+        let (tp, guard) = zero_timespec(guest).await;
+        (self.with_timeout(Some(tp)), Some(guard))
+    }
+
+    fn syscall_would_have_blocked(&self, res: Result<i64, Errno>) -> bool {
+        res == Err(Errno::EAGAIN)
+    }
+}
+
+impl TimeoutableSyscall for reverie::syscalls::Semtimedop {
+    fn timeout_return_val(&self) -> Result<i64, Errno> {
+        Err(Errno::EAGAIN)
+    }
+}
+
+#[async_trait]
+impl NonblockableSyscall for reverie::syscalls::Msgsnd {
+    async fn into_nonblocking<T: RecordOrReplay, G: Guest<Detcore<T>>>(
+        self,
+        _guest: &mut G,
+    ) -> (Self, Option<<G::Stack as Stack>::StackGuard>) {
+        (self.with_msgflg(self.msgflg() | libc::IPC_NOWAIT), None)
+    }
+
+    fn syscall_would_have_blocked(&self, res: Result<i64, Errno>) -> bool {
+        res == Err(Errno::EAGAIN)
+    }
+}
+
+/// Message queue sends never time out; this only serves `retry_nonblocking_syscall_with_timeout`.
+impl TimeoutableSyscall for reverie::syscalls::Msgsnd {
+    fn timeout_return_val(&self) -> Result<i64, Errno> {
+        Err(Errno::EAGAIN)
+    }
+}
+
+#[async_trait]
+impl NonblockableSyscall for reverie::syscalls::Msgrcv {
+    async fn into_nonblocking<T: RecordOrReplay, G: Guest<Detcore<T>>>(
+        self,
+        _guest: &mut G,
+    ) -> (Self, Option<<G::Stack as Stack>::StackGuard>) {
+        (self.with_msgflg(self.msgflg() | libc::IPC_NOWAIT), None)
+    }
+
+    fn syscall_would_have_blocked(&self, res: Result<i64, Errno>) -> bool {
+        res == Err(Errno::ENOMSG)
+    }
+}
+
+/// Message queue receives never time out; this only serves
+/// `retry_nonblocking_syscall_with_timeout`.
+impl TimeoutableSyscall for reverie::syscalls::Msgrcv {
+    fn timeout_return_val(&self) -> Result<i64, Errno> {
+        Err(Errno::ENOMSG)
+    }
+}
+
+/// POSIX message queue operations take an absolute deadline, so a zero one has always
+/// passed, and the operation fails with ETIMEDOUT rather than block.
+#[async_trait]
+impl NonblockableSyscall for reverie::syscalls::MqTimedsend {
+    async fn into_nonblocking<T: RecordOrReplay, G: Guest<Detcore<T>>>(
+        self,
+        guest: &mut G,
+    ) -> (Self, Option<<G::Stack as Stack>::StackGuard>) {
+        let (tp, guard) = zero_timespec(guest).await;
+        (self.with_abs_timeout(Some(tp)), Some(guard))
+    }
+
+    fn syscall_would_have_blocked(&self, res: Result<i64, Errno>) -> bool {
+        res == Err(Errno::ETIMEDOUT)
+    }
+}
+
+impl TimeoutableSyscall for reverie::syscalls::MqTimedsend {
+    fn timeout_return_val(&self) -> Result<i64, Errno> {
+        Err(Errno::ETIMEDOUT)
+    }
+}
+
+#[async_trait]
+impl NonblockableSyscall for reverie::syscalls::MqTimedreceive {
+    async fn into_nonblocking<T: RecordOrReplay, G: Guest<Detcore<T>>>(
+        self,
+        guest: &mut G,
+    ) -> (Self, Option<<G::Stack as Stack>::StackGuard>) {
+        let (tp, guard) = zero_timespec(guest).await;
+        (self.with_abs_timeout(Some(tp)), Some(guard))
+    }
+
+    fn syscall_would_have_blocked(&self, res: Result<i64, Errno>) -> bool {
+        res == Err(Errno::ETIMEDOUT)
+    }
+}
+
+impl TimeoutableSyscall for reverie::syscalls::MqTimedreceive {
+    fn timeout_return_val(&self) -> Result<i64, Errno> {
+        Err(Errno::ETIMEDOUT)
+    }
+}
+
 async fn zero_timespec<'stack, T: RecordOrReplay, G: Guest<Detcore<T>>>(
     guest: &mut G,
 ) -> (Addr<'stack, Timespec>, <G::Stack as Stack>::StackGuard) {
@@ -438,7 +580,7 @@ impl TimeoutableSyscall for reverie::syscalls::RtSigtimedwait {
 }
 
 /// While the read syscall is quite general, this nonblocking capacity is used
-/// ONLY for sockets and pipes.
+/// ONLY for sockets, pipes, eventfds, and signalfds.
 #[async_trait]
 impl NonblockableSyscall for reverie::syscalls::Read {
     async fn into_nonblocking<T: RecordOrReplay, G: Guest<Detcore<T>>>(
@@ -455,8 +597,8 @@ impl NonblockableSyscall for reverie::syscalls::Read {
     }
 }
 
-/// While the read syscall is quite general, this nonblocking capacity is used
-/// ONLY for sockets and pipes.
+/// While the write syscall is quite general, this nonblocking capacity is used
+/// ONLY for sockets, pipes, and eventfds.
 #[async_trait]
 impl NonblockableSyscall for reverie::syscalls::Write {
     async fn into_nonblocking<T: RecordOrReplay, G: Guest<Detcore<T>>>(
@@ -669,9 +811,22 @@ where
     // surviving multiple syscall injections:
     let (call, _maybe_stackguard) = call0.into_nonblocking(guest).await;
     let mut rsrc = rsrc.clone();
+    // The processes of the thread's pidfds, whose exits the scheduler reports with each grant:
+    let pidfd_targets = Detcore::<T>::pidfd_targets(guest);
 
     loop {
-        resource_request(guest, rsrc.clone()).await;
+        let exited = if pidfd_targets.is_empty() {
+            resource_request(guest, rsrc.clone()).await;
+            Vec::new()
+        } else {
+            poll_resource_request(guest, rsrc.clone(), pidfd_targets.clone())
+                .await
+                .1
+        };
+        // Virtual timerfds which have expired must look ready to the kernel:
+        Detcore::<T>::materialize_expired_timerfds(guest).await?;
+        // Likewise pidfds of processes which have exited:
+        Detcore::<T>::settle_exited_pidfds(guest, &exited).await?;
         let res = guest.inject_with_retry(call).await;
         if call.syscall_would_have_blocked(res) {
             rsrc.poll_attempt += 1;
@@ -683,6 +838,7 @@ where
                         rsrc.poll_attempt - 1,
                         call.display(&guest.memory())
                     );
+                    record_poll_event(guest, call, SyscallPhase::TimedOut).await;
                     return timeout_result.map_err(|e| e.into());
                 } else {
                     tracing::trace!(
@@ -692,7 +848,7 @@ where
                         timeout - new_time,
                         call.display(&guest.memory())
                     );
-                    record_retry_event(guest, call).await;
+                    record_poll_event(guest, call, SyscallPhase::Polling).await;
                 }
             } else {
                 tracing::trace!(
@@ -701,7 +857,7 @@ where
                     res,
                     call.display(&guest.memory())
                 );
-                record_retry_event(guest, call).await;
+                record_poll_event(guest, call, SyscallPhase::Polling).await;
             }
         } else {
             let res = res.map_err(|e| e.into());
@@ -716,7 +872,8 @@ where
     }
 }
 
-async fn record_retry_event<G, C, T>(guest: &mut G, call: C)
+/// Trace a retry of a nonblocking syscall, or its (logical) timeout, as a schedule event.
+async fn record_poll_event<G, C, T>(guest: &mut G, call: C, phase: SyscallPhase)
 where
     C: NonblockableSyscall,
     T: RecordOrReplay,
@@ -728,26 +885,13 @@ where
     if cfg.sequentialize_threads && cfg.should_trace_schedevent() {
         trace_schedevent(
             guest,
-            SchedEvent::syscall(dettid, call.number(), SyscallPhase::Polling).with_time(nanos),
+            SchedEvent::syscall(dettid, call.number(), phase).with_time(nanos),
             true,
         )
         .await;
     }
 }
 
-// Convert to absolute logical time point for the timeout.
-// 0 duration means no timeout, and this will return None.
-pub async fn millis_duration_to_absolute_timeout<G: Guest<Detcore<T>>, T: RecordOrReplay>(
-    guest: &mut G,
-    timeout_millis: i32,
-) -> Option<LogicalTime> {
-    if timeout_millis > 0 {
-        nanos_duration_to_absolute_timeout(guest, (timeout_millis as u128) * 1000).await
-    } else {
-        None
-    }
-}
-
 // Convert to absolute logical time point for the timeout.
 // 0 duration means no timeout, and this will return None.
 pub async fn nanos_duration_to_absolute_timeout<G: Guest<Detcore<T>>, T: RecordOrReplay>(
@@ -757,7 +901,9 @@ pub async fn nanos_duration_to_absolute_timeout<G: Guest<Detcore<T>>, T: RecordO
     if timeout_nanos > 0 {
         let ns_delta = Duration::from_nanos(timeout_nanos as u64);
         let base_time = thread_observe_time(guest).await;
-        let target_time = base_time + ns_delta;
+        let target_time = guest
+            .config()
+            .round_timer_expiration(base_time + ns_delta);
         Some(target_time)
     } else {
         None
@@ -10,27 +10,107 @@
 //!
 //! Of course this overlaps somewhat with "files.rs".
 
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
 use std::time::Duration;
+use std::time::SystemTime;
 
 use reverie::syscalls;
+use reverie::syscalls::Addr;
+use reverie::syscalls::AddrMut;
+use reverie::syscalls::Errno;
+use reverie::syscalls::MemoryAccess;
 use reverie::syscalls::Syscall;
 use reverie::syscalls::SyscallInfo;
 use reverie::Error;
 use reverie::Guest;
+use reverie::Stack;
 use tracing::debug;
+use tracing::error;
 use tracing::trace;
+use tracing::warn;
 
 use crate::config::SchedHeuristic;
+use crate::fd::FdType;
+use crate::netrecord::Direction;
+use crate::netrecord::NetStream;
+use crate::netrecord::ReplayedStream;
+use crate::netrecord::DNS_PEER;
+use crate::netrecord::DNS_PORT;
 use crate::record_or_replay::RecordOrReplay;
 use crate::resources::Permission;
 use crate::resources::ResourceID;
 use crate::resources::Resources;
 use crate::scheduler::runqueue::FIRST_PRIORITY;
-use crate::syscalls::helpers::millis_duration_to_absolute_timeout;
+use crate::syscalls::helpers::get_fd;
+use crate::syscalls::helpers::nanos_duration_to_absolute_timeout;
 use crate::syscalls::helpers::retry_nonblocking_syscall_with_timeout;
 use crate::syscalls::helpers::NonblockableSyscall;
+use crate::syscalls::helpers::TimeoutableSyscall;
+use crate::timers::timespec_to_duration;
+use crate::timers::timeval_to_duration;
 use crate::tool_global::*;
 use crate::tool_local::Detcore;
+use crate::types::SocketOp;
+
+/// The most fds a select can wait on.
+const FD_SETSIZE: usize = libc::FD_SETSIZE as usize;
+
+/// The poll events requested for each of the read, write, and exception sets of a select,
+/// and the events reported by poll which make an fd count as ready in that set (as with
+/// the kernel's own select).
+const SELECT_EVENTS: [(i16, i16); 3] = [
+    (libc::POLLIN, libc::POLLIN | libc::POLLHUP | libc::POLLERR),
+    (libc::POLLOUT, libc::POLLOUT | libc::POLLERR),
+    (libc::POLLPRI, libc::POLLPRI),
+];
+
+/// The timeout of a poll or epoll wait, in milliseconds, where a negative timeout means
+/// to wait forever.
+fn millis_to_timeout(millis: i32) -> Option<Duration> {
+    u64::try_from(millis).ok().map(Duration::from_millis)
+}
+
+/// Read a relative timeout from the guest, rejecting invalid values as the kernel does.
+fn read_timespec<T: RecordOrReplay, G: Guest<Detcore<T>>>(
+    guest: &G,
+    addr: Addr<libc::timespec>,
+) -> Result<Duration, Errno> {
+    let ts: libc::timespec = guest.memory().read_value(addr)?;
+    timespec_to_duration(&ts).ok_or(Errno::EINVAL)
+}
+
+/// The bytes of the `sockaddr_in` or `sockaddr_in6` for an address, as the kernel reports it.
+fn sockaddr_bytes(addr: SocketAddr) -> Vec<u8> {
+    fn as_bytes<S>(sockaddr: &S) -> Vec<u8> {
+        // Safe: these are plain C structs, which are fully initialized.
+        unsafe {
+            std::slice::from_raw_parts(sockaddr as *const S as *const u8, std::mem::size_of::<S>())
+        }
+        .to_vec()
+    }
+    match addr {
+        SocketAddr::V4(v4) => {
+            let mut sin: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = v4.port().to_be();
+            sin.sin_addr.s_addr = u32::from(*v4.ip()).to_be();
+            as_bytes(&sin)
+        }
+        SocketAddr::V6(v6) => {
+            let mut sin6: libc::sockaddr_in6 = unsafe { std::mem::zeroed() };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = v6.port().to_be();
+            sin6.sin6_flowinfo = v6.flowinfo().to_be();
+            sin6.sin6_addr.s6_addr = v6.ip().octets();
+            sin6.sin6_scope_id = v6.scope_id();
+            as_bytes(&sin6)
+        }
+    }
+}
 
 // Printing helper
 // TODO: this should be subsumed by better syscall printing.
@@ -57,6 +137,8 @@ impl<T: RecordOrReplay> Detcore<T> {
 
         call: syscalls::Poll,
     ) -> Result<i64, Error> {
+        let fds = call.fds().map_or(0, |addr| addr.as_raw());
+        Self::refuse_replayed_sockets_in_poll(guest, "poll", fds, call.nfds() as usize)?;
         if !self.cfg.sequentialize_threads || self.cfg.recordreplay_modes {
             // In replay mode, we cannot assume the existence of FILES during replay.
             // Thus we must record the poll and replay it from the trace.
@@ -74,15 +156,243 @@ impl<T: RecordOrReplay> Detcore<T> {
         guest: &mut G,
         call: syscalls::Poll,
     ) -> Result<i64, Error> {
-        let timeout_millis = call.timeout();
-        if timeout_millis == 0 {
-            Ok(guest.inject(call).await?) // Already non-blocking.
+        Self::retry_poll(guest, call, millis_to_timeout(call.timeout())).await
+    }
+
+    /// ppoll syscall (MAYHANG)
+    pub async fn handle_ppoll<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Ppoll,
+    ) -> Result<i64, Error> {
+        let fds = call.fds().map_or(0, |addr| addr.as_raw());
+        Self::refuse_replayed_sockets_in_poll(guest, "ppoll", fds, call.nfds() as usize)?;
+        if !self.cfg.sequentialize_threads || self.cfg.recordreplay_modes {
+            return Ok(self
+                .record_or_replay_blocking(guest, Syscall::Ppoll(call))
+                .await?);
+        }
+        let timeout = match call.timeout() {
+            Some(addr) => Some(read_timespec(guest, addr.cast())?),
+            None => None,
+        };
+        Self::retry_poll(guest, call, timeout).await
+    }
+
+    /// select syscall (MAYHANG).  When determinized, this is served by an equivalent poll,
+    /// which (unlike select) can be retried without losing the fd sets.
+    pub async fn handle_select<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Select,
+    ) -> Result<i64, Error> {
+        if !self.cfg.sequentialize_threads || self.cfg.recordreplay_modes {
+            return Ok(self
+                .record_or_replay_blocking(guest, Syscall::Select(call))
+                .await?);
+        }
+        let timeout = match call.timeout() {
+            Some(addr) => {
+                let tv: libc::timeval = guest.memory().read_value(addr.cast())?;
+                Some(timeval_to_duration(&tv).ok_or(Errno::EINVAL)?)
+            }
+            None => None,
+        };
+        let sets = [call.readfds(), call.writefds(), call.exceptfds()].map(|set| {
+            set.map(|addr| addr.cast::<u64>())
+        });
+        let (ready, remaining) =
+            Self::select_via_poll(guest, call.nfds(), sets, timeout, None).await?;
+        // Like the kernel, report the time left.
+        if let (Some(addr), Some(remaining)) = (call.timeout(), remaining) {
+            let tv = libc::timeval {
+                tv_sec: remaining.as_secs() as libc::time_t,
+                tv_usec: remaining.subsec_micros() as libc::suseconds_t,
+            };
+            guest.memory().write_value(addr.cast(), &tv)?;
+        }
+        Ok(ready)
+    }
+
+    /// pselect6 syscall (MAYHANG).  When determinized, this is served by an equivalent
+    /// ppoll, with the same signal mask.
+    pub async fn handle_pselect6<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Pselect6,
+    ) -> Result<i64, Error> {
+        if !self.cfg.sequentialize_threads || self.cfg.recordreplay_modes {
+            return Ok(self
+                .record_or_replay_blocking(guest, Syscall::Pselect6(call))
+                .await?);
+        }
+        let timeout = match call.timeout() {
+            Some(addr) => Some(read_timespec(guest, addr.cast())?),
+            None => None,
+        };
+        // The last argument points to the signal mask and its size.
+        let sigmask = match call.sigmask() {
+            Some(addr) => {
+                let [mask, size] = guest.memory().read_value(addr.cast::<[usize; 2]>())?;
+                Some((mask, size))
+            }
+            None => None,
+        };
+        let sets = [call.readfds(), call.writefds(), call.exceptfds()].map(|set| {
+            set.map(|addr| addr.cast::<u64>())
+        });
+        let (ready, remaining) =
+            Self::select_via_poll(guest, call.nfds(), sets, timeout, sigmask).await?;
+        if let (Some(addr), Some(remaining)) = (call.timeout(), remaining) {
+            let ts = libc::timespec {
+                tv_sec: remaining.as_secs() as libc::time_t,
+                tv_nsec: remaining.subsec_nanos() as libc::c_long,
+            };
+            guest.memory().write_value(addr.cast(), &ts)?;
+        }
+        Ok(ready)
+    }
+
+    /// Wait on the read, write, and exception fd sets of a select by polling for the
+    /// corresponding events, and then rewrite the sets to hold only the ready fds.  Returns
+    /// the number of ready fds, and the logical time left before the timeout (if any).
+    async fn select_via_poll<G: Guest<Self>>(
+        guest: &mut G,
+        nfds: i32,
+        sets: [Option<AddrMut<'_, u64>>; 3],
+        timeout: Option<Duration>,
+        sigmask: Option<(usize, usize)>,
+    ) -> Result<(i64, Option<Duration>), Error> {
+        if nfds < 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let nfds = (nfds as usize).min(FD_SETSIZE);
+        let words = (nfds + 63) / 64;
+        let mut bits = [[0u64; FD_SETSIZE / 64]; 3];
+        for (set, addr) in bits.iter_mut().zip(sets) {
+            if let Some(addr) = addr {
+                guest.memory().read_values(addr.into(), &mut set[..words])?;
+            }
+        }
+
+        let mut pollfds = [libc::pollfd {
+            fd: -1,
+            events: 0,
+            revents: 0,
+        }; FD_SETSIZE];
+        let mut len = 0;
+        for fd in 0..nfds {
+            let events = bits
+                .iter()
+                .zip(SELECT_EVENTS)
+                .filter(|(set, _)| set[fd / 64] & (1 << (fd % 64)) != 0)
+                .fold(0, |acc, (_, (requested, _))| acc | requested);
+            if events != 0 {
+                pollfds[len] = libc::pollfd {
+                    fd: fd as i32,
+                    events,
+                    revents: 0,
+                };
+                len += 1;
+            }
+        }
+        Self::refuse_replayed_sockets(guest, "select", pollfds[..len].iter().map(|p| p.fd))?;
+
+        let deadline = match timeout {
+            Some(timeout) => Some(thread_observe_time(guest).await + timeout.as_nanos()),
+            None => None,
+        };
+        let (fds, _guard) = {
+            let mut stack = guest.stack().await;
+            let fds = stack.push(pollfds);
+            let guard = stack.commit().expect("stack.commit to succeed");
+            (fds, guard)
+        };
+        let fds_addr = AddrMut::from_raw(fds.as_raw());
+        let ready = match sigmask {
+            Some((mask, size)) => {
+                let ppoll = syscalls::Ppoll::new()
+                    .with_fds(fds_addr)
+                    .with_nfds(len as _)
+                    .with_sigmask(Addr::from_raw(mask))
+                    .with_sigsetsize(size);
+                Self::retry_poll(guest, ppoll, timeout).await?
+            }
+            None => {
+                let poll = syscalls::Poll::new()
+                    .with_fds(fds_addr)
+                    .with_nfds(len as _)
+                    .with_timeout(0);
+                Self::retry_poll(guest, poll, timeout).await?
+            }
+        };
+
+        let mut out = [[0u64; FD_SETSIZE / 64]; 3];
+        if ready > 0 {
+            guest
+                .memory()
+                .read_values(fds.cast::<libc::pollfd>(), &mut pollfds[..len])?;
+        }
+        let mut count = 0;
+        for pollfd in &pollfds[..len] {
+            if pollfd.revents & libc::POLLNVAL != 0 {
+                return Err(Errno::EBADF.into());
+            }
+            let fd = pollfd.fd as usize;
+            for (set, (requested, reported)) in out.iter_mut().zip(SELECT_EVENTS) {
+                if pollfd.events & requested != 0 && pollfd.revents & reported != 0 {
+                    set[fd / 64] |= 1 << (fd % 64);
+                    count += 1;
+                }
+            }
+        }
+        for (set, addr) in out.iter().zip(sets) {
+            if let Some(addr) = addr {
+                guest.memory().write_values(addr, &set[..words])?;
+            }
+        }
+
+        let remaining = match deadline {
+            Some(deadline) => {
+                let now = thread_observe_time(guest).await;
+                Some(deadline.max(now).duration_since(now))
+            }
+            None => None,
+        };
+        Ok((count, remaining))
+    }
+
+    /// Wait for a poll-like call to be ready, by retrying it without blocking, for up to
+    /// `timeout` of logical time (forever, if `None`).
+    async fn retry_poll<G, C>(
+        guest: &mut G,
+        call: C,
+        timeout: Option<Duration>,
+    ) -> Result<i64, Error>
+    where
+        G: Guest<Self>,
+        C: NonblockableSyscall + TimeoutableSyscall + Into<Syscall>,
+    {
+        if timeout == Some(Duration::ZERO) {
+            Self::materialize_expired_timerfds(guest).await?;
+            let targets = Self::pidfd_targets(guest);
+            if !targets.is_empty() {
+                let exited = exited_unreaped(guest, targets).await;
+                Self::settle_exited_pidfds(guest, &exited).await?;
+            }
+            let (call, _guard) = call.into_nonblocking(guest).await;
+            Ok(guest.inject(call).await?)
         } else {
-            let maybe_timeout_ns = millis_duration_to_absolute_timeout(guest, timeout_millis).await;
+            let maybe_timeout = match timeout {
+                Some(timeout) => {
+                    nanos_duration_to_absolute_timeout(guest, timeout.as_nanos()).await
+                }
+                None => None,
+            };
             let mut rsrc = Resources::new(guest.thread_state().dettid);
             rsrc.insert(ResourceID::InternalIOPolling, Permission::W);
-            rsrc.fyi("poll");
-            retry_nonblocking_syscall_with_timeout(guest, call, rsrc, maybe_timeout_ns).await
+            rsrc.fyi(call.name());
+            retry_nonblocking_syscall_with_timeout(guest, call, rsrc, maybe_timeout).await
         }
     }
 
@@ -107,6 +417,63 @@ impl<T: RecordOrReplay> Detcore<T> {
         }
     }
 
+    /// Whether an fd is a socket whose connection is replayed from `--replay-network-from`.
+    fn is_replayed_socket<G: Guest<Self>>(guest: &G, fd: i32) -> bool {
+        guest
+            .thread_state()
+            .with_detfd(fd, |detfd| {
+                matches!(detfd.net, Some(NetStream::Replaying(_)))
+            })
+            .unwrap_or(false)
+    }
+
+    /// A socket replayed from `--replay-network-from` is never really connected, so the
+    /// kernel's idea of its readiness has nothing to do with the recording, and waiting on
+    /// it with poll, select, or epoll is not supported.  Such calls fail with `ENOTSUP`,
+    /// rather than report a bogus readiness.
+    fn refuse_replayed_sockets<G: Guest<Self>>(
+        guest: &G,
+        name: &str,
+        fds: impl IntoIterator<Item = i32>,
+    ) -> Result<(), Errno> {
+        if guest.config().replay_network_from.is_none() {
+            return Ok(());
+        }
+        for fd in fds {
+            if Self::is_replayed_socket(guest, fd) {
+                error!(
+                    "[detcore, dtid {}] {} is not supported on fd {}, a replayed network socket",
+                    guest.thread_state().dettid,
+                    name,
+                    fd
+                );
+                return Err(Errno::ENOTSUP);
+            }
+        }
+        Ok(())
+    }
+
+    /// `refuse_replayed_sockets` for the array of `nfds` pollfds at `fds`.
+    fn refuse_replayed_sockets_in_poll<G: Guest<Self>>(
+        guest: &G,
+        name: &str,
+        fds: usize,
+        nfds: usize,
+    ) -> Result<(), Errno> {
+        if guest.config().replay_network_from.is_none() || nfds == 0 {
+            return Ok(());
+        }
+        let addr = Addr::<libc::pollfd>::from_raw(fds).ok_or(Errno::EFAULT)?;
+        let empty = libc::pollfd {
+            fd: -1,
+            events: 0,
+            revents: 0,
+        };
+        let mut pollfds = vec![empty; nfds];
+        guest.memory().read_values(addr, &mut pollfds)?;
+        Self::refuse_replayed_sockets(guest, name, pollfds.iter().map(|p| p.fd))
+    }
+
     /// epoll_create1 syscall
     pub async fn handle_epoll_create1<G: Guest<Self>>(
         &self,
@@ -124,6 +491,8 @@ impl<T: RecordOrReplay> Detcore<T> {
         guest: &mut G,
         call: syscalls::EpollCtl,
     ) -> Result<i64, Error> {
+        // Fail with EPERM, as the kernel does for files which cannot be polled.
+        Self::refuse_replayed_sockets(guest, "epoll", [call.fd()]).map_err(|_| Errno::EPERM)?;
         let dettid = guest.thread_state().dettid;
         resource_request(guest, Resources::new(dettid)).await; // empty request
         Ok(self.record_or_replay(guest, call).await?)
@@ -135,9 +504,15 @@ impl<T: RecordOrReplay> Detcore<T> {
         guest: &mut G,
         call: syscalls::EpollPwait,
     ) -> Result<i64, Error> {
-        let dettid = guest.thread_state().dettid;
-        resource_request(guest, Resources::new(dettid)).await; // empty request
-        Ok(self.record_or_replay(guest, call).await?)
+        if !self.cfg.sequentialize_threads || self.cfg.recordreplay_modes {
+            Ok(self
+                .record_or_replay_blocking(guest, Syscall::EpollPwait(call))
+                .await?)
+        } else {
+            let ready = Self::retry_poll(guest, call, millis_to_timeout(call.timeout())).await?;
+            Self::sort_epoll_events(guest, call.events().map(|addr| addr.cast()), ready)?;
+            Ok(ready)
+        }
     }
 
     /// epoll_wait syscall (MAYHANG)
@@ -161,16 +536,27 @@ impl<T: RecordOrReplay> Detcore<T> {
         guest: &mut G,
         call: syscalls::EpollWait,
     ) -> Result<i64, Error> {
-        let timeout_millis = call.timeout();
-        if timeout_millis == 0 {
-            Ok(guest.inject(call).await?) // Already non-blocking.
-        } else {
-            let maybe_timeout_ns = millis_duration_to_absolute_timeout(guest, timeout_millis).await;
-            let mut rsrc = Resources::new(guest.thread_state().dettid);
-            rsrc.insert(ResourceID::InternalIOPolling, Permission::W);
-            rsrc.fyi("epoll_wait");
-            retry_nonblocking_syscall_with_timeout(guest, call, rsrc, maybe_timeout_ns).await
+        let ready = Self::retry_poll(guest, call, millis_to_timeout(call.timeout())).await?;
+        Self::sort_epoll_events(guest, call.events().map(|addr| addr.cast()), ready)?;
+        Ok(ready)
+    }
+
+    /// Put the events reported by an epoll wait in a canonical order: by their user data,
+    /// and then their event mask, rather than the order in which the kernel noticed them
+    /// becoming ready.
+    fn sort_epoll_events<G: Guest<Self>>(
+        guest: &mut G,
+        events: Option<AddrMut<libc::epoll_event>>,
+        ready: i64,
+    ) -> Result<(), Errno> {
+        if let (Some(addr), true) = (events, ready > 1) {
+            let empty = libc::epoll_event { events: 0, u64: 0 };
+            let mut events = vec![empty; ready as usize];
+            guest.memory().read_values(addr.into(), &mut events)?;
+            events.sort_by_key(|ev| (ev.u64, ev.events));
+            guest.memory().write_values(addr, &events)?;
         }
+        Ok(())
     }
 
     /// Connect system call (MAYHANG)
@@ -192,7 +578,291 @@ impl<T: RecordOrReplay> Detcore<T> {
             resource_request(guest, req).await;
         }
 
-        self.execute_nonblockable_fd_syscall(guest, call).await
+        let external = if self.cfg.record_network_to.is_some() {
+            Self::external_peer(guest, &call).map(|(_, peer)| peer)
+        } else if self.cfg.replay_network_from.is_some() {
+            if let Some((addr, peer)) = Self::external_peer(guest, &call) {
+                return self.replay_connect(guest, call.fd(), addr, peer).await;
+            }
+            None
+        } else {
+            None
+        };
+
+        if self.cfg.loopback_networking {
+            self.bind_ephemeral_port(guest, call.fd()).await;
+        }
+        let res = self.execute_nonblockable_fd_syscall(guest, call).await;
+        if let Some(peer) = external {
+            // A nonblocking connect is recorded as soon as it is under way.
+            if matches!(res, Ok(_) | Err(Error::Errno(Errno::EINPROGRESS))) {
+                let id = record_connection(guest, peer).await;
+                let stream = NetStream::Recording(id, SystemTime::now());
+                guest
+                    .thread_state()
+                    .with_detfd(call.fd(), |detfd| detfd.net = Some(stream.clone()))?;
+            }
+        }
+        res
+    }
+
+    /// The address a socket is connecting to, if it is an internet address outside the
+    /// container, whose traffic is recorded or replayed under `--record-network-to` or
+    /// `--replay-network-from`.  This comes with the name of the peer in the recording.
+    fn external_peer<G: Guest<Self>>(
+        guest: &G,
+        call: &syscalls::Connect,
+    ) -> Option<(SocketAddr, String)> {
+        let addr = call.uservaddr()?;
+        let memory = guest.memory();
+        let peer = match i32::from(memory.read_value(addr.cast::<u16>()).ok()?) {
+            libc::AF_INET => {
+                let sin: libc::sockaddr_in = memory.read_value(addr.cast()).ok()?;
+                let ip = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+                SocketAddr::from((ip, u16::from_be(sin.sin_port)))
+            }
+            libc::AF_INET6 => {
+                let sin6: libc::sockaddr_in6 = memory.read_value(addr.cast()).ok()?;
+                let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+                SocketAddr::from((ip, u16::from_be(sin6.sin6_port)))
+            }
+            _ => return None,
+        };
+        if peer.port() == DNS_PORT {
+            // Which resolver the host uses is beside the point, and it may well be on the
+            // loopback interface.
+            Some((peer, DNS_PEER.to_owned()))
+        } else if peer.ip().is_loopback() || peer.ip().is_unspecified() {
+            None
+        } else {
+            Some((peer, peer.to_string()))
+        }
+    }
+
+    /// Connect to an external peer from a recording, without touching the network.  The
+    /// socket itself is left unconnected, and its reads and writes are emulated.
+    async fn replay_connect<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        fd: i32,
+        addr: SocketAddr,
+        peer: String,
+    ) -> Result<i64, Error> {
+        let conn = match replay_connection(guest, peer.clone()).await {
+            Some(conn) => conn,
+            None => {
+                warn!("No (more) recorded connections to {}, refusing", peer);
+                return Err(Errno::ECONNREFUSED.into());
+            }
+        };
+        let now = thread_observe_time(guest).await;
+        let stream = ReplayedStream::new(conn, addr, now);
+        let stream = NetStream::Replaying(Arc::new(Mutex::new(stream)));
+        guest
+            .thread_state()
+            .with_detfd(fd, |detfd| detfd.net = Some(stream.clone()))?;
+        Ok(0)
+    }
+
+    /// Send (or write) on a socket connected to an external peer, from guest buffers given
+    /// as (address, length) pairs.  The bytes are either recorded once they have gone out,
+    /// or checked against the recording and swallowed.
+    pub async fn external_send<G, C>(
+        &self,
+        guest: &mut G,
+        stream: NetStream,
+        call: C,
+        bufs: &[(usize, usize)],
+    ) -> Result<i64, Error>
+    where
+        G: Guest<Self>,
+        C: SyscallInfo + NonblockableSyscall + Into<Syscall>,
+    {
+        let mut data = Self::gather(guest, bufs, usize::MAX)?;
+        match stream {
+            NetStream::Recording(id, start) => {
+                let res = self.execute_nonblockable_fd_syscall(guest, call).await;
+                if let Ok(n) = res {
+                    data.truncate(n as usize);
+                    let elapsed = start.elapsed().unwrap_or_default();
+                    record_net_chunk(guest, id, Direction::Sent, data, elapsed).await;
+                }
+                res
+            }
+            NetStream::Replaying(stream) => {
+                let mut stream = stream.lock().unwrap();
+                if !stream.send(&data) {
+                    warn!(
+                        "Traffic to {} diverged from the recording, sent: {:?}",
+                        stream.peer(),
+                        String::from_utf8_lossy(&data)
+                    );
+                }
+                Ok(data.len() as i64)
+            }
+        }
+    }
+
+    /// Receive (or read) on a socket connected to an external peer, into guest buffers given
+    /// as (address, length) pairs.  The bytes are either recorded, or served from the
+    /// recording at the time they were recorded as arriving.  In the latter case, a
+    /// nonblocking receive fails with `EAGAIN` until then.
+    pub async fn external_recv<G, C>(
+        &self,
+        guest: &mut G,
+        stream: NetStream,
+        call: C,
+        bufs: &[(usize, usize)],
+        nonblocking: bool,
+    ) -> Result<i64, Error>
+    where
+        G: Guest<Self>,
+        C: SyscallInfo + NonblockableSyscall + Into<Syscall>,
+    {
+        match stream {
+            NetStream::Recording(id, start) => {
+                let res = self.execute_nonblockable_fd_syscall(guest, call).await;
+                if let Ok(n) = res {
+                    let data = Self::gather(guest, bufs, n as usize)?;
+                    if !data.is_empty() {
+                        let elapsed = start.elapsed().unwrap_or_default();
+                        record_net_chunk(guest, id, Direction::Received, data, elapsed).await;
+                    }
+                }
+                res
+            }
+            NetStream::Replaying(stream) => {
+                if nonblocking {
+                    let next_arrival = stream.lock().unwrap().next_arrival();
+                    if let Some(arrival) = next_arrival {
+                        if arrival > thread_observe_time(guest).await {
+                            return Err(Errno::EAGAIN.into());
+                        }
+                    }
+                }
+                let len = bufs.iter().map(|(_, len)| len).sum();
+                let (data, arrival) = stream.lock().unwrap().recv(len);
+                if self.cfg.sequentialize_threads && !data.is_empty() {
+                    let request = Self::sleep_request_abs(guest, arrival).await;
+                    resource_request(guest, request).await;
+                }
+                Self::scatter(guest, bufs, &data)?;
+                Ok(data.len() as i64)
+            }
+        }
+    }
+
+    /// Read up to `limit` bytes from guest buffers, given as (address, length) pairs.
+    fn gather<G: Guest<Self>>(
+        guest: &G,
+        bufs: &[(usize, usize)],
+        limit: usize,
+    ) -> Result<Vec<u8>, Errno> {
+        let mut data = Vec::new();
+        for &(base, len) in bufs {
+            let len = len.min(limit - data.len());
+            if len == 0 {
+                continue;
+            }
+            let addr = Addr::<u8>::from_raw(base).ok_or(Errno::EFAULT)?;
+            let start = data.len();
+            data.resize(start + len, 0);
+            guest.memory().read_exact(addr, &mut data[start..])?;
+        }
+        Ok(data)
+    }
+
+    /// Write bytes out across guest buffers, given as (address, length) pairs.
+    fn scatter<G: Guest<Self>>(
+        guest: &mut G,
+        bufs: &[(usize, usize)],
+        mut data: &[u8],
+    ) -> Result<(), Errno> {
+        for &(base, len) in bufs {
+            let len = len.min(data.len());
+            if len == 0 {
+                continue;
+            }
+            let addr = AddrMut::<u8>::from_raw(base).ok_or(Errno::EFAULT)?;
+            guest.memory().write_exact(addr, &data[..len])?;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    /// The buffers of the `msghdr` of a sendmsg or recvmsg, as (address, length) pairs.
+    fn msg_iovecs<G: Guest<Self>>(guest: &G, msg: usize) -> Result<Vec<(usize, usize)>, Error> {
+        let addr = Addr::<libc::msghdr>::from_raw(msg).ok_or(Errno::EFAULT)?;
+        let hdr: libc::msghdr = guest.memory().read_value(addr)?;
+        Self::read_iovecs(guest, hdr.msg_iov as usize, hdr.msg_iovlen as usize)
+    }
+
+    /// A recvmsg on a replayed stream socket reports no peer address, ancillary data, or
+    /// flags, as the kernel would for a connected TCP socket.
+    fn clear_msg_extras<G: Guest<Self>>(guest: &mut G, msg: usize) -> Result<(), Errno> {
+        let addr = Addr::<libc::msghdr>::from_raw(msg).ok_or(Errno::EFAULT)?;
+        let mut hdr: libc::msghdr = guest.memory().read_value(addr)?;
+        hdr.msg_namelen = 0;
+        hdr.msg_controllen = 0;
+        hdr.msg_flags = 0;
+        let addr = AddrMut::<libc::msghdr>::from_raw(msg).ok_or(Errno::EFAULT)?;
+        guest.memory().write_value(addr, &hdr)
+    }
+
+    /// getpeername syscall.  A socket replayed from `--replay-network-from` was never really
+    /// connected, so it reports the address the guest connected to.
+    pub async fn handle_getpeername<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Getpeername,
+    ) -> Result<i64, Error> {
+        let stream = guest
+            .thread_state()
+            .with_detfd(call.fd(), |detfd| detfd.net.clone())
+            .ok()
+            .flatten();
+        let peer = match stream {
+            Some(NetStream::Replaying(stream)) => stream.lock().unwrap().addr(),
+            _ => return Ok(guest.inject(call).await?),
+        };
+        let sockaddr = sockaddr_bytes(peer);
+        let len_raw = call.usockaddr_len().map_or(0, |addr| addr.as_raw());
+        let len_addr = Addr::<libc::socklen_t>::from_raw(len_raw).ok_or(Errno::EFAULT)?;
+        let len: libc::socklen_t = guest.memory().read_value(len_addr)?;
+        // Like the kernel, truncate the address to fit, but report its full length.
+        let n = sockaddr.len().min(len as usize);
+        if n > 0 {
+            let addr_raw = call.usockaddr().map_or(0, |addr| addr.as_raw());
+            let addr = AddrMut::<u8>::from_raw(addr_raw).ok_or(Errno::EFAULT)?;
+            guest.memory().write_exact(addr, &sockaddr[..n])?;
+        }
+        let len_addr = AddrMut::<libc::socklen_t>::from_raw(len_raw).ok_or(Errno::EFAULT)?;
+        guest
+            .memory()
+            .write_value(len_addr, &(sockaddr.len() as libc::socklen_t))?;
+        Ok(0)
+    }
+
+    /// Recognize the socket operations which are traced as `Op::Socket` events under
+    /// `--loopback-networking`.  Reads and writes count if the fd is a socket.
+    pub fn classify_socket_op<G: Guest<Self>>(guest: &G, call: &Syscall) -> Option<SocketOp> {
+        let is_socket = |fd| {
+            guest
+                .thread_state()
+                .with_detfd(fd, |detfd| detfd.ty == FdType::Socket)
+                .unwrap_or(false)
+        };
+        match call {
+            Syscall::Connect(_) => Some(SocketOp::Connect),
+            Syscall::Accept(_) | Syscall::Accept4(_) => Some(SocketOp::Accept),
+            Syscall::Sendto(_) | Syscall::Sendmsg(_) | Syscall::Sendmmsg(_) => Some(SocketOp::Send),
+            Syscall::Recvfrom(_) | Syscall::Recvmsg(_) | Syscall::Recvmmsg(_) => {
+                Some(SocketOp::Recv)
+            }
+            Syscall::Read(s) if is_socket(s.fd()) => Some(SocketOp::Recv),
+            Syscall::Write(s) if is_socket(s.fd()) => Some(SocketOp::Send),
+            _ => None,
+        }
     }
 
     /// Handles all of: recvfrom, recvmsg, sendto, sendmsg, sendmmsg syscalls (MAYHANG)
@@ -204,6 +874,104 @@ impl<T: RecordOrReplay> Detcore<T> {
         guest: &mut G,
         call: C,
     ) -> Result<i64, Error> {
+        let wrapped: Syscall = call.into();
+        if self.cfg.record_network_to.is_some() || self.cfg.replay_network_from.is_some() {
+            let stream = get_fd(wrapped).and_then(|fd| {
+                guest
+                    .thread_state()
+                    .with_detfd(fd, |detfd| detfd.net.clone())
+                    .ok()
+                    .flatten()
+            });
+            let nonblocking = |guest: &G, fd, flags: i32| {
+                flags & libc::MSG_DONTWAIT != 0
+                    || guest
+                        .thread_state()
+                        .with_detfd(fd, |detfd| detfd.is_nonblocking())
+                        .unwrap_or(false)
+            };
+            match (wrapped, stream) {
+                (Syscall::Sendto(s), Some(stream)) => {
+                    let bufs = [(s.buf().map_or(0, |addr| addr.as_raw()), s.len())];
+                    return self.external_send(guest, stream, call, &bufs).await;
+                }
+                (Syscall::Sendmsg(s), Some(stream)) => {
+                    let msg = s.msg().map_or(0, |addr| addr.as_raw());
+                    let bufs = Self::msg_iovecs(guest, msg)?;
+                    return self.external_send(guest, stream, call, &bufs).await;
+                }
+                (Syscall::Recvfrom(s), Some(stream)) => {
+                    let bufs = [(s.buf().map_or(0, |addr| addr.as_raw()), s.len())];
+                    let nonblocking = nonblocking(guest, s.fd(), s.flags().bits());
+                    return self
+                        .external_recv(guest, stream, call, &bufs, nonblocking)
+                        .await;
+                }
+                (Syscall::Recvmsg(s), Some(stream)) => {
+                    let msg = s.msg().map_or(0, |addr| addr.as_raw());
+                    let bufs = Self::msg_iovecs(guest, msg)?;
+                    let nonblocking = nonblocking(guest, s.sockfd(), s.flags().bits());
+                    let replaying = matches!(stream, NetStream::Replaying(_));
+                    let res = self
+                        .external_recv(guest, stream, call, &bufs, nonblocking)
+                        .await?;
+                    if replaying {
+                        Self::clear_msg_extras(guest, msg)?;
+                    }
+                    return Ok(res);
+                }
+                _ => {}
+            }
+        }
+        if self.cfg.loopback_networking {
+            if let (Syscall::Sendto(_) | Syscall::Sendmsg(_) | Syscall::Sendmmsg(_), Some(fd)) =
+                (wrapped, get_fd(wrapped))
+            {
+                self.bind_ephemeral_port(guest, fd).await;
+            }
+        }
         self.execute_nonblockable_fd_syscall(guest, call).await
     }
+
+    /// Under `--loopback-networking`, give an internet socket which is about to connect or
+    /// send without having been bound a port from the deterministic pool (see `handle_bind`),
+    /// rather than letting the kernel pick an ephemeral port.
+    async fn bind_ephemeral_port<G: Guest<Self>>(&self, guest: &mut G, fd: i32) {
+        let (addr, addrlen, _guard) = {
+            let mut stack = guest.stack().await;
+            let addr = stack.push(unsafe { std::mem::zeroed::<libc::sockaddr_storage>() });
+            let addrlen =
+                stack.push(std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t);
+            let guard = stack.commit().expect("stack.commit to succeed");
+            (addr, addrlen, guard)
+        };
+        let getsockname = syscalls::Getsockname::new()
+            .with_fd(fd)
+            .with_usockaddr(AddrMut::from_raw(addr.as_raw()))
+            .with_usockaddr_len(AddrMut::from_raw(addrlen.as_raw()));
+        if guest.inject(getsockname).await.is_err() {
+            // Not a socket; the call itself will report that.
+            return;
+        }
+
+        // An unbound socket reports the wildcard address, which is also what we bind to.
+        let memory = guest.memory();
+        let family = memory.read_value(addr.cast::<u16>()).map_or(0, i32::from);
+        // The port sits in the same place in sockaddr_in and sockaddr_in6.
+        let port = memory
+            .read_value(addr.cast::<libc::sockaddr_in>())
+            .map_or(0, |sin| sin.sin_port);
+        let len = memory.read_value(addrlen).unwrap_or(0);
+        if (family != libc::AF_INET && family != libc::AF_INET6) || port != 0 {
+            return;
+        }
+        let bind = syscalls::Bind::new()
+            .with_fd(fd)
+            .with_umyaddr(Some(addr.cast()))
+            .with_addrlen(len as _);
+        if let Err(err) = self.handle_bind(guest, bind).await {
+            // Fall back on the kernel's choice of port.
+            debug!("Could not bind fd {} to a deterministic port: {:?}", fd, err);
+        }
+    }
 }
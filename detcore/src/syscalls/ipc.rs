@@ -0,0 +1,300 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! System V and POSIX IPC.
+//!
+//! The container has an IPC namespace of its own, so keys and queue names do not collide
+//! with the host, and the ids handed out by the kernel are the same on every run.  What is
+//! left is to keep the blocking operations from blocking in the kernel: semaphore
+//! operations and message queue sends and receives are retried without blocking, at points
+//! chosen by the scheduler, like other internal IO.
+
+use reverie::syscalls;
+use reverie::syscalls::Addr;
+use reverie::syscalls::AddrMut;
+use reverie::syscalls::Errno;
+use reverie::syscalls::MemoryAccess;
+use reverie::syscalls::Syscall;
+use reverie::syscalls::SyscallInfo;
+use reverie::syscalls::Timespec;
+use reverie::Error;
+use reverie::Guest;
+use reverie::Stack;
+use tracing::trace;
+
+use crate::address_space::page_align;
+use crate::record_or_replay::RecordOrReplay;
+use crate::resources::Permission;
+use crate::resources::ResourceID;
+use crate::resources::Resources;
+use crate::syscalls::helpers::nanos_duration_to_absolute_timeout;
+use crate::syscalls::helpers::retry_nonblocking_syscall_with_timeout;
+use crate::syscalls::helpers::NonblockableSyscall;
+use crate::syscalls::helpers::TimeoutableSyscall;
+use crate::timers::timespec_to_duration;
+use crate::tool_global::thread_observe_time;
+use crate::tool_local::Detcore;
+use crate::types::LogicalTime;
+
+impl<T: RecordOrReplay> Detcore<T> {
+    /// semop system call (MAYHANG), which is semtimedop without a timeout.
+    pub async fn handle_semop<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Semop,
+    ) -> Result<i64, Error> {
+        let call = syscalls::Semtimedop::new()
+            .with_semid(call.semid())
+            .with_tsops(call.tsops())
+            .with_nsops(call.nsops());
+        self.handle_semtimedop(guest, call).await
+    }
+
+    /// semtimedop system call (MAYHANG).
+    pub async fn handle_semtimedop<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Semtimedop,
+    ) -> Result<i64, Error> {
+        if !self.cfg.sequentialize_threads || self.cfg.recordreplay_modes {
+            return Ok(self
+                .record_or_replay_blocking(guest, Syscall::Semtimedop(call))
+                .await?);
+        }
+        let addr = call.tsops().ok_or(Errno::EFAULT)?;
+        let empty = libc::sembuf {
+            sem_num: 0,
+            sem_op: 0,
+            sem_flg: 0,
+        };
+        let mut sops = vec![empty; call.nsops()];
+        guest.memory().read_values(addr.cast(), &mut sops)?;
+        if sops.iter().any(|sop| sop.sem_flg & libc::IPC_NOWAIT as i16 != 0) {
+            // Some operation would fail rather than block, which retrying would hide.
+            return Ok(self.record_or_replay(guest, call).await?);
+        }
+        let timeout = match call.timeout() {
+            Some(addr) => {
+                let ts: libc::timespec = guest.memory().read_value(addr.cast())?;
+                let timeout = timespec_to_duration(&ts).ok_or(Errno::EINVAL)?;
+                Some(timeout.as_nanos())
+            }
+            None => None,
+        };
+        Self::retry_ipc(guest, call, timeout).await
+    }
+
+    /// msgsnd system call (MAYHANG).
+    pub async fn handle_msgsnd<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Msgsnd,
+    ) -> Result<i64, Error> {
+        if !self.cfg.sequentialize_threads
+            || self.cfg.recordreplay_modes
+            || call.msgflg() & libc::IPC_NOWAIT != 0
+        {
+            return Ok(self
+                .record_or_replay_blocking(guest, Syscall::Msgsnd(call))
+                .await?);
+        }
+        Self::retry_ipc(guest, call, None).await
+    }
+
+    /// msgrcv system call (MAYHANG).
+    pub async fn handle_msgrcv<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Msgrcv,
+    ) -> Result<i64, Error> {
+        if !self.cfg.sequentialize_threads
+            || self.cfg.recordreplay_modes
+            || call.msgflg() & libc::IPC_NOWAIT != 0
+        {
+            return Ok(self
+                .record_or_replay_blocking(guest, Syscall::Msgrcv(call))
+                .await?);
+        }
+        Self::retry_ipc(guest, call, None).await
+    }
+
+    /// mq_timedsend system call (MAYHANG).
+    pub async fn handle_mq_timedsend<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::MqTimedsend,
+    ) -> Result<i64, Error> {
+        if !self.cfg.sequentialize_threads || self.cfg.recordreplay_modes {
+            return Ok(self
+                .record_or_replay_blocking(guest, Syscall::MqTimedsend(call))
+                .await?);
+        }
+        let timeout = Self::mq_timeout(guest, call.abs_timeout()).await?;
+        Self::retry_ipc(guest, call, timeout).await
+    }
+
+    /// mq_timedreceive system call (MAYHANG).
+    pub async fn handle_mq_timedreceive<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::MqTimedreceive,
+    ) -> Result<i64, Error> {
+        if !self.cfg.sequentialize_threads || self.cfg.recordreplay_modes {
+            return Ok(self
+                .record_or_replay_blocking(guest, Syscall::MqTimedreceive(call))
+                .await?);
+        }
+        let timeout = Self::mq_timeout(guest, call.abs_timeout()).await?;
+        Self::retry_ipc(guest, call, timeout).await
+    }
+
+    /// The timeout of a POSIX message queue operation is an absolute (realtime) deadline,
+    /// which is turned into the time left from now, in logical time.
+    async fn mq_timeout<G: Guest<Self>>(
+        guest: &mut G,
+        abs_timeout: Option<Addr<'_, Timespec>>,
+    ) -> Result<Option<u128>, Error> {
+        match abs_timeout {
+            Some(addr) => {
+                let t: Timespec = guest.memory().read_value(addr)?;
+                if t.tv_sec < 0 || !(0..1_000_000_000).contains(&t.tv_nsec) {
+                    return Err(Errno::EINVAL.into());
+                }
+                let deadline = LogicalTime::from_secs(t.tv_sec as u64)
+                    + LogicalTime::from_nanos(t.tv_nsec as u64);
+                let now = thread_observe_time(guest).await;
+                // An expired deadline means trying just once, as with a zero timeout.
+                Ok(Some(if deadline > now {
+                    deadline.duration_since(now).as_nanos()
+                } else {
+                    0
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Wait for an IPC operation to go through, by retrying it without blocking, for up to
+    /// `timeout_nanos` of logical time (forever, if `None`).
+    async fn retry_ipc<G, C>(
+        guest: &mut G,
+        call: C,
+        timeout_nanos: Option<u128>,
+    ) -> Result<i64, Error>
+    where
+        G: Guest<Self>,
+        C: NonblockableSyscall + TimeoutableSyscall + Into<Syscall>,
+    {
+        if timeout_nanos == Some(0) {
+            let (call, _guard) = call.into_nonblocking(guest).await;
+            return Ok(guest.inject(call).await?);
+        }
+        let maybe_timeout = match timeout_nanos {
+            Some(nanos) => nanos_duration_to_absolute_timeout(guest, nanos).await,
+            None => None,
+        };
+        let mut rsrc = Resources::new(guest.thread_state().dettid);
+        rsrc.insert(ResourceID::InternalIOPolling, Permission::W);
+        rsrc.fyi(call.name());
+        retry_nonblocking_syscall_with_timeout(guest, call, rsrc, maybe_timeout).await
+    }
+
+    /// shmat system call.  With `--deterministic-mmap`, a segment attached without an
+    /// address goes at the lowest free address of the deterministic window, as with mmap.
+    pub async fn handle_shmat<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Shmat,
+    ) -> Result<i64, Error> {
+        guest
+            .thread_state()
+            .address_space
+            .lock()
+            .unwrap()
+            .mappings_changed();
+        if !self.cfg.deterministic_mmap {
+            return Ok(self.record_or_replay(guest, call).await?);
+        }
+        if let Some(addr) = call.shmaddr() {
+            let res = self.record_or_replay(guest, call).await?;
+            let len = Self::shm_segment_size(guest, call.shmid()).await?;
+            trace!("shmat: segment attached at {:#x}", addr.as_raw());
+            guest
+                .thread_state()
+                .address_space
+                .lock()
+                .unwrap()
+                .reserve(res as u64, len);
+            return Ok(res);
+        }
+        let len = Self::shm_segment_size(guest, call.shmid()).await?;
+        self.check_memory_limit(guest, page_align(len))?;
+        let addr = guest
+            .thread_state()
+            .address_space
+            .lock()
+            .unwrap()
+            .allocate(len)
+            .ok_or(Errno::ENOMEM)?;
+        let placed = call.with_shmaddr(AddrMut::from_raw(addr as usize));
+        match self.record_or_replay(guest, placed).await {
+            Ok(res) => Ok(res),
+            Err(err) => {
+                guest
+                    .thread_state()
+                    .address_space
+                    .lock()
+                    .unwrap()
+                    .release(addr, len);
+                Err(err.into())
+            }
+        }
+    }
+
+    /// shmdt system call.
+    pub async fn handle_shmdt<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Shmdt,
+    ) -> Result<i64, Error> {
+        guest
+            .thread_state()
+            .address_space
+            .lock()
+            .unwrap()
+            .mappings_changed();
+        let res = self.record_or_replay(guest, call).await?;
+        if self.cfg.deterministic_mmap {
+            let addr = call.shmaddr().map_or(0, |a| a.as_raw()) as u64;
+            guest
+                .thread_state()
+                .address_space
+                .lock()
+                .unwrap()
+                .release_region(addr);
+        }
+        Ok(res)
+    }
+
+    /// The size of a shared memory segment.
+    async fn shm_segment_size<G: Guest<Self>>(guest: &mut G, shmid: i32) -> Result<u64, Error> {
+        let (buf, _guard) = {
+            let mut stack = guest.stack().await;
+            let buf = stack.push(unsafe { std::mem::zeroed::<libc::shmid_ds>() });
+            let guard = stack.commit().expect("stack.commit to succeed");
+            (buf, guard)
+        };
+        let stat = syscalls::Shmctl::new()
+            .with_shmid(shmid)
+            .with_cmd(libc::IPC_STAT)
+            .with_buf(AddrMut::from_raw(buf.as_raw()));
+        guest.inject(stat).await?;
+        let ds = guest.memory().read_value(buf)?;
+        Ok(ds.shm_segsz as u64)
+    }
+}
@@ -0,0 +1,312 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! System calls dealing with the layout of the guest address space, see
+//! `--deterministic-mmap`.
+
+use nix::sys::mman::MRemapFlags;
+use reverie::syscalls;
+use reverie::syscalls::AddrMut;
+use reverie::syscalls::Errno;
+use reverie::syscalls::MapFlags;
+use reverie::syscalls::ProtFlags;
+use reverie::Error;
+use reverie::Guest;
+use tracing::debug;
+use tracing::trace;
+
+use crate::address_space::page_align;
+use crate::address_space::BRK_BASE;
+use crate::address_space::BRK_END;
+use crate::record_or_replay::RecordOrReplay;
+use crate::tool_local::Detcore;
+
+impl<T: RecordOrReplay> Detcore<T> {
+    /// mmap with `--deterministic-mmap`.  Unless the guest insists on an address with
+    /// `MAP_FIXED`, the mapping goes at the lowest free address of the deterministic window.
+    /// (Address hints are ignored, which the kernel is also free to do.)
+    pub async fn handle_deterministic_mmap<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Mmap,
+    ) -> Result<i64, Error> {
+        let len = call.len() as u64;
+        if call
+            .flags()
+            .intersects(MapFlags::MAP_FIXED | MapFlags::MAP_FIXED_NOREPLACE)
+        {
+            let addr = self.record_or_replay(guest, call).await?;
+            guest
+                .thread_state()
+                .address_space
+                .lock()
+                .unwrap()
+                .reserve(addr as u64, len);
+            return Ok(addr);
+        }
+
+        loop {
+            let addr = Self::allocate_address(guest, len)?;
+            let placed = call
+                .with_addr(AddrMut::from_raw(addr as usize))
+                .with_flags(call.flags() | MapFlags::MAP_FIXED_NOREPLACE);
+            match self.record_or_replay(guest, placed).await {
+                Ok(ret) => {
+                    if ret as u64 != addr {
+                        // Kernels before 4.17 take MAP_FIXED_NOREPLACE as a mere hint.
+                        let mut space = guest.thread_state().address_space.lock().unwrap();
+                        space.release(addr, len);
+                        space.reserve(ret as u64, len);
+                    }
+                    return Ok(ret);
+                }
+                Err(Errno::EEXIST) => {
+                    // Something we did not place (e.g. the binary, mapped at exec time) is
+                    // in the way.  Leave the range marked as occupied and try further up.
+                    trace!("mmap: {:#x} (+{:#x}) is occupied, retrying", addr, len);
+                }
+                Err(err) => {
+                    guest
+                        .thread_state()
+                        .address_space
+                        .lock()
+                        .unwrap()
+                        .release(addr, len);
+                    return Err(err.into());
+                }
+            }
+        }
+    }
+
+    /// With `--memory-limit`, fails an allocation of `growth` more bytes which would take this
+    /// address space over the limit, as the kernel would when truly out of memory.
+    pub(crate) fn check_memory_limit<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        growth: u64,
+    ) -> Result<(), Errno> {
+        if let Some(limit) = self.cfg.memory_limit {
+            let mapped = guest.thread_state().address_space.lock().unwrap().mapped();
+            if growth > 0 && mapped.saturating_add(growth) > limit {
+                debug!(
+                    "--memory-limit: failing an allocation of {} bytes, with {} of {} in use",
+                    growth, mapped, limit
+                );
+                return Err(Errno::ENOMEM);
+            }
+        }
+        Ok(())
+    }
+
+    /// `check_memory_limit` for an mmap, which only grows the address space by the part of it
+    /// which does not replace an existing mapping.
+    pub(crate) fn check_mmap_memory_limit<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Mmap,
+    ) -> Result<(), Errno> {
+        let len = call.len() as u64;
+        let growth = if call.flags().contains(MapFlags::MAP_FIXED) {
+            let addr = call.addr().map_or(0, |a| a.as_raw()) as u64;
+            let space = guest.thread_state().address_space.lock().unwrap();
+            space.unoccupied(addr, len)
+        } else {
+            page_align(len)
+        };
+        self.check_memory_limit(guest, growth)
+    }
+
+    /// munmap system call.
+    pub async fn handle_munmap<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Munmap,
+    ) -> Result<i64, Error> {
+        guest
+            .thread_state()
+            .address_space
+            .lock()
+            .unwrap()
+            .mappings_changed();
+        let ret = self.record_or_replay(guest, call).await?;
+        if self.cfg.deterministic_mmap {
+            let addr = call.addr().map_or(0, |a| a.as_raw()) as u64;
+            guest
+                .thread_state()
+                .address_space
+                .lock()
+                .unwrap()
+                .release(addr, call.len() as u64);
+        }
+        Ok(ret)
+    }
+
+    /// mremap system call.  With `--deterministic-mmap`, a mapping which must move to grow
+    /// is moved to the lowest free address of the deterministic window.
+    pub async fn handle_mremap<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Mremap,
+    ) -> Result<i64, Error> {
+        guest
+            .thread_state()
+            .address_space
+            .lock()
+            .unwrap()
+            .mappings_changed();
+        if !self.cfg.deterministic_mmap {
+            return Ok(self.record_or_replay(guest, call).await?);
+        }
+        let old_addr = call.old_address().map_or(0, |a| a.as_raw()) as u64;
+        let (old_len, new_len) = (call.old_size() as u64, call.new_size() as u64);
+        let flags = call.flags();
+        self.check_memory_limit(
+            guest,
+            page_align(new_len).saturating_sub(page_align(old_len)),
+        )?;
+
+        let may_move = flags.contains(MRemapFlags::MREMAP_MAYMOVE);
+        if may_move
+            && !flags.contains(MRemapFlags::MREMAP_FIXED)
+            && page_align(new_len) > page_align(old_len)
+        {
+            // Grow in place if we can, which leaves the kernel no choice to make:
+            let in_place = call.with_flags(flags - MRemapFlags::MREMAP_MAYMOVE);
+            match self.record_or_replay(guest, in_place).await {
+                Ok(ret) => {
+                    self.remapped(guest, old_addr, old_len, ret as u64, new_len);
+                    return Ok(ret);
+                }
+                Err(Errno::ENOMEM) => {}
+                Err(err) => return Err(err.into()),
+            }
+            // Otherwise claim a new range with a placeholder mapping, and move over it.
+            // (MREMAP_FIXED would silently clobber anything already there.)
+            let new_addr = self.map_placeholder(guest, new_len).await?;
+            let moved = call
+                .with_flags(flags | MRemapFlags::MREMAP_FIXED)
+                .with_new_address(AddrMut::from_raw(new_addr as usize));
+            return match self.record_or_replay(guest, moved).await {
+                Ok(ret) => {
+                    self.remapped(guest, old_addr, old_len, ret as u64, new_len);
+                    Ok(ret)
+                }
+                Err(err) => {
+                    let unmap = syscalls::Munmap::new()
+                        .with_addr(AddrMut::from_raw(new_addr as usize))
+                        .with_len(new_len as usize);
+                    self.handle_munmap(guest, unmap).await?;
+                    Err(err.into())
+                }
+            };
+        }
+
+        let ret = self.record_or_replay(guest, call).await?;
+        self.remapped(guest, old_addr, old_len, ret as u64, new_len);
+        Ok(ret)
+    }
+
+    /// brk system call.  With `--deterministic-mmap` the program break is emulated, in a
+    /// window of its own, with anonymous mappings.
+    pub async fn handle_brk<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Brk,
+    ) -> Result<i64, Error> {
+        if !self.cfg.deterministic_mmap {
+            return Ok(self.record_or_replay(guest, call).await?);
+        }
+        let current = guest.thread_state().address_space.lock().unwrap().brk();
+        let requested = call.addr().map_or(0, |a| a.as_raw()) as u64;
+        // As with the kernel, an invalid request just reports the current break.
+        if !(BRK_BASE..=BRK_END).contains(&requested) {
+            return Ok(current as i64);
+        }
+
+        let (mapped_end, new_end) = (page_align(current), page_align(requested));
+        if new_end > mapped_end {
+            if self
+                .check_memory_limit(guest, new_end - mapped_end)
+                .is_err()
+            {
+                return Ok(current as i64);
+            }
+            let grow = syscalls::Mmap::new()
+                .with_addr(AddrMut::from_raw(mapped_end as usize))
+                .with_len((new_end - mapped_end) as usize)
+                .with_prot(ProtFlags::PROT_READ | ProtFlags::PROT_WRITE)
+                .with_flags(
+                    MapFlags::MAP_PRIVATE
+                        | MapFlags::MAP_ANONYMOUS
+                        | MapFlags::MAP_FIXED_NOREPLACE,
+                )
+                .with_fd(-1);
+            if self.record_or_replay(guest, grow).await.is_err() {
+                return Ok(current as i64);
+            }
+        } else if new_end < mapped_end {
+            let shrink = syscalls::Munmap::new()
+                .with_addr(AddrMut::from_raw(new_end as usize))
+                .with_len((mapped_end - new_end) as usize);
+            self.record_or_replay(guest, shrink).await?;
+        }
+        trace!("brk: moving the program break {:#x} => {:#x}", current, requested);
+        guest
+            .thread_state()
+            .address_space
+            .lock()
+            .unwrap()
+            .set_brk(requested);
+        Ok(requested as i64)
+    }
+
+    /// Pick the next deterministic address for a mapping of the given length.
+    fn allocate_address<G: Guest<Self>>(guest: &mut G, len: u64) -> Result<u64, Errno> {
+        guest
+            .thread_state()
+            .address_space
+            .lock()
+            .unwrap()
+            .allocate(len)
+            .ok_or(Errno::ENOMEM)
+    }
+
+    /// Claim the next free range of the deterministic window with an inaccessible
+    /// mapping, returning its address.
+    async fn map_placeholder<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        len: u64,
+    ) -> Result<u64, Error> {
+        let placeholder = syscalls::Mmap::new()
+            .with_len(len as usize)
+            .with_prot(ProtFlags::PROT_NONE)
+            .with_flags(MapFlags::MAP_PRIVATE | MapFlags::MAP_ANONYMOUS)
+            .with_fd(-1);
+        Ok(self.handle_deterministic_mmap(guest, placeholder).await? as u64)
+    }
+
+    /// Update the address space after a successful mremap.
+    fn remapped<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        old_addr: u64,
+        old_len: u64,
+        new_addr: u64,
+        new_len: u64,
+    ) {
+        let mut space = guest.thread_state().address_space.lock().unwrap();
+        if new_addr != old_addr {
+            space.release(old_addr, old_len);
+        } else if page_align(new_len) < page_align(old_len) {
+            let new_end = old_addr + page_align(new_len);
+            space.release(new_end, page_align(old_len) - page_align(new_len));
+        }
+        space.reserve(new_addr, new_len);
+    }
+}
@@ -14,18 +14,51 @@ use std::hash::Hasher;
 
 use chrono::DateTime;
 use chrono::Local;
-use rand::Rng;
 use reverie::syscalls;
+use reverie::syscalls::Addr;
 use reverie::syscalls::Errno;
 use reverie::syscalls::MemoryAccess;
+use reverie::syscalls::Syscall;
+use reverie::syscalls::SyscallInfo;
+use reverie::syscalls::Sysno;
 use reverie::Error;
 use reverie::Guest;
+use tracing::warn;
 
-use crate::consts::DEFAULT_HOSTNAME;
+use crate::audit::Imperfection;
+use crate::config::IoUringMode;
 use crate::detlog;
 use crate::record_or_replay::RecordOrReplay;
+use crate::resources::Resources;
+use crate::tool_global::audit_syscall;
+use crate::tool_global::fill_guest_random;
+use crate::tool_global::resource_request;
 use crate::tool_local::Detcore;
 
+// membarrier(2) commands, which the libc crate does not define.
+const MEMBARRIER_CMD_QUERY: i32 = 0;
+
+// io_uring_enter(2) flag asking to wait for completions, which the libc crate does not define.
+const IORING_ENTER_GETEVENTS: u32 = 1 << 0;
+const MEMBARRIER_CMD_GLOBAL: i32 = 1 << 0;
+const MEMBARRIER_CMD_GLOBAL_EXPEDITED: i32 = 1 << 1;
+const MEMBARRIER_CMD_REGISTER_GLOBAL_EXPEDITED: i32 = 1 << 2;
+const MEMBARRIER_CMD_PRIVATE_EXPEDITED: i32 = 1 << 3;
+const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED: i32 = 1 << 4;
+const MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE: i32 = 1 << 5;
+const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE: i32 = 1 << 6;
+const MEMBARRIER_CMD_GET_REGISTRATIONS: i32 = 1 << 9;
+
+/// The membarrier commands we emulate.  (The rseq ones are left out, as rseq is not.)
+const MEMBARRIER_SUPPORTED: i32 = MEMBARRIER_CMD_GLOBAL
+    | MEMBARRIER_CMD_GLOBAL_EXPEDITED
+    | MEMBARRIER_CMD_REGISTER_GLOBAL_EXPEDITED
+    | MEMBARRIER_CMD_PRIVATE_EXPEDITED
+    | MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED
+    | MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE
+    | MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE
+    | MEMBARRIER_CMD_GET_REGISTRATIONS;
+
 fn from_str(s: &str) -> [i8; 65] {
     let mut ret: [i8; 65] = [0; 65];
     for (i, ch) in s.bytes().take(64).enumerate() {
@@ -49,11 +82,12 @@ impl<T: RecordOrReplay> Detcore<T> {
             if !guest.config().has_uts_namespace {
                 // FIXME: It should be possible to remove this once all tests
                 // are also using namespaces.
-                un.nodename = from_str(DEFAULT_HOSTNAME);
-                un.domainname = from_str(DEFAULT_HOSTNAME.split('.').last().unwrap_or(""));
+                let hostname = &guest.config().hostname;
+                un.nodename = from_str(hostname);
+                un.domainname = from_str(hostname.split('.').last().unwrap_or(""));
             }
 
-            un.release = from_str("5.2.0");
+            un.release = from_str(&guest.config().uname_release);
             un.version = from_str(&format!("#1 SMP {}", epoch.format("%a %b %d %T %Z %Y")));
             guest.memory().write_value(buf, &un)?;
         }
@@ -61,6 +95,66 @@ impl<T: RecordOrReplay> Detcore<T> {
         Ok(ret)
     }
 
+    /// getuid, geteuid, getgid, and getegid system calls.  Reports the id fixed with `--uid`
+    /// or `--gid`, if any.
+    pub async fn handle_getid<G: Guest<Self>, S: Into<Syscall>>(
+        &self,
+        guest: &mut G,
+        call: S,
+        id: Option<u32>,
+    ) -> Result<i64, Error> {
+        match id {
+            Some(id) => Ok(id as i64),
+            None => Ok(self.record_or_replay(guest, call).await?),
+        }
+    }
+
+    /// io_uring_setup system call, see `--io-uring`.
+    pub async fn handle_io_uring_setup<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::IoUringSetup,
+    ) -> Result<i64, Error> {
+        match guest.config().io_uring {
+            IoUringMode::Disable => Err(Errno::ENOSYS.into()),
+            IoUringMode::Passthrough => {
+                if guest.config().audit_syscalls {
+                    audit_syscall(guest, call.number(), Imperfection::PassedThrough).await;
+                }
+                Ok(self.record_or_replay(guest, call).await?)
+            }
+        }
+    }
+
+    /// io_uring_enter and io_uring_register system calls, which are only reachable under
+    /// `--io-uring=passthrough`.  Each is a scheduler point, so that submissions, and the waits
+    /// for their completions, happen in a deterministic order among the guest's threads.  What
+    /// the kernel does with a submission, and when it completes, is still up to the host.
+    pub async fn handle_io_uring_call<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: Syscall,
+    ) -> Result<i64, Error> {
+        if guest.config().audit_syscalls {
+            audit_syscall(guest, call.number(), Imperfection::PassedThrough).await;
+        }
+        if !self.cfg.sequentialize_threads || self.cfg.recordreplay_modes {
+            return Ok(self.record_or_replay(guest, call).await?);
+        }
+        let (sysno, args) = call.into_parts();
+        let min_complete = args.arg2 as u32;
+        let flags = args.arg3 as u32;
+        let waits = flags & IORING_ENTER_GETEVENTS != 0 && min_complete > 0;
+        if sysno == Sysno::io_uring_enter && waits {
+            // Waiting for completions may block on the host, so it happens in the background.
+            Ok(self.record_or_replay_blocking(guest, call).await?)
+        } else {
+            let dettid = guest.thread_state().dettid;
+            resource_request(guest, Resources::new(dettid)).await; // empty request
+            Ok(self.record_or_replay(guest, call).await?)
+        }
+    }
+
     /// getrandon system call
     pub async fn handle_getrandom<G: Guest<Self>>(
         &self,
@@ -70,10 +164,7 @@ impl<T: RecordOrReplay> Detcore<T> {
         let buf = call.buf().ok_or(Errno::EFAULT)?;
 
         let mut local_buf: Vec<u8> = vec![0; call.buflen()];
-        guest
-            .thread_state_mut()
-            .thread_prng()
-            .fill(local_buf.as_mut_slice());
+        fill_guest_random(guest, local_buf.as_mut_slice()).await;
         let n = guest.memory().write(buf, local_buf.as_slice())?;
         if cfg!(debug_assertions) {
             let mut hasher = DefaultHasher::new();
@@ -123,4 +214,97 @@ impl<T: RecordOrReplay> Detcore<T> {
 
         Ok(0)
     }
+
+    /// rseq system call.  When sequentializing, restartable sequences are reported as
+    /// unsupported, as on a kernel without them.  The kernel would keep the cpu id in the
+    /// registered area up to date with the *real* CPU, and we cannot abort a critical
+    /// section when detcore preempts the thread inside one, as the kernel does when it
+    /// preempts a thread.  The C library then falls back on getcpu, which reports the
+    /// virtual CPU.
+    pub async fn handle_rseq<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Rseq,
+    ) -> Result<i64, Error> {
+        if guest.config().sequentialize_threads {
+            if guest.config().audit_syscalls {
+                audit_syscall(guest, call.number(), Imperfection::Stubbed).await;
+            }
+            Err(Errno::ENOSYS.into())
+        } else {
+            Ok(self.record_or_replay(guest, call).await?)
+        }
+    }
+
+    /// perf_event_open system call.  When sequentializing, performance monitoring is reported
+    /// as unsupported, as on a kernel without it.  The counters would measure the host, and
+    /// whether they can be opened at all depends on the host's perf_event_paranoid setting and
+    /// on the counters hermit itself uses, so self-profiling guests would diverge between runs.
+    pub async fn handle_perf_event_open<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::PerfEventOpen,
+    ) -> Result<i64, Error> {
+        if !guest.config().sequentialize_threads {
+            return Ok(self.record_or_replay(guest, call).await?);
+        }
+        let (_, args) = Syscall::PerfEventOpen(call).into_parts();
+        // The attributes start with the event's type, then their own size, then its config.
+        let attr = Addr::<[u64; 2]>::from_raw(args.arg0).ok_or(Errno::EFAULT)?;
+        let [type_and_size, config] = guest.memory().read_value(attr)?;
+        warn!(
+            "[dtid {}] perf_event_open (type {}, config {:#x}) is unsupported under hermit, failing with ENOSYS",
+            guest.thread_state().dettid,
+            type_and_size as u32,
+            config
+        );
+        if guest.config().audit_syscalls {
+            audit_syscall(guest, call.number(), Imperfection::Stubbed).await;
+        }
+        Err(Errno::ENOSYS.into())
+    }
+
+    /// membarrier system call.  When sequentializing, only one guest thread runs at a time,
+    /// and every switch between threads already orders their memory accesses, so the
+    /// barriers themselves are no-ops.  What is emulated is the bookkeeping: which commands
+    /// are supported, and which the process has registered for.
+    pub async fn handle_membarrier<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Membarrier,
+    ) -> Result<i64, Error> {
+        if !guest.config().sequentialize_threads {
+            return Ok(self.record_or_replay(guest, call).await?);
+        }
+        if call.flags() != 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let mut registrations = guest.thread_state().membarrier_registrations.lock().unwrap();
+        let res = match call.cmd() {
+            MEMBARRIER_CMD_QUERY => MEMBARRIER_SUPPORTED,
+            MEMBARRIER_CMD_GLOBAL | MEMBARRIER_CMD_GLOBAL_EXPEDITED => 0,
+            cmd @ (MEMBARRIER_CMD_REGISTER_GLOBAL_EXPEDITED
+            | MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED
+            | MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE) => {
+                *registrations |= cmd;
+                0
+            }
+            MEMBARRIER_CMD_PRIVATE_EXPEDITED
+                if *registrations & MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED != 0 =>
+            {
+                0
+            }
+            MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE
+                if *registrations & MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE != 0 =>
+            {
+                0
+            }
+            MEMBARRIER_CMD_PRIVATE_EXPEDITED | MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE => {
+                return Err(Errno::EPERM.into());
+            }
+            MEMBARRIER_CMD_GET_REGISTRATIONS => *registrations,
+            _ => return Err(Errno::EINVAL.into()),
+        };
+        Ok(res as i64)
+    }
 }
@@ -8,10 +8,17 @@
 
 //! This module just aggregates submodules.
 
+mod compat;
 mod files;
 mod helpers;
 mod io;
+mod ipc;
+mod memory;
 mod misc;
+mod pidfd;
+mod process_vm;
+mod ptrace;
+mod seccomp;
 mod signal;
 mod sysinfo;
 mod threads;
@@ -0,0 +1,165 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Process file descriptors.
+//!
+//! A pidfd becomes readable when its process exits.  Under sequentialization the process
+//! exits, as far as the other guests are concerned, at a point in the schedule.  The kernel
+//! only catches up a little later, once the exiting thread has left its last ptrace stop, so
+//! before polling we wait for the kernel to agree with the schedule on every exited process.
+//! Which processes have exited comes with the scheduler's answer to each polling attempt, and
+//! each pidfd is waited for at most once.
+//! Likewise, signalling a process which exited in the schedule fails, whether or not the
+//! kernel has finished with it.
+
+use std::fs;
+
+use nix::fcntl::OFlag;
+use reverie::syscalls;
+use reverie::syscalls::AddrMut;
+use reverie::syscalls::Errno;
+use reverie::Error;
+use reverie::Guest;
+use reverie::Stack;
+use tracing::trace;
+
+use crate::fd::FdType;
+use crate::record_or_replay::RecordOrReplay;
+use crate::tool_global::process_exited;
+use crate::tool_local::Detcore;
+use crate::types::DetPid;
+use crate::types::RawFd;
+
+impl<T: RecordOrReplay> Detcore<T> {
+    /// pidfd_open system call.
+    pub async fn handle_pidfd_open<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::PidfdOpen,
+    ) -> Result<i64, Error> {
+        let fd = self.record_or_replay(guest, call).await? as RawFd;
+        // PIDFD_NONBLOCK is O_NONBLOCK, and pidfds are always close-on-exec.
+        let flags = OFlag::from_bits_truncate(call.flags() as i32) & OFlag::O_NONBLOCK;
+        let target = DetPid::from_raw(call.pid()); // TODO(T78538674): virtualize pid
+        self.add_pidfd(guest, fd, flags, target).await?;
+        Ok(fd as i64)
+    }
+
+    /// pidfd_send_signal system call.
+    pub async fn handle_pidfd_send_signal<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::PidfdSendSignal,
+    ) -> Result<i64, Error> {
+        if self.cfg.sequentialize_threads && !self.cfg.recordreplay_modes {
+            if let Some(target) = guest.thread_state().pidfd_target(call.pidfd()) {
+                if process_exited(guest, target).await {
+                    return Err(Errno::ESRCH.into());
+                }
+            }
+        }
+        Ok(self.record_or_replay(guest, call).await?)
+    }
+
+    /// pidfd_getfd system call.
+    pub async fn handle_pidfd_getfd<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::PidfdGetfd,
+    ) -> Result<i64, Error> {
+        let fd = self.record_or_replay(guest, call).await? as RawFd;
+        // We don't know the other process's file table, so ask the kernel what we now hold.
+        let link = fs::read_link(format!("/proc/{}/fd/{}", guest.pid(), fd))
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let ty = fd_type_of_link(&link);
+        trace!("[detcore] pidfd_getfd received fd {} ({}, {:?})", fd, link, ty);
+        self.add_fd(guest, fd, OFlag::O_CLOEXEC, ty).await?;
+        Ok(fd as i64)
+    }
+
+    /// Track a new pidfd referring to `target`.
+    pub(crate) async fn add_pidfd<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        fd: RawFd,
+        flags: OFlag,
+        target: DetPid,
+    ) -> Result<(), Errno> {
+        self.add_fd(guest, fd, flags | OFlag::O_CLOEXEC, FdType::Pidfd)
+            .await?;
+        guest
+            .thread_state()
+            .with_detfd(fd, |detfd| detfd.pidfd = Some(target))
+    }
+
+    /// The processes of this thread's pidfds which may yet exit in the schedule ahead of the
+    /// kernel.  Empty unless the schedule decides when processes exit.
+    pub(crate) fn pidfd_targets<G: Guest<Self>>(guest: &G) -> Vec<DetPid> {
+        if !guest.config().sequentialize_threads || guest.config().recordreplay_modes {
+            return Vec::new();
+        }
+        let pidfds = guest.thread_state().unsettled_pidfds();
+        pidfds.into_iter().map(|(_, target)| target).collect()
+    }
+
+    /// Before polling, wait for the kernel to report each pidfd whose process has `exited` in
+    /// the schedule as readable.
+    pub async fn settle_exited_pidfds<G: Guest<Self>>(
+        guest: &mut G,
+        exited: &[DetPid],
+    ) -> Result<(), Error> {
+        if exited.is_empty() {
+            return Ok(());
+        }
+        let pidfds = guest.thread_state().unsettled_pidfds();
+        for (fd, target) in pidfds {
+            if exited.contains(&target) {
+                trace!("Waiting for the kernel to see process {} exit, on pidfd {}", target, fd);
+                let (pollfd, _guard) = {
+                    let mut stack = guest.stack().await;
+                    let pollfd = stack.push(libc::pollfd {
+                        fd,
+                        events: libc::POLLIN,
+                        revents: 0,
+                    });
+                    let guard = stack.commit()?;
+                    (pollfd, guard)
+                };
+                // The process has already exited: blocking takes the kernel no more than a
+                // moment.
+                let poll = syscalls::Poll::new()
+                    .with_fds(AddrMut::from_raw(pollfd.as_raw()))
+                    .with_nfds(1)
+                    .with_timeout(-1);
+                guest.inject_with_retry(poll).await?;
+                guest
+                    .thread_state()
+                    .with_detfd(fd, |detfd| detfd.pidfd_settled = true)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The kind of file descriptor, from its `/proc/<pid>/fd` link.
+fn fd_type_of_link(link: &str) -> FdType {
+    match link {
+        _ if link.starts_with("socket:") => FdType::Socket,
+        _ if link.starts_with("pipe:") => FdType::Pipe,
+        _ if link.starts_with("/memfd:") => FdType::Memfd,
+        _ if link.starts_with("/secretmem") => FdType::Secretmem,
+        "anon_inode:[eventfd]" => FdType::Eventfd,
+        "anon_inode:[signalfd]" => FdType::Signalfd,
+        "anon_inode:[timerfd]" => FdType::Timerfd,
+        "anon_inode:[pidfd]" => FdType::Pidfd,
+        "anon_inode:[userfaultfd]" => FdType::Userfaultfd,
+        "anon_inode:inotify" => FdType::Inotify,
+        _ => FdType::Regular,
+    }
+}
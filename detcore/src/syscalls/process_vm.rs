@@ -0,0 +1,190 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Access to the memory of other guest processes.
+//!
+//! Under sequentialization the target of `process_vm_readv`/`process_vm_writev` is stopped
+//! while the caller runs, so what it reads is fixed by the schedule.  We additionally request
+//! the target's address space from the scheduler, so that the access is ordered (and shows
+//! up in the schedule) like any other conflicting operation.
+//!
+//! The access itself is performed by us, as the tracer of both processes.  Left to the
+//! kernel, whether one guest may access a sibling's memory depends on the host's ptrace
+//! policy (e.g. Yama's `ptrace_scope`, which only admits descendants).  Reads and writes of
+//! an already opened `/proc/<pid>/mem` are ordinary file IO, and need nothing extra.
+
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::fs::FileExt;
+
+use reverie::syscalls;
+use reverie::syscalls::Addr;
+use reverie::syscalls::AddrMut;
+use reverie::syscalls::Errno;
+use reverie::syscalls::MemoryAccess;
+use reverie::Error;
+use reverie::Guest;
+use tracing::trace;
+
+use crate::record_or_replay::RecordOrReplay;
+use crate::resources::Permission;
+use crate::resources::ResourceID;
+use crate::tool_global::resource_request;
+use crate::tool_local::Detcore;
+use crate::types::DetPid;
+
+/// The most iovecs one call may pass, as in the kernel.
+const IOV_MAX: usize = 1024;
+
+impl<T: RecordOrReplay> Detcore<T> {
+    /// process_vm_readv system call.
+    pub async fn handle_process_vm_readv<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::ProcessVmReadv,
+    ) -> Result<i64, Error> {
+        if !self.cfg.sequentialize_threads || self.cfg.recordreplay_modes {
+            return Ok(self.record_or_replay(guest, call).await?);
+        }
+        if call.flags() != 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let lvec = call.lvec().map_or(0, |addr| addr.as_raw());
+        let lvec = Self::read_iovecs(guest, lvec, call.liovcnt())?;
+        let rvec = call.rvec().map_or(0, |addr| addr.as_raw());
+        let rvec = Self::read_iovecs(guest, rvec, call.riovcnt())?;
+        self.process_vm_access(guest, call.pid(), &lvec, &rvec, false).await
+    }
+
+    /// process_vm_writev system call.
+    pub async fn handle_process_vm_writev<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::ProcessVmWritev,
+    ) -> Result<i64, Error> {
+        if !self.cfg.sequentialize_threads || self.cfg.recordreplay_modes {
+            return Ok(self.record_or_replay(guest, call).await?);
+        }
+        if call.flags() != 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        let lvec = call.lvec().map_or(0, |addr| addr.as_raw());
+        let lvec = Self::read_iovecs(guest, lvec, call.liovcnt())?;
+        let rvec = call.rvec().map_or(0, |addr| addr.as_raw());
+        let rvec = Self::read_iovecs(guest, rvec, call.riovcnt())?;
+        self.process_vm_access(guest, call.pid(), &lvec, &rvec, true).await
+    }
+
+    /// Read an array of iovecs from the guest, as (base, len) pairs.
+    pub(crate) fn read_iovecs<G: Guest<Self>>(
+        guest: &G,
+        addr: usize,
+        count: usize,
+    ) -> Result<Vec<(usize, usize)>, Error> {
+        if count > IOV_MAX {
+            return Err(Errno::EINVAL.into());
+        }
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        let addr = Addr::<usize>::from_raw(addr).ok_or(Errno::EFAULT)?;
+        let mut words = vec![0usize; 2 * count];
+        guest.memory().read_values(addr, &mut words)?;
+        Ok(words.chunks(2).map(|iov| (iov[0], iov[1])).collect())
+    }
+
+    /// Copy between the caller's local iovecs and the remote iovecs of process `pid`, in
+    /// order, stopping at the first remote range that cannot be accessed.
+    async fn process_vm_access<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        pid: i32,
+        lvec: &[(usize, usize)],
+        rvec: &[(usize, usize)],
+        write: bool,
+    ) -> Result<i64, Error> {
+        let target = DetPid::from_raw(pid); // TODO(T78538674): virtualize pid/tid
+        let perm = if write { Permission::W } else { Permission::R };
+        let mut request = guest
+            .thread_state()
+            .mk_request(ResourceID::MemAddrSpace(target), perm);
+        request.fyi(if write {
+            "process_vm_writev"
+        } else {
+            "process_vm_readv"
+        });
+        resource_request(guest, request).await;
+
+        let mem = open_mem(pid, write)?;
+        let mut locals = lvec.iter().copied().filter(|(_, len)| *len > 0);
+        let mut local = locals.next();
+        let mut total = 0;
+        'remote: for &(mut remote, mut remaining) in rvec {
+            while remaining > 0 {
+                let (base, len) = match local {
+                    Some(iov) => iov,
+                    None => break 'remote,
+                };
+                let mut buf = vec![0u8; len.min(remaining)];
+                let res = if write {
+                    let src = Addr::<u8>::from_raw(base).ok_or(Errno::EFAULT)?;
+                    guest.memory().read_values(src, &mut buf)?;
+                    mem.write_at(&buf, remote as u64)
+                } else {
+                    mem.read_at(&mut buf, remote as u64)
+                };
+                let n = match res {
+                    Ok(n) if n > 0 => n,
+                    _ => break 'remote,
+                };
+                if !write {
+                    let dst = AddrMut::<u8>::from_raw(base).ok_or(Errno::EFAULT)?;
+                    guest.memory().write(dst, &buf[..n])?;
+                }
+                total += n;
+                remote += n;
+                remaining -= n;
+                local = if n == len {
+                    locals.next()
+                } else {
+                    Some((base + n, len - n))
+                };
+                if n < buf.len() {
+                    break 'remote;
+                }
+            }
+        }
+        trace!(
+            "[detcore] {} bytes {} process {}",
+            total,
+            if write { "written to" } else { "read from" },
+            pid
+        );
+        if total == 0 && rvec.iter().any(|(_, len)| *len > 0) && local.is_some() {
+            // Not even the first byte could be accessed.
+            return Err(Errno::EFAULT.into());
+        }
+        Ok(total as i64)
+    }
+}
+
+/// Open the memory of a guest process, which we may do as its tracer.
+pub(crate) fn open_mem(pid: i32, write: bool) -> Result<File, Errno> {
+    if pid <= 0 {
+        return Err(Errno::ESRCH);
+    }
+    OpenOptions::new()
+        .read(true)
+        .write(write)
+        .open(format!("/proc/{}/mem", pid))
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => Errno::ESRCH,
+            _ => Errno::EPERM,
+        })
+}
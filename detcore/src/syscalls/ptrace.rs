@@ -0,0 +1,359 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! ptrace between guests, under sequentialization.
+//!
+//! We are the (real) tracer of every guest thread, so a guest's ptrace requests are emulated
+//! rather than passed to the kernel.  A tracee stops by parking in detcore with a snapshot of
+//! its registers, at signal delivery, after an exec, and at syscalls if resumed with
+//! `PTRACE_SYSCALL`.  The tracer learns of the stop through SIGCHLD and its waits, and then
+//! reads the tracee's registers and memory, before resuming it.  Every step goes through the
+//! scheduler, so stops and resumptions happen at fixed points in the schedule.
+//!
+//! The tracer can read, and write, the tracee's memory, but not change its registers, nor
+//! single-step it.  Syscall stops only happen at the syscalls we intercept (all of them, in
+//! debug builds).  Only the `PTRACE_O_TRACESYSGOOD`, `PTRACE_O_TRACEEXEC` and
+//! `PTRACE_O_EXITKILL` options are supported.
+
+use std::os::unix::fs::FileExt;
+
+use nix::sys::signal;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use reverie::syscalls;
+use reverie::syscalls::AddrMut;
+use reverie::syscalls::Errno;
+use reverie::syscalls::MemoryAccess;
+use reverie::syscalls::Syscall;
+use reverie::syscalls::SyscallInfo;
+use reverie::Error;
+use reverie::Guest;
+use tracing::info;
+
+use crate::ptrace::AttachError;
+use crate::ptrace::Resume;
+use crate::ptrace::OPTION_TRACEEXEC;
+use crate::ptrace::OPTION_TRACESYSGOOD;
+use crate::ptrace::SUPPORTED_OPTIONS;
+use crate::record_or_replay::RecordOrReplay;
+use crate::resources::Permission;
+use crate::resources::ResourceID;
+use crate::resources::Resources;
+use crate::syscalls::process_vm::open_mem;
+use crate::tool_global::ptrace_attach;
+use crate::tool_global::ptrace_get_stop;
+use crate::tool_global::ptrace_poll_resume;
+use crate::tool_global::ptrace_resume;
+use crate::tool_global::ptrace_set_options;
+use crate::tool_global::ptrace_stop;
+use crate::tool_global::ptrace_tracer;
+use crate::tool_global::resource_request;
+use crate::tool_local::Detcore;
+use crate::types::DetTid;
+
+// The ptrace requests.
+const PTRACE_TRACEME: usize = 0;
+const PTRACE_PEEKTEXT: usize = 1;
+const PTRACE_PEEKDATA: usize = 2;
+const PTRACE_PEEKUSER: usize = 3;
+const PTRACE_POKETEXT: usize = 4;
+const PTRACE_POKEDATA: usize = 5;
+const PTRACE_CONT: usize = 7;
+const PTRACE_KILL: usize = 8;
+const PTRACE_GETREGS: usize = 12;
+const PTRACE_ATTACH: usize = 16;
+const PTRACE_DETACH: usize = 17;
+const PTRACE_SYSCALL: usize = 24;
+const PTRACE_SETOPTIONS: usize = 0x4200;
+const PTRACE_GETEVENTMSG: usize = 0x4201;
+const PTRACE_GETSIGINFO: usize = 0x4202;
+const PTRACE_SEIZE: usize = 0x4206;
+
+/// The event reported by a stop after an exec, with `PTRACE_O_TRACEEXEC`.
+const PTRACE_EVENT_EXEC: i32 = 4;
+
+/// The highest signal number.
+const SIGRTMAX: usize = 64;
+
+impl<T: RecordOrReplay> Detcore<T> {
+    /// Whether guests' ptrace requests are emulated, rather than passed to the kernel (which
+    /// refuses them, as the guests are already traced).
+    pub(crate) fn emulates_ptrace(&self) -> bool {
+        self.cfg.sequentialize_threads && !self.cfg.recordreplay_modes
+    }
+
+    /// ptrace system call.
+    pub async fn handle_ptrace<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Ptrace,
+    ) -> Result<i64, Error> {
+        if !self.emulates_ptrace() {
+            return Ok(self.record_or_replay(guest, call).await?);
+        }
+        let (_, args) = Syscall::Ptrace(call).into_parts();
+        let request = args.arg0;
+        let tracee = DetTid::from_raw(args.arg1 as i32); // TODO(T78538674): virtualize pid/tid
+        let (addr, data) = (args.arg2, args.arg3);
+        info!(
+            "[detcore, dtid {}] ptrace request {:#x} on {}",
+            guest.thread_state().dettid,
+            request,
+            tracee
+        );
+        match request {
+            PTRACE_TRACEME => {
+                let dettid = guest.thread_state().dettid;
+                ptrace_attach(guest, dettid, None, 0, false)
+                    .await
+                    .map_err(attach_errno)?;
+                return Ok(0);
+            }
+            PTRACE_ATTACH | PTRACE_SEIZE => {
+                let seize = request == PTRACE_SEIZE;
+                if seize && addr != 0 {
+                    return Err(Errno::EIO.into());
+                }
+                let options = if seize { data as u32 } else { 0 };
+                if options & !SUPPORTED_OPTIONS != 0 {
+                    return Err(Errno::EINVAL.into());
+                }
+                let tracer = guest.thread_state().detpid.expect("detpid unset");
+                ptrace_attach(guest, tracee, Some(tracer), options, !seize)
+                    .await
+                    .map_err(attach_errno)?;
+                return Ok(0);
+            }
+            _ => {}
+        }
+
+        // The remaining requests need the tracee to be stopped.
+        let stop = ptrace_get_stop(guest, tracee).await.ok_or(Errno::ESRCH)?;
+        match request {
+            PTRACE_PEEKTEXT | PTRACE_PEEKDATA => {
+                let mut word = [0u8; 8];
+                let n = open_mem(tracee.as_raw(), false)?
+                    .read_at(&mut word, addr as u64)
+                    .map_err(|_| Errno::EIO)?;
+                if n < word.len() {
+                    return Err(Errno::EIO.into());
+                }
+                // The raw syscall stores the word at `data`, rather than returning it.
+                let dst = AddrMut::<u64>::from_raw(data).ok_or(Errno::EFAULT)?;
+                guest.memory().write_value(dst, &u64::from_ne_bytes(word))?;
+                Ok(0)
+            }
+            PTRACE_POKETEXT | PTRACE_POKEDATA => {
+                let n = open_mem(tracee.as_raw(), true)?
+                    .write_at(&(data as u64).to_ne_bytes(), addr as u64)
+                    .map_err(|_| Errno::EIO)?;
+                if n < 8 {
+                    return Err(Errno::EIO.into());
+                }
+                Ok(0)
+            }
+            PTRACE_PEEKUSER => {
+                // Only the registers, at the start of `struct user`, are available.
+                if addr & 7 != 0 || addr / 8 >= stop.regs.len() {
+                    return Err(Errno::EIO.into());
+                }
+                let dst = AddrMut::<u64>::from_raw(data).ok_or(Errno::EFAULT)?;
+                guest.memory().write_value(dst, &stop.regs[addr / 8])?;
+                Ok(0)
+            }
+            PTRACE_GETREGS => {
+                let bytes: Vec<u8> = stop.regs.iter().flat_map(|r| r.to_ne_bytes()).collect();
+                let dst = AddrMut::<u8>::from_raw(data).ok_or(Errno::EFAULT)?;
+                guest.memory().write_exact(dst, &bytes)?;
+                Ok(0)
+            }
+            PTRACE_GETSIGINFO => {
+                // si_signo (with a zero si_errno), and si_code: SI_USER for a signal, or the
+                // SIGTRAP (and event) for other stops.
+                let mut info = [0u64; 16];
+                info[0] = stop.signal as u64;
+                if stop.status >> 8 != stop.signal {
+                    info[1] = (stop.status >> 8) as u64;
+                }
+                let dst = AddrMut::<[u64; 16]>::from_raw(data).ok_or(Errno::EFAULT)?;
+                guest.memory().write_value(dst, &info)?;
+                Ok(0)
+            }
+            PTRACE_GETEVENTMSG => {
+                // After an exec, the former thread id, which is still the tracee's.
+                let msg = if stop.status >> 16 == PTRACE_EVENT_EXEC {
+                    tracee.as_raw() as u64
+                } else {
+                    0
+                };
+                let dst = AddrMut::<u64>::from_raw(data).ok_or(Errno::EFAULT)?;
+                guest.memory().write_value(dst, &msg)?;
+                Ok(0)
+            }
+            PTRACE_SETOPTIONS => {
+                if data as u32 & !SUPPORTED_OPTIONS != 0 {
+                    return Err(Errno::EINVAL.into());
+                }
+                if !ptrace_set_options(guest, tracee, data as u32).await {
+                    return Err(Errno::ESRCH.into());
+                }
+                Ok(0)
+            }
+            PTRACE_CONT | PTRACE_SYSCALL | PTRACE_DETACH | PTRACE_KILL => {
+                if data > SIGRTMAX {
+                    return Err(Errno::EIO.into());
+                }
+                let signal = data as i32;
+                let resume = match request {
+                    PTRACE_CONT | PTRACE_SYSCALL => Resume::Continue {
+                        signal,
+                        syscall_stops: request == PTRACE_SYSCALL,
+                    },
+                    PTRACE_DETACH => Resume::Detach { signal },
+                    _ => Resume::Kill,
+                };
+                if !ptrace_resume(guest, tracee, resume).await {
+                    return Err(Errno::ESRCH.into());
+                }
+                Ok(0)
+            }
+            // E.g. PTRACE_SETREGS and PTRACE_SINGLESTEP.
+            _ => Err(Errno::EIO.into()),
+        }
+    }
+
+    /// A signal is about to be delivered to the calling thread.  If it is ptraced, it first
+    /// enters a signal-delivery-stop, and its tracer decides which signal (if any) to deliver.
+    pub(crate) async fn ptrace_signal_stop<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        signal: Signal,
+    ) -> Option<Signal> {
+        if !self.emulates_ptrace() || signal == Signal::SIGKILL {
+            return Some(signal);
+        }
+        let sig = signal as i32;
+        let regs = guest.regs().await;
+        match Self::enter_ptrace_stop(guest, (sig << 8) | 0x7f, sig, regs).await {
+            0 => None,
+            sig => Signal::try_from(sig).ok(),
+        }
+    }
+
+    /// The calling thread has exec'd.  If it is ptraced, it stops to tell its tracer.
+    pub(crate) async fn ptrace_exec_stop<G: Guest<Self>>(&self, guest: &mut G) {
+        if !self.emulates_ptrace() {
+            return;
+        }
+        let options = match ptrace_tracer(guest).await {
+            Some((_, options)) => options,
+            None => return,
+        };
+        if options & OPTION_TRACEEXEC != 0 {
+            let status = (PTRACE_EVENT_EXEC << 16) | (libc::SIGTRAP << 8) | 0x7f;
+            let regs = guest.regs().await;
+            // There is no signal to deliver from an event stop.
+            Self::enter_ptrace_stop(guest, status, libc::SIGTRAP, regs).await;
+        } else {
+            // As the kernel does, raise SIGTRAP, which the tracee then stops for.
+            let _ = signal::kill(Pid::from_raw(guest.pid().into()), Signal::SIGTRAP);
+        }
+    }
+
+    /// A syscall-enter-stop, or given the syscall's result, a syscall-exit-stop, for a thread
+    /// resumed with `PTRACE_SYSCALL`.
+    pub(crate) async fn ptrace_syscall_stop<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        result: Option<&Result<i64, Error>>,
+    ) {
+        let options = match ptrace_tracer(guest).await {
+            Some((_, options)) => options,
+            None => {
+                guest.thread_state_mut().ptrace_syscall_stops = false;
+                return;
+            }
+        };
+        let mut regs = guest.regs().await;
+        regs.rax = match result {
+            // At syscall entry, the return register holds -ENOSYS.
+            None => -libc::ENOSYS as u64,
+            Some(Ok(ret)) => *ret as u64,
+            Some(Err(Error::Errno(errno))) => -errno.into_raw() as u64,
+            Some(Err(_)) => -libc::ENOSYS as u64,
+        };
+        let trap = if options & OPTION_TRACESYSGOOD != 0 {
+            libc::SIGTRAP | 0x80
+        } else {
+            libc::SIGTRAP
+        };
+        // Signals injected at a syscall stop are not delivered, which the kernel allows.
+        Self::enter_ptrace_stop(guest, (trap << 8) | 0x7f, libc::SIGTRAP, regs).await;
+    }
+
+    /// Stop the calling thread, if it is ptraced, until its tracer resumes it.  Returns the
+    /// signal the tracer asked to deliver (or zero), which is `signal` itself if the thread is
+    /// not traced.
+    async fn enter_ptrace_stop<G: Guest<Self>>(
+        guest: &mut G,
+        status: i32,
+        signal: i32,
+        regs: libc::user_regs_struct,
+    ) -> i32 {
+        if !ptrace_stop(guest, status, signal, regs_words(&regs)).await {
+            return signal;
+        }
+        let dettid = guest.thread_state().dettid;
+        info!(
+            "[dtid {}] entered ptrace stop, status {:#x}",
+            dettid, status
+        );
+        let mut rsrc = Resources::new(dettid);
+        rsrc.insert(ResourceID::InternalIOPolling, Permission::W);
+        rsrc.fyi("ptrace stop");
+        let resume = loop {
+            if let Some(resume) = ptrace_poll_resume(guest).await {
+                break resume;
+            }
+            // Come back once other threads, the tracer among them, have had their turns.
+            resource_request(guest, rsrc.clone()).await;
+            rsrc.poll_attempt += 1;
+        };
+        info!("[dtid {}] resumed from ptrace stop: {:?}", dettid, resume);
+        let (syscall_stops, signal) = match resume {
+            Resume::Continue {
+                signal,
+                syscall_stops,
+            } => (syscall_stops, signal),
+            Resume::Detach { signal } => (false, signal),
+            Resume::Kill => {
+                // Taking the whole process down on its way back to the guest.
+                let _ = signal::kill(Pid::from_raw(guest.pid().into()), Signal::SIGKILL);
+                (false, 0)
+            }
+        };
+        guest.thread_state_mut().ptrace_syscall_stops = syscall_stops;
+        signal
+    }
+}
+
+/// The errno for a failed attach.
+fn attach_errno(err: AttachError) -> Errno {
+    match err {
+        AttachError::NoSuchThread => Errno::ESRCH,
+        AttachError::NotPermitted => Errno::EPERM,
+    }
+}
+
+/// The words of `struct user_regs_struct`, as `PTRACE_GETREGS` and `PTRACE_PEEKUSER` see them.
+fn regs_words(regs: &libc::user_regs_struct) -> Vec<u64> {
+    let len = std::mem::size_of::<libc::user_regs_struct>() / 8;
+    let words = (regs as *const libc::user_regs_struct).cast::<u64>();
+    // SAFETY: The struct consists of nothing but 64-bit registers.
+    unsafe { std::slice::from_raw_parts(words, len) }.to_vec()
+}
@@ -0,0 +1,339 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! seccomp, and prctl's seccomp operations, under `--emulate-seccomp`.
+//!
+//! The guest's filters are kept in its thread state rather than installed in the kernel, and
+//! each syscall the guest makes is run past them (by `seccomp_action`) before it is handled.
+//! A process's threads all share its filters, as if each filter had been installed with
+//! `SECCOMP_FILTER_FLAG_TSYNC`.  User-space notification is not supported.
+
+use std::fs;
+
+use reverie::syscalls;
+use reverie::syscalls::Addr;
+use reverie::syscalls::Errno;
+use reverie::syscalls::MemoryAccess;
+use reverie::syscalls::Syscall;
+use reverie::syscalls::SyscallArgs;
+use reverie::syscalls::SyscallInfo;
+use reverie::syscalls::Sysno;
+use reverie::Error;
+use reverie::Guest;
+use reverie::Stack;
+use tracing::info;
+
+use crate::record_or_replay::RecordOrReplay;
+use crate::seccomp;
+use crate::seccomp::SeccompAction;
+use crate::seccomp::SeccompData;
+use crate::seccomp::SockFilter;
+use crate::tool_local::Detcore;
+
+// The operations of the seccomp syscall.
+const SECCOMP_SET_MODE_STRICT: usize = 0;
+const SECCOMP_SET_MODE_FILTER: usize = 1;
+const SECCOMP_GET_ACTION_AVAIL: usize = 2;
+
+/// The flags `SECCOMP_SET_MODE_FILTER` knows of.
+const SECCOMP_FILTER_FLAGS: usize = 0x3f;
+
+/// `SECCOMP_FILTER_FLAG_NEW_LISTENER`, asking for a user-space notification fd.
+const SECCOMP_FILTER_FLAG_NEW_LISTENER: usize = 1 << 3;
+
+/// The `si_code` of a SIGSYS raised by a filter.
+const SYS_SECCOMP: u64 = 1;
+
+/// The capability which permits installing a filter without `no_new_privs`.
+const CAP_SYS_ADMIN: u32 = 21;
+
+impl<T: RecordOrReplay> Detcore<T> {
+    /// seccomp system call.
+    pub async fn handle_seccomp<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Seccomp,
+    ) -> Result<i64, Error> {
+        if !self.cfg.emulate_seccomp {
+            return Ok(self.record_or_replay(guest, call).await?);
+        }
+        let (_, args) = Syscall::Seccomp(call).into_parts();
+        match args.arg0 {
+            SECCOMP_SET_MODE_STRICT if args.arg1 != 0 || args.arg2 != 0 => {
+                Err(Errno::EINVAL.into())
+            }
+            SECCOMP_SET_MODE_STRICT => Self::set_seccomp_strict(guest),
+            SECCOMP_SET_MODE_FILTER => self.add_seccomp_filter(guest, args.arg1, args.arg2).await,
+            SECCOMP_GET_ACTION_AVAIL if args.arg1 != 0 => Err(Errno::EINVAL.into()),
+            SECCOMP_GET_ACTION_AVAIL => {
+                let addr = Addr::<u32>::from_raw(args.arg2).ok_or(Errno::EFAULT)?;
+                match guest.memory().read_value(addr)? {
+                    seccomp::ret::KILL_PROCESS
+                    | seccomp::ret::KILL_THREAD
+                    | seccomp::ret::TRAP
+                    | seccomp::ret::ERRNO
+                    | seccomp::ret::TRACE
+                    | seccomp::ret::LOG
+                    | seccomp::ret::ALLOW => Ok(0),
+                    _ => Err(Errno::EOPNOTSUPP.into()),
+                }
+            }
+            _ => Ok(self.record_or_replay(guest, call).await?),
+        }
+    }
+
+    /// prctl system call.  Only the seccomp operations need emulating.
+    pub async fn handle_prctl<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Prctl,
+    ) -> Result<i64, Error> {
+        if self.cfg.emulate_seccomp {
+            let (_, args) = Syscall::Prctl(call).into_parts();
+            match args.arg0 as i32 {
+                libc::PR_GET_SECCOMP => {
+                    return Ok(guest.thread_state().seccomp.lock().unwrap().mode());
+                }
+                libc::PR_SET_SECCOMP => {
+                    return match args.arg1 as u32 {
+                        libc::SECCOMP_MODE_STRICT => Self::set_seccomp_strict(guest),
+                        libc::SECCOMP_MODE_FILTER => {
+                            self.add_seccomp_filter(guest, 0, args.arg2).await
+                        }
+                        _ => Err(Errno::EINVAL.into()),
+                    };
+                }
+                _ => {}
+            }
+        }
+        Ok(self.record_or_replay(guest, call).await?)
+    }
+
+    /// Run a syscall past the guest's filters, to decide whether it is handled as usual.
+    pub(crate) async fn seccomp_action<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: &Syscall,
+    ) -> SeccompAction {
+        if !self.cfg.emulate_seccomp {
+            return SeccompAction::Allow;
+        }
+        let state = guest.thread_state().seccomp.lock().unwrap().clone();
+        if !state.is_enabled() {
+            return SeccompAction::Allow;
+        }
+        let (sysno, args) = call.into_parts();
+        let data = SeccompData {
+            nr: sysno as i32,
+            arch: seccomp::AUDIT_ARCH,
+            instruction_pointer: guest.regs().await.rip,
+            args: [
+                args.arg0 as u64,
+                args.arg1 as u64,
+                args.arg2 as u64,
+                args.arg3 as u64,
+                args.arg4 as u64,
+                args.arg5 as u64,
+            ],
+        };
+        match state.check(&data) {
+            SeccompAction::Log => {
+                info!(
+                    "[detcore, dtid {}] seccomp filter logged syscall {}",
+                    guest.thread_state().dettid,
+                    sysno
+                );
+                SeccompAction::Allow
+            }
+            action => action,
+        }
+    }
+
+    /// Carry out what the guest's filters decided for a syscall, in place of running it.
+    pub(crate) async fn handle_seccomp_action<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: Syscall,
+        action: SeccompAction,
+    ) -> Result<i64, Error> {
+        let sysno = call.number();
+        let pid: i32 = guest.pid().into();
+        let tid: i32 = guest.tid().into();
+        info!(
+            "[detcore, dtid {}] seccomp filter returned {:?} for syscall {}",
+            guest.thread_state().dettid,
+            action,
+            sysno
+        );
+        match action {
+            SeccompAction::Allow | SeccompAction::Log => {
+                unreachable!("allowed syscalls are handled as usual")
+            }
+            SeccompAction::Errno(errno) => Err(Errno::new(errno).into()),
+            // There is no tracer, nor a listener, to pass the syscall to.
+            SeccompAction::Trace(_) | SeccompAction::UserNotif => Err(Errno::ENOSYS.into()),
+            SeccompAction::Trap(data) => {
+                let info = {
+                    let ip = guest.regs().await.rip;
+                    let mut info = [0u64; 16];
+                    // si_signo and si_errno, si_code, then si_call_addr, si_syscall and
+                    // si_arch.
+                    info[0] = libc::SIGSYS as u64 | (u64::from(data) << 32);
+                    info[1] = SYS_SECCOMP;
+                    info[2] = ip;
+                    info[3] = sysno as u64 | (u64::from(seccomp::AUDIT_ARCH) << 32);
+                    info
+                };
+                let (info, _guard) = {
+                    let mut stack = guest.stack().await;
+                    let info = stack.push(info);
+                    let guard = stack.commit()?;
+                    (info, guard)
+                };
+                let sigqueue = SyscallArgs::new(
+                    pid as usize,
+                    tid as usize,
+                    libc::SIGSYS as usize,
+                    info.as_raw(),
+                    0,
+                    0,
+                );
+                guest
+                    .inject(Syscall::from_raw(Sysno::rt_tgsigqueueinfo, sigqueue))
+                    .await?;
+                // The kernel rolls the syscall back, which leaves its number in the return
+                // register.
+                Ok(sysno as i64)
+            }
+            // The kernel kills just the thread, unless it is the last one.
+            SeccompAction::KillThread if thread_count(pid) > 1 => {
+                let exit = syscalls::Exit::new().with_status(libc::SIGSYS);
+                self.handle_exit(guest, exit).await
+            }
+            SeccompAction::KillThread | SeccompAction::KillProcess => {
+                Self::seccomp_kill(guest, pid, tid, libc::SIGSYS).await
+            }
+            SeccompAction::StrictViolation => {
+                Self::seccomp_kill(guest, pid, tid, libc::SIGKILL).await
+            }
+        }
+    }
+
+    /// Enter strict mode.
+    fn set_seccomp_strict<G: Guest<Self>>(guest: &mut G) -> Result<i64, Error> {
+        if guest.thread_state().seccomp.lock().unwrap().set_strict() {
+            Ok(0)
+        } else {
+            Err(Errno::EINVAL.into())
+        }
+    }
+
+    /// Install a filter, given the address of its `struct sock_fprog`.
+    async fn add_seccomp_filter<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        flags: usize,
+        fprog: usize,
+    ) -> Result<i64, Error> {
+        if flags & !SECCOMP_FILTER_FLAGS != 0 {
+            return Err(Errno::EINVAL.into());
+        }
+        if flags & SECCOMP_FILTER_FLAG_NEW_LISTENER != 0 {
+            // As on a kernel without user-space notification.
+            info!("seccomp: user-space notification is not supported");
+            return Err(Errno::EINVAL.into());
+        }
+        if !Self::may_install_seccomp_filter(guest).await? {
+            return Err(Errno::EACCES.into());
+        }
+        // The length (an unsigned short) and the address of the instructions.
+        let addr = Addr::<[u64; 2]>::from_raw(fprog).ok_or(Errno::EFAULT)?;
+        let [len, filter] = guest.memory().read_value(addr)?;
+        let len = usize::from(len as u16);
+        if len == 0 || len > seccomp::MAX_INSNS {
+            return Err(Errno::EINVAL.into());
+        }
+        let mut prog = Vec::with_capacity(len);
+        for i in 0..len {
+            let addr = Addr::<u64>::from_raw(filter as usize + 8 * i).ok_or(Errno::EFAULT)?;
+            prog.push(SockFilter::from_raw(guest.memory().read_value(addr)?));
+        }
+        if !seccomp::validate(&prog) {
+            return Err(Errno::EINVAL.into());
+        }
+        info!(
+            "[detcore, dtid {}] installing a seccomp filter of {} instructions",
+            guest.thread_state().dettid,
+            len
+        );
+        let mut state = guest.thread_state().seccomp.lock().unwrap();
+        if state.add_filter(prog) {
+            Ok(0)
+        } else {
+            Err(Errno::EINVAL.into())
+        }
+    }
+
+    /// A filter may only be installed with `no_new_privs` set, or by a process with
+    /// `CAP_SYS_ADMIN`.
+    async fn may_install_seccomp_filter<G: Guest<Self>>(guest: &mut G) -> Result<bool, Error> {
+        let args = SyscallArgs::new(libc::PR_GET_NO_NEW_PRIVS as usize, 0, 0, 0, 0, 0);
+        if guest.inject(Syscall::from_raw(Sysno::prctl, args)).await? == 1 {
+            return Ok(true);
+        }
+        let status = fs::read_to_string(format!("/proc/{}/status", guest.tid()))
+            .map_err(|_| Errno::ESRCH)?;
+        let caps = status
+            .lines()
+            .find_map(|line| line.strip_prefix("CapEff:"))
+            .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+            .unwrap_or(0);
+        Ok(caps & (1 << CAP_SYS_ADMIN) != 0)
+    }
+
+    /// Kill the process with `signal`, which cannot be blocked or caught, as the kernel does
+    /// for the fatal filter actions.
+    async fn seccomp_kill<G: Guest<Self>>(
+        guest: &mut G,
+        pid: i32,
+        tid: i32,
+        signal: i32,
+    ) -> Result<i64, Error> {
+        if signal == libc::SIGSYS {
+            // SIG_DFL, with no flags.
+            let (action, mask, _guard) = {
+                let mut stack = guest.stack().await;
+                let action = stack.push([0u64; 4]);
+                let mask = stack.push(1u64 << (libc::SIGSYS - 1));
+                let guard = stack.commit()?;
+                (action, mask, guard)
+            };
+            let sigaction = SyscallArgs::new(signal as usize, action.as_raw(), 0, 8, 0, 0);
+            guest
+                .inject(Syscall::from_raw(Sysno::rt_sigaction, sigaction))
+                .await?;
+            let unblock = SyscallArgs::new(libc::SIG_UNBLOCK as usize, mask.as_raw(), 0, 8, 0, 0);
+            guest
+                .inject(Syscall::from_raw(Sysno::rt_sigprocmask, unblock))
+                .await?;
+        }
+        let tgkill = SyscallArgs::new(pid as usize, tid as usize, signal as usize, 0, 0, 0);
+        guest
+            .inject(Syscall::from_raw(Sysno::tgkill, tgkill))
+            .await?;
+        // The signal is delivered, fatally, on the way back to the guest.
+        Err(Errno::ENOSYS.into())
+    }
+}
+
+/// The number of threads in a process.
+fn thread_count(pid: i32) -> usize {
+    fs::read_dir(format!("/proc/{}/task", pid))
+        .map(|tasks| tasks.count())
+        .unwrap_or(1)
+}
@@ -20,14 +20,24 @@ use reverie::Error;
 use reverie::Guest;
 use reverie::Stack;
 use tracing::info;
+use tracing::warn;
 
 use crate::record_or_replay::RecordOrReplay;
 use crate::resources::Permission;
 use crate::resources::ResourceID;
 use crate::resources::Resources;
 use crate::syscalls::helpers::retry_nonblocking_syscall_with_timeout;
+use crate::timers::timespec_to_duration;
+use crate::timers::timeval_to_duration;
+use crate::timers::to_itimerspec;
+use crate::timers::to_itimerval;
+use crate::timers::TimerId;
+use crate::tool_global::create_timer;
+use crate::tool_global::delete_timer;
+use crate::tool_global::get_timer;
 use crate::tool_global::register_alarm;
 use crate::tool_global::resource_request;
+use crate::tool_global::set_timer;
 use crate::tool_global::thread_observe_time;
 use crate::tool_global::ResumeStatus;
 use crate::types::LogicalTime;
@@ -57,6 +67,179 @@ impl<T: RecordOrReplay> Detcore<T> {
         }
     }
 
+    /// setitimer.  `ITIMER_REAL` shares its timer with `alarm` and is handled by the
+    /// global scheduler.  The CPU-time timers are not virtualized.
+    pub async fn handle_setitimer<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Setitimer,
+    ) -> Result<i64, Error> {
+        if !guest.config().sequentialize_threads {
+            return Ok(guest.inject(call).await?);
+        }
+        if call.which() != libc::ITIMER_REAL {
+            warn!(
+                "[dtid {}] setitimer({}) on a CPU-time timer is not virtualized, letting it through...",
+                guest.thread_state().dettid,
+                call.which()
+            );
+            return Ok(guest.inject(call).await?);
+        }
+        let new_value = call.new_value().ok_or(Errno::EFAULT)?;
+        let new_value: libc::itimerval = guest.memory().read_value(new_value.cast())?;
+        let value = timeval_to_duration(&new_value.it_value).ok_or(Errno::EINVAL)?;
+        let interval = timeval_to_duration(&new_value.it_interval).ok_or(Errno::EINVAL)?;
+        let old = set_timer(guest, TimerId::Alarm, value, interval, false)
+            .await
+            .expect("alarm timer exists");
+        if let Some(old_value) = call.old_value() {
+            guest
+                .memory()
+                .write_value(old_value.cast::<libc::itimerval>(), &to_itimerval(old))?;
+        }
+        Ok(0)
+    }
+
+    /// getitimer.  See `handle_setitimer`.
+    pub async fn handle_getitimer<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Getitimer,
+    ) -> Result<i64, Error> {
+        if !guest.config().sequentialize_threads || call.which() != libc::ITIMER_REAL {
+            return Ok(guest.inject(call).await?);
+        }
+        let curr_value = call.curr_value().ok_or(Errno::EFAULT)?;
+        let (value, interval, _) = get_timer(guest, TimerId::Alarm)
+            .await
+            .expect("alarm timer exists");
+        guest.memory().write_value(
+            curr_value.cast::<libc::itimerval>(),
+            &to_itimerval((value, interval)),
+        )?;
+        Ok(0)
+    }
+
+    /// timer_create.  Timers which notify by signal (or not at all) are handled by the
+    /// global scheduler, against logical time, whatever their clock.  Note that their
+    /// signals are delivered like `kill`, without the `SI_TIMER` siginfo payload.  Timers
+    /// which notify a specific thread (including glibc's `SIGEV_THREAD`) are not
+    /// virtualized.
+    pub async fn handle_timer_create<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::TimerCreate,
+    ) -> Result<i64, Error> {
+        if !guest.config().sequentialize_threads {
+            return Ok(guest.inject(call).await?);
+        }
+        let (notify, signo) = match call.sevp() {
+            Some(sevp) => {
+                let sev: libc::sigevent = guest.memory().read_value(sevp.cast())?;
+                (sev.sigev_notify, sev.sigev_signo)
+            }
+            // A null sigevent means SIGEV_SIGNAL with SIGALRM.
+            None => (libc::SIGEV_SIGNAL, libc::SIGALRM),
+        };
+        let sig = match notify {
+            libc::SIGEV_NONE => None,
+            libc::SIGEV_SIGNAL => Some(Signal::try_from(signo).map_err(|_| Errno::EINVAL)?),
+            _ => {
+                warn!(
+                    "[dtid {}] timer_create with sigev_notify {} is not virtualized, letting it through...",
+                    guest.thread_state().dettid,
+                    notify
+                );
+                return Ok(guest.inject(call).await?);
+            }
+        };
+        let timerid = call.timerid().ok_or(Errno::EFAULT)?;
+        let id = create_timer(guest, sig).await;
+        guest.memory().write_value(timerid.cast::<i32>(), &id)?;
+        Ok(0)
+    }
+
+    /// timer_settime.  Timers unknown to the scheduler were created by the kernel.
+    pub async fn handle_timer_settime<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::TimerSettime,
+    ) -> Result<i64, Error> {
+        if !guest.config().sequentialize_threads {
+            return Ok(guest.inject(call).await?);
+        }
+        let new_value = call.new_value().ok_or(Errno::EFAULT)?;
+        let new_value: libc::itimerspec = guest.memory().read_value(new_value.cast())?;
+        let value = timespec_to_duration(&new_value.it_value).ok_or(Errno::EINVAL)?;
+        let interval = timespec_to_duration(&new_value.it_interval).ok_or(Errno::EINVAL)?;
+        let absolute = call.flags() & libc::TIMER_ABSTIME != 0;
+        let id = TimerId::Posix(call.timerid() as i32);
+        match set_timer(guest, id, value, interval, absolute).await {
+            Some(old) => {
+                if let Some(old_value) = call.old_value() {
+                    guest
+                        .memory()
+                        .write_value(old_value.cast::<libc::itimerspec>(), &to_itimerspec(old))?;
+                }
+                Ok(0)
+            }
+            None => Ok(guest.inject(call).await?),
+        }
+    }
+
+    /// timer_gettime.  See `handle_timer_settime`.
+    pub async fn handle_timer_gettime<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::TimerGettime,
+    ) -> Result<i64, Error> {
+        if !guest.config().sequentialize_threads {
+            return Ok(guest.inject(call).await?);
+        }
+        match get_timer(guest, TimerId::Posix(call.timerid() as i32)).await {
+            Some((value, interval, _)) => {
+                let curr_value = call.curr_value().ok_or(Errno::EFAULT)?;
+                guest.memory().write_value(
+                    curr_value.cast::<libc::itimerspec>(),
+                    &to_itimerspec((value, interval)),
+                )?;
+                Ok(0)
+            }
+            None => Ok(guest.inject(call).await?),
+        }
+    }
+
+    /// timer_getoverrun.  See `handle_timer_settime`.
+    pub async fn handle_timer_getoverrun<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::TimerGetoverrun,
+    ) -> Result<i64, Error> {
+        if !guest.config().sequentialize_threads {
+            return Ok(guest.inject(call).await?);
+        }
+        match get_timer(guest, TimerId::Posix(call.timerid() as i32)).await {
+            // The kernel caps the count at DELAYTIMER_MAX:
+            Some((_, _, overrun)) => Ok(overrun.min(i32::MAX as u64) as i64),
+            None => Ok(guest.inject(call).await?),
+        }
+    }
+
+    /// timer_delete.  See `handle_timer_settime`.
+    pub async fn handle_timer_delete<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::TimerDelete,
+    ) -> Result<i64, Error> {
+        if guest.config().sequentialize_threads
+            && delete_timer(guest, TimerId::Posix(call.timerid() as i32)).await
+        {
+            Ok(0)
+        } else {
+            Ok(guest.inject(call).await?)
+        }
+    }
+
     /// A pause is really just an unbounded sleep.
     pub async fn handle_pause<G: Guest<Self>>(
         &self,
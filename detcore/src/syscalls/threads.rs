@@ -10,7 +10,10 @@
 
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
 
+use nix::fcntl::OFlag;
+use nix::unistd::getpgid;
 use reverie::syscalls;
 use reverie::syscalls::Addr;
 use reverie::syscalls::AddrMut;
@@ -18,6 +21,9 @@ use reverie::syscalls::CloneFlags;
 use reverie::syscalls::Errno;
 use reverie::syscalls::MemoryAccess;
 use reverie::syscalls::Syscall;
+use reverie::syscalls::SyscallArgs;
+use reverie::syscalls::SyscallInfo;
+use reverie::syscalls::Sysno;
 use reverie::syscalls::Timespec;
 use reverie::syscalls::WaitPidFlag;
 use reverie::Error;
@@ -28,7 +34,9 @@ use tracing::error;
 use tracing::info;
 use tracing::trace;
 
+use crate::address_space::SharedMapping;
 use crate::config::BlockingMode;
+use crate::procmaps;
 use crate::record_or_replay::RecordOrReplay;
 use crate::resources::Permission;
 use crate::resources::ResourceID;
@@ -37,18 +45,29 @@ use crate::scheduler::SchedValue;
 use crate::syscalls::helpers::nanos_duration_to_absolute_timeout;
 use crate::syscalls::helpers::retry_nonblocking_syscall;
 use crate::syscalls::helpers::retry_nonblocking_syscall_with_timeout;
+use crate::syscalls::time::user_time_rusage;
+use crate::tool_global::child_processes;
 use crate::tool_global::create_child_thread;
 use crate::tool_global::futex_action;
+use crate::tool_global::ptrace_stop_reported;
+use crate::tool_global::ptrace_stops;
+use crate::tool_global::reap_child;
 use crate::tool_global::resource_request;
+use crate::tool_global::vfork_pending;
 use crate::tool_global::FutexAction;
 use crate::tool_local::Detcore;
+use crate::types::CondvarOp;
 use crate::types::DetPid;
 use crate::types::DetTid;
+use crate::types::FutexID;
 use crate::types::LogicalTime;
 use crate::FileMetadata;
 
 impl<T: RecordOrReplay> Detcore<T> {
     /// Clone, clone3, fork, vfork system calls
+    ///
+    /// The rest of clone3's extended arguments (`set_tid`, `cgroup`) are left to the kernel:
+    /// they are chosen by the caller, and so are as deterministic as the caller.
     pub async fn handle_clone_family<G: Guest<Self>>(
         &self,
         guest: &mut G,
@@ -61,16 +80,25 @@ impl<T: RecordOrReplay> Detcore<T> {
         assert_eq!(ts.clone_flags, None);
         ts.clone_flags = Some(flags);
 
-        // TODO(T94530014):
-        if flags.contains(CloneFlags::CLONE_VFORK) {
+        let vfork = flags.contains(CloneFlags::CLONE_VFORK);
+        if vfork && !self.cfg.sequentialize_threads {
             error!(
-                "hermit: clone() with CLONE_VFORK argument.  This is not currently supported and will not work."
+                "hermit: clone() with CLONE_VFORK argument.  This is only supported when sequentializing threads."
             )
         }
+        let vfork = vfork && self.cfg.sequentialize_threads;
 
         let parent_dettid = ts.dettid;
         trace!("[detcore, dtid {}] parent invoking clone.", parent_dettid);
-        let maybe_res = guest.inject(Syscall::from(clone_family)).await;
+        let (sysno, args) = Syscall::from(clone_family).into_parts();
+        let maybe_res = if vfork {
+            Self::inject_without_vfork(guest, sysno, args).await
+        } else {
+            guest
+                .inject(Syscall::from_raw(sysno, args))
+                .await
+                .map_err(Error::from)
+        };
         guest.thread_state_mut().clone_flags = None; // Unset, now that it has been read by the child.
 
         let res = maybe_res?;
@@ -81,16 +109,96 @@ impl<T: RecordOrReplay> Detcore<T> {
             child_dettid
         );
 
+        if flags.contains(CloneFlags::CLONE_PIDFD) {
+            // The parent received a pidfd for the child, which we track like any other fd.
+            let pidfd_addr = if sysno == Sysno::clone3 {
+                let addr = Addr::<u64>::from_raw(args.arg0 + 8).ok_or(Errno::EFAULT)?;
+                guest.memory().read_value(addr)? as usize
+            } else {
+                args.arg2 // parent_tid
+            };
+            let pidfd_addr = Addr::<i32>::from_raw(pidfd_addr).ok_or(Errno::EFAULT)?;
+            let pidfd = guest.memory().read_value(pidfd_addr)?;
+            self.add_pidfd(guest, pidfd, OFlag::empty(), child_dettid)
+                .await?;
+        }
+
         create_child_thread(guest, child_dettid, ctid, Some(flags)).await;
+        if vfork {
+            Self::wait_for_vfork_child(guest, child_dettid).await;
+        }
         Ok(child_dettid.as_raw() as i64)
     }
 
+    /// Like the kernel, keep the parent of a vfork suspended until the child execs or exits.
+    pub(crate) async fn wait_for_vfork_child<G: Guest<Self>>(guest: &mut G, child_dettid: DetTid) {
+        let parent_dettid = guest.thread_state().dettid;
+        let mut rsrc = Resources::new(parent_dettid);
+        rsrc.insert(ResourceID::InternalIOPolling, Permission::W);
+        rsrc.fyi("vfork");
+        while vfork_pending(guest, child_dettid).await {
+            resource_request(guest, rsrc.clone()).await;
+            rsrc.poll_attempt += 1;
+        }
+        trace!(
+            "[detcore, dtid {}] vfork child {} released its parent.",
+            parent_dettid, child_dettid
+        );
+    }
+
+    /// Vfork system call.  Under sequentialization this is a clone with `CLONE_VM |
+    /// CLONE_VFORK`, the child running on the parent's stack while the parent is suspended, as
+    /// in the kernel.  Otherwise it is demoted to a fork.
+    pub async fn handle_vfork<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        _call: syscalls::Vfork,
+    ) -> Result<i64, Error> {
+        if !self.cfg.sequentialize_threads {
+            return self
+                .handle_clone_family(guest, syscalls::Fork::new().into())
+                .await;
+        }
+        let flags = (CloneFlags::CLONE_VM | CloneFlags::CLONE_VFORK).bits() | libc::SIGCHLD;
+        let args = SyscallArgs::new(flags as usize, 0, 0, 0, 0, 0);
+        match Syscall::from_raw(Sysno::clone, args) {
+            Syscall::Clone(call) => self.handle_clone_family(guest, call.into()).await,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Perform a clone with `CLONE_VFORK`, but without the kernel suspending the parent.  The
+    /// kernel would keep the parent in the syscall until the child execs or exits, while the
+    /// child waits for the scheduler to give it a turn, and the scheduler waits for the
+    /// parent.  Instead, the parent waits for the child in the scheduler.
+    async fn inject_without_vfork<G: Guest<Self>>(
+        guest: &mut G,
+        sysno: Sysno,
+        mut args: SyscallArgs,
+    ) -> Result<i64, Error> {
+        let vfork = CloneFlags::CLONE_VFORK.bits() as u64;
+        if sysno == Sysno::clone3 {
+            // The flags are the first field of the guest's `struct clone_args`.
+            let flags_addr = AddrMut::<u64>::from_raw(args.arg0).ok_or(Errno::EFAULT)?;
+            let flags = guest.memory().read_value(Addr::<u64>::from_raw(args.arg0).unwrap())?;
+            guest.memory().write_value(flags_addr, &(flags & !vfork))?;
+            let res = guest.inject(Syscall::from_raw(sysno, args)).await;
+            guest.memory().write_value(flags_addr, &flags)?;
+            Ok(res?)
+        } else {
+            args.arg0 &= !(vfork as usize);
+            Ok(guest.inject(Syscall::from_raw(sysno, args)).await?)
+        }
+    }
+
     /// Exit system call
     pub async fn handle_exit<G: Guest<Self>>(
         &self,
         guest: &mut G,
         call: syscalls::Exit,
     ) -> Result<i64, Error> {
+        let dettid = guest.thread_state().dettid;
+        self.release_robust_futexes(guest, &[dettid]).await?;
         let request = guest
             .thread_state()
             .mk_request(ResourceID::Exit(false), Permission::RW);
@@ -105,6 +213,16 @@ impl<T: RecordOrReplay> Detcore<T> {
         guest: &mut G,
         call: syscalls::ExitGroup,
     ) -> Result<i64, Error> {
+        // Every thread in the group dies, not just this one.
+        let dettids: Vec<DetTid> = guest
+            .thread_state()
+            .robust_lists
+            .lock()
+            .unwrap()
+            .keys()
+            .copied()
+            .collect();
+        self.release_robust_futexes(guest, &dettids).await?;
         let request = guest
             .thread_state()
             .mk_request(ResourceID::Exit(true), Permission::RW);
@@ -113,6 +231,117 @@ impl<T: RecordOrReplay> Detcore<T> {
         guest.tail_inject(call).await
     }
 
+    /// set_robust_list system call.  The kernel keeps its own copy of the list head, but we
+    /// remember it as well, to release the thread's robust futexes ourselves when it exits.
+    pub async fn handle_set_robust_list<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::SetRobustList,
+    ) -> Result<i64, Error> {
+        let res = self.record_or_replay(guest, call).await?;
+        let dettid = guest.thread_state().dettid;
+        let head = call.head().map_or(0, |head| head.as_raw());
+        let mut robust_lists = guest.thread_state().robust_lists.lock().unwrap();
+        if head == 0 {
+            robust_lists.remove(&dettid);
+        } else {
+            robust_lists.insert(dettid, head);
+        }
+        Ok(res)
+    }
+
+    /// Release the robust futexes held by exiting threads, as the kernel would once they are
+    /// gone: each futex word still owned by one of them is marked `FUTEX_OWNER_DIED`, and
+    /// one waiter is woken, to acquire the lock and see `EOWNERDEAD`.
+    ///
+    /// Under precise futex emulation the waiters are waiting in our scheduler rather than in
+    /// the kernel, so the kernel's own wakeups would never reach them.  Doing this here, in
+    /// the exiting thread's turn, means the waiter wakes at a deterministic point.  Having
+    /// cleared the owner, we leave the kernel nothing to do for these words when the thread
+    /// does exit.  (Threads killed by a signal never get here, and are left to the kernel.)
+    async fn release_robust_futexes<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        dettids: &[DetTid],
+    ) -> Result<(), Error> {
+        /// The kernel's bound on the length of a robust list, against circular lists.
+        const ROBUST_LIST_LIMIT: usize = 2048;
+        let word_size = std::mem::size_of::<usize>();
+
+        if !self.cfg.sequentialize_threads || self.cfg.debug_futex_mode != BlockingMode::Precise {
+            return Ok(());
+        }
+        for dettid in dettids {
+            let head = guest.thread_state().robust_lists.lock().unwrap().remove(dettid);
+            let head = match head {
+                Some(head) => head,
+                None => continue,
+            };
+            // struct robust_list_head { next, futex_offset, list_op_pending }
+            let memory = guest.memory();
+            let mut entry: usize = memory.read_value(Addr::from_raw(head).unwrap())?;
+            let offset: isize = memory.read_value(Addr::from_raw(head + word_size).unwrap())?;
+            let pending: usize = memory.read_value(Addr::from_raw(head + 2 * word_size).unwrap())?;
+            let mut futexes = Vec::new();
+            while entry != head && futexes.len() < ROBUST_LIST_LIMIT {
+                // The low bit marks a PI futex, which we leave to the kernel.
+                if entry & 1 == 0 && entry != pending {
+                    futexes.push((entry.wrapping_add_signed(offset), false));
+                }
+                entry = match Addr::<usize>::from_raw(entry & !1) {
+                    Some(addr) => guest.memory().read_value(addr)?,
+                    None => break,
+                };
+            }
+            if pending != 0 && pending & 1 == 0 {
+                futexes.push((pending.wrapping_add_signed(offset), true));
+            }
+            for (addr, is_pending) in futexes {
+                self.release_robust_futex(guest, *dettid, addr, is_pending).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Release one robust futex word, per `handle_futex_death` in the kernel.
+    async fn release_robust_futex<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        dettid: DetTid,
+        addr: usize,
+        is_pending: bool,
+    ) -> Result<(), Error> {
+        let uaddr = AddrMut::<u32>::from_raw(addr).ok_or(Errno::EFAULT)?;
+        let word: u32 = guest.memory().read_value(uaddr)?;
+        // A lock that was being taken, and whose word is still zero, may have a waiter who
+        // missed its wakeup.
+        let wake = if is_pending && word == 0 {
+            true
+        } else if word & libc::FUTEX_TID_MASK == dettid.as_raw() as u32 {
+            let owner_died = (word & libc::FUTEX_WAITERS) | libc::FUTEX_OWNER_DIED;
+            guest.memory().write_value(uaddr, &owner_died)?;
+            trace!(
+                "[detcore, dtid {}] robust futex at {:#x} released: {:#x} -> {:#x}",
+                dettid,
+                addr,
+                word,
+                owner_died
+            );
+            word & libc::FUTEX_WAITERS != 0
+        } else {
+            false
+        };
+        if wake {
+            let call = syscalls::Futex::new()
+                .with_uaddr(Some(uaddr.cast()))
+                .with_futex_op(libc::FUTEX_WAKE)
+                .with_val(1);
+            let init_val = guest.memory().read_value(uaddr.cast())?;
+            self.handle_futex_blocking(guest, call, init_val).await?;
+        }
+        Ok(())
+    }
+
     /// Futex system call, which can block.
     pub async fn handle_futex<G: Guest<Self>>(
         &self,
@@ -137,26 +366,96 @@ impl<T: RecordOrReplay> Detcore<T> {
         if !self.cfg.sequentialize_threads {
             Ok(guest.inject(call).await?)
         } else {
-            match self.cfg.debug_futex_mode {
+            let condvar_op = if self.cfg.condvar_sched_points {
+                Self::classify_condvar_futex(guest, &call)
+            } else {
+                None
+            };
+            if condvar_op == Some(CondvarOp::Wait) {
+                let mut condvar_futexes = guest.thread_state().condvar_futexes.lock().unwrap();
+                condvar_futexes.insert(AddrMut::as_raw(ptr));
+            }
+            if matches!(condvar_op, Some(CondvarOp::Signal | CondvarOp::Broadcast)) {
+                // Let other threads run before the waiters are woken.
+                self.condvar_sched_point(guest).await;
+            }
+            let res = match self.cfg.debug_futex_mode {
                 BlockingMode::Precise => self.handle_futex_blocking(guest, call, init_val).await,
                 BlockingMode::Polling => self.handle_futex_polling(guest, call, init_val).await,
                 BlockingMode::External => self.record_or_replay_blocking(guest, call.into()).await,
+            };
+            if condvar_op == Some(CondvarOp::Wait) {
+                // Woken waiters must also race with other threads to reacquire the mutex.
+                self.condvar_sched_point(guest).await;
             }
+            res
         }
     }
 
+    /// Recognize the futex calls that glibc uses to implement pthread condition variables.
+    ///
+    /// Waits use `FUTEX_WAIT_BITSET` matching any bit, and broadcasts wake every waiter.  A signal
+    /// wakes a single waiter, which looks just like releasing a mutex, so it is only recognized on
+    /// futex words that have previously been waited on as a condition variable.  Older versions
+    /// of glibc (before 2.25) use `FUTEX_WAKE_OP` and `FUTEX_CMP_REQUEUE` instead.
+    ///
+    /// This is a heuristic.  Other primitives built from the same futex patterns (e.g. semaphores
+    /// and rwlocks) may also be reported as condition variable operations.
+    pub fn classify_condvar_futex<G: Guest<Self>>(
+        guest: &G,
+        call: &syscalls::Futex,
+    ) -> Option<CondvarOp> {
+        let addr = AddrMut::as_raw(call.uaddr()?);
+        match call.futex_op() & libc::FUTEX_CMD_MASK {
+            libc::FUTEX_WAIT_BITSET if call.val3() == !0 => Some(CondvarOp::Wait),
+            libc::FUTEX_WAKE if call.val() == i32::MAX => Some(CondvarOp::Broadcast),
+            libc::FUTEX_WAKE
+                if call.val() == 1
+                    && guest
+                        .thread_state()
+                        .condvar_futexes
+                        .lock()
+                        .unwrap()
+                        .contains(&addr) =>
+            {
+                Some(CondvarOp::Signal)
+            }
+            libc::FUTEX_WAKE_OP => Some(CondvarOp::Signal),
+            libc::FUTEX_CMP_REQUEUE => Some(CondvarOp::Broadcast),
+            _ => None,
+        }
+    }
+
+    /// An extra scheduling point at a condition variable operation, per `--condvar-sched-points`.
+    /// In chaos mode this is also a priority change point, unless priorities are being replayed.
+    async fn condvar_sched_point<G: Guest<Self>>(&self, guest: &mut G) {
+        let ts = guest.thread_state();
+        let req = if self.cfg.chaos
+            && ts.preemption_points.is_none()
+            && self.cfg.replay_schedule_from.is_none()
+        {
+            let now = ts.thread_logical_time.as_nanos();
+            Self::random_priority_changepoint_request(guest, now)
+        } else {
+            Self::yield_request(guest)
+        };
+        resource_request(guest, req).await;
+    }
+
     /// Blocking (precise) Futex implementation.
     /// Here we use a two-phase request to the scheduler: before and after the futex wait/wake
     /// side effects. We EMULATE futex calls and NEVER run them inside the kernel.
+    ///
+    /// With `--lean-sched-points`, only the first request is made, and a wait's timeout is
+    /// resolved against the global time by the scheduler, as part of that request.
     pub async fn handle_futex_blocking<G: Guest<Self>>(
         &self,
         guest: &mut G,
         call: syscalls::Futex,
         init_val: i32,
     ) -> Result<i64, Error> {
-        let detpid = DetPid::from_raw(guest.pid().into()); // TODO(T78538674): virtualize pid/tid
         let ptr = call.uaddr().unwrap();
-        let futexid = (detpid, AddrMut::as_raw(ptr));
+        let futexid = Self::futex_id(guest, &call)?;
         let futex_op = call.futex_op() & libc::FUTEX_CMD_MASK;
         let mask = match futex_op {
             libc::FUTEX_WAKE_BITSET | libc::FUTEX_WAIT_BITSET => call.val3(),
@@ -184,14 +483,16 @@ impl<T: RecordOrReplay> Detcore<T> {
                     guest.memory().read_value(ptr).unwrap(),
                     call.val(),
                 );
-                let _ = futex_action(
-                    guest,
-                    FutexAction::WakeFinished(0),
-                    &futexid,
-                    init_val,
-                    mask,
-                )
-                .await;
+                if !self.cfg.lean_sched_points {
+                    let _ = futex_action(
+                        guest,
+                        FutexAction::WakeFinished(0),
+                        &futexid,
+                        init_val,
+                        mask,
+                    )
+                    .await;
+                }
                 Ok(num as i64)
             }
             libc::FUTEX_WAIT | libc::FUTEX_WAIT_BITSET => {
@@ -213,19 +514,16 @@ impl<T: RecordOrReplay> Detcore<T> {
                     );
                     Err(Error::Errno(Errno::EAGAIN))
                 } else {
-                    let maybe_timeout_lt = if let Some(ns) = timeout_nanos {
-                        nanos_duration_to_absolute_timeout(guest, ns).await
-                    } else {
-                        None
+                    let action = match timeout_nanos {
+                        Some(ns) if ns > 0 && self.cfg.lean_sched_points => {
+                            FutexAction::WaitFor(Duration::from_nanos(ns as u64))
+                        }
+                        Some(ns) => FutexAction::WaitRequest(
+                            nanos_duration_to_absolute_timeout(guest, ns).await,
+                        ),
+                        None => FutexAction::WaitRequest(None),
                     };
-                    let ans = futex_action(
-                        guest,
-                        FutexAction::WaitRequest(maybe_timeout_lt),
-                        &futexid,
-                        init_val,
-                        mask,
-                    )
-                    .await;
+                    let ans = futex_action(guest, action, &futexid, init_val, mask).await;
                     let res = if ans != Some(SchedValue::TimeOut) {
                         let expected = call.val();
                         let observed = guest.memory().read_value(ptr).unwrap();
@@ -245,7 +543,10 @@ impl<T: RecordOrReplay> Detcore<T> {
                         trace!("[detcore, dtid {}] futex wait timed out", &dettid);
                         Err(Error::Errno(Errno::ETIMEDOUT))
                     };
-                    futex_action(guest, FutexAction::WaitFinished, &futexid, init_val, mask).await;
+                    if !self.cfg.lean_sched_points {
+                        futex_action(guest, FutexAction::WaitFinished, &futexid, init_val, mask)
+                            .await;
+                    }
                     res
                 }
             }
@@ -258,6 +559,53 @@ impl<T: RecordOrReplay> Detcore<T> {
         }
     }
 
+    /// Identify the futex word a call operates on, as the kernel does.  Unless the call is
+    /// marked `FUTEX_PRIVATE_FLAG`, the word may be in a mapping shared with other processes
+    /// (e.g. a process-shared mutex, or the head of a queue between a producer and a
+    /// consumer process), in which case waiters and wakers in every process must agree on it
+    /// regardless of where each one has it mapped.
+    fn futex_id<G: Guest<Self>>(guest: &G, call: &syscalls::Futex) -> Result<FutexID, Error> {
+        let detpid = DetPid::from_raw(guest.pid().into()); // TODO(T78538674): virtualize pid/tid
+        let addr = AddrMut::as_raw(call.uaddr().unwrap());
+        if call.futex_op() & libc::FUTEX_PRIVATE_FLAG != 0 {
+            return Ok(FutexID::Private(detpid, addr));
+        }
+        // The shared mappings are only read again once the address space has changed.
+        let mut space = guest.thread_state().address_space.lock().unwrap();
+        if space.shared_mappings().is_none() {
+            let maps = procmaps::from_pid(guest.pid(), |map| map.perms.contains('s'))?;
+            let mappings = maps
+                .iter()
+                .map(|map| SharedMapping {
+                    start: map.address.0,
+                    end: map.address.1,
+                    dev: (map.dev.0 as u64, map.dev.1 as u64),
+                    inode: map.inode,
+                    offset: map.offset,
+                })
+                .collect();
+            space.set_shared_mappings(mappings);
+        }
+        let addr64 = addr as u64;
+        let shared = space
+            .shared_mappings()
+            .unwrap()
+            .iter()
+            .find(|map| map.start <= addr64 && addr64 < map.end);
+        match shared {
+            Some(map) => {
+                let futexid = FutexID::Shared {
+                    dev: map.dev,
+                    inode: map.inode,
+                    offset: map.offset + (addr64 - map.start),
+                };
+                trace!("[detcore] futex at {:#x} is shared: {:?}", addr, futexid);
+                Ok(futexid)
+            }
+            None => Ok(FutexID::Private(detpid, addr)),
+        }
+    }
+
     /// Futex system call, alternative implemenattion where we treat futexes as InternalIOPolling
     /// operations.
     pub async fn handle_futex_polling<G: Guest<Self>>(
@@ -356,6 +704,16 @@ impl<T: RecordOrReplay> Detcore<T> {
         &self,
         guest: &mut G,
         call: syscalls::Execveat,
+    ) -> Result<i64, Error> {
+        self.exec_with(guest, Syscall::Execveat(call)).await
+    }
+
+    /// Run an exec syscall (passed in as it is), with the process state kept for it: the fds
+    /// are closed on exec, and the address space is forgotten.  Doesn't return if successful.
+    pub(crate) async fn exec_with<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: Syscall,
     ) -> Result<i64, Error> {
         let metadata: FileMetadata = {
             let guard = guest.thread_state().file_metadata.lock().unwrap();
@@ -375,14 +733,26 @@ impl<T: RecordOrReplay> Detcore<T> {
                 .collect(),
         };
 
-        // close fds with O_CLOEXEC
+        // close fds with O_CLOEXEC, and forget about the old address space
+        let condvar_futexes = guest.thread_state().condvar_futexes.clone();
+        let address_space = guest.thread_state().address_space.clone();
+        let membarrier_registrations = guest.thread_state().membarrier_registrations.clone();
+        let robust_lists = guest.thread_state().robust_lists.clone();
         guest.thread_state_mut().file_metadata = Arc::new(Mutex::new(new_metadata));
+        guest.thread_state_mut().condvar_futexes = Default::default();
+        guest.thread_state_mut().address_space = Default::default();
+        guest.thread_state_mut().membarrier_registrations = Default::default();
+        guest.thread_state_mut().robust_lists = Default::default();
 
         // execve(2) doesn't return upon success.
         let errno = self.record_or_replay(guest, call).await.unwrap_err();
 
         // execve failed, restore fds
         guest.thread_state_mut().file_metadata = Arc::new(Mutex::new(metadata));
+        guest.thread_state_mut().condvar_futexes = condvar_futexes;
+        guest.thread_state_mut().address_space = address_space;
+        guest.thread_state_mut().membarrier_registrations = membarrier_registrations;
+        guest.thread_state_mut().robust_lists = robust_lists;
 
         Err(errno.into())
     }
@@ -406,10 +776,154 @@ impl<T: RecordOrReplay> Detcore<T> {
 
     /// wait4 system call
     /// This is handled by the scheduler and not passed to the record/replay layer.
+    ///
+    /// Under sequentialization, exited children are reaped in the order they exited in the
+    /// schedule, and their resource usage is their virtual CPU time, as user time.  The stops
+    /// of the guests this process ptraces are reported likewise, with no resource usage.
     pub async fn handle_wait4<G: Guest<Self>>(
         &self,
         guest: &mut G,
         call: syscalls::Wait4,
+    ) -> Result<i64, Error> {
+        if !self.cfg.sequentialize_threads
+            || self.cfg.recordreplay_modes
+            || call.options().intersects(WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED)
+        {
+            return self.poll_wait4(guest, call).await;
+        }
+        let selector = match call.pid() {
+            pid if pid < -1 => WaitSelector::Pgid(-pid),
+            -1 => WaitSelector::Any,
+            0 => WaitSelector::Pgid(process_group(guest.pid().into())?),
+            pid => WaitSelector::Pid(DetPid::from_raw(pid)), // TODO(T78538674): virtualize pid
+        };
+        let wnohang = call.options().contains(WaitPidFlag::WNOHANG);
+        let child = match wait_for_child(guest, selector, wnohang).await? {
+            Some(Waited::Exited(child)) => child,
+            Some(Waited::Stopped(tracee, status)) => {
+                if let Some(addr) = call.status() {
+                    guest.memory().write_value(addr, &status)?;
+                }
+                if let Some(rusage) = call.rusage() {
+                    let zero: libc::rusage = unsafe { std::mem::zeroed() };
+                    guest.memory().write_value(rusage, &zero)?;
+                }
+                ptrace_stop_reported(guest, tracee).await;
+                let dettid = guest.thread_state().dettid;
+                info!("[dtid {}] wait4 reported ptrace stop of {}", dettid, tracee);
+                return Ok(tracee.as_raw() as i64);
+            }
+            None => return Ok(0),
+        };
+        let mut options = call.options();
+        options.remove(WaitPidFlag::WNOHANG);
+        let res = guest
+            .inject_with_retry(call.with_pid(child.as_raw()).with_options(options))
+            .await?;
+        let detpid = guest.thread_state().detpid.expect("detpid unset");
+        let cpu = reap_child(guest, detpid, child).await;
+        if let Some(rusage) = call.rusage() {
+            guest.memory().write_value(rusage, &user_time_rusage(cpu))?;
+        }
+        info!("[dtid {}] wait4 reaped child {}", detpid, child);
+        Ok(res)
+    }
+
+    /// waitid system call
+    ///
+    /// Like wait4, children which exited, and ptrace stops, are waited for in the order they
+    /// happened in the schedule.  With `WNOWAIT`, the child is left to be waited for again.
+    pub async fn handle_waitid<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Waitid,
+    ) -> Result<i64, Error> {
+        let (sysno, mut args) = Syscall::Waitid(call).into_parts();
+        let options = WaitPidFlag::from_bits_truncate(args.arg3 as i32);
+        let selector = match args.arg0 as libc::idtype_t {
+            libc::P_ALL => Some(WaitSelector::Any),
+            libc::P_PID => Some(WaitSelector::Pid(DetPid::from_raw(args.arg1 as i32))),
+            libc::P_PGID if args.arg1 == 0 => {
+                Some(WaitSelector::Pgid(process_group(guest.pid().into())?))
+            }
+            libc::P_PGID => Some(WaitSelector::Pgid(args.arg1 as i32)),
+            P_PIDFD => guest
+                .thread_state()
+                .pidfd_target(args.arg1 as i32)
+                .map(WaitSelector::Pid),
+            _ => None,
+        };
+        let selector = match selector {
+            Some(selector)
+                if self.cfg.sequentialize_threads
+                    && !self.cfg.recordreplay_modes
+                    && options.contains(WaitPidFlag::WEXITED)
+                    && !options.intersects(WaitPidFlag::WSTOPPED | WaitPidFlag::WCONTINUED) =>
+            {
+                selector
+            }
+            _ => {
+                return self
+                    .record_or_replay_blocking(guest, Syscall::Waitid(call))
+                    .await;
+            }
+        };
+        let wnohang = options.contains(WaitPidFlag::WNOHANG);
+        let child = match wait_for_child(guest, selector, wnohang).await? {
+            Some(Waited::Exited(child)) => child,
+            Some(Waited::Stopped(tracee, status)) => {
+                if let Some(infop) = AddrMut::<[i32; 32]>::from_raw(args.arg2) {
+                    // si_signo, si_code, si_pid and si_status.
+                    let mut info = [0; 32];
+                    info[0] = libc::SIGCHLD;
+                    info[2] = libc::CLD_TRAPPED;
+                    info[4] = tracee.as_raw();
+                    info[6] = status >> 8;
+                    guest.memory().write_value(infop, &info)?;
+                }
+                if let Some(rusage) = AddrMut::<libc::rusage>::from_raw(args.arg4) {
+                    let zero: libc::rusage = unsafe { std::mem::zeroed() };
+                    guest.memory().write_value(rusage, &zero)?;
+                }
+                if !options.contains(WaitPidFlag::WNOWAIT) {
+                    ptrace_stop_reported(guest, tracee).await;
+                }
+                return Ok(0);
+            }
+            None => {
+                // Nothing to report: the kernel zeroes the siginfo.
+                if let Some(infop) = AddrMut::<u8>::from_raw(args.arg2) {
+                    guest
+                        .memory()
+                        .write_exact(infop, &[0; std::mem::size_of::<libc::siginfo_t>()])?;
+                }
+                return Ok(0);
+            }
+        };
+        let mut blocking = options;
+        blocking.remove(WaitPidFlag::WNOHANG);
+        args.arg0 = libc::P_PID as usize;
+        args.arg1 = child.as_raw() as usize;
+        args.arg3 = blocking.bits() as usize;
+        let res = guest.inject_with_retry(Syscall::from_raw(sysno, args)).await?;
+        // Left to be waited for again, a child's usage is only reported once it is reaped.
+        let cpu = if options.contains(WaitPidFlag::WNOWAIT) {
+            Duration::ZERO
+        } else {
+            let detpid = guest.thread_state().detpid.expect("detpid unset");
+            reap_child(guest, detpid, child).await
+        };
+        if let Some(rusage) = AddrMut::<libc::rusage>::from_raw(args.arg4) {
+            guest.memory().write_value(rusage, &user_time_rusage(cpu))?;
+        }
+        Ok(res)
+    }
+
+    /// wait4 by polling the kernel, in whatever order it reports children.
+    async fn poll_wait4<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Wait4,
     ) -> Result<i64, Error> {
         let dettid = guest.thread_state().dettid;
         let mut rsrc = Resources::new(dettid);
@@ -482,3 +996,80 @@ impl<T: RecordOrReplay> Detcore<T> {
         }
     }
 }
+
+/// The `waitid` id type for waiting on a pidfd.
+const P_PIDFD: libc::idtype_t = 3;
+
+/// Which children a wait is for.
+#[derive(Debug, Clone, Copy)]
+enum WaitSelector {
+    Any,
+    Pid(DetPid),
+    Pgid(i32),
+}
+
+impl WaitSelector {
+    fn matches(&self, child: DetPid) -> bool {
+        match self {
+            WaitSelector::Any => true,
+            WaitSelector::Pid(pid) => *pid == child,
+            WaitSelector::Pgid(pgid) => process_group(child.as_raw()).ok() == Some(*pgid),
+        }
+    }
+}
+
+/// The process group of a guest process.  (Which may be a zombie.)
+fn process_group(pid: i32) -> Result<i32, Errno> {
+    getpgid(Some(nix::unistd::Pid::from_raw(pid)))
+        .map(|pgid| pgid.as_raw())
+        .map_err(|_| Errno::ESRCH)
+}
+
+/// What a wait reports.
+enum Waited {
+    /// A child process which exited.
+    Exited(DetPid),
+    /// A ptraced thread which stopped, with its wait status.
+    Stopped(DetTid, i32),
+}
+
+/// Wait for the first child (in exit order) matching the selector to exit, or else for the
+/// first matching tracee (in stop order) to stop.  Returns `None` if there is nothing to report
+/// yet and `wnohang` is set, and ECHILD if there is no such child or tracee at all.
+async fn wait_for_child<G, T>(
+    guest: &mut G,
+    selector: WaitSelector,
+    wnohang: bool,
+) -> Result<Option<Waited>, Errno>
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let detpid = guest.thread_state().detpid.expect("detpid unset");
+    let mut rsrc = Resources::new(guest.thread_state().dettid);
+    rsrc.insert(ResourceID::InternalIOPolling, Permission::W);
+    rsrc.fyi("wait");
+    loop {
+        let (exited, running) = child_processes(guest, detpid).await;
+        if let Some(child) = exited.into_iter().find(|child| selector.matches(*child)) {
+            return Ok(Some(Waited::Exited(child)));
+        }
+        let (stops, tracees) = ptrace_stops(guest, detpid).await;
+        if let Some((tracee, status)) = stops.into_iter().find(|(t, _)| selector.matches(*t)) {
+            return Ok(Some(Waited::Stopped(tracee, status)));
+        }
+        if !running
+            .into_iter()
+            .chain(tracees)
+            .any(|child| selector.matches(child))
+        {
+            return Err(Errno::ECHILD);
+        }
+        if wnohang {
+            return Ok(None);
+        }
+        // Come back once other threads have had their turns.
+        resource_request(guest, rsrc.clone()).await;
+        rsrc.poll_attempt += 1;
+    }
+}
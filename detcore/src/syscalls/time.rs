@@ -7,13 +7,22 @@
  */
 
 //! System calls for dealing with threads and concurrency.
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Mutex;
 use std::time::Duration;
 
+use nix::fcntl::OFlag;
 use reverie::syscalls;
 use reverie::syscalls::family::NanosleepFamily;
+use reverie::syscalls::AddrMut;
 use reverie::syscalls::Errno;
 use reverie::syscalls::MemoryAccess;
 use reverie::syscalls::Syscall;
+use reverie::syscalls::SyscallArgs;
+use reverie::syscalls::SyscallInfo;
+use reverie::syscalls::Sysno;
 use reverie::syscalls::Timespec;
 use reverie::syscalls::Timeval;
 use reverie::Error;
@@ -29,11 +38,97 @@ use crate::resources::ResourceID;
 use crate::resources::Resources;
 use crate::scheduler::entropy_to_priority;
 use crate::scheduler::Priority;
+use crate::timers::timespec_to_duration;
+use crate::timers::to_itimerspec;
+use crate::timers::TimerfdState;
+use crate::tool_global::cpu_times;
 use crate::tool_global::resource_request;
+use crate::tool_global::sleep_for;
 use crate::tool_global::thread_observe_time;
 use crate::tool_global::ResumeStatus;
 use crate::tool_local::Detcore;
+use crate::types::DetTid;
+use crate::types::DetTime;
 use crate::types::LogicalTime;
+use crate::types::RawFd;
+
+/// The unit in which `times` and `/proc/<pid>/stat` count CPU time, i.e. `sysconf(_SC_CLK_TCK)`.
+const CLOCK_TICKS_PER_SEC: u64 = 100;
+
+fn to_clock_ticks(d: Duration) -> u64 {
+    d.as_millis() as u64 / (1000 / CLOCK_TICKS_PER_SEC)
+}
+
+fn to_timespec(d: Duration) -> Timespec {
+    Timespec {
+        tv_sec: d.as_secs() as i64,
+        tv_nsec: d.subsec_nanos() as i64,
+    }
+}
+
+/// Resource usage of the given virtual CPU time, which is all user time.  The other counters
+/// are zero.
+pub(crate) fn user_time_rusage(utime: Duration) -> libc::rusage {
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    rusage.ru_utime = libc::timeval {
+        tv_sec: utime.as_secs() as i64,
+        tv_usec: utime.subsec_micros() as i64,
+    };
+    rusage
+}
+
+/// Decode a CPU-time clock into the thread or process it measures (`None` for the caller), and
+/// whether it measures a single thread.  `None` for any other clock.
+fn decode_cpu_clock(clockid: i32) -> Option<(Option<i32>, bool)> {
+    match clockid {
+        libc::CLOCK_PROCESS_CPUTIME_ID => Some((None, false)),
+        libc::CLOCK_THREAD_CPUTIME_ID => Some((None, true)),
+        // As made by `clock_getcpuclockid` and `pthread_getcpuclockid`: the complement of the
+        // pid, above a per-thread bit and two bits for the kind of clock.  All kinds measure the
+        // same virtual time.
+        id if id < 0 => {
+            let pid = !(id >> 3);
+            Some(((pid != 0).then_some(pid), id & 4 != 0))
+        }
+        _ => None,
+    }
+}
+
+/// Which thread or process a `/proc/.../stat` path describes (`None` for the caller), and
+/// whether it describes a single thread.  `None` for any other path.
+fn proc_stat_target(path: &Path) -> Option<(Option<i32>, bool)> {
+    let rest = path.strip_prefix("/proc").ok()?;
+    let parts: Vec<&str> = rest.iter().map(|c| c.to_str()).collect::<Option<_>>()?;
+    let pid = |p: &str| {
+        if p == "self" {
+            Some(None)
+        } else {
+            p.parse().ok().map(Some)
+        }
+    };
+    match parts.as_slice() {
+        ["thread-self", "stat"] => Some((None, true)),
+        [p, "stat"] => Some((pid(p)?, false)),
+        [p, "task", tid, "stat"] => {
+            pid(p)?;
+            Some((Some(tid.parse().ok()?), true))
+        }
+        _ => None,
+    }
+}
+
+/// Replace the utime, stime, cutime and cstime fields (14 to 17) of the contents of a
+/// `/proc/<pid>/stat` file.
+fn patch_stat_cpu_times(stat: &str, ticks: [u64; 4]) -> Option<String> {
+    // The command name may contain spaces and parentheses, so count from its end: the state
+    // after it is field 3.
+    let (head, tail) = stat.split_at(stat.rfind(')')? + 1);
+    let mut fields: Vec<String> = tail.split_whitespace().map(String::from).collect();
+    for (field, t) in fields.get_mut(11..15)?.iter_mut().zip(ticks) {
+        *field = t.to_string();
+    }
+    Some(format!("{} {}\n", head, fields.join(" ")))
+}
 
 fn time_from_resources(rsrcs: &Resources) -> Option<LogicalTime> {
     if rsrcs.resources.len() > 1 {
@@ -54,7 +149,9 @@ impl<T: RecordOrReplay> Detcore<T> {
     /// Convenience function for constructing a sleep request with a nanosecond offset from "now".
     pub async fn sleep_request<G: Guest<Self>>(guest: &mut G, ns_delta: Duration) -> Resources {
         let base_time = thread_observe_time(guest).await;
-        let target_time = base_time + ns_delta;
+        let target_time = guest
+            .config()
+            .round_timer_expiration(base_time + ns_delta);
         let resource = ResourceID::SleepUntil(target_time);
         guest.thread_state().mk_request(resource, Permission::W)
     }
@@ -62,7 +159,7 @@ impl<T: RecordOrReplay> Detcore<T> {
     /// Convenience function for constructing a sleep request with a absolute nanosecond value from the realtime clock.
     pub async fn sleep_request_abs<G: Guest<Self>>(guest: &mut G, time: LogicalTime) -> Resources {
         // TODO T124594597 Record-replay case requires better handling of time
-        let resource = ResourceID::SleepUntil(time);
+        let resource = ResourceID::SleepUntil(guest.config().round_timer_expiration(time));
         guest.thread_state().mk_request(resource, Permission::W)
     }
 
@@ -132,11 +229,24 @@ impl<T: RecordOrReplay> Detcore<T> {
     }
 
     /// clock_gettime
+    ///
+    /// The CPU-time clocks report virtual CPU time; all other clocks, global virtual time.
     pub async fn handle_clock_gettime<G: Guest<Self>>(
         &self,
         guest: &mut G,
         call: syscalls::ClockGettime,
     ) -> Result<i64, Error> {
+        let (_, args) = Syscall::ClockGettime(call).into_parts();
+        if let Some((target, per_thread)) = decode_cpu_clock(args.arg0 as i32) {
+            let (thread, process, _) = Self::cpu_times_of(guest, target)
+                .await
+                .ok_or(Errno::EINVAL)?;
+            let tp = call.tp().ok_or(Errno::EFAULT)?;
+            let t = to_timespec(if per_thread { thread } else { process });
+            guest.memory().write_value(tp, &t)?;
+            return Ok(0);
+        }
+
         let time_ns = thread_observe_time(guest).await;
         trace!("Converting nanoseconds into clock_gettime: {}", time_ns);
 
@@ -157,12 +267,16 @@ impl<T: RecordOrReplay> Detcore<T> {
     ) -> Result<i64, Error> {
         let res = call.res().ok_or(Errno::EFAULT)?;
 
-        // For now we report a constant clock res of 10ms:
-        let clock_res = 10;
-
-        let t = Timespec {
-            tv_sec: 0,
-            tv_nsec: 1000 * clock_res as i64,
+        let t = match self.cfg.timer_resolution {
+            Some(res) => LogicalTime::from_nanos(res.get()).into(),
+            None => {
+                // Absent a configured timer resolution we report a constant clock res of 10us:
+                let clock_res = 10;
+                Timespec {
+                    tv_sec: 0,
+                    tv_nsec: 1000 * clock_res as i64,
+                }
+            }
         };
 
         guest.memory().write_value(res, &t)?;
@@ -170,6 +284,112 @@ impl<T: RecordOrReplay> Detcore<T> {
         Ok(0)
     }
 
+    /// The virtual CPU times of a thread (the caller, if `None`), of its process, and of the
+    /// children its process has waited for.  `None` if there is no such thread.
+    async fn cpu_times_of<G: Guest<Self>>(
+        guest: &mut G,
+        target: Option<i32>,
+    ) -> Option<(Duration, Duration, Duration)> {
+        let dettid = match target {
+            Some(tid) => DetTid::from_raw(tid), // TODO(T78538674): virtualize pid/tid
+            None => guest.thread_state().dettid,
+        };
+        cpu_times(guest, dettid).await
+    }
+
+    /// getrusage
+    ///
+    /// All CPU time is virtual user time.  The other counters are zero.
+    pub async fn handle_getrusage<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: Syscall,
+    ) -> Result<i64, Error> {
+        let (_, args) = call.into_parts();
+        let (thread, process, children) = Self::cpu_times_of(guest, None).await.unwrap_or_default();
+        let utime = match args.arg0 as i32 {
+            libc::RUSAGE_SELF => process,
+            libc::RUSAGE_THREAD => thread,
+            libc::RUSAGE_CHILDREN => children,
+            _ => return Err(Errno::EINVAL.into()),
+        };
+        let usage = AddrMut::<libc::rusage>::from_raw(args.arg1).ok_or(Errno::EFAULT)?;
+        guest
+            .memory()
+            .write_value(usage, &user_time_rusage(utime))?;
+        Ok(0)
+    }
+
+    /// times
+    ///
+    /// Like getrusage, all CPU time is virtual user time.  The return value counts the virtual
+    /// time since the container started.
+    pub async fn handle_times<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: Syscall,
+    ) -> Result<i64, Error> {
+        let (_, args) = call.into_parts();
+        let (_, process, children) = Self::cpu_times_of(guest, None).await.unwrap_or_default();
+        if let Some(buf) = AddrMut::<libc::tms>::from_raw(args.arg0) {
+            let tms = libc::tms {
+                tms_utime: to_clock_ticks(process) as libc::clock_t,
+                tms_stime: 0,
+                tms_cutime: to_clock_ticks(children) as libc::clock_t,
+                tms_cstime: 0,
+            };
+            guest.memory().write_value(buf, &tms)?;
+        }
+        let now = thread_observe_time(guest).await;
+        let start = DetTime::new(&self.cfg).as_nanos();
+        Ok(to_clock_ticks(now.duration_since(start)) as i64)
+    }
+
+    /// Having opened a `/proc/.../stat` file, swap in a memfd holding its contents with the CPU
+    /// times replaced by virtual ones.  Any other file is left alone.
+    pub(crate) async fn virtualize_proc_stat<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        path: &Path,
+        fd: RawFd,
+        flags: OFlag,
+    ) -> Result<(), Error> {
+        let (target, per_thread) = match proc_stat_target(path) {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+        let (thread, process, children) = match Self::cpu_times_of(guest, target).await {
+            Some(times) => times,
+            None => return Ok(()),
+        };
+        let stat = fs::read_to_string(format!("/proc/{}/fd/{}", guest.tid(), fd))
+            .map_err(|_| Errno::EIO)?;
+        let utime = if per_thread { thread } else { process };
+        let ticks = [to_clock_ticks(utime), 0, to_clock_ticks(children), 0];
+        let stat = match patch_stat_cpu_times(&stat, ticks) {
+            Some(stat) => stat,
+            None => return Ok(()),
+        };
+
+        let (name, _guard) = {
+            let mut stack = guest.stack().await;
+            let name = stack.push(*b"stat\0");
+            let guard = stack.commit()?;
+            (name, guard)
+        };
+        let memfd_create = SyscallArgs::new(name.as_raw(), 0, 0, 0, 0, 0);
+        let memfd = guest
+            .inject(Syscall::from_raw(Sysno::memfd_create, memfd_create))
+            .await? as RawFd;
+        fs::write(format!("/proc/{}/fd/{}", guest.tid(), memfd), stat).map_err(|_| Errno::EIO)?;
+        let cloexec = (flags & OFlag::O_CLOEXEC).bits() as usize;
+        let dup3 = SyscallArgs::new(memfd as usize, fd as usize, cloexec, 0, 0, 0);
+        guest.inject(Syscall::from_raw(Sysno::dup3, dup3)).await?;
+        let close = SyscallArgs::new(memfd as usize, 0, 0, 0, 0, 0);
+        guest.inject(Syscall::from_raw(Sysno::close, close)).await?;
+        Ok(())
+    }
+
     /// Helper function to wait a given period, which may either succeed or be interrupted by a signal.
     /// Return 0 or EINTR respectively.
     async fn wait_and_return<R: Guest<Self>>(
@@ -178,7 +398,19 @@ impl<T: RecordOrReplay> Detcore<T> {
         call: NanosleepFamily,
     ) -> Result<i64, Error> {
         let target_time = time_from_resources(&request).expect("a sleepuntil resource request");
-        match resource_request(guest, request).await {
+        let status = resource_request(guest, request).await;
+        Self::return_from_sleep(guest, status, target_time, call).await
+    }
+
+    /// Return 0 from a sleep until `target_time`, or EINTR if it was interrupted by a signal,
+    /// having written the time left to sleep.
+    async fn return_from_sleep<R: Guest<Self>>(
+        guest: &mut R,
+        status: ResumeStatus,
+        target_time: LogicalTime,
+        call: NanosleepFamily,
+    ) -> Result<i64, Error> {
+        match status {
             ResumeStatus::Normal => Ok(0),
             ResumeStatus::Signaled => {
                 let now = thread_observe_time(guest).await;
@@ -202,6 +434,23 @@ impl<T: RecordOrReplay> Detcore<T> {
         }
     }
 
+    /// A relative nanosleep with `--lean-sched-points`.  Spin loops sleep for zero to yield.
+    /// Without timer rounding that ends at the current time anyway, so the time is not read.
+    /// Other sleeps have the scheduler read it as it takes the request.
+    async fn lean_nanosleep<R: Guest<Self>>(
+        guest: &mut R,
+        time: Duration,
+        call: NanosleepFamily,
+    ) -> Result<i64, Error> {
+        if time.is_zero() && guest.config().timer_resolution.is_none() {
+            let request = Self::yield_request(guest);
+            return Self::wait_and_return(guest, request, call).await;
+        }
+        let (status, target_time) = sleep_for(guest, time).await;
+        trace!("nanosleep for {:?}, until {}", time, target_time);
+        Self::return_from_sleep(guest, status, target_time, call).await
+    }
+
     /// clock_nanosleep and nanosleep
     pub async fn handle_nanosleep_family<R: Guest<Self>>(
         &self,
@@ -226,6 +475,9 @@ impl<T: RecordOrReplay> Detcore<T> {
                 if self.cfg.sequentialize_threads {
                     let time = Duration::from_secs(t.tv_sec as u64)
                         + Duration::from_nanos(t.tv_nsec as u64);
+                    if self.cfg.lean_sched_points {
+                        return Self::lean_nanosleep(guest, time, call).await;
+                    }
                     let request = Self::sleep_request(guest, time).await;
                     trace!(
                         "nanosleep adding delta {:?} to yield request {:?}",
@@ -279,6 +531,168 @@ impl<T: RecordOrReplay> Detcore<T> {
         }
     }
 
+    /// timerfd_settime, on a timerfd with a virtual timer (see `handle_timerfd_create`).  The
+    /// kernel timer is left disarmed, and expirations are counted against logical time.
+    pub async fn handle_timerfd_settime<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::TimerfdSettime,
+    ) -> Result<i64, Error> {
+        let timer = guest
+            .thread_state()
+            .with_detfd(call.fd(), |detfd| detfd.timer.clone())?;
+        let timer = match timer {
+            Some(timer) => timer,
+            None => return Ok(guest.inject(call).await?),
+        };
+        let new_value = call.new_value().ok_or(Errno::EFAULT)?;
+        let new_value: libc::itimerspec = guest.memory().read_value(new_value.cast())?;
+        let value = timespec_to_duration(&new_value.it_value).ok_or(Errno::EINVAL)?;
+        let interval = timespec_to_duration(&new_value.it_interval).ok_or(Errno::EINVAL)?;
+
+        let now = thread_observe_time(guest).await;
+        let deadline = if value.is_zero() {
+            None
+        } else if call.flags() & libc::TFD_TIMER_ABSTIME != 0 {
+            Some(LogicalTime::from_nanos(value.as_nanos() as u64))
+        } else {
+            Some(now + value)
+        };
+        let deadline = deadline.map(|t| guest.config().round_timer_expiration(t));
+        trace!(
+            "timerfd_settime on fd {}: deadline {:?}, interval {:?}",
+            call.fd(),
+            deadline,
+            interval
+        );
+        let (old, materialized) = {
+            let mut state = timer.lock().unwrap();
+            let old = state.timer.remaining(now);
+            state.timer.set(deadline, interval);
+            (old, std::mem::replace(&mut state.materialized, false))
+        };
+        if materialized {
+            Self::arm_kernel_timerfd(guest, call.fd(), Duration::ZERO).await?;
+        }
+        if let Some(old_value) = call.old_value() {
+            guest
+                .memory()
+                .write_value(old_value.cast::<libc::itimerspec>(), &to_itimerspec(old))?;
+        }
+        Ok(0)
+    }
+
+    /// timerfd_gettime, on a timerfd with a virtual timer.
+    pub async fn handle_timerfd_gettime<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::TimerfdGettime,
+    ) -> Result<i64, Error> {
+        let timer = guest
+            .thread_state()
+            .with_detfd(call.fd(), |detfd| detfd.timer.clone())?;
+        let timer = match timer {
+            Some(timer) => timer,
+            None => return Ok(guest.inject(call).await?),
+        };
+        let curr_value = call.curr_value().ok_or(Errno::EFAULT)?;
+        let now = thread_observe_time(guest).await;
+        let curr = timer.lock().unwrap().timer.remaining(now);
+        guest
+            .memory()
+            .write_value(curr_value.cast::<libc::itimerspec>(), &to_itimerspec(curr))?;
+        Ok(0)
+    }
+
+    /// read on a timerfd with a virtual timer: wait (in logical time) for the timer to
+    /// expire, then report the number of expirations since the last read.
+    pub async fn read_virtual_timerfd<G: Guest<Self>>(
+        &self,
+        guest: &mut G,
+        call: syscalls::Read,
+        timer: Arc<Mutex<TimerfdState>>,
+        nonblocking: bool,
+    ) -> Result<i64, Error> {
+        const LEN: usize = std::mem::size_of::<u64>();
+        if call.len() < LEN {
+            return Err(Errno::EINVAL.into());
+        }
+        let buf = call.buf().ok_or(Errno::EFAULT)?;
+        loop {
+            let now = thread_observe_time(guest).await;
+            let (expirations, materialized, deadline) = {
+                let mut state = timer.lock().unwrap();
+                let expirations = state.timer.expire(now);
+                let materialized =
+                    expirations > 0 && std::mem::replace(&mut state.materialized, false);
+                (expirations, materialized, state.timer.deadline)
+            };
+            if expirations > 0 {
+                if materialized {
+                    Self::arm_kernel_timerfd(guest, call.fd(), Duration::ZERO).await?;
+                }
+                guest
+                    .memory()
+                    .write_exact(buf, &expirations.to_ne_bytes())?;
+                return Ok(LEN as i64);
+            }
+            if nonblocking {
+                return Err(Errno::EAGAIN.into());
+            }
+            // A disarmed timer may yet be armed by another thread, so keep yielding.
+            let request = match deadline {
+                Some(deadline) => Self::sleep_request_abs(guest, deadline).await,
+                None => Self::yield_request(guest),
+            };
+            if let ResumeStatus::Signaled = resource_request(guest, request).await {
+                return Err(Errno::EINTR.into());
+            }
+        }
+    }
+
+    /// Arm the kernel timer behind each virtual timerfd whose logical timer has expired,
+    /// so that it polls as readable.  This runs before each attempt of a polling syscall.
+    /// The kernel timer is set to expire immediately, and is disarmed again once the
+    /// expirations are read.
+    pub async fn materialize_expired_timerfds<G: Guest<Self>>(guest: &mut G) -> Result<(), Error> {
+        let timerfds = guest.thread_state().virtual_timerfds();
+        if timerfds.is_empty() {
+            return Ok(());
+        }
+        let now = thread_observe_time(guest).await;
+        for (fd, timer) in timerfds {
+            let due = {
+                let mut state = timer.lock().unwrap();
+                let due = !state.materialized
+                    && matches!(state.timer.deadline, Some(deadline) if deadline <= now);
+                state.materialized |= due;
+                due
+            };
+            if due {
+                trace!("Materializing expired virtual timer on timerfd {}", fd);
+                Self::arm_kernel_timerfd(guest, fd, Duration::from_nanos(1)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the kernel timer behind a virtual timerfd to a one-shot value, or disarm it
+    /// with a zero value.  This also resets its expiration count.
+    async fn arm_kernel_timerfd<G: Guest<Self>>(
+        guest: &mut G,
+        fd: RawFd,
+        value: Duration,
+    ) -> Result<(), Error> {
+        let mut stack = guest.stack().await;
+        let new_value = stack.push(to_itimerspec((value, Duration::ZERO)));
+        stack.commit()?;
+        let call = syscalls::TimerfdSettime::new()
+            .with_fd(fd)
+            .with_new_value(Some(new_value.cast()));
+        guest.inject(call).await?;
+        Ok(())
+    }
+
     async fn relative_time_from_abs_target<G: Guest<Self>>(
         guest: &mut G,
         target_time: LogicalTime,
@@ -292,3 +706,42 @@ impl<T: RecordOrReplay> Detcore<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cpu_clocks() {
+        assert_eq!(decode_cpu_clock(libc::CLOCK_MONOTONIC), None);
+        assert_eq!(
+            decode_cpu_clock(libc::CLOCK_THREAD_CPUTIME_ID),
+            Some((None, true))
+        );
+        // clock_getcpuclockid(42) and pthread_getcpuclockid of thread 43.
+        assert_eq!(decode_cpu_clock((!42 << 3) | 2), Some((Some(42), false)));
+        assert_eq!(decode_cpu_clock((!43 << 3) | 6), Some((Some(43), true)));
+    }
+
+    #[test]
+    fn proc_stat_paths() {
+        let target = |p: &str| proc_stat_target(Path::new(p));
+        assert_eq!(target("/proc/self/stat"), Some((None, false)));
+        assert_eq!(target("/proc/thread-self/stat"), Some((None, true)));
+        assert_eq!(target("/proc/7/stat"), Some((Some(7), false)));
+        assert_eq!(target("/proc/self/task/8/stat"), Some((Some(8), true)));
+        assert_eq!(target("/proc/self/statm"), None);
+        assert_eq!(target("/proc/stat"), None);
+        assert_eq!(target("/proc/net/stat"), None);
+    }
+
+    #[test]
+    fn patch_stat() {
+        let stat = "3 (a (b) c) R 1 3 3 0 -1 4194560 107 0 0 0 5 6 7 8 20 0 1 0 56 2437120\n";
+        assert_eq!(
+            patch_stat_cpu_times(stat, [1, 0, 2, 0]).unwrap(),
+            "3 (a (b) c) R 1 3 3 0 -1 4194560 107 0 0 0 1 0 2 0 20 0 1 0 56 2437120\n"
+        );
+        assert_eq!(patch_stat_cpu_times("3 (a) R 1", [0; 4]), None);
+    }
+}
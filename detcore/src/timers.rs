@@ -0,0 +1,189 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Virtual interval timers (timerfd, setitimer, POSIX timers) driven by logical time
+//! rather than the host clock.
+
+use std::fmt;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::types::LogicalTime;
+
+/// Ids for virtual POSIX timers start here, well clear of the small ids the kernel
+/// allocates to any timers that are not virtualized.
+pub const FIRST_VIRTUAL_TIMER_ID: i32 = 0x10000;
+
+/// Identifies one of the signal-delivering timers belonging to a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum TimerId {
+    /// The real-time timer shared by `alarm` and `setitimer(ITIMER_REAL)`.
+    Alarm,
+    /// A timer created by `timer_create`, with its (per-process) id.
+    Posix(i32),
+    /// The `--timeout` of the whole run, which belongs to the root process.
+    Timeout,
+}
+
+impl fmt::Display for TimerId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimerId::Alarm => write!(f, "alarm"),
+            TimerId::Posix(id) => write!(f, "timer{}", id),
+            TimerId::Timeout => write!(f, "timeout"),
+        }
+    }
+}
+
+/// A one-shot or periodic timer, following the `itimerspec` model: an absolute time of
+/// next expiration plus an optional reload interval.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VirtualTimer {
+    /// Next expiration, or `None` if the timer is disarmed.
+    pub deadline: Option<LogicalTime>,
+    /// Reload interval after each expiration; zero for a one-shot timer.
+    pub interval: Duration,
+}
+
+impl VirtualTimer {
+    /// Arm the timer with a new first expiration (or disarm it, if `None`), returning the
+    /// old setting.
+    pub fn set(&mut self, deadline: Option<LogicalTime>, interval: Duration) -> VirtualTimer {
+        let new = VirtualTimer {
+            deadline,
+            // A disarmed timer has no interval, as with the kernel.
+            interval: if deadline.is_some() {
+                interval
+            } else {
+                Duration::ZERO
+            },
+        };
+        std::mem::replace(self, new)
+    }
+
+    /// Consume all expirations up to and including `now`.  A periodic timer is reloaded
+    /// for its next period after `now`, and a one-shot timer is disarmed.  Returns the
+    /// number of expirations, zero if the timer has not fired.
+    pub fn expire(&mut self, now: LogicalTime) -> u64 {
+        match self.deadline {
+            Some(deadline) if deadline <= now => {
+                if self.interval.is_zero() {
+                    self.deadline = None;
+                    1
+                } else {
+                    let periods = (now - deadline).as_nanos() as u128 / self.interval.as_nanos();
+                    let count = periods + 1;
+                    self.deadline = Some(deadline + count * self.interval.as_nanos());
+                    count as u64
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    /// The `(value, interval)` pair reported by the `*_gettime` family: time until the
+    /// next expiration (zero if disarmed) and the reload interval.
+    pub fn remaining(&self, now: LogicalTime) -> (Duration, Duration) {
+        let mut next = *self;
+        next.expire(now);
+        let value = match next.deadline {
+            Some(deadline) => deadline.duration_since(now),
+            None => Duration::ZERO,
+        };
+        (value, self.interval)
+    }
+}
+
+/// The virtual state behind a timerfd.  Shared (via `Arc`) by every dup of the fd.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimerfdState {
+    /// The logical timer itself.
+    pub timer: VirtualTimer,
+    /// Whether the underlying kernel timerfd has been armed so that it polls as readable,
+    /// because the virtual timer has expired.  The kernel timer is otherwise never armed.
+    pub materialized: bool,
+}
+
+/// Convert a guest timespec to a duration, rejecting out-of-range values as the kernel does.
+pub fn timespec_to_duration(ts: &libc::timespec) -> Option<Duration> {
+    if ts.tv_sec < 0 || ts.tv_nsec < 0 || ts.tv_nsec >= 1_000_000_000 {
+        None
+    } else {
+        Some(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+    }
+}
+
+/// Convert a guest timeval to a duration, rejecting out-of-range values as the kernel does.
+pub fn timeval_to_duration(tv: &libc::timeval) -> Option<Duration> {
+    if tv.tv_sec < 0 || tv.tv_usec < 0 || tv.tv_usec >= 1_000_000 {
+        None
+    } else {
+        Some(Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000))
+    }
+}
+
+/// Build the `itimerspec` reported to the guest for a `(value, interval)` pair.
+pub fn to_itimerspec((value, interval): (Duration, Duration)) -> libc::itimerspec {
+    let ts = |d: Duration| libc::timespec {
+        tv_sec: d.as_secs() as libc::time_t,
+        tv_nsec: d.subsec_nanos() as libc::c_long,
+    };
+    libc::itimerspec {
+        it_interval: ts(interval),
+        it_value: ts(value),
+    }
+}
+
+/// Build the `itimerval` reported to the guest for a `(value, interval)` pair.  Like the
+/// kernel, a non-zero value is never rounded down to zero microseconds.
+pub fn to_itimerval((value, interval): (Duration, Duration)) -> libc::itimerval {
+    let tv = |d: Duration| {
+        let micros = (d.as_nanos() + 999) / 1000;
+        libc::timeval {
+            tv_sec: (micros / 1_000_000) as libc::time_t,
+            tv_usec: (micros % 1_000_000) as libc::suseconds_t,
+        }
+    };
+    libc::itimerval {
+        it_interval: tv(interval),
+        it_value: tv(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn periodic_expirations() {
+        let mut timer = VirtualTimer::default();
+        assert_eq!(timer.expire(LogicalTime::from_secs(100)), 0);
+
+        timer.set(Some(LogicalTime::from_secs(10)), Duration::from_secs(3));
+        assert_eq!(timer.expire(LogicalTime::from_secs(9)), 0);
+        assert_eq!(
+            timer.remaining(LogicalTime::from_secs(9)),
+            (Duration::from_secs(1), Duration::from_secs(3))
+        );
+        // Fires at 10, 13, and 16:
+        assert_eq!(timer.expire(LogicalTime::from_secs(17)), 3);
+        assert_eq!(timer.deadline, Some(LogicalTime::from_secs(19)));
+        assert_eq!(
+            timer.remaining(LogicalTime::from_secs(20)),
+            (Duration::from_secs(2), Duration::from_secs(3))
+        );
+
+        let old = timer.set(Some(LogicalTime::from_secs(30)), Duration::ZERO);
+        assert_eq!(old.interval, Duration::from_secs(3));
+        assert_eq!(timer.expire(LogicalTime::from_secs(30)), 1);
+        assert_eq!(timer.deadline, None);
+        assert_eq!(timer.expire(LogicalTime::from_secs(40)), 0);
+    }
+}
@@ -18,12 +18,14 @@ use std::fmt::Write;
 use std::fs;
 use std::fs::File;
 use std::num::NonZeroUsize;
+use std::path::Path;
 use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicU16;
 use std::sync::atomic::Ordering::SeqCst;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
 use std::time::SystemTime;
 
 use chrono::DateTime;
@@ -31,6 +33,7 @@ use chrono::Utc;
 use nix::sys::signal;
 use nix::sys::signal::Signal;
 use nix::unistd::Pid;
+use rand::Rng;
 use reverie::syscalls::AddrMut;
 use reverie::syscalls::CloneFlags;
 use reverie::syscalls::MemoryAccess;
@@ -47,11 +50,25 @@ use tracing::info;
 use tracing::trace;
 use tracing::warn;
 
+use crate::audit::Imperfection;
+use crate::audit::SyscallAudit;
 use crate::config::Config;
+use crate::config::SpawnOrder;
 use crate::consts::ROOT_DETPID;
+use crate::detlog;
+use crate::entropy::EntropyPool;
+use crate::instrument::take_registered_instruments;
 use crate::ivar::Ivar;
+use crate::netrecord::Direction;
+use crate::netrecord::NetConnection;
+use crate::netrecord::NetRecording;
+use crate::perfetto::Trace;
 use crate::preemptions::PreemptionReader;
+use crate::preemptions::PreemptionRecord;
 use crate::preemptions::ThreadHistory;
+use crate::ptrace::AttachError;
+use crate::ptrace::Resume;
+use crate::ptrace::Stop;
 use crate::record_or_replay::RecordOrReplay;
 use crate::resources::Permission;
 use crate::resources::ResourceID;
@@ -70,7 +87,14 @@ use crate::scheduler::Scheduler;
 use crate::scheduler::Seconds;
 use crate::scheduler::ThreadNextTurn;
 use crate::scheduler::DEFAULT_PRIORITY;
+use crate::stacktrace;
+use crate::summary::nondeterminism_warnings;
+use crate::summary::RunSummary;
+use crate::summary::Scorecard;
+use crate::summary::ThreadSummary;
+use crate::timers::TimerId;
 use crate::tool_local::Detcore;
+use crate::tool_local::ThreadStats;
 use crate::types::*;
 use crate::util::truncated;
 
@@ -80,6 +104,8 @@ struct InodePool {
     inodes: HashMap<RawInode, DetInode>,
     detinodes_info: HashMap<DetInode, DetInodeInfo>,
     next_inode: RawInode,
+    /// Raw device ids, to the deterministic ones reported with `--normalize-stat`.
+    devices: HashMap<u64, u64>,
 }
 
 /// Everything we know (globally) about a DetInode.
@@ -101,9 +127,17 @@ impl InodePool {
             inodes: HashMap::new(),
             detinodes_info: HashMap::new(),
             next_inode: 1,
+            devices: HashMap::new(),
         }
     }
 
+    // Map a raw device id to a deterministic one, numbered in order of discovery.  These
+    // read as device 0:N, the major number used by virtual filesystems.
+    fn add_device(&mut self, raw_dev: u64) -> u64 {
+        let next = self.devices.len() as u64 + 1;
+        *self.devices.entry(raw_dev).or_insert(next)
+    }
+
     // Allocate the next deterministic inode.  This takes the raw-inode and
     // can return an existing mapping or extend the mapping by creating a
     // new deterministic inode. The returned inode is strictly increasing
@@ -187,6 +221,22 @@ pub struct GlobalState {
 
     /// The start is when we construct the global state.  Close enough.
     realtime_start: SystemTime,
+
+    /// Bytes loaded from `--entropy-file`, handed out to guest threads on request.
+    entropy: Mutex<EntropyPool>,
+
+    /// External connections being recorded for `--record-network-to`, or those yet to be
+    /// replayed from `--replay-network-from`.
+    network: Mutex<NetRecording>,
+
+    /// The final stats of each thread that has exited, for the run summary.
+    exited_threads: Mutex<Vec<ThreadSummary>>,
+
+    /// The signals delivered to guest threads, and when, for `--emit-trace`.
+    signals: Mutex<Vec<(DetTid, LogicalTime, Signal)>>,
+
+    /// The syscalls which were not made deterministic, for `--audit-syscalls`.
+    audit: Mutex<SyscallAudit>,
 }
 
 impl Default for GlobalState {
@@ -235,6 +285,19 @@ impl GlobalState {
         info!("Scheduler state at exit:\n{}", sched.full_summary());
     }
 
+    /// Write the timeline of the run, from the record of its schedule, for `--emit-trace`.
+    fn emit_trace(&self, path: &Path, record: PreemptionRecord) {
+        let mut trace = Trace::new(&record.as_vecs(), &record.into_global());
+        let mut signals = self.signals.lock().unwrap();
+        signals.sort_by_key(|(dettid, time, _)| (*dettid, *time));
+        for (dettid, time, signal) in signals.iter() {
+            trace.add_signal(*dettid, *time, signal);
+        }
+        if let Err(e) = trace.write_to(path) {
+            warn!("Failed to write the timeline to {:?}: {}", path, e);
+        }
+    }
+
     /// Shut down anything running, in particular wait on the scheduler.
     ///
     /// This is basically the destructor for the global state, but is here rather than in the
@@ -243,8 +306,8 @@ impl GlobalState {
     /// Print a summary of the execution, typically called when it is complete.
     ///
     /// If the boolean argument is true, print to stderr, otherwise only print the summary
-    /// to the log.
-    pub async fn clean_up(mut self, to_stderr: bool) {
+    /// to the log.  Either way, the summary is also returned in machine-readable form.
+    pub async fn clean_up(mut self, to_stderr: bool) -> RunSummary {
         if let Some(handle) = self.sched_handle.take() {
             debug!("Global state cleanup, confirming scheduler has shut down...");
             handle.await.expect("Global scheduler clean shutdown");
@@ -260,7 +323,6 @@ impl GlobalState {
         let flush = |det: bool, buf: &mut String| {
             if to_stderr {
                 // In this case, print summary irrespective of logging level.
-                // TODO: output summary in machine-readable, JSON form.
                 eprint!("{}", buf);
             } else if det {
                 info!("{}", buf);
@@ -281,7 +343,19 @@ impl GlobalState {
             sched.turn, sched.recorded_event_count, sched.traced_event_count
         )
         .unwrap();
+        if let Some(divergence) = &sched.fuzzy_replay {
+            // Asked for with --fuzzy-replay, so printed whatever the logging level.
+            eprint!("{}", divergence);
+        }
+        if self.cfg.audit_syscalls {
+            // Likewise with --audit-syscalls.
+            eprint!("{}", self.audit.lock().unwrap());
+        }
 
+        if let (Some(path), Some(pw)) = (&self.cfg.emit_trace, &mut sched.preemption_writer) {
+            self.emit_trace(path, pw.snapshot());
+            writeln!(buf, "Wrote a timeline of the run to {:?}", path).unwrap();
+        }
         if let Some(pw) = sched.preemption_writer.take() {
             writeln!(
                 buf,
@@ -299,6 +373,20 @@ impl GlobalState {
             }
         }
 
+        if let Some(path) = &self.cfg.record_network_to {
+            let network = self.network.lock().unwrap();
+            writeln!(
+                buf,
+                "Recorded {} external connection(s) to file {:?}",
+                network.num_connections(),
+                path
+            )
+            .unwrap();
+            if let Err(str) = network.save(path) {
+                warn!("{}", str);
+            }
+        }
+
         // Real time report:
         // N.B.: We don't have a job-level exit hook atm (T76248597), so we use the
         // CURRENT time -- that we are calling summarize -- as the end time:
@@ -313,6 +401,7 @@ impl GlobalState {
         flush(false, &mut buf);
 
         // Virtual time report:
+        let mut virtual_time_ns = None;
         if self.cfg.virtualize_time {
             let final_time = self.global_time.lock().unwrap();
             let final_time_ns = final_time.as_nanos();
@@ -325,6 +414,7 @@ impl GlobalState {
                     final_time_ns - epoch_ns
                 )
                 .unwrap();
+                virtual_time_ns = Some((final_time_ns - epoch_ns).as_nanos());
             } else {
                 error!(
                     "Internal invariant violated! Global time is before epoch start {}",
@@ -333,6 +423,28 @@ impl GlobalState {
             }
         }
         flush(true, &mut buf);
+
+        let mut summary = RunSummary {
+            seed: self.cfg.seed,
+            sched_seed: self.cfg.sched_seed.unwrap_or(self.cfg.seed),
+            turns: sched.turn,
+            schedule_hash: format!("{:016x}", sched.schedule_hash),
+            virtual_time_ns,
+            nondeterminism_warnings: nondeterminism_warnings(&self.cfg),
+            replay_divergence: sched.fuzzy_replay.take(),
+            sched_overhead: sched.overhead,
+            ..Default::default()
+        };
+        let mut threads = std::mem::take(&mut *self.exited_threads.lock().unwrap());
+        threads.sort_by_key(|thread| thread.dettid);
+        for thread in threads {
+            summary.add_thread(thread);
+        }
+        summary.scorecard =
+            Scorecard::new(&self.cfg, &summary.syscall_counts, summary.threads.len());
+        buf.push_str(&summary.scorecard.to_string());
+        flush(true, &mut buf);
+        summary
     }
 }
 
@@ -358,6 +470,8 @@ impl GlobalTool for GlobalState {
 
     /// Called once during startup.
     async fn init_global_state(cfg: &Config) -> GlobalState {
+        // The run takes the instruments registered for it, as its scheduler takes the hooks.
+        take_registered_instruments();
         let sched = Arc::new(Mutex::new(Scheduler::new(cfg)));
         let global_time = Arc::new(Mutex::new(GlobalTime::new(cfg)));
         let handle = if cfg.sequentialize_threads {
@@ -373,6 +487,14 @@ impl GlobalTool for GlobalState {
 
         let range = GlobalState::read_port_range();
 
+        let entropy = Mutex::new(EntropyPool::new(cfg));
+
+        let network = Mutex::new(cfg.replay_network_from.as_ref().map_or_else(
+            NetRecording::default,
+            // The CLI checks that the recording loads before the run.
+            |path| NetRecording::load(path).unwrap_or_else(|e| panic!("{}", e)),
+        ));
+
         GlobalState {
             sched,
             next_port: AtomicU16::new(range[0]),
@@ -387,6 +509,11 @@ impl GlobalTool for GlobalState {
             realtime_start: SystemTime::now(),
             global_time,
             preemptions_to_replay,
+            entropy,
+            network,
+            exited_threads: Default::default(),
+            signals: Default::default(),
+            audit: Default::default(),
         }
     }
 
@@ -413,6 +540,19 @@ impl GlobalTool for GlobalState {
             GlobalRequest::RequestResources(rs, pid) => {
                 R::RequestResources(self.recv_request_resources(from, pid, rs).await)
             }
+            GlobalRequest::RequestPollResources(rs, pid, targets) => {
+                let status = self.recv_request_resources(from, pid, rs).await;
+                let exited = self.sched.lock().unwrap().exited_unreaped(&targets);
+                R::RequestPollResources((status, exited))
+            }
+            GlobalRequest::RequestSleep(delta, pid) => {
+                let now = self.global_time.lock().unwrap().as_nanos();
+                let until = self.cfg.round_timer_expiration(now + delta);
+                let mut rs = Resources::new(dtid);
+                rs.insert(ResourceID::SleepUntil(until), Permission::W);
+                let status = self.recv_request_resources(from, pid, rs).await;
+                R::RequestSleep((status, until))
+            }
             GlobalRequest::ReleaseResources(rs) => {
                 R::ReleaseResources(self.recv_release_resources(from, rs).await)
             }
@@ -420,19 +560,25 @@ impl GlobalTool for GlobalState {
                 R::ReleaseAllResources(self.recv_release_all_resources(from).await)
             }
             // Requested by the parent thread:
-            GlobalRequest::CreateChildThread(dettid, parent_detpid, ctid, flags, priority) => {
-                R::CreateChildThread(
-                    self.recv_create_child_thread(
-                        from,
-                        parent_detpid,
-                        dettid,
-                        ctid,
-                        flags,
-                        priority,
-                    )
-                    .await,
+            GlobalRequest::CreateChildThread(
+                dettid,
+                parent_detpid,
+                ctid,
+                flags,
+                priority,
+                spawn_order,
+            ) => R::CreateChildThread(
+                self.recv_create_child_thread(
+                    from,
+                    parent_detpid,
+                    dettid,
+                    ctid,
+                    flags,
+                    priority,
+                    spawn_order,
                 )
-            }
+                .await,
+            ),
             // Requested by the child thread itself:
             GlobalRequest::StartNewThread(dettid, detpid) => {
                 R::StartNewThread(self.recv_start_new_thread(from, dettid, detpid).await)
@@ -440,6 +586,11 @@ impl GlobalTool for GlobalState {
             GlobalRequest::DeregisterThread(dettid, detpid) => {
                 R::DeregisterThread(self.recv_deregister_thread(from, dettid, detpid).await)
             }
+            GlobalRequest::ThreadStats(dettid, detpid, stats) => {
+                let thread = ThreadSummary::new(dettid, detpid, &stats);
+                self.exited_threads.lock().unwrap().push(thread);
+                R::ThreadStats(())
+            }
             GlobalRequest::FutexAction(dettid, action, futexid, init_read, mask) => R::FutexAction(
                 self.recv_futex_action(from, dettid, action, futexid, init_read, mask)
                     .await,
@@ -447,6 +598,9 @@ impl GlobalTool for GlobalState {
             GlobalRequest::DeterminizeInode(ino) => {
                 R::DeterminizeInode(self.recv_determinize_inode(from, ino).await)
             }
+            GlobalRequest::DeterminizeDevice(dev) => {
+                R::DeterminizeDevice(self.recv_determinize_device(from, dev).await)
+            }
             GlobalRequest::UnlinkInode(d_ino) => {
                 R::UnlinkInode(self.recv_unlink_inode(from, d_ino).await)
             }
@@ -455,14 +609,143 @@ impl GlobalTool for GlobalState {
                 let ns = self.global_time.lock().unwrap().as_nanos();
                 R::GlobalTimeLowerBound(ns)
             }
+            GlobalRequest::SchedulePosition => {
+                let sched = self.sched.lock().unwrap();
+                R::SchedulePosition((sched.turn, sched.recorded_event_count))
+            }
+            GlobalRequest::ChildProcesses(detpid) => {
+                R::ChildProcesses(self.sched.lock().unwrap().child_processes(detpid))
+            }
+            GlobalRequest::ReapChild(parent, child) => {
+                let mut sched = self.sched.lock().unwrap();
+                let global_time = self.global_time.lock().unwrap();
+                R::ReapChild(sched.reap_child(parent, child, &global_time))
+            }
+            GlobalRequest::CpuTimes(dettid) => {
+                let mut sched = self.sched.lock().unwrap();
+                let global_time = self.global_time.lock().unwrap();
+                R::CpuTimes(sched.cpu_times(dettid, &global_time))
+            }
+            GlobalRequest::ClaimSigchld(detpid) => {
+                R::ClaimSigchld(self.sched.lock().unwrap().claim_sigchld(detpid))
+            }
+            GlobalRequest::ProcessExited(detpid) => {
+                R::ProcessExited(self.sched.lock().unwrap().process_exited(detpid))
+            }
+            GlobalRequest::ExitedUnreaped(targets) => {
+                R::ExitedUnreaped(self.sched.lock().unwrap().exited_unreaped(&targets))
+            }
+            GlobalRequest::VforkPending(dettid) => {
+                R::VforkPending(self.sched.lock().unwrap().vfork_children.contains(&dettid))
+            }
+            GlobalRequest::VforkDone(dettid) => {
+                self.sched.lock().unwrap().vfork_children.remove(&dettid);
+                R::VforkDone(())
+            }
+            GlobalRequest::ProcessExeced(detpid) => {
+                self.sched.lock().unwrap().process_execed(detpid);
+                R::ProcessExeced(())
+            }
+            GlobalRequest::PtraceAttach(tracee, tracer, options, stop) => R::PtraceAttach(
+                self.sched
+                    .lock()
+                    .unwrap()
+                    .ptrace_attach(tracee, tracer, options, stop),
+            ),
+            GlobalRequest::PtraceTracer(dettid) => {
+                R::PtraceTracer(self.sched.lock().unwrap().ptrace.tracer_of(dettid))
+            }
+            GlobalRequest::PtraceStop(dettid, status, signal, regs) => R::PtraceStop(
+                self.sched
+                    .lock()
+                    .unwrap()
+                    .ptrace_stop(dettid, status, signal, regs),
+            ),
+            GlobalRequest::PtracePollResume(dettid) => {
+                R::PtracePollResume(self.sched.lock().unwrap().ptrace.poll_resume(dettid))
+            }
+            GlobalRequest::PtraceGetStop(tracee, tracer) => R::PtraceGetStop(
+                self.sched
+                    .lock()
+                    .unwrap()
+                    .ptrace
+                    .stopped(tracee, tracer)
+                    .cloned(),
+            ),
+            GlobalRequest::PtraceSetOptions(tracee, tracer, options) => R::PtraceSetOptions(
+                self.sched
+                    .lock()
+                    .unwrap()
+                    .ptrace
+                    .set_options(tracee, tracer, options),
+            ),
+            GlobalRequest::PtraceResume(tracee, tracer, resume) => R::PtraceResume(
+                self.sched
+                    .lock()
+                    .unwrap()
+                    .ptrace
+                    .resume(tracee, tracer, resume),
+            ),
+            GlobalRequest::PtraceStops(tracer) => {
+                R::PtraceStops(self.sched.lock().unwrap().ptrace.stops_for(tracer))
+            }
+            GlobalRequest::PtraceStopReported(dettid) => {
+                self.sched.lock().unwrap().ptrace.stop_reported(dettid);
+                R::PtraceStopReported(())
+            }
             GlobalRequest::TraceSchedEvent(ev, detpid) => {
                 let print_backtrace = self.recv_trace_schedevent(ev, detpid).await;
                 R::TraceSchedEvent(print_backtrace)
             }
+            GlobalRequest::TraceSignal(SigWrapper(signal)) => {
+                let time = LogicalTime::from_nanos(time_from_guest);
+                self.signals.lock().unwrap().push((dtid, time, signal));
+                R::TraceSignal(())
+            }
+            GlobalRequest::AuditSyscall(name, how) => {
+                R::AuditSyscall(self.audit.lock().unwrap().record(name, how, dtid))
+            }
+            GlobalRequest::AuditStack(name, how, stack) => {
+                self.audit.lock().unwrap().set_stack(name, how, stack);
+                R::AuditStack(())
+            }
             GlobalRequest::RegisterAlarm(dpid, dtid, secs, sig) => {
                 let remaining = self.recv_register_alarm(dpid, dtid, secs, sig).await;
                 R::RegisterAlarm(remaining)
             }
+            GlobalRequest::CreateTimer(dpid, dtid, sig) => R::CreateTimer(
+                self.sched
+                    .lock()
+                    .unwrap()
+                    .create_timer(dpid, dtid, sig.map(|s| s.0)),
+            ),
+            GlobalRequest::SetTimer(dpid, dtid, id, value, interval, absolute) => R::SetTimer(
+                self.sched
+                    .lock()
+                    .unwrap()
+                    .set_timer(dpid, dtid, id, value, interval, absolute),
+            ),
+            GlobalRequest::GetTimer(dpid, id) => {
+                R::GetTimer(self.sched.lock().unwrap().get_timer(dpid, id))
+            }
+            GlobalRequest::DeleteTimer(dpid, id) => {
+                R::DeleteTimer(self.sched.lock().unwrap().delete_timer(dpid, id))
+            }
+            GlobalRequest::TakeEntropy(dtid, len) => {
+                R::TakeEntropy(self.entropy.lock().unwrap().take(dtid, len))
+            }
+            GlobalRequest::RecordConnection(peer) => {
+                R::RecordConnection(self.network.lock().unwrap().open(peer))
+            }
+            GlobalRequest::RecordNetChunk(id, direction, data, elapsed) => R::RecordNetChunk(
+                self.network
+                    .lock()
+                    .unwrap()
+                    .append(id, direction, data, elapsed),
+            ),
+            GlobalRequest::ReplayConnection(peer) => {
+                R::ReplayConnection(self.network.lock().unwrap().take(&peer))
+            }
             GlobalRequest::UnrecoverableShutdown => {
                 self.force_shutdown_with_error();
                 R::UnrecoverableShutdown(())
@@ -612,6 +895,7 @@ impl GlobalState {
     }
 
     /// Global portion of parent-forking-child protocol.  Called by the parent thread.
+    #[allow(clippy::too_many_arguments)]
     async fn recv_create_child_thread(
         &self,
         from_parent: Tid,
@@ -620,8 +904,19 @@ impl GlobalState {
         ctid: usize,
         flags: Option<CloneFlags>,
         maybe_priority: Option<Priority>,
+        maybe_spawn_order: Option<SpawnOrder>,
     ) {
-        let initial_priority = if let Some(pr) = &self.preemptions_to_replay {
+        // The root thread has no parent to be ordered against.
+        let spawn_order = if flags.is_none() {
+            None
+        } else if let Some(pr) = &self.preemptions_to_replay {
+            pr.thread_spawn_order(&child_dettid)
+        } else {
+            maybe_spawn_order
+        };
+        assert_ne!(spawn_order, Some(SpawnOrder::Random));
+
+        let mut initial_priority = if let Some(pr) = &self.preemptions_to_replay {
             assert!(maybe_priority.is_none());
             let prio = pr
                 .thread_initial_priority(&child_dettid)
@@ -634,9 +929,9 @@ impl GlobalState {
                 });
             if !is_ordinary_priority(prio) {
                 panic!(
-                    "Read a bad initial_prority from file: {}\nFull file: {}",
+                    "Read a bad initial_prority from file: {}\nPreemptions in the file: {}",
                     prio,
-                    pr.load_all(),
+                    pr.preemptions(),
                 );
             }
             prio
@@ -667,8 +962,8 @@ impl GlobalState {
                     resp: Ivar::new(),
                 });
 
+            let parent_dettid = DetTid::from_raw(from_parent.into()); // TODO(T78538674)
             {
-                let parent_dettid = DetTid::from_raw(from_parent.into()); // TODO(T78538674)
                 let is_group_leader = if let Some(f) = flags {
                     !f.contains(CloneFlags::CLONE_THREAD)
                 } else {
@@ -677,9 +972,34 @@ impl GlobalState {
                 sched
                     .thread_tree
                     .add_child(parent_dettid, child_dettid, is_group_leader);
+                if flags.map_or(false, |f| f.contains(CloneFlags::CLONE_VFORK)) {
+                    sched.vfork_children.insert(child_dettid);
+                }
+            }
+
+            // Make sure the child's priority doesn't contradict the requested spawn order.
+            // Ties are broken by queue position below.
+            if let Some(parent_prio) = sched.priorities.get(&parent_dettid) {
+                match spawn_order {
+                    Some(SpawnOrder::ChildFirst) => {
+                        initial_priority = initial_priority.min(*parent_prio)
+                    }
+                    Some(SpawnOrder::ParentFirst) => {
+                        initial_priority = initial_priority.max(*parent_prio)
+                    }
+                    _ => {}
+                }
             }
 
-            if self.cfg.replay_schedule_from.is_none() {
+            if sched.replay_abandoned {
+                // The priority picked for replaying no longer applies.
+                let prio = if self.cfg.chaos {
+                    initial_priority
+                } else {
+                    DEFAULT_PRIORITY
+                };
+                sched.priorities.insert(child_dettid, prio);
+            } else if self.cfg.replay_schedule_from.is_none() {
                 // Give the thread an initial priority
                 let old_prio = sched.priorities.insert(child_dettid, initial_priority);
                 assert!(old_prio.is_none());
@@ -694,17 +1014,32 @@ impl GlobalState {
 
             if let Some(pr) = &mut sched.preemption_writer {
                 pr.register_thread(child_dettid, initial_priority);
+                if let Some(order) = spawn_order {
+                    pr.set_spawn_order(child_dettid, order);
+                }
             }
 
-            let pos = sched.runqueue_push_back(child_dettid);
-            debug!(
-                "[detcore] CreateChildThread with dtid {}: Added child to back of queue, position {}.",
-                child_dettid, pos,
-            );
+            if spawn_order == Some(SpawnOrder::ChildFirst) {
+                let pos = sched.runqueue_push_front(child_dettid);
+                debug!(
+                    "[detcore] CreateChildThread with dtid {}: Added child to front of queue (child-first), position {}.",
+                    child_dettid, pos,
+                );
+            } else {
+                let pos = sched.runqueue_push_back(child_dettid);
+                debug!(
+                    "[detcore] CreateChildThread with dtid {}: Added child to back of queue, position {}.",
+                    child_dettid, pos,
+                );
+            }
+            if parent_detpid == ROOT_DETPID {
+                sched.arm_timeout(ROOT_DETPID, child_dettid);
+            }
             sched.started_up.try_put(());
         }
-        // Parent thread yields so child can run (if it is higher priority).
-        if self.cfg.sequentialize_threads {
+        // Parent thread yields so child can run (if it is higher priority).  When running
+        // parent-first, the parent instead keeps its turn and the child waits in the queue.
+        if self.cfg.sequentialize_threads && spawn_order != Some(SpawnOrder::ParentFirst) {
             let mut rs = Resources::new(parent_detpid);
             rs.insert(ResourceID::ParentContinue(), Permission::W);
             self.recv_request_resources(from_parent, parent_detpid, rs)
@@ -838,6 +1173,12 @@ impl GlobalState {
                     sched.sleep_futex_waiter(&dettid, futexid, maybe_timeout);
                     // block on ivar, below
                 }
+                FutexAction::WaitFor(timeout) => {
+                    let now = self.global_time.lock().unwrap().as_nanos();
+                    let timeout = self.cfg.round_timer_expiration(now + timeout);
+                    sched.sleep_futex_waiter(&dettid, futexid, Some(timeout));
+                    // block on ivar, below
+                }
                 FutexAction::WaitFinished => {
                     return None;
                 }
@@ -883,6 +1224,12 @@ impl GlobalState {
         (dino, ns)
     }
 
+    async fn recv_determinize_device(&self, from: Tid, dev: u64) -> u64 {
+        let ddev = self.inodes.lock().unwrap().add_device(dev);
+        trace!("[detcore, dtid {}] resolved (raw) device {:#x} to {}", from, dev, ddev);
+        ddev
+    }
+
     async fn recv_unlink_inode(&self, from: Tid, d_ino: DetInode) {
         trace!("[detcore, dtid {}] unlink (det) inode {:?}", from, d_ino);
         self.inodes.lock().unwrap().remove_inode(d_ino);
@@ -944,6 +1291,23 @@ impl GlobalState {
             self.past_first_execve.store(true, SeqCst);
         }
 
+        let ev = {
+            let mut sched = self.sched.lock().unwrap();
+            // Only these can change the name of the thread (short of writing its comm file).
+            if let Op::Syscall(Sysno::prctl | Sysno::execve, SyscallPhase::Posthook) = ev.op {
+                // TODO(T78538674): virtualize pid/tid:
+                if let Ok(comm) = fs::read_to_string(format!("/proc/{}/comm", ev.dettid)) {
+                    sched
+                        .thread_tree
+                        .rename(&ev.dettid, comm.trim_end().to_owned());
+                }
+            }
+            match sched.thread_tree.identity_of(&ev.dettid) {
+                Some(identity) => ev.with_identity(identity.clone()),
+                None => ev,
+            }
+        };
+
         // Yield this guest thread if needed to follow schedule.
         let maybe_print = if self.cfg.replay_schedule_from.is_some() {
             let ConsumeResult {
@@ -1043,6 +1407,13 @@ pub enum GlobalRequest {
     /// Lock the resources
     /// Also contains the `DetPid` of the process containing the thread requesting resources.
     RequestResources(Resources, DetPid),
+    /// Lock the resources for a retry of a nonblocking syscall, like `RequestResources`, and
+    /// then check which of the given processes (those of the thread's pidfds) have exited in
+    /// the schedule but have not been waited for yet.
+    RequestPollResources(Resources, DetPid, Vec<DetPid>),
+    /// Sleep for the given time from the global time, like `RequestResources` for a
+    /// `SleepUntil`, but reading the global time in the same request.
+    RequestSleep(Duration, DetPid),
     /// Release the locks
     ReleaseResources(Resources),
     /// For convenience, release all the resources held by the current TID.
@@ -1052,8 +1423,16 @@ pub enum GlobalRequest {
     /// of the new child and it's starting scheduler priority IF it is available to the caller.
     /// The only scenario where the Priority will be missing is when we're replaying preemptions.
     /// In that case it is the global state that holds the information regarding the new thread's
-    /// initial priority.
-    CreateChildThread(DetTid, DetPid, usize, Option<CloneFlags>, Option<Priority>),
+    /// initial priority.  Likewise for the child-first/parent-first order, which is only
+    /// present when `--spawn-order` is in effect.
+    CreateChildThread(
+        DetTid,
+        DetPid,
+        usize,
+        Option<CloneFlags>,
+        Option<Priority>,
+        Option<SpawnOrder>,
+    ),
 
     /// New thread is alive and waiting to run its first instruction.  Contains the dettid
     /// and detpid of the new child.
@@ -1063,6 +1442,9 @@ pub enum GlobalRequest {
     /// further turns.
     DeregisterThread(DetTid, DetPid),
 
+    /// The final stats of an exiting thread, for the run summary.
+    ThreadStats(DetTid, DetPid, ThreadStats),
+
     /// Notify scheduler before/after futex action.
     /// The last two arguments are the initial contents of the memory word, and the mask.
     FutexAction(DetTid, FutexAction, FutexID, i32, i32),
@@ -1070,6 +1452,9 @@ pub enum GlobalRequest {
     /// Translate nondeterministic to deterministic inode.
     DeterminizeInode(RawInode),
 
+    /// Translate nondeterministic to deterministic device id.
+    DeterminizeDevice(u64),
+
     /// unlink an inode
     UnlinkInode(DetInode),
 
@@ -1079,12 +1464,108 @@ pub enum GlobalRequest {
     /// Retrieve global time.
     GlobalTimeLowerBound,
 
+    /// Retrieve the scheduler turn and the number of schedule events recorded so far.
+    SchedulePosition,
+
+    /// Retrieve the exited (in exit order) and the running child processes of a process.
+    ChildProcesses(DetPid),
+
+    /// Forget an exited child process, which its parent has waited for.
+    ReapChild(DetPid, DetPid),
+
+    /// Retrieve the virtual CPU times of a thread, of its process, and of the children its
+    /// process has waited for.
+    CpuTimes(DetTid),
+
+    /// A process received SIGCHLD: check whether the scheduler sent it.
+    ClaimSigchld(DetPid),
+
+    /// Check whether a process has exited in the schedule.
+    ProcessExited(DetPid),
+
+    /// Check which of the given processes have exited in the schedule but have not been
+    /// waited for yet.
+    ExitedUnreaped(Vec<DetPid>),
+
+    /// Check whether a vfork child has yet to exec or exit.
+    VforkPending(DetTid),
+
+    /// A vfork child has exec'd, releasing its parent.
+    VforkDone(DetTid),
+
+    /// A process has exec'd, discarding the timers which do not survive execve.
+    ProcessExeced(DetPid),
+
+    /// Start ptracing a thread with the given options, on behalf of a process, or of the
+    /// parent of the thread's process (`PTRACE_TRACEME`) if none is given.  The flag asks for
+    /// the thread to be stopped with SIGSTOP.
+    PtraceAttach(DetTid, Option<DetPid>, u32, bool),
+
+    /// Retrieve the process ptracing a thread, if any, and its options.
+    PtraceTracer(DetTid),
+
+    /// A ptraced thread enters a stop, with its wait status, signal and registers.
+    PtraceStop(DetTid, i32, i32, Vec<u64>),
+
+    /// A stopped ptraced thread checks whether its tracer has resumed it.
+    PtracePollResume(DetTid),
+
+    /// Retrieve the current stop of a thread ptraced by the given process.
+    PtraceGetStop(DetTid, DetPid),
+
+    /// Set the options of a stopped thread ptraced by the given process.
+    PtraceSetOptions(DetTid, DetPid, u32),
+
+    /// Resume a stopped thread ptraced by the given process.
+    PtraceResume(DetTid, DetPid, Resume),
+
+    /// Retrieve the unreported stops (in stop order) and all the tracees of a process.
+    PtraceStops(DetPid),
+
+    /// A wait has reported the stop of a ptraced thread.
+    PtraceStopReported(DetTid),
+
     /// Record scheduling event in a total order.
     TraceSchedEvent(SchedEvent, DetPid),
 
+    /// Record the delivery of a signal to a thread, for `--emit-trace`.
+    TraceSignal(SigWrapper),
+
+    /// Count a syscall which was not made deterministic, for `--audit-syscalls`.
+    AuditSyscall(String, Imperfection),
+
+    /// Keep the stack of the first audited syscall of its kind.
+    AuditStack(String, Imperfection, String),
+
     /// Basically performs an alarm syscall, takes seconds.
     RegisterAlarm(DetPid, DetTid, Seconds, SigWrapper),
 
+    /// Create a POSIX timer delivering the given signal (if any), like `timer_create`.
+    CreateTimer(DetPid, DetTid, Option<SigWrapper>),
+
+    /// Arm or disarm a process timer with a value and reload interval, like
+    /// `setitimer`/`timer_settime`.  The flag marks the value as an absolute time.
+    SetTimer(DetPid, DetTid, TimerId, Duration, Duration, bool),
+
+    /// Read back a process timer's setting and overrun count.
+    GetTimer(DetPid, TimerId),
+
+    /// Delete a POSIX timer.
+    DeleteTimer(DetPid, TimerId),
+
+    /// Take up to this many bytes from the `--entropy-file` stream for the given thread.
+    TakeEntropy(DetTid, usize),
+
+    /// Start recording a connection to the given external peer, for `--record-network-to`.
+    RecordConnection(String),
+
+    /// Append traffic to a recorded connection, along with the (host) time elapsed since
+    /// it was made.
+    RecordNetChunk(usize, Direction, Vec<u8>, Duration),
+
+    /// Take the next recorded connection to the given peer, for `--replay-network-from`.
+    ReplayConnection(String),
+
     /// The container is shutting down.  Exit the scheduler "thread".
     UnrecoverableShutdown,
 
@@ -1105,20 +1586,61 @@ pub enum GlobalRequest {
 #[derive(PartialEq, Debug, Eq, Clone, Serialize, Deserialize)]
 pub enum GlobalResponse {
     RequestResources(ResumeStatus),
+    RequestPollResources((ResumeStatus, Vec<DetPid>)),
+    /// Includes the time the sleep was to end at.
+    RequestSleep((ResumeStatus, LogicalTime)),
     ReleaseResources(()),
     ReleaseAllResources(()),
     CreateChildThread(()),
     /// Includes optional preemption points for the new thread.
     StartNewThread(Option<ThreadHistory>),
     DeregisterThread(()),
+    ThreadStats(()),
     FutexAction(Option<SchedValue>),
     /// Return the mtime as well:
     DeterminizeInode((DetInode, LogicalTime)),
+    DeterminizeDevice(u64),
     UnlinkInode(()),
     TouchFile(()),
     GlobalTimeLowerBound(LogicalTime),
+    SchedulePosition((u64, u64)),
+    ChildProcesses((Vec<DetPid>, Vec<DetPid>)),
+    ReapChild(Duration),
+    CpuTimes(Option<(Duration, Duration, Duration)>),
+    ClaimSigchld(bool),
+    ProcessExited(bool),
+    ExitedUnreaped(Vec<DetPid>),
+    VforkPending(bool),
+    VforkDone(()),
+    ProcessExeced(()),
+    PtraceAttach(Result<(), AttachError>),
+    PtraceTracer(Option<(DetPid, u32)>),
+    /// False if the thread is not ptraced.
+    PtraceStop(bool),
+    PtracePollResume(Option<Resume>),
+    PtraceGetStop(Option<Stop>),
+    PtraceSetOptions(bool),
+    PtraceResume(bool),
+    PtraceStops((Vec<(DetTid, i32)>, Vec<DetTid>)),
+    PtraceStopReported(()),
     TraceSchedEvent(MaybePrintStack),
+    TraceSignal(()),
+    /// True if it was the first of its kind.
+    AuditSyscall(bool),
+    AuditStack(()),
     RegisterAlarm(Seconds),
+    CreateTimer(i32),
+    /// The old setting, or `None` if the timer does not exist.
+    SetTimer(Option<(Duration, Duration)>),
+    GetTimer(Option<(Duration, Duration, u64)>),
+    DeleteTimer(bool),
+    /// Possibly fewer bytes than requested, if the entropy is exhausted.
+    TakeEntropy(Vec<u8>),
+    /// The id of the connection being recorded.
+    RecordConnection(usize),
+    RecordNetChunk(()),
+    /// `None` if there are no (more) recorded connections to the peer.
+    ReplayConnection(Option<NetConnection>),
     // TODO: use void_send_rpc, and remove this bogus response:
     UnrecoverableShutdown(()),
 
@@ -1187,6 +1709,49 @@ where
     }
 }
 
+/// Like `resource_request`, for a retry of a nonblocking syscall by a thread holding pidfds.
+/// Once the resources are acquired, also returns which of the pidfds' processes, `targets`,
+/// have exited in the schedule but have not been waited for yet.
+pub async fn poll_resource_request<G, T>(
+    guest: &mut G,
+    r: Resources,
+    targets: Vec<DetPid>,
+) -> (ResumeStatus, Vec<DetPid>)
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    if guest.config().sequentialize_threads {
+        let detpid = guest.thread_state().detpid.expect("detpid unset");
+        let req = GlobalRequest::RequestPollResources(r, detpid, targets);
+        let resp = send_and_update_time(guest, req).await;
+        match resp.1 {
+            GlobalResponse::RequestPollResources(x) => x,
+            _ => unreachable!(),
+        }
+    } else {
+        (ResumeStatus::Normal, Vec::new())
+    }
+}
+
+/// Like `resource_request` for a sleep of `delta` from the global time, which the scheduler
+/// reads as it takes the request, rather than in a round trip of its own.
+///
+/// Blocking: returns once the sleep is over, along with the time it was to end at.
+pub async fn sleep_for<G, T>(guest: &mut G, delta: Duration) -> (ResumeStatus, LogicalTime)
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    assert!(guest.config().sequentialize_threads);
+    let detpid = guest.thread_state().detpid.expect("detpid unset");
+    let resp = send_and_update_time(guest, GlobalRequest::RequestSleep(delta, detpid)).await;
+    match resp.1 {
+        GlobalResponse::RequestSleep(x) => x,
+        _ => unreachable!(),
+    }
+}
+
 /// Global method RPC to release all held resources.
 ///
 /// Nonblocking: future may return immediately before the central global object has
@@ -1255,7 +1820,9 @@ pub async fn create_child_thread<G, T>(
         // In preemption replay mode, the initial priority is set on the other
         // side of the rpc, in recv_create_child_thread.
         None
-    } else if guest.config().replay_schedule_from.is_some() {
+    } else if guest.config().replay_schedule_from.is_some()
+        && (child_dettid <= DetTid::from_raw(3) || !guest.config().chaos)
+    {
         // FIXME!  Find a cleaner way to make the root thread start off high-priority:
         if child_dettid <= DetTid::from_raw(3) {
             Some(REPLAY_FOREGROUND_PRIORITY)
@@ -1263,6 +1830,8 @@ pub async fn create_child_thread<G, T>(
             Some(REPLAY_DEFERRED_PRIORITY)
         }
     } else if guest.config().chaos {
+        // When replaying with chaos mode on, this is only used once the replay is abandoned, such
+        // as after a `--replay-prefix-then-chaos` prefix.
         Some(entropy_to_priority(
             guest
                 .thread_state_mut()
@@ -1272,11 +1841,31 @@ pub async fn create_child_thread<G, T>(
         Some(DEFAULT_PRIORITY)
     };
 
+    // Pick a child-first or parent-first ordering, unless replaying the recorded one.
+    let spawn_order = match guest.config().spawn_order {
+        _ if guest.config().replay_preemptions_from.is_some() => None,
+        Some(SpawnOrder::Random) => {
+            if guest.thread_state_mut().chaos_prng_next_u64("spawn_order") % 2 == 0 {
+                Some(SpawnOrder::ChildFirst)
+            } else {
+                Some(SpawnOrder::ParentFirst)
+            }
+        }
+        order => order,
+    };
+
     let detpid = guest.thread_state().detpid.expect("detpid unset");
 
     let resp = send_and_update_time(
         guest,
-        GlobalRequest::CreateChildThread(child_dettid, detpid, ctid, flags, starting_priority),
+        GlobalRequest::CreateChildThread(
+            child_dettid,
+            detpid,
+            ctid,
+            flags,
+            starting_priority,
+            spawn_order,
+        ),
     )
     .await;
     match resp.1 {
@@ -1315,11 +1904,37 @@ pub async fn deregister_thread<R>(
     }
 }
 
+/// Hand the final stats of an exiting thread to the global state.
+pub async fn report_thread_stats<R>(
+    dettid: DetTid,
+    detpid: DetPid,
+    threads_time: DetTime,
+    stats: ThreadStats,
+    reverie: &R,
+) where
+    // Note, this is called from a context where we DON'T have a full, operable `Guest`.
+    R: GlobalRPC<GlobalState>,
+{
+    let resp = reverie
+        .send_rpc((
+            threads_time,
+            GlobalRequest::ThreadStats(dettid, detpid, stats),
+        ))
+        .await;
+    match resp.1 {
+        GlobalResponse::ThreadStats(x) => x,
+        _ => unreachable!(),
+    }
+}
+
 /// Which actions we can take before/after a futex system call.
 #[derive(PartialEq, Debug, Eq, Clone, Copy, Serialize, Deserialize)]
 pub enum FutexAction {
     /// Check in before a FUTEX_WAIT, including an optional timeout.
     WaitRequest(Option<LogicalTime>),
+    /// Check in before a FUTEX_WAIT which times out after the given time from the global time,
+    /// as read by the scheduler (with `--lean-sched-points`).
+    WaitFor(Duration),
     /// Check in after a FUTEX_WAIT
     WaitFinished,
     /// Check in before a FUTEX_WAKE, parameterized by the number of threads woken.
@@ -1374,6 +1989,64 @@ where
     }
 }
 
+/// Map a (possibly new) raw device id to a deterministic one, for `--normalize-stat`.
+pub async fn determinize_device<G, T>(guest: &mut G, dev: u64) -> u64
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let resp = send_and_update_time(guest, GlobalRequest::DeterminizeDevice(dev)).await;
+    match resp.1 {
+        GlobalResponse::DeterminizeDevice(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Start recording a connection to an external peer, returning its id.
+pub async fn record_connection<G, T>(guest: &mut G, peer: String) -> usize
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let resp = send_and_update_time(guest, GlobalRequest::RecordConnection(peer)).await;
+    match resp.1 {
+        GlobalResponse::RecordConnection(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Record traffic on a connection to an external peer.
+pub async fn record_net_chunk<G, T>(
+    guest: &mut G,
+    id: usize,
+    direction: Direction,
+    data: Vec<u8>,
+    elapsed: Duration,
+) where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let req = GlobalRequest::RecordNetChunk(id, direction, data, elapsed);
+    let resp = send_and_update_time(guest, req).await;
+    match resp.1 {
+        GlobalResponse::RecordNetChunk(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Take the next recorded connection to an external peer, if there is one.
+pub async fn replay_connection<G, T>(guest: &mut G, peer: String) -> Option<NetConnection>
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let resp = send_and_update_time(guest, GlobalRequest::ReplayConnection(peer)).await;
+    match resp.1 {
+        GlobalResponse::ReplayConnection(x) => x,
+        _ => unreachable!(),
+    }
+}
+
 /// unlink a detfd, i.e. When `unlink` a file
 #[allow(unused)]
 pub async fn unlink_inode<G, T>(guest: &mut G, d_ino: DetInode)
@@ -1426,12 +2099,349 @@ where
     global_time_lower_bound(guest).await
 }
 
+/// Retrieve the current scheduler turn, and the number of schedule events recorded so far.
+pub async fn schedule_position<G, T>(guest: &mut G) -> (u64, u64)
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let resp = send_and_update_time(guest, GlobalRequest::SchedulePosition).await;
+    match resp.1 {
+        GlobalResponse::SchedulePosition(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Retrieve the child processes of a process which may be waited for: those which have exited,
+/// in the order they exited, and those still running.
+pub async fn child_processes<G, T>(guest: &mut G, detpid: DetPid) -> (Vec<DetPid>, Vec<DetPid>)
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let resp = send_and_update_time(guest, GlobalRequest::ChildProcesses(detpid)).await;
+    match resp.1 {
+        GlobalResponse::ChildProcesses(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Tell the scheduler that an exited child process has been waited for.
+pub async fn reap_child<G, T>(guest: &mut G, parent: DetPid, child: DetPid) -> Duration
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let resp = send_and_update_time(guest, GlobalRequest::ReapChild(parent, child)).await;
+    match resp.1 {
+        GlobalResponse::ReapChild(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Retrieve the virtual CPU times of a thread, of its process, and of the children its process
+/// has waited for.  `None` if the thread does not exist.
+pub async fn cpu_times<G, T>(
+    guest: &mut G,
+    dettid: DetTid,
+) -> Option<(Duration, Duration, Duration)>
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let resp = send_and_update_time(guest, GlobalRequest::CpuTimes(dettid)).await;
+    match resp.1 {
+        GlobalResponse::CpuTimes(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Check whether a SIGCHLD received by a process was sent by the scheduler, rather than by the
+/// kernel.
+pub async fn claim_sigchld<G, T>(guest: &mut G, detpid: DetPid) -> bool
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let resp = send_and_update_time(guest, GlobalRequest::ClaimSigchld(detpid)).await;
+    match resp.1 {
+        GlobalResponse::ClaimSigchld(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Check whether a guest process has exited, as of this point in the schedule.
+pub async fn process_exited<G, T>(guest: &mut G, detpid: DetPid) -> bool
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let resp = send_and_update_time(guest, GlobalRequest::ProcessExited(detpid)).await;
+    match resp.1 {
+        GlobalResponse::ProcessExited(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Check which of the given guest processes have exited, as of this point in the schedule, but
+/// have not been waited for yet.
+pub async fn exited_unreaped<G, T>(guest: &mut G, targets: Vec<DetPid>) -> Vec<DetPid>
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let resp = send_and_update_time(guest, GlobalRequest::ExitedUnreaped(targets)).await;
+    match resp.1 {
+        GlobalResponse::ExitedUnreaped(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Check whether a vfork child still holds its parent suspended.
+pub async fn vfork_pending<G, T>(guest: &mut G, child: DetTid) -> bool
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let resp = send_and_update_time(guest, GlobalRequest::VforkPending(child)).await;
+    match resp.1 {
+        GlobalResponse::VforkPending(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Called by a vfork child once it has exec'd, to let its parent resume.
+pub async fn vfork_done<G, T>(guest: &mut G)
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let dettid = guest.thread_state().dettid;
+    let resp = send_and_update_time(guest, GlobalRequest::VforkDone(dettid)).await;
+    match resp.1 {
+        GlobalResponse::VforkDone(()) => {}
+        _ => unreachable!(),
+    }
+}
+
+/// Called once a process has exec'd, to discard its POSIX timers.
+pub async fn process_execed<G, T>(guest: &mut G)
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let detpid = guest.thread_state().detpid.expect("detpid unset");
+    let resp = send_and_update_time(guest, GlobalRequest::ProcessExeced(detpid)).await;
+    match resp.1 {
+        GlobalResponse::ProcessExeced(()) => {}
+        _ => unreachable!(),
+    }
+}
+
+/// Start ptracing a thread, on behalf of `tracer` or, if `None`, of the parent of the thread's
+/// process.  With `stop`, the thread is also sent SIGSTOP.
+pub async fn ptrace_attach<G, T>(
+    guest: &mut G,
+    tracee: DetTid,
+    tracer: Option<DetPid>,
+    options: u32,
+    stop: bool,
+) -> Result<(), AttachError>
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let req = GlobalRequest::PtraceAttach(tracee, tracer, options, stop);
+    let resp = send_and_update_time(guest, req).await;
+    match resp.1 {
+        GlobalResponse::PtraceAttach(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Retrieve the process ptracing the calling thread, if any, and the options it set.
+pub async fn ptrace_tracer<G, T>(guest: &mut G) -> Option<(DetPid, u32)>
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let dettid = guest.thread_state().dettid;
+    let resp = send_and_update_time(guest, GlobalRequest::PtraceTracer(dettid)).await;
+    match resp.1 {
+        GlobalResponse::PtraceTracer(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Enter a ptrace stop, notifying the tracer.  Returns false if the calling thread is not
+/// ptraced (any more).
+pub async fn ptrace_stop<G, T>(guest: &mut G, status: i32, signal: i32, regs: Vec<u64>) -> bool
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let dettid = guest.thread_state().dettid;
+    let req = GlobalRequest::PtraceStop(dettid, status, signal, regs);
+    let resp = send_and_update_time(guest, req).await;
+    match resp.1 {
+        GlobalResponse::PtraceStop(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Check whether the tracer has resumed the calling thread from its ptrace stop.
+pub async fn ptrace_poll_resume<G, T>(guest: &mut G) -> Option<Resume>
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let dettid = guest.thread_state().dettid;
+    let resp = send_and_update_time(guest, GlobalRequest::PtracePollResume(dettid)).await;
+    match resp.1 {
+        GlobalResponse::PtracePollResume(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Retrieve the current stop of a tracee of the calling process.
+pub async fn ptrace_get_stop<G, T>(guest: &mut G, tracee: DetTid) -> Option<Stop>
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let detpid = guest.thread_state().detpid.expect("detpid unset");
+    let resp = send_and_update_time(guest, GlobalRequest::PtraceGetStop(tracee, detpid)).await;
+    match resp.1 {
+        GlobalResponse::PtraceGetStop(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Set the options of a stopped tracee of the calling process.
+pub async fn ptrace_set_options<G, T>(guest: &mut G, tracee: DetTid, options: u32) -> bool
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let detpid = guest.thread_state().detpid.expect("detpid unset");
+    let req = GlobalRequest::PtraceSetOptions(tracee, detpid, options);
+    let resp = send_and_update_time(guest, req).await;
+    match resp.1 {
+        GlobalResponse::PtraceSetOptions(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Resume a stopped tracee of the calling process.
+pub async fn ptrace_resume<G, T>(guest: &mut G, tracee: DetTid, resume: Resume) -> bool
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let detpid = guest.thread_state().detpid.expect("detpid unset");
+    let req = GlobalRequest::PtraceResume(tracee, detpid, resume);
+    let resp = send_and_update_time(guest, req).await;
+    match resp.1 {
+        GlobalResponse::PtraceResume(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Retrieve the stops of a process's tracees which no wait has reported yet, in the order they
+/// stopped, along with all of its tracees.
+pub async fn ptrace_stops<G, T>(guest: &mut G, detpid: DetPid) -> (Vec<(DetTid, i32)>, Vec<DetTid>)
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let resp = send_and_update_time(guest, GlobalRequest::PtraceStops(detpid)).await;
+    match resp.1 {
+        GlobalResponse::PtraceStops(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Tell the scheduler that a wait has reported a tracee's stop.
+pub async fn ptrace_stop_reported<G, T>(guest: &mut G, tracee: DetTid)
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let resp = send_and_update_time(guest, GlobalRequest::PtraceStopReported(tracee)).await;
+    match resp.1 {
+        GlobalResponse::PtraceStopReported(()) => {}
+        _ => unreachable!(),
+    }
+}
+
+/// Write a report on a guest thread that received a crashing signal, with its registers and
+/// backtrace, into the `--crash-report-dir`.  Only the crashing thread is covered: the other
+/// threads of the process are not at a point where we could inspect them.
+#[cfg(target_arch = "x86_64")]
+pub async fn write_crash_report<G, T>(guest: &mut G, dir: &Path, signal: Signal)
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let (turn, event) = schedule_position(guest).await;
+    let regs = guest.regs().await;
+    let ts = guest.thread_state();
+    let dettid = ts.dettid;
+    let time = ts.thread_logical_time.as_nanos();
+    let mut report = String::new();
+    writeln!(
+        report,
+        ":: Guest tid {} (pid {}) received {} at thread time {}, scheduler turn {}, schedule event {}.",
+        dettid,
+        ts.detpid.expect("detpid unset"),
+        signal,
+        time,
+        turn,
+        event,
+    )
+    .unwrap();
+    for (name, value) in [
+        ("rip", regs.rip),
+        ("rsp", regs.rsp),
+        ("rbp", regs.rbp),
+        ("rax", regs.rax),
+        ("rbx", regs.rbx),
+        ("rcx", regs.rcx),
+        ("rdx", regs.rdx),
+        ("rsi", regs.rsi),
+        ("rdi", regs.rdi),
+        ("r8", regs.r8),
+        ("r9", regs.r9),
+        ("r10", regs.r10),
+        ("r11", regs.r11),
+        ("r12", regs.r12),
+        ("r13", regs.r13),
+        ("r14", regs.r14),
+        ("r15", regs.r15),
+        ("eflags", regs.eflags),
+    ] {
+        writeln!(report, "{:>6} {:#018x}", name, value).unwrap();
+    }
+    match stacktrace::backtrace(guest).await {
+        Some(backtrace) => writeln!(report, "{}", backtrace).unwrap(),
+        None => warn!("Could not read backtrace!"),
+    }
+    let path = dir.join(format!("crash-{}-{}.txt", dettid, time));
+    if let Err(err) = fs::create_dir_all(dir).and_then(|()| fs::write(&path, report)) {
+        warn!("Could not write crash report {}: {}", path.display(), err);
+    } else {
+        info!("Wrote crash report to {}", path.display());
+    }
+}
+
 /// Helper function just for printing backtrace to a given file (otherwise stderr)
-fn print_backtrace<G, T>(guest: &mut G, maybe_path: &Option<PathBuf>)
+async fn print_backtrace<G, T>(guest: &mut G, maybe_path: &Option<PathBuf>)
 where
     G: Guest<Detcore<T>>,
     T: RecordOrReplay,
 {
+    // Unwound before the writer is opened, which must not be held across an await.
+    let backtrace = stacktrace::backtrace(guest).await;
     let mut file_writer: Box<dyn std::io::Write> = match &maybe_path {
         Some(path) => {
             Box::new(File::create(path).expect("Failed to open preemption stacktrace log file"))
@@ -1446,12 +2456,8 @@ where
         ts.thread_logical_time.as_nanos(),
     )
     .unwrap();
-    if let Some(backtrace) = guest.backtrace() {
-        if let Ok(pbt) = backtrace.pretty() {
-            writeln!(file_writer, "{}", pbt).unwrap();
-        } else {
-            writeln!(file_writer, "{}", backtrace).unwrap();
-        }
+    if let Some(backtrace) = backtrace {
+        writeln!(file_writer, "{}", backtrace).unwrap();
     } else {
         warn!("Could not read backtrace!");
     }
@@ -1508,7 +2514,46 @@ where
     };
     if let Some(x) = do_backtrace {
         trace!("[trace_schedevent] printing stacktrace via Reverie...");
-        print_backtrace(guest, &x);
+        print_backtrace(guest, &x).await;
+    }
+}
+
+/// Record that the current thread is handling a signal, for `--emit-trace`.
+pub async fn trace_signal<G, T>(guest: &mut G, signal: Signal)
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let resp = send_and_update_time(guest, GlobalRequest::TraceSignal(SigWrapper(signal))).await;
+    match resp.1 {
+        GlobalResponse::TraceSignal(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Record a syscall which was not made deterministic, for `--audit-syscalls`, along with the
+/// stack of the current thread if it is the first of its kind.  The syscall is given by its
+/// number, or by name (for those of 32-bit guests, which have no `Sysno`).
+pub async fn audit_syscall<G, T>(guest: &mut G, syscall: impl ToString, how: Imperfection)
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let name = syscall.to_string();
+    let resp = send_and_update_time(guest, GlobalRequest::AuditSyscall(name.clone(), how)).await;
+    let first = match resp.1 {
+        GlobalResponse::AuditSyscall(x) => x,
+        _ => unreachable!(),
+    };
+    if !first {
+        return;
+    }
+    if let Some(stack) = stacktrace::backtrace(guest).await {
+        let resp = send_and_update_time(guest, GlobalRequest::AuditStack(name, how, stack)).await;
+        match resp.1 {
+            GlobalResponse::AuditStack(x) => x,
+            _ => unreachable!(),
+        }
     }
 }
 
@@ -1532,6 +2577,112 @@ where
     }
 }
 
+/// Create a virtual POSIX timer for the current process, returning its id.
+pub async fn create_timer<G, T>(guest: &mut G, sig: Option<Signal>) -> i32
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let dettid = guest.thread_state().dettid;
+    let detpid = guest.thread_state().detpid.expect("detpid unset");
+    let resp = send_and_update_time(
+        guest,
+        GlobalRequest::CreateTimer(detpid, dettid, sig.map(SigWrapper)),
+    )
+    .await;
+    match resp.1 {
+        GlobalResponse::CreateTimer(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Arm or disarm one of the current process's timers with the global scheduler.
+/// Returns the previous `(value, interval)`, or `None` if the timer does not exist.
+pub async fn set_timer<G, T>(
+    guest: &mut G,
+    id: TimerId,
+    value: Duration,
+    interval: Duration,
+    absolute: bool,
+) -> Option<(Duration, Duration)>
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let dettid = guest.thread_state().dettid;
+    let detpid = guest.thread_state().detpid.expect("detpid unset");
+    let resp = send_and_update_time(
+        guest,
+        GlobalRequest::SetTimer(detpid, dettid, id, value, interval, absolute),
+    )
+    .await;
+    match resp.1 {
+        GlobalResponse::SetTimer(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Read one of the current process's timers: its `(value, interval, overrun)`, or `None`
+/// if the timer does not exist.
+pub async fn get_timer<G, T>(guest: &mut G, id: TimerId) -> Option<(Duration, Duration, u64)>
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let detpid = guest.thread_state().detpid.expect("detpid unset");
+    let resp = send_and_update_time(guest, GlobalRequest::GetTimer(detpid, id)).await;
+    match resp.1 {
+        GlobalResponse::GetTimer(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Delete one of the current process's POSIX timers.  Returns false if it did not exist.
+pub async fn delete_timer<G, T>(guest: &mut G, id: TimerId) -> bool
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let detpid = guest.thread_state().detpid.expect("detpid unset");
+    let resp = send_and_update_time(guest, GlobalRequest::DeleteTimer(detpid, id)).await;
+    match resp.1 {
+        GlobalResponse::DeleteTimer(x) => x,
+        _ => unreachable!(),
+    }
+}
+
+/// Fill a buffer with the guest-visible randomness for the current thread.  This comes from
+/// `--entropy-file` while it lasts, and otherwise from the thread's seeded PRNG.
+pub async fn fill_guest_random<G, T>(guest: &mut G, buf: &mut [u8])
+where
+    G: Guest<Detcore<T>>,
+    T: RecordOrReplay,
+{
+    let mut filled = 0;
+    if !guest.config().entropy_file.is_empty() {
+        let dettid = guest.thread_state().dettid;
+        let resp = send_and_update_time(guest, GlobalRequest::TakeEntropy(dettid, buf.len())).await;
+        let bytes = match resp.1 {
+            GlobalResponse::TakeEntropy(x) => x,
+            _ => unreachable!(),
+        };
+        filled = bytes.len();
+        buf[..filled].copy_from_slice(&bytes);
+        if filled < buf.len() {
+            detlog!(
+                "[dtid {}] USER RAND entropy file exhausted after {} of {} bytes, using seeded PRNG for the rest",
+                dettid,
+                filled,
+                buf.len()
+            );
+        }
+    }
+    guest
+        .thread_state_mut()
+        .thread_prng()
+        .fill(&mut buf[filled..]);
+}
+
 /// Signal an unrecoverable error that exits the entire container.
 /// Such exits are not determinizable (see "quasi-determinism").
 pub async fn unrecoverable_shutdown<G, T>(guest: &G) -> !
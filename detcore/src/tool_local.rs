@@ -8,6 +8,7 @@
 
 //! The process-local portion of the Detcore Reverie-tool.
 
+use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -32,7 +33,11 @@ use serde::Deserialize;
 use serde::Serialize;
 use tracing::debug;
 
+use crate::address_space::AddressSpace;
+use crate::compat::CompatAbi;
 use crate::config::Config;
+use crate::config::RdtscModel;
+use crate::cpuid::InterceptedCpuid;
 use crate::detlog;
 use crate::fd::*;
 use crate::preemptions::ThreadHistoryIterator;
@@ -42,7 +47,9 @@ use crate::resources::Permission;
 use crate::resources::ResourceID;
 use crate::resources::Resources;
 use crate::scheduler::Priority;
+use crate::seccomp::SeccompState;
 use crate::stat::*;
+use crate::timers::TimerfdState;
 use crate::types::*;
 use crate::util::rcbs_to_duration;
 
@@ -63,6 +70,9 @@ pub struct Detcore<T = NoopTool> {
     /// deterministic are forwarded to this tool. Thus, Detcore acts as a
     /// filter-map for syscalls.
     pub(crate) record_or_replay: T,
+
+    /// The cpuid results reported to the guest, see `--cpu-profile`.
+    pub(crate) cpuid: InterceptedCpuid,
 }
 
 /// The metadata associated with the file system view of a particular *process*.
@@ -212,11 +222,14 @@ impl FileMetadata {
 
 /// Various measurements of one guest thread's execution. This is useful for printing
 /// context in logs as we go and printing a final summary.
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
 pub struct ThreadStats {
     /// A simple count of how many syscalls have been handled on this thread.
     pub syscall_count: u64,
 
+    /// How many of each syscall, by name, have been handled on this thread.
+    pub syscall_counts: BTreeMap<String, u64>,
+
     /// A count of how many signals have arrived at this thread, total.
     pub signal_count: u64,
 
@@ -241,11 +254,16 @@ impl ThreadStats {
         Default::default()
     }
 
-    // TODO: this can evolve to keep a full histogram:
-    /// Increment the count of system calls
-    pub fn count_syscall(&mut self) {
+    /// Increment the count of system calls, and of this one in particular.
+    pub fn count_syscall(&mut self, name: &str) {
         self.syscall_count += 1;
         self.timeslice_syscall_count += 1;
+        match self.syscall_counts.get_mut(name) {
+            Some(count) => *count += 1,
+            None => {
+                self.syscall_counts.insert(name.to_owned(), 1);
+            }
+        }
     }
 
     /// Increment the count of signals.
@@ -293,6 +311,39 @@ pub struct ThreadState<T> {
     /// Initialized for new threads (shared or fresh), and then overwritten again on `execve`.
     pub file_metadata: Arc<Mutex<FileMetadata>>,
 
+    /// Futex addresses which have been waited on as condition variables, shared among all threads
+    /// in the same address space.  Reset on `execve`.
+    pub condvar_futexes: Arc<Mutex<BTreeSet<usize>>>,
+
+    /// The deterministic placement of mappings in this address space (`--deterministic-mmap`),
+    /// shared among all threads in the same address space.  Reset on `execve`.
+    pub address_space: Arc<Mutex<AddressSpace>>,
+
+    /// The `membarrier` commands this address space has registered for (as a mask of the
+    /// `MEMBARRIER_CMD_REGISTER_*` commands), shared among all threads in the same address
+    /// space.  Reset on `execve`.
+    pub membarrier_registrations: Arc<Mutex<i32>>,
+
+    /// The robust futex list heads registered (with `set_robust_list`) by each thread in this
+    /// process, shared among all threads in the same process.  Reset on `execve`.
+    pub robust_lists: Arc<Mutex<BTreeMap<DetTid, usize>>>,
+
+    /// Whether this thread was created by `vfork` (or `CLONE_VFORK`), and its parent is still
+    /// suspended, waiting for it to exec or exit.
+    pub vfork_child: bool,
+
+    /// Whether this thread is ptraced by another guest, which resumed it with `PTRACE_SYSCALL`
+    /// and so wants it to stop at its next syscall entry or exit.
+    pub ptrace_syscall_stops: bool,
+
+    /// The seccomp filters (or strict mode) this process has installed, under
+    /// `--emulate-seccomp`, shared among all threads in the same process.  Kept across `execve`.
+    pub seccomp: Arc<Mutex<SeccompState>>,
+
+    /// The 32-bit x86 ABI of the binary this process runs, if it is not a native one.  Set on
+    /// `execve`, and inherited by new threads and processes.
+    pub compat_abi: Option<CompatAbi>,
+
     /// pseudo random number state
     pub prng: Pcg64Mcg,
 
@@ -305,6 +356,9 @@ pub struct ThreadState<T> {
     /// the last RCB clock value committed to `thread_logical_time`
     pub committed_clock_value: u64,
 
+    /// The last virtual time stamp counter value returned by `rdtsc` on this thread.
+    pub last_tsc: u64,
+
     /// Thread state associated with record/replay.
     pub record_or_replay: T,
 
@@ -338,16 +392,30 @@ impl<T> std::fmt::Debug for ThreadState<T> {
             .field("stats", &self.stats)
             .field("clone_flags", &self.clone_flags)
             .field("file_metadata", &self.file_metadata)
+            .field("condvar_futexes", &self.condvar_futexes)
+            .field("address_space", &self.address_space)
+            .field("membarrier_registrations", &self.membarrier_registrations)
+            .field("robust_lists", &self.robust_lists)
+            .field("vfork_child", &self.vfork_child)
+            .field("ptrace_syscall_stops", &self.ptrace_syscall_stops)
+            .field("seccomp", &self.seccomp)
+            .field("compat_abi", &self.compat_abi)
             .field("prng", &self.prng)
             .field("chaos_prng", &self.chaos_prng)
             .field("thread_logical_time", &self.thread_logical_time)
             .field("committed_clock_value", &self.committed_clock_value)
+            .field("last_tsc", &self.last_tsc)
             .field("end_of_timeslice", &self.end_of_timeslice)
             .field("last_rcb_timer", &self.last_rcb_timer)
             .finish()
     }
 }
 
+/// Convert logical time into cycles of the virtual time stamp counter.
+fn nanos_to_cycles(cfg: &Config, nanos: LogicalTime) -> u64 {
+    (nanos.as_nanos() as u128 * cfg.tsc_mhz as u128 / 1000) as u64
+}
+
 impl<T> Default for ThreadState<T> {
     fn default() -> Self {
         unreachable!()
@@ -405,12 +473,21 @@ impl<T> ThreadState<T> {
             detpid: None, // Initialized later.
             stats: ThreadStats::new(),
             file_metadata: Arc::new(Mutex::new(FileMetadata::new().setup_stdio(pid.into()))),
+            condvar_futexes: Default::default(),
+            address_space: Default::default(),
+            membarrier_registrations: Default::default(),
+            robust_lists: Default::default(),
+            vfork_child: false,
+            ptrace_syscall_stops: false,
+            seccomp: Default::default(),
+            compat_abi: None,
             clone_flags: None,
             // For the root thread, we initialize from the seed in the config:
             prng: Pcg64Mcg::seed_from_u64(cfg.seed),
             chaos_prng: Pcg64Mcg::seed_from_u64(chaos_seed),
             thread_logical_time: DetTime::new(cfg),
             committed_clock_value: 0,
+            last_tsc: 0,
             end_of_timeslice: None, // Temporary/bogus.
             last_rcb_timer: None,
             record_or_replay,
@@ -478,6 +555,40 @@ impl<T> ThreadState<T> {
         self.metadata().with_detfd(fd, f)
     }
 
+    /// The timerfds with virtual timer state visible to this thread, in fd order.
+    pub fn virtual_timerfds(&self) -> Vec<(RawFd, Arc<Mutex<TimerfdState>>)> {
+        let mut timerfds: Vec<_> = self
+            .metadata()
+            .file_handles
+            .values()
+            .filter_map(|detfd| detfd.timer.as_ref().map(|t| (detfd.fd, t.clone())))
+            .collect();
+        timerfds.sort_by_key(|(fd, _)| *fd);
+        timerfds
+    }
+
+    /// The pidfds visible to this thread whose process we know, and which the kernel has not
+    /// been seen to report readable yet, in fd order.
+    pub fn unsettled_pidfds(&self) -> Vec<(RawFd, DetPid)> {
+        let mut pidfds: Vec<_> = self
+            .metadata()
+            .file_handles
+            .values()
+            .filter(|detfd| !detfd.pidfd_settled)
+            .filter_map(|detfd| detfd.pidfd.map(|pid| (detfd.fd, pid)))
+            .collect();
+        pidfds.sort_by_key(|(fd, _)| *fd);
+        pidfds
+    }
+
+    /// The process a pidfd refers to, if `fd` is a pidfd whose process we know.
+    pub fn pidfd_target(&self, fd: RawFd) -> Option<DetPid> {
+        self.metadata()
+            .file_handles
+            .get(&fd)
+            .and_then(|detfd| detfd.pidfd)
+    }
+
     /// remove a rawfd
     pub fn remove_fd(&self, fd: RawFd) {
         self.metadata().remove_fd(fd)
@@ -494,6 +605,28 @@ impl<T> ThreadState<T> {
         &mut self.prng
     }
 
+    /// Produce the virtual time stamp counter value for an `rdtsc` executed by this thread,
+    /// according to `--rdtsc-model`.  Advances logical time when the model counts `rdtsc` as
+    /// progress.
+    pub fn next_virtual_tsc(&mut self, cfg: &Config) -> u64 {
+        let tsc = match cfg.rdtsc_model {
+            RdtscModel::Fixed => {
+                self.thread_logical_time.add_rdtsc();
+                nanos_to_cycles(cfg, self.thread_logical_time.as_nanos())
+            }
+            RdtscModel::Instruction => {
+                self.thread_logical_time.add_rdtsc();
+                self.last_tsc + cfg.tsc_increment
+            }
+            RdtscModel::Syscall => {
+                let cycles = nanos_to_cycles(cfg, self.thread_logical_time.as_nanos());
+                cycles.max(self.last_tsc + 1)
+            }
+        };
+        self.last_tsc = tsc;
+        tsc
+    }
+
     /// Choose an amount of time (RCBs) for our next timeslice based on various settings.
     ///
     /// Effects:
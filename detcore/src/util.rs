@@ -8,6 +8,12 @@
 
 //! Widely useful small utilities.
 
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
 use std::time::Duration;
 
 use crate::types::NANOS_PER_RCB;
@@ -53,3 +59,27 @@ pub fn truncated(width: usize, mut s: String) -> String {
         s
     }
 }
+
+/// The first bytes of a zstd frame.  Records and logs may be compressed in place, so their
+/// readers tell by these bytes, rather than by the name of the file, whether to decompress it.
+pub const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Whether the file at `path` is compressed with zstd.
+pub fn is_zstd_file(path: &Path) -> bool {
+    let mut magic = [0; 4];
+    let read = File::open(path).and_then(|mut file| file.read_exact(&mut magic));
+    read.is_ok() && magic == ZSTD_MAGIC
+}
+
+/// Compress the file at `path` with zstd, keeping its name.  It is replaced only once the
+/// compressed copy is complete, so a reader never sees part of it.
+pub fn compress_in_place(path: &Path) -> io::Result<()> {
+    if is_zstd_file(path) {
+        return Ok(());
+    }
+    let compressed = zstd::encode_all(File::open(path)?, 0)?;
+    let mut partial = OsString::from(path);
+    partial.push(".partial");
+    fs::write(&partial, compressed)?;
+    fs::rename(&partial, path)
+}
@@ -295,3 +295,57 @@ mod futex_wait_parent {
         let _ = unsafe { libc::syscall(libc::SYS_exit_group, 0) };
     }
 }
+
+/// Timed futex waits and zero-length sleeps, with `--lean-sched-points`.
+mod lean_sched_points {
+    use std::sync::atomic::AtomicU32;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::thread;
+
+    use detcore_testutils::det_test_fn_with_config;
+    use detcore_testutils::expect_success;
+
+    fn raw() {
+        let word = Arc::new(AtomicU32::new(0));
+        let word2 = word.clone();
+        let child = thread::spawn(move || {
+            // `thread::sleep` makes no syscall for zero.
+            let zero = libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            };
+            for _ in 0..10 {
+                unsafe { libc::nanosleep(&zero, std::ptr::null_mut()) };
+            }
+            word2.store(1, Ordering::SeqCst);
+        });
+        let timeout = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 1_000_000,
+        };
+        // Nobody wakes the word, so the wait ends at its timeout, or fizzles if the child has
+        // already stored to it.
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                word.as_mut_ptr(),
+                libc::FUTEX_WAIT,
+                0,
+                &timeout as *const libc::timespec,
+                0,
+                0,
+            )
+        };
+        assert_eq!(res, -1);
+        child.join().unwrap();
+        println!("Word after the wait: {}", word.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn top_detcore() {
+        let mut cfg = detcore_testutils::TOP_CFG.clone();
+        cfg.lean_sched_points = true;
+        det_test_fn_with_config(true, raw, cfg, expect_success);
+    }
+}
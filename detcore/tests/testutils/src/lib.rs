@@ -51,25 +51,44 @@ lazy_static! {
   /// (This is the bottom element of a lattice containing exponentially many possibly Configs.)
   pub static ref BOTTOM_CFG: Config = Config {
     virtualize_cpuid: false,
+    cpu_profile: None,
     virtualize_time: false,
     virtualize_metadata: false,
+    sort_dirents: DEFAULT_CFG.sort_dirents,
+    normalize_stat: false,
     sequentialize_threads: false,
     imprecise_timers: false,
     chaos: false,
     clock_multiplier: DEFAULT_CFG.clock_multiplier,
+    rdtsc_model: DEFAULT_CFG.rdtsc_model,
+    tsc_mhz: 1000,
+    tsc_increment: 1000,
     epoch: DEFAULT_CFG.epoch,
     deterministic_io: false,
+    deterministic_mmap: false,
+    memory_limit: None,
+    timeout: None,
+    timeout_signal: DEFAULT_CFG.timeout_signal.clone(),
+    io_uring: DEFAULT_CFG.io_uring,
+    emulate_seccomp: false,
+    backend: DEFAULT_CFG.backend,
     has_uts_namespace: false,
     panic_on_unsupported_syscalls: false,
     replay_data: None,
+    record_metadata: None,
     kill_daemons: false,
     seed: DEFAULT_CFG.seed,
     sched_seed: None,
-    gdbserver: false,
+    gdbserver: None,
     gdbserver_port: 1234,
     preemption_timeout: NonZeroU64::new(5000000),
+    virtual_quantum: 500_000,
+    timer_resolution: None,
     sigint_instakill: false,
     warn_non_zero_binds: false,
+    loopback_networking: false,
+    record_network_to: None,
+    replay_network_from: None,
     recordreplay_modes: false,
     sched_heuristic: SchedHeuristic::None,
     record_preemptions: false,
@@ -77,95 +96,161 @@ lazy_static! {
     replay_preemptions_from: None,
     replay_schedule_from: None,
     replay_exhausted_panic: false,
+    replay_prefix_then_chaos: None,
     die_on_desync: false,
+    fuzzy_replay: false,
+    on_divergence: None,
     stacktrace_event: Vec::new(),
     stacktrace_signal: None,
     preemption_stacktrace: false,
     preemption_stacktrace_log_file: None,
+    crash_report_dir: None,
+    emit_trace: None,
+    audit_syscalls: false,
     stop_after_turn: None,
     stop_after_iter: None,
     debug_externalize_sockets: false,
     debug_futex_mode: DEFAULT_CFG.debug_futex_mode,
     sched_sticky_random_param: 0.0,
+    condvar_sched_points: false,
+    lean_sched_points: false,
+    spawn_order: None,
     no_rcb_time: false,
     detlog_heap: false,
     detlog_stack: false,
     sysinfo_uptime_offset: 60,
     memory: 1024 * 1024 * 1024, //1 GiB
+    hostname: "hermetic-container.local".to_string(),
+    uname_release: "5.2.0".to_string(),
+    uid: None,
+    gid: None,
     interrupt_at: vec![],
+    entropy_file: vec![],
   };
 
   /// Standardized test config: common options on.
   /// (This is drawn from the middle of the lattice of possible Configs.)
   pub static ref MIDDLE_CFG: Config = Config {
     virtualize_cpuid: true,
+    cpu_profile: None,
     virtualize_time: true,  // stat* could depends on this
     virtualize_metadata: true,
+    sort_dirents: DEFAULT_CFG.sort_dirents,
+    normalize_stat: false,
     sequentialize_threads: false,
     imprecise_timers: false,
     chaos: false,
     clock_multiplier: DEFAULT_CFG.clock_multiplier,
+    rdtsc_model: DEFAULT_CFG.rdtsc_model,
+    tsc_mhz: 1000,
+    tsc_increment: 1000,
     epoch: DEFAULT_CFG.epoch,
     deterministic_io: true,
+    deterministic_mmap: false,
+    memory_limit: None,
+    timeout: None,
+    timeout_signal: DEFAULT_CFG.timeout_signal.clone(),
+    io_uring: DEFAULT_CFG.io_uring,
+    emulate_seccomp: false,
+    backend: DEFAULT_CFG.backend,
     has_uts_namespace: false,
     panic_on_unsupported_syscalls: false,
     replay_data: None,
+    record_metadata: None,
     kill_daemons: false,
     seed: DEFAULT_CFG.seed,
     sched_seed: None,
-    gdbserver: false,
+    gdbserver: None,
     gdbserver_port: 1234,
     preemption_timeout: NonZeroU64::new(5000000),
+    virtual_quantum: 500_000,
+    timer_resolution: None,
     sigint_instakill: false,
     warn_non_zero_binds: false,
+    loopback_networking: false,
+    record_network_to: None,
+    replay_network_from: None,
     recordreplay_modes: false,
     sched_heuristic: SchedHeuristic::None,
     record_preemptions: false,
     record_preemptions_to: None,
     replay_preemptions_from: None,
     die_on_desync: false,
+    fuzzy_replay: false,
+    on_divergence: None,
     replay_schedule_from: None,
     replay_exhausted_panic: false,
+    replay_prefix_then_chaos: None,
     stacktrace_event: Vec::new(),
     stacktrace_signal: None,
     preemption_stacktrace: false,
     preemption_stacktrace_log_file: None,
+    crash_report_dir: None,
+    emit_trace: None,
+    audit_syscalls: false,
     stop_after_turn: None,
     stop_after_iter: None,
     debug_externalize_sockets: false,
     debug_futex_mode: DEFAULT_CFG.debug_futex_mode,
     sched_sticky_random_param: 0.0,
+    condvar_sched_points: false,
+    lean_sched_points: false,
+    spawn_order: None,
     no_rcb_time: false,
     detlog_heap: false,
     detlog_stack: false,
     sysinfo_uptime_offset: 60,
     memory: 1024 * 1024 * 1024, //1 GiB
+    hostname: "hermetic-container.local".to_string(),
+    uname_release: "5.2.0".to_string(),
+    uid: None,
+    gid: None,
     interrupt_at: vec![],
+    entropy_file: vec![],
   };
 
   /// Standardized test config: all options on.
   /// (This is the top element of a lattice containing exponentially many possibly Configs.)
   pub static ref TOP_CFG: Config = Config {
     virtualize_cpuid: true,
+    cpu_profile: None,
     virtualize_time: true,
     virtualize_metadata: true,
+    sort_dirents: DEFAULT_CFG.sort_dirents,
+    normalize_stat: false,
     sequentialize_threads: true,
     imprecise_timers: false,
     chaos: false,
     clock_multiplier: DEFAULT_CFG.clock_multiplier,
+    rdtsc_model: DEFAULT_CFG.rdtsc_model,
+    tsc_mhz: 1000,
+    tsc_increment: 1000,
     epoch: DEFAULT_CFG.epoch,
     deterministic_io: true,
+    deterministic_mmap: false,
+    memory_limit: None,
+    timeout: None,
+    timeout_signal: DEFAULT_CFG.timeout_signal.clone(),
+    io_uring: DEFAULT_CFG.io_uring,
+    emulate_seccomp: false,
+    backend: DEFAULT_CFG.backend,
     has_uts_namespace: false,
     panic_on_unsupported_syscalls: false,
     replay_data: None,
+    record_metadata: None,
     kill_daemons: false,
     seed: DEFAULT_CFG.seed,
     sched_seed: None,
-    gdbserver: false,
+    gdbserver: None,
     gdbserver_port: 1234,
     preemption_timeout: NonZeroU64::new(5000000),
+    virtual_quantum: 500_000,
+    timer_resolution: None,
     sigint_instakill: true,
     warn_non_zero_binds: false,
+    loopback_networking: false,
+    record_network_to: None,
+    replay_network_from: None,
     recordreplay_modes: false,
     sched_heuristic: SchedHeuristic::None,
     record_preemptions: false,
@@ -173,22 +258,36 @@ lazy_static! {
     replay_preemptions_from: None,
     replay_schedule_from: None,
     replay_exhausted_panic: false,
+    replay_prefix_then_chaos: None,
     die_on_desync: false,
+    fuzzy_replay: false,
+    on_divergence: None,
     stacktrace_event: Vec::new(),
     stacktrace_signal: None,
     preemption_stacktrace: false,
     preemption_stacktrace_log_file: None,
+    crash_report_dir: None,
+    emit_trace: None,
+    audit_syscalls: false,
     stop_after_turn: None,
     stop_after_iter: None,
     debug_externalize_sockets: false,
     debug_futex_mode: DEFAULT_CFG.debug_futex_mode,
     sched_sticky_random_param: 0.0,
+    condvar_sched_points: false,
+    lean_sched_points: false,
+    spawn_order: None,
     no_rcb_time: false,
     detlog_heap: false,
     detlog_stack: false,
     sysinfo_uptime_offset: 60,
     memory: 1024 * 1024 * 1024, //1 GiB
+    hostname: "hermetic-container.local".to_string(),
+    uname_release: "5.2.0".to_string(),
+    uid: None,
+    gid: None,
     interrupt_at: vec![],
+    entropy_file: vec![],
   };
 }
 
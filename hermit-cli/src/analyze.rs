@@ -0,0 +1,177 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Driving `hermit analyze` from other Rust programs, in the same process, and getting back what
+//! it found rather than parsing what it prints.
+//!
+//! ```no_run
+//! use hermit::Analyzer;
+//!
+//! let analysis = Analyzer::new(["--chaos", "./test"])
+//!     .args(["--search", "--minimize"])
+//!     .on_progress(|progress| eprintln!("{}", progress))
+//!     .analyze()?;
+//! println!("{}", analysis.report.stack1);
+//! # Ok::<(), hermit::Error>(())
+//! ```
+
+use std::fmt;
+use std::path::PathBuf;
+
+use clap::Parser;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::cli::analyze::AnalyzeOpts;
+use crate::cli::global_opts::GlobalOpts;
+use crate::error::Error;
+
+/// The final report that comes out of the analyze process.
+#[derive(PartialEq, Default, Debug, Eq, Clone, Hash, Serialize, Deserialize)]
+pub struct Report {
+    /// Any additional context about the error detected.
+    pub header: String,
+    /// The runtime context for one critical event, which is racing with, and does not commute with
+    /// the other.
+    pub stack1: String,
+    /// The runtime context for the other identified critical event.
+    pub stack2: String,
+    /// Crash reports (registers and backtrace) for any guest thread that received a crashing
+    /// signal in the final run, or empty.
+    #[serde(default)]
+    pub crash: String,
+    /// How the final on-target schedule differs from the final baseline schedule.
+    #[serde(default)]
+    pub schedule_diff: String,
+}
+
+/// Everything an analysis found.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Analysis {
+    /// The report of the critical events.
+    pub report: Report,
+    /// The final on-target schedule, which `hermit run --replay-schedule-from` reproduces.
+    pub target_schedule: PathBuf,
+    /// The final baseline schedule, which differs from the target at the critical events.
+    pub baseline_schedule: PathBuf,
+    /// The directory holding the logs, outputs and schedules of every run.
+    pub workspace: PathBuf,
+}
+
+/// How far an analysis has got.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Progress {
+    /// A phase of the analysis has started.
+    Phase(String),
+    /// A run has finished, and did or did not match the target criteria.
+    Run {
+        /// The name of the run, which its artifacts in the workspace are named after.
+        name: String,
+        /// Whether the run matched the target criteria.
+        matched: bool,
+    },
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Progress::Phase(phase) => write!(f, "Starting phase: {}", phase),
+            Progress::Run { name, matched } => {
+                let outcome = if *matched { "matched" } else { "did not match" };
+                write!(f, "Run {} {} the target", name, outcome)
+            }
+        }
+    }
+}
+
+/// Runs `hermit analyze` on a `hermit run`, as the command line would, reporting its progress
+/// and returning its [`Analysis`].  The analysis runs in this process, and forks the runs it
+/// launches from it.
+pub struct Analyzer {
+    args: Vec<String>,
+    run_args: Vec<String>,
+    progress: Option<Box<dyn Fn(&Progress)>>,
+}
+
+impl Analyzer {
+    /// Analyze `hermit run` with the given arguments, with the defaults of `hermit analyze`.
+    pub fn new<I, S>(run_args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Analyzer {
+            args: Vec::new(),
+            run_args: run_args.into_iter().map(Into::into).collect(),
+            progress: None,
+        }
+    }
+
+    /// Pass options to `hermit analyze`, e.g. `--search` or `--target-stdout=REGEX`.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Call `f` as the analysis progresses.
+    pub fn on_progress(mut self, f: impl Fn(&Progress) + 'static) -> Self {
+        self.progress = Some(Box::new(f));
+        self
+    }
+
+    /// Run the whole analysis. Its output other than the progress and the result, e.g. the stack
+    /// traces it prints, goes to this process's stdout and stderr.
+    pub fn analyze(self) -> Result<Analysis, Error> {
+        let args = std::iter::once("analyze".to_owned())
+            .chain(self.args)
+            .chain(std::iter::once("--".to_owned()))
+            .chain(self.run_args);
+        let mut opts = AnalyzeOpts::try_parse_from(args)?;
+        if let Some(f) = self.progress {
+            opts = opts.on_progress(f);
+        }
+        // Logging, if any, is left to the subscriber of this process.
+        let global = GlobalOpts {
+            log: None,
+            log_file: None,
+            otlp_endpoint: None,
+        };
+        opts.analyze(&global)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn analyzer_rejects_bad_args() {
+        let analysis = Analyzer::new(["true"]).args(["--no-such-flag"]).analyze();
+        assert!(analysis.is_err());
+    }
+
+    #[test]
+    fn progress_display() {
+        assert_eq!(
+            Progress::Phase("bisect".to_owned()).to_string(),
+            "Starting phase: bisect"
+        );
+        let run = Progress::Run {
+            name: "bisect_round_1".to_owned(),
+            matched: false,
+        };
+        assert_eq!(
+            run.to_string(),
+            "Run bisect_round_1 did not match the target"
+        );
+    }
+}
@@ -9,40 +9,30 @@
 // Treat all Clippy warnings as errors.
 #![deny(clippy::all)]
 
-mod analyze;
-mod bnz;
-mod clean;
-mod container;
-mod global_opts;
-mod list;
-mod logdiff;
-mod record;
-mod remove;
-mod replay;
-mod run;
-mod schedule_search;
-mod tracing;
-mod verify;
-mod version;
-
 use clap::AppSettings;
 use clap::Parser;
 use colored::*;
+use hermit::cli::analyze::AnalyzeOpts;
+use hermit::cli::bench::BenchOpts;
+use hermit::cli::bnz::BnzOpts;
+use hermit::cli::clean::CleanOpts;
+use hermit::cli::convert_schedule::ConvertScheduleOpts;
+use hermit::cli::flake_hunt::FlakeHuntOpts;
+use hermit::cli::fuzz::FuzzOpts;
+use hermit::cli::global_opts::GlobalOpts;
+use hermit::cli::list::ListOpts;
+use hermit::cli::logdiff::LogDiffCLIOpts;
+use hermit::cli::record::RecordOpts;
+use hermit::cli::remove::RemoveOpts;
+use hermit::cli::replay::ReplayOpts;
+use hermit::cli::run::RunOpts;
+use hermit::cli::sched::SchedOpts;
+use hermit::cli::sched_diff::SchedDiffOpts;
+use hermit::cli::serve::ServeOpts;
+use hermit::cli::version::Version;
 use hermit::Error;
 use hermit::ExitStatus;
 
-use self::analyze::AnalyzeOpts;
-use self::bnz::BnzOpts;
-use self::clean::CleanOpts;
-use self::global_opts::GlobalOpts;
-use self::list::ListOpts;
-use self::logdiff::LogDiffCLIOpts;
-use self::record::RecordOpts;
-use self::remove::RemoveOpts;
-use self::replay::ReplayOpts;
-use self::run::RunOpts;
-use self::version::Version;
-
 #[derive(Debug, Parser)]
 #[clap(
     name = "hermit",
@@ -89,7 +79,32 @@ enum Subcommand {
     /// Check bind syscall when port number is non-zero.
     Bnz(BnzOpts),
 
+    /// Convert a schedule or preemption record between the JSON and binary formats.
+    ConvertSchedule(ConvertScheduleOpts),
+
+    /// Inspect, validate, or summarize a schedule or preemption record.
+    Sched(SchedOpts),
+
+    /// Align two schedules and show where they diverge.
+    SchedDiff(SchedDiffOpts),
+
     Analyze(AnalyzeOpts),
+
+    /// Run a program under many chaos seeds, to find the seeds which make it fail.
+    #[clap(name = "fuzz", setting = AppSettings::TrailingVarArg)]
+    Fuzz(FuzzOpts),
+
+    /// Reproduce and analyze the failure of a flaky test, with exit codes for CI.
+    #[clap(name = "flake-hunt", setting = AppSettings::TrailingVarArg)]
+    FlakeHunt(FlakeHuntOpts),
+
+    /// Measure how much hermit slows down a program, in several configurations.
+    #[clap(name = "bench", setting = AppSettings::TrailingVarArg)]
+    Bench(BenchOpts),
+
+    /// Serve runs, verifications and analyses to other programs, over a JSON-RPC socket.
+    #[clap(name = "serve")]
+    Serve(ServeOpts),
 }
 
 impl Subcommand {
@@ -103,7 +118,14 @@ impl Subcommand {
             Subcommand::Clean(x) => x.main(global),
             Subcommand::LogDiff(x) => Ok(x.main(global)),
             Subcommand::Bnz(x) => x.main(global),
+            Subcommand::ConvertSchedule(x) => x.main(global),
+            Subcommand::Sched(x) => x.main(global),
+            Subcommand::SchedDiff(x) => x.main(global),
             Subcommand::Analyze(x) => x.main(global),
+            Subcommand::Fuzz(x) => x.main(global),
+            Subcommand::FlakeHunt(x) => x.main(global),
+            Subcommand::Bench(x) => x.main(global),
+            Subcommand::Serve(x) => x.main(global),
         }
     }
 }
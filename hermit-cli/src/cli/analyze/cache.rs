@@ -0,0 +1,256 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Reusing the runs of earlier analyses of the same program, with `--cache-dir`.
+//!
+//! The runs of a program are cached in a directory of their own, named after a hash of the
+//! program's binary and of the `hermit run` arguments it is analyzed with, so rebuilding the
+//! program or running it differently starts afresh. Each run is keyed by its options and by the
+//! contents of the schedule it replays, but not by the target criteria: the output of a cached
+//! run is checked against the criteria of the analysis reusing it.
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use rand::Rng;
+use reverie::process::ExitStatus;
+use reverie::process::Output;
+use uuid::Uuid;
+
+use crate::cli::run::RunOpts;
+use crate::Error;
+
+// The files of a cached run.
+const STATUS: &str = "status";
+const STDOUT: &str = "stdout";
+const STDERR: &str = "stderr";
+const LOG: &str = "log";
+const PREEMPTS: &str = "preempts";
+
+/// Stands in for the workspace of the analysis in the keys of runs, as each analysis has its own.
+const WORKSPACE: &str = "$WORKSPACE";
+
+/// The cached runs of one program.
+#[derive(Debug)]
+pub struct RunCache {
+    /// The directory of the program's runs.
+    dir: PathBuf,
+    /// The workspace of the current analysis.
+    workspace: PathBuf,
+}
+
+/// A hash which, unlike those of the standard library, is the same in every build: a name-based
+/// UUID.
+fn stable_hash(bytes: &[u8]) -> String {
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, bytes)
+        .to_simple()
+        .to_string()
+}
+
+/// Find the program's binary, searching `PATH` for a bare name as `execvp` does.
+fn resolve_program(program: &Path) -> Option<PathBuf> {
+    if program.components().count() > 1 {
+        return Some(program.to_path_buf());
+    }
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| candidate.is_file())
+}
+
+impl RunCache {
+    /// Open the cache of the runs of the program in `base`, analyzed with `run_args`, in
+    /// `cache_dir`.  The runs of the current analysis write their artifacts to `workspace`.
+    pub fn open(
+        cache_dir: &Path,
+        base: &RunOpts,
+        run_args: &[String],
+        workspace: &Path,
+    ) -> Result<Self, Error> {
+        let program = resolve_program(base.program())
+            .with_context(|| format!("Failed to find the program {}", base.program().display()))?;
+        let mut key = fs::read(&program)
+            .with_context(|| format!("Failed to read the program {}", program.display()))?;
+        for arg in run_args {
+            key.push(0);
+            key.extend_from_slice(arg.as_bytes());
+        }
+        let dir = cache_dir.join(stable_hash(&key));
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        Ok(RunCache {
+            dir,
+            workspace: workspace.to_path_buf(),
+        })
+    }
+
+    /// The directory of the program's runs.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The seed of the analyzer's random choices. This is the seed of the first analysis using the
+    /// cache, so that later ones make the same runs, and find them in the cache.
+    pub fn seed(&self) -> Result<u64, Error> {
+        let path = self.dir.join("seed");
+        if let Ok(seed) = fs::read_to_string(&path) {
+            return seed
+                .trim()
+                .parse()
+                .with_context(|| format!("Corrupt seed in {}", path.display()));
+        }
+        let seed: u64 = rand::thread_rng().gen();
+        fs::write(&path, seed.to_string())?;
+        Ok(seed)
+    }
+
+    /// The key of a run, or `None` if it is not cached: the final run, which writes stack traces
+    /// and crash reports, and runs which emit traces, are made again.
+    fn key(&self, runopts: &RunOpts) -> Result<Option<String>, Error> {
+        let config = &runopts.det_opts.det_config;
+        if !config.stacktrace_event.is_empty()
+            || config.crash_report_dir.is_some()
+            || config.emit_trace.is_some()
+        {
+            return Ok(None);
+        }
+        // The paths of the schedules differ from one analysis to the next, so the contents of
+        // what is replayed go into the key instead, and where the preemptions are recorded to is
+        // left out.
+        let mut ro = runopts.clone();
+        let config = &mut ro.det_opts.det_config;
+        let mut replayed = Vec::new();
+        for (name, path) in [
+            ("preemptions", config.replay_preemptions_from.take()),
+            ("schedule", config.replay_schedule_from.take()),
+        ] {
+            if let Some(path) = path {
+                let contents = fs::read(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                replayed.extend_from_slice(name.as_bytes());
+                replayed.push(0);
+                replayed.extend_from_slice(&contents);
+            }
+        }
+        if config.record_preemptions_to.is_some() {
+            config.record_preemptions_to = Some(PathBuf::from(PREEMPTS));
+        }
+        let workspace = self.workspace.to_string_lossy();
+        let mut key = format!("{:?}", ro).replace(workspace.as_ref(), WORKSPACE);
+        key.push('\0');
+        let mut key = key.into_bytes();
+        key.extend_from_slice(&replayed);
+        Ok(Some(stable_hash(&key)))
+    }
+
+    /// The output of an earlier run with the same options as `runopts`, if there was one.  Its
+    /// log, and the preemptions it recorded, are copied to where this run would have written
+    /// them.
+    pub fn lookup(&self, runopts: &RunOpts, log_path: &Path) -> Result<Option<Output>, Error> {
+        let entry = match self.key(runopts)? {
+            Some(key) => self.dir.join(key),
+            None => return Ok(None),
+        };
+        if !entry.exists() {
+            return Ok(None);
+        }
+        let status: i32 = fs::read_to_string(entry.join(STATUS))?
+            .trim()
+            .parse()
+            .with_context(|| format!("Corrupt exit status in {}", entry.display()))?;
+        fs::copy(entry.join(LOG), log_path)?;
+        if let Some(path) = &runopts.det_opts.det_config.record_preemptions_to {
+            fs::copy(entry.join(PREEMPTS), path)?;
+        }
+        Ok(Some(Output {
+            status: ExitStatus::from_raw(status),
+            stdout: fs::read(entry.join(STDOUT))?,
+            stderr: fs::read(entry.join(STDERR))?,
+        }))
+    }
+
+    /// Keep the output and artifacts of a finished run, for later analyses.
+    pub fn store(&self, runopts: &RunOpts, out: &Output, log_path: &Path) -> Result<(), Error> {
+        let entry = match self.key(runopts)? {
+            Some(key) => self.dir.join(key),
+            None => return Ok(()),
+        };
+        if entry.exists() {
+            return Ok(());
+        }
+        // The run is written aside and then moved into place, so that an analysis which is
+        // interrupted, or which stores the same run at the same time, leaves no partial entry.
+        let partial = tempfile::Builder::new()
+            .prefix(".partial")
+            .tempdir_in(&self.dir)?;
+        let dir = partial.path();
+        fs::write(dir.join(STATUS), out.status.into_raw().to_string())?;
+        fs::write(dir.join(STDOUT), &out.stdout)?;
+        fs::write(dir.join(STDERR), &out.stderr)?;
+        fs::copy(log_path, dir.join(LOG))?;
+        if let Some(path) = &runopts.det_opts.det_config.record_preemptions_to {
+            fs::copy(path, dir.join(PREEMPTS))?;
+        }
+        match fs::rename(dir, &entry) {
+            Err(_) if entry.exists() => Ok(()),
+            res => Ok(res?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_runs_replaying_the_same_schedule() {
+        let cache_dir = tempfile::TempDir::new().unwrap();
+        let workspace = tempfile::TempDir::new().unwrap();
+        let path = |name: &str| workspace.path().join(name);
+        let read = |name: &str| fs::read_to_string(path(name)).unwrap();
+        let base = RunOpts::from_iter(["hermit-run", "/bin/true"]);
+        let cache = RunCache::open(cache_dir.path(), &base, &[], workspace.path()).unwrap();
+        let replaying = |schedule: &Path, record: &Path| {
+            let mut ro = base.clone();
+            ro.det_opts.det_config.replay_preemptions_from = Some(schedule.to_path_buf());
+            ro.det_opts.det_config.record_preemptions_to = Some(record.to_path_buf());
+            ro
+        };
+
+        fs::write(path("a.preempts"), "schedule 1").unwrap();
+        fs::write(path("a.log"), "log of a").unwrap();
+        fs::write(path("a.recorded"), "recorded by a").unwrap();
+        let a = replaying(&path("a.preempts"), &path("a.recorded"));
+        assert!(cache.lookup(&a, &path("a.log")).unwrap().is_none());
+        let out = Output {
+            status: ExitStatus::Exited(3),
+            stdout: b"out".to_vec(),
+            stderr: b"err".to_vec(),
+        };
+        cache.store(&a, &out, &path("a.log")).unwrap();
+
+        // The same schedule, under another name:
+        fs::copy(path("a.preempts"), path("b.preempts")).unwrap();
+        let b = replaying(&path("b.preempts"), &path("b.recorded"));
+        let cached = cache.lookup(&b, &path("b.log")).unwrap().unwrap();
+        assert_eq!(cached.status, out.status);
+        assert_eq!(cached.stdout, out.stdout);
+        assert_eq!(read("b.log"), "log of a");
+        assert_eq!(read("b.recorded"), "recorded by a");
+
+        fs::write(path("c.preempts"), "schedule 2").unwrap();
+        let c = replaying(&path("c.preempts"), &path("c.recorded"));
+        assert!(cache.lookup(&c, &path("c.log")).unwrap().is_none());
+
+        let mut final_run = a.clone();
+        final_run.det_opts.det_config.stacktrace_event = vec![(1, None)];
+        assert!(cache.lookup(&final_run, &path("a.log")).unwrap().is_none());
+        assert_eq!(cache.seed().unwrap(), cache.seed().unwrap());
+    }
+}
@@ -27,8 +27,8 @@ use rand::Rng;
 use rand::SeedableRng;
 use rand_pcg::Pcg64Mcg;
 
-use crate::analyze::types::AnalyzeOpts;
-use crate::global_opts::GlobalOpts;
+use crate::cli::analyze::types::AnalyzeOpts;
+use crate::cli::global_opts::GlobalOpts;
 
 /// Sanity check that a series of preemptions don't include duplicates and are monotonically increasing.
 fn sanity_preempts(vec: &[(LogicalTime, Priority)]) -> anyhow::Result<()> {
@@ -75,7 +75,7 @@ impl AnalyzeOpts {
         assert!(tmp_dir.pop());
 
         let tids: Vec<DetTid> = pr.all_threads();
-        let init_schedule: PreemptionRecord = pr.load_all();
+        let init_schedule: PreemptionRecord = pr.preemptions();
         let mut round: u64 = 0;
 
         // All the preemption points we don't know about yet.  Are they critical?
@@ -180,17 +180,31 @@ impl AnalyzeOpts {
                 }
             }
 
-            let selected_ix = rng.gen_range(0..remaining_threads.len());
-            let selected_tid = *remaining_threads.get(selected_ix).unwrap();
-            let mut cut = {
+            // Each candidate knocks out a batch of the preemptions of one thread, and each is tried
+            // against the current schedule.  With more than one job, the candidates are of distinct
+            // threads and run at once, and their results are then taken in the order they were
+            // picked, as if they had run one after another: those after the first match are
+            // dropped, because they were not tried against the schedule it leaves.
+            let jobs = self.minimize_jobs.clamp(1, remaining_threads.len());
+            let mut selected_tids: Vec<DetTid> = Vec::new();
+            while selected_tids.len() < jobs {
+                let selected_ix = rng.gen_range(0..remaining_threads.len());
+                let selected_tid = *remaining_threads.get(selected_ix).unwrap();
+                if !selected_tids.contains(&selected_tid) {
+                    selected_tids.push(selected_tid);
+                }
+            }
+
+            let mut candidates = Vec::new();
+            for (i, &selected_tid) in selected_tids.iter().enumerate() {
                 let batch = batch_sizes.get_mut(&selected_tid).unwrap();
                 assert!(*batch > 0);
-                let preempts = remaining_unknown.get_mut(&selected_tid).unwrap();
+                let preempts = remaining_unknown.get(&selected_tid).unwrap();
                 assert!(!preempts.is_empty());
                 *batch = std::cmp::min(*batch, preempts.len());
 
                 let len = preempts.len();
-                let cut = preempts.split_off(len - *batch);
+                let cut = preempts[len - *batch..].to_vec();
                 eprintln!(
                     ":: Shaving {} off of {} preemptions for tid {}: {}",
                     batch,
@@ -202,18 +216,13 @@ impl AnalyzeOpts {
                         "".to_string()
                     }
                 );
-                if preempts.is_empty() {
-                    remaining_threads.swap_remove(selected_ix);
-                }
-                cut
-            };
 
-            {
-                let new_preempts_path =
-                    tmp_dir.join(format!("round_{:0wide$}.preempts", round, wide = 3));
+                let runname = format!("round_{:0wide$}", round + i as u64, wide = 3);
+                let new_preempts_path = tmp_dir.join(&runname).with_extension("preempts");
                 let pr_new = {
                     // Expensive... union back in the critical_preempts:
                     let mut btmap = remaining_unknown.clone();
+                    btmap.get_mut(&selected_tid).unwrap().truncate(len - *batch);
                     btmap
                         .iter_mut()
                         .try_for_each(|(tid, vec)| -> anyhow::Result<()> {
@@ -248,31 +257,55 @@ impl AnalyzeOpts {
                 pr_new
                     .write_to_disk(&new_preempts_path)
                     .expect("write of preempts file to succeed");
-                last_attempt = Some(pr_new);
+                last_attempt = Some(pr_new.clone());
+                eprintln!("    {}", self.to_repro_cmd(&new_preempts_path, ""));
+                candidates.push((selected_tid, cut, runname, new_preempts_path, pr_new));
+            }
+            round += candidates.len() as u64 - 1;
 
+            let matches = if let [(_, _, runname, new_preempts_path, _)] = candidates.as_slice() {
+                vec![
+                    self.launch_from_preempts_to_sched(runname, new_preempts_path, None)
+                        .unwrap(),
+                ]
+            } else {
+                let runs: Vec<(String, PathBuf)> = candidates
+                    .iter()
+                    .map(|(_, _, runname, path, _)| (runname.clone(), path.clone()))
+                    .collect();
+                self.launch_from_preempts_concurrently(&runs)?
+            };
+
+            for ((selected_tid, mut cut, runname, new_preempts_path, pr_new), is_a_match) in
+                candidates.into_iter().zip(matches)
+            {
                 let batch = batch_sizes.get_mut(&selected_tid).unwrap();
-                eprintln!("    {}", self.to_repro_cmd(&new_preempts_path, ""));
-                let runname = format!("round_{:0wide$}", round, wide = 3);
-                let log_path = tmp_dir.join(&runname).with_extension("log");
-                if self
-                    .launch_from_preempts_to_sched(&runname, &new_preempts_path, None)
-                    .unwrap()
-                {
+                let preempts = remaining_unknown.get_mut(&selected_tid).unwrap();
+                if preempts.len() == cut.len() {
+                    let selected_ix = remaining_threads
+                        .iter()
+                        .position(|tid| *tid == selected_tid)
+                        .unwrap();
+                    remaining_threads.swap_remove(selected_ix);
+                }
+                if is_a_match {
                     eprintln!(
                         ":: {}",
                         "New run matches criteria, continuing.".green().bold()
                     );
-                    last_matching_attempt = last_attempt.clone();
-                    last_matching_pr_file = Some(new_preempts_path.clone());
-                    last_matching_log = Some(log_path);
+                    preempts.truncate(preempts.len() - cut.len());
+                    last_matching_attempt = Some(pr_new);
+                    last_matching_pr_file = Some(new_preempts_path);
+                    last_matching_log = Some(tmp_dir.join(&runname).with_extension("log"));
                     *batch += 1;
-                    continue;
+                    break;
                 } else {
                     eprintln!(
                         ":: {}",
                         "New run fails criteria, backtracking..".red().bold()
                     );
                     if *batch == 1 {
+                        preempts.truncate(preempts.len() - cut.len());
                         let critical = cut.pop().unwrap();
                         assert!(cut.is_empty());
                         eprintln!(
@@ -284,19 +317,12 @@ impl AnalyzeOpts {
                             .or_insert_with(BTreeSet::new);
 
                         assert!(entry.insert(critical));
-                        continue;
                     } else {
                         *batch /= 2;
                         eprintln!(
                             ":: Batch size for minimizing tid {} reduced to {}",
                             selected_tid, batch
                         );
-                        // Hacky way to backtrack one step for now:
-                        {
-                            let preempts = remaining_unknown.get_mut(&selected_tid).unwrap();
-                            preempts.append(&mut cut);
-                        }
-                        continue;
                     }
                 }
             }
@@ -8,9 +8,10 @@
 
 //! A mode for analyzing a hermit run to detect concurrency bugs.
 
+mod cache;
 mod minimize;
 mod phases;
+mod snapshot;
 mod types;
 
 pub use types::AnalyzeOpts;
-pub use types::Report;
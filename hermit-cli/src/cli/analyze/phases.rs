@@ -10,41 +10,72 @@
 
 use std::fs;
 use std::fs::File;
+use std::io;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
-use std::str::FromStr;
 
 use anyhow::bail;
 use anyhow::Context;
 use clap::Parser;
 use colored::Colorize;
-use detcore::preemptions::read_trace;
+use detcore::preemptions::write_sched_events;
 use detcore::preemptions::PreemptionReader;
 use detcore::preemptions::PreemptionRecord;
 use detcore::types::SchedEvent;
+use detcore::util::compress_in_place;
 use detcore::util::truncated;
-use hermit::process::Bind;
-use hermit::Error;
+use detcore::SPAN_TARGET;
 use rand::Rng;
 use rand::SeedableRng;
 use rand_pcg::Pcg64Mcg;
 use reverie::process::ExitStatus;
 use reverie::process::Output;
-
-use crate::analyze::types::AnalyzeOpts;
-use crate::analyze::types::ExitStatusConstraint;
-use crate::analyze::types::Report;
-use crate::global_opts::GlobalOpts;
-use crate::logdiff::LogDiffCLIOpts;
-use crate::run::RunOpts;
-use crate::schedule_search::search_for_critical_schedule;
-use crate::schedule_search::CriticalSchedule;
-
+use tracing::info_span;
+
+use crate::cli::analyze::cache::RunCache;
+use crate::cli::analyze::snapshot::copy_tree;
+use crate::cli::analyze::snapshot::BindSnapshot;
+use crate::cli::analyze::types::AnalyzeOpts;
+use crate::cli::analyze::types::ExitStatusConstraint;
+use crate::cli::analyze::types::ProgressCallback;
+use crate::cli::bind::Bind;
+use crate::cli::global_opts::GlobalOpts;
+use crate::cli::hb_graph;
+use crate::cli::hb_graph::HbGraph;
+use crate::cli::logdiff::LogDiffCLIOpts;
+use crate::cli::run::RunOpts;
+use crate::cli::sched_diff::SchedDiff;
+use crate::cli::schedule_search::search_for_critical_schedule;
+use crate::cli::schedule_search::CriticalSchedule;
+use crate::cli::test_output::TestFramework;
+use crate::cli::zygote::Zygote;
+use crate::Analysis;
+use crate::Error;
+use crate::Progress;
+use crate::Report;
+
+/// Whether two records are the same, comparing their events one at a time.
 fn preempt_files_equal(path1: &Path, path2: &Path) -> bool {
-    let pr1 = PreemptionReader::new(path1).load_all();
-    let pr2 = PreemptionReader::new(path2).load_all();
-    pr1 == pr2
+    let pr1 = PreemptionReader::new(path1);
+    let pr2 = PreemptionReader::new(path2);
+    pr1.preemptions() == pr2.preemptions()
+        && pr1.num_events() == pr2.num_events()
+        && pr1.iter_events().eq(pr2.iter_events())
+}
+
+/// Concatenate the crash reports written to a `--crash-report-dir`, in order of name.
+fn read_crash_reports(dir: &Path) -> String {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+        Err(_) => return String::new(),
+    };
+    paths.sort();
+    paths
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
 /// Right now we don't want turning on logging for `hermit analyze` itself to ALSO turn on logging
@@ -53,6 +84,7 @@ fn preempt_files_equal(path1: &Path, path2: &Path) -> bool {
 const NO_LOGGING_PLZ: GlobalOpts = GlobalOpts {
     log: None,
     log_file: None,
+    otlp_endpoint: None,
 };
 
 // We identify a run by a root file name, and then append a standard set of suffixes to store the
@@ -61,11 +93,67 @@ const LOG_EXT: &str = "log";
 const PREEMPTS_EXT: &str = "preempts";
 const SCHED_EXT: &str = "events";
 
+/// Where the workspace is made, with `--workspace-in-memory`.
+const IN_MEMORY_DIR: &str = "/dev/shm";
+
+/// The prefixes of the names of the files in the workspace which are kept when an in-memory
+/// workspace is freed.
+const FINAL_ARTIFACTS: [&str; 2] = ["final", "first_matching"];
+
+/// The prefixes of the names of the intermediate runs, whose artifacts may be deleted to keep
+/// under `--max-workspace-size` if they did not match.
+const INTERMEDIATE_RUNS: [&str; 3] = ["search_round_", "round_", "bisect_round_"];
+
+/// The total size of the files under `path`.
+fn tree_size(path: &Path) -> io::Result<u64> {
+    let meta = path.symlink_metadata()?;
+    if !meta.is_dir() {
+        return Ok(meta.len());
+    }
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        size += tree_size(&entry?.path())?;
+    }
+    Ok(size)
+}
+
 /// Return true the launched run matches the target criteria.
 /// Also return the path to the log file that was written.
 type LaunchResult = Result<(bool, PathBuf), Error>;
 
+/// Make a run with `run`, which logs to the given file, or reuse the output of an earlier run with
+/// the same options from the cache.
+fn run_or_reuse(
+    runopts: &RunOpts,
+    log_path: &Path,
+    cache: Option<&RunCache>,
+    run: impl FnOnce(File) -> Result<Output, Error>,
+) -> Result<Output, Error> {
+    if let Some(cache) = cache {
+        if let Some(out) = cache.lookup(runopts, log_path)? {
+            return Ok(out);
+        }
+    }
+    let out = run(File::create(log_path)?)?;
+    if let Some(cache) = cache {
+        cache.store(runopts, &out, log_path)?;
+    }
+    Ok(out)
+}
+
 impl AnalyzeOpts {
+    fn report_progress(&self, progress: Progress) {
+        if let Some(callback) = &self.progress {
+            (callback.0)(&progress);
+        }
+    }
+
+    /// Run one phase of the analysis, in a span of its own.
+    fn phase<T>(&mut self, name: &'static str, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.report_progress(Progress::Phase(name.to_owned()));
+        info_span!(target: SPAN_TARGET, "phase", name).in_scope(|| f(self))
+    }
+
     fn log_path(&self, runname: &str) -> PathBuf {
         let tmp_dir = self.tmp_dir.as_ref().unwrap();
         tmp_dir.join(runname).with_extension(LOG_EXT)
@@ -76,7 +164,7 @@ impl AnalyzeOpts {
         tmp_dir.join(runname).with_extension(PREEMPTS_EXT)
     }
 
-    fn print_and_validate_runopts(&self, ro: &mut RunOpts, log_path: &Path) {
+    fn print_and_validate_runopts(&self, ro: &mut RunOpts, log_path: &Path) -> Result<(), Error> {
         if self.verbose {
             ro.summary = true;
             eprintln!(
@@ -89,31 +177,147 @@ impl AnalyzeOpts {
                 self.runopts_to_repro(ro, None)
             );
         }
-        ro.validate_args();
+        ro.validate_args()
     }
 
     /// Launch a single run with the given options.
     /// (Also set up logging and temp dir binding.)
     fn launch_config(&self, runname: &str, runopts: &mut RunOpts) -> LaunchResult {
+        let _span = info_span!(target: SPAN_TARGET, "run", name = runname).entered();
+        let log_path = self.log_path(runname);
+        self.print_and_validate_runopts(runopts, &log_path)?;
+
+        if let Some(snapshot) = &self.bind_snapshot {
+            snapshot
+                .restore()
+                .context("Failed to restore the bound paths before a run")?;
+        }
+
+        let runopts = &*runopts;
+        let cache = self.run_cache.as_ref();
+        let out1 = run_or_reuse(runopts, &log_path, cache, |log_file| {
+            match &self.zygote_server {
+                Some(zygote) => zygote.run(runopts, log_file),
+                None => runopts.run_verify(log_file, &NO_LOGGING_PLZ),
+            }
+        })?;
+        let is_a_match = self.record_output(runname, &out1);
+        self.tidy_workspace(runname, is_a_match)?;
+        Ok((is_a_match, log_path))
+    }
+
+    /// Launch several runs at once, each in a container of its own.  Return whether each one
+    /// matches the criteria, in the order given.  The bound paths are restored once for all of
+    /// them, which `snapshot_binds` makes safe by refusing writable ones.
+    fn launch_configs_concurrently(
+        &self,
+        runs: &mut [(String, RunOpts)],
+    ) -> Result<Vec<bool>, Error> {
+        if let Some(snapshot) = &self.bind_snapshot {
+            snapshot
+                .restore()
+                .context("Failed to restore the bound paths before a run")?;
+        }
+        let mut log_paths = Vec::new();
+        for (runname, runopts) in runs.iter_mut() {
+            let log_path = self.log_path(runname);
+            self.print_and_validate_runopts(runopts, &log_path)?;
+            log_paths.push(log_path);
+        }
+
+        let cache = self.run_cache.as_ref();
+        let outputs: Vec<Result<Output, Error>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = runs
+                .iter()
+                .zip(&log_paths)
+                .map(|((runname, runopts), log_path)| {
+                    // Made here, as the threads do not export spans of their own.
+                    let span = info_span!(target: SPAN_TARGET, "run", name = runname.as_str());
+                    scope.spawn(move || {
+                        span.in_scope(|| {
+                            run_or_reuse(runopts, log_path, cache, |log_file| {
+                                runopts.run_verify(log_file, &NO_LOGGING_PLZ)
+                            })
+                        })
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("analyze run panicked"))
+                .collect()
+        });
+        runs.iter()
+            .zip(outputs)
+            .map(|((runname, _), out)| {
+                let is_a_match = self.record_output(runname, &out?);
+                self.tidy_workspace(runname, is_a_match)?;
+                Ok(is_a_match)
+            })
+            .collect()
+    }
+
+    /// Compress the artifacts of a finished run, with `--compress-workspace`, and then delete
+    /// those of the oldest runs which did not match, until the workspace is back under
+    /// `--max-workspace-size`.
+    fn tidy_workspace(&self, runname: &str, is_a_match: bool) -> Result<(), Error> {
         let tmp_dir = self.tmp_dir.as_ref().unwrap();
         let root = tmp_dir.join(runname);
-        let log_path = self.log_path(runname);
-        self.print_and_validate_runopts(runopts, &log_path);
+        if self.compress_workspace {
+            for ext in [LOG_EXT, PREEMPTS_EXT, SCHED_EXT] {
+                let path = root.with_extension(ext);
+                if path.exists() {
+                    compress_in_place(&path)
+                        .with_context(|| format!("Failed to compress {}", path.display()))?;
+                }
+            }
+        }
 
-        let log_file = File::create(&log_path)?;
-        let out1: Output = runopts.run_verify(log_file, &NO_LOGGING_PLZ)?;
+        let max_size = match self.max_workspace_size {
+            Some(size) => size,
+            None => return Ok(()),
+        };
+        let mut evictable = self.evictable_runs.borrow_mut();
+        if !is_a_match && INTERMEDIATE_RUNS.iter().any(|p| runname.starts_with(p)) {
+            evictable.push_back(runname.to_owned());
+        }
+        while tree_size(tmp_dir)? > max_size {
+            let oldest = match evictable.pop_front() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            let prefix = format!("{}.", oldest);
+            for entry in fs::read_dir(tmp_dir)? {
+                let entry = entry?;
+                if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                    fs::remove_file(entry.path())?;
+                }
+            }
+            if self.verbose {
+                eprintln!(":: [verbose] Deleted the artifacts of {}", oldest);
+            }
+        }
+        Ok(())
+    }
 
+    /// Save the output of a finished run next to its log.  Return true if it matches the criteria.
+    fn record_output(&self, runname: &str, out: &Output) -> bool {
+        let root = self.tmp_dir.as_ref().unwrap().join(runname);
         File::create(root.with_extension("stdout"))
             .unwrap()
-            .write_all(&out1.stdout)
+            .write_all(&out.stdout)
             .unwrap();
         File::create(root.with_extension("stderr"))
             .unwrap()
-            .write_all(&out1.stderr)
+            .write_all(&out.stderr)
             .unwrap();
 
-        let is_a_match = self.output_matches(&out1);
-        Ok((is_a_match, log_path))
+        let is_a_match = self.output_matches(out);
+        self.report_progress(Progress::Run {
+            name: runname.to_owned(),
+            matched: is_a_match,
+        });
+        is_a_match
     }
 
     /// Launch a chaos run searching for a failing schudule.
@@ -136,6 +340,12 @@ impl AnalyzeOpts {
         if self.imprecise_search {
             ro.det_opts.det_config.imprecise_timers = true; // TODO: enable this by default when bugs are fixed.
         }
+        if let Some(prefix) = &self.search_prefix {
+            // The base runopts are already validated, so also set what validation would derive.
+            ro.det_opts.det_config.replay_prefix_then_chaos = Some(prefix.clone());
+            ro.det_opts.det_config.replay_schedule_from = Some(prefix.path.clone());
+            ro.det_opts.det_config.chaos = true;
+        }
 
         let (is_a_match, _) = self.launch_config(&runname, &mut ro)?;
         if is_a_match {
@@ -182,18 +392,37 @@ impl AnalyzeOpts {
         Ok(is_a_match)
     }
 
+    /// Launch a run replaying each of the given preempts at once.  Return whether each one matches
+    /// the criteria, in the order given.
+    pub(super) fn launch_from_preempts_concurrently(
+        &self,
+        runs: &[(String, PathBuf)],
+    ) -> Result<Vec<bool>, Error> {
+        let mut runs = runs
+            .iter()
+            .map(|(runname, preempts_path)| {
+                let mut ro = self.get_base_runopts()?;
+                ro.det_opts.det_config.replay_preemptions_from = Some(preempts_path.clone());
+                Ok((runname.clone(), ro))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        self.launch_configs_concurrently(&mut runs)
+    }
+
     /// Runs the program with the specified schedule.
     /// Returns whether the final run met the criteria as expected.
-    /// Also returns the paths to stack traces of the two critical events.
+    /// Also returns the paths to stack traces of the two critical events, and to the directory
+    /// of crash reports.
     fn launch_for_stacktraces(
         &self,
         runname: &str,
         schedule_path: &Path,
         critical_event_index: u64,
-    ) -> Result<(bool, PathBuf, PathBuf, RunOpts), Error> {
+    ) -> Result<(bool, PathBuf, PathBuf, PathBuf, RunOpts), Error> {
         let tmp_dir = self.tmp_dir.as_ref().context("tmp_dir set")?;
         let stack1_path = tmp_dir.join(runname).with_extension("stack1");
         let stack2_path = tmp_dir.join(runname).with_extension("stack2");
+        let crash_dir = tmp_dir.join(runname).with_extension("crashes");
 
         let mut ro = self.get_base_runopts()?;
         ro.det_opts.det_config.replay_schedule_from = Some(schedule_path.to_path_buf());
@@ -202,9 +431,10 @@ impl AnalyzeOpts {
             (critical_event_index, Some(stack2_path.clone())),
         ]
         .to_vec();
+        ro.det_opts.det_config.crash_report_dir = Some(crash_dir.clone());
 
         let (is_a_match, _log_path) = self.launch_config(runname, &mut ro)?;
-        Ok((is_a_match, stack1_path, stack2_path, ro))
+        Ok((is_a_match, stack1_path, stack2_path, crash_dir, ro))
     }
 
     fn runopts_to_repro(&self, runopts: &RunOpts, runname: Option<&str>) -> String {
@@ -222,10 +452,8 @@ impl AnalyzeOpts {
 
     fn runopts_add_binds(&self, runopts: &mut RunOpts) -> anyhow::Result<()> {
         let tmp_dir = self.tmp_dir.as_ref().unwrap();
-        let bind_dir: Bind = Bind::from_str(tmp_dir.to_str().unwrap())?;
-        runopts.bind.push(bind_dir);
-        runopts.validate_args();
-        Ok(())
+        runopts.bind.push(Bind::same(tmp_dir));
+        runopts.validate_args()
     }
 
     // TODO: replace this with a more general way to convert RunOpts back to CLI args.
@@ -242,6 +470,13 @@ impl AnalyzeOpts {
 
     fn to_repro_chaos(&self, seed: u64) -> String {
         let mut str = format!("hermit --log-file=/dev/stderr run --seed={} ", seed);
+        if let Some(prefix) = &self.search_prefix {
+            let prefix = prefix.to_string();
+            str.push_str(&format!(
+                "--replay-prefix-then-chaos={} ",
+                shell_words::quote(&prefix)
+            ));
+        }
         str.push_str(&self.run_args.join(" "));
         str
     }
@@ -266,7 +501,7 @@ impl AnalyzeOpts {
             )
         }
 
-        ro.validate_args();
+        ro.validate_args()?;
         assert!(ro.det_opts.det_config.sequentialize_threads);
         if self.run1_seed.is_some() && !ro.det_opts.det_config.chaos {
             eprintln!(
@@ -328,14 +563,37 @@ impl AnalyzeOpts {
     ///
     /// Returns the logs and preemption (path) extracted from the initial target run.
     fn phase1_establish_target_run(&mut self) -> Result<(PathBuf, PathBuf), Error> {
-        let dir = tempfile::Builder::new()
-            .prefix("hermit_analyze")
-            .tempdir()?;
+        let mut builder = tempfile::Builder::new();
+        builder.prefix("hermit_analyze");
+        let dir = if self.workspace_in_memory {
+            builder
+                .tempdir_in(IN_MEMORY_DIR)
+                .with_context(|| format!("Failed to create a workspace in {}", IN_MEMORY_DIR))?
+        } else {
+            builder.tempdir()?
+        };
         let tmpdir_path = dir.into_path(); // For now always keep the temporary results.
         eprintln!(":: Temp workspace: {}", tmpdir_path.display());
         self.tmp_dir = Some(tmpdir_path);
 
         // Must run after tmp_dir is set:
+        self.snapshot_binds()?;
+        if let Some(cache_dir) = &self.cache_dir {
+            let base = self.get_base_runopts()?;
+            let workspace = self.tmp_dir.as_ref().unwrap();
+            let cache = RunCache::open(cache_dir, &base, &self.run_args, workspace)
+                .context("Failed to open the run cache")?;
+            eprintln!(":: Run cache: {}", cache.dir().display());
+            if self.analyze_seed.is_none() {
+                self.analyze_seed = Some(cache.seed()?);
+            }
+            self.run_cache = Some(cache);
+        }
+        if !self.no_zygote {
+            let zygote = Zygote::start(&self.get_base_runopts()?, &NO_LOGGING_PLZ)
+                .context("Failed to start the zygote container (--no-zygote does without it)")?;
+            self.zygote_server = Some(zygote);
+        }
         let run1_opts = self.get_run1_runopts()?;
         eprintln!(
             ":: {} hermit run {}",
@@ -374,7 +632,7 @@ impl AnalyzeOpts {
                         .red()
                         .bold()
                 );
-                self.do_search(&preempts_path);
+                self.do_search(&preempts_path)?;
             } else {
                 bail!("FAILED. The run did not match the target criteria. Try --search.");
             }
@@ -396,6 +654,36 @@ impl AnalyzeOpts {
         Ok((run1_log_path, preempts_path))
     }
 
+    /// Save the paths bound into the guest's container (other than our workspace), so that each
+    /// run can start from the same state, even if earlier runs wrote to them.
+    ///
+    /// Runs made at once with `--minimize-jobs` would write to such paths at the same time, so
+    /// they may only be bound read-only then.
+    fn snapshot_binds(&mut self) -> Result<(), Error> {
+        let tmp_dir = self.tmp_dir.as_ref().unwrap();
+        let binds: Vec<Bind> = self
+            .get_base_runopts()?
+            .bind
+            .into_iter()
+            .filter(|bind| bind.source.is_some() && bind.source.as_ref() != Some(tmp_dir))
+            .collect();
+        if self.minimize_jobs > 1 {
+            if let Some(bind) = binds.iter().find(|bind| !bind.read_only) {
+                bail!(
+                    "--minimize-jobs={} would make runs which share the writable bound path {}. \
+                     Bind it read-only, or use --minimize-jobs=1.",
+                    self.minimize_jobs,
+                    bind.source.as_ref().unwrap().display()
+                );
+            }
+        }
+        let paths: Vec<PathBuf> = binds.into_iter().filter_map(|bind| bind.source).collect();
+        let snapshot = BindSnapshot::take(&paths, &tmp_dir.join("bind_snapshot"))
+            .context("Failed to snapshot the bound paths")?;
+        self.bind_snapshot = Some(snapshot);
+        Ok(())
+    }
+
     /// Reduce the set of preemptions needed to match the criteria.
     ///
     /// Takes the input (non-minimized) preemptions as a file path and returns the minimized
@@ -425,7 +713,7 @@ impl AnalyzeOpts {
         } else {
             // In this scenario we only care about event traces, and never realyl need to work with
             // preemptions.  Still, we'll need to do another run to record the trace.
-            let loaded = PreemptionReader::new(preempts_path).load_all();
+            let loaded = PreemptionReader::new(preempts_path).preemptions();
             Ok((loaded, preempts_path.to_path_buf(), None))
         }
     }
@@ -436,18 +724,14 @@ impl AnalyzeOpts {
         run1_log_path: &Path,
         run2_log_path: &Path,
     ) -> ExitStatus {
+        let ldopts = self.log_diff_opts(run1_log_path, run2_log_path);
         if self.verbose {
             eprintln!(
                 ":: {}",
                 "[comparing] with log-diff command:".yellow().bold()
             );
-            eprintln!(
-                "    hermit log-diff {} {}",
-                run1_log_path.display(),
-                run2_log_path.display(),
-            );
+            eprintln!("    hermit log-diff {}", ldopts);
         }
-        let ldopts = LogDiffCLIOpts::new(run1_log_path, run2_log_path);
         ldopts.main(global)
     }
 
@@ -458,22 +742,25 @@ impl AnalyzeOpts {
         run1_log_path: &Path,
         run2_log_path: &Path,
     ) -> ExitStatus {
+        let mut ldopts = self.log_diff_opts(run1_log_path, run2_log_path);
+        ldopts.more.ignore_lines = vec!["CHAOSRAND".to_string()];
         if self.verbose {
             eprintln!(
                 ":: {}",
                 "[comparing] with log-diff command:".yellow().bold()
             );
-            eprintln!(
-                "    hermit log-diff --ignore-lines=CHAOSRAND {} {}",
-                run1_log_path.display(),
-                run2_log_path.display(),
-            );
+            eprintln!("    hermit log-diff {}", ldopts);
         }
-        let mut ldopts = LogDiffCLIOpts::new(run1_log_path, run2_log_path);
-        ldopts.more.ignore_lines = vec!["CHAOSRAND".to_string()];
         ldopts.main(global)
     }
 
+    /// Options to compare two logs, with the `--log-diff-rules`.
+    fn log_diff_opts(&self, run1_log_path: &Path, run2_log_path: &Path) -> LogDiffCLIOpts {
+        let mut ldopts = LogDiffCLIOpts::new(run1_log_path, run2_log_path);
+        ldopts.more.rules = self.log_diff_rules.clone();
+        ldopts
+    }
+
     /// Optionally do an extra run to verify that preemptions replay and yield the exact same
     /// execution.a
     pub fn phase3_strict_preempt_replay_check(
@@ -563,7 +850,7 @@ impl AnalyzeOpts {
             .unwrap();
             eprintln!(":: Recorded preemptions from --run2-seed as baseline run.");
         } else if let Some(path) = &self.run2_preemptions {
-            let pr = PreemptionReader::new(path).load_all();
+            let pr = PreemptionReader::new(path).preemptions();
             self.save_final_baseline_sched_events(&pr, path, global);
         } else if self.minimize {
             // If we're minimizing, then we know that ALL interventions in the schedule are critical.
@@ -587,14 +874,39 @@ impl AnalyzeOpts {
     }
 
     /// Perform the binary search through schedule-space, identifying critical events.
+    ///
+    /// Every round runs the guest from its start, even though consecutive candidate schedules
+    /// share long prefixes: there is no snapshot of a running guest to resume a round from.
+    ///
+    /// Only the events where the two schedules differ are held in memory while searching.  The
+    /// common prefix and suffix are read back from `target` as each round's schedule is written.
     pub fn phase5_bisect_traces(
         &mut self,
-        target: Vec<SchedEvent>,
-        baseline: Vec<SchedEvent>,
+        target: PreemptionReader,
+        baseline: PreemptionReader,
     ) -> anyhow::Result<CriticalSchedule> {
         let tmp_dir = self.tmp_dir.as_ref().context("tmp_dir set")?;
         let mut i = 0;
 
+        let (target_len, baseline_len) = (target.num_events(), baseline.num_events());
+        let shorter = target_len.min(baseline_len);
+        let prefix = (0..shorter)
+            .take_while(|&ix| target.event(ix) == baseline.event(ix))
+            .count();
+        let suffix = (1..=shorter - prefix)
+            .take_while(|&n| target.event(target_len - n) == baseline.event(baseline_len - n))
+            .count();
+        let target_middle = target.slice(prefix..target_len - suffix);
+        let baseline_middle = baseline.slice(prefix..baseline_len - suffix);
+        let common_suffix = target_len - suffix..target_len;
+        let write_with_common = |path: &Path, middle: &[SchedEvent]| {
+            let events = (0..prefix)
+                .map(|ix| target.event(ix))
+                .chain(middle.iter().cloned())
+                .chain(common_suffix.clone().map(|ix| target.event(ix)));
+            write_sched_events(path, events)
+        };
+
         let base_opts = self.get_base_runopts()?;
         let test_fn = |sched: &[SchedEvent]| {
             i += 1;
@@ -602,8 +914,7 @@ impl AnalyzeOpts {
 
             // Prepare the next synthetic schedule on disk:
             let sched_path = tmp_dir.join(format!("{}.events", &runname));
-            let next_sched = PreemptionRecord::from_sched_events(sched.to_owned());
-            next_sched.write_to_disk(&sched_path).unwrap();
+            write_with_common(&sched_path, sched).unwrap();
 
             let mut runopts = base_opts.clone();
             runopts.det_opts.det_config.replay_schedule_from = Some(sched_path);
@@ -627,7 +938,18 @@ impl AnalyzeOpts {
             (!is_match, sched.to_owned())
         };
 
-        let crit = search_for_critical_schedule(test_fn, baseline, target);
+        let crit = search_for_critical_schedule(test_fn, baseline_middle, target_middle);
+        let with_common = |middle: Vec<SchedEvent>| {
+            let mut events = target.slice(0..prefix);
+            events.extend(middle);
+            events.extend(target.slice(common_suffix.clone()));
+            events
+        };
+        let crit = CriticalSchedule {
+            failing_schedule: with_common(crit.failing_schedule),
+            passing_schedule: with_common(crit.passing_schedule),
+            critical_event_index: prefix + crit.critical_event_index,
+        };
         eprintln!(
             "Critical event of final on-target schedule is {}",
             crit.critical_event_index
@@ -636,16 +958,28 @@ impl AnalyzeOpts {
     }
 
     /// Record the schedules on disk as reproducers and report stack-traces of critical events.
-    pub fn phase6_record_outputs(&mut self, crit: CriticalSchedule) -> Result<Report, Error> {
+    pub fn phase6_record_outputs(&mut self, crit: CriticalSchedule) -> Result<Analysis, Error> {
         let tmp_dir = self.tmp_dir.as_ref().unwrap();
         let CriticalSchedule {
             failing_schedule,
             passing_schedule,
             critical_event_index,
         } = crit;
+        let schedule_diff = SchedDiff::new(&passing_schedule, &failing_schedule).to_string();
+        if let Some(path) = &self.hb_graph {
+            let critical = (critical_event_index - 1, critical_event_index);
+            let graph = HbGraph::new(&failing_schedule, critical, hb_graph::WINDOW);
+            graph.write_to(path)?;
+            eprintln!(
+                "Wrote the happens-before graph around the critical events to {}\n{}",
+                path.display(),
+                graph.summary()
+            );
+        }
 
         let runname = "final_target_for_stacktraces";
         let final_failing_path = tmp_dir.join(runname).with_extension(SCHED_EXT);
+        let final_passing_path = tmp_dir.join("final_baseline").with_extension(SCHED_EXT);
         {
             let pr = PreemptionRecord::from_sched_events(failing_schedule);
             pr.write_to_disk(&final_failing_path).unwrap();
@@ -654,7 +988,6 @@ impl AnalyzeOpts {
                 self.display_criteria(),
                 final_failing_path.display()
             );
-            let final_passing_path = tmp_dir.join("final_baseline").with_extension(SCHED_EXT);
             let pr = PreemptionRecord::from_sched_events(passing_schedule);
             pr.write_to_disk(&final_passing_path).unwrap();
             eprintln!(
@@ -683,7 +1016,7 @@ impl AnalyzeOpts {
                     .green()
                     .bold()
             );
-            let (res, stack1_path, stack2_path, runopts) = self.launch_for_stacktraces(
+            let (res, stack1_path, stack2_path, crash_dir, runopts) = self.launch_for_stacktraces(
                 runname,
                 &final_failing_path,
                 critical_event_index as u64,
@@ -692,6 +1025,7 @@ impl AnalyzeOpts {
 
             let stack1 = fs::read_to_string(stack1_path).unwrap();
             let stack2 = fs::read_to_string(stack2_path).unwrap();
+            let crash = read_crash_reports(&crash_dir);
 
             if res {
                 // Also print to the screen:
@@ -701,11 +1035,25 @@ impl AnalyzeOpts {
                 println!("{}", header);
                 println!("{}", stack1);
                 println!("{}", stack2);
+                println!(
+                    "The on-target schedule differs from the baseline schedule by:\n{}",
+                    schedule_diff
+                );
+                if !crash.is_empty() {
+                    println!("The program crashed:\n{}", crash);
+                }
                 eprintln!(":: {}", "Completed analysis successfully.".green().bold());
-                Ok(Report {
-                    header,
-                    stack1,
-                    stack2,
+                Ok(Analysis {
+                    report: Report {
+                        header,
+                        stack1,
+                        stack2,
+                        crash,
+                        schedule_diff,
+                    },
+                    target_schedule: final_failing_path,
+                    baseline_schedule: final_passing_path,
+                    workspace: tmp_dir.clone(),
                 })
             } else {
                 bail!("Internal error! Final run did NOT match the criteria as expected!")
@@ -714,6 +1062,38 @@ impl AnalyzeOpts {
     }
 
     pub fn main(&mut self, global: &GlobalOpts) -> Result<ExitStatus, Error> {
+        let _spans = global.init_span_export();
+        if self.verbose && self.progress.is_none() {
+            self.progress = Some(ProgressCallback(Box::new(|progress: &Progress| {
+                eprintln!(":: [verbose] {}", progress)
+            })));
+        }
+        let analysis = self.analyze(global)?;
+        eprintln!(
+            ":: {}\n    hermit run --replay-schedule-from={} ...\n    (baseline: {}, all runs: {})",
+            "Reproduce the target run with:".green().bold(),
+            analysis.target_schedule.display(),
+            analysis.baseline_schedule.display(),
+            analysis.workspace.display()
+        );
+        if let Some(path) = &self.report_file {
+            let txt = serde_json::to_string(&analysis.report).unwrap();
+            std::fs::write(path, txt).expect("Unable to write report file");
+            eprintln!(
+                ":: {}\n {}",
+                "Final analysis report written to:".green().bold(),
+                path.display()
+            );
+        }
+        self.success_exit_code
+            .map_or(Ok(ExitStatus::SUCCESS), |exit_code| {
+                Ok(ExitStatus::Exited(exit_code))
+            })
+    }
+
+    /// Run the whole analysis, returning what it found rather than printing it, so that it can
+    /// be driven by other code.  Progress is reported to the callback set by `on_progress`.
+    pub fn analyze(&mut self, global: &GlobalOpts) -> Result<Analysis, Error> {
         // Not implemented yet:
         if self.run1_schedule.is_some() {
             todo!()
@@ -721,13 +1101,21 @@ impl AnalyzeOpts {
         if self.run2_schedule.is_some() {
             todo!()
         }
+        if let Some(framework) = self.criteria_from {
+            let test = self.criteria_test.as_deref();
+            self.target_stdout = Some(TestFramework::failure_regex(&[framework], test));
+        }
 
-        let (run1_log_path, preempts_path) = self.phase1_establish_target_run()?;
+        let _span = info_span!(target: SPAN_TARGET, "analyze").entered();
+        let (run1_log_path, preempts_path) =
+            self.phase("establish target run", |a| a.phase1_establish_target_run())?;
 
         let (min_preempts, min_preempts_path, maybe_min_log) =
-            self.phase2_minimize(global, &preempts_path)?;
+            self.phase("minimize", |a| a.phase2_minimize(global, &preempts_path))?;
         let min_log_path = maybe_min_log.unwrap_or(run1_log_path);
-        self.phase3_strict_preempt_replay_check(global, &min_log_path, &min_preempts_path)?;
+        self.phase("selfcheck", |a| {
+            a.phase3_strict_preempt_replay_check(global, &min_log_path, &min_preempts_path)
+        })?;
 
         let mut normalized_preempts = min_preempts.normalize();
         normalized_preempts.preemptions_only();
@@ -755,30 +1143,55 @@ impl AnalyzeOpts {
 
         // The other endpoint of the bisection search:
         // What we thought was the final_pr can change here:
-        let (final_pr, non_matching_sched_events_path) =
-            self.phase4_choose_baseline_sched_events(global, normalized_preempts)?;
+        let (final_pr, non_matching_sched_events_path) = self.phase("choose baseline", |a| {
+            a.phase4_choose_baseline_sched_events(global, normalized_preempts)
+        })?;
 
         self.save_final_baseline_sched_events(&final_pr, &target_sched_events_path, global);
 
-        let target = read_trace(&target_sched_events_path);
-        let baseline = read_trace(&non_matching_sched_events_path);
+        let target = PreemptionReader::new(&target_sched_events_path);
+        let baseline = PreemptionReader::new(&non_matching_sched_events_path);
 
-        let crit_sched = self.phase5_bisect_traces(target, baseline)?;
+        let crit_sched = self.phase("bisect", |a| a.phase5_bisect_traces(target, baseline))?;
 
-        let report = self.phase6_record_outputs(crit_sched)?;
-        if let Some(path) = &self.report_file {
-            let txt = serde_json::to_string(&report).unwrap();
-            std::fs::write(path, txt).expect("Unable to write report file");
-            eprintln!(
-                ":: {}\n {}",
-                "Final analysis report written to:".green().bold(),
-                path.display()
-            );
+        let analysis = self.phase("record outputs", |a| a.phase6_record_outputs(crit_sched))?;
+        if self.workspace_in_memory {
+            self.persist_final_artifacts(analysis)
+        } else {
+            Ok(analysis)
         }
-        self.success_exit_code
-            .map_or(Ok(ExitStatus::SUCCESS), |exit_code| {
-                Ok(ExitStatus::Exited(exit_code))
-            })
+    }
+
+    /// Copy the final artifacts out of the in-memory workspace into a new workspace on disk, and
+    /// free the in-memory one.
+    fn persist_final_artifacts(&mut self, analysis: Analysis) -> Result<Analysis, Error> {
+        // The zygote's container has the workspace bound into it.
+        self.zygote_server = None;
+        let memory = self.tmp_dir.take().unwrap();
+        let disk = tempfile::Builder::new()
+            .prefix("hermit_analyze")
+            .tempdir()?
+            .into_path();
+        for entry in fs::read_dir(&memory)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let is_final = |prefix: &&str| name.to_string_lossy().starts_with(prefix);
+            if FINAL_ARTIFACTS.iter().any(is_final) {
+                copy_tree(&entry.path(), &disk.join(&name))?;
+            }
+        }
+        fs::remove_dir_all(&memory)?;
+        eprintln!(":: Final artifacts copied to workspace: {}", disk.display());
+
+        let rebase = |path: &Path| disk.join(path.strip_prefix(&memory).unwrap());
+        let analysis = Analysis {
+            report: analysis.report,
+            target_schedule: rebase(&analysis.target_schedule),
+            baseline_schedule: rebase(&analysis.baseline_schedule),
+            workspace: disk.clone(),
+        };
+        self.tmp_dir = Some(disk);
+        Ok(analysis)
     }
 
     fn save_final_baseline_sched_events(
@@ -931,7 +1344,7 @@ impl AnalyzeOpts {
     }
 
     /// Search for a failing run. Destination passing style: takes the path that it writes its output to.
-    fn do_search(&self, preempts_path: &Path) {
+    fn do_search(&self, preempts_path: &Path) -> Result<(), Error> {
         let search_seed = self.analyze_seed.unwrap_or_else(|| {
             let mut rng0 = rand::thread_rng();
             let seed: u64 = rng0.gen();
@@ -947,12 +1360,20 @@ impl AnalyzeOpts {
 
         let mut round = 0;
         loop {
+            if self.search_limit.map_or(false, |limit| round >= limit) {
+                bail!(
+                    "No run matched the target criteria ({}) in {} search rounds.",
+                    self.display_criteria(),
+                    round
+                );
+            }
             let sched_seed = rng.gen();
             if let Some(preempts) = self
                 .launch_search(round, sched_seed)
                 .unwrap_or_else(|e| panic!("Error: {}", e))
             {
-                let init_schedule: PreemptionRecord = PreemptionReader::new(&preempts).load_all();
+                let init_schedule: PreemptionRecord =
+                    PreemptionReader::new(&preempts).preemptions();
                 if self.verbose {
                     eprintln!(
                         ":: {}:\nSchedule:\n {}",
@@ -966,7 +1387,7 @@ impl AnalyzeOpts {
                     self.to_repro_chaos(sched_seed)
                 );
                 std::fs::copy(&preempts, preempts_path).expect("file copy to succeed");
-                break;
+                return Ok(());
             }
             round += 1;
         }
@@ -0,0 +1,134 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Saving and restoring the host paths bound into the guest's container. The guest can write to
+//! these, so without restoring them, a run could see what an earlier run left behind.
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Copies of a set of paths, as they were when the snapshot was taken.
+#[derive(Debug)]
+pub struct BindSnapshot {
+    /// Each path, with where its copy is kept. `None` if the path did not exist.
+    saved: Vec<(PathBuf, Option<PathBuf>)>,
+}
+
+impl BindSnapshot {
+    /// Copies each of `paths` into `dir`.
+    pub fn take(paths: &[PathBuf], dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut saved = Vec::new();
+        for (i, path) in paths.iter().enumerate() {
+            if path.symlink_metadata().is_ok() {
+                let copy = dir.join(i.to_string());
+                copy_tree(path, &copy)?;
+                saved.push((path.clone(), Some(copy)));
+            } else {
+                saved.push((path.clone(), None));
+            }
+        }
+        Ok(Self { saved })
+    }
+
+    /// Puts every path back the way it was when the snapshot was taken.
+    pub fn restore(&self) -> io::Result<()> {
+        for (path, copy) in &self.saved {
+            match copy {
+                Some(copy) if path.is_dir() && copy.is_dir() => {
+                    // Keep the directory itself, as it may be a mount point.
+                    for entry in fs::read_dir(path)? {
+                        remove_tree(&entry?.path())?;
+                    }
+                    for entry in fs::read_dir(copy)? {
+                        let entry = entry?;
+                        copy_tree(&entry.path(), &path.join(entry.file_name()))?;
+                    }
+                }
+                Some(copy) => {
+                    remove_tree(path)?;
+                    copy_tree(copy, path)?;
+                }
+                None => remove_tree(path)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Copies a file, symlink, or directory with everything in it.
+pub(super) fn copy_tree(src: &Path, dst: &Path) -> io::Result<()> {
+    let metadata = src.symlink_metadata()?;
+    let file_type = metadata.file_type();
+    if file_type.is_symlink() {
+        symlink(fs::read_link(src)?, dst)
+    } else if file_type.is_dir() {
+        fs::create_dir(dst)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &dst.join(entry.file_name()))?;
+        }
+        // Only now, in case the directory is read-only.
+        fs::set_permissions(dst, metadata.permissions())
+    } else {
+        fs::copy(src, dst).map(|_| ())
+    }
+}
+
+/// Removes a path of any kind, if it exists.
+fn remove_tree(path: &Path) -> io::Result<()> {
+    match path.symlink_metadata() {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restore_undoes_writes() {
+        let host = tempfile::TempDir::new().unwrap();
+        let data = host.path().join("data");
+        let config = host.path().join("config");
+        let output = host.path().join("output");
+        fs::create_dir_all(data.join("inputs")).unwrap();
+        fs::write(data.join("inputs/a"), "a").unwrap();
+        symlink("inputs/a", data.join("latest")).unwrap();
+        fs::write(&config, "x=1").unwrap();
+
+        let snapshot = BindSnapshot::take(
+            &[data.clone(), config.clone(), output.clone()],
+            &host.path().join("snapshot"),
+        )
+        .unwrap();
+
+        fs::write(data.join("inputs/a"), "changed").unwrap();
+        fs::write(data.join("inputs/b"), "b").unwrap();
+        fs::remove_file(data.join("latest")).unwrap();
+        fs::write(&config, "x=2").unwrap();
+        fs::create_dir(&output).unwrap();
+
+        snapshot.restore().unwrap();
+
+        assert_eq!(fs::read_to_string(data.join("inputs/a")).unwrap(), "a");
+        assert!(!data.join("inputs/b").exists());
+        assert_eq!(
+            fs::read_link(data.join("latest")).unwrap(),
+            Path::new("inputs/a")
+        );
+        assert_eq!(fs::read_to_string(&config).unwrap(), "x=1");
+        assert!(!output.exists());
+    }
+}
@@ -0,0 +1,421 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! A mode for analyzing a hermit run.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use clap::Parser;
+use detcore_model::config::SchedulePrefix;
+use regex::Regex;
+use reverie::process::ExitStatus;
+
+use crate::cli::analyze::cache::RunCache;
+use crate::cli::analyze::snapshot::BindSnapshot;
+use crate::cli::test_output::TestFramework;
+use crate::cli::zygote::Zygote;
+use crate::Progress;
+
+/// Repeat a run multiple times in a controlled search to find concurrency bugs.
+///
+/// Hermit analyze searches over runs of `hermit run`, and its primary input is a set of CLI flags
+/// that constitute a hermit run, i.e., ARGS in `hermit run ARGS`.  These arguments are passed to
+/// all runs that analyze considers (but some options may be overwritten by the settings varied by
+/// analyze's search process).
+///
+/// The analyzer is interested in programs with some *target* property (such as non-zero exit code
+/// due to crashing).  Analyze must have, or find, a `hermit run` execution that exhibits this
+/// target property. If the target "run1" is not explicitly provided, the tool can search for it
+/// (--search).
+///
+/// Analyze must also have a second (baseline) one that does not. If a "run2" is not provided,
+/// analyze assumes that a hermit run without chaos is the baseline.
+///
+/// Finally, given a target and a baseline run, the job of analyze is to search between them and
+/// find the critical events: instructions which, when reordered, cause the target property to hold
+/// or not.  This is evidence of an order violation or race condition bug, and means the the program
+/// has at least one concurrency bug.
+///
+/// The critical events are identified to the user by printing their stack traces, optionally into a
+/// separate report file.
+///
+#[derive(Debug, Parser)]
+pub struct AnalyzeOpts {
+    /// Target: Analyze runs that have collected stdout output matching this regular expression.
+    #[clap(long, value_name = "REGEX")]
+    pub target_stdout: Option<Regex>,
+
+    /// Target: Analyze runs that have collected stderr output matching this regular expression.
+    #[clap(long, value_name = "REGEX")]
+    pub target_stderr: Option<Regex>,
+
+    /// Target: Analyze runs that have the specified exit code.  Accepts "nonzero" for all nonzero
+    /// exit codes.  Accepts "none" or "any" for no filter at all (accepts any exit code).  The
+    /// default is "nonzero" because it's very common to analyze a bug that causes the program to
+    /// crash or error.
+    #[clap(long, default_value = "nonzero", value_name = "NUM|nonzero|any")]
+    pub target_exit_code: ExitStatusConstraint,
+
+    /// Target: Analyze runs in which a test failed, as this test framework reports it on stdout.
+    /// This builds the stdout criteria rather than leaving them to be written by hand with
+    /// `--target-stdout`.
+    #[clap(
+        long,
+        value_name = "cargo-test|gtest|pytest|tap",
+        conflicts_with = "target-stdout"
+    )]
+    pub criteria_from: Option<TestFramework>,
+
+    /// With `--criteria-from`, analyze only runs in which this test failed, rather than any test.
+    #[clap(long, value_name = "NAME", requires = "criteria-from")]
+    pub criteria_test: Option<String>,
+
+    /// Insist on perfect determinism before proceeding with the analysis.
+    #[clap(long)]
+    pub selfcheck: bool,
+
+    /// A `hermit log-diff --rules` file of known-benign differences, for the comparisons of logs
+    /// made by the analysis, such as under `--selfcheck`.
+    #[clap(long, value_name = "PATH")]
+    pub log_diff_rules: Option<PathBuf>,
+
+    /// If the first run doesn't match the target criteria, search for one that does.
+    #[clap(long)]
+    pub search: bool,
+
+    /// Give up the search after this many runs, rather than searching until a run matches.
+    #[clap(long, value_name = "N", requires = "search")]
+    pub search_limit: Option<u64>,
+
+    /// Given a passing/failing run pair, based on different chaos seeds, first minimize the
+    /// chaos-mode interventions necessary to flip between the two outcomes.  This may accelerate
+    /// the subsequent binary search.
+    #[clap(long)]
+    pub minimize: bool,
+
+    /// While minimizing, try knocking out the preemptions of up to this many threads at once, each
+    /// in a run of its own. The minimized schedule is the same as with one job at a time, for the
+    /// same `--analyze-seed` and number of jobs. The runs would share any paths bound into the
+    /// guest's container, so these must be bound read-only. Each of them is set up in a container
+    /// of its own, rather than forked from the zygote.
+    #[clap(long, value_name = "N", default_value = "1", requires = "minimize")]
+    pub minimize_jobs: usize,
+
+    /// Use `--imprecise-timers` during the (chaos) search phase. Only has an effect if search is
+    /// enabled.
+    #[clap(long)]
+    pub imprecise_search: bool,
+
+    /// Start each run of the (chaos) search phase by replaying a prefix of a recorded schedule, as
+    /// with `hermit run --replay-prefix-then-chaos`.  This focuses the search on races late in the
+    /// execution.  Only has an effect if search is enabled.
+    #[clap(long, value_name = "FILE:INDEX")]
+    pub search_prefix: Option<SchedulePrefix>,
+
+    /// Identify the target execution by chaos seed (hermit run --seed).
+    ///
+    /// It is an error if this execution does not meet the indicated target criteria.
+    #[clap(
+        long,
+        conflicts_with = "run1-preemptions",
+        conflicts_with = "run1-schedule",
+        value_name = "NUM"
+    )]
+    pub run1_seed: Option<u64>,
+
+    /// Load target execution from hermit run --record-preemptions-to.
+    #[clap(
+        long,
+        conflicts_with = "run1-seed",
+        conflicts_with = "run1-schedule",
+        value_name = "PATH"
+    )]
+    pub run1_preemptions: Option<PathBuf>,
+
+    /// Load target execution from hermit run --record-schedule-to.
+    #[clap(
+        long,
+        conflicts_with = "run1-seed",
+        conflicts_with = "run1-preemptions",
+        value_name = "PATH"
+    )]
+    pub run1_schedule: Option<PathBuf>,
+
+    /// Optionally use a second chaos seed to identify the baseline run that
+    /// does NOT meet the target criteria.
+    #[clap(
+        long,
+        conflicts_with = "run2-preemptions",
+        conflicts_with = "run2-schedule",
+        value_name = "NUM"
+    )]
+    pub run2_seed: Option<u64>,
+
+    /// Load baseline execution from hermit run --record-preemptions-to.
+    #[clap(
+        long,
+        conflicts_with = "run2-seed",
+        conflicts_with = "run2-schedule",
+        value_name = "PATH"
+    )]
+    pub run2_preemptions: Option<PathBuf>,
+
+    /// Load baseline execution from hermit run --record-schedule-to.
+    #[clap(
+        long,
+        conflicts_with = "run2-seed",
+        conflicts_with = "run2-preemptions",
+        value_name = "PATH"
+    )]
+    pub run2_schedule: Option<PathBuf>,
+
+    /// A path to write the final analyze result, showing the critical event stack traces.
+    ///
+    /// Otherwise it is written to stdout.
+    #[clap(long)]
+    pub report_file: Option<PathBuf>,
+
+    /// Write the happens-before relation among the events around the critical events of the final
+    /// on-target schedule to this file: as JSON if it ends in `.json`, and as a Graphviz graph
+    /// otherwise. The relation is induced by each thread's order, spawns and exits, and futex,
+    /// pipe and socket operations, so that a race shows up as the edge missing between the
+    /// critical events.
+    #[clap(long, value_name = "PATH")]
+    pub hb_graph: Option<PathBuf>,
+
+    // TODO: run2_schedule
+    //
+    /// Use to seed the PRNG that supplies randomness to the analyzer when it is making random
+    /// decisions during search or minimization.  If unset, then system randomness is used.
+    #[clap(long)]
+    pub analyze_seed: Option<u64>,
+
+    /// Print quite a bit of extra information so that you can see exactly what is happening.
+    #[clap(long, short)]
+    pub verbose: bool,
+
+    /// Where the analysis run will store its temporary artifacts.
+    ///
+    /// By default this is a directory in `/tmp`
+    #[clap(long, value_name = "PATH")]
+    pub tmp_dir: Option<PathBuf>,
+
+    /// Keep the workspace in memory, on the tmpfs at `/dev/shm`, so that the logs, schedules and
+    /// outputs of many short runs do not wait on the disk. When the analysis finishes, only its
+    /// final artifacts are copied to a workspace on disk, and the rest is freed.
+    #[clap(long)]
+    pub workspace_in_memory: bool,
+
+    /// Keep the output and artifacts of every run in this directory, and reuse them when a later
+    /// analysis of the same program makes the same run, e.g. with tweaked target criteria. Runs
+    /// are keyed by a hash of the program's binary, of their options, and of the schedule they
+    /// replay, but not of the contents of other paths bound into the container. Unless
+    /// `--analyze-seed` is given, the seed of the first analysis is reused, so that later ones
+    /// make the same search and minimization runs.
+    #[clap(long, value_name = "PATH")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Compress the log and schedules of each run with zstd once it finishes, keeping their
+    /// names. Hermit reads them as it does uncompressed ones, e.g. in `hermit log-diff` and
+    /// `hermit run --replay-schedule-from`. Other tools need them decompressed with `zstd -d`.
+    /// Compressed schedules are read whole into memory, where uncompressed ones are read in place.
+    #[clap(long)]
+    pub compress_workspace: bool,
+
+    /// Keep the workspace under this size, e.g. "20GB", by deleting the artifacts of search,
+    /// minimization and bisection runs which did not match the target, oldest first. The runs
+    /// which matched, and the endpoints of each phase, are kept.
+    #[clap(long, parse(try_from_str = try_parse_bytesize), value_name = "bytesize")]
+    pub max_workspace_size: Option<u64>,
+
+    /// Specify that analyze itself should return a non-zero exit code on success.
+    /// This is needed under esoteric invocation scenarios.
+    #[clap(long, value_name = "INT32")]
+    pub success_exit_code: Option<i32>,
+
+    /// Set up a new container for every run. By default, the guest's container is set up once,
+    /// and each run is forked from a process (the zygote) waiting inside it. The runs then share
+    /// the container, except for `/tmp`, which is cleared before each run, so this is needed by
+    /// guests which leave state elsewhere in it that would change the runs after them.
+    #[clap(long)]
+    pub no_zygote: bool,
+
+    /// A full set of CLI arguments for the original `hermit run` to analyze.
+    #[clap(value_name = "ARGS")]
+    pub run_args: Vec<String>,
+
+    /// The paths bound into the guest's container, as they were before the first run.
+    #[clap(skip)]
+    pub bind_snapshot: Option<BindSnapshot>,
+
+    /// The container each run is forked from, unless `--no-zygote`.
+    #[clap(skip)]
+    pub zygote_server: Option<Zygote>,
+
+    /// The runs of earlier analyses, with `--cache-dir`.
+    #[clap(skip)]
+    pub run_cache: Option<RunCache>,
+
+    /// The runs whose artifacts may be deleted to keep under `--max-workspace-size`, oldest
+    /// first.
+    #[clap(skip)]
+    pub evictable_runs: RefCell<VecDeque<String>>,
+
+    /// Told of the progress of the analysis, e.g. by `hermit::Analyzer`.
+    #[clap(skip)]
+    pub progress: Option<ProgressCallback>,
+}
+
+// Entry points for driving an analysis from other subcommands, e.g. `hermit flake-hunt`.
+impl AnalyzeOpts {
+    /// Options to analyze `hermit run` with the given arguments, and otherwise the defaults of
+    /// `hermit analyze`.
+    pub fn new<I, S>(run_args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut opts = AnalyzeOpts::try_parse_from(["analyze"])?;
+        opts.run_args = run_args.into_iter().map(Into::into).collect();
+        Ok(opts)
+    }
+
+    /// Call `f` as the analysis progresses.
+    pub fn on_progress(mut self, f: impl Fn(&Progress) + 'static) -> Self {
+        self.progress = Some(ProgressCallback(Box::new(f)));
+        self
+    }
+}
+
+/// A function told of the progress of an analysis.
+pub struct ProgressCallback(pub Box<dyn Fn(&Progress)>);
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ProgressCallback")
+    }
+}
+
+// TODO: introduce a new type to encapsulate the state of the search, and make it immutable.
+// pub struct SearchState {}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum ExitStatusConstraint {
+    /// Accept only a specific exit code.
+    Exact(i32),
+    /// Accept any nonzero exit code.
+    NonZero,
+    /// Accept any exit code.  No filter.
+    Any,
+}
+
+impl ExitStatusConstraint {
+    /// Is the constraint met for a given exit code?
+    pub fn is_match(&self, exit_status: ExitStatus) -> bool {
+        let exit_code = exit_status.into_raw();
+        match self {
+            ExitStatusConstraint::Exact(code) => exit_code == *code,
+            ExitStatusConstraint::NonZero => exit_code != 0,
+            ExitStatusConstraint::Any => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn analyze_opts_from_code() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        let opts = AnalyzeOpts::new(["--chaos", "./test"])
+            .unwrap()
+            .on_progress(move |p| log.borrow_mut().push(p.to_string()));
+        assert_eq!(opts.run_args, vec!["--chaos", "./test"]);
+        assert_eq!(opts.target_exit_code, ExitStatusConstraint::NonZero);
+
+        let progress = opts.progress.as_ref().unwrap();
+        (progress.0)(&Progress::Phase("bisect".to_owned()));
+        (progress.0)(&Progress::Run {
+            name: "bisect_round_1".to_owned(),
+            matched: false,
+        });
+        assert_eq!(
+            *seen.borrow(),
+            vec![
+                "Starting phase: bisect",
+                "Run bisect_round_1 did not match the target",
+            ]
+        );
+    }
+
+    #[test]
+    fn criteria_from_test_output() {
+        let args = "analyze --criteria-from=gtest --criteria-test=Queue.Push ./test".split(' ');
+        let opts = AnalyzeOpts::try_parse_from(args).unwrap();
+        assert_eq!(opts.criteria_from, Some(TestFramework::Gtest));
+        assert_eq!(opts.criteria_test.as_deref(), Some("Queue.Push"));
+        assert!(AnalyzeOpts::try_parse_from(["analyze", "--criteria-test=Queue.Push"]).is_err());
+    }
+
+    #[test]
+    fn minimize_jobs() {
+        let opts = AnalyzeOpts::try_parse_from(["analyze", "./test"]).unwrap();
+        assert_eq!(opts.minimize_jobs, 1);
+        let args = "analyze --minimize --minimize-jobs=8 ./test".split(' ');
+        assert_eq!(AnalyzeOpts::try_parse_from(args).unwrap().minimize_jobs, 8);
+    }
+
+    #[test]
+    fn zygote_by_default() {
+        let opts = AnalyzeOpts::try_parse_from(["analyze", "./test"]).unwrap();
+        assert!(!opts.no_zygote);
+        let opts = AnalyzeOpts::try_parse_from(["analyze", "--no-zygote", "./test"]).unwrap();
+        assert!(opts.no_zygote);
+    }
+
+    #[test]
+    fn max_workspace_size() {
+        let args = "analyze --max-workspace-size=2KB ./test".split(' ');
+        let opts = AnalyzeOpts::try_parse_from(args).unwrap();
+        assert_eq!(opts.max_workspace_size, Some(2000));
+        let args = "analyze --max-workspace-size=lots ./test".split(' ');
+        assert!(AnalyzeOpts::try_parse_from(args).is_err());
+    }
+}
+
+impl FromStr for ExitStatusConstraint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(n) = s.parse::<i32>() {
+            Ok(ExitStatusConstraint::Exact(n))
+        } else {
+            match s.to_lowercase().as_str() {
+                "nonzero" => Ok(ExitStatusConstraint::NonZero),
+                "none" | "any" => Ok(ExitStatusConstraint::Any),
+                _ => Err(format!(
+                    "Unable to parse string as exit code constraint, expected a number, 'none'/'any', or 'nonzero'.  Received: {}",
+                    s
+                )),
+            }
+        }
+    }
+}
+
+fn try_parse_bytesize(from_str: &str) -> Result<u64, String> {
+    <bytesize::ByteSize as FromStr>::from_str(from_str).map(|size| size.as_u64())
+}
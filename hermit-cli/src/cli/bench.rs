@@ -0,0 +1,285 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::fmt;
+use std::fmt::Write;
+use std::fs;
+use std::str::FromStr;
+use std::time::Duration;
+use std::time::Instant;
+
+use clap::Parser;
+use colored::Colorize;
+use detcore::RunSummary;
+use reverie::process::ExitStatus;
+
+use super::global_opts::GlobalOpts;
+use super::run::RunOpts;
+use crate::Context;
+use crate::Error;
+
+/// A way of running the program under hermit, whose cost `hermit bench` measures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchConfig {
+    /// A plain `hermit run`.
+    Strict,
+    /// `hermit run --chaos`.
+    Chaos,
+    /// `hermit run --record-preemptions`.
+    Record,
+}
+
+impl fmt::Display for BenchConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BenchConfig::Strict => write!(f, "strict"),
+            BenchConfig::Chaos => write!(f, "chaos"),
+            BenchConfig::Record => write!(f, "record"),
+        }
+    }
+}
+
+impl FromStr for BenchConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "strict" => Ok(BenchConfig::Strict),
+            "chaos" => Ok(BenchConfig::Chaos),
+            "record" => Ok(BenchConfig::Record),
+            _ => Err(format!(
+                "Expected strict | chaos | record, could not parse: {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// Measure how much hermit slows down a program.
+///
+/// The program is run natively, and then under each hermit configuration, the same number of
+/// times each. The mean wall-clock time of each configuration is reported, with its slowdown
+/// relative to running natively. For the hermit configurations, the syscalls intercepted and the
+/// scheduler's turns and timeslices are reported too, along with the wall-clock time per turn,
+/// which is what each handoff between threads costs. A second table breaks down the scheduler's
+/// time, in its last run: the time it spent choosing threads and granting their requests, which
+/// is its own overhead, and the time it waited for threads to park, for the chosen thread's
+/// request, and in backing off when no thread could run.
+///
+/// Fails if any run of the program fails, natively or under hermit.
+#[derive(Debug, Parser)]
+pub struct BenchOpts {
+    /// How many times to run the program in each configuration.
+    #[clap(long, value_name = "N", default_value = "3")]
+    runs: u32,
+
+    /// The hermit configurations to measure, separated by commas.
+    #[clap(
+        long,
+        value_name = "strict|chaos|record",
+        default_value = "strict,chaos,record",
+        value_delimiter = ',',
+        use_delimiter = true
+    )]
+    configs: Vec<BenchConfig>,
+
+    /// A full set of CLI arguments for `hermit run`, which each configuration adds to.
+    #[clap(value_name = "ARGS")]
+    run_args: Vec<String>,
+}
+
+/// The measurements of the runs in one configuration.
+#[derive(Debug)]
+struct Measurement {
+    /// "native", or the hermit configuration.
+    name: String,
+    /// The wall-clock time of each run.
+    times: Vec<Duration>,
+    /// The summary of the last run, under hermit.
+    summary: Option<RunSummary>,
+}
+
+impl Measurement {
+    fn new(name: impl ToString) -> Self {
+        Measurement {
+            name: name.to_string(),
+            times: Vec::new(),
+            summary: None,
+        }
+    }
+
+    fn mean(&self) -> Duration {
+        self.times.iter().sum::<Duration>() / self.times.len() as u32
+    }
+}
+
+impl BenchOpts {
+    pub fn main(&self, global: &GlobalOpts) -> Result<ExitStatus, Error> {
+        if self.runs == 0 {
+            return Err(Error::msg("--runs needs at least one run"));
+        }
+        // Bogus arg 0 for CLI argument parsing:
+        let args = std::iter::once("hermit-run").chain(self.run_args.iter().map(String::as_str));
+        let base = RunOpts::from_iter(args);
+
+        // The summaries are written from inside the container, which has its own `/tmp`.
+        let data = crate::HermitData::new();
+        fs::create_dir_all(data.data_dir())?;
+        let workspace = tempfile::Builder::new()
+            .prefix("bench_")
+            .tempdir_in(data.data_dir())?;
+
+        eprintln!(":: {}", "Running natively...".yellow().bold());
+        let mut native = Measurement::new("native");
+        for _ in 0..self.runs {
+            let mut command = base.native_command()?;
+            let start = Instant::now();
+            let status = command
+                .status()
+                .context("Failed to run the program natively")?;
+            native.times.push(start.elapsed());
+            // Timings of a program that failed would not say what it costs to run it.
+            if !status.success() {
+                return Err(Error::msg(format!(
+                    "The program failed when run natively, with {}",
+                    status
+                )));
+            }
+        }
+        let mut measurements = vec![native];
+
+        for config in &self.configs {
+            let title = format!("Running under hermit ({})...", config);
+            eprintln!(":: {}", title.yellow().bold());
+            let mut ro = base.clone();
+            match config {
+                BenchConfig::Strict => {}
+                BenchConfig::Chaos => ro.det_opts.det_config.chaos = true,
+                BenchConfig::Record => {
+                    let path = workspace.path().join("record.preempts");
+                    ro.det_opts.det_config.record_preemptions_to = Some(path);
+                }
+            }
+            let summary_path = workspace.path().join(format!("{}.json", config));
+            ro.summary_json = Some(summary_path.clone());
+            ro.validate_args()?;
+
+            let mut measurement = Measurement::new(config);
+            for _ in 0..self.runs {
+                let start = Instant::now();
+                let status = ro.run(global)?;
+                measurement.times.push(start.elapsed());
+                if status != ExitStatus::SUCCESS {
+                    return Err(Error::msg(format!(
+                        "The program failed under hermit ({}), with {:?}",
+                        config, status
+                    )));
+                }
+            }
+            let summary = fs::read(&summary_path)
+                .with_context(|| format!("Failed to read {}", summary_path.display()))?;
+            measurement.summary = Some(serde_json::from_slice(&summary)?);
+            measurements.push(measurement);
+        }
+
+        print!("{}", report(&measurements));
+        Ok(ExitStatus::SUCCESS)
+    }
+}
+
+/// A table of the measurements, the first of which is of the native runs.
+fn report(measurements: &[Measurement]) -> String {
+    let native = measurements[0].mean();
+    let mut out = format!(
+        "{:<8} {:>10} {:>9} {:>9} {:>8} {:>10} {:>10}\n",
+        "config", "wall (ms)", "slowdown", "syscalls", "turns", "timeslices", "us/turn"
+    );
+    for measurement in measurements {
+        let mean = measurement.mean();
+        let slowdown = mean.as_secs_f64() / native.as_secs_f64();
+        write!(
+            out,
+            "{:<8} {:>10.1} {:>8.1}x",
+            measurement.name,
+            mean.as_secs_f64() * 1000.0,
+            slowdown
+        )
+        .unwrap();
+        match &measurement.summary {
+            Some(summary) => {
+                let syscalls: u64 = summary.syscall_counts.values().sum();
+                let timeslices: u64 = summary.threads.iter().map(|t| t.timeslices).sum();
+                let per_turn = mean.as_secs_f64() * 1e6 / summary.turns.max(1) as f64;
+                writeln!(
+                    out,
+                    " {:>9} {:>8} {:>10} {:>10.1}",
+                    syscalls, summary.turns, timeslices, per_turn
+                )
+                .unwrap();
+            }
+            None => writeln!(out, " {:>9} {:>8} {:>10} {:>10}", "-", "-", "-", "-").unwrap(),
+        }
+    }
+
+    writeln!(
+        out,
+        "\n{:<8} {:>11} {:>12} {:>12} {:>12}",
+        "config", "decide (ms)", "quiesce (ms)", "request (ms)", "backoff (ms)"
+    )
+    .unwrap();
+    for measurement in measurements {
+        if let Some(summary) = &measurement.summary {
+            let overhead = &summary.sched_overhead;
+            let ms = |ns: u64| ns as f64 / 1e6;
+            writeln!(
+                out,
+                "{:<8} {:>11.1} {:>12.1} {:>12.1} {:>12.1}",
+                measurement.name,
+                ms(overhead.decide_ns),
+                ms(overhead.quiesce_ns),
+                ms(overhead.request_ns),
+                ms(overhead.backoff_ns)
+            )
+            .unwrap();
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bench_report() {
+        let mut native = Measurement::new("native");
+        native.times = vec![Duration::from_millis(9), Duration::from_millis(11)];
+        let mut strict = Measurement::new(BenchConfig::Strict);
+        strict.times = vec![Duration::from_millis(50)];
+        let mut summary = RunSummary {
+            turns: 100,
+            ..Default::default()
+        };
+        summary.syscall_counts.insert("write".to_owned(), 7);
+        summary.sched_overhead.decide_ns = 2_500_000;
+        summary.sched_overhead.request_ns = 40_000_000;
+        strict.summary = Some(summary);
+
+        let report = report(&[native, strict]);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert!(lines[1].starts_with("native         10.0      1.0x         -"));
+        assert!(lines[2].starts_with("strict         50.0      5.0x         7      100"));
+        assert!(lines[2].ends_with("500.0"));
+        assert!(lines[4].starts_with("config   decide (ms)"));
+        assert_eq!(
+            lines[5],
+            "strict           2.5          0.0         40.0          0.0"
+        );
+    }
+}
@@ -0,0 +1,196 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! The `--bind` option of `hermit run`.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use reverie::process::Mount;
+
+/// A path made available to the guest. Parsed from `path`, `src:dst`, or
+/// `src:dst:opts`, where `opts` is a comma-separated list of:
+///
+///  - `ro`: the guest may not write to the path.
+///  - `rw`: the guest may write to the path (the default).
+///  - `size=N`: a size limit, such as `64m`, for a tmpfs.
+///
+/// A `src` of `tmpfs` mounts a fresh, empty tmpfs at `dst` instead. To bind a
+/// relative path by that name, write it as `./tmpfs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bind {
+    /// The host path, or `None` for a tmpfs.
+    pub source: Option<PathBuf>,
+
+    /// Where the guest sees it.
+    pub target: PathBuf,
+
+    /// Whether the guest can only read it.
+    pub read_only: bool,
+
+    /// The size limit of a tmpfs, as given to `mount -o size=`.
+    pub size: Option<String>,
+}
+
+impl Bind {
+    /// A bind that makes a host path available at the same path.
+    pub fn same<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        Self {
+            source: Some(path.clone()),
+            target: path,
+            read_only: false,
+            size: None,
+        }
+    }
+
+    /// True if this only asks for the guest to see the host's path as it is.
+    pub fn is_identity(&self) -> bool {
+        self.source.as_ref() == Some(&self.target) && !self.read_only
+    }
+}
+
+impl FromStr for Bind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let source = parts.next().unwrap_or_default();
+        let target = parts.next();
+        let opts = parts.next();
+
+        if source.is_empty() || target == Some("") {
+            return Err(format!(
+                "expected path, src:dst, or src:dst:opts, got '{}'",
+                s
+            ));
+        }
+
+        let mut bind = Bind::same(target.unwrap_or(source));
+        if source == "tmpfs" && target.is_some() {
+            bind.source = None;
+        } else {
+            bind.source = Some(PathBuf::from(source));
+        }
+
+        for opt in opts.into_iter().flat_map(|opts| opts.split(',')) {
+            if let (Some(size), None) = (opt.strip_prefix("size="), &bind.source) {
+                bind.size = Some(size.to_owned());
+                continue;
+            }
+            match opt {
+                "ro" => bind.read_only = true,
+                "rw" => bind.read_only = false,
+                _ => return Err(format!("unknown --bind option '{}' in '{}'", opt, s)),
+            }
+        }
+
+        Ok(bind)
+    }
+}
+
+impl fmt::Display for Bind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let target = self.target.to_str().expect("valid unicode bind target");
+        match &self.source {
+            Some(source) => {
+                let source = source.to_str().expect("valid unicode bind source");
+                if self.is_identity() {
+                    return write!(f, "{}", shell_words::quote(source));
+                }
+                write!(f, "{}", shell_words::quote(source))?;
+            }
+            None => write!(f, "tmpfs")?,
+        }
+        write!(f, ":{}", shell_words::quote(target))?;
+
+        let mut opts = Vec::new();
+        if self.read_only {
+            opts.push("ro".to_owned());
+        }
+        if let Some(size) = &self.size {
+            opts.push(format!("size={}", size));
+        }
+        if !opts.is_empty() {
+            write!(f, ":{}", opts.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Bind> for Mount {
+    fn from(bind: Bind) -> Self {
+        let mount = match bind.source {
+            Some(source) => Mount::bind(source, bind.target).recursive(),
+            None => {
+                let mut mount = Mount::new(bind.target).source("tmpfs").fstype("tmpfs");
+                if let Some(size) = bind.size {
+                    mount = mount.data(format!("size={}", size));
+                }
+                mount
+            }
+        };
+        if bind.read_only {
+            mount.readonly()
+        } else {
+            mount
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Bind {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_and_display() {
+        for s in [
+            "/data",
+            "/data:/tmp/data",
+            "/data:/data:ro",
+            "/data:/tmp/data:ro",
+            "tmpfs:/scratch",
+            "tmpfs:/scratch:size=64m",
+            "'/my data':/tmp/data",
+        ] {
+            let unquoted = shell_words::split(s).unwrap().join("");
+            assert_eq!(parse(&unquoted).to_string(), s);
+        }
+    }
+
+    #[test]
+    fn parse_fields() {
+        assert!(parse("/data").is_identity());
+        assert!(parse("/data:/data").is_identity());
+        assert!(!parse("/data:/data:ro").is_identity());
+        assert_eq!(
+            parse("tmpfs:/scratch:ro,size=1g"),
+            Bind {
+                source: None,
+                target: "/scratch".into(),
+                read_only: true,
+                size: Some("1g".to_owned()),
+            }
+        );
+        // Only a tmpfs source has that special meaning.
+        assert_eq!(parse("tmpfs"), Bind::same("tmpfs"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!("".parse::<Bind>().is_err());
+        assert!("/data:".parse::<Bind>().is_err());
+        assert!("/data:/data:rx".parse::<Bind>().is_err());
+        assert!("/data:/data:size=1g".parse::<Bind>().is_err());
+    }
+}
@@ -14,7 +14,6 @@ use std::net::SocketAddr;
 use std::path::PathBuf;
 
 use clap::Parser;
-use hermit::Error;
 use once_cell::sync::OnceCell;
 use reverie::process::Command;
 use reverie::syscalls;
@@ -36,6 +35,7 @@ use serde::Serialize;
 use super::container::default_container;
 use super::container::with_container;
 use super::global_opts::GlobalOpts;
+use crate::Error;
 
 /// A tool to trace system calls.
 #[derive(Parser, Debug)]
@@ -10,12 +10,12 @@ use std::path::PathBuf;
 
 use clap::Parser;
 use colored::Colorize;
-use hermit::Context;
-use hermit::Error;
-use hermit::HermitData;
 use reverie::ExitStatus;
 
 use super::global_opts::GlobalOpts;
+use crate::Context;
+use crate::Error;
+use crate::HermitData;
 
 /// Command-line options for the "clean" subcommand.
 #[derive(Debug, Parser)]
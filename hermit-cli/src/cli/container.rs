@@ -6,19 +6,20 @@
  * LICENSE file in the root directory of this source tree.
  */
 
-use hermit::Context;
-use hermit::Error;
-use hermit::SerializableError;
 use rand::thread_rng;
 use rand::Rng;
 use reverie::process::Container;
 use reverie::process::Mount;
 use reverie::process::Namespace;
 
+use crate::Context;
+use crate::Error;
+use crate::SerializableError;
+
 pub fn default_container(pin_threads: bool) -> Container {
     let mut container = Container::new();
     container
-        .unshare(Namespace::PID)
+        .unshare(Namespace::PID | Namespace::IPC)
         .map_root()
         .hostname("hermetic-container.local")
         .domainname("local")
@@ -0,0 +1,52 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use detcore::preemptions::PreemptionReader;
+use detcore::preemptions::RecordFormat;
+use reverie::ExitStatus;
+
+use super::global_opts::GlobalOpts;
+use crate::Error;
+
+/// Command-line options for the "convert-schedule" subcommand.
+#[derive(Debug, Parser)]
+pub struct ConvertScheduleOpts {
+    /// A schedule or preemption record, such as written by `--record-preemptions-to`, in either
+    /// format.
+    #[clap(value_name = "INPUT")]
+    input: PathBuf,
+
+    /// Where to write the converted record.
+    #[clap(value_name = "OUTPUT")]
+    output: PathBuf,
+
+    /// The format to write. By default this is binary if OUTPUT ends in `.bin`, streaming JSON
+    /// lines if it ends in `.jsonl`, and JSON otherwise. Any format can be read wherever a
+    /// schedule or preemption record is expected.
+    #[clap(long, value_name = "json|binary|stream")]
+    format: Option<RecordFormat>,
+}
+
+impl ConvertScheduleOpts {
+    pub fn main(&self, global: &GlobalOpts) -> Result<ExitStatus, Error> {
+        let _guard = global.init_tracing();
+
+        let record = PreemptionReader::new(&self.input).into_inner();
+        let format = self
+            .format
+            .unwrap_or_else(|| RecordFormat::for_path(&self.output));
+        record
+            .write_to_disk_as(&self.output, format)
+            .map_err(Error::msg)?;
+
+        Ok(ExitStatus::SUCCESS)
+    }
+}
@@ -0,0 +1,193 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! A copy-on-write view of the host's root filesystem. The guest is chrooted
+//! into an overlayfs mount whose lower layer is `/`, so that all of its writes
+//! land in an upper directory that we own, and never on the host itself.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::path::PathBuf;
+
+use reverie::process::Mount;
+
+use super::rootfs::RootFs;
+
+/// The directories making up a copy-on-write root.
+pub struct CowRoot {
+    root: PathBuf,
+}
+
+impl CowRoot {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+        }
+    }
+
+    /// Receives the guest's writes.
+    fn upper(&self) -> PathBuf {
+        self.root.join("upper")
+    }
+
+    /// Scratch space for overlayfs.
+    fn work(&self) -> PathBuf {
+        self.root.join("work")
+    }
+
+    /// The directory the guest is chrooted to.
+    pub fn merged(&self) -> RootFs {
+        RootFs::new(self.root.join("merged"))
+    }
+
+    /// Throws away the changes of any previous run, so that every run starts
+    /// from the host's filesystem as it is now.
+    pub fn reset(&self) -> io::Result<()> {
+        for dir in [self.upper(), self.work()] {
+            if dir.exists() {
+                fs::remove_dir_all(&dir)?;
+            }
+            fs::create_dir_all(&dir)?;
+        }
+        fs::create_dir_all(self.merged().path())
+    }
+
+    /// Returns the mounts that set up the overlay and pass the host's special
+    /// filesystems through to it. These must come before any mounts made
+    /// with [`RootFs::rebase`] on [`CowRoot::merged`].
+    pub fn mounts(&self) -> Vec<Mount> {
+        let data = format!(
+            "lowerdir=/,upperdir={},workdir={}",
+            self.upper().display(),
+            self.work().display()
+        );
+        let merged = self.merged();
+        let mut mounts = vec![
+            Mount::new(merged.path())
+                .source("overlay")
+                .fstype("overlay")
+                .data(data),
+        ];
+        mounts.extend(merged.mounts());
+        mounts
+    }
+
+    /// Lists the changes the guest made to the filesystem, in path order. Paths
+    /// that exist in `lower` were modified rather than created.
+    pub fn changes(&self, lower: &Path) -> io::Result<Vec<Change>> {
+        let mut changes = Vec::new();
+        collect_changes(&self.upper(), Path::new("/"), lower, &mut changes)?;
+        Ok(changes)
+    }
+}
+
+/// A change to a single path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Created(PathBuf),
+    Modified(PathBuf),
+    Deleted(PathBuf),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Created(path) => write!(f, "A {}", path.display()),
+            Self::Modified(path) => write!(f, "M {}", path.display()),
+            Self::Deleted(path) => write!(f, "D {}", path.display()),
+        }
+    }
+}
+
+fn collect_changes(
+    dir: &Path,
+    guest_dir: &Path,
+    lower: &Path,
+    changes: &mut Vec<Change>,
+) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let guest_path = guest_dir.join(entry.file_name());
+        let lower_path = lower.join(guest_path.strip_prefix("/").unwrap());
+        let metadata = entry.path().symlink_metadata()?;
+        let file_type = metadata.file_type();
+        let in_lower = lower_path.symlink_metadata().is_ok();
+
+        if file_type.is_char_device() && metadata.rdev() == 0 {
+            // A whiteout, hiding the path in the lower layer.
+            changes.push(Change::Deleted(guest_path));
+            continue;
+        }
+
+        if file_type.is_dir() {
+            // Directories that already existed are only here because something
+            // in them changed.
+            if !in_lower {
+                changes.push(Change::Created(guest_path.clone()));
+            }
+            collect_changes(&entry.path(), &guest_path, lower, changes)?;
+        } else if in_lower {
+            changes.push(Change::Modified(guest_path));
+        } else {
+            changes.push(Change::Created(guest_path));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_against_lower() {
+        let lower = tempfile::TempDir::new().unwrap();
+        fs::create_dir_all(lower.path().join("etc")).unwrap();
+        fs::write(lower.path().join("etc/passwd"), "root").unwrap();
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let cow = CowRoot::new(dir.path());
+        cow.reset().unwrap();
+        fs::create_dir_all(cow.upper().join("etc")).unwrap();
+        fs::write(cow.upper().join("etc/passwd"), "root\nguest").unwrap();
+        fs::create_dir_all(cow.upper().join("out/logs")).unwrap();
+        fs::write(cow.upper().join("out/logs/run.log"), "").unwrap();
+
+        assert_eq!(
+            cow.changes(lower.path()).unwrap(),
+            vec![
+                Change::Modified("/etc/passwd".into()),
+                Change::Created("/out".into()),
+                Change::Created("/out/logs".into()),
+                Change::Created("/out/logs/run.log".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reset_discards_changes() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let cow = CowRoot::new(dir.path());
+        cow.reset().unwrap();
+        fs::write(cow.upper().join("file"), "").unwrap();
+        cow.reset().unwrap();
+        assert_eq!(cow.changes(Path::new("/")).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Change::Deleted("/a b".into()).to_string(), "D /a b");
+    }
+}
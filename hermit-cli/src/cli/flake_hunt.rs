@@ -0,0 +1,161 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::cell::Cell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use clap::Parser;
+use colored::Colorize;
+use reverie::process::ExitStatus;
+
+use super::analyze::AnalyzeOpts;
+use super::global_opts::GlobalOpts;
+use super::test_output::TestFramework;
+use crate::Error;
+use crate::Progress;
+
+/// The exit code when no run made the test fail.
+const NOT_REPRODUCED: i32 = 10;
+/// The exit code when the test failed under hermit, but the analysis could not finish.
+const REPRODUCED: i32 = 11;
+/// The exit code when the failure was analyzed, and the race behind it reported.
+const ANALYZED: i32 = 12;
+
+/// Hunt down the race behind a flaky test, for CI.
+///
+/// Runs the test command under chaos until the named test fails, then minimizes and bisects the
+/// schedule to find the race, as `hermit analyze --search --minimize` would. A run matches when
+/// the output reports the test as failed, in the format of Rust's libtest, GoogleTest, pytest, or
+/// TAP, so no criteria need to be written by hand.
+///
+/// Exits with 10 if the test never failed, 11 if it failed but the analysis did not finish, and 12
+/// if the analysis finished and its report was written. Other errors exit with 1.
+#[derive(Debug, Parser)]
+pub struct FlakeHuntOpts {
+    /// The name of the flaky test, as its framework prints it, e.g. `queue::test_push`,
+    /// `Queue.Push`, or `tests/test_queue.py::test_push`.
+    #[clap(long, value_name = "NAME")]
+    test: String,
+
+    /// Only recognize the output of this test framework, rather than of any of them.
+    #[clap(long, value_name = "cargo-test|gtest|pytest|tap")]
+    framework: Option<TestFramework>,
+
+    /// Give up on making the test fail after this many runs.
+    #[clap(long, value_name = "N", default_value = "100")]
+    max_runs: u64,
+
+    /// A path to write the final analysis report to.
+    #[clap(long, value_name = "PATH")]
+    report_file: Option<PathBuf>,
+
+    /// Seed the choices of the search, for a repeatable hunt. If unset, system randomness is used.
+    #[clap(long, value_name = "NUM")]
+    analyze_seed: Option<u64>,
+
+    /// A full set of CLI arguments for the `hermit run` of the test command.
+    #[clap(value_name = "ARGS")]
+    run_args: Vec<String>,
+}
+
+impl FlakeHuntOpts {
+    pub fn main(&self, global: &GlobalOpts) -> Result<ExitStatus, Error> {
+        let failed = Rc::new(Cell::new(false));
+        let passed = Rc::new(Cell::new(false));
+        let mut opts = self.analyze_opts()?.on_progress({
+            let failed = failed.clone();
+            let passed = passed.clone();
+            move |progress| {
+                if let Progress::Run { matched, .. } = progress {
+                    if *matched {
+                        failed.set(true);
+                    } else {
+                        passed.set(true);
+                    }
+                }
+            }
+        });
+
+        let code = match opts.analyze(global) {
+            Ok(analysis) => {
+                eprintln!(
+                    ":: {}\n    hermit run --replay-schedule-from={} ...",
+                    "Analyzed the failure of the test. Reproduce it with:"
+                        .green()
+                        .bold(),
+                    analysis.target_schedule.display()
+                );
+                if let Some(path) = &self.report_file {
+                    let txt = serde_json::to_string(&analysis.report)?;
+                    std::fs::write(path, txt)?;
+                    eprintln!(":: Report written to {}", path.display());
+                }
+                ANALYZED
+            }
+            Err(err) if failed.get() => {
+                eprintln!(
+                    ":: {} {:#}",
+                    "The test failed, but analyzing the failure did not finish:"
+                        .red()
+                        .bold(),
+                    err
+                );
+                REPRODUCED
+            }
+            Err(err) if passed.get() => {
+                eprintln!(
+                    ":: {} {:#}",
+                    "The test never failed under hermit:".yellow().bold(),
+                    err
+                );
+                NOT_REPRODUCED
+            }
+            // Not a single run finished, so the test could not be run at all.
+            Err(err) => return Err(err),
+        };
+        Ok(ExitStatus::Exited(code))
+    }
+
+    /// The options of the analysis, with the criteria derived from the test.
+    fn analyze_opts(&self) -> Result<AnalyzeOpts, Error> {
+        let mut run_args = Vec::new();
+        if !self.run_args.iter().any(|arg| arg == "--chaos") {
+            run_args.push("--chaos".to_owned());
+        }
+        run_args.extend(self.run_args.iter().cloned());
+        let mut opts = AnalyzeOpts::new(run_args)?;
+        let frameworks = match self.framework {
+            Some(framework) => vec![framework],
+            None => TestFramework::ALL.to_vec(),
+        };
+        opts.target_stdout = Some(TestFramework::failure_regex(&frameworks, Some(&self.test)));
+        opts.search = true;
+        opts.search_limit = Some(self.max_runs);
+        opts.minimize = true;
+        opts.analyze_seed = self.analyze_seed;
+        Ok(opts)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flake_hunt_criteria() {
+        let args = ["flake-hunt", "--test=queue::push", "--", "cargo", "test"];
+        let opts = FlakeHuntOpts::try_parse_from(args).unwrap();
+        let analyze = opts.analyze_opts().unwrap();
+        assert_eq!(analyze.run_args, vec!["--chaos", "cargo", "test"]);
+        assert_eq!(analyze.search_limit, Some(100));
+        let target = analyze.target_stdout.unwrap();
+        assert!(target.is_match("test queue::push ... FAILED\n"));
+        assert!(!target.is_match("test queue::push ... ok\n"));
+    }
+}
@@ -0,0 +1,208 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
+
+use clap::Parser;
+use colored::Colorize;
+use reverie::process::ExitStatus;
+
+use super::bind::Bind;
+use super::global_opts::GlobalOpts;
+use super::run::RunOpts;
+use crate::Context;
+use crate::Error;
+
+/// The runs log to their own files, not to wherever `hermit fuzz` itself logs.
+const NO_LOGGING_PLZ: GlobalOpts = GlobalOpts {
+    log: None,
+    log_file: None,
+    otlp_endpoint: None,
+};
+
+/// Run a program under many chaos seeds, and report which of them make it fail.
+///
+/// The stdout, stderr, log, and recorded preemptions of each failing seed are kept in the
+/// artifacts directory. The smallest failing seed is printed along with the `hermit analyze`
+/// command that finds the race behind it.
+#[derive(Debug, Parser)]
+pub struct FuzzOpts {
+    /// How many seeds to try.
+    #[clap(long, value_name = "N", default_value = "100")]
+    seeds: u64,
+
+    /// The first seed to try. The others follow it in order.
+    #[clap(long, value_name = "SEED", default_value = "0")]
+    first_seed: u64,
+
+    /// Make up to this many runs at the same time.
+    #[clap(long, value_name = "J", default_value = "1")]
+    jobs: usize,
+
+    /// Where to keep the artifacts of the failing seeds.
+    ///
+    /// By default this is a new directory in `/tmp`.
+    #[clap(long, value_name = "PATH")]
+    artifacts_dir: Option<PathBuf>,
+
+    /// A full set of CLI arguments for `hermit run`, to which `--chaos` and each seed are added.
+    #[clap(value_name = "ARGS")]
+    run_args: Vec<String>,
+}
+
+impl FuzzOpts {
+    pub fn main(&self, _global: &GlobalOpts) -> Result<ExitStatus, Error> {
+        if self.seeds == 0 {
+            return Err(Error::msg("--seeds needs at least one seed to try"));
+        }
+        let artifacts = match &self.artifacts_dir {
+            Some(dir) => {
+                fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create {}", dir.display()))?;
+                dir.clone()
+            }
+            None => tempfile::Builder::new()
+                .prefix("hermit_fuzz")
+                .tempdir()?
+                .into_path(),
+        };
+        eprintln!(":: Artifacts directory: {}", artifacts.display());
+
+        let seeds: Vec<u64> = (self.first_seed..).take(self.seeds as usize).collect();
+        let jobs = self.jobs.clamp(1, seeds.len());
+        let mut passed = Vec::new();
+        let mut failed = Vec::new();
+        for batch in seeds.chunks(jobs) {
+            let results: Vec<_> = std::thread::scope(|scope| {
+                let handles: Vec<_> = batch
+                    .iter()
+                    .map(|&seed| scope.spawn(move || self.run_seed(seed, &artifacts)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().expect("fuzz run panicked"))
+                    .collect()
+            });
+            for (&seed, result) in batch.iter().zip(results) {
+                if result? {
+                    passed.push(seed);
+                } else {
+                    failed.push(seed);
+                }
+            }
+        }
+
+        eprintln!(
+            ":: {} passed, {} failed, out of {} seeds.",
+            passed.len(),
+            failed.len(),
+            seeds.len()
+        );
+        match failed.first() {
+            None => {
+                eprintln!(":: {}", "Success: no seed failed.".green().bold());
+                if self.artifacts_dir.is_none() {
+                    fs::remove_dir_all(&artifacts)?;
+                }
+                Ok(ExitStatus::SUCCESS)
+            }
+            Some(&smallest) => {
+                let failed: Vec<String> = failed.iter().map(u64::to_string).collect();
+                eprintln!(":: {} {}", "Failing seeds:".red().bold(), failed.join(", "));
+                eprintln!(
+                    ":: Smallest failing seed: {}, artifacts in {}",
+                    smallest,
+                    artifacts.display()
+                );
+                eprintln!(":: To find the race behind it:");
+                let baseline = passed.first().copied();
+                println!("{}", self.analyze_command(smallest, baseline));
+                Ok(ExitStatus::Exited(1))
+            }
+        }
+    }
+
+    /// The `hermit run` options for one seed, with its artifacts at `root` plus an extension.
+    fn runopts(&self, seed: u64, root: &Path, artifacts: &Path) -> Result<RunOpts, Error> {
+        // Bogus arg 0 for CLI argument parsing:
+        let args = std::iter::once("hermit-run").chain(self.run_args.iter().map(String::as_str));
+        let mut ro = RunOpts::from_iter(args);
+        let config = &mut ro.det_opts.det_config;
+        config.chaos = true;
+        config.seed = seed;
+        config.record_preemptions_to = Some(root.with_extension("preempts"));
+        // The runs have their own `/tmp`, so the artifacts must be bound into it.
+        ro.bind.push(Bind::same(artifacts));
+        ro.validate_args()?;
+        Ok(ro)
+    }
+
+    /// Run the program under one seed, and report whether it passed.  The artifacts of a failing
+    /// run are kept.
+    fn run_seed(&self, seed: u64, artifacts: &Path) -> Result<bool, Error> {
+        let root = artifacts.join(format!("seed{}", seed));
+        let extensions = ["log", "stdout", "stderr", "preempts"];
+        let ro = self.runopts(seed, &root, artifacts)?;
+        let log_file = File::create(root.with_extension("log"))?;
+        let out = ro.run_verify(log_file, &NO_LOGGING_PLZ)?;
+
+        if out.status.success() {
+            eprintln!(":: Seed {}: {}", seed, "passed".green());
+            for ext in extensions {
+                let _ = fs::remove_file(root.with_extension(ext));
+            }
+            Ok(true)
+        } else {
+            let failed = "failed".red().bold();
+            eprintln!(":: Seed {}: {} ({:?})", seed, failed, out.status);
+            fs::write(root.with_extension("stdout"), &out.stdout)?;
+            fs::write(root.with_extension("stderr"), &out.stderr)?;
+            Ok(false)
+        }
+    }
+
+    /// The `hermit analyze` command which looks for the race that makes `failing` fail, using
+    /// `passing`, if any seed passed, as the baseline.
+    fn analyze_command(&self, failing: u64, passing: Option<u64>) -> String {
+        let mut cmd = format!("hermit analyze --run1-seed={}", failing);
+        if let Some(seed) = passing {
+            cmd.push_str(&format!(" --run2-seed={}", seed));
+        }
+        cmd.push_str(" --");
+        if !self.run_args.iter().any(|arg| arg == "--chaos") {
+            cmd.push_str(" --chaos");
+        }
+        for arg in &self.run_args {
+            cmd.push(' ');
+            cmd.push_str(&shell_words::quote(arg));
+        }
+        cmd
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fuzz_analyze_command() {
+        let opts = FuzzOpts::try_parse_from(["fuzz", "--seeds=20", "--", "./test", "a b"]).unwrap();
+        assert_eq!(opts.seeds, 20);
+        assert_eq!(
+            opts.analyze_command(3, Some(0)),
+            "hermit analyze --run1-seed=3 --run2-seed=0 -- --chaos ./test 'a b'"
+        );
+        let ro = opts.runopts(3, Path::new("/tmp/fuzz/seed3"), Path::new("/tmp/fuzz"));
+        assert!(ro.det_opts.det_config.chaos);
+        assert_eq!(ro.det_opts.det_config.seed, 3);
+        assert!(ro.det_opts.det_config.record_preemptions);
+    }
+}
@@ -13,7 +13,10 @@ use clap::Parser;
 use tracing::metadata::LevelFilter;
 
 use super::tracing::init_file_tracing;
+use super::tracing::init_span_export;
 use super::tracing::init_stderr_tracing;
+use super::tracing::OtlpExporter;
+use super::tracing::TracingGuard;
 
 /// Hermit provides a sandbox for deterministic and reproducible execution.
 /// Arbitrary programs run inside (guests) become deterministic
@@ -46,22 +49,46 @@ pub struct GlobalOpts {
     )]
     pub log: Option<LevelFilter>,
 
-    /// Log to a file instead of the terminal.
+    /// Log to a file instead of the terminal. If its name ends in `.zst`, it is compressed with
+    /// zstd, which `hermit log-diff` reads as is.
     #[clap(long, value_name = "FILE", env = "HERMIT_LOG_FILE", parse(from_os_str))]
     pub log_file: Option<PathBuf>,
+
+    /// Export spans to the OTLP gRPC endpoint of an OpenTelemetry collector, e.g.
+    /// `http://localhost:4317`: of each phase of `hermit analyze` and each run it launches, and of
+    /// each batch of the scheduler's decisions in a run. Needs hermit built with the `otlp`
+    /// feature.
+    #[clap(long, value_name = "URL", env = "HERMIT_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
 }
 
 impl GlobalOpts {
     /// Initalizes tracing. If using a container, this must be done *inside* of
     /// the container because the tracer may create a new thread.
-    #[must_use = "This function returns a guard that should not be immediately dropped"]
-    pub fn init_tracing(&self) -> Option<impl Drop> {
+    pub fn init_tracing(&self) -> TracingGuard {
+        let otlp = self.start_otlp();
         if let Some(path) = &self.log_file {
             let file_writer = File::create(path).expect("Failed to open log file");
-            Some(init_file_tracing(self.log, file_writer))
+            if path.extension().map_or(false, |ext| ext == "zst") {
+                let encoder = zstd::Encoder::new(file_writer, 0).expect("Failed to start zstd");
+                init_file_tracing(self.log, encoder.auto_finish(), otlp)
+            } else {
+                init_file_tracing(self.log, file_writer, otlp)
+            }
         } else {
-            init_stderr_tracing(self.log);
-            None
+            init_stderr_tracing(self.log, otlp)
         }
     }
+
+    /// Starts exporting spans, if there is an `--otlp-endpoint`.
+    pub fn start_otlp(&self) -> Option<OtlpExporter> {
+        let endpoint = self.otlp_endpoint.as_ref()?;
+        Some(OtlpExporter::start(endpoint).expect("Failed to start the OTLP exporter"))
+    }
+
+    /// Exports the spans of the current thread, if there is an `--otlp-endpoint`, for commands
+    /// which launch runs rather than tracing themselves.
+    pub fn init_span_export(&self) -> Option<TracingGuard> {
+        self.start_otlp().map(init_span_export)
+    }
 }
@@ -0,0 +1,366 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! The happens-before relation among the events of a schedule, around the critical events which
+//! `hermit analyze` finds, to show which synchronization is missing between them.
+//!
+//! Schedules record which operation each thread performed, but not on which futex word or file
+//! descriptor, so the synchronization is inferred from blocking: when a thread's call completes,
+//! the last operation by another thread while it was in the call that could have released it is
+//! taken to have done so. The events of each thread are ordered as they ran, and a new thread
+//! starts after the last spawn by another thread.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use detcore::types::CondvarOp;
+use detcore::types::Op;
+use detcore::types::SchedEvent;
+use detcore::types::SocketOp;
+use detcore::types::SyscallPhase;
+use detcore::DetTid;
+use reverie::syscalls::Sysno;
+use serde::Serialize;
+
+use crate::Context;
+use crate::Error;
+
+/// How many events either side of the critical events are in the graph.
+pub const WINDOW: usize = 20;
+
+/// What orders one event before another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EdgeKind {
+    /// Both are events of the same thread.
+    ProgramOrder,
+    /// The first spawned the thread of the second.
+    Spawn,
+    /// A futex wake, or a condition variable signal, released a waiter.
+    Futex,
+    /// A write to a pipe, or a file, released a blocked read.
+    Pipe,
+    /// A send, or a connection, released a blocked receive, or accept.
+    Socket,
+    /// The exit of a thread released a wait for it.
+    Exit,
+}
+
+impl fmt::Display for EdgeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EdgeKind::ProgramOrder => write!(f, "program order"),
+            EdgeKind::Spawn => write!(f, "spawn"),
+            EdgeKind::Futex => write!(f, "futex"),
+            EdgeKind::Pipe => write!(f, "pipe"),
+            EdgeKind::Socket => write!(f, "socket"),
+            EdgeKind::Exit => write!(f, "exit"),
+        }
+    }
+}
+
+/// An event of the schedule.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Node {
+    /// Its index in the schedule.
+    pub index: usize,
+    /// The thread which performed it.
+    pub dettid: DetTid,
+    /// What it did.
+    pub op: Op,
+    /// How many times in a row.
+    pub count: u32,
+}
+
+/// The event at index `from` happens before the one at index `to`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Edge {
+    /// The index of the earlier event.
+    pub from: usize,
+    /// The index of the later event.
+    pub to: usize,
+    /// What orders them.
+    pub kind: EdgeKind,
+}
+
+/// The happens-before graph of a window of a schedule.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HbGraph {
+    /// The indices of the critical events.
+    pub critical: (usize, usize),
+    /// Whether the edges order the critical events, in either direction. If not, they race.
+    pub ordered: bool,
+    /// The events in the window, in schedule order.
+    pub nodes: Vec<Node>,
+    /// The edges among them, by the index of the event they lead to.
+    pub edges: Vec<Edge>,
+}
+
+/// The kind of release a call blocks on, if it can block.
+fn waits_for(op: &Op) -> Option<EdgeKind> {
+    match op {
+        Op::Syscall(Sysno::futex, _) | Op::Condvar(CondvarOp::Wait, _) => Some(EdgeKind::Futex),
+        Op::Syscall(Sysno::read | Sysno::readv | Sysno::pread64 | Sysno::preadv, _) => {
+            Some(EdgeKind::Pipe)
+        }
+        Op::Syscall(Sysno::recvfrom | Sysno::recvmsg | Sysno::accept | Sysno::accept4, _)
+        | Op::Socket(SocketOp::Recv | SocketOp::Accept, _) => Some(EdgeKind::Socket),
+        Op::Syscall(Sysno::wait4 | Sysno::waitid, _) => Some(EdgeKind::Exit),
+        _ => None,
+    }
+}
+
+/// The kinds of waits a call can release.
+fn releases(op: &Op) -> &'static [EdgeKind] {
+    match op {
+        Op::Syscall(Sysno::futex, _) | Op::Condvar(CondvarOp::Signal | CondvarOp::Broadcast, _) => {
+            &[EdgeKind::Futex]
+        }
+        Op::Syscall(Sysno::write | Sysno::writev | Sysno::pwrite64 | Sysno::close, _) => {
+            &[EdgeKind::Pipe]
+        }
+        Op::Syscall(Sysno::sendto | Sysno::sendmsg | Sysno::connect, _)
+        | Op::Socket(SocketOp::Send | SocketOp::Connect, _) => &[EdgeKind::Socket],
+        // A thread which exits wakes those joining it, through the futex the kernel clears.
+        Op::Syscall(Sysno::exit | Sysno::exit_group, _) => &[EdgeKind::Exit, EdgeKind::Futex],
+        _ => &[],
+    }
+}
+
+fn is_spawn(op: &Op) -> bool {
+    matches!(
+        op,
+        Op::Syscall(
+            Sysno::clone | Sysno::clone3 | Sysno::fork | Sysno::vfork,
+            SyscallPhase::Prehook
+        )
+    )
+}
+
+fn phase(op: &Op) -> Option<SyscallPhase> {
+    match op {
+        Op::Syscall(_, phase) | Op::Condvar(_, phase) | Op::Socket(_, phase) => Some(*phase),
+        Op::Branch | Op::Rdtsc | Op::Cpuid | Op::OtherInstructions => None,
+    }
+}
+
+impl HbGraph {
+    /// The graph of the events of `schedule` within `radius` of the `critical` pair.
+    pub fn new(schedule: &[SchedEvent], critical: (usize, usize), radius: usize) -> Self {
+        let start = critical.0.saturating_sub(radius);
+        let end = schedule.len().min(critical.1 + radius + 1);
+
+        let mut edges = Vec::new();
+        let mut last_of_thread: HashMap<DetTid, usize> = HashMap::new();
+        // The call each thread is in, and the index of its prehook.
+        let mut waiting: HashMap<DetTid, (EdgeKind, usize)> = HashMap::new();
+        let mut released: Vec<(usize, DetTid, EdgeKind)> = Vec::new();
+        let mut spawns: Vec<(usize, DetTid)> = Vec::new();
+        // The whole prefix is walked, so that edges into the window from calls made before it
+        // are found, but only the edges within the window are kept.
+        for (i, ev) in schedule.iter().enumerate().take(end) {
+            let tid = ev.dettid;
+            match last_of_thread.insert(tid, i) {
+                Some(prev) => edges.push(Edge {
+                    from: prev,
+                    to: i,
+                    kind: EdgeKind::ProgramOrder,
+                }),
+                None => {
+                    if let Some(&(spawn, _)) = spawns.iter().rev().find(|(_, t)| *t != tid) {
+                        edges.push(Edge {
+                            from: spawn,
+                            to: i,
+                            kind: EdgeKind::Spawn,
+                        });
+                    }
+                }
+            }
+            if is_spawn(&ev.op) {
+                spawns.push((i, tid));
+            }
+            match (phase(&ev.op), waits_for(&ev.op)) {
+                (Some(SyscallPhase::Prehook), Some(kind)) => {
+                    waiting.insert(tid, (kind, i));
+                }
+                (Some(SyscallPhase::Posthook | SyscallPhase::TimedOut), _) => {
+                    if let Some((kind, since)) = waiting.remove(&tid) {
+                        let waker = released
+                            .iter()
+                            .rev()
+                            .take_while(|(j, _, _)| *j > since)
+                            .find(|(_, t, k)| *t != tid && *k == kind);
+                        if let Some(&(from, _, kind)) = waker {
+                            edges.push(Edge { from, to: i, kind });
+                        }
+                    }
+                }
+                _ => {}
+            }
+            if phase(&ev.op) == Some(SyscallPhase::Prehook) {
+                for kind in releases(&ev.op) {
+                    released.push((i, tid, *kind));
+                }
+            }
+        }
+        edges.retain(|edge| edge.from >= start && edge.to < end);
+
+        let nodes = (start..end)
+            .map(|index| Node {
+                index,
+                dettid: schedule[index].dettid,
+                op: schedule[index].op,
+                count: schedule[index].count,
+            })
+            .collect();
+        let mut graph = HbGraph {
+            critical,
+            ordered: false,
+            nodes,
+            edges,
+        };
+        graph.ordered =
+            graph.reaches(critical.0, critical.1) || graph.reaches(critical.1, critical.0);
+        graph
+    }
+
+    /// Whether a path of edges leads from event `from` to event `to`.
+    fn reaches(&self, from: usize, to: usize) -> bool {
+        // Edges only lead forward in the schedule.
+        let mut reached = HashSet::new();
+        reached.insert(from);
+        let mut edges: Vec<&Edge> = self.edges.iter().collect();
+        edges.sort_by_key(|edge| edge.from);
+        for edge in edges {
+            if reached.contains(&edge.from) {
+                reached.insert(edge.to);
+            }
+        }
+        reached.contains(&to)
+    }
+
+    /// Says whether the critical events are ordered.
+    pub fn summary(&self) -> String {
+        let (a, b) = self.critical;
+        if self.ordered {
+            format!("Events {} and {} are ordered by synchronization.", a, b)
+        } else {
+            format!(
+                "No synchronization orders events {} and {}: an edge between them is missing.",
+                a, b
+            )
+        }
+    }
+
+    /// The graph in the Graphviz DOT language, with a cluster for each thread, and the critical
+    /// events in red.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph happens_before {\n");
+        let mut threads: Vec<DetTid> = self.nodes.iter().map(|node| node.dettid).collect();
+        threads.sort();
+        threads.dedup();
+        for tid in threads {
+            writeln!(out, "  subgraph cluster_{} {{", tid).unwrap();
+            writeln!(out, "    label=\"thread {}\";", tid).unwrap();
+            for node in self.nodes.iter().filter(|node| node.dettid == tid) {
+                let mut label = format!("#{} {:?}", node.index, node.op);
+                if node.count != 1 {
+                    write!(label, " x{}", node.count).unwrap();
+                }
+                let style = if node.index == self.critical.0 || node.index == self.critical.1 {
+                    ", style=filled, fillcolor=red"
+                } else {
+                    ""
+                };
+                writeln!(
+                    out,
+                    "    e{} [shape=box, label={:?}{}];",
+                    node.index, label, style
+                )
+                .unwrap();
+            }
+            writeln!(out, "  }}").unwrap();
+        }
+        for edge in &self.edges {
+            match edge.kind {
+                EdgeKind::ProgramOrder => {
+                    writeln!(out, "  e{} -> e{} [color=gray];", edge.from, edge.to).unwrap()
+                }
+                kind => writeln!(
+                    out,
+                    "  e{} -> e{} [penwidth=2, label=\"{}\"];",
+                    edge.from, edge.to, kind
+                )
+                .unwrap(),
+            }
+        }
+        if !self.ordered {
+            writeln!(
+                out,
+                "  e{} -> e{} [style=dotted, color=red, constraint=false, label=\"missing\"];",
+                self.critical.0, self.critical.1
+            )
+            .unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Write the graph to `path`: as JSON if its extension is `json`, and in the DOT language
+    /// otherwise.
+    pub fn write_to(&self, path: &Path) -> Result<(), Error> {
+        let contents = if path.extension().map_or(false, |ext| ext == "json") {
+            serde_json::to_string_pretty(self)?
+        } else {
+            self.to_dot()
+        };
+        fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infers_synchronization_around_critical_events() {
+        let (t3, t4) = (DetTid::from_raw(3), DetTid::from_raw(4));
+        let call = |tid, sysno, phase| SchedEvent::syscall(tid, sysno, phase);
+        let schedule = vec![
+            call(t3, Sysno::clone, SyscallPhase::Prehook),
+            call(t3, Sysno::clone, SyscallPhase::Posthook),
+            SchedEvent::branches(t4, 5),
+            call(t3, Sysno::read, SyscallPhase::Prehook),
+            call(t4, Sysno::write, SyscallPhase::Prehook),
+            call(t3, Sysno::read, SyscallPhase::Posthook),
+            SchedEvent::branches(t4, 2),
+            SchedEvent::branches(t3, 2),
+        ];
+
+        let graph = HbGraph::new(&schedule, (4, 5), 10);
+        let sync: Vec<(usize, usize, EdgeKind)> = graph
+            .edges
+            .iter()
+            .filter(|edge| edge.kind != EdgeKind::ProgramOrder)
+            .map(|edge| (edge.from, edge.to, edge.kind))
+            .collect();
+        assert_eq!(sync, vec![(0, 2, EdgeKind::Spawn), (4, 5, EdgeKind::Pipe)]);
+        assert!(graph.ordered);
+        assert_eq!(graph.nodes.len(), schedule.len());
+
+        // The branches after the read are not ordered with those of the other thread.
+        let graph = HbGraph::new(&schedule, (6, 7), 1);
+        assert!(!graph.ordered);
+        assert_eq!(graph.nodes.first().unwrap().index, 5);
+        assert!(graph.to_dot().contains("e6 -> e7 [style=dotted"));
+    }
+}
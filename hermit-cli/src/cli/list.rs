@@ -10,11 +10,11 @@ use std::path::PathBuf;
 
 use clap::Parser;
 use colored::Colorize;
-use hermit::Error;
-use hermit::HermitData;
 use reverie::ExitStatus;
 
 use super::global_opts::GlobalOpts;
+use crate::Error;
+use crate::HermitData;
 
 /// Command-line options for the "list" subcommand.
 #[derive(Debug, Parser)]
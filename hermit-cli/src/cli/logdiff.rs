@@ -0,0 +1,81 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+
+use clap::Parser;
+use detcore::logdiff;
+use reverie::process::ExitStatus;
+
+use super::global_opts::GlobalOpts;
+
+/// Command-line options for the "logdiff" subcommand.
+#[derive(Debug, Parser)]
+pub struct LogDiffCLIOpts {
+    /// First log to compare.
+    file_a: PathBuf,
+    /// Second log to compare.
+    file_b: PathBuf,
+    /// More logs to compare, with `--many`.
+    #[clap(requires = "many")]
+    rest: Vec<PathBuf>,
+
+    /// Compare any number of logs, such as those of one test under many seeds, reporting which
+    /// logs agree with the most others, and where each of the rest first differs from them.
+    #[clap(long)]
+    many: bool,
+
+    #[clap(flatten)]
+    pub more: logdiff::LogDiffOpts,
+}
+
+impl LogDiffCLIOpts {
+    /// Construct LogDiffOpts to compare two files.
+    pub fn new(a: &Path, b: &Path) -> Self {
+        Self {
+            file_a: PathBuf::from(a),
+            file_b: PathBuf::from(b),
+            rest: Vec::new(),
+            many: false,
+            more: Default::default(),
+        }
+    }
+
+    /// Process log messages from two files.
+    pub fn main(&self, _global: &GlobalOpts) -> ExitStatus {
+        let differs = if self.many {
+            let mut files = vec![self.file_a.clone(), self.file_b.clone()];
+            files.extend(self.rest.iter().cloned());
+            logdiff::log_diff_many(&files, &self.more)
+        } else {
+            logdiff::log_diff(&self.file_a, &self.file_b, &self.more)
+        };
+        if differs {
+            ExitStatus::Exited(1)
+        } else {
+            ExitStatus::Exited(0)
+        }
+    }
+}
+
+/// The arguments of a `hermit log-diff` command comparing the same logs, with the options which
+/// are used by analyze.
+impl fmt::Display for LogDiffCLIOpts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.more.ignore_lines {
+            write!(f, "--ignore-lines={} ", shell_words::quote(line))?;
+        }
+        if let Some(rules) = &self.more.rules {
+            let s = rules.to_str().expect("valid unicode path");
+            write!(f, "--rules={} ", shell_words::quote(s))?;
+        }
+        write!(f, "{} {}", self.file_a.display(), self.file_b.display())
+    }
+}
@@ -0,0 +1,39 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! The subcommands of the `hermit` command line.  They live in the library, rather than in the
+//! binary, so that [`crate::Analyzer`] can drive an analysis in the calling process.
+
+pub mod analyze;
+pub mod bench;
+pub mod bind;
+pub mod bnz;
+pub mod clean;
+pub mod container;
+pub mod convert_schedule;
+pub mod cow;
+pub mod flake_hunt;
+pub mod fuzz;
+pub mod global_opts;
+pub mod hb_graph;
+pub mod list;
+pub mod logdiff;
+pub mod record;
+pub mod remove;
+pub mod replay;
+pub mod rootfs;
+pub mod run;
+pub mod sched;
+pub mod sched_diff;
+pub mod schedule_search;
+pub mod serve;
+pub mod test_output;
+pub mod tracing;
+pub mod verify;
+pub mod version;
+pub mod zygote;
@@ -10,11 +10,6 @@ use std::path::PathBuf;
 
 use clap::Parser;
 use colored::Colorize;
-use hermit::Context;
-use hermit::Error;
-use hermit::HermitData;
-use hermit::SerializableError;
-use hermit::Shebang;
 use reverie::process::Command;
 use reverie::process::ExitStatus;
 
@@ -22,6 +17,11 @@ use super::container::default_container;
 use super::global_opts::GlobalOpts;
 use super::verify::compare_two_runs;
 use super::verify::setup_double_run;
+use crate::Context;
+use crate::Error;
+use crate::HermitData;
+use crate::SerializableError;
+use crate::Shebang;
 /// Command-line options for the "record" subcommand.
 #[derive(Debug, Parser)]
 pub struct RecordOpts {
@@ -110,7 +110,7 @@ impl RecordOpts {
                 let mut command = Command::new(&self.program);
                 command.args(&self.args);
 
-                hermit::record_with_output(command, data_dir).map_err(SerializableError::from)
+                crate::record_with_output(command, data_dir).map_err(SerializableError::from)
             })
             .context("Container exited unexpectedly")??;
 
@@ -128,7 +128,7 @@ impl RecordOpts {
         let replay = container
             .run(|| {
                 let _guard = global2.init_tracing();
-                hermit::replay_with_output(data_dir).map_err(SerializableError::from)
+                crate::replay_with_output(data_dir).map_err(SerializableError::from)
             })
             .context("Container exited unexpectedly")??;
 
@@ -157,7 +157,7 @@ impl RecordOpts {
                 let mut command = Command::new(&self.program);
                 command.args(&self.args);
 
-                hermit::record_to(command, data_dir).map_err(SerializableError::from)
+                crate::record_to(command, data_dir).map_err(SerializableError::from)
             })
             .context("Container exited unexpectedly")??;
 
@@ -212,7 +212,7 @@ impl RecordOpts {
         let result = container
             .run(|| {
                 let _guard = global.init_tracing();
-                hermit::replay_with_gdbserver(data_dir, gdbserver_port)
+                crate::replay_with_gdbserver(data_dir, gdbserver_port)
                     .map_err(SerializableError::from)
             })
             .context("Container exited unexpectedly")??;
@@ -9,13 +9,13 @@
 use std::path::PathBuf;
 
 use clap::Parser;
-use hermit::Context;
-use hermit::Error;
-use hermit::HermitData;
-use hermit::Id;
 use reverie::ExitStatus;
 
 use super::global_opts::GlobalOpts;
+use crate::Context;
+use crate::Error;
+use crate::HermitData;
+use crate::Id;
 
 /// Subcommand for removing a previous recording.
 #[derive(Debug, Parser)]
@@ -9,16 +9,16 @@
 use std::path::PathBuf;
 
 use clap::Parser;
-use hermit::Context;
-use hermit::Error;
-use hermit::HermitData;
-use hermit::Id;
-use hermit::Shebang;
 use reverie::process::ExitStatus;
 
 use super::container::default_container;
 use super::container::with_container;
 use super::global_opts::GlobalOpts;
+use crate::Context;
+use crate::Error;
+use crate::HermitData;
+use crate::Id;
+use crate::Shebang;
 
 /// Command-line options for the "replay" subcommand.
 #[derive(Debug, Parser)]
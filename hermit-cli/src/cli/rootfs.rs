@@ -0,0 +1,93 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! A directory tree that the guest sees as its root filesystem, so that it runs
+//! against the libraries and tools in that tree rather than the host's.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use reverie::process::Mount;
+
+/// Host filesystems that are passed through to the guest, since they are
+/// mounted on top of the root filesystem rather than being part of it.
+const PASSTHROUGH: &[&str] = &["/dev", "/proc", "/sys"];
+
+/// Directories that must exist in the root for the container to mount over.
+const MOUNT_POINTS: &[&str] = &["/dev", "/proc", "/sys", "/tmp", "/etc"];
+
+/// The directory the guest is chrooted to.
+pub struct RootFs {
+    root: PathBuf,
+}
+
+impl RootFs {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Creates any missing mount points in the root. Does nothing to an
+    /// existing tree beyond adding empty directories.
+    pub fn prepare(&self) -> io::Result<()> {
+        for path in MOUNT_POINTS {
+            fs::create_dir_all(self.rebase_path(Path::new(path)))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the mounts that pass the host's special filesystems through to
+    /// the root. These must come before any mounts made with
+    /// [`RootFs::rebase`].
+    pub fn mounts(&self) -> Vec<Mount> {
+        PASSTHROUGH
+            .iter()
+            .map(|path| Mount::bind(path, self.rebase_path(Path::new(path))).recursive())
+            .collect()
+    }
+
+    /// Moves a mount meant for the guest's `/` into the root.
+    pub fn rebase(&self, mount: Mount) -> Mount {
+        let target = self.rebase_path(mount.get_target());
+        mount.target(target)
+    }
+
+    fn rebase_path(&self, path: &Path) -> PathBuf {
+        self.root.join(path.strip_prefix("/").unwrap_or(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prepare_adds_mount_points() {
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("usr/lib")).unwrap();
+        fs::write(dir.path().join("usr/lib/libc.so.6"), "").unwrap();
+
+        let rootfs = RootFs::new(dir.path());
+        rootfs.prepare().unwrap();
+        rootfs.prepare().unwrap();
+
+        for path in MOUNT_POINTS {
+            assert!(rootfs.rebase_path(Path::new(path)).is_dir());
+        }
+        assert!(dir.path().join("usr/lib/libc.so.6").is_file());
+        assert_eq!(
+            rootfs.rebase_path(Path::new("/etc/hosts")),
+            dir.path().join("etc/hosts")
+        );
+    }
+}
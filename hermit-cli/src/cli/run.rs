@@ -0,0 +1,2131 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::fs;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::io::Read;
+use std::io::Write;
+use std::net::IpAddr;
+use std::num::NonZeroU64;
+use std::os::unix::io::FromRawFd;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use ::tracing::metadata::LevelFilter;
+use chrono::DateTime;
+use chrono::Utc;
+use clap::Parser;
+use colored::Colorize;
+use detcore::preemptions::PreemptionRecord;
+use detcore::types::RecordMetadata;
+use detcore::Backend;
+use detcore::BlockingMode;
+use detcore::CpuProfile;
+use detcore::DivergencePolicy;
+use detcore::IoUringMode;
+use detcore::NetRecording;
+use detcore::RdtscModel;
+use detcore::SchedHeuristic;
+use detcore::SpawnOrder;
+use detcore_model::config::DEFAULT_EPOCH_STR;
+use detcore_model::config::DEFAULT_HOSTNAME;
+use lazy_static::lazy_static;
+use nix::sys::signal::Signal;
+use rand::Rng;
+use reverie::process::Command;
+use reverie::process::Container;
+use reverie::process::ExitStatus;
+use reverie::process::Mount;
+use reverie::process::Namespace;
+use reverie::process::Output;
+use reverie::process::Stdio;
+
+use super::bind::Bind;
+use super::container::default_container;
+use super::container::with_container;
+use super::cow::CowRoot;
+use super::global_opts::GlobalOpts;
+use super::rootfs::RootFs;
+use super::tracing::init_file_tracing;
+use super::verify::compare_against_references;
+use super::verify::compare_many_runs;
+use super::verify::compare_outputs;
+use super::verify::compare_two_runs;
+use super::verify::temp_log_file;
+use super::verify::temp_log_files;
+use super::version::Version;
+use crate::Context;
+use crate::DetConfig;
+use crate::Error;
+
+const TMP_DIR: &str = "/tmp";
+
+// Just a place to put the clap(flatten) directive..
+#[derive(Debug, Parser, Clone)]
+pub(crate) struct DetOptions {
+    /// detcore configuration
+    #[clap(flatten)]
+    pub det_config: DetConfig,
+}
+
+/// Command-line options for the "run" subcommand.
+#[derive(Debug, Parser, Clone)]
+pub struct RunOpts {
+    /// Program to run. With `--script`, the shell to run the script's steps with.
+    #[clap(
+        value_name = "PROGRAM",
+        required_unless_present = "script",
+        default_value_if("script", None, Some("/bin/sh"))
+    )]
+    program: PathBuf,
+
+    /// Arguments for the program. With `--script`, the script's positional parameters.
+    #[clap(value_name = "ARGS")]
+    args: Vec<String>,
+
+    /// Runs each line of this file as a shell command, one after another in the same
+    /// container, so that the steps share one filesystem sandbox and one virtual clock. A
+    /// sequence of setup, test and teardown steps is then deterministic as a whole. Blank lines
+    /// and lines starting with `#` are skipped. The run stops at the first step that fails.
+    #[clap(long, value_name = "path")]
+    script: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub(crate) det_opts: DetOptions,
+
+    /// Enables strict mode. Currently this implies default mode plus
+    /// --sequentialize-threads, and --deterministic-io.
+    // #[clap(long, short)]
+    // strict: bool,
+
+    // Disables sequentialize threads. On by default
+    #[clap(long)]
+    pub(crate) no_sequentialize_threads: bool,
+
+    // Disables deterministic io. On by default
+    #[clap(long)]
+    no_deterministic_io: bool,
+
+    /// Pin all guest threads to one or more cores, so that they do not migrate
+    /// during execution. This is off by default, but it is implied by setting
+    /// `preemption_timeout` which requires stable RCB counters. RCB counters are
+    /// not maintained consistently when Linux migrates a thread between cores.
+    #[clap(long)]
+    pin_threads: bool,
+
+    /// Mount a file or directory. This uses the same syntax as the Docker
+    /// `--mount` option. For simple bind-mount cases, use `bind` instead.
+    #[clap(long, value_name = "path")]
+    mount: Vec<Mount>,
+
+    /// Bind-mounts the provided path to the same path inside of the container if
+    /// it is not already available. Also accepts `src:dst` to make `src` available
+    /// at `dst`, and `src:dst:ro` to make it read-only. A `src` of `tmpfs` mounts
+    /// an empty tmpfs instead, whose size can be limited with `tmpfs:dst:size=64m`.
+    #[clap(long, value_name = "path|src:dst[:opts]")]
+    pub(crate) bind: Vec<Bind>,
+
+    /// Disables external networking using a network namespace.
+    #[clap(long, alias = "no-net", alias = "disable-networking")]
+    no_networking: bool,
+
+    /// Runs the given program in "lite" mode. In this mode, a PID namespace is
+    /// created and `/tmp` is isolated. It is still possible to introduce
+    /// non-determinism through time and thread scheduling. Can be combined with
+    /// `--no-networking` to also disable networking.
+    #[clap(
+        long,
+        conflicts_with = "chaos",
+        conflicts_with = "verify",
+        conflicts_with = "verify-runs",
+        conflicts_with = "verify-against",
+        conflicts_with = "tool"
+        // conflicts_with = "strict"
+    )]
+    lite: bool,
+
+    /// Specifies the directory to use as `/tmp`. This path gets bind-mounted
+    /// over `/tmp` and the guest program does not see the real `/tmp` directory.
+    /// If this path does not exist, it is created.
+    ///
+    /// If this option is not specified, a temporary directory is created,
+    /// mounted over `/tmp`, and deleted when the guest has exited.
+    #[clap(long, value_name = "dirpath")]
+    tmp: Option<PathBuf>,
+
+    /// Runs the guest on a copy-on-write view of the host's filesystem, kept in
+    /// the given directory. The guest's writes land in an overlay there and the
+    /// host is left untouched. Each run starts from the host's filesystem as it
+    /// is, discarding the changes of the previous run. After the run, the paths
+    /// the guest created (A), modified (M) and deleted (D) are printed to stderr.
+    ///
+    /// Requires a kernel which allows overlayfs mounts in user namespaces (5.11+).
+    #[clap(long, value_name = "dirpath", conflicts_with = "lite")]
+    cow_root: Option<PathBuf>,
+
+    /// With `--cow-root`, write the list of changed paths to this file instead
+    /// of stderr.
+    #[clap(long, value_name = "path", requires = "cow-root")]
+    cow_report: Option<PathBuf>,
+
+    /// Runs the guest with the given directory as its root filesystem, so that
+    /// it uses the programs and libraries in that tree (e.g. an unpacked
+    /// container image) rather than the host's. The host's `/dev`, `/proc` and
+    /// `/sys` are passed through, and `--bind` and `--mount` targets are
+    /// relative to the new root. The guest starts in `/` unless `--workdir` is
+    /// given.
+    #[clap(long, value_name = "dirpath", conflicts_with_all = &["lite", "cow-root"])]
+    rootfs: Option<PathBuf>,
+
+    /// Exactly like "seed" but we generate a seed for you. This is useful if multiple
+    /// hermit runs execute in parallel and rand based collisions exist.  "Args" generates
+    /// the seed from the other arguments passed to hermit, "SystemRandom" uses system
+    /// randomness to generate a seed, and creates a log message recording it.
+    #[clap(long, value_name = "'Args'|'SystemRandom'")]
+    seed_from: Option<SeedFrom>,
+
+    /// After running, immediately run a SECOND time, and compare the two
+    /// executions. This will exit with an error if the guest process does OR if
+    /// the executions do not match. In order to match, they must have the same
+    /// observed output (e.g. stdout/stderr), and the same log of internal
+    /// scheduler steps.
+    ///
+    /// It's on the user to ensure that the command run is idempotent, and thus
+    /// that the first run will not have any side effects that affect the
+    /// execution of the second run.
+    #[clap(long)]
+    verify: bool,
+
+    /// If --verify is specified, indicates what guest exit status is required for
+    /// hermit to consider the verification successful.  Both runs must satisfy this criteria,
+    /// and hermit does not perform the second run if the first does not.
+    #[clap(long, value_name = "success|failure|both", default_value = "success")]
+    verify_allow: VerifyAllow,
+
+    /// Like `--verify`, but run the program N times, at least twice, and compare each run with
+    /// the first. This gives much more confidence in the determinism of a program which is only
+    /// occasionally nondeterministic. A summary of which runs disagree is printed at the end.
+    #[clap(long, value_name = "N", conflicts_with = "verify")]
+    verify_runs: Option<usize>,
+
+    /// With `--verify-runs`, make up to this many of the runs at the same time.
+    #[clap(long, value_name = "J", requires = "verify-runs")]
+    verify_jobs: Option<usize>,
+
+    /// Run the program once and check that it behaves exactly as a reference run did, perhaps
+    /// on another machine or at another time. The reference is either the log of that run, as
+    /// written with `--log=debug --log-file`, or its `--summary-json`, if the path ends in
+    /// `.json`. May be given more than once, to check against both.
+    #[clap(long, value_name = "path", conflicts_with_all = &["verify", "verify-runs"])]
+    verify_against: Vec<PathBuf>,
+
+    /// Run the program twice, first with `--backend=ptrace` and then with
+    /// `--backend=seccomp-fast`, and check that both runs have the same output
+    /// and exit status. This audits that letting the syscalls detcore does not
+    /// handle run natively does not change what the guest observes. The logs
+    /// are not compared, since the first run also logs the extra syscalls it
+    /// intercepts. In debug builds both runs intercept every syscall, so this
+    /// only means something in release builds.
+    #[clap(long, conflicts_with_all = &["verify", "verify-runs", "verify-against"])]
+    audit_seccomp: bool,
+
+    /// Replay a `--replay-preemptions-from` or `--replay-schedule-from` record even if it was
+    /// made by another version of hermit, with other scheduling settings, or for another command
+    /// or seed. Such a record is otherwise refused, as it is unlikely to replay faithfully.
+    #[clap(long)]
+    force: bool,
+
+    /// Load an instrumentation tool from this shared library, and run it alongside detcore, so
+    /// that it observes the guest as detcore determinizes it. The library is a `cdylib` which
+    /// exports its tool with `hermit::export_instrument!`, and is refused unless it was built
+    /// against a hermit with the same version of the instrument interface.
+    /// May be given more than once.
+    #[clap(long, value_name = "cdylib")]
+    tool: Vec<PathBuf>,
+
+    /// Print a summary of the process tree's execution to stderr before exiting.
+    #[clap(long, short = 'u')]
+    pub(crate) summary: bool,
+
+    /// Write a machine-readable summary of the run to this file, as JSON: the exit status,
+    /// seeds, a hash of the schedule, each thread's syscall and signal counts, and any
+    /// settings that left the run less than fully deterministic.
+    #[clap(long, value_name = "path")]
+    pub(crate) summary_json: Option<PathBuf>,
+
+    /// Containarize networking and warn for non-zero bindings. Implies
+    /// `--no-networking`.
+    #[clap(long)]
+    analyze_networking: bool,
+
+    /// Resolve a host name to the given address inside the container, e.g.
+    /// `--dns-host=example.com=93.184.216.34`.  May be given more than once.  Name lookups then
+    /// only consult these names (and the container's own), never the network.  With
+    /// `--record-network-to` or `--replay-network-from`, other names are looked up over TCP,
+    /// so that the resolver's responses are recorded and replayed along with everything else.
+    #[clap(long, parse(try_from_str = parse_dns_host), value_name = "name=addr")]
+    dns_host: Vec<(String, IpAddr)>,
+
+    /// The base environment that is presented to the guest. "Empty" is completely empty, and "Host"
+    /// allows through all the environment variables in hermit's own environment.
+    /// "Minimal" provides a minimal deterministic environment, setting only PATH, HOSTNAME, HOME,
+    /// TZ, and LANG.
+    #[clap(long, default_value = "host", value_name = "str", possible_values = &["empty", "minimal", "host"])]
+    base_env: BaseEnv,
+
+    /// Additionally append one or more environment variables to the container environment. If a
+    /// name is provided without a value, pass that variable through from the host.
+    #[clap(short = 'e', long, parse(try_from_str = parse_assignment), value_name="name[=val]")]
+    env: Vec<(String, String)>,
+
+    /// Read environment variables from a file, with one `name[=val]` per line, as for `--env`.
+    /// Blank lines and lines starting with `#` are ignored. `--env` takes precedence.
+    #[clap(long, value_name = "path")]
+    env_file: Vec<PathBuf>,
+
+    /// Pass through the host's environment variables whose names match this glob, in which `*`
+    /// matches any run of characters and `?` any one character, e.g. `--env-passthrough='LC_*'`.
+    /// Only useful with a `--base-env` other than "host".
+    #[clap(long, value_name = "glob")]
+    env_passthrough: Vec<String>,
+
+    /// Feed the guest's stdin from this file, rather than from hermit's own stdin. Unlike a
+    /// terminal or a pipe, a file always has all of its data ready, so how much each read returns
+    /// does not depend on when it happens. Without this, `--verify` and analyze runs get an empty
+    /// stdin.
+    #[clap(long, value_name = "path", conflicts_with = "record-stdin-to")]
+    stdin: Option<PathBuf>,
+
+    /// Save everything the guest is given on stdin to this file, to feed to later runs with
+    /// `--stdin`. This is how to repeat a run of an interactive program without retyping its input.
+    /// The later runs see the same bytes, but not necessarily split up across reads the same way.
+    #[clap(long, value_name = "path", conflicts_with = "verify")]
+    record_stdin_to: Option<PathBuf>,
+
+    /// An option to set current directory for the guest process.
+    /// Note that the directory is relative to the guest. i.e. all mounted directories will be respected (e.g /tmp)
+    #[clap(long, value_name = "path")]
+    workdir: Option<String>,
+}
+
+fn parse_assignment(src: &str) -> Result<(String, String), Error> {
+    lazy_static! {
+        static ref ENV_RE: regex::Regex =
+           // Here we are extremely permissive, allowing all charecters in the "Portable Character
+           // Set", ISO/IEC 6429:1992 standard:
+           regex::Regex::new("^([\x07-<>-~]+)=([\x07-~]*)$").unwrap();
+        static ref VAR_RE: regex::Regex =
+           regex::Regex::new("^([\x07-<>-~]+)$").unwrap();
+    }
+    if let Some(capture) = ENV_RE.captures(src) {
+        if let (Some(name), Some(value)) = (capture.get(1), capture.get(2)) {
+            Ok((name.as_str().to_owned(), value.as_str().to_owned()))
+        } else {
+            anyhow::bail!("unable to parse name=value from '{}'", src)
+        }
+    } else if VAR_RE.is_match(src) {
+        let var: String = src.to_owned();
+        if let Ok(value) = std::env::var(&var) {
+            Ok((var, value))
+        } else {
+            anyhow::bail!(
+                "Attempt to pass through env var {}, but it is not set in the host environment",
+                var
+            )
+        }
+    } else {
+        anyhow::bail!("unable to parse env var name or name=value from '{}'", src)
+    }
+}
+
+/// Turns the contents of a `--script` into a shell script which runs each step in turn, stopping
+/// at the first that fails.
+fn script_steps(contents: &str) -> String {
+    let mut script = String::from("set -e\n");
+    for step in contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    {
+        script.push_str(step);
+        script.push('\n');
+    }
+    script
+}
+
+/// Parses the contents of an `--env-file`.
+fn parse_env_file(contents: &str) -> Result<Vec<(String, String)>, Error> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(parse_assignment)
+        .collect()
+}
+
+/// Matches a whole name against a glob, in which `*` matches any run of characters, and `?` any
+/// one character.
+fn glob_match(glob: &str, name: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Where to resume after the last `*`, if matching fails: just past the `*` in the glob, and
+    // one character further along in the name than the last attempt.
+    let mut backtrack = None;
+    let (mut g, mut n) = (0, 0);
+    while n < name.len() {
+        match glob.get(g) {
+            Some('*') => {
+                backtrack = Some((g + 1, n + 1));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                g += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((bg, bn)) => {
+                    backtrack = Some((bg, bn + 1));
+                    g = bg;
+                    n = bn;
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+fn parse_dns_host(src: &str) -> Result<(String, IpAddr), Error> {
+    match src.split_once('=') {
+        Some((name, addr)) if !name.is_empty() => {
+            let addr = addr
+                .parse()
+                .with_context(|| format!("unable to parse address from '{}'", src))?;
+            Ok((name.to_owned(), addr))
+        }
+        _ => anyhow::bail!("unable to parse name=addr from '{}'", src),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Parser, Eq, PartialEq)]
+pub enum VerifyAllow {
+    Success,
+    Failure,
+    Both,
+}
+
+impl FromStr for VerifyAllow {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "success" => Ok(VerifyAllow::Success),
+            "failure" => Ok(VerifyAllow::Failure),
+            "both" => Ok(VerifyAllow::Both),
+            _ => Err(format!("Could not parse: {:?}", s)),
+        }
+    }
+}
+
+impl VerifyAllow {
+    fn satisfies(&self, status: ExitStatus) -> bool {
+        match self {
+            VerifyAllow::Success => status == ExitStatus::SUCCESS,
+            VerifyAllow::Failure => status != ExitStatus::SUCCESS,
+            VerifyAllow::Both => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum BaseEnv {
+    Empty,
+    Minimal,
+    Host,
+}
+
+impl FromStr for BaseEnv {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "empty" => Ok(BaseEnv::Empty),
+            "minimal" => Ok(BaseEnv::Minimal),
+            "host" => Ok(BaseEnv::Host),
+            _ => Err(format!(
+                "Expected Empty | Minimal | Host, could not parse: {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// Where to generate the random seed from.
+#[derive(Debug, Clone)]
+pub enum SeedFrom {
+    Args,
+    SystemRandom,
+}
+
+// Error boilerplate.
+#[derive(Debug, Clone)]
+pub struct ParseSeedFromError {
+    details: String,
+}
+
+impl fmt::Display for ParseSeedFromError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl std::error::Error for ParseSeedFromError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl FromStr for SeedFrom {
+    type Err = ParseSeedFromError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "args" => Ok(SeedFrom::Args),
+            "systemrandom" => Ok(SeedFrom::SystemRandom),
+            _ => Err(ParseSeedFromError {
+                details: format!("Expected Args | SystemRandom, could not parse: {:?}", s),
+            }),
+        }
+    }
+}
+
+/// Displays as a string which needs only to be prepended with "hermit " to be a runnable command.
+impl fmt::Display for RunOpts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.no_sequentialize_threads {
+            write!(f, " --no-sequentialize-threads")?;
+        }
+        if self.no_deterministic_io {
+            write!(f, " --no-deterministic-io")?;
+        }
+        if self.no_networking {
+            write!(f, " --no-networking")?;
+        }
+        if self.lite {
+            write!(f, " --lite")?;
+        }
+        if self.summary {
+            write!(f, " --summary")?;
+        }
+        if let Some(p) = &self.summary_json {
+            let s = p.to_str().expect("valid unicode path");
+            write!(f, " --summary-json={}", shell_words::quote(s))?;
+        }
+        if self.analyze_networking {
+            write!(f, " --analyze-networking")?;
+        }
+        for (name, addr) in &self.dns_host {
+            write!(f, " --dns-host={}={}", name, addr)?;
+        }
+        if self.verify {
+            write!(f, " --verify")?;
+        }
+        if let Some(n) = self.verify_runs {
+            write!(f, " --verify-runs={}", n)?;
+        }
+        if let Some(j) = self.verify_jobs {
+            write!(f, " --verify-jobs={}", j)?;
+        }
+        for p in &self.verify_against {
+            let s = p.to_str().expect("valid unicode path");
+            write!(f, " --verify-against={}", shell_words::quote(s))?;
+        }
+        if self.audit_seccomp {
+            write!(f, " --audit-seccomp")?;
+        }
+        if self.force {
+            write!(f, " --force")?;
+        }
+        for p in &self.tool {
+            let s = p.to_str().expect("valid unicode path");
+            write!(f, " --tool={}", shell_words::quote(s))?;
+        }
+        if let Some(p) = &self.tmp {
+            let s = p.to_str().expect("valid unicode path");
+            write!(f, " --tmp={}", shell_words::quote(s))?;
+        }
+        if let Some(p) = &self.cow_root {
+            let s = p.to_str().expect("valid unicode path");
+            write!(f, " --cow-root={}", shell_words::quote(s))?;
+        }
+        if let Some(p) = &self.cow_report {
+            let s = p.to_str().expect("valid unicode path");
+            write!(f, " --cow-report={}", shell_words::quote(s))?;
+        }
+        if let Some(p) = &self.rootfs {
+            let s = p.to_str().expect("valid unicode path");
+            write!(f, " --rootfs={}", shell_words::quote(s))?;
+        }
+        match &self.verify_allow {
+            VerifyAllow::Success => {} // default
+            VerifyAllow::Failure => {
+                write!(f, " --verify-allow=failure")?;
+            }
+            VerifyAllow::Both => {
+                write!(f, " --verify-allow=both")?;
+            }
+        }
+        match &self.base_env {
+            BaseEnv::Empty => {
+                write!(f, " --base-env=empty")?;
+            }
+            BaseEnv::Minimal => {
+                write!(f, " --base-env=minimal")?;
+            }
+            BaseEnv::Host => {} // default
+        }
+        for glob in &self.env_passthrough {
+            write!(f, " --env-passthrough={}", shell_words::quote(glob))?;
+        }
+        for p in &self.env_file {
+            let s = p.to_str().expect("valid unicode path");
+            write!(f, " --env-file={}", shell_words::quote(s))?;
+        }
+        for (key, val) in &self.env {
+            write!(f, " --env={}={}", key, val)?;
+        }
+        if let Some(p) = &self.workdir {
+            write!(f, " --workdir={}", p)?;
+        }
+        if let Some(p) = &self.stdin {
+            let s = p.to_str().expect("valid unicode path");
+            write!(f, " --stdin={}", shell_words::quote(s))?;
+        }
+        if let Some(p) = &self.record_stdin_to {
+            let s = p.to_str().expect("valid unicode path");
+            write!(f, " --record-stdin-to={}", shell_words::quote(s))?;
+        }
+        if let Some(p) = &self.script {
+            let s = p.to_str().expect("valid unicode path");
+            write!(f, " --script={}", shell_words::quote(s))?;
+        }
+        for mount in &self.mount {
+            let mut acc = Vec::new();
+            if let Some(s) = &mount.get_source() {
+                acc.push(format!("source={}", s.display()));
+            }
+            acc.push(format!("target={}", mount.get_target().display()));
+            write!(f, "--mount={}", shell_words::quote(&acc.join(",")),)?;
+        }
+        for bind in &self.bind {
+            write!(f, " --bind={}", bind)?;
+        }
+
+        let dop = &self.det_opts.det_config;
+        if !dop.virtualize_time {
+            write!(f, " --no-virtualize-time")?;
+        }
+        if !dop.virtualize_cpuid {
+            write!(f, " --no-virtualize-cpuid")?;
+        }
+        if let Some(profile) = &dop.cpu_profile {
+            write!(f, " --cpu-profile={}", shell_words::quote(profile))?;
+        }
+        if !dop.virtualize_metadata {
+            write!(f, " --no-virtualize-metadata")?;
+        }
+        if !dop.sort_dirents {
+            write!(f, " --no-sort-dirents")?;
+        }
+        if dop.normalize_stat {
+            write!(f, " --normalize-stat")?;
+        }
+        let default_epoch: DateTime<Utc> = DEFAULT_EPOCH_STR.parse::<DateTime<Utc>>().unwrap();
+        if dop.epoch != default_epoch {
+            write!(f, " --epoch={}", dop.epoch.to_rfc3339())?;
+        }
+        if dop.seed != 0 {
+            write!(f, " --seed={}", dop.seed)?;
+        }
+        if let Some(m) = dop.clock_multiplier {
+            write!(f, " --clock-multiplier={}", m)?;
+        }
+        match &dop.rdtsc_model {
+            RdtscModel::Fixed => {}
+            RdtscModel::Instruction => {
+                write!(f, " --rdtsc-model=instruction")?;
+            }
+            RdtscModel::Syscall => {
+                write!(f, " --rdtsc-model=syscall")?;
+            }
+        }
+        if dop.tsc_mhz != 1000 {
+            write!(f, " --tsc-mhz={}", dop.tsc_mhz)?;
+        }
+        if dop.tsc_increment != 1000 {
+            write!(f, " --tsc-increment={}", dop.tsc_increment)?;
+        }
+        if dop.imprecise_timers {
+            write!(f, " --imprecise-timers")?;
+        }
+        if dop.chaos {
+            write!(f, " --chaos")?;
+        }
+        if dop.record_preemptions {
+            write!(f, " --record-preemptions")?;
+        }
+        if let Some(p) = &dop.record_preemptions_to {
+            let s = p.to_str().expect("valid unicode path");
+            write!(f, " --record-preemptions-to={}", shell_words::quote(s))?;
+        }
+        if let Some(p) = &dop.replay_preemptions_from {
+            let s = p.to_str().expect("valid unicode path");
+            write!(f, " --replay-preemptions-from={}", shell_words::quote(s))?;
+        }
+        match (&dop.replay_prefix_then_chaos, &dop.replay_schedule_from) {
+            (Some(prefix), _) => {
+                // Replaying a prefix implies replaying its schedule.
+                let s = prefix.to_string();
+                write!(f, " --replay-prefix-then-chaos={}", shell_words::quote(&s))?;
+            }
+            (None, Some(p)) => {
+                let s = p.to_str().expect("valid unicode path");
+                write!(f, " --replay-schedule-from={}", shell_words::quote(s))?;
+            }
+            (None, None) => {}
+        }
+        if dop.replay_exhausted_panic {
+            write!(f, " --replay-exhausted-panic")?;
+        }
+        if dop.die_on_desync {
+            write!(f, " --die-on-desync")?;
+        }
+        if dop.fuzzy_replay {
+            write!(f, " --fuzzy-replay")?;
+        }
+        match &dop.on_divergence {
+            None => {}
+            Some(DivergencePolicy::Abort) => {
+                write!(f, " --on-divergence=abort")?;
+            }
+            Some(DivergencePolicy::Resync) => {
+                write!(f, " --on-divergence=resync")?;
+            }
+            Some(DivergencePolicy::Record) => {
+                write!(f, " --on-divergence=record")?;
+            }
+        }
+        for (index, path) in &dop.stacktrace_event {
+            write!(f, " --stacktrace-event={}", index)?;
+            if let Some(p) = path {
+                let s = p.to_str().expect("valid unicode path");
+                write!(f, ",{}", shell_words::quote(s))?;
+            }
+        }
+        if dop.preemption_stacktrace {
+            write!(f, " --preemption-stacktrace")?;
+        }
+        if let Some(p) = &dop.crash_report_dir {
+            let s = p.to_str().expect("valid unicode path");
+            write!(f, " --crash-report-dir={}", shell_words::quote(s))?;
+        }
+        if let Some(p) = &dop.emit_trace {
+            let s = p.to_str().expect("valid unicode path");
+            write!(f, " --emit-trace={}", shell_words::quote(s))?;
+        }
+        if dop.audit_syscalls {
+            write!(f, " --audit-syscalls")?;
+        }
+        if dop.deterministic_io {
+            write!(f, " --deterministic-io")?;
+        }
+        if dop.deterministic_mmap {
+            write!(f, " --deterministic-mmap")?;
+        }
+        if dop.io_uring == IoUringMode::Passthrough {
+            write!(f, " --io-uring=passthrough")?;
+        }
+        if dop.emulate_seccomp {
+            write!(f, " --emulate-seccomp")?;
+        }
+        match dop.backend {
+            Backend::Ptrace => write!(f, " --backend=ptrace")?,
+            Backend::SeccompFast => {}
+        }
+        if dop.panic_on_unsupported_syscalls {
+            write!(f, " --panic-on-unsupported-syscalls")?;
+        }
+        if dop.kill_daemons {
+            write!(f, " --kill-daemons")?;
+        }
+        match dop.gdbserver {
+            Some(Some(port)) => write!(f, " --gdbserver=:{}", port)?,
+            Some(None) => write!(f, " --gdbserver")?,
+            None => {}
+        }
+        if dop.gdbserver_port != /* default */ 1234u16 {
+            write!(f, " --gdbserver-port={}", dop.gdbserver_port)?;
+        }
+        match &dop.preemption_timeout {
+            Some(x) => {
+                if *x != NonZeroU64::new(200_000_000).unwrap() {
+                    write!(f, " --preemption-timeout={}", x)?;
+                }
+            }
+            None => {
+                write!(f, " --preemption-timeout=disabled")?;
+            }
+        }
+        if dop.virtual_quantum != /* default */ 500_000 {
+            write!(f, " --virtual-quantum={}", dop.virtual_quantum)?;
+        }
+        if let Some(res) = dop.timer_resolution {
+            write!(f, " --timer-resolution={}", res)?;
+        }
+        if dop.sigint_instakill {
+            write!(f, " --sigint-instakill")?;
+        }
+        if dop.warn_non_zero_binds {
+            write!(f, " --warn-non-zero-binds")?;
+        }
+        if dop.loopback_networking {
+            write!(f, " --loopback-networking")?;
+        }
+        if let Some(p) = &dop.record_network_to {
+            let s = p.to_str().expect("valid unicode path");
+            write!(f, " --record-network-to={}", shell_words::quote(s))?;
+        }
+        if let Some(p) = &dop.replay_network_from {
+            let s = p.to_str().expect("valid unicode path");
+            write!(f, " --replay-network-from={}", shell_words::quote(s))?;
+        }
+        match &dop.sched_heuristic {
+            SchedHeuristic::None => {}
+            SchedHeuristic::ConnectBind => {
+                write!(f, " --sched-heuristic=connectbind")?;
+            }
+            SchedHeuristic::Random => {
+                write!(f, " --sched-heuristic=random")?;
+            }
+            SchedHeuristic::StickyRandom => {
+                write!(f, " --sched-heuristic=stickyrandom")?;
+            }
+        }
+        if let Some(s) = dop.sched_seed {
+            write!(f, " --sched-seed={}", s)?;
+        }
+        if dop.sched_sticky_random_param != 0.0 {
+            write!(
+                f,
+                " --sched-sticky-random-param={}",
+                dop.sched_sticky_random_param
+            )?;
+        }
+        if dop.condvar_sched_points {
+            write!(f, " --condvar-sched-points")?;
+        }
+        if dop.lean_sched_points {
+            write!(f, " --lean-sched-points")?;
+        }
+        match &dop.spawn_order {
+            None => {}
+            Some(SpawnOrder::ChildFirst) => {
+                write!(f, " --spawn-order=childfirst")?;
+            }
+            Some(SpawnOrder::ParentFirst) => {
+                write!(f, " --spawn-order=parentfirst")?;
+            }
+            Some(SpawnOrder::Random) => {
+                write!(f, " --spawn-order=random")?;
+            }
+        }
+        if let Some(t) = dop.stop_after_turn {
+            write!(f, " --stop-after-turn={}", t)?;
+        }
+        if let Some(i) = dop.stop_after_iter {
+            write!(f, " --stop-after-iter={}", i)?;
+        }
+        if dop.debug_externalize_sockets {
+            write!(f, " --debug-externalize-sockets")?;
+        }
+        match &dop.debug_futex_mode {
+            BlockingMode::External => {
+                write!(f, " --debug-futex-mode=external")?;
+            }
+            BlockingMode::Polling => {
+                write!(f, " --debug-futex-mode=polling")?;
+            }
+            BlockingMode::Precise => { /* default */ }
+        }
+        if dop.no_rcb_time {
+            write!(f, " --no-rcb-time")?;
+        }
+        if dop.detlog_heap {
+            write!(f, " --detlog-heap")?;
+        }
+        if dop.detlog_stack {
+            write!(f, " --detlog-stack")?;
+        }
+        if dop.sysinfo_uptime_offset != /* default */ 120 {
+            write!(f, " --sysinfo-uptime-offset={}", dop.sysinfo_uptime_offset)?;
+        }
+        if dop.memory != 1_000_000_000 {
+            write!(f, " --memory={}", dop.memory)?;
+        }
+        if let Some(limit) = dop.memory_limit {
+            write!(f, " --memory-limit={}", limit)?;
+        }
+        if let Some(timeout) = dop.timeout {
+            write!(f, " --timeout={:?}", timeout)?;
+        }
+        if dop.timeout_signal.0 != Signal::SIGTERM {
+            write!(f, " --timeout-signal={}", dop.timeout_signal.0)?;
+        }
+        if dop.hostname != DEFAULT_HOSTNAME {
+            write!(f, " --hostname={}", shell_words::quote(&dop.hostname))?;
+        }
+        if dop.uname_release != "5.2.0" {
+            write!(f, " --uname-release={}", shell_words::quote(&dop.uname_release))?;
+        }
+        if let Some(uid) = dop.uid {
+            write!(f, " --uid={}", uid)?;
+        }
+        if let Some(gid) = dop.gid {
+            write!(f, " --gid={}", gid)?;
+        }
+        for (tid, rcb) in &dop.interrupt_at {
+            write!(f, " --interrupt-at={}:{}", tid, rcb)?;
+        }
+        for (tid, path) in &dop.entropy_file {
+            let s = path.to_str().expect("valid unicode path");
+            if let Some(tid) = tid {
+                write!(
+                    f,
+                    " --entropy-file={}",
+                    shell_words::quote(&format!("{}:{}", tid, s))
+                )?;
+            } else {
+                write!(f, " --entropy-file={}", shell_words::quote(s))?;
+            }
+        }
+
+        write!(
+            f,
+            " -- {}",
+            shell_words::quote(self.program.to_str().expect("valid unicode path"))
+        )?;
+        if !self.args.is_empty() {
+            write!(f, " {}", shell_words::join(&self.args))?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn display_runopts1() {
+    let vec: Vec<&str> = vec!["fakehermit", "fakeprog", "arg1", "arg2"];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(format!("{}", ro), " -- fakeprog arg1 arg2");
+}
+
+#[test]
+fn display_runopts2() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--sequentialize-threads",
+        "fakeprog",
+        "arg1",
+        "arg2",
+    ];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(format!("{}", ro), " -- fakeprog arg1 arg2");
+}
+
+#[test]
+fn display_runopts3() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--no-sequentialize-threads",
+        "--no-virtualize-metadata",
+        "--epoch=2000-12-31T23:59:59+00:00",
+        "fakeprog",
+        "arg1",
+        "arg2",
+    ];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(
+        format!("{}", ro),
+        " --no-sequentialize-threads --no-virtualize-metadata --epoch=2000-12-31T23:59:59+00:00 -- fakeprog arg1 arg2"
+    );
+}
+
+#[test]
+fn display_runopts4() {
+    let vec: Vec<&str> = vec!["fakehermit", "--sequentialize-threads", "fakeprog", "arg1"];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(format!("{}", ro), " -- fakeprog arg1");
+}
+
+#[test]
+fn display_runopts5() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--virtual-quantum=1000",
+        "--timer-resolution=4000000",
+        "fakeprog",
+    ];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(
+        format!("{}", ro),
+        " --virtual-quantum=1000 --timer-resolution=4000000 -- fakeprog"
+    );
+}
+
+#[test]
+fn display_runopts6() {
+    let vec: Vec<&str> = vec!["fakehermit", "--spawn-order=ChildFirst", "fakeprog"];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(format!("{}", ro), " --spawn-order=childfirst -- fakeprog");
+}
+
+#[test]
+fn display_runopts7() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--entropy-file=/tmp/bytes",
+        "--entropy-file=3:/tmp/more bytes",
+        "fakeprog",
+    ];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(
+        format!("{}", ro),
+        " --entropy-file=/tmp/bytes --entropy-file='3:/tmp/more bytes' -- fakeprog"
+    );
+}
+
+#[test]
+fn display_runopts8() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--rdtsc-model=Instruction",
+        "--tsc-increment=50",
+        "fakeprog",
+    ];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(
+        format!("{}", ro),
+        " --rdtsc-model=instruction --tsc-increment=50 -- fakeprog"
+    );
+}
+
+#[test]
+fn display_runopts9() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--virtual-epoch=2024-02-29T12:00:00Z",
+        "--time-dilation=10",
+        "fakeprog",
+    ];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(
+        format!("{}", ro),
+        " --epoch=2024-02-29T12:00:00+00:00 --clock-multiplier=10 -- fakeprog"
+    );
+}
+
+#[test]
+fn display_runopts10() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--hostname=build-host.example.com",
+        "--uid=1000",
+        "fakeprog",
+    ];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(
+        format!("{}", ro),
+        " --hostname=build-host.example.com --uid=1000 -- fakeprog"
+    );
+}
+
+#[test]
+fn display_runopts11() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--dns-host=example.com=93.184.216.34",
+        "--dns-host=db=::1",
+        "fakeprog",
+    ];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(
+        format!("{}", ro),
+        " --dns-host=example.com=93.184.216.34 --dns-host=db=::1 -- fakeprog"
+    );
+}
+
+#[test]
+fn display_runopts12() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--cow-root=/tmp/cow",
+        "--cow-report=/tmp/cow changes",
+        "fakeprog",
+    ];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(
+        format!("{}", ro),
+        " --cow-root=/tmp/cow --cow-report='/tmp/cow changes' -- fakeprog"
+    );
+}
+
+#[test]
+fn display_runopts13() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--bind=/data",
+        "--bind=/inputs:/tmp/inputs:ro",
+        "--bind=tmpfs:/scratch:size=64m",
+        "fakeprog",
+    ];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(
+        format!("{}", ro),
+        " --bind=/data --bind=/inputs:/tmp/inputs:ro --bind=tmpfs:/scratch:size=64m -- fakeprog"
+    );
+}
+
+#[test]
+fn display_runopts14() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--base-env=minimal",
+        "--env-passthrough=LC_*",
+        "--env-file=/tmp/guest env",
+        "--env=FOO=1",
+        "fakeprog",
+    ];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(
+        format!("{}", ro),
+        " --base-env=minimal --env-passthrough='LC_*' --env-file='/tmp/guest env' --env=FOO=1 -- fakeprog"
+    );
+}
+
+#[test]
+fn display_runopts15() {
+    let vec: Vec<&str> = vec!["fakehermit", "--stdin=/tmp/input", "fakeprog"];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(format!("{}", ro), " --stdin=/tmp/input -- fakeprog");
+    let vec: Vec<&str> = vec!["fakehermit", "--record-stdin-to=/tmp/input", "fakeprog"];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(
+        format!("{}", ro),
+        " --record-stdin-to=/tmp/input -- fakeprog"
+    );
+}
+
+#[test]
+fn display_runopts16() {
+    let vec: Vec<&str> = vec!["fakehermit", "--memory-limit=64MB", "fakeprog"];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(format!("{}", ro), " --memory-limit=64000000 -- fakeprog");
+}
+
+#[test]
+fn display_runopts17() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--timeout=1500ms",
+        "--timeout-signal=SIGABRT",
+        "fakeprog",
+    ];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(
+        format!("{}", ro),
+        " --timeout=1.5s --timeout-signal=SIGABRT -- fakeprog"
+    );
+    let ro2 = RunOpts::from_iter(format!("fakehermit{}", ro).split_whitespace());
+    assert_eq!(format!("{}", ro2), format!("{}", ro));
+}
+
+#[test]
+fn display_runopts18() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--rootfs=/images/debian root",
+        "--workdir=/src",
+        "fakeprog",
+    ];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(
+        format!("{}", ro),
+        " --rootfs='/images/debian root' --workdir=/src -- fakeprog"
+    );
+}
+
+#[test]
+fn display_runopts19() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--summary",
+        "--summary-json=/tmp/summary.json",
+        "fakeprog",
+    ];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(
+        format!("{}", ro),
+        " --summary --summary-json=/tmp/summary.json -- fakeprog"
+    );
+}
+
+#[test]
+fn display_runopts20() {
+    let vec: Vec<&str> = vec!["fakehermit", "--script=steps.txt"];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(format!("{}", ro), " --script=steps.txt -- /bin/sh");
+    let vec: Vec<&str> = vec!["fakehermit", "--script=steps.txt", "bash", "x"];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(format!("{}", ro), " --script=steps.txt -- bash x");
+}
+
+#[test]
+fn display_runopts21() {
+    let vec: Vec<&str> = vec!["fakehermit", "--gdbserver", ":2345", "fakeprog"];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(format!("{}", ro), " --gdbserver=:2345 -- fakeprog");
+    let vec: Vec<&str> = vec!["fakehermit", "--gdbserver", "--", "fakeprog"];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(format!("{}", ro), " --gdbserver -- fakeprog");
+}
+
+#[test]
+fn display_runopts22() {
+    let vec: Vec<&str> = vec!["fakehermit", "--backend=ptrace", "fakeprog"];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(format!("{}", ro), " --backend=ptrace -- fakeprog");
+    let vec: Vec<&str> = vec!["fakehermit", "--backend=seccomp-fast", "fakeprog"];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(format!("{}", ro), " -- fakeprog");
+}
+
+#[test]
+fn display_runopts23() {
+    let vec: Vec<&str> = vec!["fakehermit", "--audit-seccomp", "fakeprog"];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(format!("{}", ro), " --audit-seccomp -- fakeprog");
+    assert!(RunOpts::try_parse_from(["fakehermit", "--audit-seccomp", "--verify", "x"]).is_err());
+}
+
+#[test]
+fn display_runopts24() {
+    let vec: Vec<&str> = vec!["fakehermit", "--force", "fakeprog"];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(format!("{}", ro), " --force -- fakeprog");
+}
+
+#[test]
+fn display_runopts25() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--replay-schedule-from=sched.json",
+        "--fuzzy-replay",
+        "fakeprog",
+    ];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(
+        format!("{}", ro),
+        " --replay-schedule-from=sched.json --fuzzy-replay -- fakeprog"
+    );
+}
+
+#[test]
+fn display_runopts26() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--replay-schedule-from=sched.json",
+        "--on-divergence=resync",
+        "fakeprog",
+    ];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(
+        format!("{}", ro),
+        " --replay-schedule-from=sched.json --on-divergence=resync -- fakeprog"
+    );
+    assert!(RunOpts::try_parse_from(["fakehermit", "--on-divergence=abort", "fakeprog"]).is_err());
+}
+
+#[test]
+fn display_runopts27() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--replay-prefix-then-chaos=/tmp/a:b.json:120",
+        "fakeprog",
+    ];
+    let mut ro = RunOpts::from_iter(vec.iter());
+    let prefix = &ro.det_opts.det_config.replay_prefix_then_chaos;
+    assert_eq!(
+        prefix.as_ref().map(|p| (p.path.as_path(), p.events)),
+        Some((Path::new("/tmp/a:b.json"), 120))
+    );
+    ro.validate_args().unwrap();
+    assert!(ro.det_opts.det_config.chaos);
+    let s = format!("{}", ro);
+    assert!(s.contains(" --replay-prefix-then-chaos=/tmp/a:b.json:120"));
+    assert!(!s.contains("--replay-schedule-from"));
+}
+
+#[test]
+fn display_runopts28() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--verify-runs=8",
+        "--verify-jobs=4",
+        "fakeprog",
+    ];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!((ro.verify_runs, ro.verify_jobs), (Some(8), Some(4)));
+    let s = format!("{}", ro);
+    assert!(s.contains(" --verify-runs=8 --verify-jobs=4"));
+    assert!(RunOpts::try_parse_from(["fakehermit", "--verify-jobs=4", "x"]).is_err());
+    assert!(RunOpts::try_parse_from(["fakehermit", "--verify", "--verify-runs=3", "x"]).is_err());
+}
+
+#[test]
+fn display_runopts29() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--verify-against=/tmp/ref.log",
+        "--verify-against=/tmp/ref summary.json",
+        "fakeprog",
+    ];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(ro.verify_against.len(), 2);
+    let s = format!("{}", ro);
+    assert!(s.contains(" --verify-against=/tmp/ref.log --verify-against='/tmp/ref summary.json'"));
+}
+
+#[test]
+fn display_runopts30() {
+    let vec: Vec<&str> = vec!["fakehermit", "--tool=/tmp/liblogger.so", "fakeprog"];
+    let ro = RunOpts::from_iter(vec.iter());
+    assert_eq!(ro.tool, vec![PathBuf::from("/tmp/liblogger.so")]);
+    let s = format!("{}", ro);
+    assert!(s.contains(" --tool=/tmp/liblogger.so"));
+}
+
+#[test]
+fn display_runopts31() {
+    let vec: Vec<&str> = vec!["fakehermit", "--emit-trace=/tmp/run.json", "fakeprog"];
+    let mut ro = RunOpts::from_iter(vec.iter());
+    ro.validate_args().unwrap();
+    assert!(ro.det_opts.det_config.record_preemptions);
+    let s = format!("{}", ro);
+    assert!(s.contains(" --emit-trace=/tmp/run.json"));
+}
+
+#[test]
+fn display_runopts32() {
+    let vec: Vec<&str> = vec!["fakehermit", "--audit-syscalls", "fakeprog"];
+    let ro = RunOpts::from_iter(vec.iter());
+    let s = format!("{}", ro);
+    assert!(s.contains(" --audit-syscalls"));
+}
+
+#[test]
+fn display_runopts33() {
+    let vec: Vec<&str> = vec![
+        "fakehermit",
+        "--crash-report-dir=/tmp/crash reports",
+        "fakeprog",
+    ];
+    let ro = RunOpts::from_iter(vec.iter());
+    let s = format!("{}", ro);
+    assert!(s.contains(" --crash-report-dir='/tmp/crash reports'"));
+}
+
+#[test]
+fn script_step_lines() {
+    let contents = "# setup\nmkdir -p out\n\n  ./test.sh > out/log  \nrm -r out\n";
+    assert_eq!(
+        script_steps(contents),
+        "set -e\nmkdir -p out\n./test.sh > out/log\nrm -r out\n"
+    );
+}
+
+#[test]
+fn env_passthrough_globs() {
+    assert!(glob_match("LC_*", "LC_ALL"));
+    assert!(glob_match("LC_*", "LC_"));
+    assert!(!glob_match("LC_*", "LANG"));
+    assert!(glob_match("*_PROXY", "HTTPS_PROXY"));
+    assert!(glob_match("*", ""));
+    assert!(glob_match("?ERM", "TERM"));
+    assert!(!glob_match("?ERM", "ERM"));
+    assert!(glob_match("A*B*C", "AxxBxBxC"));
+    assert!(!glob_match("A*B*C", "AxxBxC_"));
+    assert!(!glob_match("HOME", "HOMEDIR"));
+}
+
+#[test]
+fn env_file_lines() {
+    let contents = "# comment\nFOO=1\n\n  BAR=two words  \nEMPTY=\n";
+    assert_eq!(
+        parse_env_file(contents).unwrap(),
+        vec![
+            ("FOO".to_owned(), "1".to_owned()),
+            ("BAR".to_owned(), "two words".to_owned()),
+            ("EMPTY".to_owned(), String::new()),
+        ]
+    );
+    assert!(parse_env_file("=1").is_err());
+}
+
+/// Create two logging destinations and two global configs. Returns non-zero exit
+/// status if there was a difference in any component of the output.
+impl RunOpts {
+    pub fn main(&mut self, global: &GlobalOpts) -> Result<ExitStatus, Error> {
+        // Set up an early tracing option before we're ready to set the global default:
+
+        // TODO(T124429978): temporarily disabling this because it inexplicably clobbers our
+        // subsequent tracing_subscriber::fmt::init() call.
+        // tracing::subscriber::with_default(super::tracing::stderr_subscriber(global.log), || {
+        self.validate_args()?;
+        // });
+
+        self.check_replay_metadata()?;
+        self.check_input_files()?;
+        for path in &self.tool {
+            crate::register_instrument(crate::load_tool(path)?);
+        }
+
+        if self.lite {
+            self.run_lite(global)
+        } else if self.verify {
+            self.verify(global)
+        } else if let Some(runs) = self.verify_runs {
+            self.verify_many(global, runs)
+        } else if !self.verify_against.is_empty() {
+            self.verify_against(global)
+        } else if self.audit_seccomp {
+            self.audit_seccomp(global)
+        } else {
+            self.run(global)
+        }
+    }
+
+    /// Some arguments imply others. This is the place where that validation occurs.
+    pub fn validate_args(&mut self) -> Result<(), Error> {
+        let config = &mut self.det_opts.det_config;
+
+        config.has_uts_namespace = true;
+
+        if self.analyze_networking {
+            config.warn_non_zero_binds = true;
+        }
+
+        config.sequentialize_threads = !self.no_sequentialize_threads;
+        config.deterministic_io = !self.no_deterministic_io;
+        config.virtualize_time = true;
+        config.virtualize_metadata = true;
+        config.virtualize_cpuid = true;
+
+        // Perform internal validation on the Config args, before taking into account the
+        // hermit run args:
+        if crate::validate_config(config)? {
+            self.pin_threads = true;
+        }
+
+        if let Some(sf) = &self.seed_from {
+            let seed = match sf {
+                SeedFrom::Args => {
+                    let mut hasher = DefaultHasher::new();
+                    self.args.hash(&mut hasher);
+                    self.program.hash(&mut hasher);
+                    hasher.finish()
+                }
+                SeedFrom::SystemRandom => {
+                    let mut rng = rand::thread_rng();
+                    let seed: u64 = rng.gen();
+                    seed
+                }
+            };
+            // TODO(T124429978): this could change back to tracing::warn! when the bug is fixed:
+            eprintln!(
+                "[hermit] auto setting --seed {0:?} --sched-seed {0:?}",
+                seed
+            );
+            config.seed = seed;
+        }
+
+        if self.det_opts.det_config.record_preemptions {
+            self.det_opts.det_config.record_metadata = Some(self.record_metadata());
+        }
+        Ok(())
+    }
+
+    /// Describes this run, for the header of the records it makes.
+    fn record_metadata(&self) -> RecordMetadata {
+        let config = &self.det_opts.det_config;
+        RecordMetadata {
+            hermit_version: Version::get().to_owned(),
+            config_hash: config.schedule_hash(),
+            command: std::iter::once(self.program.to_string_lossy().into_owned())
+                .chain(self.args.iter().cloned())
+                .collect(),
+            seed: config.seed,
+            recorded_at: Utc::now().to_rfc3339(),
+        }
+    }
+
+    /// Refuses to replay a record made on another architecture and, unless `--force` is given,
+    /// one made by a run unlike this one.
+    fn check_replay_metadata(&self) -> Result<(), Error> {
+        let config = &self.det_opts.det_config;
+        let current = self.record_metadata();
+        let replayed = [
+            &config.replay_preemptions_from,
+            &config.replay_schedule_from,
+        ];
+        for path in replayed.into_iter().flatten() {
+            let bytes =
+                fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+            let record = PreemptionRecord::from_bytes(&bytes).map_err(Error::msg)?;
+            record
+                .check_arch()
+                .map_err(|e| Error::msg(format!("Cannot replay {}: {}", path.display(), e)))?;
+            let differences = match record.metadata() {
+                Some(recorded) => recorded.differences(&current),
+                None => continue,
+            };
+            if differences.is_empty() {
+                continue;
+            }
+            let message = format!(
+                "{} was recorded by a different run:\n  {}",
+                path.display(),
+                differences.join("\n  ")
+            );
+            if self.force {
+                eprintln!("WARNING: {}", message);
+            } else {
+                return Err(Error::msg(format!(
+                    "{}\nPass --force to replay it anyway.",
+                    message
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the files that detcore only reads once the container is running, so that a bad
+    /// path is reported here rather than failing in the middle of the run.
+    fn check_input_files(&self) -> Result<(), Error> {
+        let config = &self.det_opts.det_config;
+        if let Some(profile) = &config.cpu_profile {
+            CpuProfile::load(profile).map_err(Error::msg)?;
+        }
+        if let Some(path) = &config.replay_network_from {
+            NetRecording::load(path).map_err(Error::msg)?;
+        }
+        for (_, path) in &config.entropy_file {
+            let context = || format!("Unable to read --entropy-file {}", path.display());
+            let file = fs::File::open(path).with_context(context)?;
+            if !file.metadata().with_context(context)?.is_file() {
+                return Err(Error::msg(format!(
+                    "--entropy-file {} is not a regular file",
+                    path.display()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn tmpfs(&self) -> Result<Tmpfs, Error> {
+        match self.tmp.as_ref() {
+            Some(path) => {
+                let path = path.as_path();
+                fs::create_dir_all(path)?;
+                Ok(Tmpfs::Path(path))
+            }
+            None => Ok(Tmpfs::Temp(tempfile::TempDir::new()?)),
+        }
+    }
+
+    pub fn run(&self, global: &GlobalOpts) -> Result<ExitStatus, Error> {
+        let tmpfs = self.tmpfs()?;
+        let etc = tempfile::TempDir::new()?;
+
+        let mut container = self.container(tmpfs.path(), etc.path())?;
+
+        let exit_status = with_container(&mut container, || self.run_in_container(global))?;
+
+        if let Some(cow) = self.cow_root() {
+            self.report_changes(&cow)?;
+        }
+
+        Ok(exit_status)
+    }
+
+    fn cow_root(&self) -> Option<CowRoot> {
+        self.cow_root.as_deref().map(CowRoot::new)
+    }
+
+    /// The root filesystem of the guest, if it is not the host's own.
+    fn guest_root(&self) -> Option<RootFs> {
+        match (self.cow_root(), &self.rootfs) {
+            (Some(cow), _) => Some(cow.merged()),
+            (None, Some(rootfs)) => Some(RootFs::new(rootfs)),
+            (None, None) => None,
+        }
+    }
+
+    /// Prints or saves the changes the guest made to its copy-on-write root.
+    fn report_changes(&self, cow: &CowRoot) -> Result<(), Error> {
+        let changes = cow
+            .changes(Path::new("/"))
+            .context("Failed to list the changes in --cow-root")?;
+        let report: String = changes
+            .iter()
+            .map(|change| format!("{}\n", change))
+            .collect();
+        match &self.cow_report {
+            Some(path) => fs::write(path, report)?,
+            None => eprint!("{}", report),
+        }
+        Ok(())
+    }
+
+    fn run_lite(&self, global: &GlobalOpts) -> Result<ExitStatus, Error> {
+        // TODO: Make this use detcore instead after detcore is capable of being
+        // "lightweight".
+        let _guard = global.init_tracing();
+
+        let tmpfs = self.tmpfs()?;
+        let etc = tempfile::TempDir::new()?;
+
+        let mut command = self.command()?;
+        command
+            .unshare(Namespace::PID | Namespace::IPC)
+            .map_root()
+            .hostname(&self.det_opts.det_config.hostname)
+            .domainname(self.domainname())
+            .mount(Mount::proc())
+            .mounts(self.mounts(tmpfs.path())?)
+            .mounts(self.dns_mounts(etc.path())?);
+
+        if self.no_networking {
+            command.local_networking_only();
+        }
+
+        let mut child = command.spawn()?;
+
+        let exit_status = child.wait_blocking()?;
+
+        Ok(exit_status)
+    }
+
+    // Execution mode corresponding to `run --verify`:
+    fn verify(&self, global: &GlobalOpts) -> Result<ExitStatus, Error> {
+        let (log1, log2) =
+            temp_log_files("run1", "run2").context("Failed to create temporary log files")?;
+
+        let (log1_file, log1_path) = log1.into_parts();
+        let (log2_file, log2_path) = log2.into_parts();
+
+        eprintln!(":: {}", "Run1...".yellow().bold());
+
+        let out1: Output = self.run_verify(log1_file, global)?;
+        if !self.verify_allow.satisfies(out1.status) {
+            eprintln!(
+                "First run errored during --verify, not continuing to a second. Stdout:\n{}\nStderr:\n{}",
+                String::from_utf8_lossy(&out1.stdout),
+                String::from_utf8_lossy(&out1.stderr),
+            );
+            return Err(Error::msg("First run during --verify exited in error"));
+        }
+
+        eprintln!(":: {}", "Run2...".yellow().bold());
+        let out2 = self.run_verify(log2_file, global)?;
+
+        compare_two_runs(
+            &out1,
+            log1_path,
+            &out2,
+            log2_path,
+            "Success: deterministic.",
+            "Failure: nondeterministic.",
+        )
+    }
+
+    // Execution mode corresponding to `run --verify-runs`:
+    fn verify_many(&self, global: &GlobalOpts, runs: usize) -> Result<ExitStatus, Error> {
+        if runs < 2 {
+            return Err(Error::msg(
+                "--verify-runs needs at least two runs to compare",
+            ));
+        }
+        let jobs = self.verify_jobs.unwrap_or(1).clamp(1, runs);
+        let (files, paths): (Vec<_>, Vec<_>) = (1..=runs)
+            .map(|run| temp_log_file(&format!("run{}", run)).map(|log| log.into_parts()))
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to create temporary log files")?
+            .into_iter()
+            .unzip();
+
+        let mut outputs = Vec::with_capacity(runs);
+        let mut files = files.into_iter().enumerate().peekable();
+        while files.peek().is_some() {
+            let batch: Vec<_> = files.by_ref().take(jobs).collect();
+            let results: Vec<_> = std::thread::scope(|scope| {
+                let handles: Vec<_> = batch
+                    .into_iter()
+                    .map(|(ix, file)| {
+                        eprintln!(":: {}", format!("Run{}...", ix + 1).yellow().bold());
+                        scope.spawn(move || self.run_verify(file, global))
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().expect("verify run panicked"))
+                    .collect()
+            });
+            for out in results {
+                let out: Output = out?;
+                if !self.verify_allow.satisfies(out.status) {
+                    eprintln!(
+                        "Run{} errored during --verify-runs, not continuing. Stdout:\n{}\nStderr:\n{}",
+                        outputs.len() + 1,
+                        String::from_utf8_lossy(&out.stdout),
+                        String::from_utf8_lossy(&out.stderr),
+                    );
+                    return Err(Error::msg("A run during --verify-runs exited in error"));
+                }
+                outputs.push(out);
+            }
+        }
+
+        compare_many_runs(
+            outputs.into_iter().zip(paths).collect(),
+            "Success: deterministic.",
+            "Failure: nondeterministic.",
+        )
+    }
+
+    // Execution mode corresponding to `run --verify-against`:
+    fn verify_against(&self, global: &GlobalOpts) -> Result<ExitStatus, Error> {
+        let (log_file, log_path) = temp_log_file("run")
+            .context("Failed to create temporary log file")?
+            .into_parts();
+
+        // The summary is written from inside the container, which has its own `/tmp`.
+        let mut opts = self.clone();
+        let data = crate::HermitData::new();
+        fs::create_dir_all(data.data_dir())?;
+        let summary = tempfile::Builder::new()
+            .prefix("summary_")
+            .suffix(".json")
+            .tempfile_in(data.data_dir())?
+            .into_temp_path();
+        let summary_path = match &self.summary_json {
+            Some(path) => path.clone(),
+            None => summary.to_path_buf(),
+        };
+        opts.summary_json = Some(summary_path.clone());
+
+        eprintln!(":: {}", "Run...".yellow().bold());
+        let out = opts.run_verify(log_file, global)?;
+
+        compare_against_references(&out, log_path, &summary_path, &self.verify_against)
+    }
+
+    // Execution mode corresponding to `run --audit-seccomp`:
+    fn audit_seccomp(&self, global: &GlobalOpts) -> Result<ExitStatus, Error> {
+        let (log1, log2) = temp_log_files("ptrace", "seccomp_fast")
+            .context("Failed to create temporary log files")?;
+
+        let mut ptrace = self.clone();
+        ptrace.det_opts.det_config.backend = Backend::Ptrace;
+        let mut seccomp = self.clone();
+        seccomp.det_opts.det_config.backend = Backend::SeccompFast;
+
+        eprintln!(
+            ":: {}",
+            "Run with every syscall intercepted...".yellow().bold()
+        );
+        let out1 = ptrace.run_verify(log1.reopen()?, global)?;
+
+        eprintln!(":: {}", "Run with the seccomp filter...".yellow().bold());
+        let out2 = seccomp.run_verify(log2.reopen()?, global)?;
+
+        if compare_outputs(&out1, &out2) {
+            let log1 = log1.into_temp_path().keep()?;
+            let log2 = log2.into_temp_path().keep()?;
+            eprintln!(
+                ":: {}: {} {}",
+                "Logs retained for further inspection".red(),
+                log1.display(),
+                log2.display()
+            );
+            Err(Error::msg(
+                "The seccomp filter changed the output of the run (logs retained).",
+            ))
+        } else {
+            eprintln!(
+                ":: {}",
+                "Success: the seccomp filter is transparent.".green().bold()
+            );
+            Ok(out2.status)
+        }
+    }
+
+    /// Returns the mounts to be used with the container.
+    pub(crate) fn mounts(&self, tmpfs: &Path) -> Result<Vec<Mount>, Error> {
+        let mut mounts = Vec::new();
+
+        for mount in &self.mount {
+            if let Ok(path) = mount.get_target().strip_prefix(TMP_DIR) {
+                // If the target is in /tmp, change it so it goes to our
+                // temporary /tmp instead.
+                mounts.push(mount.clone().target(tmpfs.join(path)).touch_target());
+            } else {
+                mounts.push(mount.clone());
+            }
+        }
+
+        for bind in &self.bind {
+            let mount = Mount::from(bind.clone()).rshared();
+
+            // Binding a path to itself only makes sense for things in `/tmp`
+            // since that is the only directory we overlay.
+            if let Ok(relative_path) = mount.get_target().strip_prefix(TMP_DIR) {
+                let target = tmpfs.join(relative_path);
+                mounts.push(mount.target(target).touch_target());
+            } else if !bind.is_identity() {
+                mounts.push(mount);
+            } else {
+                tracing::warn!(
+                    "The path {:?} is not in {}, --bind currently has no effect",
+                    bind,
+                    TMP_DIR
+                );
+            }
+        }
+
+        // Bind the /tmp/tmpXXXXXX tmpfs mount over /tmp to hide it. This way,
+        // we still preserve the files or directories bind-mounted inside of it
+        // while hiding the real /tmp.
+        mounts.push(Mount::bind(tmpfs, TMP_DIR).rshared());
+
+        Ok(mounts)
+    }
+
+    /// The domain name of the container: the last component of `--hostname`.
+    fn domainname(&self) -> &str {
+        let hostname = &self.det_opts.det_config.hostname;
+        hostname.rsplit('.').next().unwrap_or(hostname)
+    }
+
+    /// With `--dns-host`, `--record-network-to`, or `--replay-network-from`, write out the
+    /// container's name resolution configuration to `etc`, and bind-mount it over `/etc`.
+    fn dns_mounts(&self, etc: &Path) -> Result<Vec<Mount>, Error> {
+        let config = &self.det_opts.det_config;
+        let recorded = config.record_network_to.is_some() || config.replay_network_from.is_some();
+        if self.dns_host.is_empty() && !recorded {
+            return Ok(Vec::new());
+        }
+
+        let mut hosts = format!(
+            "127.0.0.1 localhost\n::1 localhost\n127.0.1.1 {}\n",
+            config.hostname
+        );
+        for (name, addr) in &self.dns_host {
+            hosts.push_str(&format!("{} {}\n", addr, name));
+        }
+        let mut files = vec![("hosts", hosts)];
+        if recorded {
+            // Keep the host's resolvers, but talk to them over TCP.
+            let host_resolv = fs::read_to_string("/etc/resolv.conf").unwrap_or_default();
+            let mut resolv: String = host_resolv
+                .lines()
+                .filter(|line| line.trim_start().starts_with("nameserver"))
+                .map(|line| format!("{}\n", line))
+                .collect();
+            resolv.push_str("options use-vc\n");
+            files.push(("resolv.conf", resolv));
+            files.push(("nsswitch.conf", "hosts: files dns\n".to_owned()));
+        } else {
+            files.push(("nsswitch.conf", "hosts: files\n".to_owned()));
+        }
+
+        let mut mounts = Vec::new();
+        for (name, contents) in files {
+            let source = etc.join(name);
+            fs::write(&source, contents)?;
+            mounts.push(Mount::bind(&source, Path::new("/etc").join(name)).touch_target());
+        }
+        Ok(mounts)
+    }
+
+    /// Returns a configured container to run a function in.
+    pub(crate) fn container(&self, tmpfs: &Path, etc: &Path) -> Result<Container, Error> {
+        let mut container = default_container(self.pin_threads);
+        container
+            .hostname(&self.det_opts.det_config.hostname)
+            .domainname(self.domainname());
+
+        if self.no_networking
+            || self.analyze_networking
+            || self.det_opts.det_config.loopback_networking
+        {
+            container.local_networking_only();
+        }
+
+        let mut mounts = self.mounts(tmpfs)?;
+        mounts.extend(self.dns_mounts(etc)?);
+
+        if let Some(root) = self.guest_root() {
+            if let Some(cow) = self.cow_root() {
+                cow.reset()
+                    .context("Failed to prepare the --cow-root directory")?;
+                container.mounts(cow.mounts());
+            } else {
+                root.prepare()
+                    .context("Failed to prepare the --rootfs directory")?;
+                container.mounts(root.mounts());
+            }
+            mounts = mounts.into_iter().map(|m| root.rebase(m)).collect();
+        }
+
+        container.mounts(mounts);
+
+        Ok(container)
+    }
+
+    pub fn run_verify(&self, log_file: fs::File, global: &GlobalOpts) -> Result<Output, Error> {
+        // TODO: Get this working with `--tmp`? Each run could use a separate
+        // subdirectory. Only preserve the temporary directory if verify failed?
+        let tmpfs = tempfile::TempDir::new()?;
+        let etc = tempfile::TempDir::new()?;
+
+        let mut container = self.container(tmpfs.path(), etc.path())?;
+
+        let mut log_file = Some(log_file);
+        with_container(&mut container, || {
+            self.run_verify_in_container(&mut log_file, global)
+        })
+    }
+
+    fn merge_from_env_settings(&self, command: &mut Command) -> Result<(), Error> {
+        if self.base_env != BaseEnv::Host {
+            for (name, value) in std::env::vars() {
+                if self
+                    .env_passthrough
+                    .iter()
+                    .any(|glob| glob_match(glob, &name))
+                {
+                    command.env(name, value);
+                }
+            }
+        }
+        for path in &self.env_file {
+            let contents = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read --env-file {}", path.display()))?;
+            for (name, value) in parse_env_file(&contents)? {
+                command.env(name, value);
+            }
+        }
+        for assignment in &self.env {
+            command.env(&assignment.0, &assignment.1);
+        }
+        Ok(())
+    }
+
+    /// Sets up the guest's environment according to `--base-env` and the options which add to it.
+    fn set_env(&self, command: &mut Command) -> Result<(), Error> {
+        match self.base_env {
+            BaseEnv::Empty => {
+                command.env_clear();
+            }
+            BaseEnv::Minimal => {
+                command.env_clear();
+                command.env("HOSTNAME", &self.det_opts.det_config.hostname);
+                command.env(
+                    "PATH",
+                    "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin",
+                );
+                command.env("HOME", "/root");
+                command.env("TZ", "UTC");
+                command.env("LANG", "C.UTF-8");
+            }
+            BaseEnv::Host => {
+                // Let it all through.
+            }
+        }
+        self.merge_from_env_settings(command)
+    }
+
+    /// The command to run in the guest: the program with its arguments, or with `--script`, the
+    /// shell running each of the script's steps.
+    fn command(&self) -> Result<Command, Error> {
+        let mut command = Command::new(&self.program);
+        if let Some(path) = &self.script {
+            let contents = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read --script {}", path.display()))?;
+            command.arg("-c").arg(script_steps(&contents)).arg(path);
+        }
+        command.args(&self.args);
+        Ok(command)
+    }
+
+    /// The program to run.
+    pub(crate) fn program(&self) -> &Path {
+        &self.program
+    }
+
+    /// The guest's command as it would run natively, outside of hermit, for comparison.
+    pub(crate) fn native_command(&self) -> Result<std::process::Command, Error> {
+        let mut command = std::process::Command::new(&self.program);
+        if let Some(path) = &self.script {
+            let contents = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read --script {}", path.display()))?;
+            command.arg("-c").arg(script_steps(&contents)).arg(path);
+        }
+        command.args(&self.args);
+        if let Some(current_dir) = &self.workdir {
+            command.current_dir(current_dir);
+        }
+        Ok(command)
+    }
+
+    /// Confines the guest to its copy-on-write root or `--rootfs`, if there is one.
+    fn chroot(&self, command: &mut Command) -> Result<(), Error> {
+        if let Some(root) = self.guest_root() {
+            command.chroot(root.path());
+            // Otherwise, the guest would keep the host's working directory. A copy-on-write
+            // root has the same one, but another root filesystem may well not.
+            if self.workdir.is_none() {
+                if self.cow_root.is_some() {
+                    command.current_dir(std::env::current_dir()?);
+                } else {
+                    command.current_dir("/");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Points the guest's stdin at the `--stdin` file, if any. Otherwise, if `interactive`, the
+    /// guest reads hermit's own stdin (through `--record-stdin-to`, if given), and if not, nothing.
+    fn set_stdin(&self, command: &mut Command, interactive: bool) -> Result<(), Error> {
+        if let Some(path) = &self.stdin {
+            let file = fs::File::open(path)
+                .with_context(|| format!("Failed to open --stdin {}", path.display()))?;
+            command.stdin(Stdio::from(file));
+        } else if !interactive {
+            command.stdin(Stdio::null());
+        } else if let Some(path) = &self.record_stdin_to {
+            let record = fs::File::create(path).with_context(|| {
+                format!("Failed to create --record-stdin-to {}", path.display())
+            })?;
+            let (reader, writer) = nix::unistd::pipe()?;
+            // SAFETY: We own both ends of the new pipe.
+            let (reader, writer) =
+                unsafe { (fs::File::from_raw_fd(reader), fs::File::from_raw_fd(writer)) };
+            std::thread::spawn(move || tee_stdin(record, writer));
+            command.stdin(Stdio::from(reader));
+        }
+        Ok(())
+    }
+
+    fn run_in_container(&self, global: &GlobalOpts) -> Result<ExitStatus, Error> {
+        let _guard = global.init_tracing();
+
+        let mut command = self.command()?;
+        if let Some(current_dir) = &self.workdir {
+            command.current_dir(current_dir);
+        }
+        self.chroot(&mut command)?;
+        self.set_env(&mut command)?;
+        self.set_stdin(&mut command, true)?;
+
+        let config = self.det_opts.det_config.clone();
+
+        crate::run(command, config, self.summary, self.summary_json.clone())
+    }
+
+    pub(crate) fn run_verify_in_container(
+        &self,
+        log_file: &mut Option<fs::File>,
+        global: &GlobalOpts,
+    ) -> Result<Output, Error> {
+        // HACK: Use interior mutability to workaround not being able to pass
+        // `log_file` by value. Guaranteed by caller to never panic.
+        let log_file = log_file.take().unwrap();
+
+        // Ensure at least a minimum DEBUG level.
+        let level = if let Some(requested) = global.log {
+            requested
+        } else {
+            LevelFilter::DEBUG
+        };
+
+        let _guard = init_file_tracing(Some(level), log_file, global.start_otlp());
+
+        let mut command = self.command()?;
+
+        if let Some(current_dir) = &self.workdir {
+            command.current_dir(current_dir);
+        }
+        self.chroot(&mut command)?;
+        self.set_env(&mut command)?;
+        self.set_stdin(&mut command, false)?;
+
+        let config = self.det_opts.det_config.clone();
+
+        crate::run_with_output(command, config, self.summary, self.summary_json.clone())
+    }
+}
+
+/// Copies hermit's stdin to both `record` and the guest, until either runs out.
+fn tee_stdin(mut record: fs::File, mut guest: fs::File) {
+    let mut stdin = std::io::stdin().lock();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = match stdin.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        // Record first, so that nothing typed is lost if the guest has stopped reading.
+        if record.write_all(&buf[..n]).is_err() || guest.write_all(&buf[..n]).is_err() {
+            break;
+        }
+    }
+}
+
+/// Represents a tmpfs location. There are different ways to construct `/tmp` for
+/// the container and this encapsulates all of them.
+enum Tmpfs<'a> {
+    /// Use an existing path as `/tmp`.
+    Path(&'a Path),
+
+    /// Use a new temporary directory as `/tmp`.
+    Temp(tempfile::TempDir),
+}
+
+impl<'a> Tmpfs<'a> {
+    /// Returns the path to `/tmp`.
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Path(path) => path,
+            Self::Temp(temp) => temp.path(),
+        }
+    }
+}
@@ -0,0 +1,642 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! The `hermit sched` subcommands, for looking into schedules and preemption records without
+//! reading their raw JSON.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::path::PathBuf;
+
+use clap::Parser;
+use colored::Colorize;
+use detcore::perfetto::Trace;
+use detcore::preemptions::PreemptionRecord;
+use detcore::types::LogicalTime;
+use detcore::types::Op;
+use detcore::types::SchedEvent;
+use detcore::DetTid;
+use detcore::Priority;
+use reverie::ExitStatus;
+
+use super::global_opts::GlobalOpts;
+use crate::Context;
+use crate::Error;
+
+/// Command-line options for the "sched" subcommand.
+#[derive(Debug, Parser)]
+pub struct SchedOpts {
+    #[clap(subcommand)]
+    command: SchedCommand,
+}
+
+#[derive(Debug, Parser)]
+enum SchedCommand {
+    /// Print each event of a schedule, with its thread and time, after the preemptions of each
+    /// thread. Preemptions are numbered as for `edit --remove-preempt`.
+    Inspect {
+        /// A schedule or preemption record, in any format.
+        #[clap(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Only print the events and preemptions of this thread.
+        #[clap(long, value_name = "DETTID")]
+        thread: Option<DetTid>,
+    },
+
+    /// Check that a schedule or preemption record is well formed, and can be used on this
+    /// machine.
+    Validate {
+        /// A schedule or preemption record, in any format.
+        #[clap(value_name = "FILE")]
+        file: PathBuf,
+    },
+
+    /// Summarize the events and preemptions of each thread.
+    Stats {
+        /// A schedule or preemption record, in any format.
+        #[clap(value_name = "FILE")]
+        file: PathBuf,
+    },
+
+    /// Convert a schedule into a trace for a trace viewer. With "perfetto", the trace can be
+    /// opened in ui.perfetto.dev or chrome://tracing, showing each thread as a track, its events
+    /// at their virtual times, and its preemptions as markers.
+    Export {
+        /// A schedule or preemption record, in any format.
+        #[clap(value_name = "FILE")]
+        file: PathBuf,
+
+        /// The format of the trace.
+        #[clap(long, default_value = "perfetto", possible_values = &["perfetto"])]
+        format: ExportFormat,
+
+        /// Where to write the trace, instead of stdout.
+        #[clap(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+
+    /// Edit a schedule or preemption record, to try out a schedule of your own. Preemptions are
+    /// removed first, then events are swapped, moved, and truncated, in that order. The indices
+    /// are those printed by `inspect`, and each edit sees the result of the edits before it.
+    Edit {
+        /// A schedule or preemption record, in any format.
+        #[clap(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Where to write the edited record. Its format is chosen by extension, as for
+        /// `--record-preemptions-to`.
+        #[clap(short, long, value_name = "PATH")]
+        output: PathBuf,
+
+        #[clap(flatten)]
+        edits: Edits,
+    },
+
+    /// Cut a schedule or preemption record down to some of its threads, or to a window of its
+    /// events, for example to share the part of a large schedule that matters. Only a slice that
+    /// starts at the beginning of the schedule and keeps every thread can be replayed.
+    Slice {
+        /// A schedule or preemption record, in any format.
+        #[clap(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Where to write the slice. Its format is chosen by extension, as for
+        /// `--record-preemptions-to`.
+        #[clap(short, long, value_name = "PATH")]
+        output: PathBuf,
+
+        #[clap(flatten)]
+        slice: Slice,
+    },
+
+    /// Merge schedule fragments, such as those recorded per process or per segment of a run, into
+    /// one record. Events are interleaved by their times, and the preemptions of a thread in
+    /// several fragments are joined in the order the fragments are given. Fails if the fragments
+    /// disagree about a thread, or come from different runs.
+    Merge {
+        /// The fragments, each a schedule or preemption record in any format.
+        #[clap(value_name = "FILE", required = true)]
+        files: Vec<PathBuf>,
+
+        /// Where to write the merged record. Its format is chosen by extension, as for
+        /// `--record-preemptions-to`.
+        #[clap(short, long, value_name = "PATH")]
+        output: PathBuf,
+    },
+}
+
+/// The parts of a schedule kept by `hermit sched slice`.
+#[derive(Debug, Default, Parser)]
+struct Slice {
+    /// Keep only the events with these indices, as printed by `inspect`, e.g. `100..200`.
+    /// Either end may be left out.
+    #[clap(long, parse(try_from_str = parse_range), value_name = "START..END")]
+    events: Option<Range<u64>>,
+
+    /// Keep only this thread, and its events. May be given more than once.
+    #[clap(long, value_name = "DETTID")]
+    thread: Vec<DetTid>,
+
+    /// Keep only the events which end, and the preemptions which happen, in this range of
+    /// virtual nanoseconds. Either end may be left out. Events without a time are dropped.
+    #[clap(long, parse(try_from_str = parse_range), value_name = "START..END")]
+    time: Option<Range<u64>>,
+}
+
+/// Parses `START..END`, where either end may be left out.
+fn parse_range(s: &str) -> Result<Range<u64>, String> {
+    let (start, end) = s
+        .split_once("..")
+        .ok_or_else(|| format!("Expected START..END, could not parse: {:?}", s))?;
+    let parse = |n: &str, default| {
+        if n.is_empty() {
+            Ok(default)
+        } else {
+            n.parse::<u64>().map_err(|e| format!("{}: {:?}", e, n))
+        }
+    };
+    Ok(parse(start, 0)?..parse(end, u64::MAX)?)
+}
+
+/// The changes made by `hermit sched edit`.
+#[derive(Debug, Default, Parser)]
+struct Edits {
+    /// Remove a preemption, so that the timeslices on either side of it run as one, with the
+    /// priority of the earlier. Indices refer to the record before any preemption is removed.
+    #[clap(long, value_name = "INDEX")]
+    remove_preempt: Vec<usize>,
+
+    /// Swap two events of the schedule.
+    #[clap(long, number_of_values = 2, value_names = &["I", "J"])]
+    swap: Vec<usize>,
+
+    /// Move an event of the schedule to another index, shifting the events in between.
+    #[clap(long, number_of_values = 2, value_names = &["FROM", "TO"])]
+    move_event: Vec<usize>,
+
+    /// Drop the event at this index and every event after it.
+    #[clap(long, value_name = "INDEX")]
+    truncate_at: Option<usize>,
+}
+
+/// The formats a schedule can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExportFormat {
+    /// The JSON trace event format of Chrome, which Perfetto also reads.
+    Perfetto,
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "perfetto" => Ok(ExportFormat::Perfetto),
+            _ => Err(format!("Expected perfetto, could not parse: {:?}", s)),
+        }
+    }
+}
+
+impl SchedOpts {
+    pub fn main(&self, global: &GlobalOpts) -> Result<ExitStatus, Error> {
+        let _guard = global.init_tracing();
+
+        match &self.command {
+            SchedCommand::Inspect { file, thread } => {
+                let record = load(file)?;
+                print!("{}", inspect(&record, *thread));
+                Ok(ExitStatus::SUCCESS)
+            }
+            SchedCommand::Validate { file } => {
+                let record = load(file)?;
+                match record.validate() {
+                    Ok(()) => {
+                        println!("{}: {}", file.display(), "valid".green().bold());
+                        Ok(ExitStatus::SUCCESS)
+                    }
+                    Err(e) => {
+                        println!("{}: {} {}", file.display(), "invalid:".red().bold(), e);
+                        Ok(ExitStatus::Exited(1))
+                    }
+                }
+            }
+            SchedCommand::Stats { file } => {
+                let record = load(file)?;
+                let stats = SchedStats::new(&record.as_vecs(), &record.clone().into_global());
+                print!("{}", stats);
+                Ok(ExitStatus::SUCCESS)
+            }
+            SchedCommand::Export {
+                file,
+                format: ExportFormat::Perfetto,
+                output,
+            } => {
+                let record = load(file)?;
+                let trace = Trace::new(&record.as_vecs(), &record.clone().into_global());
+                match output {
+                    Some(path) => {
+                        let out = fs::File::create(path)
+                            .with_context(|| format!("Failed to create {}", path.display()))?;
+                        serde_json::to_writer(io::BufWriter::new(out), &trace)?;
+                    }
+                    None => serde_json::to_writer(io::stdout().lock(), &trace)?,
+                }
+                Ok(ExitStatus::SUCCESS)
+            }
+            SchedCommand::Edit {
+                file,
+                output,
+                edits,
+            } => {
+                let mut record = load(file)?;
+                edits.apply(&mut record)?;
+                record.write_to_disk(output).map_err(Error::msg)?;
+                Ok(ExitStatus::SUCCESS)
+            }
+            SchedCommand::Slice {
+                file,
+                output,
+                slice,
+            } => {
+                let mut record = load(file)?;
+                slice.apply(&mut record);
+                record.write_to_disk(output).map_err(Error::msg)?;
+                if !slice.is_prefix() {
+                    eprintln!(
+                        ":: {}",
+                        "This slice leaves out part of the start of the schedule, so it cannot be \
+                         replayed."
+                            .yellow()
+                    );
+                }
+                Ok(ExitStatus::SUCCESS)
+            }
+            SchedCommand::Merge { files, output } => {
+                let fragments = files.iter().map(|f| load(f)).collect::<Result<_, _>>()?;
+                let record = PreemptionRecord::merge(fragments)
+                    .map_err(Error::msg)
+                    .context("Failed to merge the fragments, numbered in the order given")?;
+                record.write_to_disk(output).map_err(Error::msg)?;
+                Ok(ExitStatus::SUCCESS)
+            }
+        }
+    }
+}
+
+/// Reads a record without validating it, unlike `PreemptionReader`.
+pub fn load(path: &Path) -> Result<PreemptionRecord, Error> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    PreemptionRecord::from_bytes(&bytes)
+        .map_err(Error::msg)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// A short name for the kind of an operation.
+pub fn op_kind(op: &Op) -> &'static str {
+    match op {
+        Op::Branch => "branch",
+        Op::Rdtsc => "rdtsc",
+        Op::Cpuid => "cpuid",
+        Op::Syscall(..) => "syscall",
+        Op::Condvar(..) => "condvar",
+        Op::Socket(..) => "socket",
+        Op::OtherInstructions => "other",
+    }
+}
+
+/// One event, as printed by `hermit sched inspect`.
+pub fn format_event(index: usize, ev: &SchedEvent) -> String {
+    let mut line = format!("#{:<6} thread {:<4} {:?}", index, ev.dettid, ev.op);
+    if ev.count != 1 {
+        line.push_str(&format!(" x{}", ev.count));
+    }
+    if let Some(time) = ev.end_time {
+        line.push_str(&format!(" @{}", time));
+    }
+    line
+}
+
+fn inspect(record: &PreemptionRecord, thread: Option<DetTid>) -> String {
+    let wanted = |tid: &DetTid| thread.map_or(true, |t| t == *tid);
+    let mut out = String::new();
+    if let Some(meta) = record.metadata() {
+        out.push_str(&format!(
+            "recorded by hermit {} at {}, with --seed={} and scheduling settings {}: {}\n",
+            meta.hermit_version,
+            meta.recorded_at,
+            meta.seed,
+            meta.config_hash,
+            shell_words::join(&meta.command)
+        ));
+    }
+    let mut preemption = 0;
+    for (tid, history) in record.as_vecs() {
+        // The first entry is the priority the thread starts at, not a preemption.
+        let (start, rest) = history.split_first().unwrap();
+        if wanted(&tid) {
+            out.push_str(&format!("thread {}: priority {}", tid, start.1));
+            for (i, (time, prio)) in rest.iter().enumerate() {
+                out.push_str(&format!(", #{} {}@{}", preemption + i, prio, time));
+            }
+            out.push('\n');
+        }
+        preemption += rest.len();
+    }
+    for (i, ev) in record.clone().into_global().iter().enumerate() {
+        if wanted(&ev.dettid) {
+            out.push_str(&format_event(i, ev));
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Each preemption of a record, as the thread and the index of the preemption in that thread's
+/// history, in the order that `inspect` numbers them.
+fn preemptions(record: &PreemptionRecord) -> Vec<(DetTid, usize)> {
+    record
+        .as_vecs()
+        .into_iter()
+        .flat_map(|(tid, history)| (1..history.len()).map(move |i| (tid, i - 1)))
+        .collect()
+}
+
+/// Checks that `index` is in a schedule of `len` events.
+fn event_index(index: usize, len: usize) -> Result<usize, Error> {
+    if index < len {
+        Ok(index)
+    } else {
+        Err(Error::msg(format!(
+            "There is no event #{} in a schedule of {} events",
+            index, len
+        )))
+    }
+}
+
+impl Edits {
+    fn apply(&self, record: &mut PreemptionRecord) -> Result<(), Error> {
+        let numbered = preemptions(record);
+        let mut remove = self.remove_preempt.clone();
+        remove.sort_unstable();
+        remove.dedup();
+        // From the last, so that removing one does not shift the others of its thread.
+        for index in remove.into_iter().rev() {
+            let (tid, i) = *numbered
+                .get(index)
+                .with_context(|| format!("There is no preemption #{}", index))?;
+            assert!(record.remove_preemption(tid, i));
+        }
+
+        let events = record.global_mut();
+        for pair in self.swap.chunks(2) {
+            let i = event_index(pair[0], events.len())?;
+            let j = event_index(pair[1], events.len())?;
+            events.swap(i, j);
+        }
+        for pair in self.move_event.chunks(2) {
+            let from = event_index(pair[0], events.len())?;
+            let to = event_index(pair[1], events.len())?;
+            let event = events.remove(from);
+            events.insert(to, event);
+        }
+        if let Some(len) = self.truncate_at {
+            event_index(len, events.len() + 1)?;
+            events.truncate(len);
+        }
+
+        record
+            .validate()
+            .map_err(Error::msg)
+            .context("The edited record is not valid")
+    }
+}
+
+impl Slice {
+    fn apply(&self, record: &mut PreemptionRecord) {
+        if let Some(range) = &self.events {
+            let events = record.global_mut();
+            let end = usize::try_from(range.end).map_or(events.len(), |end| end.min(events.len()));
+            let start = usize::try_from(range.start).map_or(end, |start| start.min(end));
+            events.truncate(end);
+            events.drain(..start);
+        }
+        if !self.thread.is_empty() {
+            record.retain_threads(|tid| self.thread.contains(&tid));
+        }
+        if let Some(range) = &self.time {
+            record.retain_preemptions_between(
+                LogicalTime::from_nanos(range.start),
+                LogicalTime::from_nanos(range.end),
+            );
+            record
+                .global_mut()
+                .retain(|ev| ev.end_time.map_or(false, |t| range.contains(&t.as_nanos())));
+        }
+    }
+
+    /// True if the slice keeps the start of the schedule, for every thread.
+    fn is_prefix(&self) -> bool {
+        self.events.as_ref().map_or(true, |r| r.start == 0)
+            && self.thread.is_empty()
+            && self.time.as_ref().map_or(true, |r| r.start == 0)
+    }
+}
+
+/// What one thread did in a schedule.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ThreadSchedStats {
+    /// Events by kind, counting each repetition.
+    events: BTreeMap<&'static str, u64>,
+    /// The priorities the thread ran at, in order.
+    priorities: Vec<Priority>,
+    /// The time between consecutive preemptions.
+    slices: Vec<LogicalTime>,
+}
+
+/// A summary of a schedule, as printed by `hermit sched stats`.
+#[derive(Debug, Default)]
+struct SchedStats {
+    threads: BTreeMap<DetTid, ThreadSchedStats>,
+}
+
+impl SchedStats {
+    fn new(
+        preemptions: &BTreeMap<DetTid, Vec<(LogicalTime, Priority)>>,
+        events: &[SchedEvent],
+    ) -> Self {
+        let mut threads: BTreeMap<DetTid, ThreadSchedStats> = BTreeMap::new();
+        for (tid, history) in preemptions {
+            let stats = threads.entry(*tid).or_default();
+            stats.priorities = history.iter().map(|(_, prio)| *prio).collect();
+            stats.slices = history
+                .windows(2)
+                .map(|w| LogicalTime::from_nanos(w[1].0.as_nanos() - w[0].0.as_nanos()))
+                .collect();
+        }
+        for ev in events {
+            let stats = threads.entry(ev.dettid).or_default();
+            *stats.events.entry(op_kind(&ev.op)).or_default() += u64::from(ev.count);
+        }
+        SchedStats { threads }
+    }
+}
+
+impl std::fmt::Display for SchedStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let total: u64 = self
+            .threads
+            .values()
+            .flat_map(|stats| stats.events.values())
+            .sum();
+        writeln!(f, "{} threads, {} events", self.threads.len(), total)?;
+        for (tid, stats) in &self.threads {
+            writeln!(f, "thread {}:", tid)?;
+            let events: Vec<String> = stats
+                .events
+                .iter()
+                .map(|(kind, count)| format!("{} {}", count, kind))
+                .collect();
+            if !events.is_empty() {
+                writeln!(f, "  events: {}", events.join(", "))?;
+            }
+            writeln!(
+                f,
+                "  preemptions: {}",
+                stats.priorities.len().saturating_sub(1)
+            )?;
+            let mut slices = stats.slices.clone();
+            slices.sort();
+            if let (Some(min), Some(max)) = (slices.first(), slices.last()) {
+                writeln!(
+                    f,
+                    "  time between preemptions: min {}, median {}, max {}",
+                    min,
+                    slices[slices.len() / 2],
+                    max
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_per_thread() {
+        let tid = DetTid::from_raw(3);
+        let mut preemptions = BTreeMap::new();
+        preemptions.insert(
+            tid,
+            vec![
+                (LogicalTime::from_nanos(0), 10),
+                (LogicalTime::from_nanos(100), 20),
+                (LogicalTime::from_nanos(400), 30),
+            ],
+        );
+        let events = vec![
+            SchedEvent::branches(tid, 5),
+            SchedEvent::branches(DetTid::from_raw(4), 2),
+        ];
+
+        let stats = SchedStats::new(&preemptions, &events);
+        assert_eq!(stats.threads[&tid].priorities, vec![10, 20, 30]);
+        assert_eq!(
+            stats.threads[&tid].slices,
+            vec![LogicalTime::from_nanos(100), LogicalTime::from_nanos(300)]
+        );
+        assert_eq!(stats.threads[&tid].events["branch"], 5);
+        assert_eq!(stats.threads[&DetTid::from_raw(4)].events["branch"], 2);
+        assert!(stats.to_string().starts_with("2 threads, 7 events\n"));
+    }
+
+    #[test]
+    fn edits() {
+        let t3 = DetTid::from_raw(3);
+        let t4 = DetTid::from_raw(4);
+        let mut preemptions = BTreeMap::new();
+        preemptions.insert(
+            t3,
+            vec![
+                (LogicalTime::from_nanos(0), 10),
+                (LogicalTime::from_nanos(100), 20),
+            ],
+        );
+        preemptions.insert(
+            t4,
+            vec![
+                (LogicalTime::from_nanos(0), 15),
+                (LogicalTime::from_nanos(50), 5),
+                (LogicalTime::from_nanos(150), 25),
+            ],
+        );
+        let mut record = PreemptionRecord::from_vecs(&preemptions);
+        *record.global_mut() = (1..=5).map(|n| SchedEvent::branches(t3, n)).collect();
+
+        let edits = Edits {
+            remove_preempt: vec![2, 0],
+            swap: vec![0, 1],
+            move_event: vec![4, 0],
+            truncate_at: Some(4),
+        };
+        edits.apply(&mut record).unwrap();
+
+        let vecs = record.as_vecs();
+        assert_eq!(vecs[&t3], vec![(LogicalTime::from_nanos(0), 10)]);
+        assert_eq!(
+            vecs[&t4],
+            vec![
+                (LogicalTime::from_nanos(0), 15),
+                (LogicalTime::from_nanos(50), 5)
+            ]
+        );
+        let counts: Vec<u32> = record.into_global().iter().map(|ev| ev.count).collect();
+        assert_eq!(counts, vec![5, 2, 1, 3]);
+
+        let edits = Edits {
+            truncate_at: Some(6),
+            ..Default::default()
+        };
+        assert!(edits.apply(&mut PreemptionRecord::default()).is_err());
+    }
+
+    #[test]
+    fn slices() {
+        assert_eq!(parse_range("10..20"), Ok(10..20));
+        assert_eq!(parse_range("..20"), Ok(0..20));
+        assert_eq!(parse_range("10.."), Ok(10..u64::MAX));
+        assert!(parse_range("10").is_err());
+
+        let t3 = DetTid::from_raw(3);
+        let t4 = DetTid::from_raw(4);
+        let mut record = PreemptionRecord::default();
+        *record.global_mut() = (1..=6)
+            .map(|n| {
+                let mut ev = SchedEvent::branches(if n % 2 == 0 { t4 } else { t3 }, n);
+                ev.end_time = Some(LogicalTime::from_nanos(u64::from(n) * 100));
+                ev
+            })
+            .collect();
+
+        let slice = Slice {
+            events: Some(1..u64::MAX),
+            thread: vec![t4],
+            time: Some(0..500),
+        };
+        assert!(!slice.is_prefix());
+        slice.apply(&mut record);
+        let counts: Vec<u32> = record.into_global().iter().map(|ev| ev.count).collect();
+        assert_eq!(counts, vec![2, 4]);
+    }
+}
@@ -0,0 +1,221 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Aligning two schedules, to show where they diverge.
+
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use clap::Parser;
+use colored::Colorize;
+use detcore::types::MiniSchedEvent;
+use detcore::types::SchedEvent;
+use reverie::ExitStatus;
+use serde::Serialize;
+
+use super::global_opts::GlobalOpts;
+use super::sched::format_event;
+use super::sched::load;
+use crate::Context;
+use crate::Error;
+
+/// Command-line options for the "sched-diff" subcommand.
+#[derive(Debug, Parser)]
+pub struct SchedDiffOpts {
+    /// First schedule to compare.
+    file_a: PathBuf,
+    /// Second schedule to compare.
+    file_b: PathBuf,
+
+    /// Also write the alignment of the two schedules to this file, as JSON.
+    #[clap(long, value_name = "PATH")]
+    json: Option<PathBuf>,
+}
+
+impl SchedDiffOpts {
+    pub fn main(&self, global: &GlobalOpts) -> Result<ExitStatus, Error> {
+        let _guard = global.init_tracing();
+
+        let a = load(&self.file_a)?.into_global();
+        let b = load(&self.file_b)?.into_global();
+        let diff = SchedDiff::new(&a, &b);
+
+        if let Some(path) = &self.json {
+            let json = serde_json::to_string_pretty(&diff)?;
+            fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+
+        for line in diff.to_string().lines() {
+            if line.starts_with('-') {
+                println!("{}", line.red());
+            } else if line.starts_with('+') {
+                println!("{}", line.green());
+            } else {
+                println!("{}", line);
+            }
+        }
+
+        Ok(if diff.is_empty() {
+            ExitStatus::SUCCESS
+        } else {
+            ExitStatus::Exited(1)
+        })
+    }
+}
+
+/// A stretch of events where two schedules differ, between two events they have in common.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Region {
+    /// The index in the first schedule where the region starts.
+    pub start_a: usize,
+    /// The index in the second schedule where the region starts.
+    pub start_b: usize,
+    /// The events of the region in the first schedule.
+    pub only_a: Vec<SchedEvent>,
+    /// The events of the region in the second schedule.
+    pub only_b: Vec<SchedEvent>,
+}
+
+/// The alignment of two schedules. Events are matched by thread, operation, and count, ignoring
+/// their instruction pointers and times, which shift as soon as the schedules diverge.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SchedDiff {
+    /// How many events the first schedule has.
+    pub len_a: usize,
+    /// How many events the second schedule has.
+    pub len_b: usize,
+    /// How many events the schedules have in common, in the same order.
+    pub common: usize,
+    /// Where the schedules diverge, in order.
+    pub regions: Vec<Region>,
+}
+
+impl SchedDiff {
+    pub fn new(a: &[SchedEvent], b: &[SchedEvent]) -> Self {
+        let keys_a: Vec<MiniSchedEvent> = a.iter().map(MiniSchedEvent::from).collect();
+        let keys_b: Vec<MiniSchedEvent> = b.iter().map(MiniSchedEvent::from).collect();
+
+        let mut common = 0;
+        let mut regions = Vec::new();
+        let mut region: Option<Region> = None;
+        let (mut i, mut j) = (0, 0);
+        for result in diff::slice(&keys_a, &keys_b) {
+            match result {
+                diff::Result::Both(..) => {
+                    regions.extend(region.take());
+                    common += 1;
+                    i += 1;
+                    j += 1;
+                }
+                diff::Result::Left(_) => {
+                    region
+                        .get_or_insert_with(|| Region::new(i, j))
+                        .only_a
+                        .push(a[i].clone());
+                    i += 1;
+                }
+                diff::Result::Right(_) => {
+                    region
+                        .get_or_insert_with(|| Region::new(i, j))
+                        .only_b
+                        .push(b[j].clone());
+                    j += 1;
+                }
+            }
+        }
+        regions.extend(region);
+
+        SchedDiff {
+            len_a: a.len(),
+            len_b: b.len(),
+            common,
+            regions,
+        }
+    }
+
+    /// True if the schedules are the same.
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+}
+
+impl Region {
+    fn new(start_a: usize, start_b: usize) -> Self {
+        Region {
+            start_a,
+            start_b,
+            only_a: Vec::new(),
+            only_b: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for SchedDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} of {} and {} events in common, {} diverging regions",
+            self.common,
+            self.len_a,
+            self.len_b,
+            self.regions.len()
+        )?;
+        for region in &self.regions {
+            writeln!(f, "@@ #{} / #{} @@", region.start_a, region.start_b)?;
+            for (i, ev) in region.only_a.iter().enumerate() {
+                writeln!(f, "-{}", format_event(region.start_a + i, ev))?;
+            }
+            for (i, ev) in region.only_b.iter().enumerate() {
+                writeln!(f, "+{}", format_event(region.start_b + i, ev))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use detcore::types::LogicalTime;
+    use detcore::DetTid;
+
+    use super::*;
+
+    #[test]
+    fn aligns_around_diverging_events() {
+        let t3 = DetTid::from_raw(3);
+        let t4 = DetTid::from_raw(4);
+        let a = vec![
+            SchedEvent::branches(t3, 10),
+            SchedEvent::branches(t4, 5),
+            SchedEvent::branches(t3, 2),
+            SchedEvent::branches(t4, 1),
+        ];
+        let mut b = vec![
+            SchedEvent::branches(t3, 10),
+            SchedEvent::branches(t3, 7),
+            SchedEvent::branches(t4, 6),
+            SchedEvent::branches(t3, 2),
+            SchedEvent::branches(t4, 1),
+        ];
+        // Times are not part of the alignment.
+        b[4].end_time = Some(LogicalTime::from_nanos(100));
+
+        let diff = SchedDiff::new(&a, &b);
+        assert_eq!(diff.common, 3);
+        assert_eq!(diff.regions.len(), 1);
+        assert_eq!(diff.regions[0].start_a, 1);
+        assert_eq!(diff.regions[0].start_b, 1);
+        assert_eq!(diff.regions[0].only_a, vec![SchedEvent::branches(t4, 5)]);
+        assert_eq!(
+            diff.regions[0].only_b,
+            vec![SchedEvent::branches(t3, 7), SchedEvent::branches(t4, 6)]
+        );
+        assert!(SchedDiff::new(&a, &a).is_empty());
+    }
+}
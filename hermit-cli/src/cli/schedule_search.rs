@@ -310,7 +310,7 @@ where
 fn sched_event_with_new_count(original: &SchedEvent, new_count: u32) -> SchedEvent {
     SchedEvent {
         count: new_count,
-        ..*original
+        ..original.clone()
     }
 }
 
@@ -391,11 +391,11 @@ mod tests {
     /// This test runs a real search but with mocked out actual hermit runs.
     fn flaky_cas_sequence_schedules() {
         let passing_preemptions: PreemptionRecord = serde_json::from_slice(include_bytes!(
-            "../../../test-resources/flaky_cas_sequence_schedules-passing.json"
+            "../../test-resources/flaky_cas_sequence_schedules-passing.json"
         ))
         .expect("Failed to parse passing schedule");
         let failing_preemptions: PreemptionRecord = serde_json::from_slice(include_bytes!(
-            "../../../test-resources/flaky_cas_sequence_schedules-failing.json"
+            "../../test-resources/flaky_cas_sequence_schedules-failing.json"
         ))
         .expect("Failed to parse failing schedule");
 
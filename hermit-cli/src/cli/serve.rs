@@ -0,0 +1,306 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! A daemon which runs, verifies and analyzes programs on request, for tools written in other
+//! languages.
+//!
+//! Clients connect to a Unix socket and speak JSON-RPC 2.0, one message per line. Every method
+//! takes `{"args": [...]}`, the arguments of the matching subcommand:
+//!
+//! * `run` takes the arguments of `hermit run`, and returns the exit status and output of the
+//!   program.
+//! * `verify` takes the arguments of `hermit run`, runs the program twice, and returns whether the
+//!   runs agreed, and how they differed if not.
+//! * `analyze` takes the arguments of `hermit analyze`, and returns the report of the analysis.
+//!
+//! While a request is handled, `progress` notifications for it are sent, with its `id` and a
+//! `message`. Each connection is served on its own thread, with its requests handled in order.
+
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use clap::Parser;
+use detcore::logdiff;
+use reverie::process::ExitStatus;
+use reverie::process::Output;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+
+use super::analyze::AnalyzeOpts;
+use super::global_opts::GlobalOpts;
+use super::run::RunOpts;
+use super::verify::temp_log_file;
+use crate::Context;
+use crate::Error;
+
+/// The error codes defined by JSON-RPC 2.0.
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// The error code when the request was valid, but handling it failed.
+const FAILED: i64 = -32000;
+
+/// Serve runs, verifications and analyses over a JSON-RPC socket.
+///
+/// See the documentation of the `serve` module for the protocol.
+#[derive(Debug, Parser)]
+pub struct ServeOpts {
+    /// The Unix socket to listen on. A stale socket at this path is replaced.
+    #[clap(long, value_name = "PATH")]
+    socket: PathBuf,
+}
+
+/// A request, as sent by a client.
+#[derive(Debug, Deserialize)]
+struct Request {
+    /// Absent for a notification, which gets no response.
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Params,
+}
+
+/// The parameters of every method.
+#[derive(Debug, Default, Deserialize)]
+struct Params {
+    /// The CLI arguments of the subcommand, without the subcommand itself.
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+/// A request which could not be handled, with its JSON-RPC error code.
+#[derive(Debug)]
+struct RpcError(i64, String);
+
+impl From<Error> for RpcError {
+    fn from(err: Error) -> Self {
+        RpcError(FAILED, format!("{:#}", err))
+    }
+}
+
+impl From<clap::Error> for RpcError {
+    fn from(err: clap::Error) -> Self {
+        RpcError(INVALID_PARAMS, err.to_string())
+    }
+}
+
+/// Tells the client of the progress of the request being handled.
+type Notify = Rc<dyn Fn(&str)>;
+
+impl ServeOpts {
+    pub fn main(&self, global: &GlobalOpts) -> Result<ExitStatus, Error> {
+        if self.socket.exists() {
+            std::fs::remove_file(&self.socket)
+                .with_context(|| format!("Failed to remove {}", self.socket.display()))?;
+        }
+        let listener = UnixListener::bind(&self.socket)
+            .with_context(|| format!("Failed to listen on {}", self.socket.display()))?;
+        eprintln!(":: Listening on {}", self.socket.display());
+
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let global = global.clone();
+            std::thread::spawn(move || {
+                if let Err(err) = serve_connection(stream, &global) {
+                    eprintln!(":: Connection closed: {:#}", err);
+                }
+            });
+        }
+        Ok(ExitStatus::SUCCESS)
+    }
+}
+
+/// Handle the requests of one client until it disconnects.
+fn serve_connection(stream: UnixStream, global: &GlobalOpts) -> Result<(), Error> {
+    let reader = BufReader::new(stream.try_clone()?);
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let events = stream.try_clone()?;
+        let notify = move |id: &Value, message: &str| {
+            let event = json!({
+                "jsonrpc": "2.0",
+                "method": "progress",
+                "params": { "id": id, "message": message },
+            });
+            let _ = writeln!(&events, "{}", event);
+        };
+        if let Some(response) = respond(&line, global, notify) {
+            writeln!(&stream, "{}", response)?;
+        }
+    }
+    Ok(())
+}
+
+/// The response to one line from the client, if it needs one.
+fn respond(
+    line: &str,
+    global: &GlobalOpts,
+    notify: impl Fn(&Value, &str) + 'static,
+) -> Option<Value> {
+    let request: Request = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(err) => {
+            let error = json!({ "code": PARSE_ERROR, "message": err.to_string() });
+            return Some(json!({ "jsonrpc": "2.0", "id": null, "error": error }));
+        }
+    };
+    let id = request.id.clone();
+    let notify: Notify = {
+        let id = id.clone().unwrap_or(Value::Null);
+        Rc::new(move |message| notify(&id, message))
+    };
+    let result = match request.method.as_str() {
+        "run" => run(&request.params, global),
+        "verify" => verify(&request.params, global, notify),
+        "analyze" => analyze(&request.params, global, notify),
+        method => Err(RpcError(
+            METHOD_NOT_FOUND,
+            format!("Unknown method {:?}", method),
+        )),
+    };
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(RpcError(code, message)) => {
+            let error = json!({ "code": code, "message": message });
+            json!({ "jsonrpc": "2.0", "id": id, "error": error })
+        }
+    })
+}
+
+/// The `hermit run` options given as the parameters of a request.
+fn runopts(params: &Params) -> Result<RunOpts, RpcError> {
+    // Bogus arg 0 for CLI argument parsing:
+    let args = std::iter::once("hermit-run").chain(params.args.iter().map(String::as_str));
+    let mut ro = RunOpts::try_parse_from(args)?;
+    // Options which parse but cannot be used together are as invalid as those which do not.
+    ro.validate_args()
+        .map_err(|err| RpcError(INVALID_PARAMS, format!("{:#}", err)))?;
+    Ok(ro)
+}
+
+/// Describes the exit status and output of a run.
+fn output_json(out: &Output) -> Value {
+    let (exit_code, exit_signal) = match out.status {
+        ExitStatus::Exited(code) => (Some(code), None),
+        ExitStatus::Signaled(sig, _) => (None, Some(sig.to_string())),
+    };
+    json!({
+        "exit_code": exit_code,
+        "exit_signal": exit_signal,
+        "stdout": String::from_utf8_lossy(&out.stdout),
+        "stderr": String::from_utf8_lossy(&out.stderr),
+    })
+}
+
+fn run(params: &Params, global: &GlobalOpts) -> Result<Value, RpcError> {
+    let ro = runopts(params)?;
+    let log = temp_log_file("serve").context("Failed to create a temporary log file")?;
+    let out = ro.run_verify(log.into_file(), global)?;
+    Ok(output_json(&out))
+}
+
+fn verify(params: &Params, global: &GlobalOpts, notify: Notify) -> Result<Value, RpcError> {
+    let ro = runopts(params)?;
+    let log1 = temp_log_file("run1").context("Failed to create a temporary log file")?;
+    let log2 = temp_log_file("run2").context("Failed to create a temporary log file")?;
+    let (log1_file, log1) = log1.into_parts();
+    let (log2_file, log2) = log2.into_parts();
+    let out1 = ro.run_verify(log1_file, global)?;
+    notify("Run1 finished");
+    let out2 = ro.run_verify(log2_file, global)?;
+    notify("Run2 finished");
+
+    let mut differences = Vec::new();
+    if out1.status != out2.status {
+        differences.push("exit status".to_owned());
+    }
+    if out1.stdout != out2.stdout {
+        differences.push("stdout".to_owned());
+    }
+    if out1.stderr != out2.stderr {
+        differences.push("stderr".to_owned());
+    }
+    let opts = logdiff::LogDiffOpts {
+        strip_lines: true,
+        syscall_history: 5,
+        ..Default::default()
+    };
+    let log_report = logdiff::log_diff_nondeterminism(&log1, &log2, &opts);
+    if let Some(report) = &log_report {
+        differences.push(format!("log, from {}", report.source));
+    }
+    Ok(json!({
+        "deterministic": differences.is_empty(),
+        "differences": differences,
+        "log_report": log_report.map(|report| report.to_string()),
+        "run1": output_json(&out1),
+        "run2": output_json(&out2),
+    }))
+}
+
+fn analyze(params: &Params, global: &GlobalOpts, notify: Notify) -> Result<Value, RpcError> {
+    let args = std::iter::once("analyze").chain(params.args.iter().map(String::as_str));
+    let opts = AnalyzeOpts::try_parse_from(args)?;
+    let mut opts = opts.on_progress(move |progress| notify(&progress.to_string()));
+    let analysis = opts.analyze(global)?;
+    Ok(json!({
+        "report": analysis.report,
+        "target_schedule": analysis.target_schedule,
+        "baseline_schedule": analysis.baseline_schedule,
+        "workspace": analysis.workspace,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const GLOBAL: GlobalOpts = GlobalOpts {
+        log: None,
+        log_file: None,
+        otlp_endpoint: None,
+    };
+
+    fn error_code(response: Option<Value>) -> Value {
+        response.unwrap()["error"]["code"].clone()
+    }
+
+    #[test]
+    fn serve_rejects_bad_requests() {
+        let send = |line| respond(line, &GLOBAL, |_, _| {});
+        assert_eq!(error_code(send("{")), json!(PARSE_ERROR));
+
+        let unknown = r#"{"jsonrpc": "2.0", "id": 1, "method": "frobnicate"}"#;
+        assert_eq!(error_code(send(unknown)), json!(METHOD_NOT_FOUND));
+
+        let bad_args = r#"{"id": 2, "method": "run", "params": {"args": ["--no-such-flag"]}}"#;
+        let response = send(bad_args).unwrap();
+        assert_eq!(response["id"], json!(2));
+        assert_eq!(response["error"]["code"], json!(INVALID_PARAMS));
+
+        // These parse, but hermit refuses to run with them.
+        let args = json!(["--sched-sticky-random-param=2", "true"]);
+        let bad_config =
+            json!({ "id": 3, "method": "run", "params": { "args": args } }).to_string();
+        assert_eq!(error_code(send(bad_config.as_str())), json!(INVALID_PARAMS));
+
+        // Notifications get no response, even when they fail.
+        assert!(send(r#"{"method": "frobnicate"}"#).is_none());
+    }
+}
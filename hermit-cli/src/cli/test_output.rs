@@ -0,0 +1,142 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Recognizing the failure of a test in the output of common test frameworks.
+
+use std::fmt;
+use std::str::FromStr;
+
+use regex::Regex;
+
+/// A test framework, whose output says which of its tests failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestFramework {
+    /// Rust's libtest, as run by `cargo test`.
+    CargoTest,
+    /// GoogleTest.
+    Gtest,
+    /// pytest.
+    Pytest,
+    /// The Test Anything Protocol.
+    Tap,
+}
+
+impl TestFramework {
+    /// All of the frameworks, in the order their output is tried.
+    pub const ALL: [TestFramework; 4] = [
+        TestFramework::CargoTest,
+        TestFramework::Gtest,
+        TestFramework::Pytest,
+        TestFramework::Tap,
+    ];
+
+    /// A pattern matching the line this framework prints when `test` fails, or when any test
+    /// fails if there is no `test`.
+    fn failure_pattern(self, test: Option<&str>) -> String {
+        let name = |any: &str| test.map_or_else(|| any.to_owned(), regex::escape);
+        match self {
+            // test foo::bar ... FAILED
+            TestFramework::CargoTest => format!(r"^test {} \.\.\. FAILED$", name(r"\S+")),
+            // [  FAILED  ] Suite.Test (12 ms)
+            TestFramework::Gtest => format!(r"^\[  FAILED  \] {}( \(\d+ ms\))?$", name(r"\S+")),
+            // FAILED tests/test_foo.py::test_bar - AssertionError: ...
+            // tests/test_foo.py::test_bar FAILED                                   [ 50%]
+            TestFramework::Pytest => {
+                format!(r"^(FAILED {0}( - .*)?|{0} FAILED\b.*)$", name(r"\S+"))
+            }
+            // not ok 3 - foo bar
+            //
+            // A failure with a `# TODO` directive is expected, so it is not matched.
+            TestFramework::Tap => format!(r"^not ok \d+(( -)? {})?$", name("[^#]*")),
+        }
+    }
+
+    /// A regular expression matching the output of a run in which `test` failed, or any test
+    /// failed if there is no `test`, as reported by any of `frameworks`.
+    pub fn failure_regex(frameworks: &[TestFramework], test: Option<&str>) -> Regex {
+        let patterns: Vec<String> = frameworks
+            .iter()
+            .map(|framework| framework.failure_pattern(test))
+            .collect();
+        Regex::new(&format!("(?m){}", patterns.join("|"))).expect("escaped test name")
+    }
+}
+
+impl fmt::Display for TestFramework {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TestFramework::CargoTest => write!(f, "cargo-test"),
+            TestFramework::Gtest => write!(f, "gtest"),
+            TestFramework::Pytest => write!(f, "pytest"),
+            TestFramework::Tap => write!(f, "tap"),
+        }
+    }
+}
+
+impl FromStr for TestFramework {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "cargo-test" | "libtest" => Ok(TestFramework::CargoTest),
+            "gtest" => Ok(TestFramework::Gtest),
+            "pytest" => Ok(TestFramework::Pytest),
+            "tap" => Ok(TestFramework::Tap),
+            _ => Err(format!(
+                "Expected cargo-test | gtest | pytest | tap, could not parse: {:?}",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn recognizes_failures() {
+        let all = TestFramework::ALL;
+        let re = TestFramework::failure_regex(&all, Some("queue::test_push"));
+        assert!(re.is_match("running 2 tests\ntest queue::test_push ... FAILED\n"));
+        assert!(!re.is_match("test queue::test_push ... ok\n"));
+        assert!(!re.is_match("test queue::test_push_pop ... FAILED\n"));
+
+        let re = TestFramework::failure_regex(&all, Some("Queue.Push"));
+        assert!(re.is_match("[  FAILED  ] Queue.Push (3 ms)\n[==========] 2 tests ran.\n"));
+        assert!(!re.is_match("[       OK ] Queue.Push (3 ms)\n"));
+
+        let pytest = [TestFramework::Pytest];
+        let re = TestFramework::failure_regex(&pytest, Some("test_q.py::test_push"));
+        assert!(re.is_match("FAILED test_q.py::test_push - assert 1 == 2\n"));
+        assert!(re.is_match("test_q.py::test_push FAILED                    [100%]\n"));
+        assert!(!re.is_match("test_q.py::test_push PASSED                    [100%]\n"));
+
+        let re = TestFramework::failure_regex(&[TestFramework::Tap], Some("pushes in order"));
+        assert!(re.is_match("1..2\nok 1 - pops\nnot ok 2 - pushes in order\n"));
+        assert!(!re.is_match("not ok 2 - pushes in order # TODO\n"));
+    }
+
+    #[test]
+    fn recognizes_any_failure() {
+        use TestFramework::*;
+        let cases = [
+            (CargoTest, "test a::b ... FAILED\n", true),
+            (CargoTest, "test a::b ... ok\n", false),
+            (Gtest, "[  FAILED  ] A.B (0 ms)\n", true),
+            (Gtest, "[  FAILED  ] 1 test, listed below:\n", false),
+            (Pytest, "FAILED t.py::test_b - assert 0\n", true),
+            (Tap, "not ok 1\n", true),
+            (Tap, "ok 1 - a\nnot ok 2 - b # TODO\n", false),
+        ];
+        for (framework, output, failed) in cases {
+            let re = TestFramework::failure_regex(&[framework], None);
+            assert_eq!(re.is_match(output), failed, "{} on {:?}", framework, output);
+        }
+    }
+}
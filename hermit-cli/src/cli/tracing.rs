@@ -0,0 +1,240 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::io;
+use std::io::BufWriter;
+
+#[cfg(not(feature = "otlp"))]
+use anyhow::bail;
+use detcore::SPAN_TARGET;
+#[cfg(feature = "otlp")]
+use opentelemetry::sdk::trace::Tracer;
+#[cfg(feature = "otlp")]
+use opentelemetry::sdk::Resource;
+#[cfg(feature = "otlp")]
+use opentelemetry::KeyValue;
+#[cfg(feature = "otlp")]
+use opentelemetry_otlp::WithExportConfig;
+#[cfg(feature = "otlp")]
+use tokio::runtime::Runtime;
+use tracing::dispatcher::DefaultGuard;
+use tracing::metadata::LevelFilter;
+use tracing::Dispatch;
+use tracing_appender::non_blocking::NonBlockingBuilder;
+use tracing_appender::non_blocking::WorkerGuard;
+#[cfg(feature = "otlp")]
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::MakeWriter;
+#[cfg(not(feature = "otlp"))]
+use tracing_subscriber::layer::Identity;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::Layer;
+use tracing_subscriber::Registry;
+
+use crate::Error;
+
+const DEFAULT_TRACE_LEVEL: LevelFilter = LevelFilter::WARN;
+
+/// Exports spans to an OpenTelemetry collector over OTLP, in batches, from a runtime of its own.
+#[cfg(feature = "otlp")]
+pub struct OtlpExporter {
+    tracer: Tracer,
+    runtime: Runtime,
+}
+
+#[cfg(feature = "otlp")]
+impl OtlpExporter {
+    /// Start exporting to the gRPC endpoint of a collector, e.g. `http://localhost:4317`.
+    pub fn start(endpoint: &str) -> Result<Self, Error> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("otlp-exporter")
+            .enable_all()
+            .build()?;
+        let exporter = opentelemetry_otlp::new_exporter()
+            .tonic()
+            .with_endpoint(endpoint);
+        let config = opentelemetry::sdk::trace::config()
+            .with_resource(Resource::new([KeyValue::new("service.name", "hermit")]));
+        let tracer = {
+            // The exporter spawns its tasks on the current runtime.
+            let _enter = runtime.enter();
+            opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(exporter)
+                .with_trace_config(config)
+                .install_batch(opentelemetry::runtime::Tokio)?
+        };
+        Ok(OtlpExporter { tracer, runtime })
+    }
+
+    /// A layer exporting hermit's spans.
+    fn layer<S>(&self) -> impl Layer<S>
+    where
+        S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    {
+        let spans = Targets::new().with_target(SPAN_TARGET, LevelFilter::TRACE);
+        tracing_opentelemetry::layer()
+            .with_tracer(self.tracer.clone())
+            .with_filter(spans)
+    }
+}
+
+#[cfg(feature = "otlp")]
+impl Drop for OtlpExporter {
+    fn drop(&mut self) {
+        // Blocks until the spans which have not been sent yet are.
+        opentelemetry::global::shutdown_tracer_provider();
+    }
+}
+
+/// Stands in for the exporter when hermit is built without the `otlp` feature, so that none is
+/// ever made.
+#[cfg(not(feature = "otlp"))]
+pub enum OtlpExporter {}
+
+#[cfg(not(feature = "otlp"))]
+impl OtlpExporter {
+    /// Fails, as there is nothing to export with.
+    pub fn start(_endpoint: &str) -> Result<Self, Error> {
+        bail!("Exporting spans needs hermit built with the `otlp` feature")
+    }
+
+    fn layer(&self) -> Identity {
+        match *self {}
+    }
+}
+
+/// Keeps tracing going until dropped, by which time everything logged has been written, and
+/// every span exported.
+#[must_use = "Tracing stops when the guard is dropped"]
+pub struct TracingGuard {
+    _default: Option<DefaultGuard>,
+    _worker: Option<WorkerGuard>,
+    _otlp: Option<OtlpExporter>,
+}
+
+/// Returns a subscriber formatting what `filter` lets through to `writer`, which also exports
+/// spans with `otlp`, if given. Spans are left out of the formatted lines.
+fn subscriber<W>(filter: EnvFilter, writer: W, ansi: bool, otlp: Option<&OtlpExporter>) -> Dispatch
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let filter = filter.add_directive(format!("{}=off", SPAN_TARGET).parse().expect("directive"));
+    match otlp {
+        None => tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(writer)
+            .with_ansi(ansi)
+            .finish()
+            .into(),
+        Some(otlp) => {
+            let fmt = tracing_subscriber::fmt::layer()
+                .with_writer(writer)
+                .with_ansi(ansi)
+                .with_filter(filter);
+            Registry::default().with(fmt).with(otlp.layer()).into()
+        }
+    }
+}
+
+/// How many lines may wait to be written to a log file before logging blocks.
+const LOG_QUEUE_LINES: usize = 64 * 1024;
+
+/// Returns a non-blocking subscriber for logging to a file.
+///
+/// Lines are formatted on the thread that logs them, and written by a dedicated thread, through
+/// a buffer, in the order they were logged. None are ever dropped, as log-diff depends on every
+/// line: when the queue is full, logging blocks until the writer catches up.
+fn file_subscriber(
+    level: LevelFilter,
+    f: impl io::Write + Send + 'static,
+    otlp: Option<&OtlpExporter>,
+) -> (Dispatch, WorkerGuard) {
+    let filter = EnvFilter::from_default_env()
+        .add_directive("tokio=debug".parse().expect("correct directive"))
+        .add_directive(level.into());
+
+    let (writer, guard) = NonBlockingBuilder::default()
+        .lossy(false)
+        .buffered_lines_limit(LOG_QUEUE_LINES)
+        .finish(BufWriter::new(f));
+
+    (subscriber(filter, writer, false, otlp), guard)
+}
+
+/// Initializes tracing to the given file `f`, and to `otlp` if given.  Everything logged is
+/// written by the time the returned guard is dropped.
+pub fn init_file_tracing(
+    level: Option<LevelFilter>,
+    f: impl io::Write + Send + 'static,
+    otlp: Option<OtlpExporter>,
+) -> TracingGuard {
+    let level = level.unwrap_or(DEFAULT_TRACE_LEVEL);
+
+    let (subscriber, guard) = file_subscriber(level, f, otlp.as_ref());
+
+    subscriber
+        .clone()
+        .try_init()
+        .expect("global tracing subscriber to install");
+
+    // A process forked from one which traces this thread only, as `hermit analyze` does when it
+    // exports spans, would otherwise keep sending this thread's events there.
+    TracingGuard {
+        _default: Some(tracing::dispatcher::set_default(&subscriber)),
+        _worker: Some(guard),
+        _otlp: otlp,
+    }
+}
+
+/// Returns a tracing subscriber that logs to `stderr`, and exports spans with `otlp` if given.
+///
+/// NOTE: Writes to stderr are unbuffered, so this may be slow.
+pub fn stderr_subscriber(level: Option<LevelFilter>, otlp: Option<&OtlpExporter>) -> Dispatch {
+    let level = level.unwrap_or(DEFAULT_TRACE_LEVEL);
+
+    let filter = EnvFilter::from_default_env()
+        .add_directive("tokio=debug".parse().expect("correct directive"))
+        .add_directive(level.into());
+    subscriber(filter, io::stderr, atty::is(atty::Stream::Stderr), otlp)
+}
+
+/// Initializes tracing to `stderr`, and to `otlp` if given.
+///
+/// NOTE: Writes to stderr are unbuffered, so this may be slow.
+pub fn init_stderr_tracing(level: Option<LevelFilter>, otlp: Option<OtlpExporter>) -> TracingGuard {
+    // Create an extra, pointless thread just so that our thread number starts at the same DetTid
+    // "3" that the `init_file_tracing` option does.
+    std::thread::spawn(|| {}).join().unwrap();
+
+    stderr_subscriber(level, otlp.as_ref())
+        .try_init()
+        .expect("global tracing subscriber to install");
+
+    TracingGuard {
+        _default: None,
+        _worker: None,
+        _otlp: otlp,
+    }
+}
+
+/// Exports the spans of the current thread with `otlp`, without logging anything.
+///
+/// This is for commands which fork runs that initialize tracing of their own, and so must not
+/// install a global subscriber.
+pub fn init_span_export(otlp: OtlpExporter) -> TracingGuard {
+    let subscriber = Registry::default().with(otlp.layer());
+    TracingGuard {
+        _default: Some(tracing::subscriber::set_default(subscriber)),
+        _worker: None,
+        _otlp: Some(otlp),
+    }
+}
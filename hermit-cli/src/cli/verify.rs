@@ -0,0 +1,302 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use colored::Colorize;
+use detcore::RunSummary;
+use detcore::logdiff;
+use pretty_assertions::Comparison;
+use reverie::process::ExitStatus;
+use reverie::process::Output;
+use tempfile::NamedTempFile;
+use tempfile::TempPath;
+use tracing::metadata::LevelFilter;
+
+use super::global_opts::GlobalOpts;
+use crate::Context;
+use crate::Error;
+
+pub fn temp_log_file(name: &str) -> io::Result<NamedTempFile> {
+    tempfile::Builder::new()
+        .prefix(&format!("{}_log_", name))
+        .rand_bytes(5)
+        .tempfile()
+}
+
+pub fn temp_log_files(name1: &str, name2: &str) -> io::Result<(NamedTempFile, NamedTempFile)> {
+    Ok((temp_log_file(name1)?, temp_log_file(name2)?))
+}
+
+/// How the logs of runs which should be identical are compared.
+fn verify_log_diff_opts() -> logdiff::LogDiffOpts {
+    // TODO(T103558443) stripping logs until this task is completely closed:
+    logdiff::LogDiffOpts {
+        strip_lines: true,
+        syscall_history: 5,
+        ..Default::default()
+    }
+}
+
+pub fn setup_double_run(
+    global: &GlobalOpts,
+    name1: &str,
+    name2: &str,
+) -> ((GlobalOpts, NamedTempFile), (GlobalOpts, NamedTempFile)) {
+    let (file1, file2) = temp_log_files(name1, name2).unwrap();
+
+    let path1 = PathBuf::from(file1.path());
+    let path2 = PathBuf::from(file2.path());
+
+    // Override global settings.  Unfortunately we lose the log output to the
+    // screen.
+    let mut global = global.clone();
+    global.log_file = Some(path1);
+    global.log = Some(LevelFilter::DEBUG);
+
+    let mut global2 = global.clone();
+    global2.log_file = Some(path2);
+    ((global, file1), (global2, file2))
+}
+
+pub fn compare_two_runs(
+    out1: &Output,
+    log1: TempPath,
+    out2: &Output,
+    log2: TempPath,
+    success_msg: &str,
+    failure_msg: &str,
+) -> Result<ExitStatus, Error> {
+    let mut failed = compare_outputs(out1, out2);
+
+    eprintln!(
+        ":: {} {} and {}",
+        "Comparing logs...".yellow().bold(),
+        log1.display(),
+        log2.display()
+    );
+
+    if let Some(report) =
+        logdiff::log_diff_nondeterminism(log1.as_ref(), log2.as_ref(), &verify_log_diff_opts())
+    {
+        failed = true;
+        eprintln!(":: {}", "Log differences found between runs.".red().bold());
+        eprint!("{}", report);
+        eprintln!(
+            ":: {}: {} {}",
+            "Respective Logs retained for further inspection".red(),
+            log1.display(),
+            log2.display()
+        );
+    }
+
+    if failed {
+        eprintln!(":: {}", failure_msg.red().bold());
+        let _ = log1.keep()?;
+        let _ = log2.keep()?;
+        Err(Error::msg(
+            "Mismatch between run1 and run2 outputs (logs retained).",
+        ))
+    } else {
+        // Allow the NamedTempFiles to be deleted in this case:
+        eprintln!(":: {}", success_msg.green().bold());
+        Ok(out2.status)
+    }
+}
+
+/// Compares each of many runs with the first, printing their differences, then a summary of which
+/// runs disagree with the first.  The logs of those runs, and of the first, are retained.
+pub fn compare_many_runs(
+    runs: Vec<(Output, TempPath)>,
+    success_msg: &str,
+    failure_msg: &str,
+) -> Result<ExitStatus, Error> {
+    let total = runs.len();
+    let mut runs = runs.into_iter();
+    let (out1, log1) = runs.next().expect("at least one run");
+    let mut disagreements = Vec::new();
+    let mut retained = Vec::new();
+    for (ix, (out, log)) in runs.enumerate() {
+        let run = ix + 2;
+        eprintln!(
+            ":: {} {} and {}",
+            format!("Comparing run1 and run{}...", run).yellow().bold(),
+            log1.display(),
+            log.display()
+        );
+        let mut differs = Vec::new();
+        if compare_outputs(&out1, &out) {
+            differs.push("its output".to_string());
+        }
+        let opts = verify_log_diff_opts();
+        if let Some(report) = logdiff::log_diff_nondeterminism(log1.as_ref(), log.as_ref(), &opts) {
+            eprint!("{}", report);
+            differs.push(format!("its log, from {}", report.source));
+        }
+        if !differs.is_empty() {
+            disagreements.push((run, differs.join(" and ")));
+            retained.push(log.keep()?);
+        }
+    }
+
+    if disagreements.is_empty() {
+        eprintln!(
+            ":: {} All {} runs agree.",
+            success_msg.green().bold(),
+            total
+        );
+        return Ok(out1.status);
+    }
+    let summary = format!(
+        "{} of {} runs disagree with run1:",
+        disagreements.len(),
+        total
+    );
+    eprintln!(":: {}", summary.red().bold());
+    for (run, differs) in &disagreements {
+        eprintln!("  run{} differs in {}", run, differs);
+    }
+    let log1 = log1.keep()?;
+    eprintln!(
+        ":: {}: {}",
+        "Respective Logs retained for further inspection".red(),
+        std::iter::once(&log1)
+            .chain(&retained)
+            .map(|log| log.display().to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+    eprintln!(":: {}", failure_msg.red().bold());
+    Err(Error::msg(format!(
+        "Mismatch between {} of {} runs and the first (logs retained).",
+        disagreements.len(),
+        total
+    )))
+}
+
+/// Compares a run with reference runs, which were perhaps made on another machine or at another
+/// time. Each reference is a log, or a `--summary-json` if its path ends in `.json`, which is
+/// compared with the run's `summary`. The run's log is retained if it differs from any.
+pub fn compare_against_references(
+    out: &Output,
+    log: TempPath,
+    summary: &Path,
+    references: &[PathBuf],
+) -> Result<ExitStatus, Error> {
+    let read_summary = |path: &Path| -> Result<RunSummary, Error> {
+        let file = fs::File::open(path)
+            .with_context(|| format!("Failed to open summary {}", path.display()))?;
+        serde_json::from_reader(file)
+            .with_context(|| format!("Failed to parse summary {}", path.display()))
+    };
+
+    let mut failed = Vec::new();
+    for reference in references {
+        eprintln!(
+            ":: {} {}",
+            "Comparing with reference".yellow().bold(),
+            reference.display()
+        );
+        if reference.extension().map_or(false, |ext| ext == "json") {
+            let differences = read_summary(summary)?.differences(&read_summary(reference)?);
+            for difference in &differences {
+                eprintln!("Mismatch in {}", difference);
+            }
+            if !differences.is_empty() {
+                failed.push(reference);
+            }
+        } else if let Some(report) =
+            logdiff::log_diff_nondeterminism(reference, log.as_ref(), &verify_log_diff_opts())
+        {
+            eprint!("{}", report);
+            failed.push(reference);
+        }
+    }
+
+    if failed.is_empty() {
+        eprintln!(
+            ":: {}",
+            "Success: identical to the references.".green().bold()
+        );
+        return Ok(out.status);
+    }
+    for reference in &failed {
+        eprintln!(":: {}: {}", "Differs from".red(), reference.display());
+    }
+    let log = log.keep()?;
+    eprintln!(
+        ":: {}: {}",
+        "Log retained for further inspection".red(),
+        log.display()
+    );
+    eprintln!(":: {}", "Failure: not reproduced.".red().bold());
+    Err(Error::msg(format!(
+        "Mismatch between the run and {} of {} references (log retained).",
+        failed.len(),
+        references.len()
+    )))
+}
+
+/// Prints any differences in stdout, stderr, or exit status between two runs. Returns true if
+/// there were any.
+pub fn compare_outputs(out1: &Output, out2: &Output) -> bool {
+    let mut failed = false;
+
+    if out1.stdout != out2.stdout {
+        failed = true;
+        eprintln!("Mismatch in stdout between runs:",);
+        let str1 = String::from_utf8_lossy(&out1.stdout);
+        let str2 = String::from_utf8_lossy(&out2.stdout);
+        if str1.lines().count() > 1 {
+            display_diff(&str1, &str2);
+        } else {
+            eprintln!("{}", Comparison::new(&str1, &str2));
+        }
+    }
+
+    if out1.stderr != out2.stderr {
+        failed = true;
+        eprintln!("Mismatch in stderr between runs:",);
+        let str1 = String::from_utf8_lossy(&out1.stderr);
+        let str2 = String::from_utf8_lossy(&out2.stderr);
+        if str1.lines().count() > 1 {
+            display_diff(&str1, &str2);
+        } else {
+            eprintln!("{}", Comparison::new(&str1, &str2));
+        }
+    }
+
+    if out1.status != out2.status {
+        failed = true;
+        eprintln!(
+            "Mismatch in exit status between runs: {}",
+            Comparison::new(&out1.status, &out2.status)
+        );
+    }
+
+    failed
+}
+
+fn display_diff(left: &str, right: &str) {
+    for result in diff::lines(left, right) {
+        match result {
+            diff::Result::Left(s) => {
+                eprintln!("- {}", s.red());
+            }
+            diff::Result::Right(s) => {
+                eprintln!("+ {}", s.green());
+            }
+            diff::Result::Both(s, _) => {
+                eprintln!("  {}", s);
+            }
+        }
+    }
+}
@@ -27,7 +27,7 @@ impl Version {
     }
 
     /// Computes the version string from the build info.
-    pub fn new() -> Self {
+    fn new() -> Self {
         #[cfg(fbcode_build)]
         {
             use build_info::BuildInfo;
@@ -0,0 +1,308 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! A container that is set up once and then forks a process for each run, so
+//! that a series of runs with the same container options only pays for the
+//! setup of the first.
+//!
+//! The runs share the container, so anything a run leaves outside of `/tmp`
+//! (which is cleared before each run) can be seen by the runs after it.
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::IoSlice;
+use std::io::IoSliceMut;
+use std::io::Read;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::path::PathBuf;
+use std::thread::JoinHandle;
+
+use clap::Parser;
+use nix::fcntl::OFlag;
+use nix::sys::socket::recvmsg;
+use nix::sys::socket::sendmsg;
+use nix::sys::socket::socketpair;
+use nix::sys::socket::AddressFamily;
+use nix::sys::socket::ControlMessage;
+use nix::sys::socket::ControlMessageOwned;
+use nix::sys::socket::MsgFlags;
+use nix::sys::socket::SockFlag;
+use nix::sys::socket::SockType;
+use nix::sys::wait::waitpid;
+use nix::unistd::close;
+use nix::unistd::fork;
+use nix::unistd::pipe2;
+use nix::unistd::ForkResult;
+use reverie::process::Output;
+use serde::Deserialize;
+use serde::Serialize;
+
+use super::container::with_container;
+use super::global_opts::GlobalOpts;
+use super::run::RunOpts;
+use crate::Context;
+use crate::Error;
+use crate::SerializableError;
+
+/// The largest request the zygote accepts.
+const MAX_REQUEST: usize = 1 << 20;
+
+/// A run, as sent to the zygote. The log file and the write end of a pipe for
+/// the response are passed along with it.
+#[derive(Serialize, Deserialize)]
+struct Request {
+    /// The arguments of `hermit run`, as printed by `RunOpts`.
+    args: Vec<String>,
+}
+
+/// A container waiting to fork runs.
+#[derive(Debug)]
+pub struct Zygote {
+    /// Our end of the control socket. Closing it tells the zygote to exit.
+    control: Option<File>,
+
+    /// The thread waiting on the container.
+    server: Option<JoinHandle<Result<(), Error>>>,
+
+    /// The container's `/tmp`.
+    tmpfs: tempfile::TempDir,
+
+    /// Paths in `tmpfs` which are mounted over, and must be kept.
+    mount_points: Vec<PathBuf>,
+
+    /// The container's `/etc` files.
+    _etc: tempfile::TempDir,
+}
+
+impl Zygote {
+    /// Sets up a container with the container options of `base`. Runs sent to
+    /// it must use the same container options.
+    pub fn start(base: &RunOpts, global: &GlobalOpts) -> Result<Self, Error> {
+        let tmpfs = tempfile::TempDir::new()?;
+        let etc = tempfile::TempDir::new()?;
+
+        let mut container = base.container(tmpfs.path(), etc.path())?;
+        let mount_points = base
+            .mounts(tmpfs.path())?
+            .iter()
+            .filter_map(|mount| mount.get_target().strip_prefix(tmpfs.path()).ok())
+            .map(Path::to_path_buf)
+            .collect();
+
+        let (control, server) = socketpair(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )
+        .context("Failed to create the zygote's control socket")?;
+        // Safe because nothing else owns the new sockets.
+        let (control, server) = unsafe { (File::from_raw_fd(control), File::from_raw_fd(server)) };
+
+        let global = global.clone();
+        let control_fd = control.as_raw_fd();
+        let server = std::thread::spawn(move || {
+            with_container(&mut container, || serve(&server, control_fd, &global))
+        });
+
+        Ok(Self {
+            control: Some(control),
+            server: Some(server),
+            tmpfs,
+            mount_points,
+            _etc: etc,
+        })
+    }
+
+    /// Runs the guest with `runopts`, as `RunOpts::run_verify` would, but in
+    /// the zygote's container.
+    pub fn run(&self, runopts: &RunOpts, log_file: File) -> Result<Output, Error> {
+        clear_dir(self.tmpfs.path(), &self.mount_points)
+            .context("Failed to clear the zygote's /tmp")?;
+
+        let request = Request {
+            args: shell_words::split(&runopts.to_string())?,
+        };
+        let request = serde_json::to_vec(&request)?;
+
+        let (reader, writer) = pipe2(OFlag::O_CLOEXEC)?;
+        // Safe because nothing else owns the new pipe.
+        let (mut reader, writer) =
+            unsafe { (File::from_raw_fd(reader), File::from_raw_fd(writer)) };
+
+        let control = self.control.as_ref().unwrap();
+        sendmsg::<()>(
+            control.as_raw_fd(),
+            &[IoSlice::new(&request)],
+            &[ControlMessage::ScmRights(&[
+                log_file.as_raw_fd(),
+                writer.as_raw_fd(),
+            ])],
+            MsgFlags::empty(),
+            None,
+        )
+        .context("Failed to send a run to the zygote")?;
+
+        // Only the forked run may hold the write end, so that we see the end
+        // of the response when it exits.
+        drop(writer);
+        drop(log_file);
+
+        let mut response = Vec::new();
+        reader.read_to_end(&mut response)?;
+        if response.is_empty() {
+            return Err(Error::msg("The zygote exited without finishing the run"));
+        }
+
+        let result: Result<Output, SerializableError> = serde_json::from_slice(&response)?;
+        Ok(result?)
+    }
+}
+
+impl Drop for Zygote {
+    fn drop(&mut self) {
+        // Closing the control socket stops the zygote.
+        self.control.take();
+        if let Some(server) = self.server.take() {
+            match server.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => tracing::warn!("The zygote failed: {:#}", err),
+                Err(_) => tracing::warn!("The zygote thread panicked"),
+            }
+        }
+    }
+}
+
+/// Forks a run for each request on `socket`, until it is closed. Runs inside
+/// the container, which starts with a copy of the caller's end of the socket,
+/// `control`. That copy is closed first, so that closing the caller's end is
+/// seen here.
+fn serve(socket: &File, control: RawFd, global: &GlobalOpts) -> Result<(), Error> {
+    close(control)?;
+
+    let mut buf = vec![0u8; MAX_REQUEST];
+    loop {
+        let mut cmsg = nix::cmsg_space!([RawFd; 2]);
+        let (len, fds) = {
+            let mut iov = [IoSliceMut::new(&mut buf)];
+            let msg = recvmsg::<()>(
+                socket.as_raw_fd(),
+                &mut iov,
+                Some(&mut cmsg),
+                MsgFlags::MSG_CMSG_CLOEXEC,
+            )?;
+            let fds: Vec<RawFd> = msg
+                .cmsgs()
+                .flat_map(|cmsg| match cmsg {
+                    ControlMessageOwned::ScmRights(fds) => fds,
+                    _ => Vec::new(),
+                })
+                .collect();
+            (msg.bytes, fds)
+        };
+
+        if len == 0 {
+            // The control socket was closed.
+            return Ok(());
+        }
+
+        // Safe because the fds were just received, and nothing else owns them.
+        let mut files: Vec<File> = fds
+            .into_iter()
+            .map(|fd| unsafe { File::from_raw_fd(fd) })
+            .collect();
+        if files.len() != 2 {
+            tracing::warn!("Ignoring a zygote request with {} fds", files.len());
+            continue;
+        }
+        let response = files.pop().unwrap();
+        let log_file = files.pop().unwrap();
+
+        // Safe because the container runs nothing but this thread.
+        match unsafe { fork() }? {
+            ForkResult::Child => {
+                let result =
+                    run_request(&buf[..len], log_file, global).map_err(SerializableError::from);
+                let code = match serde_json::to_writer(response, &result) {
+                    Ok(()) => 0,
+                    Err(_) => 1,
+                };
+                std::process::exit(code);
+            }
+            ForkResult::Parent { child } => {
+                drop(response);
+                drop(log_file);
+                waitpid(child, None)?;
+            }
+        }
+    }
+}
+
+/// Runs the guest for a single request, in a process forked from the zygote.
+fn run_request(request: &[u8], log_file: File, global: &GlobalOpts) -> Result<Output, Error> {
+    let request: Request = serde_json::from_slice(request)?;
+    let mut runopts =
+        RunOpts::try_parse_from(std::iter::once("hermit-run".to_owned()).chain(request.args))?;
+    runopts.validate_args()?;
+    runopts.run_verify_in_container(&mut Some(log_file), global)
+}
+
+/// Removes everything in `dir` except the paths in `keep`, which are relative
+/// to `dir`, and the directories leading to them.
+fn clear_dir(dir: &Path, keep: &[PathBuf]) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let relative = path.strip_prefix(dir).unwrap();
+        if keep.iter().any(|k| k == relative) {
+            continue;
+        }
+        let inner: Vec<PathBuf> = keep
+            .iter()
+            .filter_map(|k| k.strip_prefix(relative).ok())
+            .map(Path::to_path_buf)
+            .collect();
+        if !inner.is_empty() {
+            clear_dir(&path, &inner)?;
+        } else if path.symlink_metadata()?.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clear_dir_keeps_mount_points() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path();
+        fs::create_dir_all(path.join("data/inputs")).unwrap();
+        fs::create_dir_all(path.join("data/scratch")).unwrap();
+        fs::create_dir_all(path.join("leftover/dir")).unwrap();
+        fs::write(path.join("data/inputs/a"), "a").unwrap();
+        fs::write(path.join("data/output"), "").unwrap();
+        fs::write(path.join("config"), "").unwrap();
+        fs::write(path.join("leftover/dir/file"), "").unwrap();
+
+        clear_dir(path, &["data/inputs".into(), "config".into()]).unwrap();
+
+        assert!(path.join("data/inputs/a").is_file());
+        assert!(path.join("config").is_file());
+        assert!(!path.join("data/output").exists());
+        assert!(!path.join("data/scratch").exists());
+        assert!(!path.join("leftover").exists());
+    }
+}
@@ -0,0 +1,293 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Running commands deterministically from other Rust programs, without going through the
+//! `hermit` command line and parsing what it prints.
+//!
+//! ```no_run
+//! use hermit::Command;
+//! use hermit::Container;
+//!
+//! let result = Container::builder()
+//!     .command(Command::new("date"))
+//!     .seed(42)
+//!     .record_schedule("/tmp/date.sched.json")
+//!     .run()?;
+//! println!("{}", String::from_utf8_lossy(&result.stdout));
+//! # Ok::<(), hermit::Error>(())
+//! ```
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use detcore::preemptions::PreemptionRecord;
+use detcore::Instrument;
+use detcore::RunSummary;
+use detcore::SchedulerHook;
+use rand::Rng;
+use reverie::process;
+use reverie::process::Command;
+use reverie::process::Mount;
+use reverie::process::Namespace;
+use reverie::ExitStatus;
+
+use crate::error::Context;
+use crate::error::Error;
+use crate::error::SerializableError;
+use crate::DetConfig;
+
+/// A container which runs one command deterministically, as `hermit run` would.
+pub struct Container {
+    command: Command,
+    config: DetConfig,
+    pin_threads: bool,
+    mounts: Vec<Mount>,
+    networking: bool,
+    instruments: Vec<Arc<dyn Instrument>>,
+    hooks: Vec<Box<dyn SchedulerHook>>,
+}
+
+/// Configures a [`Container`].  Unless told otherwise, the container has the settings of a plain
+/// `hermit run`: threads are sequentialized and time is virtualized.
+pub struct ContainerBuilder {
+    command: Option<Command>,
+    config: DetConfig,
+    mounts: Vec<Mount>,
+    networking: bool,
+    instruments: Vec<Arc<dyn Instrument>>,
+    hooks: Vec<Box<dyn SchedulerHook>>,
+}
+
+/// What happened when a [`Container`] ran its command.
+#[derive(Debug)]
+pub struct RunResult {
+    /// How the command exited.
+    pub status: ExitStatus,
+    /// Everything the command wrote to stdout.
+    pub stdout: Vec<u8>,
+    /// Everything the command wrote to stderr.
+    pub stderr: Vec<u8>,
+    /// A summary of the run, as written by `hermit run --summary-json`.
+    pub summary: RunSummary,
+    /// The schedule of the run, if it was recorded with
+    /// [`ContainerBuilder::record_schedule`].
+    pub schedule: Option<PreemptionRecord>,
+}
+
+impl Container {
+    /// Start configuring a container.
+    pub fn builder() -> ContainerBuilder {
+        ContainerBuilder::default()
+    }
+
+    /// Run the command to completion, capturing its output.
+    pub fn run(self) -> Result<RunResult, Error> {
+        let mut sandbox = process::Container::new();
+        sandbox
+            .unshare(Namespace::PID | Namespace::IPC)
+            .map_root()
+            .hostname(&self.config.hostname)
+            .domainname("local")
+            .mount(Mount::proc());
+        if !self.networking {
+            sandbox.local_networking_only();
+        }
+        if self.pin_threads {
+            let core = rand::thread_rng().gen_range(0..num_cpus::get());
+            tracing::info!("Pinning tracer and guest threads to core {}", core);
+            sandbox.affinity(core);
+        }
+        sandbox.mounts(self.mounts);
+
+        let schedule_path = self.config.record_preemptions_to.clone();
+        let mut job = Some((self.command, self.config, self.instruments, self.hooks));
+        let (output, summary) = sandbox
+            .run(|| {
+                let (command, config, instruments, hooks) =
+                    job.take().expect("the container runs once");
+                // Registered in the container's own process, for its run alone, so they are not
+                // left behind in ours.
+                for instrument in instruments {
+                    detcore::register_instrument(instrument);
+                }
+                for hook in hooks {
+                    detcore::register_hook(hook);
+                }
+                crate::run_with_summary(command, config).map_err(SerializableError::from)
+            })
+            .context("Sandbox container exited unexpectedly")??;
+
+        let schedule = match schedule_path {
+            Some(path) => {
+                let bytes = fs::read(&path)
+                    .with_context(|| format!("Failed to read schedule {}", path.display()))?;
+                Some(PreemptionRecord::from_bytes(&bytes).map_err(|e| anyhow!(e))?)
+            }
+            None => None,
+        };
+        Ok(RunResult {
+            status: output.status,
+            stdout: output.stdout,
+            stderr: output.stderr,
+            summary,
+            schedule,
+        })
+    }
+}
+
+impl Default for ContainerBuilder {
+    fn default() -> Self {
+        let mut config = DetConfig::default();
+        config.has_uts_namespace = true;
+        config.sequentialize_threads = true;
+        config.deterministic_io = true;
+        config.virtualize_time = true;
+        config.virtualize_metadata = true;
+        config.virtualize_cpuid = true;
+        ContainerBuilder {
+            command: None,
+            config,
+            mounts: Vec::new(),
+            networking: true,
+            instruments: Vec::new(),
+            hooks: Vec::new(),
+        }
+    }
+}
+
+impl ContainerBuilder {
+    /// The command to run.
+    pub fn command(mut self, command: Command) -> Self {
+        self.command = Some(command);
+        self
+    }
+
+    /// Seed the randomness the command is given, like `--seed`.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.config.seed = seed;
+        self
+    }
+
+    /// Seed the scheduler's choices separately, like `--sched-seed`.
+    pub fn sched_seed(mut self, seed: u64) -> Self {
+        self.config.sched_seed = Some(seed);
+        self
+    }
+
+    /// Randomize the schedule, like `--chaos`.
+    pub fn chaos(mut self, chaos: bool) -> Self {
+        self.config.chaos = chaos;
+        self
+    }
+
+    /// Record the schedule to this file, like `--record-preemptions-to`.  It is also read back
+    /// into [`RunResult::schedule`].
+    pub fn record_schedule(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.record_preemptions_to = Some(path.into());
+        self
+    }
+
+    /// Replay the schedule recorded in this file, like `--replay-schedule-from`.
+    pub fn replay_schedule(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.replay_schedule_from = Some(path.into());
+        self
+    }
+
+    /// Mount something into the container, like `--mount` and `--bind`.
+    pub fn mount(mut self, mount: Mount) -> Self {
+        self.mounts.push(mount);
+        self
+    }
+
+    /// Cut off external networking, like `--no-networking`.
+    pub fn no_networking(mut self) -> Self {
+        self.networking = false;
+        self
+    }
+
+    /// Observe the guest with this instrument, alongside detcore, like `--tool`.
+    pub fn instrument(mut self, instrument: Arc<dyn Instrument>) -> Self {
+        self.instruments.push(instrument);
+        self
+    }
+
+    /// Consult this hook on every turn of the scheduler.
+    pub fn hook(mut self, hook: Box<dyn SchedulerHook>) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Replace all of detcore's settings, for those without a method of their own.
+    pub fn config(mut self, config: DetConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Check the settings, as `hermit run` does, and make the container.
+    pub fn build(self) -> Result<Container, Error> {
+        let command = self
+            .command
+            .ok_or_else(|| anyhow!("A container needs a command to run"))?;
+        let mut config = self.config;
+        let pin_threads = crate::validate_config(&mut config)?;
+        Ok(Container {
+            command,
+            config,
+            pin_threads,
+            mounts: self.mounts,
+            networking: self.networking,
+            instruments: self.instruments,
+            hooks: self.hooks,
+        })
+    }
+
+    /// Make the container and run its command, as [`Container::run`].
+    pub fn run(self) -> Result<RunResult, Error> {
+        self.build()?.run()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Watcher;
+
+    impl SchedulerHook for Watcher {}
+
+    #[test]
+    fn builder_sets_config() {
+        assert!(Container::builder().seed(7).build().is_err());
+
+        let container = Container::builder()
+            .command(Command::new("true"))
+            .seed(7)
+            .record_schedule("/tmp/sched.json")
+            .hook(Box::new(Watcher))
+            .build()
+            .unwrap();
+        assert_eq!(container.config.seed, 7);
+        assert_eq!(container.hooks.len(), 1);
+        assert!(container.config.sequentialize_threads);
+        // Implied by recording the schedule.
+        assert!(container.config.record_preemptions);
+
+        // Refused rather than panicking.
+        let config = DetConfig {
+            virtual_quantum: 0,
+            ..Default::default()
+        };
+        let built = Container::builder()
+            .command(Command::new("true"))
+            .config(config)
+            .build();
+        assert!(built.is_err());
+    }
+}
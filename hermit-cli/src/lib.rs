@@ -9,8 +9,12 @@
 // Treat all Clippy warnings as errors.
 #![deny(clippy::all)]
 
+mod analyze;
 mod chroot;
+#[doc(hidden)]
+pub mod cli;
 mod consts;
+mod container;
 mod desync;
 mod error;
 mod event;
@@ -23,20 +27,39 @@ mod recorder;
 mod replay;
 mod replayer;
 mod script;
+#[doc(hidden)]
+pub mod test_support;
+mod tool;
 
 use std::fs;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 
+pub use analyze::Analysis;
+pub use analyze::Analyzer;
+pub use analyze::Progress;
+pub use analyze::Report;
 use anyhow::anyhow;
 use consts::METADATA_NAME;
+pub use container::Container;
+pub use container::ContainerBuilder;
+pub use container::RunResult;
+pub use detcore::export_instrument;
+pub use detcore::preemptions::PreemptionRecord;
+pub use detcore::register_hook;
+pub use detcore::register_instrument;
 pub use detcore::Config as DetConfig;
 pub use detcore::Detcore;
+pub use detcore::Instrument;
 pub use detcore::RecordOrReplay;
+pub use detcore::RunSummary;
+pub use detcore::SchedulerHook;
+pub use detcore::Verdict;
 pub use error::Context;
 pub use error::Error;
 pub use error::SerializableError;
+pub use hermit_macros::test;
 pub use id::Id;
 use metadata::Metadata;
 use record::Record;
@@ -51,6 +74,7 @@ pub use reverie::ExitStatus;
 pub use script::Shebang;
 use serde::Deserialize;
 use serde::Serialize;
+pub use tool::load_tool;
 
 /// The result of recording a command.
 #[derive(Debug, Serialize, Deserialize)]
@@ -70,38 +94,119 @@ pub struct Recording {
 // runs on different machines with a different number of cores will not be the
 // same.
 #[tokio::main(flavor = "current_thread")]
-/// Run the given command as deterministically as possible.
+/// Run the given command as deterministically as possible.  With `summary_json`, a
+/// machine-readable summary of the run is written there.
 pub async fn run(
     command: Command,
     config: DetConfig,
     print_summary: bool,
+    summary_json: Option<PathBuf>,
 ) -> Result<ExitStatus, Error> {
-    let mut builder = reverie_ptrace::TracerBuilder::<Detcore>::new(command).config(config.clone());
-    if config.gdbserver {
-        builder = builder.gdbserver(config.gdbserver_port);
-    }
+    let builder = reverie_ptrace::TracerBuilder::<Detcore>::new(command).config(config.clone());
+    let builder = with_gdbserver(builder, &config);
     let (exit_status, global_state) = builder.spawn().await?.wait().await?;
-    global_state.clean_up(print_summary).await; // Before it's dropped by this function.
+    // Before it's dropped by this function.
+    let summary = global_state.clean_up(print_summary).await;
+    if let Some(path) = summary_json {
+        write_summary_json(&path, summary, exit_status)?;
+    }
     Ok(exit_status)
 }
 
-/// Variant of `run` that also captures stdout/stderr.
+/// Variant of `run` that also captures stdout/stderr. Stdin is left as the caller set it up.
 #[tokio::main(flavor = "current_thread")]
 pub async fn run_with_output(
     mut command: Command,
     config: DetConfig,
     print_summary: bool,
+    summary_json: Option<PathBuf>,
 ) -> Result<Output, Error> {
-    command.stdin(Stdio::null());
+    let (output, summary) = run_captured(command, config, print_summary).await?;
+    if let Some(path) = summary_json {
+        write_summary_json(&path, summary, output.status)?;
+    }
+    Ok(output)
+}
+
+/// Variant of `run_with_output` that returns the summary of the run, for `Container`.
+#[tokio::main(flavor = "current_thread")]
+async fn run_with_summary(
+    command: Command,
+    config: DetConfig,
+) -> Result<(Output, RunSummary), Error> {
+    let (output, mut summary) = run_captured(command, config, false).await?;
+    summary.set_exit_status(output.status);
+    Ok((output, summary))
+}
+
+async fn run_captured(
+    mut command: Command,
+    config: DetConfig,
+    print_summary: bool,
+) -> Result<(Output, RunSummary), Error> {
     command.stdout(Stdio::piped());
     command.stderr(Stdio::piped());
-    let mut builder = reverie_ptrace::TracerBuilder::<Detcore>::new(command).config(config.clone());
-    if config.gdbserver {
-        builder = builder.gdbserver(config.gdbserver_port);
-    }
+    let builder = reverie_ptrace::TracerBuilder::<Detcore>::new(command).config(config.clone());
+    let builder = with_gdbserver(builder, &config);
     let (output, global_state) = builder.spawn().await?.wait_with_output().await?;
-    global_state.clean_up(print_summary).await;
-    Ok(output)
+    let summary = global_state.clean_up(print_summary).await;
+    Ok((output, summary))
+}
+
+/// Adds the gdbserver asked for by `--gdbserver`, if any.
+fn with_gdbserver(
+    mut builder: reverie_ptrace::TracerBuilder<Detcore>,
+    config: &DetConfig,
+) -> reverie_ptrace::TracerBuilder<Detcore> {
+    if config.gdbserver.is_some() {
+        let port = config.gdbserver.flatten().unwrap_or(config.gdbserver_port);
+        eprintln!(
+            ":: Guest paused, waiting for gdb to attach with `target remote :{}`",
+            port
+        );
+        builder = builder.gdbserver(port);
+        if config.sequentialize_threads {
+            // Detcore already runs one thread at a time, in the order of its schedule, so
+            // gdbserver must not serialize the guest itself.
+            builder = builder.sequentialized_guest();
+        }
+    }
+    builder
+}
+
+fn write_summary_json(
+    path: &Path,
+    mut summary: RunSummary,
+    exit_status: ExitStatus,
+) -> Result<(), Error> {
+    summary.set_exit_status(exit_status);
+    let file = fs::File::create(path)
+        .with_context(|| format!("Failed to create --summary-json {}", path.display()))?;
+    serde_json::to_writer_pretty(file, &summary)?;
+    Ok(())
+}
+
+/// Checks detcore's settings before a run, and adjusts them to what this host supports, as
+/// both `hermit run` and [`Container`] do.  Returns whether the tracer and the guest must be
+/// pinned to one core, for the branch counts that drive preemptions to be deterministic.
+pub fn validate_config(config: &mut DetConfig) -> Result<bool, Error> {
+    config.validate()?;
+
+    // This is a Detcore Config-internal matter, but relies on reverie_ptrace, which detcore is
+    // allowed to depend on:
+    if config.preemption_timeout.is_some() && !reverie_ptrace::is_perf_supported() {
+        // TODO(T124429978): this could change back to tracing::warn! when the bug is fixed:
+        eprintln!(
+            "WARNING: --preemption-timout requires hardware perf counters \
+            which is not supported on this host, resetting \
+            preemption-timeout to 0"
+        );
+        config.preemption_timeout = None;
+    }
+
+    // Deterministic RCB counts requires thread pinning.  But this only matters if
+    // we're expecting full determinstic execution (sequentialize_threads).
+    Ok(config.preemption_timeout.is_some() && config.sequentialize_threads)
 }
 
 /// Holds the context necessary to run high-level hermit functions.
@@ -67,6 +67,10 @@ pub struct Metadata {
     pub envs: BTreeMap<String, String>,
     /// Hermit record/replay version.
     pub version: RecordVersion,
+    /// The architecture the recording was made on, which it can only be replayed on.  Absent
+    /// in recordings from before this was tracked, all of which were made on x86_64.
+    #[serde(default)]
+    pub arch: Option<String>,
 }
 
 impl Metadata {
@@ -115,6 +119,7 @@ impl Metadata {
             domainname,
             envs,
             version: RECORD_VERSION,
+            arch: Some(env::consts::ARCH.to_string()),
         })
     }
 
@@ -151,23 +156,42 @@ pub fn record_or_replay_config(data: &Path) -> detcore::Config {
         panic_on_unsupported_syscalls: false,
         sequentialize_threads: true,
         deterministic_io: false,
+        deterministic_mmap: false,
+        memory_limit: None,
+        timeout: None,
+        timeout_signal: default_config.timeout_signal.clone(),
+        io_uring: Default::default(),
+        emulate_seccomp: false,
+        backend: Default::default(),
         virtualize_time: false,
         virtualize_metadata: false,
+        sort_dirents: true,
+        normalize_stat: false,
         virtualize_cpuid: true,
+        cpu_profile: None,
         has_uts_namespace: true,
         // The path to the directory where syscalls will be recorded.
         replay_data: Some(data.to_path_buf()),
+        record_metadata: None,
         clock_multiplier: None,
+        rdtsc_model: Default::default(),
+        tsc_mhz: 1000,
+        tsc_increment: 1000,
         epoch: default_config.epoch,
-        gdbserver: false,
+        gdbserver: None,
         gdbserver_port: default_config.gdbserver_port,
         kill_daemons: default_config.kill_daemons,
         preemption_timeout: default_config.preemption_timeout,
+        virtual_quantum: default_config.virtual_quantum,
+        timer_resolution: default_config.timer_resolution,
         seed: default_config.seed,
         imprecise_timers: false,
         chaos: false,
         sigint_instakill: false,
         warn_non_zero_binds: false,
+        loopback_networking: false,
+        record_network_to: None,
+        replay_network_from: None,
         sched_heuristic: Default::default(),
         sched_seed: default_config.sched_seed,
         recordreplay_modes: true,
@@ -176,22 +200,36 @@ pub fn record_or_replay_config(data: &Path) -> detcore::Config {
         replay_preemptions_from: None,
         replay_schedule_from: None,
         replay_exhausted_panic: false,
+        replay_prefix_then_chaos: None,
         die_on_desync: true,
+        fuzzy_replay: false,
+        on_divergence: None,
         stacktrace_event: Vec::new(),
         stacktrace_signal: None,
         preemption_stacktrace: false,
         preemption_stacktrace_log_file: None,
+        crash_report_dir: None,
+        emit_trace: None,
+        audit_syscalls: false,
         stop_after_turn: None,
         stop_after_iter: None,
         debug_externalize_sockets: false,
         debug_futex_mode: BlockingMode::Precise,
         sched_sticky_random_param: 0.0,
+        condvar_sched_points: false,
+        lean_sched_points: false,
+        spawn_order: None,
         no_rcb_time: false,
         detlog_heap: false,
         detlog_stack: false,
         sysinfo_uptime_offset: 120,
         memory: 1024 * 1024 * 1024,
+        hostname: "hermetic-container.local".to_string(),
+        uname_release: "5.2.0".to_string(),
+        uid: None,
+        gid: None,
         interrupt_at: vec![],
+        entropy_file: vec![],
     };
     if config.preemption_timeout.is_some() && !reverie_ptrace::is_perf_supported() {
         tracing::warn!(
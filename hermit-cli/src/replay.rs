@@ -65,6 +65,14 @@ impl Replay {
                 recording_version, replayer_version
             )));
         }
+        let recording_arch = metadata.arch.as_deref().unwrap_or("x86_64");
+        if recording_arch != std::env::consts::ARCH {
+            return Err(anyhow::anyhow!(format!(
+                "Architecture mismatch, recorded on {}, replaying on {}",
+                recording_arch,
+                std::env::consts::ARCH
+            )));
+        }
 
         let mut command = metadata.command();
 
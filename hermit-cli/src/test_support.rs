@@ -0,0 +1,103 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! What the tests marked with `#[hermit::test]` run.
+
+use nix::sys::ptrace;
+use nix::sys::signal::kill;
+use nix::sys::signal::raise;
+use nix::sys::signal::Signal;
+use nix::sys::wait::waitpid;
+use nix::sys::wait::WaitStatus;
+use nix::unistd::fork;
+use nix::unistd::ForkResult;
+use reverie::process::Command;
+
+use crate::Container;
+
+/// Set in the environment of a test re-executed inside hermit.
+const INSIDE_HERMIT: &str = "HERMIT_TEST_INSIDE";
+
+/// Set in the environment to skip the tests marked with `#[hermit::test]`.
+const SKIP: &str = "HERMIT_TEST_SKIP";
+
+/// Runs the test `name`, of the given module, once under hermit for each seed, by re-executing
+/// the test binary. Inside hermit, this just runs `body`.
+pub fn run(module: &str, name: &str, seeds: &[u64], chaos: bool, body: fn()) {
+    if std::env::var_os(INSIDE_HERMIT).is_some() {
+        return body();
+    }
+    if std::env::var_os(SKIP).is_some() || !ptrace_supported() {
+        eprintln!("Skipping {} because ptrace is not available.", name);
+        return;
+    }
+
+    // The name of the test, as the test harness filters it: without the crate.
+    let test = match module.split_once("::") {
+        Some((_, path)) => format!("{}::{}", path, name),
+        None => name.to_owned(),
+    };
+    let exe = std::env::current_exe().expect("Failed to find the test binary");
+    for &seed in seeds {
+        let mut command = Command::new(&exe);
+        command
+            .args([test.as_str(), "--exact", "--nocapture", "--test-threads=1"])
+            .env(INSIDE_HERMIT, "1");
+        let result = Container::builder()
+            .command(command)
+            .seed(seed)
+            .chaos(chaos)
+            .run()
+            .unwrap_or_else(|e| panic!("Failed to run {} under hermit: {:#}", test, e));
+        let stdout = String::from_utf8_lossy(&result.stdout);
+        if !result.status.success() {
+            panic!(
+                "{} failed under hermit with --seed={}{}:\n{}{}",
+                test,
+                seed,
+                if chaos { " --chaos" } else { "" },
+                stdout,
+                String::from_utf8_lossy(&result.stderr)
+            );
+        }
+        // Otherwise a test that the harness could not find by name would pass without running.
+        if !stdout.contains("running 1 test\n") {
+            panic!(
+                "{} was not run under hermit, as the test harness found no test of that name:\n{}",
+                test, stdout
+            );
+        }
+    }
+}
+
+/// Whether this process may ptrace its children, which hermit needs.  Yama, seccomp filters and
+/// missing capabilities can each forbid it, so a child is traced to find out.
+fn ptrace_supported() -> bool {
+    // Safe: the child only makes async-signal-safe calls before it exits.
+    match unsafe { fork() } {
+        Ok(ForkResult::Child) => {
+            if ptrace::traceme().is_ok() {
+                // Reported to the parent, as its tracer, rather than stopping the child.
+                let _ = raise(Signal::SIGSTOP);
+            }
+            unsafe { libc::_exit(0) }
+        }
+        Ok(ForkResult::Parent { child }) => {
+            let traced = matches!(
+                waitpid(child, None),
+                Ok(WaitStatus::Stopped(_, Signal::SIGSTOP))
+            );
+            if traced {
+                let _ = kill(child, Signal::SIGKILL);
+                let _ = waitpid(child, None);
+            }
+            traced
+        }
+        Err(_) => false,
+    }
+}
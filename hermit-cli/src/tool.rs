@@ -0,0 +1,100 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Loading instruments from shared libraries, for `hermit run --tool`.
+
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use detcore::Instrument;
+use detcore::InstrumentVtable;
+use detcore::INSTRUMENT_ABI_VERSION;
+
+use crate::error::Error;
+
+/// The function a tool library exports, as defined by
+/// [`export_instrument!`](crate::export_instrument).
+const ENTRY_POINT: &[u8] = b"hermit_instrument\0";
+
+/// The function which returns the version of the interface the tool library was built with.
+const ABI_VERSION: &[u8] = b"hermit_instrument_abi_version\0";
+
+/// Load the instrument exported by the `cdylib` at `path`.  The library stays loaded for as long
+/// as the process runs, since the instrument's code lives in it.
+pub fn load_tool(path: &Path) -> Result<Arc<dyn Instrument>, Error> {
+    let filename = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: Loading a library runs its initializers, which is what the user asked for.
+    let handle = unsafe { libc::dlopen(filename.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+    if handle.is_null() {
+        let path = path.display();
+        return Err(anyhow!("Failed to load tool {}: {}", path, dlerror()));
+    }
+    // SAFETY: The handle was just opened, and the symbol names are nul-terminated.
+    let (version, entry) = unsafe {
+        (
+            libc::dlsym(handle, ABI_VERSION.as_ptr().cast()),
+            libc::dlsym(handle, ENTRY_POINT.as_ptr().cast()),
+        )
+    };
+    if version.is_null() || entry.is_null() {
+        return Err(anyhow!(
+            "Tool {} does not export an instrument, see hermit::export_instrument!",
+            path.display()
+        ));
+    }
+    // SAFETY: `export_instrument!` defines the symbol as a function of this type, which every
+    // version of the interface keeps.
+    let version = unsafe {
+        let version: extern "C" fn() -> u32 = std::mem::transmute(version);
+        version()
+    };
+    if version != INSTRUMENT_ABI_VERSION {
+        return Err(anyhow!(
+            "Tool {} uses version {} of the instrument interface, but hermit uses version {}",
+            path.display(),
+            version,
+            INSTRUMENT_ABI_VERSION
+        ));
+    }
+    // SAFETY: `export_instrument!` defines the symbol as a function of this type, in the version
+    // of the interface just checked.
+    let instrument = unsafe {
+        let entry: extern "C" fn() -> InstrumentVtable = std::mem::transmute(entry);
+        entry()
+    };
+    Ok(Arc::new(instrument))
+}
+
+/// The reason the last `dlopen` or `dlsym` failed.
+fn dlerror() -> String {
+    // SAFETY: dlerror returns either null or a nul-terminated string.
+    unsafe {
+        let error = libc::dlerror();
+        if error.is_null() {
+            "unknown error".to_owned()
+        } else {
+            CStr::from_ptr(error).to_string_lossy().into_owned()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn missing_tool() {
+        let err = load_tool(Path::new("/nonexistent/libtool.so")).unwrap_err();
+        let expected = "Failed to load tool /nonexistent/libtool.so";
+        assert!(err.to_string().contains(expected));
+    }
+}
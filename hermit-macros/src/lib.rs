@@ -0,0 +1,128 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! Procedural macros re-exported by the `hermit` crate.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::parse::Parse;
+use syn::parse::ParseStream;
+use syn::parse_macro_input;
+use syn::punctuated::Punctuated;
+use syn::Expr;
+use syn::Ident;
+use syn::ItemFn;
+use syn::Lit;
+use syn::ReturnType;
+use syn::Token;
+
+/// One argument of `#[hermit::test(...)]`.
+enum Arg {
+    /// `seed = N`
+    Seed(Expr),
+    /// `seeds = [N, ...]`
+    Seeds(Vec<Expr>),
+    /// `chaos`
+    Chaos,
+}
+
+impl Parse for Arg {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name: Ident = input.parse()?;
+        match name.to_string().as_str() {
+            "chaos" => Ok(Arg::Chaos),
+            "seed" => {
+                input.parse::<Token![=]>()?;
+                Ok(Arg::Seed(input.parse()?))
+            }
+            "seeds" => {
+                input.parse::<Token![=]>()?;
+                match input.parse()? {
+                    Expr::Array(array) => Ok(Arg::Seeds(array.elems.into_iter().collect())),
+                    other => Err(syn::Error::new_spanned(
+                        other,
+                        "expected a list of seeds, like `seeds = [1, 2, 3]`",
+                    )),
+                }
+            }
+            _ => Err(syn::Error::new(
+                name.span(),
+                "expected `seed = N`, `seeds = [N, ...]`, or `chaos`",
+            )),
+        }
+    }
+}
+
+/// Marks a test which is run deterministically, inside a hermit container.
+///
+/// The test binary re-executes itself under hermit to run just this test, and the test fails if
+/// it fails in there. Where ptrace is unavailable, the test is skipped with a message instead.
+///
+/// ```ignore
+/// #[hermit::test(seed = 3, chaos)]
+/// fn counter_is_race_free() {
+///     // ...
+/// }
+///
+/// // Runs once under each seed.
+/// #[hermit::test(seeds = [1, 2, 3], chaos)]
+/// fn counter_is_race_free_in_any_schedule() {
+///     // ...
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn test(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args with Punctuated::<Arg, Token![,]>::parse_terminated);
+    let item = parse_macro_input!(item as ItemFn);
+
+    if item.sig.output != ReturnType::Default || item.sig.asyncness.is_some() {
+        return syn::Error::new_spanned(&item.sig, "a hermit test must be a plain `fn() {}`")
+            .to_compile_error()
+            .into();
+    }
+
+    let mut seeds = Vec::new();
+    let mut chaos = false;
+    for arg in args {
+        match arg {
+            Arg::Seed(seed) => seeds.push(seed),
+            Arg::Seeds(more) => seeds.extend(more),
+            Arg::Chaos => chaos = true,
+        }
+    }
+    if seeds.is_empty() {
+        seeds.push(Expr::Lit(syn::ExprLit {
+            attrs: Vec::new(),
+            lit: Lit::Int(syn::LitInt::new("0", Span::call_site())),
+        }));
+    }
+
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = item;
+    let name = &sig.ident;
+    quote! {
+        #[test]
+        #(#attrs)*
+        #vis #sig {
+            fn body() #block
+            ::hermit::test_support::run(
+                module_path!(),
+                stringify!(#name),
+                &[#(#seeds),*],
+                #chaos,
+                body,
+            )
+        }
+    }
+    .into()
+}
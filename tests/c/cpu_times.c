@@ -0,0 +1,96 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// A program which measures its own CPU usage, through getrusage, times,
+// the CPU-time clocks and /proc/self/stat, and that of a child it waited for.
+
+#define _GNU_SOURCE
+#include <assert.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/resource.h>
+#include <sys/times.h>
+#include <sys/wait.h>
+#include <time.h>
+#include <unistd.h>
+
+static long long nanos(clockid_t clock) {
+  struct timespec ts;
+  assert(clock_gettime(clock, &ts) == 0);
+  return ts.tv_sec * 1000000000LL + ts.tv_nsec;
+}
+
+static long long micros(struct timeval tv) {
+  return tv.tv_sec * 1000000LL + tv.tv_usec;
+}
+
+// Burn at least the given CPU time.
+static void spin(long long ns) {
+  long long start = nanos(CLOCK_PROCESS_CPUTIME_ID);
+  while (nanos(CLOCK_PROCESS_CPUTIME_ID) - start < ns) {
+  }
+}
+
+// The utime and stime fields of /proc/self/stat, summed, in clock ticks.
+static long stat_cpu_ticks(void) {
+  char buf[1024];
+  FILE* f = fopen("/proc/self/stat", "r");
+  assert(f != NULL);
+  size_t n = fread(buf, 1, sizeof(buf) - 1, f);
+  fclose(f);
+  buf[n] = '\0';
+  // The command name is field 2; the state after it is field 3.
+  char* p = strrchr(buf, ')');
+  assert(p != NULL);
+  for (int field = 3; field < 14; field++) {
+    p = strchr(p + 1, ' ');
+    assert(p != NULL);
+  }
+  char* end;
+  long utime = strtol(p + 1, &end, 10);
+  return utime + strtol(end, NULL, 10);
+}
+
+int main(void) {
+  spin(50 * 1000000LL);
+
+  long long thread = nanos(CLOCK_THREAD_CPUTIME_ID);
+  long long process = nanos(CLOCK_PROCESS_CPUTIME_ID);
+  assert(thread >= 50 * 1000000LL && process >= thread);
+
+  struct rusage usage;
+  assert(getrusage(RUSAGE_SELF, &usage) == 0);
+  long long self = micros(usage.ru_utime) + micros(usage.ru_stime);
+  assert(self >= process / 1000);
+
+  long stat_ticks = stat_cpu_ticks();
+  struct tms tms;
+  assert(times(&tms) != (clock_t)-1);
+  long ticks = sysconf(_SC_CLK_TCK);
+  // Allow for the kernel's rounding to clock ticks.
+  assert(tms.tms_utime + tms.tms_stime >= 4 * ticks / 100);
+  assert(tms.tms_cutime == 0 && tms.tms_cstime == 0);
+  assert(stat_ticks <= tms.tms_utime + tms.tms_stime);
+  printf("spent at least 50ms of CPU time\n");
+
+  fflush(stdout);
+  pid_t pid = fork();
+  assert(pid >= 0);
+  if (pid == 0) {
+    spin(30 * 1000000LL);
+    _exit(0);
+  }
+  int status;
+  assert(waitpid(pid, &status, 0) == pid);
+  assert(WIFEXITED(status) && WEXITSTATUS(status) == 0);
+  assert(getrusage(RUSAGE_CHILDREN, &usage) == 0);
+  assert(micros(usage.ru_utime) + micros(usage.ru_stime) >= 30 * 1000);
+  printf("child spent at least 30ms of CPU time\n");
+  return 0;
+}
@@ -0,0 +1,76 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// Worker threads wake each other up through an eventfd, and the main thread
+// collects signals through a signalfd, as event loops do. The interleaving,
+// and hence the output, should be the same on every run.
+
+#include <assert.h>
+#include <pthread.h>
+#include <signal.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <sys/eventfd.h>
+#include <sys/signalfd.h>
+#include <unistd.h>
+
+#define NWORKERS 3
+#define ROUNDS 5
+
+static int efd;
+
+static void* worker(void* arg) {
+  long id = (long)arg;
+  for (int i = 0; i < ROUNDS; i++) {
+    uint64_t val = 0;
+    // Blocks until some other thread adds to the counter.
+    assert(read(efd, &val, sizeof(val)) == sizeof(val));
+    printf("worker %ld: round %d took %llu\n", id, i, (unsigned long long)val);
+    val = 1;
+    assert(write(efd, &val, sizeof(val)) == sizeof(val));
+  }
+  return NULL;
+}
+
+int main() {
+  sigset_t mask;
+  sigemptyset(&mask);
+  sigaddset(&mask, SIGUSR1);
+  sigaddset(&mask, SIGUSR2);
+  assert(pthread_sigmask(SIG_BLOCK, &mask, NULL) == 0);
+  int sfd = signalfd(-1, &mask, 0);
+  assert(sfd >= 0);
+
+  efd = eventfd(0, 0);
+  assert(efd >= 0);
+  pthread_t workers[NWORKERS];
+  for (long i = 0; i < NWORKERS; i++) {
+    assert(pthread_create(&workers[i], NULL, worker, (void*)i) == 0);
+  }
+  uint64_t one = 1;
+  assert(write(efd, &one, sizeof(one)) == sizeof(one));
+  for (int i = 0; i < NWORKERS; i++) {
+    assert(pthread_join(workers[i], NULL) == 0);
+  }
+  // The last worker's increment is left over.
+  uint64_t val = 0;
+  assert(read(efd, &val, sizeof(val)) == sizeof(val));
+  assert(val == 1);
+
+  assert(kill(getpid(), SIGUSR2) == 0);
+  assert(kill(getpid(), SIGUSR1) == 0);
+  for (int i = 0; i < 2; i++) {
+    struct signalfd_siginfo info;
+    assert(read(sfd, &info, sizeof(info)) == sizeof(info));
+    printf("signalfd: got signal %u\n", info.ssi_signo);
+  }
+
+  close(efd);
+  close(sfd);
+  return 0;
+}
@@ -0,0 +1,70 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// Watch a directory with inotify while a thread creates, writes, and renames
+// files in it. The events, including rename cookies, should be the same on
+// every run.
+
+#include <assert.h>
+#include <fcntl.h>
+#include <limits.h>
+#include <pthread.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <sys/inotify.h>
+#include <unistd.h>
+
+static char dir[] = "/tmp/inotify_rename_XXXXXX";
+
+static void* writer(void* arg) {
+  (void)arg;
+  char path[PATH_MAX], renamed[PATH_MAX];
+  for (int i = 0; i < 3; i++) {
+    snprintf(path, sizeof(path), "%s/file%d", dir, i);
+    snprintf(renamed, sizeof(renamed), "%s/renamed%d", dir, i);
+    int fd = open(path, O_CREAT | O_WRONLY, 0644);
+    assert(fd >= 0);
+    assert(write(fd, "hello", 5) == 5);
+    close(fd);
+    assert(rename(path, renamed) == 0);
+    assert(unlink(renamed) == 0);
+  }
+  return NULL;
+}
+
+int main() {
+  assert(mkdtemp(dir) != NULL);
+  int in = inotify_init1(IN_CLOEXEC);
+  assert(in >= 0);
+  uint32_t mask = IN_CREATE | IN_CLOSE_WRITE | IN_MOVE | IN_DELETE;
+  assert(inotify_add_watch(in, dir, mask) >= 0);
+
+  pthread_t thread;
+  assert(pthread_create(&thread, NULL, writer, NULL) == 0);
+
+  // create, close_write, moved_from, moved_to, delete for each file:
+  int remaining = 3 * 5;
+  char buf[4096] __attribute__((aligned(__alignof__(struct inotify_event))));
+  while (remaining > 0) {
+    ssize_t n = read(in, buf, sizeof(buf));
+    assert(n > 0);
+    for (char* p = buf; p < buf + n;) {
+      struct inotify_event* ev = (struct inotify_event*)p;
+      printf("event: mask %#x cookie %u name %s\n", ev->mask, ev->cookie,
+             ev->len ? ev->name : "");
+      remaining--;
+      p += sizeof(struct inotify_event) + ev->len;
+    }
+  }
+
+  assert(pthread_join(thread, NULL) == 0);
+  close(in);
+  assert(rmdir(dir) == 0);
+  return 0;
+}
@@ -0,0 +1,44 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// Probe for io_uring the way async runtimes do, falling back to a plain
+// read(2) when it is unavailable (as it is under hermit by default).
+
+#include <errno.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/syscall.h>
+#include <unistd.h>
+
+#ifndef SYS_io_uring_setup
+#define SYS_io_uring_setup 425
+#endif
+
+int main() {
+  // Large enough for struct io_uring_params, which the kernel fills in.
+  unsigned char params[120];
+  memset(params, 0, sizeof(params));
+
+  long fd = syscall(SYS_io_uring_setup, 8, params);
+  if (fd < 0) {
+    printf("io_uring unavailable (%s), using read(2)\n", strerror(errno));
+  } else {
+    printf("io_uring available\n");
+    close(fd);
+  }
+
+  int fds[2];
+  char buf[6] = {0};
+  if (pipe(fds) != 0 || write(fds[1], "hello", 5) != 5 ||
+      read(fds[0], buf, 5) != 5) {
+    perror("pipe");
+    return 1;
+  }
+  printf("read: %s\n", buf);
+  return 0;
+}
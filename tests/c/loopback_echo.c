@@ -0,0 +1,79 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// A TCP echo server and client talking over loopback within one process.
+// Under --loopback-networking, the client's ephemeral port is deterministic.
+
+#include <arpa/inet.h>
+#include <assert.h>
+#include <netinet/in.h>
+#include <pthread.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/socket.h>
+#include <unistd.h>
+
+static int listener;
+static unsigned short server_port;
+
+static void* server(void* arg) {
+  (void)arg;
+  int conn = accept(listener, NULL, NULL);
+  assert(conn >= 0);
+  char buf[64];
+  ssize_t n;
+  while ((n = recv(conn, buf, sizeof(buf), 0)) > 0) {
+    assert(send(conn, buf, n, 0) == n);
+  }
+  close(conn);
+  return NULL;
+}
+
+int main() {
+  listener = socket(AF_INET, SOCK_STREAM, 0);
+  assert(listener >= 0);
+  struct sockaddr_in addr;
+  memset(&addr, 0, sizeof(addr));
+  addr.sin_family = AF_INET;
+  addr.sin_addr.s_addr = htonl(INADDR_LOOPBACK);
+  assert(bind(listener, (struct sockaddr*)&addr, sizeof(addr)) == 0);
+  assert(listen(listener, 1) == 0);
+  socklen_t len = sizeof(addr);
+  assert(getsockname(listener, (struct sockaddr*)&addr, &len) == 0);
+  server_port = ntohs(addr.sin_port);
+
+  pthread_t thread;
+  assert(pthread_create(&thread, NULL, server, NULL) == 0);
+
+  int client = socket(AF_INET, SOCK_STREAM, 0);
+  assert(client >= 0);
+  assert(connect(client, (struct sockaddr*)&addr, sizeof(addr)) == 0);
+
+  struct sockaddr_in local;
+  len = sizeof(local);
+  assert(getsockname(client, (struct sockaddr*)&local, &len) == 0);
+  printf("server port %u, client port %u\n", server_port, ntohs(local.sin_port));
+
+  const char* messages[] = {"ping", "hello", "goodbye"};
+  for (int i = 0; i < 3; i++) {
+    char buf[64] = {0};
+    size_t n = strlen(messages[i]);
+    assert(send(client, messages[i], n, 0) == (ssize_t)n);
+    size_t got = 0;
+    while (got < n) {
+      ssize_t r = recv(client, buf + got, sizeof(buf) - got, 0);
+      assert(r > 0);
+      got += r;
+    }
+    printf("echo: %s\n", buf);
+  }
+  close(client);
+  pthread_join(thread, NULL);
+  close(listener);
+  return 0;
+}
@@ -0,0 +1,53 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// Threads that issue membarriers and ask which CPU they are on. Both should
+// behave the same on every run.
+
+#define _GNU_SOURCE
+#include <assert.h>
+#include <errno.h>
+#include <linux/membarrier.h>
+#include <pthread.h>
+#include <sched.h>
+#include <stdio.h>
+#include <sys/syscall.h>
+#include <unistd.h>
+
+static int membarrier(int cmd) {
+  return syscall(SYS_membarrier, cmd, 0, 0);
+}
+
+static void* worker(void* arg) {
+  (void)arg;
+  for (int i = 0; i < 10; i++) {
+    assert(membarrier(MEMBARRIER_CMD_PRIVATE_EXPEDITED) == 0);
+    assert(sched_getcpu() >= 0);
+  }
+  return NULL;
+}
+
+int main() {
+  int supported = membarrier(MEMBARRIER_CMD_QUERY);
+  assert(supported >= 0);
+  assert(supported & MEMBARRIER_CMD_PRIVATE_EXPEDITED);
+
+  // Expedited barriers need registering first.
+  assert(membarrier(MEMBARRIER_CMD_PRIVATE_EXPEDITED) == -1 && errno == EPERM);
+  assert(membarrier(MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED) == 0);
+
+  pthread_t threads[2];
+  for (int i = 0; i < 2; i++) {
+    assert(pthread_create(&threads[i], NULL, worker, NULL) == 0);
+  }
+  for (int i = 0; i < 2; i++) {
+    assert(pthread_join(threads[i], NULL) == 0);
+  }
+  printf("membarriers done\n");
+  return 0;
+}
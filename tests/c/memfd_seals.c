@@ -0,0 +1,72 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// An anonymous file made with memfd_create, sealed against changes, and then
+// mapped. Also memfd_secret, which hermit always provides.
+
+#define _GNU_SOURCE
+#include <assert.h>
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <sys/syscall.h>
+#include <unistd.h>
+
+#ifndef SYS_memfd_secret
+#define SYS_memfd_secret 447
+#endif
+
+int main(void) {
+  int fd = memfd_create("plugin", MFD_CLOEXEC | MFD_ALLOW_SEALING);
+  assert(fd >= 0);
+  const char msg[] = "sealed contents";
+  assert(write(fd, msg, sizeof(msg)) == sizeof(msg));
+  assert(fcntl(fd, F_GET_SEALS) == 0);
+  assert(
+      fcntl(fd, F_ADD_SEALS, F_SEAL_SHRINK | F_SEAL_GROW | F_SEAL_WRITE) == 0);
+  assert(fcntl(fd, F_GET_SEALS) == (F_SEAL_SHRINK | F_SEAL_GROW | F_SEAL_WRITE));
+  assert(write(fd, msg, sizeof(msg)) == -1 && errno == EPERM);
+  assert(ftruncate(fd, 0) == -1 && errno == EPERM);
+
+  char* p = mmap(NULL, sizeof(msg), PROT_READ, MAP_SHARED, fd, 0);
+  assert(p != MAP_FAILED);
+  assert(strcmp(p, msg) == 0);
+  // A writable shared mapping would defeat the write seal.
+  assert(mmap(NULL, sizeof(msg), PROT_WRITE, MAP_SHARED, fd, 0) == MAP_FAILED);
+  assert(munmap(p, sizeof(msg)) == 0);
+  printf("memfd %d: %s\n", fd, msg);
+
+  // Without MFD_ALLOW_SEALING, the file comes sealed against further seals.
+  int unsealable = memfd_create("plain", 0);
+  assert(unsealable >= 0);
+  assert(fcntl(unsealable, F_GET_SEALS) == F_SEAL_SEAL);
+  assert(fcntl(unsealable, F_ADD_SEALS, F_SEAL_WRITE) == -1 && errno == EPERM);
+  assert(close(unsealable) == 0);
+  assert(close(fd) == 0);
+
+  int secret = syscall(SYS_memfd_secret, O_CLOEXEC);
+  if (secret < 0) {
+    // E.g. ENOSYS, unless the kernel was booted with secretmem enabled.
+    printf("memfd_secret unavailable\n");
+    return 0;
+  }
+  long page = sysconf(_SC_PAGESIZE);
+  assert(ftruncate(secret, page) == 0);
+  char* s = mmap(NULL, page, PROT_READ | PROT_WRITE, MAP_SHARED, secret, 0);
+  assert(s != MAP_FAILED);
+  strcpy(s, "hidden");
+  char buf[8];
+  assert(read(secret, buf, sizeof(buf)) == -1 && errno == EINVAL);
+  assert(fcntl(secret, F_GET_SEALS) == -1 && errno == EINVAL);
+  assert(munmap(s, page) == 0);
+  assert(close(secret) == 0);
+  printf("memfd_secret ok\n");
+  return 0;
+}
@@ -0,0 +1,55 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// A program which tries to count its own instructions, as self-profiling
+// libraries do, and falls back when performance counters are unavailable.
+// Whichever way it goes should not depend on the host.
+
+#define _GNU_SOURCE
+#include <assert.h>
+#include <errno.h>
+#include <linux/perf_event.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/ioctl.h>
+#include <sys/syscall.h>
+#include <unistd.h>
+
+static int open_counter(uint64_t config) {
+  struct perf_event_attr attr;
+  memset(&attr, 0, sizeof(attr));
+  attr.type = PERF_TYPE_HARDWARE;
+  attr.size = sizeof(attr);
+  attr.config = config;
+  attr.disabled = 1;
+  attr.exclude_kernel = 1;
+  return syscall(SYS_perf_event_open, &attr, 0, -1, -1, 0);
+}
+
+int main(void) {
+  int fd = open_counter(PERF_COUNT_HW_INSTRUCTIONS);
+  if (fd < 0) {
+    assert(errno != EFAULT && errno != EBADF);
+    printf("instruction counter unavailable: %s\n", strerror(errno));
+    return 0;
+  }
+  assert(ioctl(fd, PERF_EVENT_IOC_RESET, 0) == 0);
+  assert(ioctl(fd, PERF_EVENT_IOC_ENABLE, 0) == 0);
+  volatile int sum = 0;
+  for (int i = 0; i < 1000; i++) {
+    sum += i;
+  }
+  assert(ioctl(fd, PERF_EVENT_IOC_DISABLE, 0) == 0);
+  uint64_t count;
+  assert(read(fd, &count, sizeof(count)) == sizeof(count));
+  assert(count > 0);
+  close(fd);
+  printf("counted instructions\n");
+  return 0;
+}
@@ -0,0 +1,87 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// pidfd_open, polling a pidfd for the exit of its process, waitid(P_PIDFD),
+// pidfd_send_signal, and pidfd_getfd.
+
+#define _GNU_SOURCE
+#include <assert.h>
+#include <errno.h>
+#include <fcntl.h>
+#include <poll.h>
+#include <signal.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#ifndef P_PIDFD
+#define P_PIDFD 3
+#endif
+
+static int pidfd_open(pid_t pid, unsigned int flags) {
+  return syscall(SYS_pidfd_open, pid, flags);
+}
+
+int main(void) {
+  // A child which exits by itself.
+  pid_t pid = fork();
+  assert(pid >= 0);
+  if (pid == 0) {
+    usleep(10000);
+    _exit(5);
+  }
+  int pidfd = pidfd_open(pid, 0);
+  assert(pidfd >= 0);
+  assert(fcntl(pidfd, F_GETFD) & FD_CLOEXEC);
+  struct pollfd pfd = {.fd = pidfd, .events = POLLIN};
+  assert(poll(&pfd, 1, -1) == 1);
+  assert(pfd.revents & POLLIN);
+  siginfo_t info;
+  memset(&info, 0, sizeof(info));
+  assert(waitid(P_PIDFD, pidfd, &info, WEXITED) == 0);
+  assert(info.si_pid == pid);
+  assert(info.si_code == CLD_EXITED && info.si_status == 5);
+  // The process is gone for good.
+  assert(syscall(SYS_pidfd_send_signal, pidfd, SIGTERM, NULL, 0) == -1);
+  assert(errno == ESRCH);
+  assert(close(pidfd) == 0);
+  printf("child exited with status 5\n");
+
+  // A child which we kill through its pidfd, after taking a pipe from it.
+  int fds[2];
+  assert(pipe(fds) == 0);
+  pid = fork();
+  assert(pid >= 0);
+  if (pid == 0) {
+    close(fds[0]);
+    pause();
+    _exit(0);
+  }
+  pidfd = pidfd_open(pid, 0);
+  assert(pidfd >= 0);
+  int fd = syscall(SYS_pidfd_getfd, pidfd, fds[1], 0);
+  if (fd >= 0) {
+    assert(fcntl(fd, F_GETFD) & FD_CLOEXEC);
+    assert(write(fd, "x", 1) == 1);
+    char c;
+    assert(read(fds[0], &c, 1) == 1 && c == 'x');
+    close(fd);
+    printf("took the child's pipe\n");
+  } else {
+    // Not permitted to ptrace the child, e.g. under a restrictive Yama policy.
+    assert(errno == EPERM);
+  }
+  assert(syscall(SYS_pidfd_send_signal, pidfd, SIGTERM, NULL, 0) == 0);
+  memset(&info, 0, sizeof(info));
+  assert(waitid(P_PIDFD, pidfd, &info, WEXITED) == 0);
+  assert(info.si_code == CLD_KILLED && info.si_status == SIGTERM);
+  printf("child killed by SIGTERM\n");
+  return 0;
+}
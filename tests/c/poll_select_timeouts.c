@@ -0,0 +1,99 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// Wait on several ready pipes with epoll, ppoll, select, and pselect, whose
+// results (and timeouts, in logical time) should be the same on every run.
+
+#define _GNU_SOURCE
+#include <assert.h>
+#include <poll.h>
+#include <signal.h>
+#include <stdio.h>
+#include <sys/epoll.h>
+#include <sys/select.h>
+#include <time.h>
+#include <unistd.h>
+
+#define NPIPES 4
+
+int main() {
+  int pipes[NPIPES][2];
+  for (int i = 0; i < NPIPES; i++) {
+    assert(pipe(pipes[i]) == 0);
+  }
+  // Make them ready in reverse order.
+  for (int i = NPIPES - 1; i >= 0; i--) {
+    assert(write(pipes[i][1], "x", 1) == 1);
+  }
+
+  int ep = epoll_create1(0);
+  for (int i = 0; i < NPIPES; i++) {
+    struct epoll_event ev = {.events = EPOLLIN, .data.u64 = NPIPES - i};
+    assert(epoll_ctl(ep, EPOLL_CTL_ADD, pipes[i][0], &ev) == 0);
+  }
+  struct epoll_event out[NPIPES];
+  int n = epoll_wait(ep, out, NPIPES, -1);
+  assert(n == NPIPES);
+  for (int i = 0; i < n; i++) {
+    printf("epoll_wait: event %d has data %llu\n", i,
+           (unsigned long long)out[i].data.u64);
+  }
+  sigset_t mask;
+  sigemptyset(&mask);
+  assert(epoll_pwait(ep, out, NPIPES, 0, &mask) == NPIPES);
+  close(ep);
+
+  struct pollfd fds[NPIPES];
+  for (int i = 0; i < NPIPES; i++) {
+    fds[i].fd = pipes[i][0];
+    fds[i].events = POLLIN;
+  }
+  struct timespec ts = {.tv_sec = 0, .tv_nsec = 10 * 1000 * 1000};
+  assert(ppoll(fds, NPIPES, &ts, NULL) == NPIPES);
+
+  fd_set readfds, writefds;
+  FD_ZERO(&readfds);
+  FD_ZERO(&writefds);
+  int maxfd = 0;
+  for (int i = 0; i < NPIPES; i++) {
+    FD_SET(pipes[i][0], &readfds);
+    FD_SET(pipes[i][1], &writefds);
+    maxfd = pipes[i][1] > maxfd ? pipes[i][1] : maxfd;
+  }
+  n = select(maxfd + 1, &readfds, &writefds, NULL, NULL);
+  assert(n == 2 * NPIPES);
+  for (int i = 0; i < NPIPES; i++) {
+    assert(FD_ISSET(pipes[i][0], &readfds));
+    assert(FD_ISSET(pipes[i][1], &writefds));
+  }
+
+  // Drain the pipes, so that nothing is ready and the waits time out.
+  char c;
+  for (int i = 0; i < NPIPES; i++) {
+    assert(read(pipes[i][0], &c, 1) == 1);
+  }
+  struct timeval tv = {.tv_sec = 0, .tv_usec = 20 * 1000};
+  FD_ZERO(&readfds);
+  FD_SET(pipes[0][0], &readfds);
+  assert(select(pipes[0][0] + 1, &readfds, NULL, NULL, &tv) == 0);
+  assert(!FD_ISSET(pipes[0][0], &readfds));
+  printf("select: timed out, %ld.%06ld left\n", (long)tv.tv_sec,
+         (long)tv.tv_usec);
+
+  ts.tv_nsec = 5 * 1000 * 1000;
+  FD_SET(pipes[0][0], &readfds);
+  assert(pselect(pipes[0][0] + 1, &readfds, NULL, NULL, &ts, &mask) == 0);
+  assert(ppoll(fds, NPIPES, &ts, NULL) == 0);
+  printf("pselect and ppoll: timed out\n");
+
+  for (int i = 0; i < NPIPES; i++) {
+    close(pipes[i][0]);
+    close(pipes[i][1]);
+  }
+  return 0;
+}
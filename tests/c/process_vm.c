@@ -0,0 +1,67 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// A parent peeks at and pokes its child's memory, with process_vm_readv,
+// process_vm_writev, and /proc/<pid>/mem.
+
+#define _GNU_SOURCE
+#include <assert.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/uio.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static char message[32] = "hello from the child";
+
+int main() {
+  int to_parent[2], to_child[2];
+  assert(pipe(to_parent) == 0 && pipe(to_child) == 0);
+
+  pid_t child = fork();
+  assert(child >= 0);
+  if (child == 0) {
+    char* addr = message;
+    assert(write(to_parent[1], &addr, sizeof(addr)) == sizeof(addr));
+    char c;
+    assert(read(to_child[0], &c, 1) == 1);
+    printf("child: %s\n", message);
+    return 0;
+  }
+
+  char* remote;
+  assert(read(to_parent[0], &remote, sizeof(remote)) == sizeof(remote));
+
+  // Read the message in two pieces, split differently on each side.
+  char first[6] = {0}, rest[32] = {0};
+  struct iovec local[2] = {{first, 5}, {rest, 15}};
+  struct iovec remote_iov[2] = {{remote, 8}, {remote + 8, 12}};
+  assert(process_vm_readv(child, local, 2, remote_iov, 2, 0) == 20);
+  printf("parent read: '%s' + '%s'\n", first, rest);
+
+  char mem_path[64];
+  snprintf(mem_path, sizeof(mem_path), "/proc/%d/mem", child);
+  int mem = open(mem_path, O_RDONLY);
+  assert(mem >= 0);
+  char buf[32] = {0};
+  assert(pread(mem, buf, 20, (off_t)remote) == 20);
+  printf("parent read via %s: '%s'\n", "/proc/<pid>/mem", buf);
+  close(mem);
+
+  const char reply[] = "hello from the parent";
+  struct iovec out = {(void*)reply, sizeof(reply)};
+  struct iovec dst = {remote, sizeof(reply)};
+  assert(process_vm_writev(child, &out, 1, &dst, 1, 0) == sizeof(reply));
+
+  assert(write(to_child[1], "x", 1) == 1);
+  int status;
+  assert(waitpid(child, &status, 0) == child);
+  assert(WIFEXITED(status) && WEXITSTATUS(status) == 0);
+  return 0;
+}
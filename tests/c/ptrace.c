@@ -0,0 +1,101 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// A process debugging its own child, as test harnesses and crash handlers do:
+// the child stops for its tracer at a signal and at syscalls, and the tracer
+// reads its registers and memory. Also attaching to, and detaching from, a
+// running child.
+
+#define _GNU_SOURCE
+#include <assert.h>
+#include <errno.h>
+#include <signal.h>
+#include <stdio.h>
+#include <sys/ptrace.h>
+#include <sys/syscall.h>
+#include <sys/user.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static volatile long secret = 0x5ec2e7;
+
+static const char msg[] = "child resumed\n";
+
+static void expect_stop(pid_t pid, int sig) {
+  int status;
+  assert(waitpid(pid, &status, 0) == pid);
+  assert(WIFSTOPPED(status) && WSTOPSIG(status) == sig);
+}
+
+static void traceme(void) {
+  fflush(stdout);
+  pid_t pid = fork();
+  assert(pid >= 0);
+  if (pid == 0) {
+    assert(ptrace(PTRACE_TRACEME, 0, NULL, NULL) == 0);
+    raise(SIGSTOP);
+    // The tracer changes the value while we are stopped.
+    assert(write(STDOUT_FILENO, msg, sizeof(msg) - 1) == sizeof(msg) - 1);
+    _exit(secret == 42 ? 0 : 1);
+  }
+  expect_stop(pid, SIGSTOP);
+  errno = 0;
+  long word = ptrace(PTRACE_PEEKDATA, pid, &secret, NULL);
+  assert(errno == 0 && word == 0x5ec2e7);
+  assert(ptrace(PTRACE_POKEDATA, pid, &secret, (void*)42) == 0);
+  struct user_regs_struct regs;
+  assert(ptrace(PTRACE_GETREGS, pid, NULL, &regs) == 0);
+  assert(regs.rip != 0);
+
+  // Follow the child through the write: first its entry, then its exit.
+  assert(ptrace(PTRACE_SETOPTIONS, pid, NULL, PTRACE_O_TRACESYSGOOD) == 0);
+  assert(ptrace(PTRACE_SYSCALL, pid, NULL, NULL) == 0);
+  expect_stop(pid, SIGTRAP | 0x80);
+  assert(ptrace(PTRACE_GETREGS, pid, NULL, &regs) == 0);
+  assert(regs.orig_rax == SYS_write && regs.rdx == sizeof(msg) - 1);
+  assert(ptrace(PTRACE_SYSCALL, pid, NULL, NULL) == 0);
+  expect_stop(pid, SIGTRAP | 0x80);
+  assert(ptrace(PTRACE_GETREGS, pid, NULL, &regs) == 0);
+  assert(regs.orig_rax == SYS_write && regs.rax == sizeof(msg) - 1);
+  printf("tracer saw the child's write\n");
+
+  assert(ptrace(PTRACE_CONT, pid, NULL, NULL) == 0);
+  int status;
+  assert(waitpid(pid, &status, 0) == pid);
+  assert(WIFEXITED(status) && WEXITSTATUS(status) == 0);
+}
+
+static void attach(void) {
+  assert(ptrace(PTRACE_ATTACH, getpid(), NULL, NULL) == -1 && errno == EPERM);
+
+  pid_t pid = fork();
+  assert(pid >= 0);
+  if (pid == 0) {
+    for (;;) {
+      pause();
+    }
+  }
+  assert(ptrace(PTRACE_ATTACH, pid, NULL, NULL) == 0);
+  expect_stop(pid, SIGSTOP);
+  assert(ptrace(PTRACE_ATTACH, pid, NULL, NULL) == -1 && errno == EPERM);
+  assert(ptrace(PTRACE_DETACH, pid, NULL, NULL) == 0);
+  // No longer traced.
+  assert(ptrace(PTRACE_CONT, pid, NULL, NULL) == -1 && errno == ESRCH);
+  printf("attached to and detached from the child\n");
+
+  assert(kill(pid, SIGKILL) == 0);
+  int status;
+  assert(waitpid(pid, &status, 0) == pid);
+  assert(WIFSIGNALED(status) && WTERMSIG(status) == SIGKILL);
+}
+
+int main(void) {
+  traceme();
+  attach();
+  return 0;
+}
@@ -0,0 +1,62 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// List a directory twice (with a rewind in between), which should produce the
+// same, sorted, listing regardless of the order the files were created in.
+
+#include <assert.h>
+#include <dirent.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+
+static int list(DIR* dir) {
+  int count = 0;
+  struct dirent* ent;
+  while ((ent = readdir(dir)) != NULL) {
+    printf("  %s\n", ent->d_name);
+    count++;
+  }
+  return count;
+}
+
+int main() {
+  char template[] = "/tmp/readdir_order.XXXXXX";
+  char* path = mkdtemp(template);
+  assert(path != NULL);
+
+  const char* names[] = {"zeta", "alpha", "mu", "beta", "omega", "gamma"};
+  const int n = sizeof(names) / sizeof(names[0]);
+  char buf[256];
+  for (int i = 0; i < n; i++) {
+    snprintf(buf, sizeof(buf), "%s/%s", path, names[i]);
+    int fd = open(buf, O_CREAT | O_WRONLY, 0644);
+    assert(fd >= 0);
+    close(fd);
+  }
+
+  DIR* dir = opendir(path);
+  assert(dir != NULL);
+  printf("first listing:\n");
+  int first = list(dir);
+  rewinddir(dir);
+  printf("second listing:\n");
+  int second = list(dir);
+  assert(first == n + 2);
+  assert(first == second);
+  closedir(dir);
+
+  for (int i = 0; i < n; i++) {
+    snprintf(buf, sizeof(buf), "%s/%s", path, names[i]);
+    unlink(buf);
+  }
+  rmdir(path);
+  return 0;
+}
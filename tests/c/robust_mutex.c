@@ -0,0 +1,83 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// Threads and processes that die while holding a robust mutex. A waiter
+// should acquire it with EOWNERDEAD, and be able to make it consistent again.
+
+#define _GNU_SOURCE
+#include <assert.h>
+#include <errno.h>
+#include <pthread.h>
+#include <stdio.h>
+#include <sys/mman.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static pthread_mutex_t* lock;
+static int ready[2];
+
+static void take_and_die(void) {
+  assert(pthread_mutex_lock(lock) == 0);
+  assert(write(ready[1], "x", 1) == 1);
+  // Give the waiter time to block on the lock.
+  usleep(10000);
+}
+
+static void* thread_dies(void* arg) {
+  (void)arg;
+  take_and_die();
+  pthread_exit(NULL);
+}
+
+static void recover(const char* who) {
+  char c;
+  assert(read(ready[0], &c, 1) == 1);
+  int res = pthread_mutex_lock(lock);
+  printf("%s died holding the lock: %s\n", who,
+         res == EOWNERDEAD ? "EOWNERDEAD" : "acquired normally");
+  assert(res == EOWNERDEAD);
+  assert(pthread_mutex_consistent(lock) == 0);
+  assert(pthread_mutex_unlock(lock) == 0);
+}
+
+int main() {
+  lock = mmap(
+      NULL,
+      sizeof(pthread_mutex_t),
+      PROT_READ | PROT_WRITE,
+      MAP_SHARED | MAP_ANONYMOUS,
+      -1,
+      0);
+  assert(lock != MAP_FAILED);
+  pthread_mutexattr_t attr;
+  pthread_mutexattr_init(&attr);
+  pthread_mutexattr_setpshared(&attr, PTHREAD_PROCESS_SHARED);
+  pthread_mutexattr_setrobust(&attr, PTHREAD_MUTEX_ROBUST);
+  assert(pthread_mutex_init(lock, &attr) == 0);
+  assert(pipe(ready) == 0);
+
+  pthread_t thread;
+  assert(pthread_create(&thread, NULL, thread_dies, NULL) == 0);
+  recover("thread");
+  assert(pthread_join(thread, NULL) == 0);
+
+  pid_t child = fork();
+  assert(child >= 0);
+  if (child == 0) {
+    take_and_die();
+    _exit(0);
+  }
+  recover("process");
+  int status;
+  assert(waitpid(child, &status, 0) == child);
+
+  // The lock is usable as normal again.
+  assert(pthread_mutex_lock(lock) == 0);
+  assert(pthread_mutex_unlock(lock) == 0);
+  return 0;
+}
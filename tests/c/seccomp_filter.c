@@ -0,0 +1,134 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// A process installing its own seccomp filter, as sandboxes do: denied
+// syscalls fail with an errno, trap with SIGSYS, or kill the process. Also
+// strict mode.
+
+#define _GNU_SOURCE
+#include <assert.h>
+#include <errno.h>
+#include <linux/audit.h>
+#include <linux/filter.h>
+#include <linux/seccomp.h>
+#include <signal.h>
+#include <stddef.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/prctl.h>
+#include <sys/resource.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#ifndef SYS_SECCOMP
+#define SYS_SECCOMP 1
+#endif
+
+#define SANDBOXED_SID 12345
+
+static volatile sig_atomic_t trapped_syscall = -1;
+static volatile sig_atomic_t trapped_data = -1;
+
+static void on_sigsys(int sig, siginfo_t* info, void* context) {
+  (void)sig;
+  (void)context;
+  assert(info->si_code == SYS_SECCOMP);
+  trapped_syscall = info->si_syscall;
+  trapped_data = info->si_errno;
+}
+
+static void install_filter(void) {
+  struct sock_filter filter[] = {
+      BPF_STMT(BPF_LD | BPF_W | BPF_ABS, offsetof(struct seccomp_data, arch)),
+      BPF_JUMP(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH_X86_64, 1, 0),
+      BPF_STMT(BPF_RET | BPF_K, SECCOMP_RET_KILL_PROCESS),
+      BPF_STMT(BPF_LD | BPF_W | BPF_ABS, offsetof(struct seccomp_data, nr)),
+      BPF_JUMP(BPF_JMP | BPF_JEQ | BPF_K, SYS_getppid, 0, 1),
+      BPF_STMT(BPF_RET | BPF_K, SECCOMP_RET_ERRNO | EXDEV),
+      BPF_JUMP(BPF_JMP | BPF_JEQ | BPF_K, SYS_getpgrp, 0, 1),
+      BPF_STMT(BPF_RET | BPF_K, SECCOMP_RET_TRAP | 42),
+      BPF_JUMP(BPF_JMP | BPF_JEQ | BPF_K, SYS_getsid, 0, 3),
+      BPF_STMT(BPF_LD | BPF_W | BPF_ABS, offsetof(struct seccomp_data, args[0])),
+      BPF_JUMP(BPF_JMP | BPF_JEQ | BPF_K, SANDBOXED_SID, 0, 1),
+      BPF_STMT(BPF_RET | BPF_K, SECCOMP_RET_KILL_PROCESS),
+      BPF_STMT(BPF_RET | BPF_K, SECCOMP_RET_ALLOW),
+  };
+  struct sock_fprog prog = {
+      .len = sizeof(filter) / sizeof(filter[0]),
+      .filter = filter,
+  };
+  assert(prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) == 0);
+  assert(syscall(SYS_seccomp, SECCOMP_SET_MODE_FILTER, 0, &prog) == 0);
+}
+
+static void sandboxed(void) {
+  struct sigaction sa;
+  memset(&sa, 0, sizeof(sa));
+  sa.sa_sigaction = on_sigsys;
+  sa.sa_flags = SA_SIGINFO;
+  assert(sigaction(SIGSYS, &sa, NULL) == 0);
+
+  assert(prctl(PR_GET_SECCOMP) == 0);
+  install_filter();
+  assert(prctl(PR_GET_SECCOMP) == SECCOMP_MODE_FILTER);
+
+  assert(syscall(SYS_getppid) == -1 && errno == EXDEV);
+  // The syscall is skipped, leaving its own number as the result.
+  assert(syscall(SYS_getpgrp) == SYS_getpgrp);
+  assert(trapped_syscall == SYS_getpgrp && trapped_data == 42);
+  assert(getsid(0) >= 0);
+
+  // The filter is inherited by children.
+  pid_t pid = fork();
+  assert(pid >= 0);
+  if (pid == 0) {
+    _exit(syscall(SYS_getppid) == -1 && errno == EXDEV ? 0 : 1);
+  }
+  int status;
+  assert(waitpid(pid, &status, 0) == pid);
+  assert(WIFEXITED(status) && WEXITSTATUS(status) == 0);
+
+  syscall(SYS_getsid, SANDBOXED_SID);
+  _exit(1); // Unreachable.
+}
+
+static void strict(void) {
+  assert(prctl(PR_SET_SECCOMP, SECCOMP_MODE_STRICT) == 0);
+  const char msg[] = "in strict mode\n";
+  assert(write(STDOUT_FILENO, msg, sizeof(msg) - 1) == sizeof(msg) - 1);
+  syscall(SYS_getpid);
+  _exit(1); // Unreachable.
+}
+
+static int run(void (*fn)(void)) {
+  fflush(stdout);
+  pid_t pid = fork();
+  assert(pid >= 0);
+  if (pid == 0) {
+    // No core dump for the expected crash.
+    struct rlimit nocore = {0, 0};
+    assert(setrlimit(RLIMIT_CORE, &nocore) == 0);
+    fn();
+  }
+  int status;
+  assert(waitpid(pid, &status, 0) == pid);
+  assert(WIFSIGNALED(status));
+  return WTERMSIG(status);
+}
+
+int main(void) {
+  int sig = run(sandboxed);
+  assert(sig == SIGSYS);
+  printf("sandboxed child killed by SIGSYS\n");
+
+  sig = run(strict);
+  assert(sig == SIGKILL);
+  printf("strict child killed by SIGKILL\n");
+  return 0;
+}
@@ -0,0 +1,95 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// A producer and a consumer process hand items over through a queue in shared
+// memory, guarded by a process-shared mutex and condition variables, whose
+// futexes live at different addresses in the two processes.
+
+#define _GNU_SOURCE
+#include <assert.h>
+#include <pthread.h>
+#include <stdio.h>
+#include <sys/mman.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define CAPACITY 4
+#define ITEMS 20
+
+struct queue {
+  pthread_mutex_t lock;
+  pthread_cond_t not_empty;
+  pthread_cond_t not_full;
+  int items[CAPACITY];
+  int head;
+  int count;
+};
+
+static struct queue* map_queue(int fd) {
+  void* mem = mmap(
+      NULL, sizeof(struct queue), PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+  assert(mem != MAP_FAILED);
+  return mem;
+}
+
+int main() {
+  int fd = memfd_create("queue", 0);
+  assert(fd >= 0);
+  assert(ftruncate(fd, sizeof(struct queue)) == 0);
+  struct queue* q = map_queue(fd);
+  pthread_mutexattr_t mattr;
+  pthread_mutexattr_init(&mattr);
+  pthread_mutexattr_setpshared(&mattr, PTHREAD_PROCESS_SHARED);
+  assert(pthread_mutex_init(&q->lock, &mattr) == 0);
+  pthread_condattr_t cattr;
+  pthread_condattr_init(&cattr);
+  pthread_condattr_setpshared(&cattr, PTHREAD_PROCESS_SHARED);
+  assert(pthread_cond_init(&q->not_empty, &cattr) == 0);
+  assert(pthread_cond_init(&q->not_full, &cattr) == 0);
+
+  pid_t child = fork();
+  assert(child >= 0);
+  if (child == 0) {
+    // The consumer uses a second mapping of the queue, at another address.
+    struct queue* inherited = q;
+    q = map_queue(fd);
+    assert(q != inherited);
+    assert(munmap(inherited, sizeof(struct queue)) == 0);
+    for (int i = 0; i < ITEMS; i++) {
+      assert(pthread_mutex_lock(&q->lock) == 0);
+      while (q->count == 0) {
+        assert(pthread_cond_wait(&q->not_empty, &q->lock) == 0);
+      }
+      int item = q->items[q->head];
+      q->head = (q->head + 1) % CAPACITY;
+      q->count--;
+      printf("consumer: got %d (%d left queued)\n", item, q->count);
+      assert(pthread_cond_signal(&q->not_full) == 0);
+      assert(pthread_mutex_unlock(&q->lock) == 0);
+    }
+    return 0;
+  }
+
+  // The producer.
+  for (int i = 0; i < ITEMS; i++) {
+    assert(pthread_mutex_lock(&q->lock) == 0);
+    while (q->count == CAPACITY) {
+      assert(pthread_cond_wait(&q->not_full, &q->lock) == 0);
+    }
+    q->items[(q->head + q->count) % CAPACITY] = i;
+    q->count++;
+    assert(pthread_cond_signal(&q->not_empty) == 0);
+    assert(pthread_mutex_unlock(&q->lock) == 0);
+  }
+
+  int status;
+  assert(waitpid(child, &status, 0) == child);
+  assert(WIFEXITED(status) && WEXITSTATUS(status) == 0);
+  printf("producer: done\n");
+  return 0;
+}
@@ -0,0 +1,91 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// A parent and child process ping-pong through a System V message queue,
+// share a counter in a System V shared memory segment guarded by a semaphore,
+// and finish with a POSIX message queue.
+
+#include <assert.h>
+#include <fcntl.h>
+#include <mqueue.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/ipc.h>
+#include <sys/msg.h>
+#include <sys/sem.h>
+#include <sys/shm.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define ROUNDS 5
+
+struct message {
+  long mtype;
+  int round;
+};
+
+static void sem_change(int semid, int delta) {
+  struct sembuf op = {.sem_num = 0, .sem_op = delta, .sem_flg = 0};
+  assert(semop(semid, &op, 1) == 0);
+}
+
+int main() {
+  int msqid = msgget(IPC_PRIVATE, IPC_CREAT | 0600);
+  assert(msqid >= 0);
+  int semid = semget(IPC_PRIVATE, 1, IPC_CREAT | 0600);
+  assert(semid >= 0);
+  assert(semctl(semid, 0, SETVAL, 1) == 0);
+  int shmid = shmget(IPC_PRIVATE, sizeof(int), IPC_CREAT | 0600);
+  assert(shmid >= 0);
+  int* counter = shmat(shmid, NULL, 0);
+  assert(counter != (void*)-1);
+  *counter = 0;
+
+  struct mq_attr attr = {.mq_maxmsg = 1, .mq_msgsize = 16};
+  mqd_t mq = mq_open("/hermit_sysv_ipc", O_CREAT | O_RDWR, 0600, &attr);
+  assert(mq != (mqd_t)-1);
+
+  pid_t child = fork();
+  assert(child >= 0);
+  if (child == 0) {
+    struct message msg;
+    for (int i = 0; i < ROUNDS; i++) {
+      assert(msgrcv(msqid, &msg, sizeof(int), 1, 0) == sizeof(int));
+      sem_change(semid, -1);
+      (*counter)++;
+      sem_change(semid, 1);
+      msg.mtype = 2;
+      assert(msgsnd(msqid, &msg, sizeof(int), 0) == 0);
+    }
+    assert(mq_send(mq, "bye", 4, 0) == 0);
+    return 0;
+  }
+
+  for (int i = 0; i < ROUNDS; i++) {
+    struct message msg = {.mtype = 1, .round = i};
+    assert(msgsnd(msqid, &msg, sizeof(int), 0) == 0);
+    assert(msgrcv(msqid, &msg, sizeof(int), 2, 0) == sizeof(int));
+    sem_change(semid, -1);
+    printf("round %d: counter is %d\n", msg.round, *counter);
+    sem_change(semid, 1);
+  }
+  char buf[16];
+  assert(mq_receive(mq, buf, sizeof(buf), NULL) == 4);
+  printf("mq: %s\n", buf);
+
+  int status;
+  assert(waitpid(child, &status, 0) == child);
+  assert(WIFEXITED(status) && WEXITSTATUS(status) == 0);
+  assert(shmdt(counter) == 0);
+  assert(shmctl(shmid, IPC_RMID, NULL) == 0);
+  assert(semctl(semid, 0, IPC_RMID) == 0);
+  assert(msgctl(msqid, IPC_RMID, NULL) == 0);
+  mq_close(mq);
+  mq_unlink("/hermit_sysv_ipc");
+  return 0;
+}
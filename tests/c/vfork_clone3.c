@@ -0,0 +1,83 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// vfork suspends the parent until the child exits or execs, with the child
+// sharing the parent's memory meanwhile. clone3 with CLONE_PIDFD hands the
+// parent a pidfd, and posix_spawn (clone3 with CLONE_VM | CLONE_VFORK in newer
+// glibc) works.
+
+#define _GNU_SOURCE
+#include <assert.h>
+#include <fcntl.h>
+#include <linux/sched.h>
+#include <signal.h>
+#include <spawn.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+extern char** environ;
+
+int main(void) {
+  // The parent only resumes once the child has exited, and sees its writes.
+  volatile int shared = 0;
+  pid_t pid = vfork();
+  assert(pid >= 0);
+  if (pid == 0) {
+    shared = 42;
+    _exit(7);
+  }
+  assert(shared == 42);
+  int status;
+  assert(waitpid(pid, &status, 0) == pid);
+  assert(WIFEXITED(status) && WEXITSTATUS(status) == 7);
+  printf("vfork child exited, shared = %d\n", shared);
+
+  // Likewise once the child has exec'd.
+  pid = vfork();
+  assert(pid >= 0);
+  if (pid == 0) {
+    char* argv[] = {"true", NULL};
+    execve("/bin/true", argv, environ);
+    _exit(127);
+  }
+  assert(waitpid(pid, &status, 0) == pid);
+  assert(WIFEXITED(status) && WEXITSTATUS(status) == 0);
+  printf("vfork child exec'd\n");
+
+  // clone3 with a pidfd for the child.
+  int pidfd = -1;
+  struct clone_args args;
+  memset(&args, 0, sizeof(args));
+  args.flags = CLONE_PIDFD;
+  args.pidfd = (uint64_t)(uintptr_t)&pidfd;
+  args.exit_signal = SIGCHLD;
+  pid = syscall(SYS_clone3, &args, sizeof(args));
+  assert(pid >= 0);
+  if (pid == 0) {
+    _exit(3);
+  }
+  assert(pidfd >= 0);
+  assert(fcntl(pidfd, F_GETFD) & FD_CLOEXEC);
+  assert(waitpid(pid, &status, 0) == pid);
+  assert(WIFEXITED(status) && WEXITSTATUS(status) == 3);
+  assert(close(pidfd) == 0);
+  printf("clone3 child exited\n");
+
+  // posix_spawn, including its report of a failed exec.
+  char* argv[] = {"true", NULL};
+  assert(posix_spawn(&pid, "/bin/true", NULL, NULL, argv, environ) == 0);
+  assert(waitpid(pid, &status, 0) == pid);
+  assert(WIFEXITED(status) && WEXITSTATUS(status) == 0);
+  assert(posix_spawn(&pid, "/nonexistent", NULL, NULL, argv, environ) != 0);
+  printf("posix_spawn ok\n");
+  return 0;
+}
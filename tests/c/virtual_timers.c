@@ -0,0 +1,122 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// Exercise timerfd, setitimer, and POSIX timers, which should all fire in
+// logical time, in a fixed order relative to one another.
+
+#include <assert.h>
+#include <signal.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/epoll.h>
+#include <sys/time.h>
+#include <sys/timerfd.h>
+#include <time.h>
+#include <unistd.h>
+
+static volatile int alarms = 0;
+static volatile int posix_fired = 0;
+
+static void on_alarm(int signo) {
+  (void)signo;
+  alarms++;
+}
+
+static void on_posix_timer(int signo) {
+  (void)signo;
+  posix_fired++;
+}
+
+static void test_timerfd(void) {
+  int fd = timerfd_create(CLOCK_MONOTONIC, 0);
+  assert(fd >= 0);
+
+  // 10ms first expiration, then every 10ms.
+  struct itimerspec spec;
+  memset(&spec, 0, sizeof(spec));
+  spec.it_value.tv_nsec = 10 * 1000 * 1000;
+  spec.it_interval.tv_nsec = 10 * 1000 * 1000;
+  assert(timerfd_settime(fd, 0, &spec, NULL) == 0);
+
+  struct itimerspec cur;
+  assert(timerfd_gettime(fd, &cur) == 0);
+  assert(cur.it_interval.tv_nsec == 10 * 1000 * 1000);
+
+  uint64_t expirations = 0;
+  assert(read(fd, &expirations, sizeof(expirations)) == sizeof(expirations));
+  assert(expirations >= 1);
+  printf("timerfd: first read saw %llu expiration(s)\n",
+         (unsigned long long)expirations);
+
+  // The next expiration should wake up an epoll loop.
+  int ep = epoll_create1(0);
+  struct epoll_event ev = {.events = EPOLLIN, .data.fd = fd};
+  assert(epoll_ctl(ep, EPOLL_CTL_ADD, fd, &ev) == 0);
+  for (int i = 0; i < 3; i++) {
+    struct epoll_event out;
+    assert(epoll_wait(ep, &out, 1, -1) == 1);
+    assert(out.data.fd == fd);
+    assert(read(fd, &expirations, sizeof(expirations)) == sizeof(expirations));
+    printf("timerfd: epoll iteration %d, %llu expiration(s)\n", i,
+           (unsigned long long)expirations);
+  }
+
+  // Disarm.
+  memset(&spec, 0, sizeof(spec));
+  assert(timerfd_settime(fd, 0, &spec, &cur) == 0);
+  assert(timerfd_gettime(fd, &cur) == 0);
+  assert(cur.it_value.tv_sec == 0 && cur.it_value.tv_nsec == 0);
+  close(ep);
+  close(fd);
+}
+
+static void test_itimer(void) {
+  signal(SIGALRM, on_alarm);
+  struct itimerval val;
+  memset(&val, 0, sizeof(val));
+  val.it_value.tv_usec = 20 * 1000;
+  val.it_interval.tv_usec = 20 * 1000;
+  assert(setitimer(ITIMER_REAL, &val, NULL) == 0);
+  while (alarms < 3) {
+    pause();
+  }
+  memset(&val, 0, sizeof(val));
+  assert(setitimer(ITIMER_REAL, &val, NULL) == 0);
+  assert(getitimer(ITIMER_REAL, &val) == 0);
+  assert(val.it_value.tv_sec == 0 && val.it_value.tv_usec == 0);
+  printf("itimer: received %d alarms\n", alarms);
+}
+
+static void test_posix_timer(void) {
+  signal(SIGUSR1, on_posix_timer);
+  struct sigevent sev;
+  memset(&sev, 0, sizeof(sev));
+  sev.sigev_notify = SIGEV_SIGNAL;
+  sev.sigev_signo = SIGUSR1;
+  timer_t timer;
+  assert(timer_create(CLOCK_MONOTONIC, &sev, &timer) == 0);
+
+  struct itimerspec spec;
+  memset(&spec, 0, sizeof(spec));
+  spec.it_value.tv_nsec = 5 * 1000 * 1000;
+  assert(timer_settime(timer, 0, &spec, NULL) == 0);
+  while (!posix_fired) {
+    pause();
+  }
+  assert(timer_getoverrun(timer) == 0);
+  assert(timer_delete(timer) == 0);
+  printf("posix timer: fired %d time(s)\n", posix_fired);
+}
+
+int main() {
+  test_timerfd();
+  test_itimer();
+  test_posix_timer();
+  return 0;
+}
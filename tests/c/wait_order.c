@@ -0,0 +1,117 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ * All rights reserved.
+ *
+ * This source code is licensed under the BSD-style license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+// Several children exiting at nearly the same time. The order in which they
+// are reaped, and the SIGCHLDs received, should be the same on every run.
+// Also covers waiting on a process group, and waitid with WNOWAIT.
+
+#define _GNU_SOURCE
+#include <assert.h>
+#include <signal.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/resource.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define NCHILDREN 4
+
+static volatile sig_atomic_t sigchlds = 0;
+
+static void on_sigchld(int sig) {
+  (void)sig;
+  sigchlds++;
+}
+
+static pid_t spawn(int index, pid_t pgid) {
+  pid_t pid = fork();
+  assert(pid >= 0);
+  if (pid == 0) {
+    if (pgid >= 0) {
+      assert(setpgid(0, pgid) == 0);
+    }
+    // Vary the amount of work, but not by enough to settle the exit order.
+    volatile unsigned long sum = 0;
+    for (unsigned long i = 0; i < 10000UL * (NCHILDREN - index); i++) {
+      sum += i;
+    }
+    _exit(index);
+  }
+  if (pgid >= 0) {
+    // Both parent and child set the group, to avoid racing with the child.
+    setpgid(pid, pgid == 0 ? pid : pgid);
+  }
+  return pid;
+}
+
+static int index_of(pid_t* pids, int n, pid_t pid) {
+  for (int i = 0; i < n; i++) {
+    if (pids[i] == pid) {
+      return i;
+    }
+  }
+  return -1;
+}
+
+int main(void) {
+  struct sigaction sa;
+  memset(&sa, 0, sizeof(sa));
+  sa.sa_handler = on_sigchld;
+  sa.sa_flags = SA_RESTART;
+  assert(sigaction(SIGCHLD, &sa, NULL) == 0);
+
+  // Reap in exit order.
+  pid_t pids[NCHILDREN];
+  for (int i = 0; i < NCHILDREN; i++) {
+    pids[i] = spawn(i, -1);
+  }
+  for (int i = 0; i < NCHILDREN; i++) {
+    int status;
+    struct rusage usage;
+    pid_t pid = wait4(-1, &status, 0, &usage);
+    assert(pid > 0);
+    assert(WIFEXITED(status));
+    int index = index_of(pids, NCHILDREN, pid);
+    assert(index == WEXITSTATUS(status));
+    printf("reaped child %d\n", index);
+  }
+  assert(wait4(-1, NULL, WNOHANG, NULL) == -1);
+  printf("SIGCHLD received %d times\n", (int)sigchlds);
+
+  // Wait on one process group, while a child outside of it is still around.
+  pid_t outsider = spawn(NCHILDREN, -1);
+  pid_t group[2];
+  group[0] = spawn(0, 0);
+  group[1] = spawn(1, group[0]);
+  for (int i = 0; i < 2; i++) {
+    int status;
+    pid_t pid = waitpid(-group[0], &status, 0);
+    assert(pid > 0);
+    int index = index_of(group, 2, pid);
+    assert(index == WEXITSTATUS(status));
+    printf("reaped group member %d\n", index);
+  }
+  assert(waitpid(-group[0], NULL, WNOHANG) == -1);
+  assert(waitpid(outsider, NULL, 0) == outsider);
+
+  // Peek at an exited child without reaping it, then reap it.
+  pid_t child = spawn(0, -1);
+  siginfo_t info;
+  memset(&info, 0, sizeof(info));
+  assert(waitid(P_PID, child, &info, WEXITED | WNOWAIT) == 0);
+  assert(info.si_pid == child);
+  assert(info.si_code == CLD_EXITED);
+  memset(&info, 0, sizeof(info));
+  assert(waitid(P_ALL, 0, &info, WEXITED) == 0);
+  assert(info.si_pid == child);
+  assert(waitid(P_ALL, 0, &info, WEXITED | WNOHANG) == -1);
+
+  printf("done\n");
+  return 0;
+}